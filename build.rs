@@ -0,0 +1,12 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    {
+        // a vendored protoc, so that the build doesn't depend on the host
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc available");
+        std::env::set_var("PROTOC", protoc);
+
+        tonic_build::compile_protos("proto/scrolls.proto").expect("proto compiles");
+    }
+}
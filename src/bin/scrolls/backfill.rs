@@ -0,0 +1,99 @@
+use std::{str::FromStr, time::Duration};
+
+use clap::ArgMatches;
+use pallas::network::miniprotocols::Point;
+use scrolls::{crosscut, cursor};
+
+use crate::{
+    daemon,
+    shutdown::{self, Reason, Shutdown},
+};
+
+fn parse_point(args: &ArgMatches, name: &str) -> Result<crosscut::PointArg, scrolls::Error> {
+    let raw: String = args
+        .value_of_t(name)
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let point = crosscut::PointArg::from_str(&raw)?;
+
+    // make sure that the point is valid before starting the pipeline
+    let _: Point = point.clone().try_into()?;
+
+    Ok(point)
+}
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let from = parse_point(args, "from")?;
+    let to = parse_point(args, "to")?;
+
+    if let (crosscut::PointArg::Specific(a, _), crosscut::PointArg::Specific(b, _)) = (&from, &to) {
+        if b <= a {
+            return Err(scrolls::Error::config(
+                "end of range should be after its start",
+            ));
+        }
+    }
+
+    if matches!(to, crosscut::PointArg::Origin) {
+        return Err(scrolls::Error::config("end of range can't be the origin"));
+    }
+
+    let mut config = daemon::load_config(args)?;
+
+    daemon::select_reducers(&mut config, args)?;
+
+    // the live cursor is never read nor written, the range defines where to start
+    let intersect = crosscut::IntersectConfig::Range(from, to);
+    let pipeline = daemon::build_pipeline(config, &intersect, cursor::Store::Skip, &None, None)?;
+
+    // storage is the last stage, it stops once it commits the end of the range
+    let (name, storage) = pipeline
+        .tethers
+        .last()
+        .expect("pipeline has a storage stage");
+
+    shutdown::watch_signals();
+
+    loop {
+        if shutdown::signaled() {
+            return Ok(Shutdown::new(Reason::Signal).with_progress(storage));
+        }
+
+        if let gasket::runtime::TetherState::Dropped = storage.check_state() {
+            log::info!("{} stage finished, backfill completed", name);
+            return Ok(Shutdown::new(Reason::Completed).with_progress(storage));
+        }
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("backfill")
+        .about("runs the configured reducers over a fixed range of the chain and exits")
+        .arg(
+            clap::Arg::new("from")
+                .long("from")
+                .required(true)
+                .takes_value(true)
+                .help("point after which to start, in `slot,hex-hash` format"),
+        )
+        .arg(
+            clap::Arg::new("to")
+                .long("to")
+                .required(true)
+                .takes_value(true)
+                .help("last point of the range, in `slot,hex-hash` format"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file to load"),
+        )
+        .arg(daemon::reducers_arg())
+}
@@ -1,9 +1,17 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
 
 use clap::ArgMatches;
-use scrolls::{bootstrap, crosscut, reducers, sources, storage};
+use pallas::network::miniprotocols::Point;
+use scrolls::{
+    bootstrap, crosscut,
+    cursor::{self, CursorStore},
+    enrich, reducers, sources, storage,
+};
 use serde::Deserialize;
 
+use crate::metrics::{Exporter, MetricsConfig};
+use crate::shutdown::{self, Reason, Shutdown};
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum SourceConfig {
@@ -11,6 +19,30 @@ pub enum SourceConfig {
 
     #[cfg(target_family = "unix")]
     N2C(sources::n2c::Config),
+
+    Oura(sources::oura::Config),
+
+    #[cfg(target_family = "unix")]
+    CatchUp(CatchUpConfig),
+}
+
+/// Polls the mempool of a local node next to the chain, the data goes to a
+/// storage of its own so that it never mixes with the confirmed chain
+#[cfg(target_family = "unix")]
+#[derive(Deserialize)]
+pub struct MempoolConfig {
+    #[serde(flatten)]
+    monitor: sources::n2c::mempool::Config,
+    storage: StorageConfig,
+}
+
+/// Syncs up to the tip of a local node over n2c, then follows the chain from
+/// that point over n2n
+#[cfg(target_family = "unix")]
+#[derive(Deserialize)]
+pub struct CatchUpConfig {
+    catch_up: sources::n2c::Config,
+    follow: sources::n2n::Config,
 }
 
 impl SourceConfig {
@@ -19,10 +51,13 @@ impl SourceConfig {
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
         cursor: &crosscut::Cursor,
+        policy: &crosscut::RuntimePolicy,
     ) -> sources::Plugin {
         match self {
-            SourceConfig::N2N(c) => c.plugin(chain, intersect, cursor),
-            SourceConfig::N2C(c) => c.plugin(chain, intersect, cursor),
+            SourceConfig::N2N(c) => c.plugin(chain, intersect, cursor, policy),
+            SourceConfig::N2C(c) => c.plugin(chain, intersect, cursor, policy),
+            SourceConfig::Oura(c) => c.plugin(chain, intersect, cursor, policy),
+            SourceConfig::CatchUp(c) => c.follow.plugin(chain, intersect, cursor, policy),
         }
     }
 }
@@ -43,11 +78,216 @@ pub enum ReducerConfig {
     TotalTransactionsCountByContractAddresses(
         reducers::total_transactions_count_by_contract_addresses::Config,
     ),
+    BlockStats(reducers::block_stats::Config),
+    RichestAddresses(reducers::richest_addresses::Config),
+    TxValidityIntervals(reducers::tx_validity_intervals::Config),
+    PoolMetadataByPool(reducers::pool_metadata_by_pool::Config),
+    ChainTip(reducers::chain_tip::Config),
+    InputReuse(reducers::input_reuse::Config),
+    BalanceHistory(reducers::balance_history::Config),
+    UniqueAddressesByEpoch(reducers::unique_addresses_by_epoch::Config),
+    FeesByAddress(reducers::fees_by_address::Config),
+    TxIoDistribution(reducers::tx_io_distribution::Config),
+    WitnessDistribution(reducers::witness_distribution::Config),
+    AssetClassification(reducers::asset_classification::Config),
+    BlockLinkage(reducers::block_linkage::Config),
+    StakeDistribution(reducers::stake_distribution::Config),
+    DelegatorsByPool(reducers::delegators_by_pool::Config),
+    Deposits(reducers::deposits::Config),
+    TokenOutputs(reducers::token_outputs::Config),
+    TxThroughput(reducers::tx_throughput::Config),
+    AddressClusters(reducers::address_clusters::Config),
+    RollbackStats(reducers::rollback_stats::Config),
+    PoolActivity(reducers::pool_activity::Config),
+    WatchedUtxos(reducers::watched_utxos::Config),
+    UtxoValueHistogram(reducers::utxo_value_histogram::Config),
+    TransferVolume(reducers::transfer_volume::Config),
+    CertificatesByEpoch(reducers::certificates_by_epoch::Config),
+    PoolRelays(reducers::pool_relays::Config),
+    ScriptExecutionUnits(reducers::script_execution_units::Config),
+    AssetsByAddress(reducers::assets_by_address::Config),
+    OutputStats(reducers::output_stats::Config),
+    WithdrawalsByPool(reducers::withdrawals_by_pool::Config),
+    OrphanedBlocks(reducers::orphaned_blocks::Config),
+    StakeBalance(reducers::stake_balance::Config),
+    WhaleTransactions(reducers::whale_transactions::Config),
+    UtxoAgeHistogram(reducers::utxo_age_histogram::Config),
+    DelegationChurn(reducers::delegation_churn::Config),
+    SlotGaps(reducers::slot_gaps::Config),
+    AddressFlows(reducers::address_flows::Config),
+    MinUtxoViolations(reducers::min_utxo_violations::Config),
+    CurrentEpoch(reducers::current_epoch::Config),
+    AssetProvenance(reducers::asset_provenance::Config),
+    ScriptSpends(reducers::script_spends::Config),
+    RealizedRewards(reducers::realized_rewards::Config),
+    UtxoSetSize(reducers::utxo_set_size::Config),
+    BlockValueByPool(reducers::block_value_by_pool::Config),
+    RawCbor(reducers::raw_cbor::Config),
+    MetadataStandards(reducers::metadata_standards::Config),
 }
 
 impl ReducerConfig {
-    fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> reducers::Plugin {
+    fn name(&self) -> &'static str {
         match self {
+            ReducerConfig::UtxoByAddress(_) => "UtxoByAddress",
+            ReducerConfig::PointByTx(_) => "PointByTx",
+            ReducerConfig::PoolByStake(_) => "PoolByStake",
+            ReducerConfig::TotalTransactionsCount(_) => "TotalTransactionsCount",
+            ReducerConfig::TransactionsCountByEpoch(_) => "TransactionsCountByEpoch",
+            ReducerConfig::TransactionsCountByContractAddress(_) => {
+                "TransactionsCountByContractAddress"
+            }
+            ReducerConfig::TransactionsCountByContractAddressByEpoch(_) => {
+                "TransactionsCountByContractAddressByEpoch"
+            }
+            ReducerConfig::TotalTransactionsCountByContractAddresses(_) => {
+                "TotalTransactionsCountByContractAddresses"
+            }
+            ReducerConfig::BlockStats(_) => "BlockStats",
+            ReducerConfig::RichestAddresses(_) => "RichestAddresses",
+            ReducerConfig::TxValidityIntervals(_) => "TxValidityIntervals",
+            ReducerConfig::PoolMetadataByPool(_) => "PoolMetadataByPool",
+            ReducerConfig::ChainTip(_) => "ChainTip",
+            ReducerConfig::InputReuse(_) => "InputReuse",
+            ReducerConfig::BalanceHistory(_) => "BalanceHistory",
+            ReducerConfig::UniqueAddressesByEpoch(_) => "UniqueAddressesByEpoch",
+            ReducerConfig::FeesByAddress(_) => "FeesByAddress",
+            ReducerConfig::TxIoDistribution(_) => "TxIoDistribution",
+            ReducerConfig::WitnessDistribution(_) => "WitnessDistribution",
+            ReducerConfig::AssetClassification(_) => "AssetClassification",
+            ReducerConfig::BlockLinkage(_) => "BlockLinkage",
+            ReducerConfig::StakeDistribution(_) => "StakeDistribution",
+            ReducerConfig::DelegatorsByPool(_) => "DelegatorsByPool",
+            ReducerConfig::Deposits(_) => "Deposits",
+            ReducerConfig::TokenOutputs(_) => "TokenOutputs",
+            ReducerConfig::TxThroughput(_) => "TxThroughput",
+            ReducerConfig::AddressClusters(_) => "AddressClusters",
+            ReducerConfig::RollbackStats(_) => "RollbackStats",
+            ReducerConfig::PoolActivity(_) => "PoolActivity",
+            ReducerConfig::WatchedUtxos(_) => "WatchedUtxos",
+            ReducerConfig::UtxoValueHistogram(_) => "UtxoValueHistogram",
+            ReducerConfig::TransferVolume(_) => "TransferVolume",
+            ReducerConfig::CertificatesByEpoch(_) => "CertificatesByEpoch",
+            ReducerConfig::PoolRelays(_) => "PoolRelays",
+            ReducerConfig::ScriptExecutionUnits(_) => "ScriptExecutionUnits",
+            ReducerConfig::AssetsByAddress(_) => "AssetsByAddress",
+            ReducerConfig::OutputStats(_) => "OutputStats",
+            ReducerConfig::WithdrawalsByPool(_) => "WithdrawalsByPool",
+            ReducerConfig::OrphanedBlocks(_) => "OrphanedBlocks",
+            ReducerConfig::StakeBalance(_) => "StakeBalance",
+            ReducerConfig::WhaleTransactions(_) => "WhaleTransactions",
+            ReducerConfig::UtxoAgeHistogram(_) => "UtxoAgeHistogram",
+            ReducerConfig::DelegationChurn(_) => "DelegationChurn",
+            ReducerConfig::SlotGaps(_) => "SlotGaps",
+            ReducerConfig::AddressFlows(_) => "AddressFlows",
+            ReducerConfig::MinUtxoViolations(_) => "MinUtxoViolations",
+            ReducerConfig::CurrentEpoch(_) => "CurrentEpoch",
+            ReducerConfig::AssetProvenance(_) => "AssetProvenance",
+            ReducerConfig::ScriptSpends(_) => "ScriptSpends",
+            ReducerConfig::RealizedRewards(_) => "RealizedRewards",
+            ReducerConfig::UtxoSetSize(_) => "UtxoSetSize",
+            ReducerConfig::BlockValueByPool(_) => "BlockValueByPool",
+            ReducerConfig::RawCbor(_) => "RawCbor",
+            ReducerConfig::MetadataStandards(_) => "MetadataStandards",
+        }
+    }
+
+    /// Checks the parts of the config that depend on the network
+    fn validate(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<(), scrolls::Error> {
+        match self {
+            ReducerConfig::UtxoByAddress(c) => c.validate(chain),
+            ReducerConfig::WatchedUtxos(c) => c.validate(chain),
+            ReducerConfig::UtxoValueHistogram(c) => c.validate(),
+            ReducerConfig::UtxoAgeHistogram(c) => c.validate(),
+            ReducerConfig::TransferVolume(c) => c.validate(),
+            ReducerConfig::AssetsByAddress(c) => c.validate(),
+            ReducerConfig::WhaleTransactions(c) => c.validate(),
+            ReducerConfig::MinUtxoViolations(c) => c.validate(),
+            ReducerConfig::ScriptSpends(c) => c.validate(chain),
+            ReducerConfig::StakeDistribution(c) => c.validate(),
+            ReducerConfig::RawCbor(c) => c.validate(chain),
+            _ => Ok(()),
+        }
+    }
+
+    /// Allowlists of the config that can be replaced while the daemon runs
+    fn allowlists(
+        &self,
+        chain: &crosscut::ChainWellKnownInfo,
+    ) -> Result<crosscut::Allowlists, scrolls::Error> {
+        let allowlists = match self {
+            ReducerConfig::UtxoByAddress(c) => crosscut::Allowlists {
+                addresses: c.allowlist(chain)?,
+                policies: None,
+            },
+            ReducerConfig::AssetsByAddress(c) => crosscut::Allowlists {
+                addresses: None,
+                policies: c.policies()?,
+            },
+            ReducerConfig::TransferVolume(c) => crosscut::Allowlists {
+                addresses: None,
+                policies: c.policies()?,
+            },
+            ReducerConfig::ScriptSpends(c) => crosscut::Allowlists {
+                addresses: c.allowlist(chain)?,
+                policies: None,
+            },
+            ReducerConfig::RawCbor(c) => crosscut::Allowlists {
+                addresses: c.allowlist(chain)?,
+                policies: None,
+            },
+            _ => Default::default(),
+        };
+
+        Ok(allowlists)
+    }
+
+    /// Reducers that keep track of balances or positions need to see every
+    /// block, a sample of the chain would leave them in an invalid state
+    fn supports_sampling(&self) -> bool {
+        !matches!(
+            self,
+            ReducerConfig::UtxoByAddress(_)
+                | ReducerConfig::RichestAddresses(_)
+                | ReducerConfig::BalanceHistory(_)
+                | ReducerConfig::InputReuse(_)
+                | ReducerConfig::ChainTip(_)
+                | ReducerConfig::StakeDistribution(_)
+                | ReducerConfig::DelegatorsByPool(_)
+                | ReducerConfig::Deposits(_)
+                | ReducerConfig::AddressClusters(_)
+                | ReducerConfig::RollbackStats(_)
+                | ReducerConfig::PoolActivity(_)
+                | ReducerConfig::WatchedUtxos(_)
+                | ReducerConfig::UtxoValueHistogram(_)
+                | ReducerConfig::AssetsByAddress(_)
+                | ReducerConfig::WithdrawalsByPool(_)
+                | ReducerConfig::OrphanedBlocks(_)
+                | ReducerConfig::StakeBalance(_)
+                | ReducerConfig::DelegationChurn(_)
+                | ReducerConfig::SlotGaps(_)
+                | ReducerConfig::CurrentEpoch(_)
+                | ReducerConfig::UtxoSetSize(_)
+        )
+    }
+
+    fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        state: Option<&reducers::state::Db>,
+    ) -> Result<reducers::Plugin, scrolls::Error> {
+        let name = self.name();
+
+        let state = || {
+            state.ok_or_else(|| {
+                scrolls::Error::config(format!(
+                    "reducer {} keeps its state in a local db, set `db_path` of `[reducer_state]`",
+                    name
+                ))
+            })
+        };
+
+        let plugin = match self {
             ReducerConfig::UtxoByAddress(c) => c.plugin(chain),
             ReducerConfig::PointByTx(c) => c.plugin(),
             ReducerConfig::PoolByStake(c) => c.plugin(),
@@ -56,6 +296,122 @@ impl ReducerConfig {
             ReducerConfig::TransactionsCountByContractAddress(c) => c.plugin(chain),
             ReducerConfig::TransactionsCountByContractAddressByEpoch(c) => c.plugin(chain),
             ReducerConfig::TotalTransactionsCountByContractAddresses(c) => c.plugin(),
+            ReducerConfig::BlockStats(c) => c.plugin(),
+            ReducerConfig::RichestAddresses(c) => c.plugin(chain),
+            ReducerConfig::TxValidityIntervals(c) => c.plugin(state()?)?,
+            ReducerConfig::PoolMetadataByPool(c) => c.plugin(),
+            ReducerConfig::ChainTip(c) => c.plugin(),
+            ReducerConfig::InputReuse(c) => c.plugin(state()?)?,
+            ReducerConfig::BalanceHistory(c) => c.plugin(chain),
+            ReducerConfig::UniqueAddressesByEpoch(c) => c.plugin(chain),
+            ReducerConfig::FeesByAddress(c) => c.plugin(chain),
+            ReducerConfig::TxIoDistribution(c) => c.plugin(chain),
+            ReducerConfig::WitnessDistribution(c) => c.plugin(chain),
+            ReducerConfig::AssetClassification(c) => c.plugin(),
+            ReducerConfig::BlockLinkage(c) => c.plugin(),
+            ReducerConfig::StakeDistribution(c) => c.plugin(chain, state()?)?,
+            ReducerConfig::DelegatorsByPool(c) => c.plugin(state()?)?,
+            ReducerConfig::Deposits(c) => c.plugin(chain),
+            ReducerConfig::TokenOutputs(c) => c.plugin(chain),
+            ReducerConfig::TxThroughput(c) => c.plugin(chain),
+            ReducerConfig::AddressClusters(c) => c.plugin(chain, state()?)?,
+            ReducerConfig::RollbackStats(c) => c.plugin(chain),
+            ReducerConfig::PoolActivity(c) => c.plugin(state()?)?,
+            ReducerConfig::WatchedUtxos(c) => c.plugin(chain),
+            ReducerConfig::UtxoValueHistogram(c) => c.plugin(),
+            ReducerConfig::TransferVolume(c) => c.plugin(chain),
+            ReducerConfig::CertificatesByEpoch(c) => c.plugin(chain),
+            ReducerConfig::PoolRelays(c) => c.plugin(),
+            ReducerConfig::ScriptExecutionUnits(c) => c.plugin(chain),
+            ReducerConfig::AssetsByAddress(c) => c.plugin(chain),
+            ReducerConfig::OutputStats(c) => c.plugin(chain),
+            ReducerConfig::WithdrawalsByPool(c) => c.plugin(state()?)?,
+            ReducerConfig::OrphanedBlocks(c) => c.plugin(),
+            ReducerConfig::StakeBalance(c) => c.plugin(chain),
+            ReducerConfig::WhaleTransactions(c) => c.plugin(),
+            ReducerConfig::UtxoAgeHistogram(c) => c.plugin(chain),
+            ReducerConfig::DelegationChurn(c) => c.plugin(chain, state()?)?,
+            ReducerConfig::SlotGaps(c) => c.plugin(chain),
+            ReducerConfig::AddressFlows(c) => c.plugin(chain),
+            ReducerConfig::MinUtxoViolations(c) => c.plugin(),
+            ReducerConfig::CurrentEpoch(c) => c.plugin(chain),
+            ReducerConfig::AssetProvenance(c) => c.plugin(chain),
+            ReducerConfig::ScriptSpends(c) => c.plugin(chain),
+            ReducerConfig::RealizedRewards(c) => c.plugin(chain),
+            ReducerConfig::UtxoSetSize(c) => c.plugin(),
+            ReducerConfig::BlockValueByPool(c) => c.plugin(chain),
+            ReducerConfig::RawCbor(c) => c.plugin(chain),
+            ReducerConfig::MetadataStandards(c) => c.plugin(chain),
+        };
+
+        Ok(plugin)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReducerEntry {
+    #[serde(flatten)]
+    reducer: ReducerConfig,
+
+    sampling: Option<crosscut::SamplingConfig>,
+
+    /// Name of the entry of `[storages]` that receives the commands of the
+    /// reducer, the main `[storage]` when missing
+    storage: Option<String>,
+
+    /// Rewrites the keys of the reducer into another schema, see
+    /// [crosscut::KeyTemplate]
+    key_template: Option<String>,
+}
+
+impl ReducerEntry {
+    fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        state: Option<&reducers::state::Db>,
+    ) -> Result<(&'static str, reducers::Plugin, Option<crosscut::Sampler>), scrolls::Error> {
+        self.reducer.validate(chain)?;
+
+        let sampler = match self.sampling {
+            Some(sampling) => {
+                sampling.validate()?;
+
+                if !self.reducer.supports_sampling() {
+                    return Err(scrolls::Error::config(format!(
+                        "reducer {} can't work on sampled data",
+                        self.reducer.name()
+                    )));
+                }
+
+                log::warn!(
+                    "reducer {} will only process sampled blocks",
+                    self.reducer.name()
+                );
+
+                Some(sampling.sampler(self.reducer.name()))
+            }
+            None => None,
+        };
+
+        let name = self.reducer.name();
+
+        Ok((name, self.reducer.plugin(chain, state)?, sampler))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type")]
+pub enum EnrichConfig {
+    #[default]
+    Skip,
+    Sled(enrich::sled::Config),
+}
+
+impl EnrichConfig {
+    fn plugin(self) -> enrich::Plugin {
+        match self {
+            EnrichConfig::Skip => enrich::skip::Config {}.plugin(),
+            EnrichConfig::Sled(c) => c.plugin(),
         }
     }
 }
@@ -64,55 +420,191 @@ impl ReducerConfig {
 #[serde(tag = "type")]
 pub enum StorageConfig {
     Redis(storage::redis::Config),
+    DryRun(storage::dry_run::Config),
+    Memory(storage::memory::Config),
+
+    #[cfg(feature = "kafkasink")]
+    Kafka(storage::kafka::Config),
+
+    #[cfg(feature = "dynamodb")]
+    DynamoDB(storage::dynamodb::Config),
 }
 
 impl StorageConfig {
-    fn plugin(
+    pub(crate) fn validate(&self) -> Result<(), scrolls::Error> {
+        match self {
+            StorageConfig::Redis(c) => c.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn plugin(
         self,
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
+        cursor: cursor::Store,
     ) -> storage::Plugin {
         match self {
-            StorageConfig::Redis(c) => c.plugin(chain, intersect),
+            StorageConfig::Redis(c) => c.plugin(chain, intersect, cursor),
+            StorageConfig::DryRun(c) => c.plugin(chain, intersect, cursor),
+            StorageConfig::Memory(c) => c.plugin(chain, intersect, cursor),
+
+            #[cfg(feature = "kafkasink")]
+            StorageConfig::Kafka(c) => c.plugin(chain, intersect, cursor),
+
+            #[cfg(feature = "dynamodb")]
+            StorageConfig::DynamoDB(c) => c.plugin(chain, intersect, cursor),
+        }
+    }
+
+    pub(crate) fn snapshotter(
+        &self,
+    ) -> Result<Box<dyn storage::snapshot::Snapshottable>, scrolls::Error> {
+        match self {
+            StorageConfig::Redis(c) => Ok(Box::new(c.snapshotter()?)),
+            StorageConfig::DryRun(_) => Err(scrolls::Error::config(
+                "dry run storage doesn't support snapshots",
+            )),
+            StorageConfig::Memory(_) => Err(scrolls::Error::config(
+                "memory storage doesn't support snapshots",
+            )),
+
+            #[cfg(feature = "kafkasink")]
+            StorageConfig::Kafka(_) => Err(scrolls::Error::config(
+                "kafka storage doesn't support snapshots",
+            )),
+
+            #[cfg(feature = "dynamodb")]
+            StorageConfig::DynamoDB(_) => Err(scrolls::Error::config(
+                "dynamodb storage doesn't support snapshots",
+            )),
+        }
+    }
+
+    /// The server reads the storage on connections of its own
+    #[cfg(feature = "grpc")]
+    fn grpc_source(&self) -> Result<std::sync::Arc<dyn scrolls::grpc::Source>, scrolls::Error> {
+        match self {
+            StorageConfig::Redis(c) => Ok(std::sync::Arc::new(c.clone())),
+            _ => Err(scrolls::Error::config(
+                "grpc server only serves a redis storage",
+            )),
+        }
+    }
+
+    pub(crate) fn watcher(&self) -> Result<Box<dyn storage::watch::Watchable>, scrolls::Error> {
+        match self {
+            StorageConfig::Redis(c) => Ok(Box::new(c.watcher()?)),
+            StorageConfig::DryRun(_) => Err(scrolls::Error::config(
+                "dry run storage doesn't keep any change to watch",
+            )),
+            StorageConfig::Memory(_) => Err(scrolls::Error::config(
+                "memory storage can't be watched from another process",
+            )),
+
+            #[cfg(feature = "kafkasink")]
+            StorageConfig::Kafka(c) => Ok(Box::new(c.watcher()?)),
+
+            #[cfg(feature = "dynamodb")]
+            StorageConfig::DynamoDB(_) => Err(scrolls::Error::config(
+                "dynamodb storage doesn't keep any change to watch",
+            )),
         }
     }
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
+pub enum CursorConfig {
+    File(cursor::file::Config),
+    Redis(cursor::redis::Config),
+
+    #[cfg(feature = "postgres")]
+    Postgres(cursor::postgres::Config),
+}
+
+impl CursorConfig {
+    /// Store used without a `[cursor]` section: a Redis storage keeps the
+    /// cursor under its `_cursor` key, the other storages use a local file
+    fn default_for(storage: &StorageConfig) -> Self {
+        match storage {
+            StorageConfig::Redis(c) => CursorConfig::Redis(cursor::redis::Config {
+                connection_params: c.connection_params.clone(),
+                key: None,
+                timeouts: c.timeouts,
+            }),
+            _ => CursorConfig::File(Default::default()),
+        }
+    }
+
+    pub(crate) fn store(self, chain: &crosscut::ChainWellKnownInfo) -> cursor::Store {
+        match self {
+            CursorConfig::File(c) => c.store(chain),
+            CursorConfig::Redis(c) => c.store(),
+            #[cfg(feature = "postgres")]
+            CursorConfig::Postgres(c) => c.store(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[allow(clippy::large_enum_variant)]
+#[serde(tag = "type")]
 pub enum ChainConfig {
+    #[default]
     Mainnet,
     Testnet,
+    Preprod,
+    Preview,
     Custom(crosscut::ChainWellKnownInfo),
 }
 
-impl Default for ChainConfig {
-    fn default() -> Self {
-        Self::Mainnet
-    }
-}
-
 impl From<ChainConfig> for crosscut::ChainWellKnownInfo {
     fn from(other: ChainConfig) -> Self {
         match other {
             ChainConfig::Mainnet => crosscut::ChainWellKnownInfo::mainnet(),
             ChainConfig::Testnet => crosscut::ChainWellKnownInfo::testnet(),
+            ChainConfig::Preprod => crosscut::ChainWellKnownInfo::preprod(),
+            ChainConfig::Preview => crosscut::ChainWellKnownInfo::preview(),
             ChainConfig::Custom(x) => x,
         }
     }
 }
 
 #[derive(Deserialize)]
-struct ConfigRoot {
+pub(crate) struct ConfigRoot {
     source: SourceConfig,
-    reducers: Vec<ReducerConfig>,
-    storage: StorageConfig,
-    intersect: crosscut::IntersectConfig,
-    chain: Option<ChainConfig>,
+    enrich: Option<EnrichConfig>,
+    /// Names of the block transforms to run after enrichment, in order
+    transforms: Option<Vec<String>>,
+    reducers: Vec<ReducerEntry>,
+    /// Local db of the reducers that keep a state
+    reducer_state: Option<reducers::state::Config>,
+    filters: Option<crosscut::Filters>,
+    pub(crate) storage: StorageConfig,
+    /// Extra storages that reducers can write to instead of the main one
+    storages: Option<BTreeMap<String, StorageConfig>>,
+    pub(crate) cursor: Option<CursorConfig>,
+    pub(crate) intersect: crosscut::IntersectConfig,
+    pub(crate) chain: Option<ChainConfig>,
+    policy: Option<crosscut::RuntimePolicy>,
+    pipeline: Option<bootstrap::Config>,
+    coordination: Option<crosscut::CoordinationConfig>,
+    rollback_notify: Option<crosscut::RollbackNotifyConfig>,
+    metrics: Option<MetricsConfig>,
+
+    #[cfg(target_family = "unix")]
+    mempool: Option<MempoolConfig>,
+
+    #[cfg(feature = "grpc")]
+    grpc: Option<scrolls::grpc::Config>,
 }
 
 impl ConfigRoot {
-    pub fn new(explicit_file: Option<String>) -> Result<Self, config::ConfigError> {
+    /// Explicit files are merged in the given order over the default ones:
+    /// tables merge key by key, any other value (lists included) is replaced
+    /// as a whole by the last file that sets it
+    pub fn new(explicit_files: Vec<String>) -> Result<Self, config::ConfigError> {
         let mut s = config::Config::builder();
 
         // our base config will always be in /etc/scrolls
@@ -121,8 +613,8 @@ impl ConfigRoot {
         // but we can override it by having a file in the working dir
         s = s.add_source(config::File::with_name("scrolls.toml").required(false));
 
-        // if an explicit file was passed, then we load it as mandatory
-        if let Some(explicit) = explicit_file {
+        // explicit files are mandatory, each one overrides the previous ones
+        for explicit in explicit_files {
             s = s.add_source(config::File::with_name(&explicit).required(true));
         }
 
@@ -133,53 +625,596 @@ impl ConfigRoot {
     }
 }
 
-pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
-    env_logger::init();
+/// Loads the config root honoring the `--config` args, in the given order
+pub(crate) fn load_config(args: &ArgMatches) -> Result<ConfigRoot, scrolls::Error> {
+    let explicit_config = args
+        .values_of("config")
+        .map(|x| x.map(String::from).collect())
+        .unwrap_or_default();
 
-    let explicit_config = match args.is_present("config") {
-        true => {
-            let config_file_path = args
-                .value_of_t("config")
-                .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+    ConfigRoot::new(explicit_config)
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))
+}
+
+fn starts_from_origin(intersect: &crosscut::IntersectConfig) -> bool {
+    matches!(
+        intersect,
+        crosscut::IntersectConfig::Origin
+            | crosscut::IntersectConfig::Point(crosscut::PointArg::Origin)
+            | crosscut::IntersectConfig::Range(crosscut::PointArg::Origin, _)
+    )
+}
 
-            Some(config_file_path)
+fn start_override(args: &ArgMatches) -> Result<Option<crosscut::PointArg>, scrolls::Error> {
+    match args.value_of("start") {
+        Some(raw) => {
+            let point = crosscut::PointArg::from_str(raw)?;
+
+            // make sure that the point is valid before starting the pipeline
+            let _: Point = point.clone().try_into()?;
+
+            Ok(Some(point))
         }
-        false => None,
+        None => Ok(None),
+    }
+}
+
+/// Reducer names compared ignoring case and separators, so that both
+/// `BalanceByAddress` and `balance_by_address` select the same reducer
+fn normalize_reducer_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Keeps only the configured reducers named in the `--reducers` arg, if any
+pub(crate) fn select_reducers(
+    config: &mut ConfigRoot,
+    args: &ArgMatches,
+) -> Result<(), scrolls::Error> {
+    let requested: Vec<_> = match args.value_of("reducers") {
+        Some(raw) => raw
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .collect(),
+        None => return Ok(()),
     };
 
-    let config = ConfigRoot::new(explicit_config)
-        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+    if requested.is_empty() {
+        return Err(scrolls::Error::config("no reducers selected"));
+    }
+
+    let configured: Vec<_> = config.reducers.iter().map(|x| x.reducer.name()).collect();
+
+    for name in requested.iter() {
+        let known = configured
+            .iter()
+            .any(|x| normalize_reducer_name(x) == normalize_reducer_name(name));
+
+        if !known {
+            return Err(scrolls::Error::config(format!(
+                "reducer {} isn't configured, valid names are: {}",
+                name,
+                configured.join(", ")
+            )));
+        }
+    }
+
+    let requested: Vec<_> = requested.into_iter().map(normalize_reducer_name).collect();
+
+    config
+        .reducers
+        .retain(|x| requested.contains(&normalize_reducer_name(x.reducer.name())));
+
+    let selected: Vec<_> = config.reducers.iter().map(|x| x.reducer.name()).collect();
+    log::warn!("only running reducers {}", selected.join(", "));
+
+    Ok(())
+}
+
+fn resync_confirmed(args: &ArgMatches) -> bool {
+    args.is_present("confirm-resync") || std::env::var("SCROLLS_CONFIRM_RESYNC").is_ok()
+}
+
+/// With `require_explicit_origin`, a sync from origin (configured or after a
+/// lost cursor) needs the operator to confirm it
+fn check_explicit_origin(
+    policy: &crosscut::RuntimePolicy,
+    intersect: &crosscut::IntersectConfig,
+    confirmed: bool,
+) -> Result<(), scrolls::Error> {
+    if policy.require_explicit_origin.unwrap_or(false)
+        && starts_from_origin(intersect)
+        && !confirmed
+    {
+        return Err(scrolls::Error::config(
+            "refusing to sync from origin, use --confirm-resync or set SCROLLS_CONFIRM_RESYNC",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Key templates of the reducers, parsed once so that unknown placeholders
+/// are rejected before the pipeline starts
+fn key_templates(
+    reducers: &[ReducerEntry],
+) -> Result<Vec<Option<crosscut::KeyTemplate>>, scrolls::Error> {
+    reducers
+        .iter()
+        .map(|entry| {
+            entry
+                .key_template
+                .as_deref()
+                .map(crosscut::KeyTemplate::parse)
+                .transpose()
+        })
+        .collect()
+}
+
+/// Routes of the reducers to the extra storages that they name, with the
+/// names of the storages in the order of the routes. Storages that no reducer
+/// names are left out.
+fn storage_routes(
+    reducers: &[ReducerEntry],
+    storages: &BTreeMap<String, StorageConfig>,
+) -> Result<(Vec<Option<usize>>, Vec<String>), scrolls::Error> {
+    let mut used: Vec<String> = Vec::new();
+    let mut routes = Vec::new();
 
+    for entry in reducers {
+        let name = match &entry.storage {
+            Some(x) => x,
+            None => {
+                routes.push(None);
+                continue;
+            }
+        };
+
+        if !storages.contains_key(name) {
+            return Err(scrolls::Error::config(format!(
+                "reducer {} writes to storage {}, which isn't in [storages]",
+                entry.reducer.name(),
+                name
+            )));
+        }
+
+        let route = match used.iter().position(|x| x == name) {
+            Some(x) => x,
+            None => {
+                used.push(name.clone());
+                used.len() - 1
+            }
+        };
+
+        routes.push(Some(route));
+    }
+
+    for name in storages.keys().filter(|x| !used.contains(x)) {
+        log::warn!("storage {} isn't used by any reducer, skipping it", name);
+    }
+
+    Ok((routes, used))
+}
+
+/// Builds the whole pipeline from the config, shared by the daemon and other
+/// one-off commands that need to process blocks
+pub(crate) fn build_pipeline(
+    config: ConfigRoot,
+    intersect: &crosscut::IntersectConfig,
+    cursor_store: cursor::Store,
+    cursor: &crosscut::Cursor,
+    scope: Option<crosscut::ScopeHandle>,
+) -> Result<bootstrap::Pipeline, scrolls::Error> {
     let chain = config.chain.unwrap_or_default().into();
 
-    // We need to setup the storage first so that we can retrieve the potential
-    // cursor
-    let storage = config.storage.plugin(&chain, &config.intersect);
+    let intersect = &intersect.clone().resolve(&chain)?;
 
-    let cursor = storage.read_cursor()?;
+    let pipeline_config = config.pipeline.unwrap_or_default();
+    pipeline_config.validate()?;
 
-    match &cursor {
-        Some(x) => log::info!("found existing cursor in storage plugin: {:?}", x),
-        None => log::debug!("no cursor found in storage plugin"),
+    config.storage.validate()?;
+
+    let mut storages = config.storages.unwrap_or_default();
+    let (routes, used) = storage_routes(&config.reducers, &storages)?;
+    let templates = key_templates(&config.reducers)?;
+
+    // with extra storages, the cursor only moves once all of them committed
+    // the block
+    let (gate, cursor_store) = match used.is_empty() {
+        true => (None, cursor_store),
+        false => {
+            let gate = cursor::gate::Gate::new(cursor_store);
+            let member = gate.member();
+            (Some(gate), member)
+        }
     };
 
+    let storage = config.storage.plugin(&chain, intersect, cursor_store);
+
+    let extra = used
+        .into_iter()
+        .map(|name| {
+            let storage = storages.remove(&name).expect("route of a known storage");
+            storage.validate()?;
+
+            let cursor = gate.as_ref().expect("gate of the extra storages").member();
+            let plugin = storage.plugin(&chain, intersect, cursor);
+
+            Ok((name, plugin))
+        })
+        .collect::<Result<_, scrolls::Error>>()?;
+
+    let policy = config.policy.unwrap_or_default();
+
     // We can now setup the source plugin specifying a potential cursor
-    let source = config.source.plugin(&chain, &config.intersect, &cursor);
+    let source = config.source.plugin(&chain, intersect, cursor, &policy);
+
+    let transforms = enrich::TransformRegistry::builtin(&chain)
+        .resolve(&config.transforms.unwrap_or_default())?;
+
+    let enrich = config
+        .enrich
+        .unwrap_or_default()
+        .plugin()
+        .with_transforms(transforms);
+
+    let state = match &config.reducer_state {
+        Some(x) => Some(x.open()?),
+        None => None,
+    };
 
     let reducer_plugins = config
         .reducers
         .into_iter()
-        .map(|x| x.plugin(&chain))
-        .collect();
+        .map(|x| x.plugin(&chain, state.as_ref()))
+        .collect::<Result<_, _>>()?;
+
+    let filters = config.filters.unwrap_or_default();
+
+    let notifier = config.rollback_notify.and_then(|x| x.notifier());
+
+    let mut reducer = reducers::Worker::new(reducer_plugins, filters, notifier, &policy)
+        .with_routes(routes)
+        .with_key_templates(templates);
+
+    if let Some(scope) = scope {
+        reducer = reducer.with_scope(scope);
+    }
+
+    if let Some(dead_letter) = &policy.dead_letter {
+        if !policy.isolate_reducers.unwrap_or(false) {
+            return Err(scrolls::Error::config(
+                "dead_letter requires isolate_reducers to be enabled",
+            ));
+        }
+
+        reducer = reducer.with_dead_letter(dead_letter.sink()?);
+    }
+
+    #[allow(unused_mut)]
+    let mut pipeline = bootstrap::build(&pipeline_config, source, enrich, reducer, storage, extra);
+
+    #[cfg(target_family = "unix")]
+    if let Some(mempool) = config.mempool {
+        mempool.storage.validate()?;
+
+        let storage = mempool
+            .storage
+            .plugin(&chain, intersect, cursor::Store::Skip);
+        let stage = mempool.monitor.stage(&chain);
+
+        bootstrap::spawn_mempool(&mut pipeline, stage, storage);
+    }
+
+    Ok(pipeline)
+}
+
+impl ConfigRoot {
+    /// Takes the cursor store out of the config, see [CursorConfig::default_for]
+    pub(crate) fn cursor_store(&mut self, chain: &crosscut::ChainWellKnownInfo) -> cursor::Store {
+        let config = match self.cursor.take() {
+            Some(x) => x,
+            None => CursorConfig::default_for(&self.storage),
+        };
 
-    let reducer = reducers::Worker::new(reducer_plugins);
+        config.store(chain)
+    }
+
+    fn reducer_names(&self) -> Vec<&'static str> {
+        self.reducers.iter().map(|x| x.reducer.name()).collect()
+    }
+}
+
+/// Reads the filters and allowlists from the config file again. Only the
+/// scope is reloaded: a config with a different set of reducers is refused,
+/// changes to any other section need a restart.
+fn reload_scope(
+    args: &ArgMatches,
+    running: &[&'static str],
+) -> Result<crosscut::Scope, scrolls::Error> {
+    let mut config = load_config(args)?;
+
+    select_reducers(&mut config, args)?;
+
+    if config.reducer_names() != running {
+        return Err(scrolls::Error::config(
+            "the set of reducers changed, a restart is required to apply it",
+        ));
+    }
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let allowlists = config
+        .reducers
+        .iter()
+        .map(|x| x.reducer.allowlists(&chain))
+        .collect::<Result<_, _>>()?;
+
+    Ok(crosscut::Scope {
+        filters: config.filters.unwrap_or_default(),
+        allowlists,
+    })
+}
+
+/// Hands the pause gate over to the sources through the runtime policy
+fn share_pause(config: &mut ConfigRoot, pause: &crosscut::PauseGate) {
+    let mut policy = config.policy.take().unwrap_or_default();
+    policy.pause = pause.clone();
+    config.policy = Some(policy);
+}
+
+/// The range of blocks processed by the catch-up and the point where the live
+/// phase takes over
+#[cfg(target_family = "unix")]
+#[derive(Debug)]
+struct Handoff {
+    range: crosscut::IntersectConfig,
+    live: crosscut::PointArg,
+}
+
+/// Plans the catch-up from the start intersect up to the tip of the catch-up
+/// node, none if there's nothing to catch up
+///
+/// The range intersects at its first point, so it starts with the next block,
+/// and the storage stops after the block of the tip, leaving the cursor on it.
+/// The live phase intersects at that cursor and resumes with the block after
+/// the tip, so no block is skipped or processed twice.
+#[cfg(target_family = "unix")]
+fn plan_handoff(
+    intersect: &crosscut::IntersectConfig,
+    chain: &crosscut::ChainWellKnownInfo,
+    tip: crosscut::PointArg,
+) -> Result<Option<Handoff>, scrolls::Error> {
+    let from = match intersect.clone().resolve(chain)? {
+        crosscut::IntersectConfig::Origin => crosscut::PointArg::Origin,
+        crosscut::IntersectConfig::Point(x) => x,
+        _ => {
+            log::warn!("catch-up only supports origin or point intersects, following right away");
+            return Ok(None);
+        }
+    };
+
+    let behind = match (&from, &tip) {
+        (_, crosscut::PointArg::Origin) => false,
+        (crosscut::PointArg::Origin, _) => true,
+        (crosscut::PointArg::Specific(a, _), crosscut::PointArg::Specific(b, _)) => a < b,
+    };
+
+    if !behind {
+        log::info!("already past the tip of the catch-up node, following right away");
+        return Ok(None);
+    }
+
+    Ok(Some(Handoff {
+        range: crosscut::IntersectConfig::Range(from, tip.clone()),
+        live: tip,
+    }))
+}
+
+/// Processes the blocks up to the current tip of the catch-up node, so that
+/// the cursor points at it once done, see [plan_handoff]. Returns the
+/// shutdown if interrupted.
+#[cfg(target_family = "unix")]
+fn run_catch_up(
+    args: &ArgMatches,
+    intersect: &crosscut::IntersectConfig,
+    lock: &mut Option<crosscut::AdvisoryLock>,
+    pause: &crosscut::PauseGate,
+) -> Result<Option<Shutdown>, scrolls::Error> {
+    let mut config = load_config(args)?;
+
+    select_reducers(&mut config, args)?;
+    share_pause(&mut config, pause);
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let source = match config.source {
+        SourceConfig::CatchUp(x) => x.catch_up,
+        _ => return Ok(None),
+    };
+
+    let tip = crosscut::PointArg::from(source.find_tip(&chain)?);
+
+    let handoff = match plan_handoff(intersect, &chain, tip)? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    log::info!("catching up to {} over n2c", handoff.live.to_string());
+
+    config.source = SourceConfig::N2C(source);
+
+    // the live phase polls the mempool, once caught up
+    config.mempool = None;
+
+    let cursor_store = config.cursor_store(&chain);
+    let pipeline = build_pipeline(config, &handoff.range, cursor_store, &None, None)?;
+
+    let (name, storage) = pipeline
+        .tethers
+        .last()
+        .expect("pipeline has a storage stage");
+
+    loop {
+        if shutdown::signaled() {
+            return Ok(Some(Shutdown::new(Reason::Signal).with_progress(storage)));
+        }
+
+        if shutdown::resume_requested() {
+            pause.resume();
+        }
+
+        if let Some(lock) = lock.as_mut() {
+            if !lock.refresh()? {
+                log::error!("advisory lock was taken over by another instance");
+                return Ok(Some(Shutdown::new(Reason::LockLost).with_progress(storage)));
+            }
+        }
+
+        if let gasket::runtime::TetherState::Dropped = storage.check_state() {
+            log::info!(
+                "{} stage finished, following from {}",
+                name,
+                handoff.live.to_string()
+            );
+            return Ok(None);
+        }
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let mut config = load_config(args)?;
+
+    select_reducers(&mut config, args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let cursor_store = config.cursor_store(&chain);
+
+    let cursor = cursor_store.load()?;
+
+    match &cursor {
+        Some(x) => log::info!("found existing cursor: {:?}", x),
+        None => log::debug!("no cursor found in cursor store"),
+    };
+
+    let plan = crosscut::resolve_start(
+        &config.intersect,
+        cursor,
+        start_override(args)?,
+        args.is_present("reset"),
+    );
+
+    let policy = config.policy.clone().unwrap_or_default();
+
+    check_explicit_origin(&policy, &plan.intersect, resync_confirmed(args))?;
+
+    let coordination = config.coordination.take().unwrap_or_default();
+
+    let mut lock = match &coordination.lock {
+        Some(x) => Some(crosscut::AdvisoryLock::acquire(x)?),
+        None => None,
+    };
+
+    coordination.apply_startup_jitter();
 
-    let pipeline = bootstrap::build(source, reducer, storage);
+    let mut exporter = match config.metrics.take() {
+        Some(x) => Exporter::start(&x, chain.magic)?,
+        None => None,
+    };
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = config.grpc.take() {
+        scrolls::grpc::start(&grpc, config.storage.grpc_source()?)?;
+    }
+
+    shutdown::watch_signals();
+
+    let pause = match policy.start_paused.unwrap_or(false) {
+        true => {
+            log::warn!("starting paused, send SIGUSR1 to start processing blocks");
+            crosscut::PauseGate::paused()
+        }
+        false => crosscut::PauseGate::default(),
+    };
+
+    share_pause(&mut config, &pause);
+
+    #[cfg(target_family = "unix")]
+    let plan = match &config.source {
+        SourceConfig::CatchUp(_) => {
+            if let Some(shutdown) = run_catch_up(args, &plan.intersect, &mut lock, &pause)? {
+                return Ok(shutdown);
+            }
+
+            // the catch-up moved the cursor to the handoff point
+            crosscut::resolve_start(&config.intersect, cursor_store.load()?, None, false)
+        }
+        _ => plan,
+    };
+
+    // the plan already took the cursor into account
+    let reducer_names = config.reducer_names();
+    let scope = crosscut::ScopeHandle::default();
+
+    let pipeline = build_pipeline(
+        config,
+        &plan.intersect,
+        cursor_store,
+        &None,
+        Some(scope.clone()),
+    )?;
+
+    let (storage_name, storage) = pipeline
+        .tethers
+        .last()
+        .expect("pipeline has a storage stage");
 
     loop {
+        if shutdown::signaled() {
+            return Ok(Shutdown::new(Reason::Signal).with_progress(storage));
+        }
+
+        if shutdown::resume_requested() {
+            pause.resume();
+        }
+
+        if shutdown::reload_requested() {
+            match reload_scope(args, &reducer_names) {
+                Ok(x) => {
+                    log::info!("config reloaded, scope applies from the next block");
+                    scope.replace(x);
+                }
+                Err(err) => log::error!("config reload failed, keeping current scope: {}", err),
+            }
+        }
+
+        if let Some(lock) = lock.as_mut() {
+            if !lock.refresh()? {
+                log::error!("advisory lock was taken over by another instance");
+                return Ok(Shutdown::new(Reason::LockLost).with_progress(storage));
+            }
+        }
+
         for (name, tether) in pipeline.tethers.iter() {
             match tether.check_state() {
-                gasket::runtime::TetherState::Dropped => log::warn!("{} stage dropped", name),
+                gasket::runtime::TetherState::Dropped => {
+                    log::warn!("{} stage dropped", name);
+
+                    let reason = match name == storage_name || name.starts_with("storage.") {
+                        true => Reason::StorageStopped(name.clone()),
+                        false => Reason::StageStopped(name.clone()),
+                    };
+
+                    return Ok(Shutdown::new(reason).with_progress(storage));
+                }
                 gasket::runtime::TetherState::Blocked(x) => {
                     log::warn!("{} stage blocked, state: {:?}", name, x);
                 }
@@ -191,6 +1226,10 @@ pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
             match tether.read_metrics() {
                 Ok(readings) => {
                     for (key, value) in readings {
+                        if let Some(exporter) = exporter.as_mut() {
+                            exporter.set(name, key.as_ref(), &value);
+                        }
+
                         log::info!("stage {}, metric {}: {:?}", name, key, value);
                     }
                 }
@@ -203,20 +1242,354 @@ pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
 
         std::thread::sleep(Duration::from_secs(5));
     }
-
-    //for (name, tether) in tethers {
-    //    log::warn!("{}", name);
-    //    tether.dismiss_stage().expect("stage stops");
-    //    tether.join_stage();
-    //}
 }
 
 /// Creates the clap definition for this sub-command
 pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
-    clap::Command::new("daemon").arg(
-        clap::Arg::new("config")
-            .long("config")
-            .takes_value(true)
-            .help("config file to load by the daemon"),
-    )
+    clap::Command::new("daemon")
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help(
+                    "config file to load by the daemon, repeat to merge overrides over a base file",
+                ),
+        )
+        .arg(
+            clap::Arg::new("confirm-resync")
+                .long("confirm-resync")
+                .help("allow the daemon to start syncing from origin"),
+        )
+        .arg(
+            clap::Arg::new("start")
+                .long("start")
+                .takes_value(true)
+                .help("start from this point (`slot,hex-hash`), overriding cursor and config"),
+        )
+        .arg(
+            clap::Arg::new("reset")
+                .long("reset")
+                .help("ignore the persisted cursor and start from the config intersect"),
+        )
+        .arg(reducers_arg())
+}
+
+/// Arg shared by the commands that run the reducers of the config
+pub(crate) fn reducers_arg<'a>() -> clap::Arg<'a> {
+    clap::Arg::new("reducers")
+        .long("reducers")
+        .takes_value(true)
+        .help("comma-separated names of the configured reducers to run, the others are ignored")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(names: &str) -> Result<Vec<&'static str>, scrolls::Error> {
+        let mut config: ConfigRoot = serde_json::from_value(serde_json::json!({
+            "source": { "type": "N2N", "address": "localhost:3001" },
+            "reducers": [
+                { "type": "PointByTx" },
+                { "type": "TotalTransactionsCount" },
+                { "type": "PointByTx", "key_prefix": "other" },
+            ],
+            "storage": { "type": "Memory" },
+            "intersect": { "type": "Origin" },
+        }))
+        .unwrap();
+
+        let args = command_definition()
+            .try_get_matches_from(["daemon", "--reducers", names])
+            .unwrap();
+
+        select_reducers(&mut config, &args)?;
+
+        Ok(config.reducer_names())
+    }
+
+    #[test]
+    fn reducers_flag_keeps_the_configured_instances_it_names() {
+        assert_eq!(
+            select("point_by_tx").unwrap(),
+            vec!["PointByTx", "PointByTx"]
+        );
+
+        assert_eq!(
+            select("TotalTransactionsCount, point-by-tx").unwrap(),
+            vec!["PointByTx", "TotalTransactionsCount", "PointByTx"]
+        );
+
+        // configured names only, even for a reducer that exists
+        let err = select("point_by_tx,balance_by_address").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("PointByTx, TotalTransactionsCount, PointByTx"));
+
+        assert!(select(",").is_err());
+    }
+
+    #[test]
+    fn origin_start_needs_a_confirmation() {
+        let guarded = crosscut::RuntimePolicy {
+            require_explicit_origin: Some(true),
+            ..Default::default()
+        };
+
+        let origin = crosscut::IntersectConfig::Origin;
+        let point = crosscut::IntersectConfig::Point(crosscut::PointArg::Specific(
+            100,
+            hex::encode([1; 32]),
+        ));
+
+        assert!(check_explicit_origin(&guarded, &origin, false).is_err());
+        assert!(check_explicit_origin(&guarded, &origin, true).is_ok());
+        assert!(check_explicit_origin(&guarded, &point, false).is_ok());
+
+        let unguarded = crosscut::RuntimePolicy::default();
+        assert!(check_explicit_origin(&unguarded, &origin, false).is_ok());
+    }
+
+    #[test]
+    fn redis_storage_keeps_the_cursor_by_default() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+
+        let storage: StorageConfig = serde_json::from_value(serde_json::json!({
+            "type": "Redis",
+            "connection_params": "redis://127.0.0.1:6379",
+        }))
+        .unwrap();
+
+        let store = CursorConfig::default_for(&storage).store(&chain);
+
+        assert!(matches!(
+            &store,
+            cursor::Store::Redis(x)
+                if x.key() == "_cursor" && x.shares_connection("redis://127.0.0.1:6379")
+        ));
+
+        let storage = StorageConfig::Memory(Default::default());
+        let store = CursorConfig::default_for(&storage).store(&chain);
+        assert!(matches!(store, cursor::Store::File(_)));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn handoff_neither_repeats_nor_skips_a_block() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+
+        let point = |slot: u64| crosscut::PointArg::Specific(slot, hex::encode([slot as u8; 32]));
+        let slots: Vec<u64> = (1..=10).map(|x| x * 20).collect();
+        let tip = point(100);
+
+        // blocks the sources deliver after intersecting at the point
+        let after = |from: &crosscut::PointArg| -> Vec<u64> {
+            slots
+                .iter()
+                .copied()
+                .filter(|x| match from {
+                    crosscut::PointArg::Origin => true,
+                    crosscut::PointArg::Specific(slot, _) => x > slot,
+                })
+                .collect()
+        };
+
+        // the storage stops after the first block at or past the end
+        let range = |from: &crosscut::PointArg, to: crosscut::PointArg| -> Vec<u64> {
+            match to {
+                crosscut::PointArg::Specific(end, _) => {
+                    let mut blocks = after(from);
+                    let last = blocks.iter().position(|x| *x >= end).unwrap();
+                    blocks.truncate(last + 1);
+                    blocks
+                }
+                crosscut::PointArg::Origin => vec![],
+            }
+        };
+
+        let starts = [
+            crosscut::IntersectConfig::Origin,
+            crosscut::IntersectConfig::Point(point(40)),
+            crosscut::IntersectConfig::Point(tip.clone()),
+            crosscut::IntersectConfig::Point(point(140)),
+        ];
+
+        for start in starts {
+            let from = match &start {
+                crosscut::IntersectConfig::Point(x) => x.clone(),
+                _ => crosscut::PointArg::Origin,
+            };
+
+            let processed = match plan_handoff(&start, &chain, tip.clone()).unwrap() {
+                Some(Handoff {
+                    range: crosscut::IntersectConfig::Range(a, b),
+                    live,
+                }) => {
+                    assert_eq!(b.to_string(), tip.to_string());
+                    assert_eq!(live.to_string(), tip.to_string());
+
+                    let mut blocks = range(&a, b);
+                    blocks.extend(after(&live));
+                    blocks
+                }
+                Some(other) => panic!("unexpected catch-up {:?}", other),
+                None => after(&from),
+            };
+
+            assert_eq!(processed, after(&from), "starting from {:?}", start);
+        }
+
+        // nothing to catch up from an empty node or without a known start
+        assert!(plan_handoff(
+            &crosscut::IntersectConfig::Origin,
+            &chain,
+            crosscut::PointArg::Origin
+        )
+        .unwrap()
+        .is_none());
+
+        assert!(plan_handoff(&crosscut::IntersectConfig::Tip, &chain, tip)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn override_file_merges_over_the_base_one() {
+        let dir = std::env::temp_dir().join(format!("scrolls-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let base = dir.join("base.toml");
+        let overrides = dir.join("overrides.toml");
+
+        std::fs::write(
+            &base,
+            r#"
+[source]
+type = "N2N"
+address = "localhost:3001"
+
+[storage]
+type = "Redis"
+connection_params = "redis://base:6379"
+commit_every_n_blocks = 10
+
+[intersect]
+type = "Origin"
+
+[[reducers]]
+type = "UtxoByAddress"
+
+[[reducers]]
+type = "PointByTx"
+"#,
+        )
+        .unwrap();
+
+        std::fs::write(
+            &overrides,
+            r#"
+[storage]
+connection_params = "redis://override:6379"
+"#,
+        )
+        .unwrap();
+
+        let args = command_definition()
+            .try_get_matches_from([
+                "daemon",
+                "--config",
+                base.to_str().unwrap(),
+                "--config",
+                overrides.to_str().unwrap(),
+            ])
+            .unwrap();
+
+        let config = load_config(&args).unwrap();
+
+        let reducers: Vec<_> = config.reducers.iter().map(|x| x.reducer.name()).collect();
+        assert_eq!(reducers, vec!["UtxoByAddress", "PointByTx"]);
+
+        match &config.storage {
+            StorageConfig::Redis(x) => {
+                assert_eq!(x.connection_params, "redis://override:6379");
+                assert_eq!(x.commit_every_n_blocks, Some(10));
+            }
+            _ => panic!("storage type should come from the base file"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sighup_reloads_the_allowlists_from_the_config_file() {
+        const FIRST: &str = "addr1qx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzer3n0d3vllmyqwsx5wktcd8cc3sq835lu7drv2xwl2wywfgse35a3x";
+
+        let second = format!("61{}", "01".repeat(28));
+
+        let dir = std::env::temp_dir().join(format!("scrolls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("daemon.toml");
+
+        let write_config = |reducers: &str| {
+            let config = format!(
+                r#"
+[source]
+type = "N2N"
+address = "localhost:3001"
+
+[storage]
+type = "Memory"
+
+[intersect]
+type = "Origin"
+
+{}
+"#,
+                reducers
+            );
+
+            std::fs::write(&path, config).unwrap();
+        };
+
+        let allowlist = |addresses: &[&str]| {
+            let quoted: Vec<_> = addresses.iter().map(|x| format!("\"{}\"", x)).collect();
+            format!(
+                "[[reducers]]\ntype = \"UtxoByAddress\"\naddresses = [{}]",
+                quoted.join(", ")
+            )
+        };
+
+        let args = command_definition()
+            .try_get_matches_from(["daemon", "--config", path.to_str().unwrap()])
+            .unwrap();
+
+        let running = vec!["UtxoByAddress"];
+
+        shutdown::watch_signals();
+        write_config(&allowlist(&[FIRST, &second]));
+
+        signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+
+        while !shutdown::reload_requested() {
+            assert!(std::time::Instant::now() < deadline, "SIGHUP wasn't seen");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert!(!shutdown::signaled());
+
+        let scope = reload_scope(&args, &running).unwrap();
+        let addresses = scope.allowlists[0].addresses.as_ref().unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert!(addresses.contains(&hex::decode(&second).unwrap()));
+
+        // invalid configs and other reducer sets are refused
+        write_config(&allowlist(&["not-an-address"]));
+        assert!(reload_scope(&args, &running).is_err());
+
+        write_config("[[reducers]]\ntype = \"PointByTx\"");
+        assert!(reload_scope(&args, &running).is_err());
+    }
 }
@@ -0,0 +1,72 @@
+use std::{fs::File, io::BufWriter};
+
+use clap::ArgMatches;
+use scrolls::{crosscut, cursor::CursorStore, storage::snapshot};
+
+use crate::{
+    daemon,
+    shutdown::{Reason, Shutdown},
+};
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let path: String = args
+        .value_of_t("file")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let mut config = daemon::load_config(args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let cursor = config.cursor_store(&chain).load()?;
+
+    let mut source = config.storage.snapshotter()?;
+
+    let file = File::create(&path).map_err(scrolls::Error::storage)?;
+    let mut writer = snapshot::Writer::new(BufWriter::new(file), &cursor)?;
+
+    let mut count = 0;
+
+    let mut visit = |entry: snapshot::Entry| -> Result<(), scrolls::Error> {
+        writer.write(&entry)?;
+        count += 1;
+        Ok(())
+    };
+
+    match args.value_of("prefix") {
+        Some(prefix) => source.scan_prefix(prefix, &mut visit)?,
+        None => source.export(&mut visit)?,
+    };
+
+    writer.finish()?;
+
+    log::info!("exported {} keys to {}", count, path);
+
+    Ok(Shutdown::new(Reason::Completed))
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("export-snapshot")
+        .about("dumps the whole storage keyspace and the cursor into a portable file")
+        .arg(
+            clap::Arg::new("file")
+                .required(true)
+                .takes_value(true)
+                .help("path of the snapshot file to write"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file pointing at the storage to export"),
+        )
+        .arg(
+            clap::Arg::new("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .help("only export the keys that start with this prefix, eg: a single collection"),
+        )
+}
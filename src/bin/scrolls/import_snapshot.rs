@@ -0,0 +1,78 @@
+use std::{fs::File, io::BufReader};
+
+use clap::ArgMatches;
+use scrolls::{crosscut, cursor::CursorStore, storage::snapshot};
+
+use crate::{
+    daemon,
+    shutdown::{Reason, Shutdown},
+};
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let path: String = args
+        .value_of_t("file")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let mut config = daemon::load_config(args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let mut cursor_store = config.cursor_store(&chain);
+
+    if let Some(existing) = cursor_store.load()? {
+        if !args.is_present("force") {
+            return Err(scrolls::Error::message(format!(
+                "target already has a cursor at {}, use --force to overwrite its data",
+                existing
+            )));
+        }
+    }
+
+    let mut target = config.storage.snapshotter()?;
+
+    let file = File::open(&path).map_err(scrolls::Error::storage)?;
+    let mut reader = snapshot::Reader::new(BufReader::new(file))?;
+
+    let mut count = 0;
+
+    while let Some(entry) = reader.read()? {
+        target.import(entry)?;
+        count += 1;
+    }
+
+    // the cursor goes last so that an interrupted import doesn't look complete
+    if let Some(cursor) = &reader.cursor {
+        cursor_store.save(cursor)?;
+        log::info!("cursor set to {}", cursor.to_string());
+    }
+
+    log::info!("imported {} keys from {}", count, path);
+
+    Ok(Shutdown::new(Reason::Completed))
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("import-snapshot")
+        .about("restores the storage keyspace and the cursor from a snapshot file")
+        .arg(
+            clap::Arg::new("file")
+                .required(true)
+                .takes_value(true)
+                .help("path of the snapshot file to read"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file pointing at the storage to restore"),
+        )
+        .arg(
+            clap::Arg::new("force")
+                .long("force")
+                .help("import even if the target storage already has a cursor"),
+        )
+}
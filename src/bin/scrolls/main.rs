@@ -1,7 +1,16 @@
 use clap::Command;
 use std::process;
 
+mod backfill;
 mod daemon;
+mod export_snapshot;
+mod import_snapshot;
+mod materialize;
+mod metrics;
+mod seed;
+mod set_cursor;
+mod shutdown;
+mod watch;
 
 fn main() {
     let args = Command::new("app")
@@ -9,18 +18,39 @@ fn main() {
         .about("cardano cache")
         .version(env!("CARGO_PKG_VERSION"))
         .subcommand(daemon::command_definition())
+        .subcommand(set_cursor::command_definition())
+        .subcommand(backfill::command_definition())
+        .subcommand(export_snapshot::command_definition())
+        .subcommand(import_snapshot::command_definition())
+        .subcommand(materialize::command_definition())
+        .subcommand(seed::command_definition())
+        .subcommand(watch::command_definition())
         .arg_required_else_help(true)
         .get_matches();
 
     let result = match args.subcommand() {
         Some(("daemon", args)) => daemon::run(args),
+        Some(("set-cursor", args)) => {
+            set_cursor::run(args).map(|_| shutdown::Shutdown::new(shutdown::Reason::Completed))
+        }
+        Some(("backfill", args)) => backfill::run(args),
+        Some(("export-snapshot", args)) => export_snapshot::run(args),
+        Some(("import-snapshot", args)) => import_snapshot::run(args),
+        Some(("materialize", args)) => materialize::run(args),
+        Some(("seed", args)) => seed::run(args),
+        Some(("watch", args)) => watch::run(args),
         _ => Err(scrolls::Error::ConfigError("nothing to do".to_string())),
     };
 
-    if let Err(err) = &result {
-        eprintln!("ERROR: {:#?}", err);
-        process::exit(1);
-    }
+    let shutdown = match result {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("ERROR: {:#?}", err);
+            shutdown::Shutdown::from(err)
+        }
+    };
+
+    shutdown.log();
 
-    process::exit(0);
+    process::exit(shutdown.exit_code());
 }
@@ -0,0 +1,192 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::ArgMatches;
+use gasket::messaging::{connect_ports, Message, OutputPort};
+use pallas::network::miniprotocols::Point;
+use scrolls::{bootstrap, crosscut, cursor::CursorStore, model::CRDTCommand, storage::ops};
+
+use crate::{
+    daemon,
+    shutdown::{self, Reason, Shutdown},
+};
+
+const QUEUE_DEPTH: usize = 100;
+
+fn parse_line(line: &str) -> Result<(Option<crosscut::PointArg>, CRDTCommand), scrolls::Error> {
+    let payload: serde_json::Value = serde_json::from_str(line)
+        .map_err(|err| scrolls::Error::message(format!("invalid op: {}", err)))?;
+
+    let key = payload
+        .get("key")
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| scrolls::Error::message("op is missing the `key` field"))?
+        .to_string();
+
+    let point = match payload.get("point").and_then(|x| x.as_str()) {
+        Some(x) => Some(crosscut::PointArg::from_str(x)?),
+        None => None,
+    };
+
+    Ok((point, ops::from_json(key, &payload)?))
+}
+
+fn send(port: &mut OutputPort<CRDTCommand>, command: CRDTCommand) -> Result<(), scrolls::Error> {
+    port.send(Message::from(command))
+        .map_err(|err| scrolls::Error::message(format!("storage stage is gone: {:?}", err)))
+}
+
+fn block_count(storage: &gasket::runtime::Tether) -> u64 {
+    match storage.read_metrics() {
+        Ok(readings) => readings
+            .into_iter()
+            .find_map(|(key, value)| match (key, value) {
+                ("block_count", gasket::metrics::Reading::Count(x)) => Some(x),
+                _ => None,
+            })
+            .unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let path: String = args
+        .value_of_t("file")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let mut config = daemon::load_config(args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.take().unwrap_or_default().into();
+
+    let cursor_store = config.cursor_store(&chain);
+
+    if let Some(existing) = cursor_store.load()? {
+        if !args.is_present("force") {
+            return Err(scrolls::Error::message(format!(
+                "target already has a cursor at {}, use --force to apply the ops on top of its data",
+                existing
+            )));
+        }
+    }
+
+    // the end of the log is detected from the blocks processed by the storage,
+    // a batch left pending would never be committed
+    if let daemon::StorageConfig::Redis(c) = &mut config.storage {
+        c.commit_every_n_blocks = None;
+    }
+
+    let mut target =
+        config
+            .storage
+            .plugin(&chain, &crosscut::IntersectConfig::Origin, cursor_store);
+
+    let mut port = OutputPort::default();
+    connect_ports(&mut port, target.borrow_input_port(), QUEUE_DEPTH);
+
+    let mut pipeline = bootstrap::Pipeline::new();
+    target.spawn(&mut pipeline);
+
+    let (_, storage) = pipeline
+        .tethers
+        .last()
+        .expect("pipeline has a storage stage");
+
+    shutdown::watch_signals();
+
+    let file = File::open(&path).map_err(scrolls::Error::storage)?;
+
+    // ops are grouped in blocks by their point, ops without one (eg: the undos
+    // of a rollback) are applied along with the block that precedes them
+    let mut current: Option<crosscut::PointArg> = None;
+    let mut last = crosscut::PointArg::Origin;
+    let mut blocks = 0;
+    let mut count = 0;
+
+    for line in BufReader::new(file).lines() {
+        if shutdown::signaled() {
+            return Ok(Shutdown::new(Reason::Signal).with_progress(storage));
+        }
+
+        let line = line.map_err(scrolls::Error::storage)?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (point, command) = parse_line(&line)?;
+        let point = point.unwrap_or_else(|| last.clone());
+
+        let same_block = matches!(&current, Some(x) if x.to_string() == point.to_string());
+
+        if !same_block {
+            if let Some(previous) = current.take() {
+                send(&mut port, CRDTCommand::BlockFinished(previous.try_into()?))?;
+                blocks += 1;
+            }
+
+            let starting: Point = point.clone().try_into()?;
+            send(&mut port, CRDTCommand::BlockStarting(starting))?;
+            current = Some(point.clone());
+        }
+
+        send(&mut port, command)?;
+        last = point;
+        count += 1;
+    }
+
+    if let Some(previous) = current.take() {
+        send(&mut port, CRDTCommand::BlockFinished(previous.try_into()?))?;
+        blocks += 1;
+    }
+
+    log::info!("read {} ops in {} blocks from {}", count, blocks, path);
+
+    loop {
+        if shutdown::signaled() {
+            return Ok(Shutdown::new(Reason::Signal).with_progress(storage));
+        }
+
+        if let gasket::runtime::TetherState::Dropped = storage.check_state() {
+            return Ok(
+                Shutdown::new(Reason::StorageStopped("storage".into())).with_progress(storage)
+            );
+        }
+
+        if block_count(storage) >= blocks {
+            log::info!("materialized {} ops into the storage", count);
+            return Ok(Shutdown::new(Reason::Completed).with_progress(storage));
+        }
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("materialize")
+        .about("applies a log of storage ops, as published by the kafka storage, into the configured storage")
+        .arg(
+            clap::Arg::new("file")
+                .required(true)
+                .takes_value(true)
+                .help("path of the JSONL file with one op per line"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file pointing at the storage to materialize into"),
+        )
+        .arg(
+            clap::Arg::new("force")
+                .long("force")
+                .help("apply the ops even if the target storage already has a cursor"),
+        )
+}
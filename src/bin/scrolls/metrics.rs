@@ -0,0 +1,386 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+};
+
+use prometheus_exporter::prometheus::{self, IntCounterVec, IntGaugeVec, Opts};
+use serde::Deserialize;
+
+/// Only reachable from the host unless the address is set
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9186";
+const DEFAULT_STATSD_ADDRESS: &str = "127.0.0.1:8125";
+
+/// Git commit of the build, provided by the build environment
+const GIT_COMMIT: Option<&str> = option_env!("SCROLLS_GIT_COMMIT");
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExporterKind {
+    #[default]
+    Prometheus,
+    Statsd,
+    None,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// How metrics are exported, `prometheus` by default
+    pub exporter: Option<ExporterKind>,
+
+    /// Address where the `/metrics` endpoint listens, or where StatsD packets
+    /// are sent to
+    pub address: Option<String>,
+
+    /// Send the stage and network as DogStatsD tags, instead of folding the
+    /// stage into the metric name. Enabled by default.
+    pub statsd_tags: Option<bool>,
+}
+
+/// Mirrors the metrics of the pipeline stages to a monitoring system
+pub enum Exporter {
+    Prometheus(Prometheus),
+    Statsd(Statsd),
+}
+
+/// Serves the metrics of the pipeline stages in the prometheus format
+///
+/// Each stage metric becomes a `scrolls_{metric}` counter or gauge, as read
+/// from the stage, labelled by stage and refreshed every time the daemon
+/// checks on the stages.
+pub struct Prometheus {
+    counters: HashMap<String, IntCounterVec>,
+    gauges: HashMap<String, IntGaugeVec>,
+}
+
+/// Pushes the metrics of the pipeline stages over UDP in the StatsD format
+///
+/// Stage counters are sent as StatsD counters of the increase since the
+/// previous check, gauges as they are. Metrics are named `scrolls.{metric}`
+/// and tagged with the stage and network, or named
+/// `scrolls.{stage}.{metric}` when tags are disabled.
+pub struct Statsd {
+    socket: UdpSocket,
+    tags: Option<String>,
+    counts: HashMap<(String, String), u64>,
+}
+
+fn register_gauge(name: &str, help: &str, labels: &[&str]) -> Result<IntGaugeVec, scrolls::Error> {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels)
+        .map_err(|err| scrolls::Error::message(err.to_string()))?;
+
+    prometheus::register(Box::new(gauge.clone()))
+        .map_err(|err| scrolls::Error::message(err.to_string()))?;
+
+    Ok(gauge)
+}
+
+fn register_counter(
+    name: &str,
+    help: &str,
+    labels: &[&str],
+) -> Result<IntCounterVec, scrolls::Error> {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels)
+        .map_err(|err| scrolls::Error::message(err.to_string()))?;
+
+    prometheus::register(Box::new(counter.clone()))
+        .map_err(|err| scrolls::Error::message(err.to_string()))?;
+
+    Ok(counter)
+}
+
+fn metric_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    format!("scrolls_{}", key)
+}
+
+impl Exporter {
+    /// Starts the configured exporter, none if metrics are disabled
+    pub fn start(config: &MetricsConfig, magic: u64) -> Result<Option<Self>, scrolls::Error> {
+        match config.exporter.unwrap_or_default() {
+            ExporterKind::Prometheus => Ok(Some(Exporter::Prometheus(Prometheus::start(
+                config, magic,
+            )?))),
+            ExporterKind::Statsd => Ok(Some(Exporter::Statsd(Statsd::start(config, magic)?))),
+            ExporterKind::None => Ok(None),
+        }
+    }
+
+    /// Mirrors the reading of a stage metric
+    pub fn set(&mut self, stage: &str, key: &str, value: &gasket::metrics::Reading) {
+        match self {
+            Exporter::Prometheus(x) => x.set(stage, key, value),
+            Exporter::Statsd(x) => x.set(stage, key, value),
+        }
+    }
+}
+
+impl Prometheus {
+    fn start(config: &MetricsConfig, magic: u64) -> Result<Self, scrolls::Error> {
+        let address = config.address.as_deref().unwrap_or(DEFAULT_ADDRESS);
+
+        let address: SocketAddr = address
+            .parse()
+            .map_err(|_| scrolls::Error::config(format!("invalid metrics address: {}", address)))?;
+
+        let build_info = register_gauge(
+            "scrolls_build_info",
+            "version and network of the running scrolls build",
+            &["version", "git_commit", "network_magic"],
+        )?;
+
+        build_info
+            .with_label_values(&[
+                env!("CARGO_PKG_VERSION"),
+                GIT_COMMIT.unwrap_or("unknown"),
+                &magic.to_string(),
+            ])
+            .set(1);
+
+        prometheus_exporter::start(address)
+            .map_err(|err| scrolls::Error::message(format!("can't serve metrics: {}", err)))?;
+
+        log::info!("serving prometheus metrics on {}/metrics", address);
+
+        Ok(Prometheus {
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+        })
+    }
+
+    fn set(&mut self, stage: &str, key: &str, value: &gasket::metrics::Reading) {
+        let name = metric_name(key);
+        let help = format!("{} metric of the pipeline stages", key);
+
+        match value {
+            gasket::metrics::Reading::Count(x) => {
+                if !self.counters.contains_key(&name) {
+                    match register_counter(&name, &help, &["stage"]) {
+                        Ok(counter) => self.counters.insert(name.clone(), counter),
+                        Err(err) => {
+                            log::warn!("can't export metric {}: {}", key, err);
+                            return;
+                        }
+                    };
+                }
+
+                // counters are cumulative in the stages, prometheus ones can
+                // only be increased
+                let counter = self.counters[&name].with_label_values(&[stage]);
+                counter.inc_by(x.saturating_sub(counter.get()));
+            }
+            gasket::metrics::Reading::Gauge(x) => {
+                if !self.gauges.contains_key(&name) {
+                    match register_gauge(&name, &help, &["stage"]) {
+                        Ok(gauge) => self.gauges.insert(name.clone(), gauge),
+                        Err(err) => {
+                            log::warn!("can't export metric {}: {}", key, err);
+                            return;
+                        }
+                    };
+                }
+
+                self.gauges[&name].with_label_values(&[stage]).set(*x);
+            }
+        }
+    }
+}
+
+fn statsd_segment(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl Statsd {
+    fn start(config: &MetricsConfig, magic: u64) -> Result<Self, scrolls::Error> {
+        let address = config.address.as_deref().unwrap_or(DEFAULT_STATSD_ADDRESS);
+
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|x| x.connect(address).map(|_| x))
+            .map_err(|err| {
+                scrolls::Error::config(format!("invalid statsd address {}: {}", address, err))
+            })?;
+
+        let tags = match config.statsd_tags.unwrap_or(true) {
+            true => Some(format!(
+                "network_magic:{},version:{}",
+                magic,
+                env!("CARGO_PKG_VERSION")
+            )),
+            false => None,
+        };
+
+        log::info!("pushing statsd metrics to {}", address);
+
+        Ok(Statsd {
+            socket,
+            tags,
+            counts: HashMap::new(),
+        })
+    }
+
+    fn line(&self, stage: &str, key: &str, value: String, kind: &str) -> String {
+        match &self.tags {
+            Some(tags) => format!(
+                "scrolls.{}:{}|{}|#stage:{},{}",
+                statsd_segment(key),
+                value,
+                kind,
+                stage,
+                tags
+            ),
+            None => format!(
+                "scrolls.{}.{}:{}|{}",
+                statsd_segment(stage),
+                statsd_segment(key),
+                value,
+                kind
+            ),
+        }
+    }
+
+    fn set(&mut self, stage: &str, key: &str, value: &gasket::metrics::Reading) {
+        let line = match value {
+            gasket::metrics::Reading::Count(x) => {
+                let previous = self
+                    .counts
+                    .insert((stage.to_string(), key.to_string()), *x)
+                    .unwrap_or(0);
+
+                // counters are cumulative in the stages, statsd expects increases
+                let delta = x.saturating_sub(previous);
+
+                if delta == 0 {
+                    return;
+                }
+
+                self.line(stage, key, delta.to_string(), "c")
+            }
+            gasket::metrics::Reading::Gauge(x) => self.line(stage, key, x.to_string(), "g"),
+        };
+
+        if let Err(err) = self.socket.send(line.as_bytes()) {
+            log::debug!("can't send statsd metric {}: {}", key, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_serves_build_info_and_stage_metrics() {
+        // a free local port for the endpoint
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|x| x.local_addr())
+            .unwrap();
+
+        let config = MetricsConfig {
+            exporter: None,
+            address: Some(address.to_string()),
+            statsd_tags: None,
+        };
+
+        let mut exporter = match Exporter::start(&config, 764824073).unwrap() {
+            Some(Exporter::Prometheus(x)) => x,
+            _ => unreachable!("prometheus is the default exporter"),
+        };
+
+        exporter.set("reducers", "ops_count", &gasket::metrics::Reading::Count(5));
+        exporter.set(
+            "reducers",
+            "ops_count",
+            &gasket::metrics::Reading::Count(12),
+        );
+        exporter.set("storage", "last_slot", &gasket::metrics::Reading::Gauge(42));
+
+        let body = ureq::get(&format!("http://{}/metrics", address))
+            .call()
+            .unwrap()
+            .into_string()
+            .unwrap();
+
+        let build_info = body
+            .lines()
+            .find(|x| x.starts_with("scrolls_build_info{"))
+            .unwrap();
+
+        assert!(build_info.contains("network_magic=\"764824073\""));
+        assert!(build_info.contains(&format!("version=\"{}\"", env!("CARGO_PKG_VERSION"))));
+        assert!(build_info.ends_with(" 1"));
+
+        assert!(body.contains("# TYPE scrolls_ops_count counter"));
+        assert!(body.contains("scrolls_ops_count{stage=\"reducers\"} 12"));
+        assert!(body.contains("# TYPE scrolls_last_slot gauge"));
+        assert!(body.contains("scrolls_last_slot{stage=\"storage\"} 42"));
+    }
+
+    #[test]
+    fn statsd_lines_are_pushed_over_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        let config = |statsd_tags| MetricsConfig {
+            exporter: Some(ExporterKind::Statsd),
+            address: Some(listener.local_addr().unwrap().to_string()),
+            statsd_tags,
+        };
+
+        let recv = || {
+            let mut buffer = [0; 512];
+            let size = listener.recv(&mut buffer).unwrap();
+            String::from_utf8(buffer[..size].to_vec()).unwrap()
+        };
+
+        let mut exporter = match Exporter::start(&config(None), 764824073).unwrap() {
+            Some(exporter @ Exporter::Statsd(_)) => exporter,
+            _ => unreachable!("statsd is configured"),
+        };
+
+        let tags = format!(
+            "network_magic:764824073,version:{}",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        // the stage counted a block, then two more
+        exporter.set("reducers", "ops_count", &gasket::metrics::Reading::Count(1));
+        assert_eq!(
+            recv(),
+            format!("scrolls.ops_count:1|c|#stage:reducers,{}", tags)
+        );
+
+        // unchanged counters aren't sent
+        exporter.set("reducers", "ops_count", &gasket::metrics::Reading::Count(1));
+        exporter.set("reducers", "ops_count", &gasket::metrics::Reading::Count(3));
+        assert_eq!(
+            recv(),
+            format!("scrolls.ops_count:2|c|#stage:reducers,{}", tags)
+        );
+
+        exporter.set("storage", "last_slot", &gasket::metrics::Reading::Gauge(42));
+        assert_eq!(
+            recv(),
+            format!("scrolls.last_slot:42|g|#stage:storage,{}", tags)
+        );
+
+        let mut untagged = Exporter::start(&config(Some(false)), 764824073)
+            .unwrap()
+            .unwrap();
+
+        untagged.set("storage", "last_slot", &gasket::metrics::Reading::Gauge(42));
+        assert_eq!(recv(), "scrolls.storage.last_slot:42|g");
+    }
+
+    #[test]
+    fn endpoint_is_local_by_default() {
+        let address: SocketAddr = DEFAULT_ADDRESS.parse().unwrap();
+        assert!(address.ip().is_loopback());
+    }
+}
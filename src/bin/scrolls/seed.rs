@@ -0,0 +1,291 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    str::FromStr,
+};
+
+use clap::ArgMatches;
+use pallas::network::miniprotocols::Point;
+use scrolls::{crosscut, cursor::CursorStore, storage::snapshot};
+
+use crate::{
+    daemon,
+    shutdown::{Reason, Shutdown},
+};
+
+/// Reads `key,value` lines, blank lines and lines starting with `#` are
+/// skipped
+fn read_csv(file: File) -> Result<Vec<(String, String)>, scrolls::Error> {
+    let mut entries = Vec::new();
+
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(scrolls::Error::storage)?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line.split_once(',').ok_or_else(|| {
+            scrolls::Error::message(format!("line {} should be `key,value`", idx + 1))
+        })?;
+
+        entries.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(entries)
+}
+
+/// Reads a flat JSON object of keys to numbers or strings
+fn read_json(file: File) -> Result<Vec<(String, String)>, scrolls::Error> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| scrolls::Error::message(format!("invalid seed file: {}", err)))?;
+
+    object
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(x) => Ok((key, x)),
+            serde_json::Value::Number(x) => Ok((key, x.to_string())),
+            _ => Err(scrolls::Error::message(format!(
+                "value of {} should be a number or a string",
+                key
+            ))),
+        })
+        .collect()
+}
+
+/// Writes the entries under the prefix and sets the cursor to the point of
+/// the dump, refusing to seed over a target that has a cursor unless forced
+fn seed(
+    target: &mut dyn snapshot::Snapshottable,
+    cursor_store: &mut impl CursorStore,
+    prefix: &str,
+    entries: Vec<(String, String)>,
+    raw_point: &str,
+    force: bool,
+) -> Result<crosscut::PointArg, scrolls::Error> {
+    let point = crosscut::PointArg::from_str(raw_point)?;
+
+    // make sure that the point is valid before we touch the storage
+    let _: Point = point.clone().try_into()?;
+
+    if let Some(existing) = cursor_store.load()? {
+        if !force {
+            return Err(scrolls::Error::message(format!(
+                "target already has a cursor at {}, use --force to seed over its data",
+                existing
+            )));
+        }
+    }
+
+    for (key, value) in entries {
+        let key = format!("{}.{}", prefix, key);
+        target.import(snapshot::Entry::String(key, value.into_bytes()))?;
+    }
+
+    // the cursor goes last so that an interrupted seed doesn't look complete
+    cursor_store.save(&point)?;
+
+    Ok(point)
+}
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let path: String = args
+        .value_of_t("file")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let prefix: String = args
+        .value_of_t("key-prefix")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let raw_point: String = args
+        .value_of_t("point")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let mut config = daemon::load_config(args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let mut cursor_store = config.cursor_store(&chain);
+
+    let file = File::open(&path).map_err(scrolls::Error::storage)?;
+
+    let entries = match path.ends_with(".json") {
+        true => read_json(file)?,
+        false => read_csv(file)?,
+    };
+
+    let count = entries.len();
+
+    let mut target = config.storage.snapshotter()?;
+
+    let point = seed(
+        target.as_mut(),
+        &mut cursor_store,
+        &prefix,
+        entries,
+        &raw_point,
+        args.is_present("force"),
+    )?;
+
+    log::info!(
+        "seeded {} keys of {}, cursor set to {}",
+        count,
+        prefix,
+        point.to_string()
+    );
+
+    Ok(Shutdown::new(Reason::Completed))
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("seed")
+        .about("seeds the values of a collection from an external dump taken at a known point")
+        .arg(
+            clap::Arg::new("file")
+                .required(true)
+                .takes_value(true)
+                .help("CSV (`key,value` lines) or JSON object file with the values to seed"),
+        )
+        .arg(
+            clap::Arg::new("key-prefix")
+                .long("key-prefix")
+                .required(true)
+                .takes_value(true)
+                .help("key prefix of the collection to seed"),
+        )
+        .arg(
+            clap::Arg::new("point")
+                .long("point")
+                .required(true)
+                .takes_value(true)
+                .help("chain point of the dump, in `slot,hex-hash` format"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file pointing at the storage to seed"),
+        )
+        .arg(
+            clap::Arg::new("force")
+                .long("force")
+                .help("seed even if the target storage already has a cursor"),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{mpsc, Arc, Mutex},
+    };
+
+    use pallas::network::multiplexer::Channel;
+    use scrolls::{model, storage::memory};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("scrolls-seed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn seeded_balances_are_updated_from_the_point_of_the_dump() {
+        let path = temp_path("balances.csv");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "# stake credential,lovelace").unwrap();
+        writeln!(file, "aa,5000000").unwrap();
+        writeln!(file, "bb, 7000000").unwrap();
+        drop(file);
+
+        let entries = read_csv(File::open(&path).unwrap()).unwrap();
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut cursor_store = scrolls::cursor::file::Config {
+            path: Some(temp_path("seed.cursor").to_string_lossy().to_string()),
+            integrity: None,
+        }
+        .store(&chain);
+
+        let store = Arc::new(Mutex::new(memory::Store::default()));
+        let mut target = memory::Snapshotter::new(store.clone());
+
+        let dump = format!("1000,{}", "ab".repeat(32));
+
+        assert!(seed(
+            &mut target,
+            &mut cursor_store,
+            "stake_balance",
+            entries.clone(),
+            "1000,not-hex",
+            false
+        )
+        .is_err());
+        assert!(store.lock().unwrap().string("stake_balance.aa").is_none());
+
+        seed(
+            &mut target,
+            &mut cursor_store,
+            "stake_balance",
+            entries.clone(),
+            &dump,
+            false,
+        )
+        .unwrap();
+
+        // the sync resumes right after the dump, whatever the intersect config
+        let (to_node, _) = mpsc::channel();
+        let (_, from_node) = mpsc::channel();
+        let mut channel = Channel(to_node, from_node);
+
+        let known = scrolls::sources::utils::define_known_points(
+            &chain,
+            &crosscut::IntersectConfig::Origin,
+            &cursor_store.load().unwrap(),
+            &mut channel,
+        )
+        .unwrap();
+
+        let point: Point = crosscut::PointArg::from_str(&dump)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(known, Some(vec![point]));
+
+        // the next block spends from a seeded balance
+        let mut store = store.lock().unwrap();
+        store
+            .apply(model::CRDTCommand::PNCounter(
+                "stake_balance.aa".into(),
+                "-2000000".into(),
+            ))
+            .unwrap();
+
+        assert_eq!(store.counter("stake_balance.aa"), Some(3_000_000));
+        assert_eq!(store.counter("stake_balance.bb"), Some(7_000_000));
+        drop(store);
+
+        // a second seed would overwrite the synced data
+        assert!(seed(
+            &mut target,
+            &mut cursor_store,
+            "stake_balance",
+            entries,
+            &dump,
+            false
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use pallas::network::miniprotocols::Point;
+use scrolls::{crosscut, cursor::CursorStore};
+
+use crate::daemon;
+
+/// Validates the point and writes it to the store, refusing to replace an
+/// existing cursor unless forced
+fn set_cursor(
+    store: &mut impl CursorStore,
+    raw_point: &str,
+    force: bool,
+) -> Result<crosscut::PointArg, scrolls::Error> {
+    let point = crosscut::PointArg::from_str(raw_point)?;
+
+    // make sure that the point is valid before we touch the cursor store
+    let _: Point = point.clone().try_into()?;
+
+    if let Some(existing) = store.load()? {
+        if !force {
+            return Err(scrolls::Error::message(format!(
+                "a cursor is already stored at {}, use --force to overwrite it",
+                existing
+            )));
+        }
+
+        log::warn!("overwriting existing cursor {}", existing.to_string());
+    }
+
+    store.save(&point)?;
+
+    Ok(point)
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
+    env_logger::init();
+
+    let raw_point: String = args
+        .value_of_t("point")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let mut config = daemon::load_config(args)?;
+
+    let chain: crosscut::ChainWellKnownInfo = config.chain.clone().unwrap_or_default().into();
+
+    let mut store = config.cursor_store(&chain);
+
+    let point = set_cursor(&mut store, &raw_point, args.is_present("force"))?;
+
+    log::info!("cursor set to {}", point.to_string());
+
+    Ok(())
+}
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("set-cursor")
+        .about("forces the stored cursor to a specific chain point")
+        .arg(
+            clap::Arg::new("point")
+                .required(true)
+                .takes_value(true)
+                .help("chain point in `slot,hex-hash` format"),
+        )
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file to load"),
+        )
+        .arg(
+            clap::Arg::new("force")
+                .long("force")
+                .help("overwrite the cursor even if one is already stored"),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_store(name: &str) -> scrolls::cursor::Store {
+        let dir = std::env::temp_dir().join(format!("scrolls-set-cursor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+
+        scrolls::cursor::file::Config {
+            path: Some(path.to_string_lossy().to_string()),
+            integrity: None,
+        }
+        .store(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    #[test]
+    fn valid_point_updates_the_cursor() {
+        let mut store = file_store("valid.cursor");
+        let first = format!("100,{}", "ab".repeat(32));
+        let second = format!("200,{}", "cd".repeat(32));
+
+        set_cursor(&mut store, &first, false).unwrap();
+        assert_eq!(store.load().unwrap().unwrap().to_string(), first);
+
+        // an existing cursor is only replaced when forced
+        assert!(set_cursor(&mut store, &second, false).is_err());
+        assert_eq!(store.load().unwrap().unwrap().to_string(), first);
+
+        set_cursor(&mut store, &second, true).unwrap();
+        assert_eq!(store.load().unwrap().unwrap().to_string(), second);
+    }
+
+    #[test]
+    fn invalid_point_errors_without_touching_the_cursor() {
+        let mut store = file_store("invalid.cursor");
+
+        assert!(set_cursor(&mut store, "100,abcd", true).is_err());
+        assert!(set_cursor(&mut store, "100,not-hex", true).is_err());
+        assert!(set_cursor(&mut store, "not-a-point", true).is_err());
+
+        assert!(store.load().unwrap().is_none());
+    }
+}
@@ -0,0 +1,230 @@
+//! Reasons for the process to stop and the exit code reported for each
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use gasket::runtime::Tether;
+
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+static RESUME: AtomicBool = AtomicBool::new(false);
+
+/// Flags the process as interrupted on SIGINT / SIGTERM so that the
+/// monitoring loop can stop gracefully, flags a reload of the config on
+/// SIGHUP and the resume of a paused pipeline on SIGUSR1
+pub fn watch_signals() {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1]) {
+        Ok(x) => x,
+        Err(err) => {
+            log::warn!("can't watch termination signals: {}", err);
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => RELOAD.store(true, Ordering::SeqCst),
+                SIGUSR1 => RESUME.store(true, Ordering::SeqCst),
+                _ => SIGNALED.store(true, Ordering::SeqCst),
+            }
+        }
+    });
+}
+
+pub fn signaled() -> bool {
+    SIGNALED.load(Ordering::SeqCst)
+}
+
+/// True once per SIGHUP received since the previous call
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// True once per SIGUSR1 received since the previous call
+pub fn resume_requested() -> bool {
+    RESUME.swap(false, Ordering::SeqCst)
+}
+
+pub enum Reason {
+    /// The requested work is done, eg: the end of a backfill range
+    Completed,
+
+    /// An interruption signal was received
+    Signal,
+
+    /// Another instance took over the advisory lock
+    LockLost,
+
+    /// The storage stage stopped, usually after a storage error
+    StorageStopped(String),
+
+    /// A stage other than storage stopped, eg: the source after a rollback
+    /// beyond what it can handle
+    StageStopped(String),
+
+    /// The process failed before or while setting up the pipeline
+    Failed(scrolls::Error),
+}
+
+impl Reason {
+    /// Exit codes reported for each reason, documented in the README
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Reason::Completed => 0,
+            Reason::Failed(scrolls::Error::ConfigError(_)) => 2,
+            Reason::Failed(scrolls::Error::StorageError(_)) => 3,
+            Reason::StorageStopped(_) => 3,
+            Reason::Failed(scrolls::Error::TransportError(_)) => 4,
+            Reason::Failed(scrolls::Error::OuroborosError(_)) => 4,
+            Reason::StageStopped(_) => 5,
+            Reason::LockLost => 6,
+            Reason::Signal => 130,
+            Reason::Failed(_) => 1,
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Completed => write!(f, "completed"),
+            Reason::Signal => write!(f, "signal"),
+            Reason::LockLost => write!(f, "lock lost"),
+            Reason::StorageStopped(stage) => write!(f, "storage stage {} stopped", stage),
+            Reason::StageStopped(stage) => write!(f, "stage {} stopped", stage),
+            Reason::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// Summary of the run reported when the process exits
+pub struct Shutdown {
+    pub reason: Reason,
+    last_slot: Option<i64>,
+    block_count: Option<u64>,
+}
+
+impl Shutdown {
+    pub fn new(reason: Reason) -> Self {
+        Shutdown {
+            reason,
+            last_slot: None,
+            block_count: None,
+        }
+    }
+
+    /// Takes the progress of the run from the metrics of the storage stage
+    pub fn with_progress(mut self, storage: &Tether) -> Self {
+        let readings = match storage.read_metrics() {
+            Ok(x) => x,
+            Err(_) => {
+                log::warn!("couldn't read storage metrics for shutdown summary");
+                return self;
+            }
+        };
+
+        for (key, value) in readings {
+            match (key, value) {
+                ("last_slot", gasket::metrics::Reading::Gauge(x)) => self.last_slot = Some(x),
+                ("block_count", gasket::metrics::Reading::Count(x)) => self.block_count = Some(x),
+                _ => (),
+            }
+        }
+
+        self
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.reason.exit_code()
+    }
+
+    pub fn log(&self) {
+        let last_slot = self
+            .last_slot
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let block_count = self
+            .block_count
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let line = format!(
+            "shutdown reason=\"{}\" exit_code={} last_slot={} blocks={}",
+            self.reason,
+            self.exit_code(),
+            last_slot,
+            block_count
+        );
+
+        match self.exit_code() {
+            0 => log::info!("{}", line),
+            _ => log::error!("{}", line),
+        }
+    }
+}
+
+impl From<scrolls::Error> for Shutdown {
+    fn from(err: scrolls::Error) -> Self {
+        Shutdown::new(Reason::Failed(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_reason_exits_with_its_documented_code() {
+        let cases = vec![
+            (Reason::Completed, 0),
+            (Reason::Failed(scrolls::Error::message("boom")), 1),
+            (Reason::Failed(scrolls::Error::ledger("bad block")), 1),
+            (Reason::Failed(scrolls::Error::config("missing field")), 2),
+            (Reason::Failed(scrolls::Error::storage("refused")), 3),
+            (Reason::StorageStopped("storage-0".to_string()), 3),
+            (
+                Reason::Failed(scrolls::Error::TransportError("timeout".to_string())),
+                4,
+            ),
+            (
+                Reason::Failed(scrolls::Error::OuroborosError("handshake".to_string())),
+                4,
+            ),
+            (Reason::StageStopped("source".to_string()), 5),
+            (Reason::LockLost, 6),
+            (Reason::Signal, 130),
+        ];
+
+        for (reason, code) in cases {
+            let described = reason.to_string();
+            let shutdown = Shutdown::new(reason);
+
+            assert_eq!(shutdown.exit_code(), code, "{}", described);
+        }
+    }
+
+    #[test]
+    fn sigusr1_requests_a_single_resume() {
+        watch_signals();
+        signal_hook::low_level::raise(SIGUSR1).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+
+        while !resume_requested() {
+            assert!(std::time::Instant::now() < deadline, "SIGUSR1 wasn't seen");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert!(!resume_requested());
+        assert!(!signaled());
+    }
+}
@@ -0,0 +1,109 @@
+use std::io::Write;
+
+use clap::ArgMatches;
+use scrolls::storage::watch::{Change, Watchable};
+
+use crate::{
+    daemon,
+    shutdown::{Reason, Shutdown},
+};
+
+/// Prints a line for each change of the keys starting with the prefix: the
+/// key followed by the event reported by the backend
+fn print_changes(
+    watcher: &mut dyn Watchable,
+    prefix: &str,
+    out: &mut dyn Write,
+) -> Result<(), scrolls::Error> {
+    watcher.watch(
+        prefix,
+        &mut |change: Change| -> Result<(), scrolls::Error> {
+            writeln!(out, "{} {}", change.key, change.event)
+                .map_err(|x| scrolls::Error::message(x.to_string()))
+        },
+    )
+}
+
+pub fn run(args: &ArgMatches) -> Result<Shutdown, scrolls::Error> {
+    env_logger::init();
+
+    let prefix = args.value_of("key-prefix").unwrap_or_default();
+
+    let config = daemon::load_config(args)?;
+
+    let mut watcher = config.storage.watcher()?;
+
+    log::info!("watching the changes of keys starting with '{}'", prefix);
+
+    print_changes(watcher.as_mut(), prefix, &mut std::io::stdout())?;
+
+    Ok(Shutdown::new(Reason::Completed))
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("watch")
+        .about("prints the changes made to the keys of a collection as they happen")
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("config file pointing at the storage to watch"),
+        )
+        .arg(
+            clap::Arg::new("key-prefix")
+                .long("key-prefix")
+                .takes_value(true)
+                .help("only print the keys that start with this prefix, eg: the key_prefix of a reducer"),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use scrolls::{
+        model::CRDTCommand,
+        storage::{memory::Store, ops},
+    };
+
+    use super::*;
+
+    #[test]
+    fn printed_changes_are_the_applied_ops() {
+        let mut store = Store::default();
+        let mut watcher = store.watcher();
+
+        let commands = vec![
+            CRDTCommand::PNCounter("count.1".into(), "5".into()),
+            CRDTCommand::GrowOnlySetAdd("other.1".into(), "x".into()),
+            CRDTCommand::PNCounter("count.1".into(), "-5".into()),
+            CRDTCommand::AnyWriteWins("count.2".into(), "abc".into()),
+        ];
+
+        let expected: Vec<_> = commands
+            .iter()
+            .filter_map(ops::to_json)
+            .filter(|(key, _)| key.starts_with("count."))
+            .map(|(key, op)| format!("{} {}", key, op))
+            .collect();
+
+        for command in commands {
+            store.apply(command).unwrap();
+        }
+
+        // the feed ends once the store is gone
+        drop(store);
+
+        let mut out = Vec::new();
+        print_changes(&mut watcher, "count.", &mut out).unwrap();
+
+        let printed: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(printed.len(), 3);
+        assert_eq!(printed, expected);
+    }
+}
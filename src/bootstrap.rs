@@ -1,13 +1,75 @@
-use crate::{reducers, sources, storage};
+use crate::{enrich, reducers, sources, storage, Error};
 
 use gasket::{messaging::connect_ports, runtime::Tether};
+use serde::Deserialize;
 
-type NamedTether = (&'static str, Tether);
+type NamedTether = (String, Tether);
+
+const DEFAULT_QUEUE_DEPTH: usize = 100;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct StageConfig {
+    /// Name used for the stage in logs and metrics
+    pub name: Option<String>,
+
+    /// Capacity of the queue that feeds the stage. The source doesn't have an
+    /// input queue, a value set there is ignored.
+    pub queue_depth: Option<usize>,
+}
+
+impl StageConfig {
+    fn queue_depth(config: &Option<StageConfig>) -> usize {
+        config
+            .as_ref()
+            .and_then(|x| x.queue_depth)
+            .unwrap_or(DEFAULT_QUEUE_DEPTH)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    pub source: Option<StageConfig>,
+    pub enrich: Option<StageConfig>,
+    pub reducers: Option<StageConfig>,
+    pub storage: Option<StageConfig>,
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        let stages = [
+            ("source", &self.source),
+            ("enrich", &self.enrich),
+            ("reducers", &self.reducers),
+            ("storage", &self.storage),
+        ];
+
+        for (stage, config) in stages {
+            if let Some(StageConfig {
+                queue_depth: Some(0),
+                ..
+            }) = config
+            {
+                return Err(Error::config(format!(
+                    "queue depth of {} stage should be greater than zero",
+                    stage
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
 
 pub struct Pipeline {
     pub tethers: Vec<NamedTether>,
 }
 
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Pipeline {
     pub fn new() -> Self {
         Self {
@@ -15,33 +77,460 @@ impl Pipeline {
         }
     }
 
-    pub fn register_stage(&mut self, name: &'static str, tether: Tether) {
-        self.tethers.push((name, tether));
+    pub fn register_stage(&mut self, name: &str, tether: Tether) {
+        self.tethers.push((name.to_string(), tether));
+    }
+
+    /// Renames the stages registered since `from` using a custom name. If the
+    /// plugin registered several stages, the custom name is used as a prefix.
+    fn rename_since(&mut self, from: usize, config: &Option<StageConfig>) {
+        let custom = match config.as_ref().and_then(|x| x.name.as_ref()) {
+            Some(x) => x,
+            None => return,
+        };
+
+        let added = &mut self.tethers[from..];
+
+        if added.len() == 1 {
+            added[0].0 = custom.clone();
+        } else {
+            for (name, _) in added.iter_mut() {
+                *name = format!("{}.{}", custom, name);
+            }
+        }
     }
 }
 
+/// Extra storages are named and follow the routes of the reducers stage, the
+/// main storage is always the last registered stage
 pub fn build(
+    config: &Config,
     mut source: sources::Plugin,
+    mut enrich: enrich::Plugin,
     mut reducer: reducers::Worker,
     mut storage: storage::Plugin,
+    mut extra: Vec<(String, storage::Plugin)>,
 ) -> Pipeline {
     let mut pipeline = Pipeline::new();
 
     connect_ports(
         source.borrow_output_port(),
+        enrich.borrow_input_port(),
+        StageConfig::queue_depth(&config.enrich),
+    );
+
+    connect_ports(
+        enrich.borrow_output_port(),
         reducer.borrow_input_port(),
-        100,
+        StageConfig::queue_depth(&config.reducers),
     );
 
     connect_ports(
         reducer.borrow_output_port(),
         storage.borrow_input_port(),
-        100,
+        StageConfig::queue_depth(&config.storage),
     );
 
+    for (route, (_, plugin)) in extra.iter_mut().enumerate() {
+        connect_ports(
+            reducer.borrow_route_port(route),
+            plugin.borrow_input_port(),
+            StageConfig::queue_depth(&config.storage),
+        );
+    }
+
+    let from = pipeline.tethers.len();
     source.spawn(&mut pipeline);
+    pipeline.rename_since(from, &config.source);
+
+    let from = pipeline.tethers.len();
+    enrich.spawn(&mut pipeline);
+    pipeline.rename_since(from, &config.enrich);
+
+    let from = pipeline.tethers.len();
     reducer.spawn(&mut pipeline);
+    pipeline.rename_since(from, &config.reducers);
+
+    for (name, plugin) in extra {
+        let from = pipeline.tethers.len();
+        plugin.spawn(&mut pipeline);
+
+        let custom = Some(StageConfig {
+            name: Some(format!("storage.{}", name)),
+            queue_depth: None,
+        });
+
+        pipeline.rename_since(from, &custom);
+    }
+
+    let from = pipeline.tethers.len();
     storage.spawn(&mut pipeline);
+    pipeline.rename_since(from, &config.storage);
 
     pipeline
 }
+
+/// Spawns the mempool stage along with its own storage, ahead of the main
+/// storage so that it stays the last registered stage
+#[cfg(target_family = "unix")]
+pub fn spawn_mempool(
+    pipeline: &mut Pipeline,
+    mut stage: sources::n2c::mempool::Worker,
+    mut storage: storage::Plugin,
+) {
+    connect_ports(
+        stage.borrow_output_port(),
+        storage.borrow_input_port(),
+        DEFAULT_QUEUE_DEPTH,
+    );
+
+    let main = pipeline.tethers.pop();
+
+    stage.spawn(pipeline);
+
+    let from = pipeline.tethers.len();
+    storage.spawn(pipeline);
+
+    let custom = Some(StageConfig {
+        name: Some("mempool.storage".to_string()),
+        queue_depth: None,
+    });
+
+    pipeline.rename_since(from, &custom);
+    pipeline.tethers.extend(main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+
+    impl gasket::runtime::Worker for Noop {
+        fn metrics(&self) -> gasket::metrics::Registry {
+            gasket::metrics::Builder::new()
+                .with_counter("ops_count", &Default::default())
+                .build()
+        }
+
+        fn work(&mut self) -> gasket::runtime::WorkResult {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            Ok(gasket::runtime::WorkOutcome::Idle)
+        }
+    }
+
+    fn spawn(pipeline: &mut Pipeline, names: &[&str], config: &Option<StageConfig>) {
+        let from = pipeline.tethers.len();
+
+        for name in names {
+            let tether = gasket::runtime::spawn_stage(Noop, Default::default());
+            pipeline.register_stage(name, tether);
+        }
+
+        pipeline.rename_since(from, config);
+    }
+
+    fn named(name: &str) -> Option<StageConfig> {
+        Some(StageConfig {
+            name: Some(name.to_string()),
+            queue_depth: None,
+        })
+    }
+
+    #[test]
+    fn custom_stage_names_label_the_metrics() {
+        let mut pipeline = Pipeline::new();
+
+        spawn(
+            &mut pipeline,
+            &["n2n-headers", "n2n-blocks"],
+            &named("relay"),
+        );
+        spawn(&mut pipeline, &["reducers"], &named("ledger"));
+        spawn(&mut pipeline, &["redis"], &None);
+
+        // the daemon exports each reading labelled with the name of its tether
+        let labels: Vec<_> = pipeline
+            .tethers
+            .iter()
+            .flat_map(|(name, tether)| {
+                let readings = tether.read_metrics().unwrap();
+                readings
+                    .into_iter()
+                    .map(move |(key, _)| (name.clone(), key))
+            })
+            .collect();
+
+        assert_eq!(
+            labels,
+            vec![
+                ("relay.n2n-headers".to_string(), "ops_count"),
+                ("relay.n2n-blocks".to_string(), "ops_count"),
+                ("ledger".to_string(), "ops_count"),
+                ("redis".to_string(), "ops_count"),
+            ]
+        );
+
+        for (_, tether) in pipeline.tethers {
+            tether.dismiss_stage().unwrap();
+            tether.join_stage();
+        }
+    }
+
+    #[test]
+    fn backfill_runs_the_range_without_touching_the_live_cursor() {
+        use crate::{crosscut, cursor, testing};
+
+        let dir = std::env::temp_dir().join(format!("scrolls-backfill-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let blocks: Vec<_> = (0..5)
+            .map(|i| {
+                testing::block()
+                    .slot(testing::DEFAULT_SLOT + i * 20)
+                    .number(i + 1)
+                    .tx(|t| t.output(&testing::address(i as u8), 1_000_000))
+                    .build()
+            })
+            .collect();
+
+        let points: Vec<_> = blocks
+            .iter()
+            .map(|x| crosscut::PointArg::from(x.point().unwrap()))
+            .collect();
+
+        let events: String = blocks
+            .iter()
+            .map(|x| {
+                let event = serde_json::json!({
+                    "block": {
+                        "slot": x.slot(),
+                        "cbor_hex": hex::encode(x.encode().unwrap()),
+                    }
+                });
+
+                format!("{}\n", event)
+            })
+            .collect();
+
+        let events_path = dir.join("events.ndjson");
+        std::fs::write(&events_path, events).unwrap();
+
+        let cursor_path = dir.join("live.cursor");
+        let live = "70000000,".to_string() + &"cd".repeat(32);
+        std::fs::write(&cursor_path, &live).unwrap();
+
+        // the same stages as `scrolls backfill --from {1st} --to {4th}`
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let policy = crosscut::RuntimePolicy::default();
+        let intersect = crosscut::IntersectConfig::Range(points[0].clone(), points[3].clone());
+
+        let source = sources::oura::Config {
+            path: events_path.to_string_lossy().to_string(),
+        }
+        .plugin(&chain, &intersect, &None, &policy);
+
+        let reducer = reducers::Worker::new(
+            vec![(
+                "block_stats",
+                reducers::block_stats::Config { key_prefix: None }.plugin(),
+                None,
+            )],
+            Default::default(),
+            None,
+            &policy,
+        );
+
+        let memory = match storage::memory::Config::default().plugin(
+            &chain,
+            &intersect,
+            cursor::Store::Skip,
+        ) {
+            storage::Plugin::Memory(x) => x,
+            _ => unreachable!("memory config builds a memory plugin"),
+        };
+
+        let store = memory.store();
+
+        let pipeline = build(
+            &Config::default(),
+            source,
+            enrich::skip::Config {}.plugin(),
+            reducer,
+            storage::Plugin::Memory(memory),
+            Vec::new(),
+        );
+
+        let (_, storage) = pipeline.tethers.last().unwrap();
+        let started = std::time::Instant::now();
+
+        while !matches!(storage.check_state(), gasket::runtime::TetherState::Dropped) {
+            assert!(started.elapsed().as_secs() < 30, "backfill never finished");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        let store = store.lock().unwrap();
+        let mut slots: Vec<_> = store.scores("block_stats").unwrap().values().collect();
+        slots.sort();
+
+        let expected: Vec<_> = (1..4)
+            .map(|i| testing::DEFAULT_SLOT as i64 + i * 20)
+            .collect();
+        assert_eq!(slots, expected.iter().collect::<Vec<_>>());
+        assert_eq!(
+            store.cursor().map(|x| x.to_string()),
+            Some(points[3].to_string())
+        );
+
+        assert_eq!(std::fs::read_to_string(&cursor_path).unwrap(), live);
+
+        for (_, tether) in pipeline.tethers {
+            tether.dismiss_stage().unwrap();
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn each_storage_gets_its_own_reducers_and_the_cursor_waits_for_both() {
+        use crate::{crosscut, cursor, testing};
+
+        let dir = std::env::temp_dir().join(format!("scrolls-storages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let blocks: Vec<_> = (0..4)
+            .map(|i| {
+                testing::block()
+                    .slot(testing::DEFAULT_SLOT + i * 20)
+                    .number(i + 1)
+                    .tx(|t| t.output(&testing::address(i as u8), 1_000_000))
+                    .build()
+            })
+            .collect();
+
+        let points: Vec<_> = blocks
+            .iter()
+            .map(|x| crosscut::PointArg::from(x.point().unwrap()))
+            .collect();
+
+        let events: String = blocks
+            .iter()
+            .map(|x| {
+                let event = serde_json::json!({
+                    "block": {
+                        "slot": x.slot(),
+                        "cbor_hex": hex::encode(x.encode().unwrap()),
+                    }
+                });
+
+                format!("{}\n", event)
+            })
+            .collect();
+
+        let events_path = dir.join("events.ndjson");
+        std::fs::write(&events_path, events).unwrap();
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let policy = crosscut::RuntimePolicy::default();
+        let intersect = crosscut::IntersectConfig::Range(points[0].clone(), points[3].clone());
+
+        let source = sources::oura::Config {
+            path: events_path.to_string_lossy().to_string(),
+        }
+        .plugin(&chain, &intersect, &None, &policy);
+
+        // block stats stay on the main storage, tx counts go to the extra one
+        let reducer = reducers::Worker::new(
+            vec![
+                (
+                    "block_stats",
+                    reducers::block_stats::Config { key_prefix: None }.plugin(),
+                    None,
+                ),
+                (
+                    "total_transactions_count",
+                    reducers::total_transactions_count::Config { key_prefix: None }.plugin(),
+                    None,
+                ),
+            ],
+            Default::default(),
+            None,
+            &policy,
+        )
+        .with_routes(vec![None, Some(0)]);
+
+        let cursor_path = dir.join("cursor");
+        let file_store = || {
+            cursor::file::Config {
+                path: Some(cursor_path.to_string_lossy().to_string()),
+                integrity: None,
+            }
+            .store(&chain)
+        };
+
+        let gate = cursor::gate::Gate::new(file_store());
+
+        let memory =
+            |cursor| match storage::memory::Config::default().plugin(&chain, &intersect, cursor) {
+                storage::Plugin::Memory(x) => x,
+                _ => unreachable!("memory config builds a memory plugin"),
+            };
+
+        let main = memory(gate.member());
+        let extra = memory(gate.member());
+
+        let (main_store, extra_store) = (main.store(), extra.store());
+
+        let pipeline = build(
+            &Config::default(),
+            source,
+            enrich::skip::Config {}.plugin(),
+            reducer,
+            storage::Plugin::Memory(main),
+            vec![("feed".to_string(), storage::Plugin::Memory(extra))],
+        );
+
+        let started = std::time::Instant::now();
+
+        // the extra storage and the main one are the last two stages
+        for (name, tether) in pipeline.tethers.iter().rev().take(2) {
+            while !matches!(tether.check_state(), gasket::runtime::TetherState::Dropped) {
+                assert!(started.elapsed().as_secs() < 30, "{} never finished", name);
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+        }
+
+        {
+            let main = main_store.lock().unwrap();
+            assert_eq!(main.scores("block_stats").map(|x| x.len()), Some(3));
+            assert_eq!(main.counter("total_transactions_count"), None);
+
+            let extra = extra_store.lock().unwrap();
+            assert_eq!(extra.counter("total_transactions_count"), Some(3));
+            assert!(extra.scores("block_stats").is_none());
+        }
+
+        let saved = cursor::CursorStore::load(&file_store()).unwrap();
+        assert_eq!(saved.map(|x| x.to_string()), Some(points[3].to_string()));
+
+        for (_, tether) in pipeline.tethers {
+            tether.dismiss_stage().unwrap();
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn zero_queue_depth_is_rejected() {
+        let config = Config {
+            storage: Some(StageConfig {
+                name: None,
+                queue_depth: Some(0),
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+        assert!(Config::default().validate().is_ok());
+    }
+}
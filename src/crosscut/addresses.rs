@@ -0,0 +1,451 @@
+use std::fmt::Display;
+
+use bech32::{FromBase32, ToBase32, Variant};
+use pallas::ledger::primitives::{alonzo, byron, Fragment};
+use serde::Deserialize;
+
+use crate::{
+    model::{BlockContext, MultiEraOutput},
+    Error,
+};
+
+use super::ChainWellKnownInfo;
+
+/// The representation used by address-keyed reducers to build their keys
+///
+/// Byron addresses don't have Shelley credentials, they are always keyed by
+/// their base58 form except when `hex` is requested.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressKeyFormat {
+    #[default]
+    Bech32,
+    Hex,
+    PaymentCred,
+    StakeCred,
+}
+
+/// Position of the stake registration certificate referenced by a pointer
+/// address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StakePointer {
+    pub slot: u64,
+    pub tx_idx: u64,
+    pub cert_idx: u64,
+}
+
+impl Display for StakePointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.slot, self.tx_idx, self.cert_idx)
+    }
+}
+
+/// Reads a variable-length natural (big-endian base-128, high bit set on all
+/// but the last byte)
+fn read_natural(bytes: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+
+    loop {
+        let byte = *bytes.get(*offset)?;
+        *offset += 1;
+
+        value = value.checked_mul(128)? | (byte & 0x7f) as u64;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+/// Reads the pointer that follows the payment part of a pointer address
+fn read_pointer(bytes: &[u8]) -> Option<StakePointer> {
+    let mut offset = 29;
+
+    Some(StakePointer {
+        slot: read_natural(bytes, &mut offset)?,
+        tx_idx: read_natural(bytes, &mut offset)?,
+        cert_idx: read_natural(bytes, &mut offset)?,
+    })
+}
+
+/// A payment or stake credential inside an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential<'a> {
+    Key(&'a [u8]),
+    Script(&'a [u8]),
+}
+
+impl<'a> Credential<'a> {
+    fn new(hash: Option<&'a [u8]>, script: bool) -> Option<Self> {
+        match script {
+            true => hash.map(Credential::Script),
+            false => hash.map(Credential::Key),
+        }
+    }
+
+    pub fn hash(&self) -> &'a [u8] {
+        match self {
+            Credential::Key(x) | Credential::Script(x) => x,
+        }
+    }
+}
+
+/// The parts of an address, as told by its header byte
+///
+/// Headers that aren't known by this version, or addresses too short for
+/// what their header announces, are reported as `Unknown` so that reducers
+/// can skip them instead of failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsedAddress<'a> {
+    Byron,
+    Base(Credential<'a>, Credential<'a>),
+    Pointer(Credential<'a>, StakePointer),
+    Enterprise(Credential<'a>),
+    Reward(Credential<'a>),
+    Unknown { header: Option<u8> },
+}
+
+impl<'a> ParsedAddress<'a> {
+    pub fn payment(&self) -> Option<Credential<'a>> {
+        match self {
+            ParsedAddress::Base(x, _)
+            | ParsedAddress::Pointer(x, _)
+            | ParsedAddress::Enterprise(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// The stake credential, pointer addresses only reference theirs
+    pub fn stake(&self) -> Option<Credential<'a>> {
+        match self {
+            ParsedAddress::Base(_, x) | ParsedAddress::Reward(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn pointer(&self) -> Option<StakePointer> {
+        match self {
+            ParsedAddress::Pointer(_, x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Whether funds at the address are locked by a script
+    pub fn has_script_payment(&self) -> bool {
+        matches!(self.payment(), Some(Credential::Script(_)))
+    }
+}
+
+/// Splits the raw bytes of an address into its parts
+pub fn parse_output_address(bytes: &[u8]) -> ParsedAddress<'_> {
+    let header = match bytes.first() {
+        Some(x) => *x,
+        None => return ParsedAddress::Unknown { header: None },
+    };
+
+    let kind = header >> 4;
+
+    // the low bits of the type tell key (0) from script (1) credentials
+    let payment = Credential::new(bytes.get(1..29), kind & 0b0001 != 0);
+
+    let parsed = match kind {
+        0..=3 => {
+            let stake = Credential::new(bytes.get(29..57), kind & 0b0010 != 0);
+            payment.zip(stake).map(|(p, s)| ParsedAddress::Base(p, s))
+        }
+        4 | 5 => payment
+            .zip(read_pointer(bytes))
+            .map(|(p, x)| ParsedAddress::Pointer(p, x)),
+        6 | 7 => payment.map(ParsedAddress::Enterprise),
+        8 => Some(ParsedAddress::Byron),
+        14 | 15 => payment.map(ParsedAddress::Reward),
+        _ => None,
+    };
+
+    parsed.unwrap_or(ParsedAddress::Unknown {
+        header: Some(header),
+    })
+}
+
+fn payment_part(bytes: &[u8]) -> Option<&[u8]> {
+    parse_output_address(bytes).payment().map(|x| x.hash())
+}
+
+fn stake_part(bytes: &[u8]) -> Option<&[u8]> {
+    parse_output_address(bytes).stake().map(|x| x.hash())
+}
+
+/// Returns the stake pointer of a pointer address
+pub fn pointer_part(bytes: &[u8]) -> Option<StakePointer> {
+    parse_output_address(bytes).pointer()
+}
+
+/// Builds the reward account (header byte followed by the credential) for a
+/// stake credential
+pub fn reward_account(cred: &alonzo::StakeCredential, network_id: u8) -> Vec<u8> {
+    let (header, hash) = match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => (0b1110_0000, x),
+        alonzo::StakeCredential::Scripthash(x) => (0b1111_0000, x),
+    };
+
+    let mut bytes = vec![header | (network_id & 0x0f)];
+    bytes.extend_from_slice(hash.as_ref());
+
+    bytes
+}
+
+/// Encodes a reward account as a bech32 stake address (eg: `stake1...`)
+pub fn encode_stake_address(reward_account: &[u8], hrp: &str) -> Result<String, Error> {
+    bech32::encode(hrp, reward_account.to_base32(), Variant::Bech32).map_err(Error::ledger)
+}
+
+/// Decodes an address written in a config file into its raw bytes
+///
+/// Entries can be bech32 (in either lower or upper case) or hex, surrounding
+/// whitespace is ignored. Shelley addresses that belong to a network other than
+/// the configured one are rejected since they would never match.
+pub fn parse_config_address(raw: &str, chain: &ChainWellKnownInfo) -> Result<Vec<u8>, Error> {
+    let raw = raw.trim().to_lowercase();
+
+    let bytes = match raw.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => {
+            hex::decode(&raw).map_err(|_| Error::config(format!("invalid hex address {}", raw)))?
+        }
+        false => {
+            let (hrp, data, _) = bech32::decode(&raw)
+                .map_err(|err| Error::config(format!("invalid address {}: {}", raw, err)))?;
+
+            if hrp != chain.address_hrp {
+                return Err(Error::config(format!(
+                    "address {} doesn't belong to the configured network, expected prefix {}",
+                    raw, chain.address_hrp
+                )));
+            }
+
+            Vec::<u8>::from_base32(&data)
+                .map_err(|err| Error::config(format!("invalid address {}: {}", raw, err)))?
+        }
+    };
+
+    let header = *bytes
+        .first()
+        .ok_or_else(|| Error::config("empty address in config"))?;
+
+    // byron addresses don't carry a network id in the header
+    let byron = matches!(parse_output_address(&bytes), ParsedAddress::Byron);
+
+    if !byron && header & 0x0f != chain.network_id() {
+        return Err(Error::config(format!(
+            "address {} doesn't belong to the configured network",
+            raw
+        )));
+    }
+
+    Ok(bytes)
+}
+
+impl AddressKeyFormat {
+    /// Returns the key for the output, or `None` if the address doesn't have
+    /// the requested part
+    pub fn alonzo_output_key(
+        &self,
+        output: &alonzo::TransactionOutput,
+        hrp: &str,
+    ) -> Result<Option<String>, Error> {
+        let bytes = output.address.as_slice();
+
+        match self {
+            AddressKeyFormat::Bech32 => output
+                .to_bech32_address(hrp)
+                .map(Some)
+                .map_err(Error::ledger),
+            AddressKeyFormat::Hex => Ok(Some(hex::encode(bytes))),
+            AddressKeyFormat::PaymentCred => Ok(payment_part(bytes).map(hex::encode)),
+            AddressKeyFormat::StakeCred => Ok(stake_part(bytes).map(hex::encode)),
+        }
+    }
+
+    pub fn byron_output_key(&self, output: &byron::TxOut) -> Result<Option<String>, Error> {
+        match self {
+            AddressKeyFormat::Hex => {
+                let cbor = output.address.encode_fragment()?;
+                Ok(Some(hex::encode(cbor)))
+            }
+            _ => output
+                .address
+                .to_addr_string()
+                .map(Some)
+                .map_err(Error::ledger),
+        }
+    }
+
+    pub fn output_key(&self, output: &MultiEraOutput, hrp: &str) -> Result<Option<String>, Error> {
+        match output {
+            MultiEraOutput::Byron(x) => self.byron_output_key(x),
+            MultiEraOutput::AlonzoCompatible(x) => self.alonzo_output_key(x, hrp),
+        }
+    }
+
+    /// Same as [Self::alonzo_output_key], but resolves the stake credential of
+    /// pointer addresses using the pointers found by the enrichment stage
+    pub fn alonzo_output_key_ctx(
+        &self,
+        output: &alonzo::TransactionOutput,
+        hrp: &str,
+        ctx: &BlockContext,
+    ) -> Result<Option<String>, Error> {
+        if let AddressKeyFormat::StakeCred = self {
+            if let Some(pointer) = pointer_part(output.address.as_slice()) {
+                return Ok(ctx.find_stake_pointer(&pointer).map(hex::encode));
+            }
+        }
+
+        self.alonzo_output_key(output, hrp)
+    }
+
+    pub fn output_key_ctx(
+        &self,
+        output: &MultiEraOutput,
+        hrp: &str,
+        ctx: &BlockContext,
+    ) -> Result<Option<String>, Error> {
+        match output {
+            MultiEraOutput::Byron(x) => self.byron_output_key(x),
+            MultiEraOutput::AlonzoCompatible(x) => self.alonzo_output_key_ctx(x, hrp, ctx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stake_key() -> alonzo::StakeCredential {
+        let hash = "337b62cfff6403a06a3acbc34f8c46003c69fe79a3628cefa9c47251";
+        alonzo::StakeCredential::AddrKeyhash(hash.parse().unwrap())
+    }
+
+    #[test]
+    fn reward_account_encodes_per_network() {
+        // CIP-19 test vectors of the reward account with a stake key
+        let mainnet = ChainWellKnownInfo::mainnet();
+        let account = reward_account(&stake_key(), mainnet.network_id());
+
+        assert_eq!(
+            encode_stake_address(&account, &mainnet.stake_hrp()).unwrap(),
+            "stake1uyehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gh6ffgw"
+        );
+
+        let testnet = ChainWellKnownInfo::testnet();
+        let account = reward_account(&stake_key(), testnet.network_id());
+
+        assert_eq!(
+            encode_stake_address(&account, &testnet.stake_hrp()).unwrap(),
+            "stake_test1uqehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gssrtvn"
+        );
+    }
+
+    fn address(header: u8, rest: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![header];
+        bytes.extend_from_slice(rest);
+        bytes
+    }
+
+    #[test]
+    fn known_headers_are_split_into_their_parts() {
+        let payment = [1u8; 28];
+        let stake = [2u8; 28];
+        let both = [payment, stake].concat();
+
+        let key = Credential::Key;
+        let script = Credential::Script;
+
+        // the network id in the low nibble doesn't change the layout
+        let cases: Vec<(u8, &[u8], ParsedAddress)> = vec![
+            (0x01, &both, ParsedAddress::Base(key(&payment), key(&stake))),
+            (
+                0x10,
+                &both,
+                ParsedAddress::Base(script(&payment), key(&stake)),
+            ),
+            (
+                0x21,
+                &both,
+                ParsedAddress::Base(key(&payment), script(&stake)),
+            ),
+            (
+                0x31,
+                &both,
+                ParsedAddress::Base(script(&payment), script(&stake)),
+            ),
+            (0x61, &payment, ParsedAddress::Enterprise(key(&payment))),
+            (0x71, &payment, ParsedAddress::Enterprise(script(&payment))),
+            (0xe1, &stake, ParsedAddress::Reward(key(&stake))),
+            (0xf1, &stake, ParsedAddress::Reward(script(&stake))),
+        ];
+
+        for (header, rest, expected) in cases {
+            let bytes = address(header, rest);
+            assert_eq!(
+                parse_output_address(&bytes),
+                expected,
+                "header {:x}",
+                header
+            );
+        }
+
+        // slot 2498243, tx 27, cert 3 (CIP-19)
+        let pointer = [payment.as_slice(), &[0x81, 0x98, 0xbd, 0x43, 0x1b, 0x03]].concat();
+        let expected = StakePointer {
+            slot: 2498243,
+            tx_idx: 27,
+            cert_idx: 3,
+        };
+
+        let bytes = address(0x41, &pointer);
+        let parsed = parse_output_address(&bytes);
+        assert_eq!(parsed, ParsedAddress::Pointer(key(&payment), expected));
+        assert_eq!(parsed.stake(), None);
+
+        let bytes = address(0x51, &pointer);
+        let parsed = parse_output_address(&bytes);
+        assert_eq!(parsed, ParsedAddress::Pointer(script(&payment), expected));
+        assert!(parsed.has_script_payment());
+
+        assert_eq!(
+            parse_output_address(&address(0x82, &[0xd8])),
+            ParsedAddress::Byron
+        );
+    }
+
+    #[test]
+    fn unknown_headers_and_truncated_addresses_are_unknown() {
+        let both = [0u8; 56];
+
+        for kind in 9..=13u8 {
+            let header = kind << 4 | 1;
+
+            assert_eq!(
+                parse_output_address(&address(header, &both)),
+                ParsedAddress::Unknown {
+                    header: Some(header)
+                }
+            );
+        }
+
+        assert_eq!(
+            parse_output_address(&[]),
+            ParsedAddress::Unknown { header: None }
+        );
+
+        // a base address without its stake part, a pointer without its pointer
+        let bytes = address(0x01, &both[..28]);
+        let truncated = parse_output_address(&bytes);
+        assert_eq!(truncated, ParsedAddress::Unknown { header: Some(0x01) });
+        assert_eq!(truncated.payment(), None);
+
+        let bytes = address(0x41, &[0u8; 28]);
+        let truncated = parse_output_address(&bytes);
+        assert_eq!(truncated, ParsedAddress::Unknown { header: Some(0x41) });
+    }
+}
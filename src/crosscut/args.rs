@@ -4,8 +4,14 @@ use std::{fmt::Display, ops::Deref, str::FromStr};
 
 use crate::Error;
 
+/// Network magic of the preprod testnet
+pub const PREPROD_MAGIC: u64 = 1;
+
+/// Network magic of the preview testnet
+pub const PREVIEW_MAGIC: u64 = 2;
+
 /// A serialization-friendly chain Point struct using a hex-encoded hash
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PointArg {
     Origin,
     Specific(u64, String),
@@ -21,6 +27,13 @@ impl TryInto<Point> for PointArg {
                 let hash = hex::decode(&hash_hex)
                     .map_err(|_| Self::Error::message("can't decode point hash hex value"))?;
 
+                if hash.len() != 32 {
+                    return Err(Self::Error::message(format!(
+                        "point hash should be 32 bytes long, found {}",
+                        hash.len()
+                    )));
+                }
+
                 Ok(Point::Specific(slot, hash))
             }
         }
@@ -59,11 +72,50 @@ impl FromStr for PointArg {
     }
 }
 
-impl ToString for PointArg {
-    fn to_string(&self) -> String {
+impl Display for PointArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointArg::Origin => write!(f, "origin"),
+            PointArg::Specific(slot, hash) => write!(f, "{},{}", slot, hash),
+        }
+    }
+}
+
+impl PointArg {
+    /// The explicit point of the first block of the network, that some tools
+    /// emit instead of origin. Networks without Byron blocks (eg: preview)
+    /// have none.
+    pub fn genesis(info: &ChainWellKnownInfo) -> Result<PointArg, Error> {
+        if info.byron_known_hash.is_empty() {
+            return Err(Error::config(
+                "network has no byron blocks, its genesis has no explicit point",
+            ));
+        }
+
+        Ok(PointArg::Specific(
+            info.byron_known_slot,
+            info.byron_known_hash.clone(),
+        ))
+    }
+
+    /// True for origin and for the explicit genesis point of the network
+    pub fn is_origin_equivalent(&self, info: &ChainWellKnownInfo) -> bool {
         match self {
-            PointArg::Origin => "origin".to_string(),
-            PointArg::Specific(slot, hash) => format!("{},{}", slot, hash),
+            PointArg::Origin => true,
+            PointArg::Specific(slot, hash) => {
+                *slot == 0
+                    && *slot == info.byron_known_slot
+                    && !info.byron_known_hash.is_empty()
+                    && hash.eq_ignore_ascii_case(&info.byron_known_hash)
+            }
+        }
+    }
+
+    /// Maps the explicit genesis point to origin, any other point is kept
+    pub fn normalize(self, info: &ChainWellKnownInfo) -> PointArg {
+        match self.is_origin_equivalent(info) {
+            true => PointArg::Origin,
+            false => self,
         }
     }
 }
@@ -88,6 +140,8 @@ impl FromStr for MagicArg {
         let m = match s {
             "testnet" => MagicArg(TESTNET_MAGIC),
             "mainnet" => MagicArg(MAINNET_MAGIC),
+            "preprod" => MagicArg(PREPROD_MAGIC),
+            "preview" => MagicArg(PREVIEW_MAGIC),
             _ => MagicArg(u64::from_str(s).map_err(|_| "can't parse magic value")?),
         };
 
@@ -108,6 +162,91 @@ pub enum IntersectConfig {
     Origin,
     Point(PointArg),
     Fallbacks(Vec<PointArg>),
+
+    /// Sync the blocks after the first point up to the second one, then stop
+    Range(PointArg, PointArg),
+
+    /// Start from the recent point known for the network, see
+    /// [ChainWellKnownInfo::checkpoint]
+    Checkpoint,
+}
+
+impl IntersectConfig {
+    /// Replaces a `Checkpoint` intersect with the point of the network
+    ///
+    /// The explicit genesis point is turned into origin, it's the epoch
+    /// boundary block at slot 0 and carries no txs, so both start the same.
+    pub fn resolve(self, chain: &ChainWellKnownInfo) -> Result<Self, Error> {
+        match self {
+            IntersectConfig::Checkpoint => match &chain.checkpoint {
+                Some(point) => Ok(IntersectConfig::Point(point.clone())),
+                None => Err(Error::config(
+                    "no checkpoint known for this network, set `checkpoint` in the chain config",
+                )),
+            },
+            IntersectConfig::Point(x) => match x.normalize(chain) {
+                PointArg::Origin => Ok(IntersectConfig::Origin),
+                x => Ok(IntersectConfig::Point(x)),
+            },
+            IntersectConfig::Fallbacks(x) => Ok(IntersectConfig::Fallbacks(
+                x.into_iter().map(|p| p.normalize(chain)).collect(),
+            )),
+            IntersectConfig::Range(from, to) => {
+                Ok(IntersectConfig::Range(from.normalize(chain), to))
+            }
+            x => Ok(x),
+        }
+    }
+}
+
+/// The origin of the intersect chosen by [resolve_start]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartSource {
+    CliOverride,
+    Cursor,
+    Config,
+}
+
+/// Where the pipeline should start and why
+#[derive(Debug, Clone)]
+pub struct IntersectPlan {
+    pub intersect: IntersectConfig,
+    pub source: StartSource,
+}
+
+/// Decides where to start syncing
+///
+/// Precedence is: an explicit point from the command line, then the persisted
+/// cursor (unless `reset` is requested), then the intersect of the config.
+pub fn resolve_start(
+    config: &IntersectConfig,
+    cursor: Option<PointArg>,
+    cli_override: Option<PointArg>,
+    reset: bool,
+) -> IntersectPlan {
+    let plan = match (cli_override, cursor) {
+        (Some(point), _) => IntersectPlan {
+            intersect: IntersectConfig::Point(point),
+            source: StartSource::CliOverride,
+        },
+        (None, Some(point)) if !reset => IntersectPlan {
+            intersect: IntersectConfig::Point(point),
+            source: StartSource::Cursor,
+        },
+        _ => IntersectPlan {
+            intersect: config.clone(),
+            source: StartSource::Config,
+        },
+    };
+
+    match plan.source {
+        StartSource::CliOverride => log::info!("starting from point set in the command line"),
+        StartSource::Cursor => log::info!("starting from persisted cursor"),
+        StartSource::Config if reset => log::warn!("cursor reset, starting from config intersect"),
+        StartSource::Config => log::info!("starting from config intersect"),
+    };
+
+    plan
 }
 
 /// Well-known information about the blockhain network
@@ -130,7 +269,15 @@ pub struct ChainWellKnownInfo {
     pub shelley_known_hash: String,
     pub shelley_known_time: u64,
     pub address_hrp: String,
+    /// HRP of reward (stake) addresses, derived from `address_hrp` if missing
+    pub stake_address_hrp: Option<String>,
     pub adahandle_policy: String,
+    /// Recent point used by the `Checkpoint` intersect, refreshed from time to
+    /// time in the hardcoded networks
+    pub checkpoint: Option<PointArg>,
+    /// First slot of each era (eg: `["alonzo", 39916800]`), in chain order.
+    /// Custom chains without it can't tell the era of a block.
+    pub era_boundaries: Option<Vec<(String, u64)>>,
 }
 
 impl ChainWellKnownInfo {
@@ -151,8 +298,23 @@ impl ChainWellKnownInfo {
                 .to_string(),
             shelley_known_time: 1596059091,
             address_hrp: "addr".to_string(),
+            stake_address_hrp: Some("stake".to_string()),
             adahandle_policy: "f0ff48bbb7bbe9d59a40f1ce90e9e9d0ff5002ec48f232b49ca0fb9a"
                 .to_string(),
+            // last block of the Alonzo era
+            checkpoint: Some(PointArg::Specific(
+                72316796,
+                "c58a24ba8203e7629422a24d9dc68ce2ed495420bf40d9dab124373655161a20".to_string(),
+            )),
+            era_boundaries: Some(vec![
+                ("byron".to_string(), 0),
+                ("shelley".to_string(), 4492800),
+                ("allegra".to_string(), 16588800),
+                ("mary".to_string(), 23068800),
+                ("alonzo".to_string(), 39916800),
+                ("babbage".to_string(), 72316800),
+                ("conway".to_string(), 133660800),
+            ]),
         }
     }
 
@@ -173,17 +335,141 @@ impl ChainWellKnownInfo {
                 .to_string(),
             shelley_known_time: 1595967616,
             address_hrp: "addr_test".to_string(),
+            stake_address_hrp: Some("stake_test".to_string()),
             adahandle_policy: "8d18d786e92776c824607fd8e193ec535c79dc61ea2405ddf3b09fe3"
                 .to_string(),
+            // the legacy testnet is retired, there's no recent point to offer
+            checkpoint: None,
+            era_boundaries: Some(vec![
+                ("byron".to_string(), 0),
+                ("shelley".to_string(), 1598400),
+                ("allegra".to_string(), 13694400),
+                ("mary".to_string(), 18014400),
+                ("alonzo".to_string(), 36158400),
+                ("babbage".to_string(), 62510400),
+            ]),
+        }
+    }
+
+    /// Hardcoded values for the preprod testnet
+    pub fn preprod() -> Self {
+        ChainWellKnownInfo {
+            magic: PREPROD_MAGIC,
+            byron_epoch_length: 432000,
+            byron_slot_length: 20,
+            byron_known_slot: 0,
+            byron_known_time: 1654041600,
+            byron_known_hash: "9ad7ff320c9cf74e0f5ee78d22a85ce42bb0a487d0506bf60cfb5a91ea4497d2"
+                .to_string(),
+            shelley_epoch_length: 432000,
+            shelley_slot_length: 1,
+            shelley_known_slot: 86400,
+            shelley_known_hash: "c4a1595c5cc7a31eda9e544986fe9387af4e3491afe0ca9a80714f01951bbd5c"
+                .to_string(),
+            shelley_known_time: 1655769600,
+            address_hrp: "addr_test".to_string(),
+            stake_address_hrp: Some("stake_test".to_string()),
+            adahandle_policy: "f0ff48bbb7bbe9d59a40f1ce90e9e9d0ff5002ec48f232b49ca0fb9a"
+                .to_string(),
+            // last block of the Alonzo era
+            checkpoint: Some(PointArg::Specific(
+                3542390,
+                "f93e682d5b91a94d8660e748aef229c19cb285bfb9830db48941d6a78183d81f".to_string(),
+            )),
+            era_boundaries: Some(vec![
+                ("byron".to_string(), 0),
+                ("shelley".to_string(), 86400),
+                ("allegra".to_string(), 518400),
+                ("mary".to_string(), 950400),
+                ("alonzo".to_string(), 1382400),
+                ("babbage".to_string(), 3542400),
+                ("conway".to_string(), 68774400),
+            ]),
+        }
+    }
+
+    /// Hardcoded values for the preview testnet
+    ///
+    /// Preview has no Byron blocks, every era up to Alonzo starts at slot 0
+    /// and its epochs last a day.
+    pub fn preview() -> Self {
+        ChainWellKnownInfo {
+            magic: PREVIEW_MAGIC,
+            byron_epoch_length: 432000,
+            byron_slot_length: 20,
+            byron_known_slot: 0,
+            byron_known_time: 1666656000,
+            byron_known_hash: "".to_string(),
+            shelley_epoch_length: 86400,
+            shelley_slot_length: 1,
+            shelley_known_slot: 0,
+            shelley_known_hash: "268ae601af8f9214804735910a3301881fbe0eec9936db7d1fb9fc39e93d1e37"
+                .to_string(),
+            shelley_known_time: 1666656000,
+            address_hrp: "addr_test".to_string(),
+            stake_address_hrp: Some("stake_test".to_string()),
+            adahandle_policy: "f0ff48bbb7bbe9d59a40f1ce90e9e9d0ff5002ec48f232b49ca0fb9a"
+                .to_string(),
+            // no checkpoint is bundled yet, set one in the chain config
+            checkpoint: None,
+            era_boundaries: Some(vec![
+                ("alonzo".to_string(), 0),
+                ("babbage".to_string(), 259200),
+                ("conway".to_string(), 55814400),
+            ]),
+        }
+    }
+
+    /// The HRP to use for bech32 reward (stake) addresses
+    pub fn stake_hrp(&self) -> String {
+        match &self.stake_address_hrp {
+            Some(x) => x.clone(),
+            None if self.address_hrp.ends_with("_test") => "stake_test".to_string(),
+            None => "stake".to_string(),
+        }
+    }
+
+    /// The network id encoded in the header of Shelley addresses
+    pub fn network_id(&self) -> u8 {
+        match self.magic {
+            MAINNET_MAGIC => 1,
+            _ => 0,
         }
     }
 
+    /// Returns the epoch number that contains the given absolute slot
+    pub fn epoch_for_slot(&self, slot: u64) -> u64 {
+        super::EpochCalculator::get_epoch_no_for_absolute_slot(
+            self.byron_known_slot,
+            self.byron_epoch_length as u64,
+            self.byron_slot_length as u64,
+            self.shelley_known_slot,
+            self.shelley_epoch_length as u64,
+            slot,
+        )
+    }
+
+    /// Returns the name of the era that contains the given absolute slot
+    ///
+    /// Unknown without `era_boundaries`, or for a slot before the first of
+    /// them, since the Shelley anchor alone can't tell the later eras apart.
+    pub fn era_for_slot(&self, slot: u64) -> Option<&str> {
+        self.era_boundaries
+            .as_ref()?
+            .iter()
+            .take_while(|(_, start)| *start <= slot)
+            .last()
+            .map(|(era, _)| era.as_str())
+    }
+
     /// Uses the value of the magic to return either mainnet or testnet
     /// hardcoded values.
     pub fn try_from_magic(magic: u64) -> Result<ChainWellKnownInfo, Error> {
         match magic {
             MAINNET_MAGIC => Ok(Self::mainnet()),
             TESTNET_MAGIC => Ok(Self::testnet()),
+            PREPROD_MAGIC => Ok(Self::preprod()),
+            PREVIEW_MAGIC => Ok(Self::preview()),
             _ => Err(Error::ConfigError(
                 "can't infer well-known chain infro from specified magic".into(),
             )),
@@ -196,3 +482,193 @@ impl Default for ChainWellKnownInfo {
         Self::mainnet()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genesis_point_normalizes_to_origin() {
+        let mainnet = ChainWellKnownInfo::mainnet();
+
+        let genesis = PointArg::genesis(&mainnet).unwrap();
+        assert!(
+            matches!(&genesis, PointArg::Specific(0, hash) if *hash == mainnet.byron_known_hash)
+        );
+
+        let point: Point = genesis.clone().try_into().unwrap();
+        assert_eq!(
+            point,
+            Point::Specific(0, hex::decode(&mainnet.byron_known_hash).unwrap())
+        );
+
+        assert!(matches!(genesis.normalize(&mainnet), PointArg::Origin));
+
+        let upper = PointArg::Specific(0, mainnet.byron_known_hash.to_uppercase());
+        assert!(matches!(upper.normalize(&mainnet), PointArg::Origin));
+
+        // the genesis of another network is kept as is
+        let testnet = PointArg::genesis(&ChainWellKnownInfo::testnet()).unwrap();
+        assert!(matches!(
+            testnet.normalize(&mainnet),
+            PointArg::Specific(0, _)
+        ));
+
+        let other = PointArg::Specific(10, mainnet.byron_known_hash.clone());
+        assert!(matches!(
+            other.normalize(&mainnet),
+            PointArg::Specific(10, _)
+        ));
+    }
+
+    #[test]
+    fn networks_without_byron_blocks_have_no_genesis_point() {
+        let preview = ChainWellKnownInfo::preview();
+
+        assert!(PointArg::genesis(&preview).is_err());
+
+        // an empty hash doesn't make the first slot look like origin
+        let empty = PointArg::Specific(0, String::new());
+        assert!(!empty.is_origin_equivalent(&preview));
+    }
+
+    #[test]
+    fn resolve_turns_explicit_genesis_into_origin() {
+        let mainnet = ChainWellKnownInfo::mainnet();
+        let config = IntersectConfig::Point(PointArg::genesis(&mainnet).unwrap());
+
+        assert!(matches!(
+            config.resolve(&mainnet).unwrap(),
+            IntersectConfig::Origin
+        ));
+    }
+
+    fn point(slot: u64) -> PointArg {
+        PointArg::Specific(slot, "ab".repeat(32))
+    }
+
+    fn started_at(plan: &IntersectPlan) -> Option<u64> {
+        match &plan.intersect {
+            IntersectConfig::Point(PointArg::Specific(slot, _)) => Some(*slot),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn start_precedence() {
+        let config = IntersectConfig::Tip;
+
+        let plan = resolve_start(&config, Some(point(1)), Some(point(2)), false);
+        assert_eq!(plan.source, StartSource::CliOverride);
+        assert_eq!(started_at(&plan), Some(2));
+
+        // the command line wins over a reset too
+        let plan = resolve_start(&config, Some(point(1)), Some(point(2)), true);
+        assert_eq!(plan.source, StartSource::CliOverride);
+
+        let plan = resolve_start(&config, Some(point(1)), None, false);
+        assert_eq!(plan.source, StartSource::Cursor);
+        assert_eq!(started_at(&plan), Some(1));
+
+        let plan = resolve_start(&config, Some(point(1)), None, true);
+        assert_eq!(plan.source, StartSource::Config);
+        assert!(matches!(plan.intersect, IntersectConfig::Tip));
+
+        let plan = resolve_start(&config, None, None, false);
+        assert_eq!(plan.source, StartSource::Config);
+    }
+
+    #[test]
+    fn era_of_slot() {
+        let mainnet = ChainWellKnownInfo::mainnet();
+
+        // a post-Vasil mainnet block
+        assert_eq!(mainnet.era_for_slot(80_000_000), Some("babbage"));
+        assert_eq!(mainnet.era_for_slot(4492799), Some("byron"));
+        assert_eq!(mainnet.era_for_slot(4492800), Some("shelley"));
+
+        assert_eq!(
+            ChainWellKnownInfo::testnet().era_for_slot(62510400),
+            Some("babbage")
+        );
+        assert_eq!(
+            ChainWellKnownInfo::preprod().era_for_slot(3542400),
+            Some("babbage")
+        );
+        assert_eq!(
+            ChainWellKnownInfo::preview().era_for_slot(0),
+            Some("alonzo")
+        );
+
+        let custom = ChainWellKnownInfo {
+            era_boundaries: None,
+            ..ChainWellKnownInfo::mainnet()
+        };
+
+        assert_eq!(custom.era_for_slot(80_000_000), None);
+    }
+
+    #[test]
+    fn presets_by_magic() {
+        for magic in [MAINNET_MAGIC, TESTNET_MAGIC, PREPROD_MAGIC, PREVIEW_MAGIC] {
+            assert_eq!(
+                ChainWellKnownInfo::try_from_magic(magic).unwrap().magic,
+                magic
+            );
+        }
+
+        assert_eq!(*MagicArg::from_str("preview").unwrap(), PREVIEW_MAGIC);
+    }
+
+    #[test]
+    fn epoch_of_slot() {
+        let mainnet = ChainWellKnownInfo::mainnet();
+
+        assert_eq!(mainnet.epoch_for_slot(0), 0);
+        assert_eq!(mainnet.epoch_for_slot(4492799), 207);
+        assert_eq!(mainnet.epoch_for_slot(4492800), 208);
+        assert_eq!(mainnet.epoch_for_slot(4492800 + 432000), 209);
+
+        assert_eq!(ChainWellKnownInfo::testnet().epoch_for_slot(1598400), 74);
+        assert_eq!(ChainWellKnownInfo::preprod().epoch_for_slot(86400), 4);
+        assert_eq!(ChainWellKnownInfo::preview().epoch_for_slot(86400), 1);
+
+        // a custom chain whose byron anchor is past the slot doesn't underflow
+        let custom = ChainWellKnownInfo {
+            byron_known_slot: 100,
+            ..ChainWellKnownInfo::mainnet()
+        };
+
+        assert_eq!(custom.epoch_for_slot(10), 0);
+    }
+
+    #[test]
+    fn checkpoints_are_the_last_alonzo_block_of_their_network() {
+        for chain in [ChainWellKnownInfo::mainnet(), ChainWellKnownInfo::preprod()] {
+            let resolved = IntersectConfig::Checkpoint.resolve(&chain).unwrap();
+
+            let point = match resolved {
+                IntersectConfig::Point(x) => x,
+                _ => unreachable!("checkpoints resolve to a point"),
+            };
+
+            let point: Point = point.try_into().unwrap();
+
+            let slot = match point {
+                Point::Specific(slot, hash) => {
+                    assert_eq!(hash.len(), 32);
+                    slot
+                }
+                Point::Origin => unreachable!("checkpoints are specific points"),
+            };
+
+            // a block of the last minute before the Babbage hard fork
+            assert_eq!(chain.era_for_slot(slot), Some("alonzo"));
+            assert_eq!(chain.era_for_slot(slot + 60), Some("babbage"));
+        }
+
+        for chain in [ChainWellKnownInfo::testnet(), ChainWellKnownInfo::preview()] {
+            assert!(IntersectConfig::Checkpoint.resolve(&chain).is_err());
+        }
+    }
+}
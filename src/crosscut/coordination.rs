@@ -0,0 +1,205 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::Error;
+
+/// Options to coordinate several instances running against the same node
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CoordinationConfig {
+    /// Upper bound of a random delay applied before connecting to the node
+    pub startup_jitter_ms: Option<u64>,
+
+    /// Advisory lock that allows a single instance with the same name to run
+    pub lock: Option<LockConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LockConfig {
+    pub connection_params: String,
+    pub name: String,
+    pub ttl_secs: Option<u64>,
+}
+
+const DEFAULT_LOCK_TTL_SECS: u64 = 30;
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+impl CoordinationConfig {
+    /// Sleeps for a random amount of time bounded by `startup_jitter_ms`
+    pub fn apply_startup_jitter(&self) {
+        if let Some(max) = self.startup_jitter_ms.filter(|x| *x > 0) {
+            let delay = random_u64() % max;
+            log::info!("delaying startup by {}ms", delay);
+            std::thread::sleep(Duration::from_millis(delay));
+        }
+    }
+}
+
+/// An advisory lock held in Redis using `SET NX` with a TTL
+///
+/// The holder has to refresh the lock before the TTL expires, otherwise
+/// another instance might take it over.
+pub struct AdvisoryLock {
+    connection: redis::Connection,
+    key: String,
+    token: String,
+    ttl_secs: u64,
+}
+
+const REFRESH_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("expire", KEYS[1], ARGV[2])
+else
+    return 0
+end
+"#;
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end
+"#;
+
+impl AdvisoryLock {
+    fn try_acquire(&mut self) -> Result<bool, Error> {
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(&self.key)
+            .arg(&self.token)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.ttl_secs)
+            .query(&mut self.connection)
+            .map_err(Error::storage)?;
+
+        Ok(reply.is_some())
+    }
+
+    fn connect(config: &LockConfig) -> Result<Self, Error> {
+        let client =
+            redis::Client::open(config.connection_params.as_str()).map_err(Error::storage)?;
+        let connection = client.get_connection().map_err(Error::storage)?;
+
+        Ok(AdvisoryLock {
+            connection,
+            key: format!("_lock.{}", config.name),
+            token: format!("{}-{:x}", std::process::id(), random_u64()),
+            ttl_secs: config.ttl_secs.unwrap_or(DEFAULT_LOCK_TTL_SECS),
+        })
+    }
+
+    /// Blocks until the lock is acquired
+    pub fn acquire(config: &LockConfig) -> Result<Self, Error> {
+        let mut lock = Self::connect(config)?;
+
+        while !lock.try_acquire()? {
+            log::warn!("lock {} held by another instance, waiting", lock.key);
+            std::thread::sleep(Duration::from_secs(lock.ttl_secs.max(2) / 2));
+        }
+
+        log::info!("lock {} acquired", lock.key);
+
+        Ok(lock)
+    }
+
+    /// Extends the TTL of the lock, returns false if the lock isn't ours anymore
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        let extended: i64 = redis::Script::new(REFRESH_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .arg(self.ttl_secs)
+            .invoke(&mut self.connection)
+            .map_err(Error::storage)?;
+
+        Ok(extended == 1)
+    }
+
+    pub fn release(mut self) -> Result<(), Error> {
+        let _: i64 = redis::Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .invoke(&mut self.connection)
+            .map_err(Error::storage)?;
+
+        log::info!("lock {} released", self.key);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::testing::redis::{bulk, error, fake_redis, int};
+
+    /// Keeps the values and runs the lock scripts, told apart by their number
+    /// of arguments. TTLs are accepted but never expire.
+    fn fake_server() -> String {
+        let mut values = HashMap::<String, String>::new();
+
+        fake_redis(move |args| match args[0].to_uppercase().as_str() {
+            "SET" if args.contains(&"NX".to_string()) => match values.contains_key(&args[1]) {
+                true => bulk(None),
+                false => {
+                    values.insert(args[1].clone(), args[2].clone());
+                    bulk(Some("OK"))
+                }
+            },
+            "EVALSHA" => {
+                let (key, token) = (&args[3], &args[4]);
+
+                if values.get(key) != Some(token) {
+                    return int(0);
+                }
+
+                // the release script only gets the token
+                if args.len() == 5 {
+                    values.remove(key);
+                }
+
+                int(1)
+            }
+            _ => error("ERR unknown command"),
+        })
+    }
+
+    fn config(connection_params: &str) -> LockConfig {
+        LockConfig {
+            connection_params: connection_params.to_string(),
+            name: "daemon".to_string(),
+            ttl_secs: Some(5),
+        }
+    }
+
+    #[test]
+    fn lock_is_held_by_a_single_instance_until_released() {
+        let config = config(&fake_server());
+
+        let mut first = AdvisoryLock::acquire(&config).unwrap();
+        let mut second = AdvisoryLock::connect(&config).unwrap();
+
+        assert!(!second.try_acquire().unwrap());
+        assert!(first.refresh().unwrap());
+        assert!(!second.refresh().unwrap());
+
+        first.release().unwrap();
+
+        assert!(second.try_acquire().unwrap());
+        assert!(second.refresh().unwrap());
+
+        // releasing a lock taken over by another instance leaves it alone
+        let stale = AdvisoryLock::connect(&config).unwrap();
+        stale.release().unwrap();
+        assert!(second.refresh().unwrap());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fs::OpenOptions, io::Write};
+
+use pallas::ledger::primitives::byron;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{model::MultiEraBlock, Error};
+
+const DEFAULT_MAX_RECORDS: u64 = 1_000;
+
+/// Where to keep the blocks that made an isolated reducer fail
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum DeadLetterConfig {
+    /// Appends one JSON record per line to a local file
+    File {
+        path: String,
+        max_records: Option<u64>,
+    },
+
+    /// Publishes each JSON record to a Kafka topic
+    #[cfg(feature = "kafkasink")]
+    Kafka {
+        brokers: Vec<String>,
+        topic: String,
+        max_records: Option<u64>,
+    },
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Target {
+    File(std::fs::File),
+
+    #[cfg(feature = "kafkasink")]
+    Kafka(::kafka::producer::Producer, String),
+}
+
+/// Keeps the data needed to reproduce the failures of isolated reducers
+///
+/// Each record carries the reducer, the point and raw CBOR of the block, the
+/// hashes of its txs and the reason of the failure. Reducers process whole
+/// blocks, the tx that caused the failure isn't known so every tx of the block
+/// is listed. Once `max_records` are written, further records are dropped and
+/// counted.
+pub struct DeadLetterSink {
+    target: Target,
+    max_records: u64,
+    written: u64,
+    dropped_count: gasket::metrics::Counter,
+}
+
+impl DeadLetterConfig {
+    pub fn sink(&self) -> Result<DeadLetterSink, Error> {
+        let (target, max_records) = match self {
+            DeadLetterConfig::File { path, max_records } => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(Error::storage)?;
+
+                (Target::File(file), max_records)
+            }
+
+            #[cfg(feature = "kafkasink")]
+            DeadLetterConfig::Kafka {
+                brokers,
+                topic,
+                max_records,
+            } => {
+                let producer = ::kafka::producer::Producer::from_hosts(brokers.clone())
+                    .create()
+                    .map_err(Error::storage)?;
+
+                (Target::Kafka(producer, topic.clone()), max_records)
+            }
+        };
+
+        Ok(DeadLetterSink {
+            target,
+            max_records: max_records.unwrap_or(DEFAULT_MAX_RECORDS),
+            written: 0,
+            dropped_count: Default::default(),
+        })
+    }
+}
+
+fn tx_hashes(block: &MultiEraBlock) -> Vec<String> {
+    match block {
+        MultiEraBlock::Byron(byron::Block::MainBlock(x)) => x
+            .body
+            .tx_payload
+            .iter()
+            .map(|tx| tx.transaction.to_hash().to_string())
+            .collect(),
+        MultiEraBlock::Byron(_) => Vec::new(),
+        MultiEraBlock::AlonzoCompatible(x) => {
+            x.1.transaction_bodies
+                .iter()
+                .map(|tx| tx.to_hash().to_string())
+                .collect()
+        }
+    }
+}
+
+fn block_record(reducer: &str, block: &MultiEraBlock, reason: &str) -> serde_json::Value {
+    let point = match block.point() {
+        Ok(Point::Specific(slot, hash)) => json!({ "slot": slot, "hash": hex::encode(hash) }),
+        _ => json!(null),
+    };
+
+    let cbor = match block.encode() {
+        Ok(x) => json!(hex::encode(x)),
+        Err(err) => {
+            log::warn!("can't encode block for dead-letter record: {}", err);
+            json!(null)
+        }
+    };
+
+    json!({
+        "reducer": reducer,
+        "point": point,
+        "txs": tx_hashes(block),
+        "reason": reason,
+        "cbor": cbor,
+    })
+}
+
+impl DeadLetterSink {
+    fn write(&mut self, line: &str) -> Result<(), Error> {
+        match &mut self.target {
+            Target::File(file) => writeln!(file, "{}", line).map_err(Error::storage),
+
+            #[cfg(feature = "kafkasink")]
+            Target::Kafka(producer, topic) => producer
+                .send(&::kafka::producer::Record::from_value(
+                    topic,
+                    line.as_bytes(),
+                ))
+                .map_err(Error::storage),
+        }
+    }
+
+    /// Records a block that made a reducer fail. Errors of the sink itself
+    /// are only logged, they shouldn't stop the stage.
+    pub fn record(&mut self, reducer: &str, block: &MultiEraBlock, reason: &str) {
+        if self.written >= self.max_records {
+            self.dropped_count.inc(1);
+            return;
+        }
+
+        let record = block_record(reducer, block, reason);
+
+        match self.write(&record.to_string()) {
+            Ok(()) => {
+                self.written += 1;
+
+                if self.written == self.max_records {
+                    log::warn!("dead-letter sink is full, dropping further records");
+                }
+            }
+            Err(err) => {
+                log::error!("can't write dead-letter record: {}", err);
+                self.dropped_count.inc(1);
+            }
+        }
+    }
+
+    pub fn dropped_count(&self) -> &gasket::metrics::Counter {
+        &self.dropped_count
+    }
+}
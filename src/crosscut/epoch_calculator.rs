@@ -1,33 +1,51 @@
 // TODO this is temporary, we should actually use this code from Pallas as this is very generic code
-pub struct EpochCalculator {
-}
+pub struct EpochCalculator {}
 
 impl EpochCalculator {
-    
     pub fn get_shelley_epoch_no_for_absolute_slot(
         shelley_known_slot: u64,
         shelley_epoch_length: u64,
-        slot: u64
+        slot: u64,
     ) -> u64 {
         let last_byron_epoch_no = 208;
 
-        let shelley_known_slot = shelley_known_slot as u64;
-        let shelley_epoch_length = shelley_epoch_length as u64;
-
         let shelley_epoch_no = (slot - shelley_known_slot) / shelley_epoch_length;
 
-        return last_byron_epoch_no + shelley_epoch_no;
+        last_byron_epoch_no + shelley_epoch_no
     }
 
     pub fn get_byron_epoch_no_for_absolute_slot(
         byron_epoch_length: u64,
-        byron_slot_length: u64, 
+        byron_slot_length: u64,
         slot: u64,
     ) -> u64 {
-        let byron_epoch_length = byron_epoch_length as u64;
-        let byron_slot_length = byron_slot_length as u64;
-
-        return slot / (byron_epoch_length / byron_slot_length)
+        slot / (byron_epoch_length / byron_slot_length)
     }
 
+    /// Epoch of an absolute slot from the Byron and Shelley anchors of any
+    /// network, slots before the Byron anchor count as its epoch
+    pub fn get_epoch_no_for_absolute_slot(
+        byron_known_slot: u64,
+        byron_epoch_length: u64,
+        byron_slot_length: u64,
+        shelley_known_slot: u64,
+        shelley_epoch_length: u64,
+        slot: u64,
+    ) -> u64 {
+        let byron_slot = slot
+            .min(shelley_known_slot)
+            .saturating_sub(byron_known_slot);
+
+        let byron_epoch_no = Self::get_byron_epoch_no_for_absolute_slot(
+            byron_epoch_length,
+            byron_slot_length,
+            byron_slot,
+        );
+
+        if slot < shelley_known_slot {
+            return byron_epoch_no;
+        }
+
+        byron_epoch_no + (slot - shelley_known_slot) / shelley_epoch_length
+    }
 }
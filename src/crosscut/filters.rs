@@ -0,0 +1,531 @@
+use std::collections::{HashMap, HashSet};
+
+use pallas::{
+    codec::utils::{KeyValuePairs, MaybeIndefArray},
+    ledger::primitives::{alonzo, byron, Fragment},
+};
+use serde::Deserialize;
+
+use crate::model::MultiEraBlock;
+
+/// Returns the metadata labels present in the auxiliary data of a tx
+pub fn metadata_labels(aux: &alonzo::AuxiliaryData) -> Vec<u64> {
+    let metadata = match aux {
+        alonzo::AuxiliaryData::Shelley(x) => Some(x),
+        alonzo::AuxiliaryData::ShelleyMa {
+            transaction_metadata,
+            ..
+        } => Some(transaction_metadata),
+        alonzo::AuxiliaryData::Alonzo(x) => x.metadata.as_ref(),
+    };
+
+    metadata
+        .map(|m| {
+            m.iter()
+                .filter_map(|(label, _)| match label {
+                    alonzo::Metadatum::Int(x) => u64::try_from(*x).ok(),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn into_vec<T>(array: MaybeIndefArray<T>) -> Vec<T> {
+    match array {
+        MaybeIndefArray::Def(x) => x,
+        MaybeIndefArray::Indef(x) => x,
+    }
+}
+
+fn into_pairs<K, V>(pairs: KeyValuePairs<K, V>) -> Vec<(K, V)> {
+    match pairs {
+        KeyValuePairs::Def(x) => x,
+        KeyValuePairs::Indef(x) => x,
+    }
+}
+
+/// Categories of txs that can be selected by the filters
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxType {
+    /// Mints or burns assets
+    Mint,
+    /// Carries certificates
+    Certificate,
+    /// Carries native or Plutus scripts, or redeemers
+    Script,
+    /// Carries metadata
+    Metadata,
+    /// Doesn't fall in any of the other categories
+    Plain,
+}
+
+/// Returns the categories that the tx falls in, a tx can have several
+pub fn tx_types(
+    tx: &alonzo::TransactionBody,
+    witness: Option<&alonzo::TransactionWitnessSet>,
+    aux: Option<&alonzo::AuxiliaryData>,
+) -> Vec<TxType> {
+    let mut types = Vec::new();
+
+    let has_component = |f: fn(&alonzo::TransactionBodyComponent) -> bool| tx.iter().any(f);
+
+    if has_component(|c| matches!(c, alonzo::TransactionBodyComponent::Mint(_))) {
+        types.push(TxType::Mint);
+    }
+
+    if has_component(|c| matches!(c, alonzo::TransactionBodyComponent::Certificates(_))) {
+        types.push(TxType::Certificate);
+    }
+
+    let has_scripts = witness
+        .map(|w| w.native_script.is_some() || w.plutus_script.is_some() || w.redeemer.is_some())
+        .unwrap_or(false);
+
+    if has_scripts {
+        types.push(TxType::Script);
+    }
+
+    if aux.map(|x| !metadata_labels(x).is_empty()).unwrap_or(false) {
+        types.push(TxType::Metadata);
+    }
+
+    if types.is_empty() {
+        types.push(TxType::Plain);
+    }
+
+    types
+}
+
+/// Returns the categories that the byron tx falls in, byron txs have no
+/// metadata, mints nor certificates but can carry script witnesses
+pub fn byron_tx_types(tx: &byron::TxPayload) -> Vec<TxType> {
+    let has_scripts = tx
+        .witness
+        .iter()
+        .any(|w| matches!(w, byron::Twit::ScriptWitness(_)));
+
+    match has_scripts {
+        true => vec![TxType::Script],
+        false => vec![TxType::Plain],
+    }
+}
+
+/// Ledger eras, in chronological order
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Era {
+    Byron,
+    Shelley,
+    Allegra,
+    Mary,
+    Alonzo,
+}
+
+impl Era {
+    /// Era of the block, from the tag of the hard-fork combinator wrapper
+    pub fn of_block(block: &MultiEraBlock) -> Era {
+        match block {
+            MultiEraBlock::Byron(_) => Era::Byron,
+            MultiEraBlock::AlonzoCompatible(x) => match x.0 {
+                2 => Era::Shelley,
+                3 => Era::Allegra,
+                4 => Era::Mary,
+                _ => Era::Alonzo,
+            },
+        }
+    }
+}
+
+/// Filters applied to the content of each block before reaching the reducers
+///
+/// Filters work at the tx level: txs that don't match are hidden from the
+/// reducers, but the block itself is still processed so that the cursor keeps
+/// moving forward. Blocks outside of the era range are hidden as a whole.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filters {
+    /// Only process txs that carry at least one of these metadata labels
+    pub require_metadata_labels: Option<Vec<u64>>,
+
+    /// Only process txs that fall in any of these categories
+    pub tx_types: Option<Vec<TxType>>,
+
+    /// Skip the blocks of eras before this one
+    pub min_era: Option<Era>,
+
+    /// Skip the blocks of eras after this one
+    pub max_era: Option<Era>,
+}
+
+/// The view of a block that reducers should process after applying filters
+#[allow(clippy::large_enum_variant)]
+pub enum Filtered<'a> {
+    Unchanged(&'a MultiEraBlock),
+    Modified(MultiEraBlock),
+    Skipped,
+}
+
+impl<'a> Filtered<'a> {
+    pub fn block(&self) -> Option<&MultiEraBlock> {
+        match self {
+            Filtered::Unchanged(x) => Some(x),
+            Filtered::Modified(x) => Some(x),
+            Filtered::Skipped => None,
+        }
+    }
+}
+
+impl Filters {
+    fn era_matches(&self, block: &MultiEraBlock) -> bool {
+        let era = Era::of_block(block);
+
+        self.min_era.map(|min| era >= min).unwrap_or(true)
+            && self.max_era.map(|max| era <= max).unwrap_or(true)
+    }
+
+    /// True when no tx-level filter is set
+    fn is_trivial(&self) -> bool {
+        self.require_metadata_labels.is_none() && self.tx_types.is_none()
+    }
+
+    fn alonzo_tx_matches(
+        &self,
+        tx: &alonzo::TransactionBody,
+        witness: Option<&alonzo::TransactionWitnessSet>,
+        aux: Option<&alonzo::AuxiliaryData>,
+    ) -> bool {
+        if let Some(required) = &self.require_metadata_labels {
+            let labels = aux.map(metadata_labels).unwrap_or_default();
+
+            if !labels.iter().any(|l| required.contains(l)) {
+                return false;
+            }
+        }
+
+        if let Some(required) = &self.tx_types {
+            let types = tx_types(tx, witness, aux);
+
+            if !types.iter().any(|t| required.contains(t)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn byron_tx_matches(&self, tx: &byron::TxPayload) -> bool {
+        // byron txs never carry metadata
+        if self.require_metadata_labels.is_some() {
+            return false;
+        }
+
+        if let Some(required) = &self.tx_types {
+            let types = byron_tx_types(tx);
+
+            if !types.iter().any(|t| required.contains(t)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn filter_byron_block(
+        &self,
+        block: &byron::MainBlock,
+    ) -> Result<byron::MainBlock, crate::Error> {
+        let keep: Vec<_> = block
+            .body
+            .tx_payload
+            .iter()
+            .map(|tx| self.byron_tx_matches(tx))
+            .collect();
+
+        let mut copy = byron::MainBlock::decode_fragment(&block.encode_fragment()?)?;
+
+        let kept = into_vec(copy.body.tx_payload)
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(tx, keep)| keep.then_some(tx))
+            .collect();
+
+        copy.body.tx_payload = MaybeIndefArray::Def(kept);
+
+        Ok(copy)
+    }
+
+    fn filter_alonzo_block(&self, block: &alonzo::Block) -> Result<alonzo::Block, crate::Error> {
+        let aux: HashMap<_, _> = block
+            .auxiliary_data_set
+            .iter()
+            .map(|(idx, data)| (*idx, data))
+            .collect();
+
+        let invalid: HashSet<_> = block
+            .invalid_transactions
+            .iter()
+            .flat_map(|x| x.iter())
+            .copied()
+            .collect();
+
+        let keep: Vec<_> = block
+            .transaction_bodies
+            .iter()
+            .enumerate()
+            .map(|(idx, tx)| {
+                let tx_aux = aux.get(&(idx as u32)).copied();
+                let tx_witness = block.transaction_witness_sets.get(idx);
+                self.alonzo_tx_matches(tx, tx_witness, tx_aux)
+            })
+            .collect();
+
+        // pallas primitives aren't `Clone`, the matching txs are moved out of a
+        // copy of the block decoded from its own CBOR
+        let copy = alonzo::Block::decode_fragment(&block.encode_fragment()?)?;
+
+        let mut all_aux: HashMap<_, _> = into_pairs(copy.auxiliary_data_set).into_iter().collect();
+        let mut all_witnesses = into_vec(copy.transaction_witness_sets).into_iter();
+
+        let mut bodies = Vec::new();
+        let mut witnesses = Vec::new();
+        let mut aux_set = Vec::new();
+        let mut invalid_set = Vec::new();
+
+        for (idx, tx) in into_vec(copy.transaction_bodies).into_iter().enumerate() {
+            let witness = all_witnesses.next();
+
+            if !keep[idx] {
+                continue;
+            }
+
+            // indexes of aux data and invalid txs need to follow the new positions
+            let idx = idx as u32;
+            let new_idx = bodies.len() as u32;
+
+            bodies.push(tx);
+
+            if let Some(witness) = witness {
+                witnesses.push(witness);
+            }
+
+            if let Some(data) = all_aux.remove(&idx) {
+                aux_set.push((new_idx, data));
+            }
+
+            if invalid.contains(&idx) {
+                invalid_set.push(new_idx);
+            }
+        }
+
+        Ok(alonzo::Block {
+            header: copy.header,
+            transaction_bodies: MaybeIndefArray::Def(bodies),
+            transaction_witness_sets: MaybeIndefArray::Def(witnesses),
+            auxiliary_data_set: KeyValuePairs::Def(aux_set),
+            invalid_transactions: copy
+                .invalid_transactions
+                .map(|_| MaybeIndefArray::Def(invalid_set)),
+        })
+    }
+
+    pub fn apply<'a>(&self, block: &'a MultiEraBlock) -> Result<Filtered<'a>, crate::Error> {
+        if !self.era_matches(block) {
+            return Ok(Filtered::Skipped);
+        }
+
+        if self.is_trivial() {
+            return Ok(Filtered::Unchanged(block));
+        }
+
+        match block {
+            MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let filtered = self.filter_byron_block(x)?;
+                let wrapper = byron::Block::MainBlock(filtered);
+                Ok(Filtered::Modified(MultiEraBlock::Byron(wrapper)))
+            }
+            // epoch boundary blocks have no txs
+            MultiEraBlock::Byron(byron::Block::EbBlock(_)) => Ok(Filtered::Unchanged(block)),
+            MultiEraBlock::AlonzoCompatible(x) => {
+                let filtered = self.filter_alonzo_block(&x.1)?;
+                let wrapper = alonzo::BlockWrapper(x.0, filtered);
+                Ok(Filtered::Modified(MultiEraBlock::AlonzoCompatible(wrapper)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reducers::total_transactions_count;
+    use crate::testing::{address, block, byron_block, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn kept_txs(filters: &Filters, block: &MultiEraBlock) -> Vec<String> {
+        let filtered = filters.apply(block).unwrap();
+
+        match filtered.block() {
+            Some(MultiEraBlock::AlonzoCompatible(x)) => {
+                x.1.transaction_bodies
+                    .iter()
+                    .map(|tx| tx.to_hash().to_string())
+                    .collect()
+            }
+            Some(MultiEraBlock::Byron(_)) => unreachable!("built blocks are alonzo"),
+            None => vec![],
+        }
+    }
+
+    #[test]
+    fn only_txs_with_a_required_label_are_kept() {
+        let built = block()
+            .tx(|t| t.output(&address(1), 5_000_000).metadata(1968, "oracle"))
+            .tx(|t| t.output(&address(2), 1_000_000))
+            .tx(|t| t.output(&address(3), 1_000_000).metadata(674, "memo"))
+            .build();
+
+        let filters = Filters {
+            require_metadata_labels: Some(vec![1968]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            kept_txs(&filters, &built),
+            vec![tx_hash(&built, 0).to_string()]
+        );
+
+        // the kept tx still finds its metadata at its new position
+        let filtered = filters.apply(&built).unwrap();
+        match filtered.block() {
+            Some(MultiEraBlock::AlonzoCompatible(x)) => {
+                let (idx, aux) = x.1.auxiliary_data_set.iter().next().unwrap();
+                assert_eq!(*idx, 0);
+                assert_eq!(metadata_labels(aux), vec![1968]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn reducers_only_see_the_kept_txs_and_the_cursor_moves() {
+        let counter = total_transactions_count::Config { key_prefix: None }.plugin();
+
+        let filters = Filters {
+            require_metadata_labels: Some(vec![1968]),
+            ..Default::default()
+        };
+
+        let mut harness = Harness::with_filters(vec![counter], filters);
+
+        let mixed = block()
+            .tx(|t| t.output(&address(1), 5_000_000).metadata(1968, "oracle"))
+            .tx(|t| t.output(&address(2), 1_000_000))
+            .build();
+
+        harness.roll_forward(mixed);
+        assert_eq!(harness.store().counter("total_transactions_count"), Some(1));
+
+        // roll_forward returns once the cursor is at the block
+        let unlabeled = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(2), 1_000_000))
+            .build();
+
+        harness.roll_forward(unlabeled);
+        assert_eq!(harness.store().counter("total_transactions_count"), Some(1));
+    }
+
+    #[test]
+    fn byron_txs_are_evaluated_per_tx() {
+        let count_with = |filters: Filters| {
+            let counter = total_transactions_count::Config { key_prefix: None }.plugin();
+            let mut harness = Harness::with_filters(vec![counter], filters);
+
+            harness.roll_forward(byron_block(4_000_000, &[5_000_000, 2_000_000]));
+            harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+            let count = harness.store().counter("total_transactions_count");
+            count
+        };
+
+        let only = |types: Vec<TxType>| Filters {
+            tx_types: Some(types),
+            ..Default::default()
+        };
+
+        // byron txs are plain ones
+        assert_eq!(count_with(only(vec![TxType::Plain])), Some(3));
+        assert_eq!(count_with(only(vec![TxType::Mint, TxType::Plain])), Some(3));
+        assert_eq!(count_with(only(vec![TxType::Mint])), None);
+
+        // none of them carries metadata
+        let labeled = Filters {
+            require_metadata_labels: Some(vec![674]),
+            ..Default::default()
+        };
+
+        assert_eq!(count_with(labeled), None);
+        assert_eq!(count_with(Filters::default()), Some(3));
+    }
+
+    #[test]
+    fn byron_script_witnesses_make_script_txs() {
+        let built = match byron_block(4_000_000, &[5_000_000]) {
+            MultiEraBlock::Byron(byron::Block::MainBlock(x)) => x,
+            _ => unreachable!("byron_block builds main blocks"),
+        };
+
+        let mut tx = into_vec(built.body.tx_payload).remove(0);
+        assert_eq!(byron_tx_types(&tx), vec![TxType::Plain]);
+
+        let validator = (1, vec![0x01].into());
+        let redeemer = (1, vec![0x02].into());
+        tx.witness = MaybeIndefArray::Def(vec![byron::Twit::ScriptWitness(
+            pallas::codec::utils::CborWrap((validator, redeemer)),
+        )]);
+
+        assert_eq!(byron_tx_types(&tx), vec![TxType::Script]);
+    }
+
+    #[test]
+    fn blocks_outside_of_the_era_range_are_skipped_while_the_cursor_moves() {
+        let count_with = |filters: Filters| {
+            let counter = total_transactions_count::Config { key_prefix: None }.plugin();
+            let mut harness = Harness::with_filters(vec![counter], filters);
+
+            let byron = harness.roll_forward(byron_block(4_000_000, &[5_000_000, 2_000_000]));
+            assert_eq!(
+                harness.store().cursor().map(|x| x.to_string()),
+                Some(crate::crosscut::PointArg::from(byron).to_string())
+            );
+
+            harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+            let count = harness.store().counter("total_transactions_count");
+            count
+        };
+
+        let from_shelley = Filters {
+            min_era: Some(Era::Shelley),
+            ..Default::default()
+        };
+
+        assert_eq!(count_with(from_shelley), Some(1));
+
+        let until_byron = Filters {
+            max_era: Some(Era::Byron),
+            ..Default::default()
+        };
+
+        assert_eq!(count_with(until_byron), Some(2));
+
+        let only_mary = Filters {
+            min_era: Some(Era::Mary),
+            max_era: Some(Era::Mary),
+            ..Default::default()
+        };
+
+        assert_eq!(count_with(only_mary), None);
+    }
+}
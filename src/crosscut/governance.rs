@@ -0,0 +1,117 @@
+use bech32::{ToBase32, Variant};
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::alonzo;
+
+use crate::Error;
+
+/// The governance roles that a credential can hold, each with its own CIP-129
+/// prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GovernanceRole {
+    CommitteeHot,
+    CommitteeCold,
+    DRep,
+}
+
+impl GovernanceRole {
+    fn hrp(&self) -> &'static str {
+        match self {
+            GovernanceRole::CommitteeHot => "cc_hot",
+            GovernanceRole::CommitteeCold => "cc_cold",
+            GovernanceRole::DRep => "drep",
+        }
+    }
+
+    /// High nibble of the header byte
+    fn key_type(&self) -> u8 {
+        match self {
+            GovernanceRole::CommitteeHot => 0b0000,
+            GovernanceRole::CommitteeCold => 0b0001,
+            GovernanceRole::DRep => 0b0010,
+        }
+    }
+}
+
+/// Encodes a credential in the CIP-129 form of its role (eg: `drep1...`)
+///
+/// The payload is a header byte, holding the role and whether the credential
+/// is a key or a script hash, followed by the hash. Unlike CIP-105 ids, the
+/// header keeps key and script credentials apart.
+pub fn encode_governance_credential(
+    role: GovernanceRole,
+    cred: &alonzo::StakeCredential,
+) -> Result<String, Error> {
+    let (cred_type, hash) = match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => (0b0010, x),
+        alonzo::StakeCredential::Scripthash(x) => (0b0011, x),
+    };
+
+    let mut bytes = vec![role.key_type() << 4 | cred_type];
+    bytes.extend_from_slice(hash.as_ref());
+
+    bech32::encode(role.hrp(), bytes.to_base32(), Variant::Bech32).map_err(Error::ledger)
+}
+
+/// Encodes the id of a governance action, the tx that proposed it and its
+/// index in the tx, in its CIP-129 form (`gov_action1...`)
+///
+/// The index takes a single byte after the tx hash, the only layout settled
+/// by the CIP, so indexes above 255 are refused.
+pub fn encode_gov_action_id(tx: &Hash<32>, index: u64) -> Result<String, Error> {
+    let index = u8::try_from(index).map_err(|_| {
+        Error::ledger(format!(
+            "governance action index {} doesn't fit its CIP-129 id",
+            index
+        ))
+    })?;
+
+    let mut bytes = tx.to_vec();
+    bytes.push(index);
+
+    bech32::encode("gov_action", bytes.to_base32(), Variant::Bech32).map_err(Error::ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vectors of the CIP
+    #[test]
+    fn credentials_get_the_prefix_and_header_of_their_role() {
+        let key = alonzo::StakeCredential::AddrKeyhash(Hash::new([0; 28]));
+        let script = alonzo::StakeCredential::Scripthash(Hash::new([0; 28]));
+
+        let encoded = |role, cred| encode_governance_credential(role, cred).unwrap();
+
+        assert_eq!(
+            encoded(GovernanceRole::DRep, &key),
+            "drep1ygqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq7vlc9n"
+        );
+        assert_eq!(
+            encoded(GovernanceRole::DRep, &script),
+            "drep1yvqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq770f95"
+        );
+        assert_eq!(
+            encoded(GovernanceRole::CommitteeHot, &key),
+            "cc_hot1qgqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqvcdjk7"
+        );
+        assert_eq!(
+            encoded(GovernanceRole::CommitteeCold, &key),
+            "cc_cold1zgqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq6yewvh"
+        );
+    }
+
+    #[test]
+    fn gov_action_ids_append_the_index_to_the_tx() {
+        assert_eq!(
+            encode_gov_action_id(&Hash::new([0; 32]), 17).unwrap(),
+            "gov_action1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqpzklpgpf"
+        );
+        assert_eq!(
+            encode_gov_action_id(&Hash::new([0x11; 32]), 0).unwrap(),
+            "gov_action1zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygsq6dmejn"
+        );
+
+        assert!(encode_gov_action_id(&Hash::new([0; 32]), 256).is_err());
+    }
+}
@@ -1,5 +1,27 @@
+mod addresses;
 mod args;
+mod coordination;
+mod dead_letter;
 mod epoch_calculator;
+mod filters;
+mod governance;
+mod notifications;
+mod pause;
+mod policies;
+mod sampling;
+mod scope;
+mod templates;
 
+pub use addresses::*;
 pub use args::*;
-pub use epoch_calculator::*;
\ No newline at end of file
+pub use coordination::*;
+pub use dead_letter::*;
+pub use epoch_calculator::*;
+pub use filters::*;
+pub use governance::*;
+pub use notifications::*;
+pub use pause::*;
+pub use policies::*;
+pub use sampling::*;
+pub use scope::*;
+pub use templates::*;
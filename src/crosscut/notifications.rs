@@ -0,0 +1,244 @@
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::Error;
+
+/// Where to notify about rollbacks, downstream caches can use these to
+/// invalidate their entries
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RollbackNotifyConfig {
+    /// HTTP endpoint that receives a POST with a JSON payload per rollback
+    pub webhook_url: Option<String>,
+
+    /// Redis pub/sub channel that receives the same JSON payload
+    pub pubsub: Option<PubSubConfig>,
+
+    pub timeout_ms: Option<u64>,
+    pub max_retries: Option<u32>,
+    pub retry_backoff_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PubSubConfig {
+    pub connection_params: String,
+    pub channel: String,
+}
+
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 1_000;
+
+pub fn rollback_payload(point: &Point, depth: usize) -> serde_json::Value {
+    let point = match point {
+        Point::Origin => json!("origin"),
+        Point::Specific(slot, hash) => json!({ "slot": slot, "hash": hex::encode(hash) }),
+    };
+
+    json!({
+        "event": "rollback",
+        "point": point,
+        "depth": depth,
+    })
+}
+
+/// Delivers rollback notifications from a background thread
+///
+/// Deliveries never block the pipeline. Notifications that still fail after
+/// all of the retries are logged, counted and dropped.
+pub struct RollbackNotifier {
+    sender: Sender<serde_json::Value>,
+    failed_count: gasket::metrics::Counter,
+}
+
+impl RollbackNotifier {
+    pub fn notify(&self, point: &Point, depth: usize) {
+        self.send(rollback_payload(point, depth));
+    }
+
+    /// Delivers any other payload to the same destinations, used by reducers
+    /// that report their own events
+    pub fn send(&self, payload: serde_json::Value) {
+        if self.sender.send(payload).is_err() {
+            log::error!("notifier thread is gone, notification dropped");
+            self.failed_count.inc(1);
+        }
+    }
+
+    pub fn failed_count(&self) -> &gasket::metrics::Counter {
+        &self.failed_count
+    }
+}
+
+impl RollbackNotifyConfig {
+    fn deliver(&self, payload: &str) -> Result<(), Error> {
+        if let Some(url) = &self.webhook_url {
+            let timeout = Duration::from_millis(self.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+
+            ureq::post(url)
+                .timeout(timeout)
+                .set("Content-Type", "application/json")
+                .send_string(payload)
+                .map_err(|err| Error::message(format!("webhook failed: {}", err)))?;
+        }
+
+        if let Some(pubsub) = &self.pubsub {
+            let mut connection = redis::Client::open(pubsub.connection_params.clone())
+                .and_then(|x| x.get_connection())
+                .map_err(Error::storage)?;
+
+            let _: () = redis::cmd("PUBLISH")
+                .arg(&pubsub.channel)
+                .arg(payload)
+                .query(&mut connection)
+                .map_err(Error::storage)?;
+        }
+
+        Ok(())
+    }
+
+    fn run(self, receiver: Receiver<serde_json::Value>, failed_count: gasket::metrics::Counter) {
+        let max_retries = self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let backoff = self.retry_backoff_ms.unwrap_or(DEFAULT_RETRY_BACKOFF_MS);
+
+        for payload in receiver {
+            let payload = payload.to_string();
+            let mut attempt = 0;
+
+            while let Err(err) = self.deliver(&payload) {
+                if attempt >= max_retries {
+                    log::error!("dropping notification: {}", err);
+                    failed_count.inc(1);
+                    break;
+                }
+
+                attempt += 1;
+                log::warn!("{}, retry {} of {}", err, attempt, max_retries);
+                std::thread::sleep(Duration::from_millis(backoff * attempt as u64));
+            }
+        }
+    }
+
+    pub fn notifier(self) -> Option<RollbackNotifier> {
+        if self.webhook_url.is_none() && self.pubsub.is_none() {
+            return None;
+        }
+
+        let (sender, receiver) = channel();
+        let failed_count = gasket::metrics::Counter::default();
+
+        let thread_count = failed_count.clone();
+        std::thread::spawn(move || self.run(receiver, thread_count));
+
+        Some(RollbackNotifier {
+            sender,
+            failed_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Accepts one POST per status, answers with it and hands back the bodies
+    fn mock_webhook(statuses: Vec<u16>) -> (String, Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rollbacks", listener.local_addr().unwrap());
+        let (sender, receiver) = channel();
+
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    if let Some(x) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = x.trim().parse().unwrap();
+                    }
+
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let response = format!("HTTP/1.1 {} OK\r\nContent-Length: 0\r\n\r\n", status);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+                sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn config(url: String) -> RollbackNotifyConfig {
+        RollbackNotifyConfig {
+            webhook_url: Some(url),
+            retry_backoff_ms: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn webhook_receives_point_and_depth() {
+        let (url, received) = mock_webhook(vec![200, 200]);
+        let notifier = config(url).notifier().unwrap();
+
+        notifier.notify(&Point::Specific(50_000_020, vec![0xab; 32]), 2);
+        notifier.notify(&Point::Origin, 7);
+
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            json!({
+                "event": "rollback",
+                "point": { "slot": 50_000_020, "hash": "ab".repeat(32) },
+                "depth": 2,
+            })
+        );
+
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            json!({ "event": "rollback", "point": "origin", "depth": 7 })
+        );
+
+        assert_eq!(notifier.failed_count().get(), 0);
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried() {
+        let (url, received) = mock_webhook(vec![500, 200]);
+        let notifier = config(url).notifier().unwrap();
+
+        notifier.notify(&Point::Origin, 1);
+
+        let timeout = Duration::from_secs(5);
+        let first = received.recv_timeout(timeout).unwrap();
+        let retried = received.recv_timeout(timeout).unwrap();
+
+        assert_eq!(first, retried);
+        assert_eq!(notifier.failed_count().get(), 0);
+    }
+
+    #[test]
+    fn nothing_to_notify_without_destinations() {
+        assert!(RollbackNotifyConfig::default().notifier().is_none());
+    }
+}
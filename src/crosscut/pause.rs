@@ -0,0 +1,44 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Holds the source stages once the intersection with the chain is found,
+/// until the operator resumes the pipeline
+///
+/// The gate is shared by every clone, resuming it from the daemon releases
+/// all the stages holding a copy.
+#[derive(Clone, Default)]
+pub struct PauseGate(Arc<AtomicBool>);
+
+impl PauseGate {
+    pub fn paused() -> Self {
+        PauseGate(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn resume(&self) {
+        if self.0.swap(false, Ordering::SeqCst) {
+            log::warn!("pipeline resumed, processing blocks");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Blocks the calling stage while the gate is paused
+    pub fn wait(&self) {
+        while self.is_paused() {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+impl std::fmt::Debug for PauseGate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PauseGate").field(&self.is_paused()).finish()
+    }
+}
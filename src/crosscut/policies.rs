@@ -0,0 +1,36 @@
+use serde::Deserialize;
+
+/// What to do when a recoverable error is found while processing the chain
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorAction {
+    #[default]
+    Fail,
+    Skip,
+}
+
+/// Knobs that define how the pipeline reacts to unexpected situations
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RuntimePolicy {
+    pub on_decode_error: Option<ErrorAction>,
+
+    /// Refuse to sync from origin unless explicitly confirmed by the operator
+    pub require_explicit_origin: Option<bool>,
+
+    /// Keep the pipeline running when a single reducer fails or panics
+    pub isolate_reducers: Option<bool>,
+
+    /// Number of failures after which an isolated reducer is disabled
+    pub max_reducer_failures: Option<u32>,
+
+    /// Keep the blocks that made an isolated reducer fail
+    pub dead_letter: Option<super::DeadLetterConfig>,
+
+    /// Connect and find the intersection, but wait for a resume before
+    /// processing any block
+    pub start_paused: Option<bool>,
+
+    /// Gate shared with the sources, set by the daemon from `start_paused`
+    #[serde(skip)]
+    pub pause: super::PauseGate,
+}
@@ -0,0 +1,132 @@
+use serde::Deserialize;
+
+use crate::{model::CRDTCommand, Error};
+
+/// Restricts a reducer to a sample of the chain
+///
+/// Sampled reducers only see a fraction of the blocks, so their output is an
+/// approximation meant for exploratory analytics. The cursor still moves over
+/// every block.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SamplingConfig {
+    /// Process only one block out of every `every` blocks
+    pub every: Option<u64>,
+
+    /// Process only blocks whose slot is a multiple of this value
+    pub slot_modulus: Option<u64>,
+}
+
+impl SamplingConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        match (self.every, self.slot_modulus) {
+            (Some(_), Some(_)) => Err(Error::config(
+                "sampling accepts either `every` or `slot_modulus`, not both",
+            )),
+            (None, None) => Err(Error::config(
+                "sampling requires either `every` or `slot_modulus`",
+            )),
+            (Some(0), _) | (_, Some(0)) => {
+                Err(Error::config("sampling rate should be greater than zero"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (self.every, self.slot_modulus) {
+            (Some(n), _) => format!("1 in {} blocks", n),
+            (_, Some(n)) => format!("slots multiple of {}", n),
+            _ => "all blocks".to_string(),
+        }
+    }
+
+    pub fn sampler(self, reducer: &str) -> Sampler {
+        Sampler {
+            marker_key: format!("_sampling.{}", reducer),
+            config: self,
+            marked: false,
+            seen: 0,
+        }
+    }
+}
+
+/// Decides which blocks a sampled reducer gets to process
+pub struct Sampler {
+    config: SamplingConfig,
+    marker_key: String,
+    marked: bool,
+    seen: u64,
+}
+
+impl Sampler {
+    pub fn accepts(&mut self, slot: u64) -> bool {
+        let accepted = match (self.config.every, self.config.slot_modulus) {
+            (Some(n), _) => self.seen.is_multiple_of(n),
+            (_, Some(n)) => slot.is_multiple_of(n),
+            _ => true,
+        };
+
+        self.seen += 1;
+
+        accepted
+    }
+
+    /// Command flagging the data of the reducer as sampled, only returned once
+    pub fn marker(&mut self) -> Option<CRDTCommand> {
+        if self.marked {
+            return None;
+        }
+
+        self.marked = true;
+
+        Some(CRDTCommand::AnyWriteWins(
+            self.marker_key.clone(),
+            self.config.describe(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reducers;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn slot_modulus_keeps_one_block_in_ten() {
+        let config = SamplingConfig {
+            every: None,
+            slot_modulus: Some(10),
+        };
+
+        config.validate().unwrap();
+
+        let counter = reducers::total_transactions_count::Config { key_prefix: None }.plugin();
+        let sampler = config.sampler("total_transactions_count");
+        let mut harness = Harness::with_samplers(vec![(counter, Some(sampler))]);
+
+        let mut last = None;
+
+        for n in 0..30 {
+            let next = block()
+                .slot(DEFAULT_SLOT + n)
+                .number(n + 1)
+                .tx(|t| t.output(&address(1), 1_000_000))
+                .build();
+
+            last = Some(harness.roll_forward(next));
+        }
+
+        let store = harness.store();
+
+        // the cursor moved over the blocks left out of the sample
+        let last = last.map(|x| crate::crosscut::PointArg::from(x).to_string());
+        assert_eq!(store.cursor().map(|x| x.to_string()), last);
+
+        assert_eq!(store.counter("total_transactions_count"), Some(3));
+        assert_eq!(
+            store.string("_sampling.total_transactions_count"),
+            Some("slots multiple of 10")
+        );
+    }
+}
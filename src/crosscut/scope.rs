@@ -0,0 +1,73 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use super::Filters;
+
+/// Allowlists of a reducer that can be replaced while the pipeline runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlists {
+    /// Raw addresses, as compared against outputs
+    pub addresses: Option<HashSet<Vec<u8>>>,
+
+    /// Hex policy ids
+    pub policies: Option<HashSet<String>>,
+}
+
+/// The part of the config that selects what gets indexed, as opposed to how
+///
+/// Allowlists follow the order of the reducers in the config, a scope only
+/// applies to the same set of reducers it was read with.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub filters: Filters,
+    pub allowlists: Vec<Allowlists>,
+}
+
+/// Hands a new scope over to the reducers stage, which applies it before the
+/// next block it processes
+#[derive(Clone, Default)]
+pub struct ScopeHandle(Arc<Mutex<Option<Scope>>>);
+
+impl ScopeHandle {
+    /// Queues a scope, replacing any other one that wasn't applied yet
+    pub fn replace(&self, scope: Scope) {
+        *self.0.lock().expect("scope lock poisoned") = Some(scope);
+    }
+
+    pub fn take(&self) -> Option<Scope> {
+        self.0.lock().expect("scope lock poisoned").take()
+    }
+}
+
+fn describe_change<T: std::hash::Hash + Eq>(
+    before: &Option<HashSet<T>>,
+    after: &Option<HashSet<T>>,
+) -> Option<String> {
+    match (before, after) {
+        (None, None) => None,
+        (Some(_), None) => Some("allow all".to_string()),
+        (None, Some(x)) => Some(format!("allow {} only", x.len())),
+        (Some(a), Some(b)) if a == b => None,
+        (Some(a), Some(b)) => Some(format!(
+            "+{} -{}",
+            b.difference(a).count(),
+            a.difference(b).count()
+        )),
+    }
+}
+
+impl Allowlists {
+    /// Summary of the changes from another version of the allowlists, eg:
+    /// `addresses +2 -0`, empty when nothing changed
+    pub fn diff(&self, previous: &Allowlists) -> Vec<String> {
+        let addresses = describe_change(&previous.addresses, &self.addresses)
+            .map(|x| format!("addresses {}", x));
+
+        let policies =
+            describe_change(&previous.policies, &self.policies).map(|x| format!("policies {}", x));
+
+        addresses.into_iter().chain(policies).collect()
+    }
+}
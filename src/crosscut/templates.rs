@@ -0,0 +1,133 @@
+use crate::{model::CRDTCommand, Error};
+
+#[derive(Debug)]
+enum TemplatePart {
+    Literal(String),
+    Prefix,
+    Key,
+    Segment(usize),
+}
+
+/// Rewrites the keys of a reducer into the schema expected by the consumers
+///
+/// eg: `addr:{1}:bal` turns `balance.addr1xyz` into `addr:addr1xyz:bal`.
+/// Placeholders are `{prefix}` (the key up to its first dot), `{key}` (the rest
+/// of the key after that dot) and `{1}`, `{2}`... for the dot-separated parts
+/// of that rest. The template is parsed once, when the pipeline is built.
+#[derive(Debug)]
+pub struct KeyTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl KeyTemplate {
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .map(|x| start + x)
+                .ok_or_else(|| Error::config(format!("unclosed placeholder in {}", template)))?;
+
+            let part = match &rest[start + 1..end] {
+                "prefix" => TemplatePart::Prefix,
+                "key" => TemplatePart::Key,
+                x => match x.parse::<usize>() {
+                    Ok(idx) if idx > 0 => TemplatePart::Segment(idx),
+                    _ => {
+                        return Err(Error::config(format!(
+                            "unknown placeholder {{{}}} in {}",
+                            x, template
+                        )))
+                    }
+                },
+            };
+
+            parts.push(part);
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+
+        Ok(KeyTemplate { parts })
+    }
+
+    /// Keys without one of the parts of the template are left as they are
+    pub fn render(&self, key: String) -> String {
+        let (prefix, rest) = key.split_once('.').unwrap_or((&key, ""));
+        let segments: Vec<_> = rest.split('.').collect();
+
+        let mut out = String::with_capacity(key.len());
+
+        for part in self.parts.iter() {
+            match part {
+                TemplatePart::Literal(x) => out.push_str(x),
+                TemplatePart::Prefix => out.push_str(prefix),
+                TemplatePart::Key if !rest.is_empty() => out.push_str(rest),
+                TemplatePart::Key => {
+                    log::warn!("key {} has no rest for its template", key);
+                    return key;
+                }
+                TemplatePart::Segment(idx) => match segments.get(idx - 1) {
+                    Some(x) if !x.is_empty() => out.push_str(x),
+                    _ => {
+                        log::warn!("key {} has no part {} for its template", key, idx);
+                        return key;
+                    }
+                },
+            }
+        }
+
+        out
+    }
+
+    /// Undos go through the same template, so rollbacks reach the rewritten
+    /// keys
+    pub fn apply(&self, command: CRDTCommand) -> CRDTCommand {
+        command.map_keys(|x| self.render(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_take_the_parts_of_the_key() {
+        let template = KeyTemplate::parse("addr:{1}:bal").unwrap();
+        assert_eq!(
+            template.render("balance.addr1xyz".into()),
+            "addr:addr1xyz:bal"
+        );
+
+        let template = KeyTemplate::parse("{prefix}:{2}:{1}").unwrap();
+        assert_eq!(template.render("fees.addr1.310".into()), "fees:310:addr1");
+
+        let template = KeyTemplate::parse("v2/{key}").unwrap();
+        assert_eq!(template.render("fees.addr1.310".into()), "v2/addr1.310");
+    }
+
+    #[test]
+    fn keys_without_a_part_are_left_as_they_are() {
+        let template = KeyTemplate::parse("{prefix}:{2}").unwrap();
+        assert_eq!(template.render("balance.addr1".into()), "balance.addr1");
+        assert_eq!(template.render("balance".into()), "balance");
+
+        let template = KeyTemplate::parse("v2/{key}").unwrap();
+        assert_eq!(template.render("balance".into()), "balance");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert!(KeyTemplate::parse("addr:{name}").is_err());
+        assert!(KeyTemplate::parse("addr:{0}").is_err());
+        assert!(KeyTemplate::parse("addr:{1").is_err());
+    }
+}
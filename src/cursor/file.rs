@@ -0,0 +1,207 @@
+use std::{path::PathBuf, str::FromStr};
+
+use pallas::crypto::hash::Hasher;
+use serde::Deserialize;
+
+use crate::{crosscut, Error};
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    pub path: Option<String>,
+
+    /// Store the network magic and a checksum next to the point, and refuse
+    /// cursors that don't match them
+    pub integrity: Option<bool>,
+}
+
+pub struct Store {
+    path: PathBuf,
+
+    /// Network magic checked against signed cursors, none when the integrity
+    /// check is disabled
+    magic: Option<u64>,
+}
+
+fn checksum(body: &str) -> String {
+    Hasher::<256>::hash(body.as_bytes()).to_string()
+}
+
+/// Body of a signed cursor file, the checksum line is appended to it
+fn signed_body(point: &crosscut::PointArg, magic: u64) -> String {
+    format!("{}\nmagic={}", point, magic)
+}
+
+impl Store {
+    /// Checks a signed cursor file, returns the reason to refuse it if any
+    fn verify(&self, raw: &str, magic: u64) -> Option<String> {
+        let lines: Vec<_> = raw.trim().lines().collect();
+
+        let (point, found_magic, found_checksum) = match lines.as_slice() {
+            [point, magic, checksum] => (
+                point,
+                magic.strip_prefix("magic="),
+                checksum.strip_prefix("checksum="),
+            ),
+            _ => return Some("cursor file isn't signed".to_string()),
+        };
+
+        let (found_magic, found_checksum) = match (found_magic, found_checksum) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Some("cursor file has an invalid format".to_string()),
+        };
+
+        let body = format!("{}\nmagic={}", point, found_magic);
+
+        if checksum(&body) != found_checksum {
+            return Some("cursor checksum doesn't match its content".to_string());
+        }
+
+        if found_magic != magic.to_string() {
+            return Some(format!(
+                "cursor belongs to network magic {}, configured for {}",
+                found_magic, magic
+            ));
+        }
+
+        None
+    }
+}
+
+impl super::CursorStore for Store {
+    fn load(&self) -> Result<crosscut::Cursor, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let raw = std::fs::read_to_string(&self.path).map_err(Error::storage)?;
+
+        if let Some(magic) = self.magic {
+            if let Some(reason) = self.verify(&raw, magic) {
+                log::error!(
+                    "ignoring cursor file {}, falling back to the intersect config: {}",
+                    self.path.display(),
+                    reason
+                );
+
+                return Ok(None);
+            }
+        }
+
+        // the point is always the first line, signed or not
+        let first = raw.lines().next().unwrap_or_default();
+        let point = crosscut::PointArg::from_str(first.trim())?;
+
+        Ok(Some(point))
+    }
+
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error> {
+        let content = match self.magic {
+            Some(magic) => {
+                let body = signed_body(point, magic);
+                format!("{}\nchecksum={}", body, checksum(&body))
+            }
+            None => point.to_string(),
+        };
+
+        // write to a temp file first so that a crash never leaves a partial cursor
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, content).map_err(Error::storage)?;
+        std::fs::rename(&tmp, &self.path).map_err(Error::storage)?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn store(self, chain: &crosscut::ChainWellKnownInfo) -> super::Store {
+        let path = self.path.unwrap_or_else(|| "scrolls.cursor".to_string());
+
+        let magic = match self.integrity.unwrap_or(false) {
+            true => Some(chain.magic),
+            false => None,
+        };
+
+        super::Store::File(Store {
+            path: PathBuf::from(path),
+            magic,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::CursorStore;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scrolls-cursor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn point() -> crosscut::PointArg {
+        crosscut::PointArg::Specific(42, "ab".repeat(32))
+    }
+
+    #[test]
+    fn saves_through_a_temp_file() {
+        let path = temp_path("plain.cursor");
+        let mut store = Store {
+            path: path.clone(),
+            magic: None,
+        };
+
+        assert!(store.load().unwrap().is_none());
+
+        store.save(&point()).unwrap();
+
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), point().to_string());
+        assert_eq!(
+            store.load().unwrap().unwrap().to_string(),
+            point().to_string()
+        );
+    }
+
+    #[test]
+    fn signed_cursor_round_trips() {
+        let path = temp_path("signed.cursor");
+        let mut store = Store {
+            path: path.clone(),
+            magic: Some(764824073),
+        };
+
+        store.save(&point()).unwrap();
+
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(raw.lines().count(), 3);
+        assert_eq!(
+            store.load().unwrap().unwrap().to_string(),
+            point().to_string()
+        );
+    }
+
+    #[test]
+    fn refuses_tampered_or_foreign_cursors() {
+        let path = temp_path("tampered.cursor");
+        let mut store = Store {
+            path: path.clone(),
+            magic: Some(764824073),
+        };
+
+        store.save(&point()).unwrap();
+
+        let raw = std::fs::read_to_string(&path).unwrap();
+        let tampered = raw.replacen("42,", "43,", 1);
+
+        assert!(store.verify(&tampered, 764824073).is_some());
+        assert!(store.verify(&raw, 1097911063).is_some());
+        assert!(store.verify(&point().to_string(), 764824073).is_some());
+
+        std::fs::write(&path, tampered).unwrap();
+        assert!(store.load().unwrap().is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{crosscut, Error};
+
+struct State {
+    store: super::Store,
+
+    /// Points saved by each storage since the last persisted one, oldest
+    /// first
+    saved: Vec<VecDeque<crosscut::PointArg>>,
+}
+
+impl State {
+    /// Latest point saved by every storage, with its position in the queue of
+    /// each one
+    fn common(&self) -> Option<(crosscut::PointArg, Vec<usize>)> {
+        let first = self.saved.first()?;
+
+        first.iter().rev().find_map(|point| {
+            let positions: Option<Vec<_>> = self
+                .saved
+                .iter()
+                .map(|queue| queue.iter().rposition(|x| x == point))
+                .collect();
+
+            positions.map(|x| (point.clone(), x))
+        })
+    }
+}
+
+/// Shares a cursor store between the storages of a pipeline, so that the
+/// cursor only moves to a block once all of them have committed it
+///
+/// Each storage saves through its own [Member]. A point is persisted once it
+/// was saved by every member, the points older than it are dropped. Storages
+/// with different checkpoint cadences only move the cursor at the blocks that
+/// they all checkpoint.
+pub struct Gate(Arc<Mutex<State>>);
+
+impl Gate {
+    pub fn new(store: super::Store) -> Self {
+        let state = State {
+            store,
+            saved: Vec::new(),
+        };
+
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    /// Store of one more storage, every member has to save a point before it
+    /// is persisted
+    pub fn member(&self) -> super::Store {
+        let mut state = self.0.lock().expect("cursor gate lock poisoned");
+        state.saved.push(VecDeque::new());
+
+        super::Store::Gated(Member {
+            state: self.0.clone(),
+            index: state.saved.len() - 1,
+        })
+    }
+}
+
+pub struct Member {
+    state: Arc<Mutex<State>>,
+    index: usize,
+}
+
+impl super::CursorStore for Member {
+    fn load(&self) -> Result<crosscut::Cursor, Error> {
+        self.state
+            .lock()
+            .expect("cursor gate lock poisoned")
+            .store
+            .load()
+    }
+
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error> {
+        let mut state = self.state.lock().expect("cursor gate lock poisoned");
+        state.saved[self.index].push_back(point.clone());
+
+        let (point, positions) = match state.common() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        state.store.save(&point)?;
+
+        for (queue, position) in state.saved.iter_mut().zip(positions) {
+            queue.drain(..=position);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::CursorStore;
+
+    fn at(slot: u64) -> crosscut::PointArg {
+        crosscut::PointArg::Specific(slot, "ab".repeat(32))
+    }
+
+    #[test]
+    fn cursor_waits_for_the_slowest_storage() {
+        let path = std::env::temp_dir().join(format!("scrolls-gate-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = crate::cursor::file::Config {
+            path: Some(path.to_string_lossy().to_string()),
+            integrity: None,
+        }
+        .store(&crosscut::ChainWellKnownInfo::mainnet());
+
+        let gate = Gate::new(store);
+        let mut fast = gate.member();
+        let mut slow = gate.member();
+
+        let cursor = |store: &crate::cursor::Store| store.load().unwrap().map(|x| x.to_string());
+
+        for slot in [10, 20, 30] {
+            fast.save(&at(slot)).unwrap();
+        }
+
+        assert_eq!(cursor(&fast), None);
+
+        slow.save(&at(10)).unwrap();
+        assert_eq!(cursor(&fast), Some(at(10).to_string()));
+
+        // a sparser cadence only meets the other one at its own checkpoints
+        slow.save(&at(30)).unwrap();
+        assert_eq!(cursor(&slow), Some(at(30).to_string()));
+
+        fast.save(&at(40)).unwrap();
+        assert_eq!(cursor(&slow), Some(at(30).to_string()));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,159 @@
+//! Persistence of the chain cursor, decoupled from the storage of the
+//! collections themselves
+
+pub mod file;
+pub mod gate;
+pub mod redis;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+use serde::Deserialize;
+
+use crate::{crosscut, Error};
+
+pub trait CursorStore {
+    fn load(&self) -> Result<crosscut::Cursor, Error>;
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error>;
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum Store {
+    File(file::Store),
+    Redis(redis::Store),
+
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Store),
+
+    /// Shared with the other storages of the pipeline, see [gate::Gate]
+    Gated(gate::Member),
+
+    /// Doesn't persist the cursor, used by one-off runs that shouldn't
+    /// interfere with the cursor of the daemon
+    Skip,
+}
+
+impl CursorStore for Store {
+    fn load(&self) -> Result<crosscut::Cursor, Error> {
+        match self {
+            Store::File(x) => x.load(),
+            Store::Redis(x) => x.load(),
+            #[cfg(feature = "postgres")]
+            Store::Postgres(x) => x.load(),
+            Store::Gated(x) => x.load(),
+            Store::Skip => Ok(None),
+        }
+    }
+
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error> {
+        match self {
+            Store::File(x) => x.save(point),
+            Store::Redis(x) => x.save(point),
+            #[cfg(feature = "postgres")]
+            Store::Postgres(x) => x.save(point),
+            Store::Gated(x) => x.save(point),
+            Store::Skip => Ok(()),
+        }
+    }
+}
+
+/// How often a storage persists the cursor
+///
+/// A crash replays the blocks after the last persisted cursor, so a sparse
+/// cadence is only safe with storages whose writes can be applied twice.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Checkpoint {
+    EveryBlock,
+    EveryNBlocks(u64),
+    EpochBoundary,
+}
+
+/// Tells the storages which blocks are checkpoints for a [Checkpoint] policy
+pub struct Checkpointer {
+    policy: Checkpoint,
+    chain: crosscut::ChainWellKnownInfo,
+    blocks: u64,
+    last_epoch: Option<u64>,
+}
+
+impl Checkpointer {
+    pub fn new(policy: Checkpoint, chain: &crosscut::ChainWellKnownInfo) -> Self {
+        Self {
+            policy,
+            chain: chain.clone(),
+            blocks: 0,
+            last_epoch: None,
+        }
+    }
+
+    /// Registers a finished block, returns true if the cursor should be
+    /// persisted at its point. With `EpochBoundary`, that is the first block
+    /// seen of each new epoch.
+    pub fn is_due(&mut self, point: &crosscut::PointArg) -> bool {
+        self.blocks += 1;
+
+        match self.policy {
+            Checkpoint::EveryBlock => true,
+            Checkpoint::EveryNBlocks(n) => self.blocks.is_multiple_of(n.max(1)),
+            Checkpoint::EpochBoundary => {
+                let slot = match point {
+                    crosscut::PointArg::Origin => return false,
+                    crosscut::PointArg::Specific(slot, _) => *slot,
+                };
+
+                let epoch = self.chain.epoch_for_slot(slot);
+                let previous = self.last_epoch.replace(epoch);
+
+                matches!(previous, Some(x) if x != epoch)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(slot: u64) -> crosscut::PointArg {
+        crosscut::PointArg::Specific(slot, "ab".repeat(32))
+    }
+
+    fn due(policy: Checkpoint, slots: &[u64]) -> Vec<bool> {
+        let mut checkpointer = Checkpointer::new(policy, &crosscut::ChainWellKnownInfo::mainnet());
+        slots.iter().map(|x| checkpointer.is_due(&at(*x))).collect()
+    }
+
+    #[test]
+    fn every_n_blocks() {
+        assert_eq!(
+            due(Checkpoint::EveryNBlocks(2), &[1, 2, 3, 4]),
+            vec![false, true, false, true]
+        );
+
+        assert_eq!(due(Checkpoint::EveryNBlocks(0), &[1, 2]), vec![true, true]);
+        assert_eq!(due(Checkpoint::EveryBlock, &[1, 2]), vec![true, true]);
+    }
+
+    #[test]
+    fn first_block_of_each_new_epoch() {
+        // mainnet shelley epochs last 432000 slots from slot 4492800
+        let slots = [
+            4492800 + 10,
+            4492800 + 20,
+            4492800 + 432000,
+            4492800 + 432001,
+        ];
+
+        assert_eq!(
+            due(Checkpoint::EpochBoundary, &slots),
+            vec![false, false, true, false]
+        );
+
+        let mut checkpointer = Checkpointer::new(
+            Checkpoint::EpochBoundary,
+            &crosscut::ChainWellKnownInfo::mainnet(),
+        );
+        assert!(!checkpointer.is_due(&crosscut::PointArg::Origin));
+    }
+}
@@ -0,0 +1,226 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::{crosscut, storage, Error};
+
+/// Keeps the cursor in a row of a Postgres table, created when missing
+#[derive(Deserialize)]
+pub struct Config {
+    /// Connection string, eg: `host=localhost user=scrolls` or
+    /// `postgresql://scrolls@localhost/scrolls`
+    pub connection_params: String,
+
+    /// Table of the cursors, `scrolls_cursor` when missing
+    pub table: Option<String>,
+
+    /// Row of the cursor in the table, so that several pipelines can share
+    /// it. `_cursor` when missing.
+    pub key: Option<String>,
+
+    /// The operation timeout bounds each statement on the server
+    #[serde(flatten)]
+    pub timeouts: storage::timeouts::Timeouts,
+}
+
+pub struct Store {
+    config: Config,
+    client: Option<postgres::Client>,
+}
+
+/// The table name goes into the statements as is, so it's restricted to a
+/// plain identifier
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(x) if x.is_ascii_alphabetic() || x == '_' => (),
+        _ => return false,
+    }
+
+    chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+impl Store {
+    fn key(&self) -> &str {
+        match &self.config.key {
+            Some(x) => x,
+            None => "_cursor",
+        }
+    }
+
+    fn table(&self) -> Result<&str, Error> {
+        let table = match &self.config.table {
+            Some(x) => x,
+            None => "scrolls_cursor",
+        };
+
+        match is_identifier(table) {
+            true => Ok(table),
+            false => Err(Error::config(format!(
+                "postgres cursor table {} isn't a plain identifier",
+                table
+            ))),
+        }
+    }
+
+    fn postgres_connect(&self) -> Result<postgres::Client, Error> {
+        let mut client = postgres::Config::from_str(&self.config.connection_params)
+            .map_err(|x| Error::config(x.to_string()))?
+            .connect_timeout(self.config.timeouts.connect())
+            .connect(postgres::NoTls)
+            .map_err(Error::storage)?;
+
+        let statement = format!(
+            "SET statement_timeout = {}; \
+             CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, point TEXT NOT NULL)",
+            self.config.timeouts.operation().as_millis(),
+            self.table()?
+        );
+
+        client.batch_execute(&statement).map_err(Error::storage)?;
+
+        Ok(client)
+    }
+}
+
+impl super::CursorStore for Store {
+    fn load(&self) -> Result<crosscut::Cursor, Error> {
+        let mut client = self.postgres_connect()?;
+
+        let statement = format!("SELECT point FROM {} WHERE key = $1", self.table()?);
+
+        let row = client
+            .query_opt(statement.as_str(), &[&self.key()])
+            .map_err(Error::storage)?;
+
+        let point = match row {
+            Some(row) => {
+                let raw: String = row.try_get(0).map_err(Error::storage)?;
+                Some(crosscut::PointArg::from_str(&raw)?)
+            }
+            None => None,
+        };
+
+        Ok(point)
+    }
+
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error> {
+        if self.client.is_none() {
+            self.client = Some(self.postgres_connect()?);
+        }
+
+        let statement = format!(
+            "INSERT INTO {} (key, point) VALUES ($1, $2) \
+             ON CONFLICT (key) DO UPDATE SET point = EXCLUDED.point",
+            self.table()?
+        );
+
+        let key = self.key().to_string();
+
+        let result = self
+            .client
+            .as_mut()
+            .unwrap()
+            .execute(statement.as_str(), &[&key, &point.to_string()]);
+
+        // a broken connection is opened again on the next save
+        if let Err(err) = result {
+            self.client = None;
+            return Err(Error::storage(err));
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn store(self) -> super::Store {
+        super::Store::Postgres(Store {
+            config: self,
+            client: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_names_are_plain_identifiers() {
+        assert!(is_identifier("scrolls_cursor"));
+        assert!(is_identifier("_cursor2"));
+        assert!(!is_identifier("2cursor"));
+        assert!(!is_identifier("cursor; DROP TABLE x"));
+        assert!(!is_identifier("public.cursor"));
+        assert!(!is_identifier(""));
+    }
+
+    /// Connects with `POSTGRES_PARAMS` (defaults to `host=localhost
+    /// user=postgres`), each test keeps its cursor in its own table
+    #[cfg(feature = "postgres-local")]
+    fn local_store(table: &str) -> Store {
+        let connection_params = std::env::var("POSTGRES_PARAMS")
+            .unwrap_or_else(|_| "host=localhost user=postgres".to_string());
+
+        let table = format!("{}_{}", table, std::process::id());
+
+        let mut client = postgres::Client::connect(&connection_params, postgres::NoTls).unwrap();
+        client
+            .batch_execute(&format!("DROP TABLE IF EXISTS {}", table))
+            .unwrap();
+
+        Store {
+            config: Config {
+                connection_params,
+                table: Some(table),
+                key: None,
+                timeouts: Default::default(),
+            },
+            client: None,
+        }
+    }
+
+    #[cfg(feature = "postgres-local")]
+    #[test]
+    fn missing_cursor_loads_as_none() {
+        use crate::cursor::CursorStore;
+
+        let store = local_store("scrolls_test_missing");
+        assert!(store.load().unwrap().is_none());
+    }
+
+    #[cfg(feature = "postgres-local")]
+    #[test]
+    fn saved_cursor_round_trips() {
+        use crate::cursor::CursorStore;
+
+        let mut store = local_store("scrolls_test_round_trip");
+
+        let first = crosscut::PointArg::Specific(57_867_490, "ab".repeat(32));
+        store.save(&first).unwrap();
+
+        let second = crosscut::PointArg::Specific(57_867_510, "cd".repeat(32));
+        store.save(&second).unwrap();
+
+        assert_eq!(
+            store.load().unwrap().map(|x| x.to_string()),
+            Some(second.to_string())
+        );
+    }
+
+    #[cfg(feature = "postgres-local")]
+    #[test]
+    fn statements_over_the_operation_timeout_fail() {
+        let mut store = local_store("scrolls_test_timeout");
+        store.config.timeouts.operation_timeout_ms = Some(100);
+
+        let mut client = store.postgres_connect().unwrap();
+
+        let err = client.batch_execute("SELECT pg_sleep(1)").unwrap_err();
+        assert_eq!(err.code(), Some(&postgres::error::SqlState::QUERY_CANCELED));
+
+        client.batch_execute("SELECT pg_sleep(0.01)").unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+use redis::Commands;
+use serde::Deserialize;
+
+use crate::{crosscut, storage, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub connection_params: String,
+    pub key: Option<String>,
+
+    #[serde(flatten)]
+    pub timeouts: storage::timeouts::Timeouts,
+}
+
+pub struct Store {
+    config: Config,
+    connection: Option<redis::Connection>,
+}
+
+impl Store {
+    pub fn key(&self) -> &str {
+        match &self.config.key {
+            Some(x) => x,
+            None => "_cursor",
+        }
+    }
+
+    /// True if the cursor lives on the server of the connection, so that a
+    /// storage on it can write the cursor along with its data
+    pub fn shares_connection(&self, connection_params: &str) -> bool {
+        self.config.connection_params == connection_params
+    }
+
+    fn redis_connect(&self) -> Result<redis::Connection, Error> {
+        storage::redis::connect(
+            &self.config.timeouts,
+            self.config.connection_params.as_str(),
+        )
+        .map_err(Error::storage)
+    }
+}
+
+impl super::CursorStore for Store {
+    fn load(&self) -> Result<crosscut::Cursor, Error> {
+        let mut connection = self.redis_connect()?;
+
+        let raw: Option<String> = connection.get(self.key()).map_err(Error::storage)?;
+
+        let point = match raw {
+            Some(x) => Some(crosscut::PointArg::from_str(&x)?),
+            None => None,
+        };
+
+        Ok(point)
+    }
+
+    fn save(&mut self, point: &crosscut::PointArg) -> Result<(), Error> {
+        if self.connection.is_none() {
+            self.connection = Some(self.redis_connect()?);
+        }
+
+        let key = self.key().to_string();
+
+        let _: () = self
+            .connection
+            .as_mut()
+            .unwrap()
+            .set(key, point.to_string())
+            .map_err(Error::storage)?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn store(self) -> super::Store {
+        super::Store::Redis(Store {
+            config: self,
+            connection: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::cursor::CursorStore;
+    use crate::testing::redis::{bulk, error, fake_redis, ok};
+
+    type Values = Arc<Mutex<HashMap<String, String>>>;
+
+    /// Answers the GET and SET of the cursor
+    fn fake_server() -> (String, Values) {
+        let values = Values::default();
+        let shared = values.clone();
+
+        let address = fake_redis(move |args| {
+            let mut values = shared.lock().unwrap();
+
+            match args[0].to_uppercase().as_str() {
+                "GET" => bulk(values.get(&args[1]).map(String::as_str)),
+                "SET" => {
+                    values.insert(args[1].clone(), args[2].clone());
+                    ok()
+                }
+                _ => error("ERR unknown command"),
+            }
+        });
+
+        (address, values)
+    }
+
+    fn store(connection_params: &str, key: Option<&str>) -> Store {
+        Store {
+            config: Config {
+                connection_params: connection_params.to_string(),
+                key: key.map(String::from),
+                timeouts: Default::default(),
+            },
+            connection: None,
+        }
+    }
+
+    #[test]
+    fn missing_cursor_loads_as_none() {
+        let (address, _) = fake_server();
+
+        assert!(store(&address, None).load().unwrap().is_none());
+    }
+
+    #[test]
+    fn saved_cursor_round_trips() {
+        let (address, values) = fake_server();
+        let point = crosscut::PointArg::Specific(57_867_490, "ab".repeat(32));
+
+        let mut cursor = store(&address, Some("pipeline_a"));
+        cursor.save(&point).unwrap();
+
+        assert_eq!(
+            cursor.load().unwrap().map(|x| x.to_string()),
+            Some(point.to_string())
+        );
+        assert!(store(&address, None).load().unwrap().is_none());
+        assert_eq!(
+            values.lock().unwrap().get("pipeline_a"),
+            Some(&point.to_string())
+        );
+    }
+}
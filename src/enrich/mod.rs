@@ -0,0 +1,54 @@
+pub mod skip;
+pub mod sled;
+pub mod transform;
+
+use std::sync::Arc;
+
+pub use transform::{BlockTransform, EnrichedBlock, TransformRegistry};
+
+use crate::{bootstrap, model};
+
+type InputPort = gasket::messaging::InputPort<model::ChainSyncCommandEx>;
+type OutputPort = gasket::messaging::OutputPort<model::EnrichedBlockPayload>;
+
+pub enum Plugin {
+    Skip(skip::Worker),
+    Sled(sled::Worker),
+}
+
+impl Plugin {
+    pub fn borrow_input_port(&mut self) -> &'_ mut InputPort {
+        match self {
+            Plugin::Skip(x) => x.borrow_input_port(),
+            Plugin::Sled(x) => x.borrow_input_port(),
+        }
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        match self {
+            Plugin::Skip(x) => x.borrow_output_port(),
+            Plugin::Sled(x) => x.borrow_output_port(),
+        }
+    }
+
+    /// Runs the transforms over each block once its context is built
+    pub fn with_transforms(self, transforms: Vec<Arc<dyn BlockTransform>>) -> Self {
+        match self {
+            Plugin::Skip(mut x) => {
+                x.transforms = transforms;
+                Plugin::Skip(x)
+            }
+            Plugin::Sled(mut x) => {
+                x.transforms = transforms;
+                Plugin::Sled(x)
+            }
+        }
+    }
+
+    pub fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        match self {
+            Plugin::Skip(x) => x.spawn(pipeline),
+            Plugin::Sled(x) => x.spawn(pipeline),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use gasket::runtime::{spawn_stage, WorkOutcome};
+use serde::Deserialize;
+
+use crate::{bootstrap, model};
+
+#[derive(Deserialize)]
+pub struct Config {}
+
+/// Pass-through stage that forwards blocks with an empty context
+pub struct Worker {
+    input: super::InputPort,
+    output: super::OutputPort,
+    pub(super) transforms: Vec<Arc<dyn super::BlockTransform>>,
+}
+
+impl Worker {
+    pub fn borrow_input_port(&mut self) -> &'_ mut super::InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut super::OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("enrich-skip", spawn_stage(self, Default::default()));
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new().build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        match msg.payload {
+            model::ChainSyncCommandEx::RollForward(block) => {
+                let mut ctx = model::BlockContext::default();
+                super::transform::apply_all(&self.transforms, &block, &mut ctx);
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_forward(block, ctx))?;
+            }
+            model::ChainSyncCommandEx::RollBack(point) => {
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_back(point))?;
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let worker = Worker {
+            input: Default::default(),
+            output: Default::default(),
+            transforms: Vec::new(),
+        };
+
+        super::Plugin::Skip(worker)
+    }
+}
@@ -0,0 +1,593 @@
+use gasket::{
+    error::AsWorkError,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use pallas::{
+    crypto::hash::Hash,
+    ledger::primitives::{alonzo, byron},
+    network::miniprotocols::Point,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bootstrap, crosscut,
+    model::{self, BlockContext, MultiEraBlock, MultiEraOutput},
+};
+
+const STAKE_POINTERS_TREE: &str = "stake_pointers";
+const OUTPUT_SLOTS_TREE: &str = "output_slots";
+const JOURNAL_TREE: &str = "rollback_journal";
+
+/// Age in slots after which blocks can't be rolled back anymore (3k/f on
+/// mainnet) and their journal entries are pruned
+const MAX_JOURNALED_SLOTS: u64 = 129_600;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub db_path: String,
+}
+
+/// Keys inserted in the index by a block, to remove them if the block is
+/// rolled back
+#[derive(Serialize, Deserialize, Default)]
+struct Journal {
+    outputs: Vec<String>,
+    pointers: Vec<String>,
+}
+
+/// Keeps a local index of produced outputs to resolve the inputs of each block
+///
+/// The index is append-only, spent outputs are kept along with the slot that
+/// produced them, so that a block replayed after a crash still resolves its
+/// inputs. Txs that failed phase-2 validation only resolve their collateral,
+/// which is what they consume, and their outputs are never indexed. The keys
+/// inserted by the most recent blocks are journaled in the db itself, so that
+/// rollbacks remove the outputs and stake pointers of undone blocks even
+/// across restarts.
+pub struct Worker {
+    config: Config,
+    db: Option<::sled::Db>,
+    input: super::InputPort,
+    output: super::OutputPort,
+    inserts_counter: gasket::metrics::Counter,
+    matches_counter: gasket::metrics::Counter,
+    mismatches_counter: gasket::metrics::Counter,
+    rollback_removals: gasket::metrics::Counter,
+    pub(super) transforms: Vec<std::sync::Arc<dyn super::BlockTransform>>,
+}
+
+impl Worker {
+    pub fn borrow_input_port(&mut self) -> &'_ mut super::InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut super::OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("enrich-sled", spawn_stage(self, Default::default()));
+    }
+
+    fn insert_output(
+        &self,
+        slot: u64,
+        tx_hash: &Hash<32>,
+        idx: u64,
+        value: Vec<u8>,
+        journal: &mut Journal,
+    ) -> Result<(), crate::Error> {
+        let key = model::output_ref(tx_hash, idx);
+
+        let db = self.db.as_ref().unwrap();
+
+        db.insert(&key, value).map_err(crate::Error::storage)?;
+
+        db.open_tree(OUTPUT_SLOTS_TREE)
+            .and_then(|tree| tree.insert(&key, slot.to_be_bytes().to_vec()))
+            .map_err(crate::Error::storage)?;
+
+        journal.outputs.push(key);
+        self.inserts_counter.inc(1);
+
+        Ok(())
+    }
+
+    fn resolve_input(
+        &self,
+        tx_hash: &Hash<32>,
+        idx: u64,
+        ctx: &mut BlockContext,
+    ) -> Result<(), crate::Error> {
+        let key = model::output_ref(tx_hash, idx);
+
+        let found = self
+            .db
+            .as_ref()
+            .unwrap()
+            .get(&key)
+            .map_err(crate::Error::storage)?;
+
+        match found {
+            Some(bytes) => {
+                let slot = self
+                    .db
+                    .as_ref()
+                    .unwrap()
+                    .open_tree(OUTPUT_SLOTS_TREE)
+                    .and_then(|tree| tree.get(&key))
+                    .map_err(crate::Error::storage)?;
+
+                if let Some(slot) = slot {
+                    let mut raw = [0u8; 8];
+                    raw.copy_from_slice(&slot);
+                    ctx.import_ref_output_slot(key.clone(), u64::from_be_bytes(raw));
+                }
+
+                ctx.import_ref_output(key, MultiEraOutput::decode(&bytes)?);
+                self.matches_counter.inc(1);
+            }
+            None => {
+                log::debug!("couldn't resolve input {}", key);
+                self.mismatches_counter.inc(1);
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Keeps the position of stake registrations so that pointer addresses can
+    /// be resolved to their stake credential
+    fn insert_stake_pointers(
+        &self,
+        slot: u64,
+        block: &alonzo::Block,
+        journal: &mut Journal,
+    ) -> Result<(), crate::Error> {
+        let tree = self
+            .db
+            .as_ref()
+            .unwrap()
+            .open_tree(STAKE_POINTERS_TREE)
+            .map_err(crate::Error::storage)?;
+
+        for (tx_idx, tx) in block.transaction_bodies.iter().enumerate() {
+            let certs = tx
+                .iter()
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Certificates(x) => Some(x),
+                    _ => None,
+                })
+                .flat_map(|x| x.iter());
+
+            for (cert_idx, cert) in certs.enumerate() {
+                let credential = match cert {
+                    alonzo::Certificate::StakeRegistration(cred) => match cred {
+                        alonzo::StakeCredential::AddrKeyhash(x) => x,
+                        alonzo::StakeCredential::Scripthash(x) => x,
+                    },
+                    _ => continue,
+                };
+
+                let pointer = crosscut::StakePointer {
+                    slot,
+                    tx_idx: tx_idx as u64,
+                    cert_idx: cert_idx as u64,
+                };
+
+                let key = pointer.to_string();
+
+                tree.insert(&key, credential.as_ref())
+                    .map_err(crate::Error::storage)?;
+
+                journal.pointers.push(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_stake_pointer(
+        &self,
+        address: &[u8],
+        ctx: &mut BlockContext,
+    ) -> Result<(), crate::Error> {
+        let pointer = match crosscut::pointer_part(address) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let found = self
+            .db
+            .as_ref()
+            .unwrap()
+            .open_tree(STAKE_POINTERS_TREE)
+            .and_then(|tree| tree.get(pointer.to_string()))
+            .map_err(crate::Error::storage)?;
+
+        match found {
+            Some(credential) => ctx.import_stake_pointer(pointer, credential.to_vec()),
+            None => log::debug!("couldn't resolve stake pointer {}", pointer),
+        };
+
+        Ok(())
+    }
+
+    fn enrich_byron_block(
+        &self,
+        slot: u64,
+        block: &byron::MainBlock,
+        ctx: &mut BlockContext,
+        journal: &mut Journal,
+    ) -> Result<(), crate::Error> {
+        for tx in block.body.tx_payload.iter() {
+            for input in tx.transaction.inputs.iter() {
+                if let byron::TxIn::Variant0(x) = input {
+                    let (hash, idx) = &x.0;
+                    self.resolve_input(hash, *idx as u64, ctx)?;
+                }
+            }
+
+            let tx_hash = tx.transaction.to_hash();
+
+            for (idx, output) in tx.transaction.outputs.iter().enumerate() {
+                let value = MultiEraOutput::encode_byron(output)?;
+                self.insert_output(slot, &tx_hash, idx as u64, value, journal)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn enrich_alonzo_compatible_block(
+        &self,
+        block: &alonzo::Block,
+        ctx: &mut BlockContext,
+        journal: &mut Journal,
+    ) -> Result<(), crate::Error> {
+        let slot = block.header.header_body.slot;
+        let failed = model::failed_txs(block);
+
+        for (tx_idx, tx) in block.transaction_bodies.iter().enumerate() {
+            // a failed tx consumes its collateral instead of its inputs and
+            // produces nothing (alonzo bodies have no collateral return)
+            if failed.contains(&tx_idx) {
+                for input in model::collateral_inputs(tx) {
+                    self.resolve_input(&input.transaction_id, input.index, ctx)?;
+                }
+
+                continue;
+            }
+
+            for component in tx.iter() {
+                if let alonzo::TransactionBodyComponent::Inputs(inputs) = component {
+                    for input in inputs.iter() {
+                        self.resolve_input(&input.transaction_id, input.index, ctx)?;
+                    }
+                }
+            }
+
+            let tx_hash = tx.to_hash();
+
+            let outputs = tx
+                .iter()
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Outputs(o) => Some(o),
+                    _ => None,
+                })
+                .flat_map(|o| o.iter());
+
+            for (idx, output) in outputs.enumerate() {
+                self.insert_output(
+                    slot,
+                    &tx_hash,
+                    idx as u64,
+                    MultiEraOutput::encode_alonzo(output)?,
+                    journal,
+                )?;
+            }
+        }
+
+        self.insert_stake_pointers(slot, block, journal)?;
+
+        let produced = block
+            .transaction_bodies
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !failed.contains(idx))
+            .flat_map(|(_, tx)| tx.iter())
+            .filter_map(|c| match c {
+                alonzo::TransactionBodyComponent::Outputs(o) => Some(o),
+                _ => None,
+            })
+            .flat_map(|o| o.iter())
+            .map(|o| o.address.to_vec());
+
+        // addresses are copied out since resolving a pointer updates the context
+        let spent: Vec<_> = ctx
+            .ref_outputs()
+            .filter_map(|x| match x {
+                MultiEraOutput::AlonzoCompatible(x) => Some(x.address.to_vec()),
+                _ => None,
+            })
+            .collect();
+
+        for address in produced.chain(spent) {
+            self.resolve_stake_pointer(&address, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn enrich_block(&self, block: &MultiEraBlock) -> Result<BlockContext, crate::Error> {
+        let mut ctx = BlockContext::default();
+        let mut journal = Journal::default();
+
+        match block {
+            MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                self.enrich_byron_block(block.slot(), x, &mut ctx, &mut journal)?
+            }
+            MultiEraBlock::Byron(_) => (),
+            MultiEraBlock::AlonzoCompatible(x) => {
+                self.enrich_alonzo_compatible_block(&x.1, &mut ctx, &mut journal)?
+            }
+        };
+
+        self.write_journal(block.slot(), &journal)?;
+
+        Ok(ctx)
+    }
+
+    fn write_journal(&self, slot: u64, journal: &Journal) -> Result<(), crate::Error> {
+        if journal.outputs.is_empty() && journal.pointers.is_empty() {
+            return Ok(());
+        }
+
+        let tree = self
+            .db
+            .as_ref()
+            .unwrap()
+            .open_tree(JOURNAL_TREE)
+            .map_err(crate::Error::storage)?;
+
+        let value = serde_json::to_vec(journal).map_err(crate::Error::storage)?;
+
+        tree.insert(slot.to_be_bytes(), value)
+            .map_err(crate::Error::storage)?;
+
+        while let Some((key, _)) = tree.first().map_err(crate::Error::storage)? {
+            let mut journaled = [0u8; 8];
+            journaled.copy_from_slice(&key);
+
+            if u64::from_be_bytes(journaled) + MAX_JOURNALED_SLOTS >= slot {
+                break;
+            }
+
+            tree.remove(key).map_err(crate::Error::storage)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the outputs and stake pointers inserted by the blocks after the
+    /// rollback point
+    fn rollback(&self, point: &Point) -> Result<(), crate::Error> {
+        let db = self.db.as_ref().unwrap();
+
+        let journal = db.open_tree(JOURNAL_TREE).map_err(crate::Error::storage)?;
+
+        let pointers = db
+            .open_tree(STAKE_POINTERS_TREE)
+            .map_err(crate::Error::storage)?;
+
+        let slots = db
+            .open_tree(OUTPUT_SLOTS_TREE)
+            .map_err(crate::Error::storage)?;
+
+        let from = match point {
+            Point::Origin => 0,
+            Point::Specific(slot, _) => slot + 1,
+        };
+
+        let undone = journal
+            .range(from.to_be_bytes()..)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(crate::Error::storage)?;
+
+        for (key, value) in undone.into_iter().rev() {
+            let entry: Journal = serde_json::from_slice(&value).map_err(crate::Error::storage)?;
+
+            for output in entry.outputs.iter() {
+                db.remove(output).map_err(crate::Error::storage)?;
+                slots.remove(output).map_err(crate::Error::storage)?;
+            }
+
+            for pointer in entry.pointers.iter() {
+                pointers.remove(pointer).map_err(crate::Error::storage)?;
+            }
+
+            self.rollback_removals
+                .inc((entry.outputs.len() + entry.pointers.len()) as u64);
+
+            journal.remove(key).map_err(crate::Error::storage)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("enrich_inserts", &self.inserts_counter)
+            .with_counter("enrich_matches", &self.matches_counter)
+            .with_counter("enrich_mismatches", &self.mismatches_counter)
+            .with_counter("enrich_rollback_removals", &self.rollback_removals)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let db = ::sled::open(&self.config.db_path).or_work_err()?;
+        self.db = Some(db);
+
+        Ok(())
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        match msg.payload {
+            model::ChainSyncCommandEx::RollForward(block) => {
+                let mut ctx = self.enrich_block(&block).or_work_err()?;
+                super::transform::apply_all(&self.transforms, &block, &mut ctx);
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_forward(block, ctx))?;
+            }
+            model::ChainSyncCommandEx::RollBack(point) => {
+                self.rollback(&point).or_work_err()?;
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_back(point))?;
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        if let Some(db) = &self.db {
+            db.flush().or_work_err()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let worker = Worker {
+            config: self,
+            db: None,
+            input: Default::default(),
+            output: Default::default(),
+            inserts_counter: Default::default(),
+            matches_counter: Default::default(),
+            mismatches_counter: Default::default(),
+            rollback_removals: Default::default(),
+            transforms: Vec::new(),
+        };
+
+        super::Plugin::Sled(worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, DEFAULT_SLOT};
+
+    fn worker(name: &str) -> (Worker, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("scrolls-enrich-{}-{}", name, std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        let config = Config {
+            db_path: path.to_string_lossy().to_string(),
+        };
+
+        let mut worker = match config.plugin() {
+            super::super::Plugin::Sled(x) => x,
+            _ => unreachable!("sled config builds a sled plugin"),
+        };
+
+        gasket::runtime::Worker::bootstrap(&mut worker).unwrap();
+
+        (worker, path)
+    }
+
+    #[test]
+    fn failed_txs_consume_collateral_and_produce_nothing() {
+        let (worker, path) = worker("failed");
+
+        let funding = block()
+            .tx(|t| t.output(&address(1), 5_000_000))
+            .tx(|t| t.output(&address(2), 3_000_000))
+            .build();
+
+        let spent = tx_hash(&funding, 0);
+        let collateral = tx_hash(&funding, 1);
+        worker.enrich_block(&funding).unwrap();
+
+        let failing = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(spent, 0)
+                    .collateral(collateral, 0)
+                    .output(&address(3), 4_800_000)
+                    .failed()
+            })
+            .build();
+
+        let failed = tx_hash(&failing, 0);
+        let ctx = worker.enrich_block(&failing).unwrap();
+
+        // only the collateral is resolved as consumed
+        assert!(ctx.find_utxo(&collateral, 0).is_some());
+        assert!(ctx.find_utxo(&spent, 0).is_none());
+
+        // the outputs of the failed tx never make it to the index
+        let db = worker.db.as_ref().unwrap();
+        assert!(db.get(model::output_ref(&failed, 0)).unwrap().is_none());
+        assert!(db.get(model::output_ref(&spent, 0)).unwrap().is_some());
+
+        drop(worker);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn rolled_back_spend_leaves_the_output_resolvable() {
+        let (worker, path) = worker("rollback");
+
+        let funding = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funded = tx_hash(&funding, 0);
+        worker.enrich_block(&funding).unwrap();
+
+        let spending = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funded, 0).output(&address(2), 4_800_000))
+            .build();
+
+        let spend = tx_hash(&spending, 0);
+        worker.enrich_block(&spending).unwrap();
+
+        worker
+            .rollback(&Point::Specific(DEFAULT_SLOT, vec![]))
+            .unwrap();
+
+        // a different tx in the replacement block spends the same output
+        let respending = block()
+            .slot(DEFAULT_SLOT + 40)
+            .number(2)
+            .tx(|t| {
+                t.input(funded, 0)
+                    .input(spend, 0)
+                    .output(&address(3), 4_700_000)
+            })
+            .build();
+
+        let ctx = worker.enrich_block(&respending).unwrap();
+
+        assert!(ctx.find_utxo(&funded, 0).is_some());
+
+        // the output of the rolled-back spend is gone
+        assert!(ctx.find_utxo(&spend, 0).is_none());
+        assert_eq!(worker.rollback_removals.get(), 1);
+
+        drop(worker);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::json;
+
+use crate::{
+    crosscut,
+    model::{BlockContext, MultiEraBlock},
+    Error,
+};
+
+/// A block on its way to the reducers, along with its context
+pub struct EnrichedBlock<'a> {
+    pub block: &'a MultiEraBlock,
+    pub ctx: &'a mut BlockContext,
+}
+
+/// Custom computation run on each block after enrichment, before reducers
+///
+/// Transforms attach their results to the context as named fields, see
+/// [BlockContext::set_field]. They can't fail the pipeline, errors should be
+/// logged and the field left out.
+pub trait BlockTransform: Send + Sync {
+    fn apply(&self, block: &mut EnrichedBlock<'_>);
+}
+
+/// The transforms that can be enabled by name from the config
+///
+/// Crates embedding Scrolls can register their own transforms before building
+/// the pipeline.
+#[derive(Default)]
+pub struct TransformRegistry {
+    transforms: HashMap<String, Arc<dyn BlockTransform>>,
+}
+
+impl TransformRegistry {
+    /// A registry holding the transforms shipped with Scrolls
+    pub fn builtin(chain: &crosscut::ChainWellKnownInfo) -> Self {
+        let mut registry = Self::default();
+
+        registry.register(
+            "input_addresses",
+            InputAddresses {
+                address_hrp: chain.address_hrp.clone(),
+            },
+        );
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, transform: impl BlockTransform + 'static) {
+        self.transforms
+            .insert(name.to_string(), Arc::new(transform));
+    }
+
+    /// Picks the transforms enabled by the config, in the order given
+    pub fn resolve(&self, names: &[String]) -> Result<Vec<Arc<dyn BlockTransform>>, Error> {
+        names
+            .iter()
+            .map(|name| match self.transforms.get(name) {
+                Some(x) => Ok(x.clone()),
+                None => Err(Error::config(format!("unknown block transform: {}", name))),
+            })
+            .collect()
+    }
+}
+
+pub fn apply_all(
+    transforms: &[Arc<dyn BlockTransform>],
+    block: &MultiEraBlock,
+    ctx: &mut BlockContext,
+) {
+    let mut enriched = EnrichedBlock { block, ctx };
+
+    for transform in transforms {
+        transform.apply(&mut enriched);
+    }
+}
+
+/// Sets the `input_addresses` field to the addresses of the inputs resolved by
+/// the enrichment stage. Needs the `Sled` enrichment, the field is empty
+/// otherwise.
+pub struct InputAddresses {
+    address_hrp: String,
+}
+
+impl BlockTransform for InputAddresses {
+    fn apply(&self, block: &mut EnrichedBlock<'_>) {
+        let mut addresses: Vec<_> = block
+            .ctx
+            .ref_outputs()
+            .filter_map(|x| match x.address(&self.address_hrp) {
+                Ok(address) => Some(address),
+                Err(err) => {
+                    log::warn!("can't encode address of resolved input: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        addresses.sort();
+        addresses.dedup();
+
+        block.ctx.set_field("input_addresses", json!(addresses));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use gasket::{
+        messaging::{connect_ports, InputPort, OutputPort},
+        runtime::Worker as _,
+    };
+
+    use super::*;
+    use crate::{model, testing::block};
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl BlockTransform for Counting {
+        fn apply(&self, block: &mut EnrichedBlock<'_>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            block.ctx.set_field("slot", json!(block.block.slot()));
+        }
+    }
+
+    #[test]
+    fn registered_transforms_run_before_the_block_is_forwarded() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut registry = TransformRegistry::builtin(&crosscut::ChainWellKnownInfo::mainnet());
+        registry.register("counting", Counting(calls.clone()));
+
+        assert!(registry.resolve(&["unknown".to_string()]).is_err());
+
+        let transforms = registry.resolve(&["counting".to_string()]).unwrap();
+
+        let plugin = super::super::skip::Config {}
+            .plugin()
+            .with_transforms(transforms);
+
+        let mut stage = match plugin {
+            super::super::Plugin::Skip(x) => x,
+            _ => unreachable!("skip config builds a skip plugin"),
+        };
+
+        let mut upstream = OutputPort::<model::ChainSyncCommandEx>::default();
+        let mut downstream = InputPort::<model::EnrichedBlockPayload>::default();
+        connect_ports(&mut upstream, stage.borrow_input_port(), 10);
+        connect_ports(stage.borrow_output_port(), &mut downstream, 10);
+
+        let block = block().build();
+        let slot = block.slot();
+
+        upstream
+            .send(model::ChainSyncCommandEx::roll_forward(block))
+            .unwrap();
+        stage.work().unwrap();
+
+        match downstream.recv().unwrap().payload {
+            model::EnrichedBlockPayload::RollForward(_, ctx) => {
+                assert_eq!(ctx.field("slot"), Some(&json!(slot)));
+            }
+            model::EnrichedBlockPayload::RollBack(_) => panic!("expected the block"),
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,316 @@
+//! Optional gRPC server over the data of the storage, see `proto/scrolls.proto`
+//!
+//! Lookups go through the [Snapshottable] view of the storage and the
+//! subscriptions through its [Watchable] feed. Both are blocking, so each
+//! request runs them on a thread of the blocking pool of the server runtime.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    storage::{
+        memory, redis,
+        snapshot::{self, Snapshottable},
+        watch::{self, Watchable},
+    },
+    Error,
+};
+
+pub mod proto {
+    tonic::include_proto!("scrolls.v1");
+}
+
+/// Number of entries or changes buffered for a slow client before the read
+/// of the storage waits for it
+const STREAM_BUFFER: usize = 64;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Address to listen on, eg: `0.0.0.0:50051`
+    pub listen: String,
+}
+
+/// Opens the views of the storage served to the clients, once per request
+pub trait Source: Send + Sync + 'static {
+    fn reader(&self) -> Result<Box<dyn Snapshottable + Send>, Error>;
+    fn watcher(&self) -> Result<Box<dyn Watchable + Send>, Error>;
+}
+
+/// Serves a memory store of the same process
+impl Source for Arc<std::sync::Mutex<memory::Store>> {
+    fn reader(&self) -> Result<Box<dyn Snapshottable + Send>, Error> {
+        Ok(Box::new(memory::Snapshotter::new(self.clone())))
+    }
+
+    fn watcher(&self) -> Result<Box<dyn Watchable + Send>, Error> {
+        let watcher = self.lock().expect("memory store lock poisoned").watcher();
+        Ok(Box::new(watcher))
+    }
+}
+
+/// Serves a Redis storage, over connections of its own
+impl Source for redis::Config {
+    fn reader(&self) -> Result<Box<dyn Snapshottable + Send>, Error> {
+        Ok(Box::new(self.snapshotter()?))
+    }
+
+    fn watcher(&self) -> Result<Box<dyn Watchable + Send>, Error> {
+        Ok(Box::new(self.watcher()?))
+    }
+}
+
+fn to_status(err: Error) -> Status {
+    match err {
+        Error::ConfigError(x) => Status::failed_precondition(x),
+        x => Status::unavailable(x.to_string()),
+    }
+}
+
+fn to_proto(entry: snapshot::Entry) -> proto::Entry {
+    let (key, value) = match entry {
+        snapshot::Entry::String(key, value) => (key, proto::entry::Value::String(value)),
+        snapshot::Entry::Set(key, members) => {
+            (key, proto::entry::Value::Set(proto::Members { members }))
+        }
+        snapshot::Entry::SortedSet(key, members) => {
+            let members = members
+                .into_iter()
+                .map(|(member, score)| proto::ScoredMember { member, score })
+                .collect();
+
+            (
+                key,
+                proto::entry::Value::SortedSet(proto::ScoredMembers { members }),
+            )
+        }
+    };
+
+    proto::Entry {
+        key,
+        value: Some(value),
+    }
+}
+
+/// Runs a blocking op of the storage, off the threads of the runtime
+async fn blocking<T, F>(op: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(op)
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(to_status)
+}
+
+/// Stops a blocking read once the client of the stream went away
+fn gone<T>(_: mpsc::error::SendError<T>) -> Error {
+    Error::message("client closed the stream")
+}
+
+pub struct Service {
+    source: Arc<dyn Source>,
+}
+
+impl Service {
+    pub fn new(source: Arc<dyn Source>) -> Self {
+        Service { source }
+    }
+}
+
+#[tonic::async_trait]
+impl proto::query_server::Query for Service {
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let key = request.into_inner().key;
+        let source = self.source.clone();
+
+        let entry = blocking(move || source.reader()?.get(&key)).await?;
+
+        Ok(Response::new(proto::GetResponse {
+            entry: entry.map(to_proto),
+        }))
+    }
+
+    type ScanStream = ReceiverStream<Result<proto::Entry, Status>>;
+
+    async fn scan(
+        &self,
+        request: Request<proto::ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        let prefix = request.into_inner().prefix;
+        let mut reader = blocking({
+            let source = self.source.clone();
+            move || source.reader()
+        })
+        .await?;
+
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+        tokio::task::spawn_blocking(move || {
+            let result = reader.scan_prefix(&prefix, &mut |entry| {
+                sender.blocking_send(Ok(to_proto(entry))).map_err(gone)
+            });
+
+            if let Err(err) = result {
+                let _ = sender.blocking_send(Err(to_status(err)));
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<proto::Change, Status>>;
+
+    /// The watcher is open by the time the response is sent, so the client
+    /// gets every change applied after its call returns
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let prefix = request.into_inner().prefix;
+        let mut watcher = blocking({
+            let source = self.source.clone();
+            move || source.watcher()
+        })
+        .await?;
+
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+        // a client that went away is only noticed on the next change
+        tokio::task::spawn_blocking(move || {
+            let result = watcher.watch(&prefix, &mut |change: watch::Change| {
+                let change = proto::Change {
+                    key: change.key,
+                    event: change.event,
+                };
+
+                sender.blocking_send(Ok(change)).map_err(gone)
+            });
+
+            if let Err(err) = result {
+                let _ = sender.blocking_send(Err(to_status(err)));
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Serves the source on its own thread and runtime until the process exits
+pub fn start(config: &Config, source: Arc<dyn Source>) -> Result<(), Error> {
+    let addr: SocketAddr = config
+        .listen
+        .parse()
+        .map_err(|_| Error::config(format!("invalid grpc listen address {}", config.listen)))?;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("grpc")
+        .build()
+        .map_err(|err| Error::message(err.to_string()))?;
+
+    // binding up front surfaces a taken port before the pipeline starts
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind(addr))
+        .map_err(|err| Error::config(format!("can't listen on {}: {}", addr, err)))?;
+
+    log::info!("grpc server listening on {}", addr);
+
+    std::thread::spawn(move || {
+        let server = tonic::transport::Server::builder()
+            .add_service(proto::query_server::QueryServer::new(Service::new(source)))
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener));
+
+        if let Err(err) = runtime.block_on(server) {
+            log::error!("grpc server stopped: {}", err);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        reducers,
+        testing::{address, block, Harness, DEFAULT_SLOT},
+    };
+
+    #[test]
+    fn get_and_subscribe_follow_the_processed_blocks() {
+        let reducer = reducers::total_transactions_count::Config { key_prefix: None };
+        let mut harness = Harness::new(vec![reducer.plugin()]);
+
+        harness.roll_forward(block().tx(|t| t.output(&address(1), 5_000_000)).build());
+
+        let source: Arc<dyn Source> = Arc::new(harness.store_handle());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let (mut client, mut changes) = runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(proto::query_server::QueryServer::new(Service::new(source)))
+                    .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+            );
+
+            let mut client = proto::query_client::QueryClient::connect(format!("http://{}", addr))
+                .await
+                .unwrap();
+
+            let changes = client
+                .subscribe(proto::SubscribeRequest {
+                    prefix: "total_transactions_count".into(),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+
+            (client, changes)
+        });
+
+        let get = |client: &mut proto::query_client::QueryClient<tonic::transport::Channel>,
+                   key: &str| {
+            let request = proto::GetRequest { key: key.into() };
+            let response = runtime.block_on(client.get(request)).unwrap();
+            response.into_inner().entry.and_then(|x| x.value)
+        };
+
+        assert_eq!(
+            get(&mut client, "total_transactions_count"),
+            Some(proto::entry::Value::String(b"1".to_vec()))
+        );
+        assert_eq!(get(&mut client, "missing"), None);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(2), 1_000_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        let change = runtime.block_on(changes.message()).unwrap().unwrap();
+        assert_eq!(change.key, "total_transactions_count");
+        assert!(change.event.contains("pn_counter"));
+
+        assert_eq!(
+            get(&mut client, "total_transactions_count"),
+            Some(proto::entry::Value::String(b"2".to_vec()))
+        );
+
+        // the thread of the subscription only ends along with the store
+        runtime.shutdown_background();
+    }
+}
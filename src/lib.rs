@@ -1,10 +1,18 @@
 pub mod bootstrap;
 pub mod crosscut;
+pub mod cursor;
+pub mod enrich;
 pub mod model;
 pub mod reducers;
 pub mod sources;
 pub mod storage;
 
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 use std::fmt::Display;
 
 use thiserror::Error;
@@ -46,6 +54,10 @@ impl Error {
         Error::OuroborosError(format!("{}", error))
     }
 
+    pub fn ledger(error: impl Display) -> Error {
+        Error::LedgerError(format!("{}", error))
+    }
+
     pub fn storage(error: impl Display) -> Error {
         Error::StorageError(format!("{}", error))
     }
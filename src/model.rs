@@ -1,11 +1,15 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use pallas::{
-    ledger::primitives::{alonzo, byron},
+    crypto::hash::Hash,
+    ledger::primitives::{alonzo, byron, Fragment},
     network::miniprotocols::Point,
 };
 
-use crate::Error;
+use crate::{crosscut, Error};
 
 #[derive(Debug)]
 pub enum ChainSyncCommand {
@@ -47,16 +51,252 @@ impl ChainSyncCommandEx {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum EnrichedBlockPayload {
+    RollForward(Arc<MultiEraBlock>, Arc<BlockContext>),
+    RollBack(Point),
+}
+
+impl EnrichedBlockPayload {
+    pub fn roll_forward(
+        block: Arc<MultiEraBlock>,
+        ctx: BlockContext,
+    ) -> gasket::messaging::Message<Self> {
+        gasket::messaging::Message {
+            payload: Self::RollForward(block, Arc::new(ctx)),
+        }
+    }
+
+    pub fn roll_back(point: Point) -> gasket::messaging::Message<Self> {
+        gasket::messaging::Message {
+            payload: Self::RollBack(point),
+        }
+    }
+}
+
+/// Lovelace of an alonzo-compatible output, with or without assets
+pub fn alonzo_lovelace(output: &alonzo::TransactionOutput) -> u64 {
+    match output.amount {
+        alonzo::Value::Coin(x) => x,
+        alonzo::Value::Multiasset(x, _) => x,
+    }
+}
+
+/// Native assets of an alonzo-compatible output, none if it only holds
+/// lovelace
+pub fn alonzo_assets(output: &alonzo::TransactionOutput) -> Option<&alonzo::Multiasset<u64>> {
+    match &output.amount {
+        alonzo::Value::Coin(_) => None,
+        alonzo::Value::Multiasset(_, assets) => Some(assets),
+    }
+}
+
+/// A tx output from any era, as stored by the enrichment stage
 #[derive(Debug)]
+pub enum MultiEraOutput {
+    Byron(byron::TxOut),
+    AlonzoCompatible(alonzo::TransactionOutput),
+}
+
+impl MultiEraOutput {
+    pub fn address(&self, hrp: &str) -> Result<String, Error> {
+        match self {
+            MultiEraOutput::Byron(x) => x.address.to_addr_string().map_err(Error::ledger),
+            MultiEraOutput::AlonzoCompatible(x) => x.to_bech32_address(hrp).map_err(Error::ledger),
+        }
+    }
+
+    pub fn lovelace(&self) -> u64 {
+        match self {
+            MultiEraOutput::Byron(x) => x.amount,
+            MultiEraOutput::AlonzoCompatible(x) => alonzo_lovelace(x),
+        }
+    }
+
+    /// Encodes the output as CBOR prefixed by a 1-byte era tag
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            MultiEraOutput::Byron(x) => Self::encode_byron(x),
+            MultiEraOutput::AlonzoCompatible(x) => Self::encode_alonzo(x),
+        }
+    }
+
+    /// Encodes a borrowed byron output the same way as `encode`, pallas
+    /// outputs aren't `Clone`
+    pub fn encode_byron(output: &byron::TxOut) -> Result<Vec<u8>, Error> {
+        let mut cbor = output.encode_fragment()?;
+        cbor.insert(0, 0u8);
+        Ok(cbor)
+    }
+
+    /// Encodes a borrowed alonzo-compatible output the same way as `encode`
+    pub fn encode_alonzo(output: &alonzo::TransactionOutput) -> Result<Vec<u8>, Error> {
+        let mut cbor = output.encode_fragment()?;
+        cbor.insert(0, 1u8);
+        Ok(cbor)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.split_first() {
+            Some((0, cbor)) => Ok(MultiEraOutput::Byron(byron::TxOut::decode_fragment(cbor)?)),
+            Some((1, cbor)) => Ok(MultiEraOutput::AlonzoCompatible(
+                alonzo::TransactionOutput::decode_fragment(cbor)?,
+            )),
+            _ => Err(Error::message("unknown era tag for stored output")),
+        }
+    }
+}
+
+/// Indexes of the txs of the block that failed phase-2 validation (a Plutus
+/// script failed). Their inputs, outputs, fees and certificates don't apply to
+/// the ledger, their collateral inputs are consumed instead.
+pub fn failed_txs(block: &alonzo::Block) -> HashSet<usize> {
+    block
+        .invalid_transactions
+        .iter()
+        .flat_map(|x| x.iter())
+        .map(|x| *x as usize)
+        .collect()
+}
+
+/// Collateral inputs of a tx, only consumed if the tx fails phase-2
+/// validation
+pub fn collateral_inputs(
+    tx: &alonzo::TransactionBody,
+) -> impl Iterator<Item = &alonzo::TransactionInput> {
+    tx.iter()
+        .filter_map(|c| match c {
+            alonzo::TransactionBodyComponent::Collateral(x) => Some(x),
+            _ => None,
+        })
+        .flat_map(|x| x.iter())
+}
+
+/// Inputs that a tx takes out of the UTxO set: its collateral if it failed
+/// phase-2 validation, its regular inputs otherwise
+pub fn spent_inputs(tx: &alonzo::TransactionBody, failed: bool) -> Vec<&alonzo::TransactionInput> {
+    if failed {
+        return collateral_inputs(tx).collect();
+    }
+
+    tx.iter()
+        .filter_map(|c| match c {
+            alonzo::TransactionBodyComponent::Inputs(x) => Some(x),
+            _ => None,
+        })
+        .flat_map(|x| x.iter())
+        .collect()
+}
+
+/// Outputs that a tx adds to the UTxO set, by index, none if it failed
+/// phase-2 validation
+pub fn produced_outputs(
+    tx: &alonzo::TransactionBody,
+    failed: bool,
+) -> Vec<&alonzo::TransactionOutput> {
+    if failed {
+        return Vec::new();
+    }
+
+    tx.iter()
+        .filter_map(|c| match c {
+            alonzo::TransactionBodyComponent::Outputs(x) => Some(x),
+            _ => None,
+        })
+        .flat_map(|x| x.iter())
+        .collect()
+}
+
+pub type OutputRef = String;
+
+pub fn output_ref(tx_hash: &Hash<32>, idx: u64) -> OutputRef {
+    format!("{}#{}", tx_hash, idx)
+}
+
+/// Data from outside of the block required by reducers to process it
+#[derive(Debug, Default)]
+pub struct BlockContext {
+    utxos: HashMap<OutputRef, MultiEraOutput>,
+    utxo_slots: HashMap<OutputRef, u64>,
+    stake_pointers: HashMap<crosscut::StakePointer, Vec<u8>>,
+    fields: HashMap<String, serde_json::Value>,
+}
+
+impl BlockContext {
+    pub fn import_ref_output(&mut self, key: OutputRef, output: MultiEraOutput) {
+        self.utxos.insert(key, output);
+    }
+
+    pub fn import_ref_output_slot(&mut self, key: OutputRef, slot: u64) {
+        self.utxo_slots.insert(key, slot);
+    }
+
+    pub fn ref_outputs(&self) -> impl Iterator<Item = &MultiEraOutput> {
+        self.utxos.values()
+    }
+
+    /// Attaches a custom value computed by a block transform
+    pub fn set_field(&mut self, name: &str, value: serde_json::Value) {
+        self.fields.insert(name.to_string(), value);
+    }
+
+    pub fn field(&self, name: &str) -> Option<&serde_json::Value> {
+        self.fields.get(name)
+    }
+
+    pub fn import_stake_pointer(&mut self, pointer: crosscut::StakePointer, credential: Vec<u8>) {
+        self.stake_pointers.insert(pointer, credential);
+    }
+
+    pub fn find_stake_pointer(&self, pointer: &crosscut::StakePointer) -> Option<&[u8]> {
+        self.stake_pointers.get(pointer).map(|x| x.as_slice())
+    }
+
+    pub fn find_utxo(&self, tx_hash: &Hash<32>, idx: u64) -> Option<&MultiEraOutput> {
+        self.utxos.get(&output_ref(tx_hash, idx))
+    }
+
+    /// Slot of the block that produced a resolved utxo, unknown for outputs
+    /// indexed before creation slots were recorded
+    pub fn find_utxo_slot(&self, tx_hash: &Hash<32>, idx: u64) -> Option<u64> {
+        self.utxo_slots.get(&output_ref(tx_hash, idx)).copied()
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum MultiEraBlock {
     AlonzoCompatible(alonzo::BlockWrapper),
     Byron(byron::Block),
 }
 
 impl MultiEraBlock {
+    /// Byron epoch boundary blocks don't carry transactions, only a header
+    pub fn is_epoch_boundary(&self) -> bool {
+        matches!(self, MultiEraBlock::Byron(byron::Block::EbBlock(_)))
+    }
+
+    /// CBOR of the block, encoded back from its decoded form
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            MultiEraBlock::Byron(x) => Ok(x.encode_fragment()?),
+            MultiEraBlock::AlonzoCompatible(x) => Ok(x.encode_fragment()?),
+        }
+    }
+
+    pub fn slot(&self) -> u64 {
+        match self {
+            MultiEraBlock::Byron(byron::Block::EbBlock(x)) => x.header.to_abs_slot(),
+            MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                x.header.consensus_data.0.to_abs_slot()
+            }
+            MultiEraBlock::AlonzoCompatible(x) => x.1.header.header_body.slot,
+        }
+    }
+
     pub fn point(&self) -> Result<Point, Error> {
         match self {
-            MultiEraBlock::Byron(x) => match x.deref() {
+            MultiEraBlock::Byron(x) => match x {
                 byron::Block::EbBlock(x) => {
                     let hash = x.header.to_hash();
                     let slot = x.header.to_abs_slot();
@@ -81,17 +321,77 @@ pub type Member = String;
 pub type Key = String;
 pub type Value = String;
 pub type Timestamp = u64;
+pub type Delta = i64;
 
-#[derive(Debug)]
+/// Score of a timestamp in a sorted set, timestamps past the range of the
+/// scores saturate so that open-ended ranges (up to `u64::MAX`) still work
+pub fn timestamp_score(timestamp: Timestamp) -> i64 {
+    i64::try_from(timestamp).unwrap_or(i64::MAX)
+}
+
+/// Narrows an aggregated amount into a storage delta
+///
+/// Reducers accumulate amounts as `i128` so that sums of many values can't
+/// overflow. Values that still don't fit into a delta saturate instead of
+/// wrapping around.
+pub fn clamp_delta(value: i128) -> Delta {
+    match Delta::try_from(value) {
+        Ok(x) => x,
+        Err(_) => {
+            log::error!("amount {} overflows storage delta, saturating", value);
+
+            if value.is_negative() {
+                Delta::MIN
+            } else {
+                Delta::MAX
+            }
+        }
+    }
+}
+
+/// Applies a sign to a delta, eg: to undo it on rollback, saturating instead
+/// of overflowing on a delta that was already clamped
+pub fn signed_delta(delta: Delta, sign: Delta) -> Delta {
+    match delta.checked_mul(sign) {
+        Some(x) => x,
+        None => {
+            log::error!("delta {} overflows with sign {}, saturating", delta, sign);
+            delta.saturating_mul(sign)
+        }
+    }
+}
+
+/// Condition checked against the current value of the key by a
+/// `ConditionalWrite`. Missing keys satisfy every condition, comparisons parse
+/// both values as numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteCondition {
+    NotExists,
+    GreaterThan,
+    LessThan,
+}
+
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum CRDTCommand {
     BlockStarting(Point),
     TwoPhaseSetAdd(Set, Member),
     TwoPhaseSetRemove(Set, Member),
     GrowOnlySetAdd(Set, Member),
+    SetRemove(Set, Member),
+    HyperLogLogAdd(Key, Member),
     LastWriteWins(Key, Value, Timestamp),
+    AnyWriteWins(Key, Value),
+    AnyWriteWinsRemove(Key),
+    /// Writes the value only if the condition holds, checked atomically by
+    /// the storage (eg: keep the max seen value, or the first one)
+    ConditionalWrite(Key, Value, WriteCondition),
     // TODO make sure Value is a generic not stringly typed
     PNCounter(Key, Value),
+    SortedSetAdd(Set, Member, Delta),
+    SortedSetCopyTop(Set, Set, usize),
+    SortedSetRemoveRange(Set, Timestamp, Timestamp),
+    CounterSnapshot(Key, Delta, Set, Timestamp),
     BlockFinished(Point),
 }
 
@@ -105,4 +405,31 @@ impl CRDTCommand {
         let point = block.point().expect("block has defined point");
         CRDTCommand::BlockFinished(point)
     }
+
+    /// Rewrites every key of the command, the commands that delimit blocks
+    /// have none
+    pub fn map_keys<F>(self, f: F) -> CRDTCommand
+    where
+        F: Fn(String) -> String,
+    {
+        use CRDTCommand::*;
+
+        match self {
+            TwoPhaseSetAdd(key, member) => TwoPhaseSetAdd(f(key), member),
+            TwoPhaseSetRemove(key, member) => TwoPhaseSetRemove(f(key), member),
+            GrowOnlySetAdd(key, member) => GrowOnlySetAdd(f(key), member),
+            SetRemove(key, member) => SetRemove(f(key), member),
+            HyperLogLogAdd(key, member) => HyperLogLogAdd(f(key), member),
+            LastWriteWins(key, value, ts) => LastWriteWins(f(key), value, ts),
+            AnyWriteWins(key, value) => AnyWriteWins(f(key), value),
+            AnyWriteWinsRemove(key) => AnyWriteWinsRemove(f(key)),
+            ConditionalWrite(key, value, condition) => ConditionalWrite(f(key), value, condition),
+            PNCounter(key, value) => PNCounter(f(key), value),
+            SortedSetAdd(key, member, delta) => SortedSetAdd(f(key), member, delta),
+            SortedSetCopyTop(from, into, size) => SortedSetCopyTop(f(from), f(into), size),
+            SortedSetRemoveRange(key, min, max) => SortedSetRemoveRange(f(key), min, max),
+            CounterSnapshot(key, delta, set, ts) => CounterSnapshot(f(key), delta, f(set), ts),
+            x @ (BlockStarting(_) | BlockFinished(_)) => x,
+        }
+    }
 }
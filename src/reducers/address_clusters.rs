@@ -0,0 +1,424 @@
+use std::collections::BTreeSet;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+}
+
+/// A change to the clusters: an address joined a cluster, coming from a
+/// previous cluster if it had one
+type Change = (String, Option<String>);
+
+/// EXPERIMENTAL: groups addresses by the common-input-ownership heuristic
+///
+/// Addresses spent together as inputs of the same tx are assumed to belong to
+/// the same owner and share a cluster. The cluster id of each address is kept
+/// under `{prefix}.{address}`; ids are addresses of the cluster, the largest
+/// cluster keeps its id on merges. Only addresses seen as inputs get a cluster.
+///
+/// The heuristic is wrong for txs built by several parties (eg: CoinJoin-like
+/// or DEX batching) and deanonymizes wallets, so its output should be treated
+/// as a guess and handled with care. The whole partition is kept in the state
+/// db, which is heavy on mainnet.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    cluster_of: state::Map<String>,
+    members: state::Map<BTreeSet<String>>,
+    recent: state::History<Vec<Change>>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, address),
+            None => format!("address_clusters.{}", address),
+        }
+    }
+
+    fn key_format(&self) -> crosscut::AddressKeyFormat {
+        self.config.address_key_format.unwrap_or_default()
+    }
+
+    fn collect_input(
+        &self,
+        utxo: Option<&model::MultiEraOutput>,
+        ctx: &model::BlockContext,
+        addresses: &mut Vec<String>,
+    ) -> Result<(), gasket::error::Error> {
+        let utxo = match utxo {
+            Some(x) => x,
+            None => {
+                log::warn!("missing utxo in block context");
+                return Ok(());
+            }
+        };
+
+        let address = self
+            .key_format()
+            .output_key_ctx(utxo, &self.chain.address_hrp, ctx)
+            .or_work_err()?;
+
+        if let Some(address) = address {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn byron_inputs(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<String>, gasket::error::Error> {
+        let mut addresses = Vec::new();
+
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+                self.collect_input(ctx.find_utxo(hash, *idx as u64), ctx, &mut addresses)?;
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    fn alonzo_inputs(
+        &self,
+        tx: &alonzo::TransactionBody,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<String>, gasket::error::Error> {
+        let mut addresses = Vec::new();
+
+        for component in tx.iter() {
+            if let alonzo::TransactionBodyComponent::Inputs(inputs) = component {
+                for input in inputs.iter() {
+                    let utxo = ctx.find_utxo(&input.transaction_id, input.index);
+                    self.collect_input(utxo, ctx, &mut addresses)?;
+                }
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    fn send_cluster(
+        &self,
+        address: &str,
+        cluster: Option<&String>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = match cluster {
+            Some(cluster) => model::CRDTCommand::AnyWriteWins(self.key(address), cluster.clone()),
+            None => model::CRDTCommand::AnyWriteWinsRemove(self.key(address)),
+        };
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    fn join(
+        &mut self,
+        address: String,
+        cluster: &str,
+        members: &mut BTreeSet<String>,
+        changes: &mut Vec<Change>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let cluster = cluster.to_string();
+        let previous = self.cluster_of.insert(&address, &cluster).or_work_err()?;
+
+        members.insert(address.clone());
+
+        self.send_cluster(&address, Some(&cluster), output)?;
+
+        changes.push((address, previous));
+
+        Ok(())
+    }
+
+    /// Merges the clusters of all of the input addresses of a tx
+    fn merge(
+        &mut self,
+        addresses: Vec<String>,
+        changes: &mut Vec<Change>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut clusters: Vec<(String, usize)> = Vec::new();
+        let mut unclustered = Vec::new();
+
+        for address in addresses {
+            match self.cluster_of.get(&address).or_work_err()? {
+                Some(cluster) if !clusters.iter().any(|(x, _)| *x == cluster) => {
+                    let size = self.members.get(&cluster).or_work_err()?.map(|x| x.len());
+                    clusters.push((cluster, size.unwrap_or(0)));
+                }
+                Some(_) => (),
+                None => unclustered.push(address),
+            }
+        }
+
+        let largest = clusters
+            .iter()
+            .max_by_key(|(_, size)| *size)
+            .map(|(x, _)| x.clone());
+
+        let target = match largest.or_else(|| unclustered.first().cloned()) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut members = self.members.get(&target).or_work_err()?.unwrap_or_default();
+
+        for (cluster, _) in clusters.iter().filter(|(x, _)| *x != target) {
+            let moved = self
+                .members
+                .remove(cluster)
+                .or_work_err()?
+                .unwrap_or_default();
+
+            for address in moved {
+                self.join(address, &target, &mut members, changes, output)?;
+            }
+        }
+
+        for address in unclustered {
+            self.join(address, &target, &mut members, changes, output)?;
+        }
+
+        self.members.insert(&target, &members).or_work_err()?;
+
+        Ok(())
+    }
+
+    /// Moves each address of the changes back to its previous cluster
+    fn undo(&mut self, changes: Vec<Change>) -> Result<(), crate::Error> {
+        for (address, previous) in changes.into_iter().rev() {
+            if let Some(cluster) = self.cluster_of.get(&address)? {
+                if let Some(mut members) = self.members.get(&cluster)? {
+                    members.remove(&address);
+
+                    let members = Some(&members).filter(|x| !x.is_empty());
+                    self.members.restore(&cluster, members)?;
+                }
+            }
+
+            if let Some(previous) = &previous {
+                let mut members = self.members.get(previous)?.unwrap_or_default();
+                members.insert(address.clone());
+                self.members.insert(previous, &members)?;
+            }
+
+            self.cluster_of.restore(&address, previous.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        // changes left by a run that stopped before the storage committed
+        for (_, changes) in self.recent.rewind(block.slot()).or_work_err()? {
+            self.undo(changes).or_work_err()?;
+        }
+
+        let mut changes = Vec::new();
+
+        let slot = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    let addresses = self.byron_inputs(tx, ctx)?;
+                    self.merge(addresses, &mut changes, output)?;
+                }
+
+                x.header.consensus_data.0.to_abs_slot()
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                for tx in x.1.transaction_bodies.iter() {
+                    let addresses = self.alonzo_inputs(tx, ctx)?;
+                    self.merge(addresses, &mut changes, output)?;
+                }
+
+                x.1.header.header_body.slot
+            }
+        };
+
+        if !changes.is_empty() {
+            self.recent.push(slot, &changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    /// Undoes the merges of the blocks after the rollback point, moving each
+    /// address back to its previous cluster
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, changes) in self.recent.rollback(point).or_work_err()? {
+            for (address, previous) in changes.iter().rev() {
+                self.send_cluster(address, previous.as_ref(), output)?;
+            }
+
+            self.undo(changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        state: &state::Db,
+    ) -> Result<super::Plugin, crate::Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "address_clusters".to_string(),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            cluster_of: state.map(&format!("{}.cluster_of", name))?,
+            members: state.map(&format!("{}.members", name))?,
+            recent: state.history(&name)?,
+        };
+
+        Ok(super::Plugin::AddressClusters(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, state_db, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        config.plugin(&chain, state).unwrap()
+    }
+
+    fn key(seed: u8) -> String {
+        format!("address_clusters.{}", address(seed))
+    }
+
+    /// Funds addresses 1 and 2, then spends their first outputs together
+    fn funded_then_merged() -> (model::MultiEraBlock, model::MultiEraBlock) {
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 5_000_000)
+                    .output(&address(2), 3_000_000)
+                    .output(&address(1), 2_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .input(funding, 1)
+                    .output(&address(3), 7_800_000)
+                    .fee(200_000)
+            })
+            .build();
+
+        (first, second)
+    }
+
+    #[test]
+    fn inputs_spent_together_share_a_cluster_until_rolled_back() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let (first, second) = funded_then_merged();
+        let first = harness.roll_forward(first);
+        harness.roll_forward(second);
+
+        let cluster = address(1);
+
+        assert_eq!(harness.store().string(&key(1)), Some(cluster.as_str()));
+        assert_eq!(harness.store().string(&key(2)), Some(cluster.as_str()));
+        assert_eq!(harness.store().string(&key(3)), None);
+
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().string(&key(1)), None);
+        assert_eq!(harness.store().string(&key(2)), None);
+    }
+
+    #[test]
+    fn clusters_of_a_previous_run_keep_growing() {
+        let state = state_db();
+        let mut harness = Harness::new(vec![reducer(&state)]);
+
+        let (first, second) = funded_then_merged();
+        let change = tx_hash(&first, 0);
+        harness.roll_forward(first);
+        harness.roll_forward(second);
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+
+        let extra = block()
+            .slot(DEFAULT_SLOT + 40)
+            .number(3)
+            .tx(|t| t.output(&address(4), 2_000_000))
+            .build();
+
+        let extra_tx = tx_hash(&extra, 0);
+        let extra = harness.roll_forward(extra);
+
+        // without the clusters of the previous run, address 4 would give its
+        // id to a new cluster
+        let third = block()
+            .slot(DEFAULT_SLOT + 60)
+            .number(4)
+            .tx(|t| {
+                t.input(extra_tx, 0)
+                    .input(change, 2)
+                    .output(&address(5), 3_500_000)
+            })
+            .build();
+
+        harness.roll_forward(third);
+
+        let cluster = address(1);
+
+        for seed in [1, 2, 4] {
+            assert_eq!(harness.store().string(&key(seed)), Some(cluster.as_str()));
+        }
+
+        harness.roll_back(extra);
+
+        assert_eq!(harness.store().string(&key(4)), None);
+        assert_eq!(harness.store().string(&key(1)), Some(cluster.as_str()));
+    }
+}
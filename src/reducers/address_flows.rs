@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+}
+
+/// Counter deltas of a block, by key
+type Deltas = HashMap<String, i128>;
+
+/// Keeps the gross lovelace received and sent by each address
+///
+/// `{prefix}.{address}.received` adds up the outputs locked at the address
+/// and `{prefix}.{address}.sent` the inputs that it spends, so change returned
+/// to the sender counts on both sides. Requires the enrichment stage to
+/// resolve the owner and value of spent inputs.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    recent: super::RollbackHistory<Deltas>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str, side: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, address, side),
+            None => format!("address_flows.{}.{}", address, side),
+        }
+    }
+
+    fn key_format(&self) -> crosscut::AddressKeyFormat {
+        self.config.address_key_format.unwrap_or_default()
+    }
+
+    fn collect(&self, address: Option<String>, side: &str, lovelace: u64, deltas: &mut Deltas) {
+        if let Some(address) = address {
+            *deltas.entry(self.key(&address, side)).or_insert(0) += lovelace as i128;
+        }
+    }
+
+    fn collect_spent(
+        &self,
+        hash: &Hash<32>,
+        idx: u64,
+        ctx: &model::BlockContext,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        match ctx.find_utxo(hash, idx) {
+            Some(utxo) => {
+                let address = self
+                    .key_format()
+                    .output_key_ctx(utxo, &self.address_hrp, ctx)?;
+
+                self.collect(address, "sent", utxo.lovelace(), deltas);
+            }
+            None => log::warn!("missing utxo in block context"),
+        };
+
+        Ok(())
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+                self.collect_spent(hash, *idx as u64, ctx, deltas)?;
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            let address = self.key_format().byron_output_key(output)?;
+            self.collect(address, "received", output.amount, deltas);
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        for input in model::spent_inputs(tx, failed) {
+            self.collect_spent(&input.transaction_id, input.index, ctx, deltas)?;
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            let address =
+                self.key_format()
+                    .alonzo_output_key_ctx(output, &self.address_hrp, ctx)?;
+
+            let lovelace = model::alonzo_lovelace(output);
+
+            self.collect(address, "received", lovelace, deltas);
+        }
+
+        Ok(())
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &Deltas,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, delta) in deltas.iter() {
+            let crdt = model::CRDTCommand::PNCounter(
+                key.clone(),
+                model::clamp_delta(sign * delta).to_string(),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut deltas = HashMap::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut deltas).or_work_err()?;
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut deltas)
+                        .or_work_err()?;
+                }
+            }
+        };
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(block.slot(), deltas);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::AddressFlows(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn received_and_sent_are_counted_apart() {
+        let config = Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        // sends 3 ada and gets the change back
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.input(funding, 0)
+                        .output(&address(2), 3_000_000)
+                        .output(&address(1), 1_800_000)
+                        .fee(200_000)
+                })
+                .build(),
+        );
+
+        let flows = |harness: &Harness, seed| {
+            let store = harness.store();
+            let key = |side| format!("address_flows.{}.{}", address(seed), side);
+
+            (store.counter(&key("received")), store.counter(&key("sent")))
+        };
+
+        assert_eq!(flows(&harness, 1), (Some(6_800_000), Some(5_000_000)));
+        assert_eq!(flows(&harness, 2), (Some(3_000_000), None));
+
+        harness.roll_back(first);
+
+        assert_eq!(flows(&harness, 1), (Some(5_000_000), Some(0)));
+        assert_eq!(flows(&harness, 2), (Some(0), None));
+    }
+}
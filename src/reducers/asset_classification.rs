@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Description of an asset, taken from the first mint seen
+#[derive(Serialize)]
+struct AssetInfo {
+    name: String,
+    name_encoding: &'static str,
+    nft: bool,
+}
+
+/// Decodes the name of each asset and guesses if it's an NFT or a fungible
+/// token
+///
+/// Values are JSON documents under `{prefix}.{policy}.{asset_name_hex}`. The
+/// name is decoded as UTF-8, falling back to hex. An asset is flagged as NFT
+/// when its first mint has a quantity of 1 and every other asset minted under
+/// the same policy in that tx also has a quantity of 1. Only the first mint
+/// seen by this process is recorded.
+pub struct Reducer {
+    config: Config,
+    seen: HashSet<String>,
+    recent: super::RollbackHistory<Vec<String>>,
+}
+
+fn decode_name(name: &[u8]) -> (String, &'static str) {
+    match std::str::from_utf8(name) {
+        Ok(x) if !x.chars().any(|c| c.is_control()) => (x.to_string(), "utf8"),
+        _ => (hex::encode(name), "hex"),
+    }
+}
+
+impl Reducer {
+    fn key(&self, policy: &str, asset: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, policy, asset),
+            None => format!("asset_classification.{}.{}", policy, asset),
+        }
+    }
+
+    fn reduce_mint(
+        &mut self,
+        mint: &alonzo::Mint,
+        first_seen: &mut Vec<String>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (policy, assets) in mint.iter() {
+            let policy = hex::encode(policy.as_slice());
+            let all_single = assets.iter().all(|(_, quantity)| *quantity == 1);
+
+            for (name, quantity) in assets.iter() {
+                // burns don't tell anything about the nature of the asset
+                if *quantity <= 0 {
+                    continue;
+                }
+
+                let key = self.key(&policy, &hex::encode(name.as_slice()));
+
+                if !self.seen.insert(key.clone()) {
+                    continue;
+                }
+
+                let (name, name_encoding) = decode_name(name.as_slice());
+
+                let info = AssetInfo {
+                    name,
+                    name_encoding,
+                    nft: *quantity == 1 && all_single,
+                };
+
+                let value = serde_json::to_string(&info).expect("asset info is serializable");
+
+                let crdt = model::CRDTCommand::AnyWriteWins(key.clone(), value);
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                first_seen.push(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let mut first_seen = Vec::new();
+
+        for tx in x.1.transaction_bodies.iter() {
+            for component in tx.iter() {
+                if let alonzo::TransactionBodyComponent::Mint(mint) = component {
+                    self.reduce_mint(mint, &mut first_seen, output)?;
+                }
+            }
+        }
+
+        if !first_seen.is_empty() {
+            self.recent.push(x.1.header.header_body.slot, first_seen);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, keys) in self.recent.rollback(point) {
+            for key in keys {
+                self.seen.remove(&key);
+
+                let crdt = model::CRDTCommand::AnyWriteWinsRemove(key);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            seen: HashSet::new(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::AssetClassification(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    const NFT_POLICY: &str = "1e349c9bdea19fd6c147626a5260bc44b71635f398b67c59881df209";
+    const TOKEN_POLICY: &str = "29d222ce763455e3d7a09a665ce554f00ac89d2e99a1a83d267170c6";
+
+    fn info(harness: &Harness, policy: &str, name: &str) -> Option<serde_json::Value> {
+        let key = format!(
+            "asset_classification.{}.{}",
+            policy,
+            hex::encode(name.as_bytes())
+        );
+
+        let store = harness.store();
+        let raw = store.string(&key)?;
+
+        Some(serde_json::from_str(raw).unwrap())
+    }
+
+    #[test]
+    fn single_mint_is_nft_and_large_mint_is_fungible() {
+        let mut harness = Harness::new(vec![Config { key_prefix: None }.plugin()]);
+
+        let first = block()
+            .tx(|t| {
+                t.mint(NFT_POLICY, "SpaceBud42", 1).output_with_assets(
+                    &address(1),
+                    2_000_000,
+                    &[(NFT_POLICY, "SpaceBud42", 1)],
+                )
+            })
+            .build();
+
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.mint(TOKEN_POLICY, "MIN", 45_000_000_000)
+                    .output_with_assets(
+                        &address(2),
+                        2_000_000,
+                        &[(TOKEN_POLICY, "MIN", 45_000_000_000)],
+                    )
+            })
+            .build();
+
+        harness.roll_forward(second);
+
+        let nft = info(&harness, NFT_POLICY, "SpaceBud42").unwrap();
+        assert_eq!(nft["name"], "SpaceBud42");
+        assert_eq!(nft["name_encoding"], "utf8");
+        assert_eq!(nft["nft"], true);
+
+        let token = info(&harness, TOKEN_POLICY, "MIN").unwrap();
+        assert_eq!(token["name"], "MIN");
+        assert_eq!(token["nft"], false);
+
+        // the first seen entry of the rolled-back mint is removed
+        harness.roll_back(first);
+
+        assert!(info(&harness, TOKEN_POLICY, "MIN").is_none());
+        assert!(info(&harness, NFT_POLICY, "SpaceBud42").is_some());
+    }
+
+    #[test]
+    fn non_utf8_names_fall_back_to_hex() {
+        assert_eq!(decode_name(b"MIN"), ("MIN".to_string(), "utf8"));
+        assert_eq!(decode_name(&[0xff, 0x00]), ("ff00".to_string(), "hex"));
+    }
+}
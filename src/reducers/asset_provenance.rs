@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Records the tx and minter of the first mint of each asset
+///
+/// Values are JSON documents under `{prefix}.{policy}.{asset_name_hex}`, eg:
+/// `{"tx":"...","minter":"addr1...","slot":...}`. The minter is the owner of
+/// the first input of the minting tx, which requires the enrichment stage to
+/// resolve inputs, it's `null` otherwise. Records are written only if the key
+/// doesn't exist, so later mints never overwrite them. Only the first mint
+/// seen by this process is undone on rollback.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    seen: HashSet<String>,
+    recent: super::RollbackHistory<Vec<String>>,
+}
+
+impl Reducer {
+    fn key(&self, policy: &str, asset: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, policy, asset),
+            None => format!("asset_provenance.{}.{}", policy, asset),
+        }
+    }
+
+    fn minter(&self, tx: &alonzo::TransactionBody, ctx: &model::BlockContext) -> Option<String> {
+        let input = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Inputs(inputs) => Some(inputs),
+                _ => None,
+            })
+            .flat_map(|x| x.iter())
+            .next()?;
+
+        let utxo = ctx.find_utxo(&input.transaction_id, input.index)?;
+
+        match utxo.address(&self.address_hrp) {
+            Ok(x) => Some(x),
+            Err(err) => {
+                log::warn!("can't encode minter address: {}", err);
+                None
+            }
+        }
+    }
+
+    fn reduce_mint(
+        &mut self,
+        tx: &alonzo::TransactionBody,
+        mint: &alonzo::Mint,
+        slot: u64,
+        ctx: &model::BlockContext,
+        first_seen: &mut Vec<String>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (policy, assets) in mint.iter() {
+            let policy = hex::encode(policy.as_slice());
+
+            for (name, quantity) in assets.iter() {
+                if *quantity <= 0 {
+                    continue;
+                }
+
+                let key = self.key(&policy, &hex::encode(name.as_slice()));
+
+                if !self.seen.insert(key.clone()) {
+                    continue;
+                }
+
+                let value = json!({
+                    "tx": tx.to_hash().to_string(),
+                    "minter": self.minter(tx, ctx),
+                    "slot": slot,
+                });
+
+                let crdt = model::CRDTCommand::ConditionalWrite(
+                    key.clone(),
+                    value.to_string(),
+                    model::WriteCondition::NotExists,
+                );
+
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                first_seen.push(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let mut first_seen = Vec::new();
+
+        for tx in x.1.transaction_bodies.iter() {
+            for component in tx.iter() {
+                if let alonzo::TransactionBodyComponent::Mint(mint) = component {
+                    self.reduce_mint(tx, mint, slot, ctx, &mut first_seen, output)?;
+                }
+            }
+        }
+
+        if !first_seen.is_empty() {
+            self.recent.push(slot, first_seen);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, keys) in self.recent.rollback(point) {
+            for key in keys {
+                self.seen.remove(&key);
+
+                let crdt = model::CRDTCommand::AnyWriteWinsRemove(key);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            seen: HashSet::new(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::AssetProvenance(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, address_bytes, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    const POLICY: &str = "aa00000000000000000000000000000000000000000000000000000a";
+
+    #[test]
+    fn first_mint_is_kept_until_its_block_is_rolled_back() {
+        let config = Config { key_prefix: None };
+        let mut harness =
+            Harness::new(vec![config.plugin(&crosscut::ChainWellKnownInfo::mainnet())]);
+
+        let funding = block()
+            .tx(|t| t.output(&address(1), 5_000_000))
+            .tx(|t| t.output(&address(2), 5_000_000))
+            .build();
+
+        let (first_funds, second_funds) = (tx_hash(&funding, 0), tx_hash(&funding, 1));
+        let funding = harness.roll_forward(funding);
+
+        let mint = |slot: u64, number, funds, owner| {
+            block()
+                .slot(slot)
+                .number(number)
+                .tx(|t| {
+                    t.input(funds, 0)
+                        .output(&address(owner), 4_800_000)
+                        .mint(POLICY, "nft", 1)
+                })
+                .build()
+        };
+
+        let first = mint(DEFAULT_SLOT + 20, 2, first_funds, 1);
+        let first_tx = tx_hash(&first, 0).to_string();
+        harness.roll_forward(first);
+
+        harness.roll_forward(mint(DEFAULT_SLOT + 40, 3, second_funds, 2));
+
+        let key = format!("asset_provenance.{}.{}", POLICY, hex::encode("nft"));
+
+        let record: serde_json::Value =
+            serde_json::from_str(harness.store().string(&key).unwrap()).unwrap();
+
+        assert_eq!(record["tx"], json!(first_tx));
+        assert_eq!(record["slot"], json!(DEFAULT_SLOT + 20));
+
+        let minter = record["minter"].as_str().unwrap();
+        assert_eq!(address_bytes(minter), address_bytes(&address(1)));
+
+        harness.roll_back(funding);
+
+        assert_eq!(harness.store().string(&key), None);
+    }
+}
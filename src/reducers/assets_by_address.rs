@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+
+    /// Only track the assets of these policies (hex)
+    pub policies: Option<Vec<String>>,
+}
+
+/// Change in the number of utxos of each address carrying each asset
+type Deltas = HashMap<(String, String), i64>;
+
+/// Keeps the set of assets currently held by each address
+///
+/// The sorted set `{prefix}.{address}` has a `{policy}.{asset_name}` member
+/// for each asset held by the address, scored by the number of its utxos that
+/// carry the asset; the member goes away once the last of them is spent.
+/// Requires the enrichment stage to resolve the assets of spent inputs.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    policies: Option<HashSet<String>>,
+    recent: super::RollbackHistory<Deltas>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, address),
+            None => format!("assets_by_address.{}", address),
+        }
+    }
+
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        crosscut::Allowlists {
+            addresses: None,
+            policies: self.policies.clone(),
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        self.policies = allowlists.policies;
+    }
+
+    fn is_tracked(&self, policy: &str) -> bool {
+        match &self.policies {
+            Some(x) => x.contains(policy),
+            None => true,
+        }
+    }
+
+    fn collect_output(
+        &self,
+        output: &alonzo::TransactionOutput,
+        ctx: &model::BlockContext,
+        sign: i64,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        let assets = match model::alonzo_assets(output) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let address = match self
+            .config
+            .address_key_format
+            .unwrap_or_default()
+            .alonzo_output_key_ctx(output, &self.chain.address_hrp, ctx)?
+        {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for (policy, tokens) in assets.iter() {
+            let policy = hex::encode(policy.as_slice());
+
+            if !self.is_tracked(&policy) {
+                continue;
+            }
+
+            for (name, quantity) in tokens.iter() {
+                if *quantity == 0 {
+                    continue;
+                }
+
+                let asset = format!("{}.{}", policy, hex::encode(name.as_slice()));
+                *deltas.entry((address.clone(), asset)).or_insert(0) += sign;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        for input in model::spent_inputs(tx, failed) {
+            match ctx.find_utxo(&input.transaction_id, input.index) {
+                Some(model::MultiEraOutput::AlonzoCompatible(utxo)) => {
+                    self.collect_output(utxo, ctx, -1, deltas)?
+                }
+                // byron outputs don't carry assets
+                Some(model::MultiEraOutput::Byron(_)) => (),
+                None => log::warn!("missing utxo in block context"),
+            }
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            self.collect_output(output, ctx, 1, deltas)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &Deltas,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for ((address, asset), delta) in deltas.iter() {
+            let crdt =
+                model::CRDTCommand::SortedSetAdd(self.key(address), asset.clone(), sign * delta);
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        // native assets only exist since Mary
+        let block = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let mut deltas = HashMap::new();
+
+        let failed = model::failed_txs(&block.1);
+
+        for (idx, tx) in block.1.transaction_bodies.iter().enumerate() {
+            self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut deltas)
+                .or_work_err()?;
+        }
+
+        deltas.retain(|_, x| *x != 0);
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(block.1.header.header_body.slot, deltas);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn policies(&self) -> Result<Option<HashSet<String>>, Error> {
+        let policies = match &self.policies {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        policies
+            .iter()
+            .map(|raw| {
+                let policy = raw.trim().to_lowercase();
+
+                match hex::decode(&policy) {
+                    Ok(x) if x.len() == 28 => Ok(policy),
+                    _ => Err(Error::config(format!("invalid policy id {}", raw))),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.policies().map(|_| ())
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let policies = self
+            .policies()
+            .expect("policies are validated when loading the config");
+
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            policies,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::AssetsByAddress(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    const POLICY: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    #[test]
+    fn moving_an_nft_updates_both_addresses() {
+        let config = Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+            policies: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let minted = block()
+            .tx(|t| t.output_with_assets(&address(1), 2_000_000, &[(POLICY, "NFT", 1)]))
+            .build();
+
+        let funding = tx_hash(&minted, 0);
+        let minted = harness.roll_forward(minted);
+
+        let nft = format!("{}.{}", POLICY, hex::encode("NFT"));
+        let assets = |harness: &Harness, seed| {
+            let key = format!("assets_by_address.{}", address(seed));
+            harness.store().scores(&key).cloned().unwrap_or_default()
+        };
+
+        assert_eq!(assets(&harness, 1).get(&nft), Some(&1));
+        assert!(assets(&harness, 2).is_empty());
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.input(funding, 0)
+                        .output_with_assets(&address(2), 1_800_000, &[(POLICY, "NFT", 1)])
+                        .fee(200_000)
+                })
+                .build(),
+        );
+
+        assert!(assets(&harness, 1).is_empty());
+        assert_eq!(assets(&harness, 2).get(&nft), Some(&1));
+
+        harness.roll_back(minted);
+
+        assert_eq!(assets(&harness, 1).get(&nft), Some(&1));
+        assert!(assets(&harness, 2).is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryResolution {
+    #[default]
+    Slot,
+    Epoch,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+    pub history_resolution: Option<HistoryResolution>,
+
+    /// If set, history points older than this amount of slots are pruned
+    pub max_history_slots: Option<u64>,
+}
+
+struct AppliedBlock {
+    bucket: u64,
+    deltas: HashMap<String, i128>,
+}
+
+/// Records a time series of the lovelace balance of each address
+///
+/// The current balance of each address is kept under
+/// `{prefix}.balance.{address}` and every change appends a `{slot}:{balance}`
+/// member to the sorted set `{prefix}.{address}`, scored by slot. With the
+/// `epoch` resolution, a single point is kept per epoch, scored by the first
+/// slot of the epoch. Requires the enrichment stage to resolve the value of
+/// spent inputs.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<AppliedBlock>,
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "balance_history",
+        }
+    }
+
+    fn key_format(&self) -> crosscut::AddressKeyFormat {
+        self.config.address_key_format.unwrap_or_default()
+    }
+
+    fn resolution(&self) -> HistoryResolution {
+        self.config.history_resolution.unwrap_or_default()
+    }
+
+    fn balance_key(&self, address: &str) -> String {
+        format!("{}.balance.{}", self.prefix(), address)
+    }
+
+    fn history_key(&self, address: &str) -> String {
+        format!("{}.{}", self.prefix(), address)
+    }
+
+    fn byron_bucket(&self, slot: u64) -> u64 {
+        match self.resolution() {
+            HistoryResolution::Slot => slot,
+            HistoryResolution::Epoch => {
+                let epoch_slots =
+                    self.chain.byron_epoch_length as u64 / self.chain.byron_slot_length as u64;
+                (slot / epoch_slots) * epoch_slots
+            }
+        }
+    }
+
+    fn shelley_bucket(&self, slot: u64) -> u64 {
+        match self.resolution() {
+            HistoryResolution::Slot => slot,
+            HistoryResolution::Epoch => {
+                let known = self.chain.shelley_known_slot;
+                let length = self.chain.shelley_epoch_length as u64;
+                known + ((slot - known) / length) * length
+            }
+        }
+    }
+
+    fn add_to_balance(deltas: &mut HashMap<String, i128>, address: Option<String>, amount: i128) {
+        if let Some(address) = address {
+            *deltas.entry(address).or_insert(0) += amount;
+        }
+    }
+
+    fn collect_spent_utxo(
+        &self,
+        utxo: Option<&model::MultiEraOutput>,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), gasket::error::Error> {
+        match utxo {
+            Some(utxo) => {
+                let address = self
+                    .key_format()
+                    .output_key_ctx(utxo, &self.chain.address_hrp, ctx)
+                    .or_work_err()?;
+
+                Self::add_to_balance(deltas, address, -(utxo.lovelace() as i128));
+            }
+            None => log::warn!("missing utxo in block context"),
+        };
+
+        Ok(())
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), gasket::error::Error> {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+                self.collect_spent_utxo(ctx.find_utxo(hash, *idx as u64), ctx, deltas)?;
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            let address = self.key_format().byron_output_key(output).or_work_err()?;
+            Self::add_to_balance(deltas, address, output.amount as i128);
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), gasket::error::Error> {
+        for input in model::spent_inputs(tx, failed) {
+            let utxo = ctx.find_utxo(&input.transaction_id, input.index);
+            self.collect_spent_utxo(utxo, ctx, deltas)?;
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            let address = self
+                .key_format()
+                .alonzo_output_key_ctx(output, &self.chain.address_hrp, ctx)
+                .or_work_err()?;
+
+            let amount = model::alonzo_lovelace(output);
+
+            Self::add_to_balance(deltas, address, amount as i128);
+        }
+
+        Ok(())
+    }
+
+    fn send_snapshot(
+        &self,
+        address: &str,
+        delta: i128,
+        bucket: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = model::CRDTCommand::CounterSnapshot(
+            self.balance_key(address),
+            model::clamp_delta(delta),
+            self.history_key(address),
+            bucket,
+        );
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        if let Some(max) = self.config.max_history_slots {
+            if bucket > max {
+                let crdt = model::CRDTCommand::SortedSetRemoveRange(
+                    self.history_key(address),
+                    0,
+                    bucket - max - 1,
+                );
+
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut deltas = HashMap::new();
+
+        let (slot, bucket) = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut deltas)?;
+                }
+
+                let slot = x.header.consensus_data.0.to_abs_slot();
+                (slot, self.byron_bucket(slot))
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    let failed = failed.contains(&idx);
+                    self.collect_alonzo_compatible_tx(tx, failed, ctx, &mut deltas)?;
+                }
+
+                let slot = x.1.header.header_body.slot;
+                (slot, self.shelley_bucket(slot))
+            }
+        };
+
+        deltas.retain(|_, d| *d != 0);
+
+        for (address, delta) in deltas.iter() {
+            self.send_snapshot(address, *delta, bucket, output)?;
+        }
+
+        self.recent.push(slot, AppliedBlock { bucket, deltas });
+
+        Ok(())
+    }
+
+    /// Reverts the balance changes of the blocks after the rollback point.
+    /// With the `slot` resolution the points of those blocks are removed, with
+    /// the `epoch` resolution the point of the epoch is rewritten with the
+    /// reverted balance.
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point) {
+            for (address, delta) in applied.deltas.iter() {
+                match self.resolution() {
+                    HistoryResolution::Slot => {
+                        let crdt = model::CRDTCommand::PNCounter(
+                            self.balance_key(address),
+                            model::clamp_delta(-delta).to_string(),
+                        );
+                        output.send(gasket::messaging::Message::from(crdt))?;
+
+                        let crdt = model::CRDTCommand::SortedSetRemoveRange(
+                            self.history_key(address),
+                            applied.bucket,
+                            applied.bucket,
+                        );
+                        output.send(gasket::messaging::Message::from(crdt))?;
+                    }
+                    HistoryResolution::Epoch => {
+                        self.send_snapshot(address, -delta, applied.bucket, output)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::BalanceHistory(reducer)
+    }
+}
@@ -0,0 +1,160 @@
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Position of a block in the chain
+#[derive(Serialize)]
+struct Linkage {
+    slot: u64,
+    height: Option<u64>,
+    prev_hash: Option<String>,
+}
+
+/// Maps each block hash to its slot, height and previous block hash
+///
+/// Values are JSON documents under `{prefix}.{block_hash}`, which allows to
+/// walk the chain backwards from any block. Byron epoch boundary blocks never
+/// reach the reducers, so the first block of each Byron epoch links to a hash
+/// without entry.
+pub struct Reducer {
+    config: Config,
+    recent: super::RollbackHistory<String>,
+}
+
+impl Reducer {
+    fn key(&self, hash: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, hash),
+            None => format!("block_linkage.{}", hash),
+        }
+    }
+
+    fn byron_linkage(block: &byron::MainBlock) -> (Hash<32>, Linkage) {
+        let linkage = Linkage {
+            slot: block.header.consensus_data.0.to_abs_slot(),
+            height: block.header.consensus_data.2.first().copied(),
+            prev_hash: Some(block.header.prev_block.to_string()),
+        };
+
+        (block.header.to_hash(), linkage)
+    }
+
+    fn alonzo_linkage(block: &alonzo::Block) -> (Hash<32>, Linkage) {
+        let body = &block.header.header_body;
+
+        let linkage = Linkage {
+            slot: body.slot,
+            height: Some(body.block_number),
+            prev_hash: Some(body.prev_hash.to_string()),
+        };
+
+        (alonzo::crypto::hash_block_header(&block.header), linkage)
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let (hash, linkage) = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => Self::byron_linkage(x),
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => Self::alonzo_linkage(&x.1),
+        };
+
+        let hash = hash.to_string();
+        let slot = linkage.slot;
+        let value = serde_json::to_string(&linkage).expect("linkage is serializable");
+
+        let crdt = model::CRDTCommand::AnyWriteWins(self.key(&hash), value);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        self.recent.push(slot, hash);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, hash) in self.recent.rollback(point) {
+            let crdt = model::CRDTCommand::AnyWriteWinsRemove(self.key(&hash));
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::BlockLinkage(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    fn point_hash(point: &Point) -> Hash<32> {
+        match point {
+            Point::Specific(_, hash) => Hash::from(<[u8; 32]>::try_from(hash.as_slice()).unwrap()),
+            Point::Origin => unreachable!("built blocks have a specific point"),
+        }
+    }
+
+    fn linkage(harness: &Harness, hash: &Hash<32>) -> Option<serde_json::Value> {
+        let store = harness.store();
+        let raw = store.string(&format!("block_linkage.{}", hash))?;
+
+        Some(serde_json::from_str(raw).unwrap())
+    }
+
+    #[test]
+    fn consecutive_blocks_link_and_rollback_removes_the_later() {
+        let mut harness = Harness::new(vec![Config { key_prefix: None }.plugin()]);
+
+        let first = harness.roll_forward(block().number(1).build());
+        let first_hash = point_hash(&first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .prev_hash(first_hash)
+            .build();
+
+        let second_hash = point_hash(&harness.roll_forward(second));
+
+        assert_eq!(
+            linkage(&harness, &second_hash).unwrap(),
+            serde_json::json!({
+                "slot": DEFAULT_SLOT + 20,
+                "height": 2,
+                "prev_hash": first_hash.to_string(),
+            })
+        );
+
+        assert_eq!(linkage(&harness, &first_hash).unwrap()["height"], 1);
+
+        harness.roll_back(first);
+
+        assert!(linkage(&harness, &second_hash).is_none());
+        assert!(linkage(&harness, &first_hash).is_some());
+    }
+}
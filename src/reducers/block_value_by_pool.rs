@@ -0,0 +1,177 @@
+use pallas::crypto::hash::Hasher;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// A block produced by a pool: its slot, epoch, the pool id and the fees and
+/// output value that it holds
+type Produced = (u64, u64, String, u64, u64);
+
+/// Attributes the fees and the output value of each block to the pool that
+/// produced it
+///
+/// Pool ids are members of the sorted sets `{prefix}.{epoch}.fees` and
+/// `{prefix}.{epoch}.output_value`, scored by the lovelace of the blocks
+/// produced by the pool in the epoch, so the pools processing the most value
+/// are the high end. The pool id is the hash of the block issuer key, Byron
+/// blocks are left out.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<Produced>,
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64, name: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, epoch, name),
+            None => format!("block_value_by_pool.{}.{}", epoch, name),
+        }
+    }
+
+    fn send_amounts(
+        &self,
+        produced: &Produced,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let (_, epoch, pool, fees, value) = produced;
+
+        for (name, amount) in [("fees", fees), ("output_value", value)] {
+            if *amount == 0 {
+                continue;
+            }
+
+            let delta = model::clamp_delta(sign * *amount as i128);
+            let crdt =
+                model::CRDTCommand::SortedSetAdd(self.key(*epoch, name), pool.clone(), delta);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let header = &x.1.header.header_body;
+        let slot = header.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+        let pool = Hasher::<224>::hash(&header.issuer_vkey).to_string();
+
+        let mut fees = 0u64;
+        let mut value = 0u64;
+
+        for component in x.1.transaction_bodies.iter().flat_map(|tx| tx.iter()) {
+            match component {
+                alonzo::TransactionBodyComponent::Fee(x) => fees = fees.saturating_add(*x),
+                alonzo::TransactionBodyComponent::Outputs(outputs) => {
+                    for output in outputs.iter() {
+                        let lovelace = model::alonzo_lovelace(output);
+
+                        value = value.saturating_add(lovelace);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let produced = (slot, epoch, pool, fees, value);
+
+        self.send_amounts(&produced, 1, output)?;
+
+        self.recent.push(slot, produced);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, produced) in self.recent.rollback(point) {
+            self.send_amounts(&produced, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::BlockValueByPool(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn each_pool_gets_the_value_of_its_own_blocks() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = block()
+            .issuer(1)
+            .tx(|t| t.output(&address(1), 3_000_000).fee(170_000))
+            .tx(|t| t.output(&address(2), 1_000_000).fee(180_000))
+            .build();
+
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .issuer(2)
+            .tx(|t| t.output(&address(3), 2_000_000).fee(200_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        let pool = |seed: u8| Hasher::<224>::hash(&[seed; 32]).to_string();
+
+        let scores = |harness: &Harness, name: &str| {
+            let key = format!("block_value_by_pool.{}.{}", epoch, name);
+            harness.store().scores(&key).cloned().unwrap_or_default()
+        };
+
+        let fees = scores(&harness, "fees");
+        assert_eq!(fees.get(&pool(1)), Some(&350_000));
+        assert_eq!(fees.get(&pool(2)), Some(&200_000));
+
+        let value = scores(&harness, "output_value");
+        assert_eq!(value.get(&pool(1)), Some(&4_000_000));
+        assert_eq!(value.get(&pool(2)), Some(&2_000_000));
+
+        harness.roll_back(first);
+
+        let fees = scores(&harness, "fees");
+        assert_eq!(fees.get(&pool(1)), Some(&350_000));
+        assert_eq!(fees.get(&pool(2)), None);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Counts the certificates of each type per epoch
+///
+/// Counters are kept under `{prefix}.{epoch}.{type}`, eg:
+/// `certificates_by_epoch.300.stake_delegation`. Types are matched
+/// exhaustively, so certificates added by future eras need to be given a name
+/// here before they are counted.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+fn certificate_type(cert: &alonzo::Certificate) -> &'static str {
+    match cert {
+        alonzo::Certificate::StakeRegistration(..) => "stake_registration",
+        alonzo::Certificate::StakeDeregistration(..) => "stake_deregistration",
+        alonzo::Certificate::StakeDelegation(..) => "stake_delegation",
+        alonzo::Certificate::PoolRegistration { .. } => "pool_registration",
+        alonzo::Certificate::PoolRetirement(..) => "pool_retirement",
+        alonzo::Certificate::GenesisKeyDelegation(..) => "genesis_key_delegation",
+        alonzo::Certificate::MoveInstantaneousRewardsCert(..) => "move_instantaneous_rewards",
+    }
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64, kind: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, epoch, kind),
+            None => format!("certificates_by_epoch.{}.{}", epoch, kind),
+        }
+    }
+
+    fn send_counters(
+        &self,
+        counters: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, value) in counters.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * value).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let mut counters = HashMap::new();
+
+        let certs =
+            x.1.transaction_bodies
+                .iter()
+                .flat_map(|tx| tx.iter())
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Certificates(x) => Some(x),
+                    _ => None,
+                })
+                .flat_map(|x| x.iter());
+
+        for cert in certs {
+            let key = self.key(epoch, certificate_type(cert));
+            *counters.entry(key).or_insert(0) += 1;
+        }
+
+        if counters.is_empty() {
+            return Ok(());
+        }
+
+        self.send_counters(&counters, 1, output)?;
+
+        self.recent.push(slot, counters);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counters) in self.recent.rollback(point) {
+            self.send_counters(&counters, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::CertificatesByEpoch(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, delegation, pool_registration, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn each_certificate_type_has_its_counter() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.output(&address(2), 1_000_000)
+                        .cert(delegation(1, 7))
+                        .cert(delegation(2, 7))
+                })
+                .tx(|t| {
+                    t.output(&address(3), 1_000_000)
+                        .cert(pool_registration(7, None))
+                })
+                .build(),
+        );
+
+        let count = |harness: &Harness, kind: &str| {
+            let key = format!("certificates_by_epoch.{}.{}", epoch, kind);
+            harness.store().counter(&key)
+        };
+
+        assert_eq!(count(&harness, "stake_delegation"), Some(2));
+        assert_eq!(count(&harness, "pool_registration"), Some(1));
+        assert_eq!(count(&harness, "pool_retirement"), None);
+
+        harness.roll_back(first);
+
+        assert_eq!(count(&harness, "stake_delegation"), Some(0));
+        assert_eq!(count(&harness, "pool_registration"), Some(0));
+    }
+}
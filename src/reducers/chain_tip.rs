@@ -0,0 +1,59 @@
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key: Option<String>,
+}
+
+/// Keeps the latest applied point under a single well-known key
+pub struct Reducer {
+    config: Config,
+}
+
+impl Reducer {
+    fn send_point(
+        &mut self,
+        point: Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = match &self.config.key {
+            Some(key) => key.to_string(),
+            None => "chain_tip".to_string(),
+        };
+
+        let value = crosscut::PointArg::from(point).to_string();
+
+        let crdt = model::CRDTCommand::AnyWriteWins(key, value);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let point = block.point().expect("block has defined point");
+        self.send_point(point, output)
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        self.send_point(point.clone(), output)
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer { config: self };
+        super::Plugin::ChainTip(reducer)
+    }
+}
@@ -0,0 +1,125 @@
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key: Option<String>,
+}
+
+/// Keeps the epoch of the latest applied block under a single well-known key
+///
+/// The key is only written when the epoch changes. A rollback across an epoch
+/// boundary writes back the epoch of the rollback point.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    current: Option<u64>,
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key {
+            Some(key) => key.to_string(),
+            None => "current_epoch".to_string(),
+        }
+    }
+
+    fn send_epoch(
+        &mut self,
+        epoch: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if self.current == Some(epoch) {
+            return Ok(());
+        }
+
+        let crdt = model::CRDTCommand::AnyWriteWins(self.key(), epoch.to_string());
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        self.current = Some(epoch);
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let epoch = self.chain.epoch_for_slot(block.slot());
+        self.send_epoch(epoch, output)
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        match point {
+            Point::Specific(slot, _) => {
+                let epoch = self.chain.epoch_for_slot(*slot);
+                self.send_epoch(epoch, output)
+            }
+            Point::Origin => {
+                let crdt = model::CRDTCommand::AnyWriteWinsRemove(self.key());
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                self.current = None;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            current: None,
+        };
+
+        super::Plugin::CurrentEpoch(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn epoch_follows_the_blocks_across_a_boundary() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+
+        // first slot of the next epoch, shelley epochs start from its known slot
+        let length = chain.shelley_epoch_length as u64;
+        let boundary = chain.shelley_known_slot
+            + ((DEFAULT_SLOT - chain.shelley_known_slot) / length + 1) * length;
+        assert_eq!(chain.epoch_for_slot(boundary - 1), epoch);
+
+        let config = Config { key: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let current = |harness: &Harness| harness.store().counter("current_epoch");
+
+        let first = harness.roll_forward(block().build());
+        assert_eq!(current(&harness), Some(epoch as i64));
+
+        harness.roll_forward(block().slot(boundary - 1).number(2).build());
+        assert_eq!(current(&harness), Some(epoch as i64));
+
+        harness.roll_forward(block().slot(boundary).number(3).build());
+        assert_eq!(current(&harness), Some(epoch as i64 + 1));
+
+        harness.roll_back(first);
+        assert_eq!(current(&harness), Some(epoch as i64));
+
+        harness.roll_back(Point::Origin);
+        assert_eq!(current(&harness), None);
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model, Error};
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// A change of delegation: the credential, its previous pool and its new pool
+type Change = (String, Option<String>, Option<String>);
+
+/// Counter deltas of a block, by key
+type Deltas = HashMap<String, i64>;
+
+#[derive(Serialize, Deserialize)]
+struct AppliedBlock {
+    changes: Vec<Change>,
+    deltas: Deltas,
+}
+
+/// Counts, per pool and epoch, the delegators gained and lost
+///
+/// Counters are `{prefix}.{pool}.{epoch}.gained` and
+/// `{prefix}.{pool}.{epoch}.lost`. A re-delegation is a loss for the previous
+/// pool and a gain for the new one, a deregistration is a loss. Like
+/// `DelegatorsByPool`, the current pool of each credential is kept in the
+/// state db.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    current: state::Map<String>,
+    recent: state::History<AppliedBlock>,
+}
+
+fn credential_key(cred: &alonzo::StakeCredential) -> String {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => x.to_string(),
+        alonzo::StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+impl Reducer {
+    fn key(&self, pool: &str, epoch: u64, kind: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}.{}", prefix, pool, epoch, kind),
+            None => format!("delegation_churn.{}.{}.{}", pool, epoch, kind),
+        }
+    }
+
+    fn apply(
+        &mut self,
+        epoch: u64,
+        cred: String,
+        pool: Option<String>,
+        changes: &mut Vec<Change>,
+        deltas: &mut Deltas,
+    ) -> Result<(), Error> {
+        let previous = match &pool {
+            Some(pool) => self.current.insert(&cred, pool)?,
+            None => self.current.remove(&cred)?,
+        };
+
+        if previous != pool {
+            if let Some(from) = &previous {
+                *deltas.entry(self.key(from, epoch, "lost")).or_insert(0) += 1;
+            }
+
+            if let Some(into) = &pool {
+                *deltas.entry(self.key(into, epoch, "gained")).or_insert(0) += 1;
+            }
+        }
+
+        changes.push((cred, previous, pool));
+
+        Ok(())
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &Deltas,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, delta) in deltas.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * delta).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        // changes left by a run that stopped before the storage committed
+        for (_, applied) in self.recent.rewind(slot).or_work_err()? {
+            self.undo(&applied.changes).or_work_err()?;
+        }
+
+        let mut changes = Vec::new();
+        let mut deltas = HashMap::new();
+
+        let certs =
+            x.1.transaction_bodies
+                .iter()
+                .flat_map(|tx| tx.iter())
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                    _ => None,
+                })
+                .flat_map(|c| c.iter());
+
+        for cert in certs {
+            match cert {
+                alonzo::Certificate::StakeDelegation(cred, pool) => self
+                    .apply(
+                        epoch,
+                        credential_key(cred),
+                        Some(pool.to_string()),
+                        &mut changes,
+                        &mut deltas,
+                    )
+                    .or_work_err()?,
+                alonzo::Certificate::StakeDeregistration(cred) => self
+                    .apply(epoch, credential_key(cred), None, &mut changes, &mut deltas)
+                    .or_work_err()?,
+                _ => (),
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent
+            .push(slot, &AppliedBlock { changes, deltas })
+            .or_work_err()?;
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point).or_work_err()? {
+            self.send_deltas(&applied.deltas, -1, output)?;
+            self.undo(&applied.changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the pools that the credentials had before the changes
+    fn undo(&mut self, changes: &[Change]) -> Result<(), Error> {
+        for (cred, previous, _) in changes.iter().rev() {
+            self.current.restore(cred, previous.as_ref())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        state: &state::Db,
+    ) -> Result<super::Plugin, Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "delegation_churn".to_string(),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            current: state.map(&format!("{}.current", name))?,
+            recent: state.history(&name)?,
+        };
+
+        Ok(super::Plugin::DelegationChurn(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, delegation, state_db, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        Config { key_prefix: None }.plugin(&chain, state).unwrap()
+    }
+
+    fn key(pool: u8, kind: &str) -> String {
+        let epoch = crosscut::ChainWellKnownInfo::mainnet().epoch_for_slot(DEFAULT_SLOT);
+
+        format!(
+            "delegation_churn.{}.{}.{}",
+            hex::encode([pool; 28]),
+            epoch,
+            kind
+        )
+    }
+
+    #[test]
+    fn redelegation_counts_a_loss_and_a_gain_until_rolled_back() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = block().tx(|t| t.cert(delegation(9, 7))).build();
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.cert(delegation(9, 8)))
+            .build();
+
+        harness.roll_forward(second);
+
+        assert_eq!(harness.store().counter(&key(7, "gained")), Some(1));
+        assert_eq!(harness.store().counter(&key(7, "lost")), Some(1));
+        assert_eq!(harness.store().counter(&key(8, "gained")), Some(1));
+
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter(&key(7, "gained")), Some(1));
+        assert_eq!(harness.store().counter(&key(7, "lost")), Some(0));
+        assert_eq!(harness.store().counter(&key(8, "gained")), Some(0));
+    }
+
+    #[test]
+    fn delegations_survive_a_restart() {
+        let state = state_db();
+
+        let mut harness = Harness::new(vec![reducer(&state)]);
+        let first = harness.roll_forward(block().tx(|t| t.cert(delegation(9, 7))).build());
+
+        // the new run only knows the previous pool through the state
+        let mut harness = harness.restart(vec![reducer(&state)]);
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| t.cert(delegation(9, 8)))
+                .build(),
+        );
+
+        assert_eq!(harness.store().counter(&key(7, "lost")), Some(1));
+        assert_eq!(harness.store().counter(&key(8, "gained")), Some(1));
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter(&key(7, "lost")), Some(0));
+        assert_eq!(harness.store().counter(&key(8, "gained")), Some(0));
+    }
+}
@@ -0,0 +1,253 @@
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{model, Error};
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// A change of delegation: the credential, its previous pool and its new pool
+type Change = (String, Option<String>, Option<String>);
+
+/// Keeps, per pool id, the set of stake credentials delegating to it
+///
+/// Members of `{prefix}.{pool}` are the hex stake credential hashes. A
+/// re-delegation moves the credential to the set of the new pool and a
+/// deregistration removes it. The current pool of each credential is kept in
+/// the state db to know the set to move it away from.
+pub struct Reducer {
+    config: Config,
+    current: state::Map<String>,
+    recent: state::History<Vec<Change>>,
+}
+
+fn credential_key(cred: &alonzo::StakeCredential) -> String {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => x.to_string(),
+        alonzo::StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+impl Reducer {
+    fn key(&self, pool: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, pool),
+            None => format!("delegators_by_pool.{}", pool),
+        }
+    }
+
+    /// Moves a credential from one pool set to another
+    fn send_move(
+        &self,
+        cred: &str,
+        from: Option<&String>,
+        into: Option<&String>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if from == into {
+            return Ok(());
+        }
+
+        if let Some(pool) = from {
+            let crdt = model::CRDTCommand::SetRemove(self.key(pool), cred.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        if let Some(pool) = into {
+            let crdt = model::CRDTCommand::GrowOnlySetAdd(self.key(pool), cred.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply(
+        &mut self,
+        cred: String,
+        pool: Option<String>,
+        changes: &mut Vec<Change>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let previous = match &pool {
+            Some(pool) => self.current.insert(&cred, pool),
+            None => self.current.remove(&cred),
+        }
+        .or_work_err()?;
+
+        self.send_move(&cred, previous.as_ref(), pool.as_ref(), output)?;
+
+        changes.push((cred, previous, pool));
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+
+        // changes left by a run that stopped before the storage committed
+        for (_, changes) in self.recent.rewind(slot).or_work_err()? {
+            self.undo(&changes).or_work_err()?;
+        }
+
+        let mut changes = Vec::new();
+
+        let certs =
+            x.1.transaction_bodies
+                .iter()
+                .flat_map(|tx| tx.iter())
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                    _ => None,
+                })
+                .flat_map(|c| c.iter());
+
+        for cert in certs {
+            match cert {
+                alonzo::Certificate::StakeDelegation(cred, pool) => {
+                    self.apply(
+                        credential_key(cred),
+                        Some(pool.to_string()),
+                        &mut changes,
+                        output,
+                    )?;
+                }
+                alonzo::Certificate::StakeDeregistration(cred) => {
+                    self.apply(credential_key(cred), None, &mut changes, output)?;
+                }
+                _ => (),
+            }
+        }
+
+        if !changes.is_empty() {
+            self.recent.push(slot, &changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, changes) in self.recent.rollback(point).or_work_err()? {
+            for (cred, previous, pool) in changes.iter().rev() {
+                self.send_move(cred, pool.as_ref(), previous.as_ref(), output)?;
+            }
+
+            self.undo(&changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the pools that the credentials had before the changes
+    fn undo(&mut self, changes: &[Change]) -> Result<(), Error> {
+        for (cred, previous, _) in changes.iter().rev() {
+            self.current.restore(cred, previous.as_ref())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, state: &state::Db) -> Result<super::Plugin, Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "delegators_by_pool".to_string(),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            current: state.map(&format!("{}.current", name))?,
+            recent: state.history(&name)?,
+        };
+
+        Ok(super::Plugin::DelegatorsByPool(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, delegation, state_db, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        Config { key_prefix: None }.plugin(state).unwrap()
+    }
+
+    fn key(pool: u8) -> String {
+        format!("delegators_by_pool.{}", hex::encode([pool; 28]))
+    }
+
+    #[test]
+    fn redelegation_moves_the_credential_and_rolls_back() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = block().tx(|t| t.cert(delegation(9, 7))).build();
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.cert(delegation(9, 8)))
+            .build();
+
+        harness.roll_forward(second);
+
+        let cred = hex::encode([9; 28]);
+
+        assert!(harness.store().members(&key(7)).is_none());
+        assert!(harness.store().members(&key(8)).unwrap().contains(&cred));
+
+        harness.roll_back(first);
+
+        assert!(harness.store().members(&key(7)).unwrap().contains(&cred));
+        assert!(harness.store().members(&key(8)).is_none());
+    }
+
+    #[test]
+    fn redelegation_after_a_restart_leaves_the_previous_pool() {
+        let state = state_db();
+
+        let mut harness = Harness::new(vec![reducer(&state)]);
+        let first = harness.roll_forward(block().tx(|t| t.cert(delegation(9, 7))).build());
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.cert(delegation(9, 8)))
+            .build();
+
+        // the new run only knows the pool of the credential through the state
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_forward(second);
+
+        let cred = hex::encode([9; 28]);
+
+        assert!(harness.store().members(&key(7)).is_none());
+        assert!(harness.store().members(&key(8)).unwrap().contains(&cred));
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        assert!(harness.store().members(&key(7)).unwrap().contains(&cred));
+        assert!(harness.store().members(&key(8)).is_none());
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Deposit taken by stake key registrations (`key_deposit` protocol param)
+    pub key_deposit: Option<u64>,
+
+    /// Deposit taken by pool registrations (`pool_deposit` protocol param)
+    pub pool_deposit: Option<u64>,
+}
+
+// values of the protocol params on mainnet since the start of Shelley
+const DEFAULT_KEY_DEPOSIT: u64 = 2_000_000;
+const DEFAULT_POOL_DEPOSIT: u64 = 500_000_000;
+
+enum Change {
+    StakeDeposit(String, u64),
+    StakeRefund(String, u64),
+    PoolDeposit(String, u64),
+    PoolRefund(String, u64),
+    RetirementScheduled(String, Option<u64>),
+    RetirementCanceled(String, u64),
+}
+
+/// Tracks the deposits locked by stake key and pool registrations
+///
+/// The total held is a counter under `{prefix}.total`, while the deposit held
+/// by each stake credential or pool is kept under `{prefix}.stake.{cred}` and
+/// `{prefix}.pool.{pool}`. Refunds take back the amount that was paid on
+/// registration; pool deposits are refunded when the retirement epoch starts.
+///
+/// Deposit amounts come from the `key_deposit` and `pool_deposit` settings,
+/// which default to the mainnet protocol params. Credentials registered before
+/// the start of the sync are refunded with the current settings.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    epoch: Option<u64>,
+    stake: HashMap<String, u64>,
+    pools: HashMap<String, u64>,
+    retiring: HashMap<String, u64>,
+    recent: super::RollbackHistory<Vec<Change>>,
+}
+
+fn credential_key(cred: &alonzo::StakeCredential) -> String {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => x.to_string(),
+        alonzo::StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "deposits",
+        }
+    }
+
+    fn key_deposit(&self) -> u64 {
+        self.config.key_deposit.unwrap_or(DEFAULT_KEY_DEPOSIT)
+    }
+
+    fn pool_deposit(&self) -> u64 {
+        self.config.pool_deposit.unwrap_or(DEFAULT_POOL_DEPOSIT)
+    }
+
+    fn send(
+        &self,
+        kind: &str,
+        id: &str,
+        held: Option<u64>,
+        delta: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = format!("{}.{}.{}", self.prefix(), kind, id);
+
+        let crdt = match held {
+            Some(amount) => model::CRDTCommand::AnyWriteWins(key, amount.to_string()),
+            None => model::CRDTCommand::AnyWriteWinsRemove(key),
+        };
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        let crdt =
+            model::CRDTCommand::PNCounter(format!("{}.total", self.prefix()), delta.to_string());
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    /// Applies a change to the in-memory state and sends its commands, or the
+    /// commands that undo it
+    fn apply(
+        &mut self,
+        change: &Change,
+        undo: bool,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let sign = if undo { -1 } else { 1 };
+
+        match change {
+            Change::StakeDeposit(cred, amount) | Change::StakeRefund(cred, amount) => {
+                let deposit = matches!(change, Change::StakeDeposit(..)) != undo;
+
+                match deposit {
+                    true => self.stake.insert(cred.clone(), *amount),
+                    false => self.stake.remove(cred),
+                };
+
+                let delta = match change {
+                    Change::StakeDeposit(..) => sign * *amount as i64,
+                    _ => -sign * *amount as i64,
+                };
+
+                self.send("stake", cred, deposit.then_some(*amount), delta, output)?;
+            }
+            Change::PoolDeposit(pool, amount) | Change::PoolRefund(pool, amount) => {
+                let deposit = matches!(change, Change::PoolDeposit(..)) != undo;
+
+                match deposit {
+                    true => self.pools.insert(pool.clone(), *amount),
+                    false => self.pools.remove(pool),
+                };
+
+                let delta = match change {
+                    Change::PoolDeposit(..) => sign * *amount as i64,
+                    _ => -sign * *amount as i64,
+                };
+
+                self.send("pool", pool, deposit.then_some(*amount), delta, output)?;
+            }
+            Change::RetirementScheduled(pool, previous) => {
+                match (undo, previous) {
+                    (true, Some(epoch)) => self.retiring.insert(pool.clone(), *epoch),
+                    (true, None) => self.retiring.remove(pool),
+                    (false, _) => None,
+                };
+            }
+            Change::RetirementCanceled(pool, epoch) => {
+                if undo {
+                    self.retiring.insert(pool.clone(), *epoch);
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    fn collect_cert(&mut self, cert: &alonzo::Certificate, changes: &mut Vec<Change>) {
+        match cert {
+            alonzo::Certificate::StakeRegistration(cred) => {
+                let cred = credential_key(cred);
+
+                if !self.stake.contains_key(&cred) {
+                    changes.push(Change::StakeDeposit(cred, self.key_deposit()));
+                }
+            }
+            alonzo::Certificate::StakeDeregistration(cred) => {
+                let cred = credential_key(cred);
+                let amount = self.stake.get(&cred).copied().unwrap_or(self.key_deposit());
+                changes.push(Change::StakeRefund(cred, amount));
+            }
+            alonzo::Certificate::PoolRegistration { operator, .. } => {
+                let pool = operator.to_string();
+
+                // re-registrations update the params without a new deposit
+                if let Some(epoch) = self.retiring.remove(&pool) {
+                    changes.push(Change::RetirementCanceled(pool.clone(), epoch));
+                }
+
+                if !self.pools.contains_key(&pool) {
+                    changes.push(Change::PoolDeposit(pool, self.pool_deposit()));
+                }
+            }
+            alonzo::Certificate::PoolRetirement(pool, epoch) => {
+                let pool = pool.to_string();
+                let previous = self.retiring.insert(pool.clone(), *epoch);
+                changes.push(Change::RetirementScheduled(pool, previous));
+            }
+            _ => (),
+        }
+    }
+
+    fn collect_retirements(&mut self, epoch: u64, changes: &mut Vec<Change>) {
+        let retired: Vec<_> = self
+            .retiring
+            .iter()
+            .filter(|(_, e)| **e <= epoch)
+            .map(|(pool, e)| (pool.clone(), *e))
+            .collect();
+
+        for (pool, e) in retired {
+            self.retiring.remove(&pool);
+            changes.push(Change::RetirementCanceled(pool.clone(), e));
+
+            let amount = self
+                .pools
+                .get(&pool)
+                .copied()
+                .unwrap_or(self.pool_deposit());
+            changes.push(Change::PoolRefund(pool, amount));
+        }
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let mut changes = Vec::new();
+
+        // pools retire at the epoch boundary, before any tx of the new epoch
+        if self.epoch != Some(epoch) {
+            self.collect_retirements(epoch, &mut changes);
+            self.epoch = Some(epoch);
+        }
+
+        for change in changes.iter() {
+            self.apply(change, false, output)?;
+        }
+
+        let certs =
+            x.1.transaction_bodies
+                .iter()
+                .flat_map(|tx| tx.iter())
+                .filter_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                    _ => None,
+                })
+                .flat_map(|c| c.iter());
+
+        // each cert is applied right away, later certs depend on the state
+        for cert in certs {
+            let applied = changes.len();
+            self.collect_cert(cert, &mut changes);
+
+            for change in changes[applied..].iter() {
+                self.apply(change, false, output)?;
+            }
+        }
+
+        if !changes.is_empty() {
+            self.recent.push(slot, changes);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        for (_, changes) in self.recent.rollback(point) {
+            for change in changes.iter().rev() {
+                self.apply(change, true, output)?;
+            }
+        }
+
+        self.epoch = slot.map(|x| self.chain.epoch_for_slot(x));
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            epoch: None,
+            stake: HashMap::new(),
+            pools: HashMap::new(),
+            retiring: HashMap::new(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::Deposits(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+    use pallas::crypto::hash::Hash;
+
+    fn stake_key() -> alonzo::StakeCredential {
+        alonzo::StakeCredential::AddrKeyhash(Hash::from([9; 28]))
+    }
+
+    #[test]
+    fn registration_then_deregistration_nets_to_zero() {
+        let config = Config {
+            key_prefix: None,
+            key_deposit: None,
+            pool_deposit: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let registration = block()
+            .tx(|t| t.cert(alonzo::Certificate::StakeRegistration(stake_key())))
+            .build();
+
+        let registration = harness.roll_forward(registration);
+
+        let held = format!("deposits.stake.{}", hex::encode([9; 28]));
+
+        assert_eq!(harness.store().counter("deposits.total"), Some(2_000_000));
+        assert_eq!(harness.store().string(&held), Some("2000000"));
+
+        let deregistration = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.cert(alonzo::Certificate::StakeDeregistration(stake_key())))
+            .build();
+
+        harness.roll_forward(deregistration);
+
+        assert_eq!(harness.store().counter("deposits.total"), Some(0));
+        assert!(harness.store().string(&held).is_none());
+
+        // the refund is undone, the deposit is held again
+        harness.roll_back(registration);
+
+        assert_eq!(harness.store().counter("deposits.total"), Some(2_000_000));
+        assert_eq!(harness.store().string(&held), Some("2000000"));
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeAttribution {
+    /// Split the fee between input owners proportionally to the value of their inputs
+    #[default]
+    Split,
+    /// Attribute the whole fee to the owner of the first input
+    AttributeToFirst,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+    pub attribution: Option<FeeAttribution>,
+}
+
+/// Accumulates the total fees paid by each address
+///
+/// Fees are attributed to the owners of the inputs funding each tx, which
+/// requires the enrichment stage. A tx that fails phase-2 validation pays with
+/// its collateral instead, which is attributed to the collateral owners. Byron
+/// txs don't declare a fee and are ignored.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, address),
+            None => format!("fees_by_address.{}", address),
+        }
+    }
+
+    /// Owners of the inputs with the lovelace of each input
+    fn funders<'a>(
+        &self,
+        inputs: impl Iterator<Item = &'a alonzo::TransactionInput>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<(String, u64)>, gasket::error::Error> {
+        let mut funders = Vec::new();
+
+        for input in inputs {
+            let utxo = match ctx.find_utxo(&input.transaction_id, input.index) {
+                Some(x) => x,
+                None => {
+                    log::warn!(
+                        "missing utxo {}#{} in block context",
+                        input.transaction_id,
+                        input.index
+                    );
+                    continue;
+                }
+            };
+
+            let address = self
+                .config
+                .address_key_format
+                .unwrap_or_default()
+                .output_key_ctx(utxo, &self.address_hrp, ctx)
+                .or_work_err()?;
+
+            if let Some(address) = address {
+                funders.push((address, utxo.lovelace()));
+            }
+        }
+
+        Ok(funders)
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        fees: &mut HashMap<String, i64>,
+    ) -> Result<(), gasket::error::Error> {
+        // a failed tx doesn't pay its fee, its collateral is taken instead
+        let (fee, funders) = match failed {
+            true => {
+                let funders = self.funders(model::collateral_inputs(tx), ctx)?;
+                let collateral = funders.iter().map(|(_, v)| *v).sum();
+
+                (Some(collateral), funders)
+            }
+            false => {
+                let fee = tx.iter().find_map(|c| match c {
+                    alonzo::TransactionBodyComponent::Fee(x) => Some(*x),
+                    _ => None,
+                });
+
+                let inputs = tx
+                    .iter()
+                    .filter_map(|c| match c {
+                        alonzo::TransactionBodyComponent::Inputs(x) => Some(x),
+                        _ => None,
+                    })
+                    .flat_map(|x| x.iter());
+
+                (fee, self.funders(inputs, ctx)?)
+            }
+        };
+
+        let fee = match (fee, funders.first()) {
+            (Some(fee), Some(_)) => fee,
+            _ => return Ok(()),
+        };
+
+        match self.config.attribution.unwrap_or_default() {
+            FeeAttribution::AttributeToFirst => {
+                *fees.entry(funders[0].0.clone()).or_insert(0) += fee as i64;
+            }
+            FeeAttribution::Split => {
+                let total: u128 = funders.iter().map(|(_, v)| *v as u128).sum();
+                let mut assigned = 0;
+
+                for (address, value) in funders.iter() {
+                    let share = match total {
+                        0 => 0,
+                        _ => (fee as u128 * *value as u128 / total) as u64,
+                    };
+
+                    assigned += share;
+                    *fees.entry(address.clone()).or_insert(0) += share as i64;
+                }
+
+                // rounding leftovers go to the first funder so that the whole fee is accounted
+                *fees.entry(funders[0].0.clone()).or_insert(0) += (fee - assigned) as i64;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_fees(
+        &self,
+        fees: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (address, fee) in fees.iter() {
+            let crdt = model::CRDTCommand::PNCounter(self.key(address), (sign * fee).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let mut fees = HashMap::new();
+        let failed = model::failed_txs(&x.1);
+
+        for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+            let failed = failed.contains(&idx);
+            self.collect_alonzo_compatible_tx(tx, failed, ctx, &mut fees)?;
+        }
+
+        fees.retain(|_, f| *f != 0);
+
+        self.send_fees(&fees, 1, output)?;
+
+        self.recent.push(x.1.header.header_body.slot, fees);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, fees) in self.recent.rollback(point) {
+            self.send_fees(&fees, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::FeesByAddress(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn reducer() -> super::super::Plugin {
+        Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+            attribution: None,
+        }
+        .plugin(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    fn key(seed: u8) -> String {
+        format!("fees_by_address.{}", address(seed))
+    }
+
+    #[test]
+    fn single_funder_pays_the_whole_fee_until_rolled_back() {
+        let mut harness = Harness::new(vec![reducer()]);
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let spend = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .output(&address(2), 4_830_000)
+                    .fee(170_000)
+            })
+            .build();
+
+        harness.roll_forward(spend);
+        assert_eq!(harness.store().counter(&key(1)), Some(170_000));
+
+        harness.roll_back(first);
+        assert_eq!(harness.store().counter(&key(1)), Some(0));
+    }
+
+    #[test]
+    fn failed_tx_charges_the_collateral() {
+        let mut harness = Harness::new(vec![reducer()]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 5_000_000)
+                    .output(&address(3), 2_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        harness.roll_forward(first);
+
+        let failed = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .collateral(funding, 1)
+                    .output(&address(2), 4_830_000)
+                    .fee(170_000)
+                    .failed()
+            })
+            .build();
+
+        harness.roll_forward(failed);
+
+        assert_eq!(harness.store().counter(&key(1)), None);
+        assert_eq!(harness.store().counter(&key(3)), Some(2_000_000));
+    }
+}
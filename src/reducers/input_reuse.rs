@@ -0,0 +1,324 @@
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// How many slots worth of spends to retain, should cover the rollback
+    /// window of the chain (defaults to 3k/f of mainnet)
+    pub window_slots: Option<u64>,
+}
+
+const DEFAULT_WINDOW_SLOTS: u64 = 129_600;
+
+#[derive(Serialize, Deserialize)]
+struct Spend {
+    tx: String,
+    slot: u64,
+}
+
+/// Changes of a block, to undo them on rollback
+#[derive(Serialize, Deserialize, Default)]
+struct Applied {
+    /// Inputs spent by the block, with the hash of their tx
+    spends: Vec<(model::OutputRef, String)>,
+
+    /// Rolled-back spends of the same inputs, forgotten once matched
+    matched: Vec<(model::OutputRef, Spend)>,
+}
+
+/// Detects inputs spent in a rolled-back block that get re-spent by a
+/// different tx after the rollback
+///
+/// Each event is stored as a member with the format
+/// `{input_ref},{original_tx},{new_tx}` of a sorted set scored by the slot of
+/// the new spend. The spends of the recent blocks and the rolled-back ones
+/// are kept in the state db, so they survive restarts.
+pub struct Reducer {
+    config: Config,
+    recent: state::History<Applied>,
+    rolled_back: state::Map<Spend>,
+}
+
+impl Reducer {
+    fn send_reuse_event(
+        &mut self,
+        input: &str,
+        original: &Spend,
+        tx: &str,
+        slot: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "input_reuse".to_string(),
+        };
+
+        log::warn!(
+            "input {} spent by rolled-back tx {} was re-spent by tx {}",
+            input,
+            original.tx,
+            tx
+        );
+
+        let member = format!("{},{},{}", input, original.tx, tx);
+        let crdt = model::CRDTCommand::LastWriteWins(key, member, slot);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    fn process_spend(
+        &mut self,
+        input: model::OutputRef,
+        tx: &Hash<32>,
+        slot: u64,
+        applied: &mut Applied,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let tx = tx.to_string();
+
+        if let Some(original) = self.rolled_back.remove(&input).or_work_err()? {
+            if original.tx != tx {
+                self.send_reuse_event(&input, &original, &tx, slot, output)?;
+            }
+
+            applied.matched.push((input.clone(), original));
+        }
+
+        applied.spends.push((input, tx));
+
+        Ok(())
+    }
+
+    fn prune(&mut self, slot: u64) -> Result<(), crate::Error> {
+        let window = self.config.window_slots.unwrap_or(DEFAULT_WINDOW_SLOTS);
+        let threshold = slot.saturating_sub(window);
+
+        for (input, spend) in self.rolled_back.entries()? {
+            if spend.slot < threshold {
+                self.rolled_back.remove(&input)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the rolled-back spends matched by the undone blocks
+    fn restore_matched(&mut self, applied: Applied) -> Result<(), crate::Error> {
+        for (input, spend) in applied.matched {
+            self.rolled_back.insert(&input, &spend)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish_block(&mut self, slot: u64, applied: Applied) -> Result<(), crate::Error> {
+        self.recent.push(slot, &applied)?;
+        self.prune(slot)
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if block.is_epoch_boundary() {
+            return Ok(());
+        }
+
+        // changes left by a run that stopped before the storage committed
+        for (_, applied) in self.recent.rewind(block.slot()).or_work_err()? {
+            self.restore_matched(applied).or_work_err()?;
+        }
+
+        let mut applied = Applied::default();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let slot = x.header.consensus_data.0.to_abs_slot();
+
+                for tx in x.body.tx_payload.iter() {
+                    let tx_hash = tx.transaction.to_hash();
+
+                    for input in tx.transaction.inputs.iter() {
+                        if let byron::TxIn::Variant0(x) = input {
+                            let (hash, idx) = &x.0;
+                            let input = model::output_ref(hash, *idx as u64);
+                            self.process_spend(input, &tx_hash, slot, &mut applied, output)?;
+                        }
+                    }
+                }
+
+                self.finish_block(slot, applied).or_work_err()?;
+            }
+            model::MultiEraBlock::Byron(_) => (),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let slot = x.1.header.header_body.slot;
+
+                for tx in x.1.transaction_bodies.iter() {
+                    let tx_hash = tx.to_hash();
+
+                    let inputs = tx
+                        .iter()
+                        .filter_map(|c| match c {
+                            alonzo::TransactionBodyComponent::Inputs(x) => Some(x),
+                            _ => None,
+                        })
+                        .flat_map(|x| x.iter());
+
+                    for input in inputs {
+                        let input = model::output_ref(&input.transaction_id, input.index);
+                        self.process_spend(input, &tx_hash, slot, &mut applied, output)?;
+                    }
+                }
+
+                self.finish_block(slot, applied).or_work_err()?;
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Remembers the spends of the blocks after the point as rolled back
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        _output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (slot, mut applied) in self.recent.rollback(point).or_work_err()? {
+            let spends = std::mem::take(&mut applied.spends);
+            self.restore_matched(applied).or_work_err()?;
+
+            for (input, tx) in spends {
+                self.rolled_back
+                    .insert(&input, &Spend { tx, slot })
+                    .or_work_err()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, state: &state::Db) -> Result<super::Plugin, crate::Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "input_reuse".to_string(),
+        };
+
+        let window = self.window_slots.unwrap_or(DEFAULT_WINDOW_SLOTS);
+
+        let reducer = Reducer {
+            config: self,
+            recent: state.history(&name)?.with_window(window),
+            rolled_back: state.map(&format!("{}.rolled_back", name))?,
+        };
+
+        Ok(super::Plugin::InputReuse(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, state_db, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            window_slots: None,
+        };
+
+        config.plugin(state).unwrap()
+    }
+
+    #[test]
+    fn respend_after_rollback_is_reported() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let spend = |slot, seed| {
+            block()
+                .slot(slot)
+                .number(2)
+                .tx(|t| t.input(funding, 0).output(&address(seed), 4_800_000))
+                .build()
+        };
+
+        let original = spend(DEFAULT_SLOT + 20, 2);
+        let original_tx = tx_hash(&original, 0);
+        harness.roll_forward(original);
+
+        harness.roll_back(first);
+
+        let respend = spend(DEFAULT_SLOT + 40, 3);
+        let respend_tx = tx_hash(&respend, 0);
+        harness.roll_forward(respend);
+
+        let events = harness.store().scores("input_reuse").unwrap().clone();
+        let input = model::output_ref(&funding, 0);
+        let member = format!("{},{},{}", input, original_tx, respend_tx);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(&member), Some(&(DEFAULT_SLOT as i64 + 40)));
+    }
+
+    #[test]
+    fn spends_of_a_previous_run_are_rolled_back() {
+        let state = state_db();
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funding = tx_hash(&first, 0);
+
+        let spend = |slot, seed| {
+            block()
+                .slot(slot)
+                .number(2)
+                .tx(|t| t.input(funding, 0).output(&address(seed), 4_800_000))
+                .build()
+        };
+
+        let original = spend(DEFAULT_SLOT + 20, 2);
+        let original_tx = tx_hash(&original, 0);
+
+        let first = {
+            let mut harness = Harness::new(vec![reducer(&state)]);
+            let first = harness.roll_forward(first);
+            harness.roll_forward(original);
+            first
+        };
+
+        // the rollback reaches a new run, which only knows the spend through
+        // the state db
+        let mut harness = Harness::new(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        let respend = spend(DEFAULT_SLOT + 40, 3);
+        let respend_tx = tx_hash(&respend, 0);
+        harness.roll_forward(respend);
+
+        let events = harness.store().scores("input_reuse").unwrap().clone();
+        let member = format!(
+            "{},{},{}",
+            model::output_ref(&funding, 0),
+            original_tx,
+            respend_tx
+        );
+
+        assert_eq!(events.keys().collect::<Vec<_>>(), vec![&member]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use pallas::codec::minicbor;
+use pallas::crypto::hash::Hasher;
+use serde::Deserialize;
+
+use crate::{model, Error};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// A tx seen in the mempool, with the fee that it declares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingTx {
+    pub hash: String,
+    pub fee: u64,
+}
+
+/// Reads the hash and the fee of a tx of a Shelley-onwards era, without
+/// decoding anything else so that txs of newer eras are understood too
+pub fn pending_tx(cbor: &[u8]) -> Result<PendingTx, Error> {
+    let mut d = minicbor::Decoder::new(cbor);
+
+    d.array().map_err(Error::ledger)?;
+
+    let start = d.position();
+    d.skip().map_err(Error::ledger)?;
+    let body = &cbor[start..d.position()];
+
+    let hash = Hasher::<256>::hash(body).to_string();
+
+    let mut d = minicbor::Decoder::new(body);
+    let entries = d
+        .map()
+        .map_err(Error::ledger)?
+        .ok_or_else(|| Error::message("tx body with indefinite length"))?;
+
+    for _ in 0..entries {
+        match d.u32().map_err(Error::ledger)? {
+            2 => {
+                let fee = d.u64().map_err(Error::ledger)?;
+                return Ok(PendingTx { hash, fee });
+            }
+            _ => d.skip().map_err(Error::ledger)?,
+        }
+    }
+
+    Err(Error::message(format!("tx {} without a fee", hash)))
+}
+
+/// Pending txs of the mempool of the node, apart from the reducers of the
+/// chain since these txs can vanish without ever reaching a block
+///
+/// Each snapshot of the mempool is compared to the previous one: the hashes
+/// of the new txs are added to the `{prefix}.txs` set and the ones that left
+/// are removed from it. The number of pending txs and the sum of their fees
+/// are written under `{prefix}.tx_count` and `{prefix}.fees`. The previous
+/// snapshot is kept in memory, the set is cleared by the first snapshot of a
+/// run.
+pub struct Reducer {
+    config: Config,
+    pending: Option<HashMap<String, u64>>,
+}
+
+impl Reducer {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            pending: None,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, name),
+            None => format!("mempool.{}", name),
+        }
+    }
+
+    /// The commands turning the previous snapshot into this one
+    pub fn reduce_snapshot(&mut self, txs: Vec<PendingTx>) -> Vec<model::CRDTCommand> {
+        let set = self.key("txs");
+        let mut commands = Vec::new();
+
+        let previous = match self.pending.take() {
+            Some(x) => x,
+            None => {
+                commands.push(model::CRDTCommand::AnyWriteWinsRemove(set.clone()));
+                HashMap::new()
+            }
+        };
+
+        let current: HashMap<_, _> = txs.into_iter().map(|x| (x.hash, x.fee)).collect();
+
+        for hash in previous.keys() {
+            if !current.contains_key(hash) {
+                commands.push(model::CRDTCommand::SetRemove(set.clone(), hash.clone()));
+            }
+        }
+
+        for hash in current.keys() {
+            if !previous.contains_key(hash) {
+                commands.push(model::CRDTCommand::GrowOnlySetAdd(
+                    set.clone(),
+                    hash.clone(),
+                ));
+            }
+        }
+
+        let fees: u64 = current.values().sum();
+
+        commands.push(model::CRDTCommand::AnyWriteWins(
+            self.key("tx_count"),
+            current.len().to_string(),
+        ));
+
+        commands.push(model::CRDTCommand::AnyWriteWins(
+            self.key("fees"),
+            fees.to_string(),
+        ));
+
+        self.pending = Some(current);
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::storage::memory::Store;
+
+    fn tx(hash: &str, fee: u64) -> PendingTx {
+        PendingTx {
+            hash: hash.to_string(),
+            fee,
+        }
+    }
+
+    fn apply(store: &mut Store, reducer: &mut Reducer, txs: Vec<PendingTx>) {
+        for command in reducer.reduce_snapshot(txs) {
+            store.apply(command).unwrap();
+        }
+    }
+
+    #[test]
+    fn pending_txs_follow_the_snapshots() {
+        let mut store = Store::default();
+
+        // left by a previous run, the first snapshot starts over
+        store
+            .apply(model::CRDTCommand::GrowOnlySetAdd(
+                "mempool.txs".into(),
+                "stale".into(),
+            ))
+            .unwrap();
+
+        let mut reducer = Reducer::new(Config { key_prefix: None });
+
+        apply(
+            &mut store,
+            &mut reducer,
+            vec![tx("a", 170_000), tx("b", 200_000)],
+        );
+
+        let members = |store: &Store| store.members("mempool.txs").cloned().unwrap_or_default();
+        let set = |hashes: &[&str]| {
+            hashes
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<BTreeSet<_>>()
+        };
+
+        assert_eq!(members(&store), set(&["a", "b"]));
+        assert_eq!(store.string("mempool.tx_count"), Some("2"));
+        assert_eq!(store.string("mempool.fees"), Some("370000"));
+
+        // a was included in a block, c arrived
+        apply(
+            &mut store,
+            &mut reducer,
+            vec![tx("b", 200_000), tx("c", 180_000)],
+        );
+
+        assert_eq!(members(&store), set(&["b", "c"]));
+        assert_eq!(store.string("mempool.tx_count"), Some("2"));
+        assert_eq!(store.string("mempool.fees"), Some("380000"));
+
+        apply(&mut store, &mut reducer, vec![]);
+
+        assert_eq!(members(&store), set(&[]));
+        assert_eq!(store.string("mempool.tx_count"), Some("0"));
+        assert_eq!(store.string("mempool.fees"), Some("0"));
+    }
+
+    #[test]
+    fn hash_and_fee_are_read_from_the_body() {
+        // [{0: [], 1: [], 2: 170000}, {}, true, null]
+        let body = hex::decode("a300800180021a00029810").unwrap();
+        let mut cbor = vec![0x84];
+        cbor.extend(&body);
+        cbor.extend(hex::decode("a0f5f6").unwrap());
+
+        let pending = pending_tx(&cbor).unwrap();
+
+        assert_eq!(pending.hash, Hasher::<256>::hash(&body).to_string());
+        assert_eq!(pending.fee, 170_000);
+
+        assert!(pending_tx(&hex::decode("84a0a0f5f6").unwrap()).is_err());
+    }
+}
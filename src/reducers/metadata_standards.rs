@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Pairs of metadata label and standard name, added to the default ones
+    /// or replacing the name of a default label
+    pub standards: Option<Vec<(u64, String)>>,
+}
+
+/// Labels of the CIP standards known out of the box
+const DEFAULT_STANDARDS: [(u64, &str); 5] = [
+    (674, "cip20_message"),
+    (721, "cip25_nft"),
+    (777, "cip27_royalties"),
+    (61284, "cip36_voting"),
+    (61285, "cip36_voting"),
+];
+
+/// Name of the bucket for the labels of no known standard
+const OTHER: &str = "other";
+
+/// Counts the txs that carry metadata of each standard
+///
+/// `{prefix}.{standard}` is the number of txs with metadata of the standard
+/// and `{prefix}.{standard}.{epoch}` the number for each epoch, to follow
+/// adoption trends. Labels of no known standard count as `other`. A tx counts
+/// once per standard, whatever the number of its labels of that standard.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    standards: HashMap<u64, String>,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, suffix: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, suffix),
+            None => format!("metadata_standards.{}", suffix),
+        }
+    }
+
+    fn standard(&self, label: u64) -> &str {
+        self.standards
+            .get(&label)
+            .map(String::as_str)
+            .unwrap_or(OTHER)
+    }
+
+    fn send_counts(
+        &self,
+        counts: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, count) in counts.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * count).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let mut counts: HashMap<String, i64> = HashMap::new();
+
+        for (_, aux) in x.1.auxiliary_data_set.iter() {
+            let standards: HashSet<_> = crosscut::metadata_labels(aux)
+                .into_iter()
+                .map(|label| self.standard(label))
+                .collect();
+
+            for standard in standards {
+                let total = self.key(standard);
+                let by_epoch = self.key(&format!("{}.{}", standard, epoch));
+
+                for key in [total, by_epoch] {
+                    *counts.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        self.send_counts(&counts, 1, output)?;
+
+        self.recent.push(slot, counts);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counts) in self.recent.rollback(point) {
+            self.send_counts(&counts, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    fn standards(&self) -> HashMap<u64, String> {
+        let mut standards: HashMap<_, _> = DEFAULT_STANDARDS
+            .iter()
+            .map(|(label, name)| (*label, name.to_string()))
+            .collect();
+
+        for (label, name) in self.standards.iter().flatten() {
+            standards.insert(*label, name.clone());
+        }
+
+        standards
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            standards: self.standards(),
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::MetadataStandards(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn txs_count_once_for_each_standard_of_their_labels() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let config = Config {
+            key_prefix: None,
+            standards: Some(vec![(1967, "nmkr".to_string())]),
+        };
+
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        let count = |harness: &Harness, key: &str| {
+            harness
+                .store()
+                .counter(&format!("metadata_standards.{}", key))
+        };
+
+        let first = block()
+            .tx(|t| t.metadata(721, "nft"))
+            .tx(|t| t.metadata(721, "nft").metadata(674, "hello"))
+            .tx(|t| t.metadata(1967, "mint").metadata(42, "x").metadata(43, "y"))
+            .tx(|t| t.fee(170_000))
+            .build();
+
+        let first = harness.roll_forward(first);
+
+        assert_eq!(count(&harness, "cip25_nft"), Some(2));
+        assert_eq!(count(&harness, &format!("cip25_nft.{}", epoch)), Some(2));
+        assert_eq!(count(&harness, "cip20_message"), Some(1));
+        assert_eq!(count(&harness, "nmkr"), Some(1));
+        assert_eq!(count(&harness, "other"), Some(1));
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.metadata(721, "nft"))
+            .build();
+
+        harness.roll_forward(second);
+        assert_eq!(count(&harness, "cip25_nft"), Some(3));
+
+        harness.roll_back(first);
+        assert_eq!(count(&harness, "cip25_nft"), Some(2));
+        assert_eq!(count(&harness, "other"), Some(1));
+    }
+}
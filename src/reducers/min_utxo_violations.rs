@@ -0,0 +1,227 @@
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Min-UTxO schedule as `[slot, lovelace]` pairs, in slot order. Each
+    /// minimum applies from its slot until the next one.
+    pub thresholds: Vec<(u64, u64)>,
+}
+
+/// Flags the outputs holding less lovelace than the min-UTxO effective at the
+/// slot of their block
+///
+/// Protocol parameters aren't tracked by any reducer, so the minimums come
+/// from the configured schedule. Each flagged output is a `{tx}#{index}`
+/// member of the sorted set `{prefix}`, scored by the slot of its block. The
+/// entries of rolled-back blocks are removed.
+pub struct Reducer {
+    config: Config,
+    recent: super::RollbackHistory<()>,
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "min_utxo_violations".to_string(),
+        }
+    }
+
+    /// Minimum effective at the slot, none before the first one of the schedule
+    fn threshold_at(&self, slot: u64) -> Option<u64> {
+        self.config
+            .thresholds
+            .iter()
+            .take_while(|(from, _)| *from <= slot)
+            .last()
+            .map(|(_, lovelace)| *lovelace)
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = block.slot();
+
+        let threshold = match self.threshold_at(slot) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut flagged = Vec::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    let hash = tx.transaction.to_hash();
+
+                    for (idx, out) in tx.transaction.outputs.iter().enumerate() {
+                        if out.amount < threshold {
+                            flagged.push(format!("{}#{}", hash, idx));
+                        }
+                    }
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                for tx in x.1.transaction_bodies.iter() {
+                    let hash = tx.to_hash();
+
+                    let outputs = tx
+                        .iter()
+                        .filter_map(|x| match x {
+                            alonzo::TransactionBodyComponent::Outputs(outputs) => Some(outputs),
+                            _ => None,
+                        })
+                        .flat_map(|x| x.iter());
+
+                    for (idx, out) in outputs.enumerate() {
+                        if model::alonzo_lovelace(out) < threshold {
+                            flagged.push(format!("{}#{}", hash, idx));
+                        }
+                    }
+                }
+            }
+        };
+
+        if flagged.is_empty() {
+            return Ok(());
+        }
+
+        for member in flagged {
+            log::warn!("output {} is below the min-utxo of {}", member, threshold);
+
+            let crdt = model::CRDTCommand::LastWriteWins(self.key(), member, slot);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        self.recent.push(slot, ());
+
+        Ok(())
+    }
+
+    /// Removes the entries of the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, ()) in self.recent.rollback(point) {
+            let crdt = model::CRDTCommand::SortedSetRemoveRange(self.key(), applied, applied);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.thresholds.is_empty() {
+            return Err(Error::config(
+                "MinUtxoViolations reducer needs at least one threshold",
+            ));
+        }
+
+        let ordered = self.thresholds.windows(2).all(|x| x[0].0 < x[1].0);
+
+        if !ordered {
+            return Err(Error::config(
+                "MinUtxoViolations thresholds should be in increasing slot order",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::MinUtxoViolations(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn outputs_below_the_threshold_of_their_slot_are_flagged() {
+        let config = Config {
+            key_prefix: None,
+            thresholds: vec![(0, 1_000_000), (DEFAULT_SLOT + 10, 2_000_000)],
+        };
+
+        assert!(config.validate().is_ok());
+
+        let mut harness = Harness::new(vec![config.plugin()]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 1_500_000)
+                    .output(&address(2), 500_000)
+            })
+            .build();
+
+        let below = format!("{}#1", tx_hash(&first, 0));
+        let first = harness.roll_forward(first);
+
+        // above the first minimum, below the one in effect from this slot
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(3), 1_500_000))
+            .build();
+
+        let later = format!("{}#0", tx_hash(&second, 0));
+        harness.roll_forward(second);
+
+        let flagged = |harness: &Harness| {
+            harness
+                .store()
+                .scores("min_utxo_violations")
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        assert_eq!(
+            flagged(&harness),
+            BTreeMap::from([
+                (below.clone(), DEFAULT_SLOT as i64),
+                (later, DEFAULT_SLOT as i64 + 20)
+            ])
+        );
+
+        harness.roll_back(first);
+
+        assert_eq!(
+            flagged(&harness),
+            BTreeMap::from([(below, DEFAULT_SLOT as i64)])
+        );
+    }
+
+    #[test]
+    fn thresholds_should_be_in_slot_order() {
+        let config = |thresholds| Config {
+            key_prefix: None,
+            thresholds,
+        };
+
+        assert!(config(vec![]).validate().is_err());
+        assert!(config(vec![(10, 1), (5, 2)]).validate().is_err());
+        assert!(config(vec![(5, 1), (10, 2)]).validate().is_ok());
+    }
+}
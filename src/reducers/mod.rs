@@ -1,21 +1,102 @@
-use gasket::runtime::{spawn_stage, WorkOutcome};
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+use gasket::{
+    error::AsWorkError,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use pallas::network::miniprotocols::Point;
 
 use crate::{
-    bootstrap,
-    model::{self, CRDTCommand, MultiEraBlock},
+    bootstrap, crosscut,
+    model::{self, BlockContext, CRDTCommand, MultiEraBlock},
 };
 
-type InputPort = gasket::messaging::InputPort<model::ChainSyncCommandEx>;
-type OutputPort = gasket::messaging::OutputPort<model::CRDTCommand>;
+type InputPort = gasket::messaging::InputPort<model::EnrichedBlockPayload>;
+type StagePort = gasket::messaging::OutputPort<model::CRDTCommand>;
+
+/// Port handed to a reducer, its commands go through the key template of the
+/// reducer, if any, on their way to the storage
+pub struct OutputPort<'a> {
+    port: &'a mut StagePort,
+    template: Option<&'a crosscut::KeyTemplate>,
+}
+
+impl<'a> OutputPort<'a> {
+    fn new(port: &'a mut StagePort, template: Option<&'a crosscut::KeyTemplate>) -> Self {
+        OutputPort { port, template }
+    }
+
+    pub fn send(
+        &mut self,
+        msg: gasket::messaging::Message<CRDTCommand>,
+    ) -> Result<(), gasket::error::Error> {
+        match self.template {
+            Some(template) => self.port.send(gasket::messaging::Message::from(
+                template.apply(msg.payload),
+            )),
+            None => self.port.send(msg),
+        }
+    }
+}
 
+pub mod address_clusters;
+pub mod address_flows;
+pub mod asset_classification;
+pub mod asset_provenance;
+pub mod assets_by_address;
+pub mod balance_history;
+pub mod block_linkage;
+pub mod block_stats;
+pub mod block_value_by_pool;
+pub mod certificates_by_epoch;
+pub mod chain_tip;
+pub mod current_epoch;
+pub mod delegation_churn;
+pub mod delegators_by_pool;
+pub mod deposits;
+pub mod fees_by_address;
+pub mod input_reuse;
+pub mod mempool;
+pub mod metadata_standards;
+pub mod min_utxo_violations;
+pub mod orphaned_blocks;
+pub mod output_stats;
 pub mod point_by_tx;
+pub mod pool_activity;
 pub mod pool_by_stake;
+pub mod pool_metadata_by_pool;
+pub mod pool_relays;
+pub mod raw_cbor;
+pub mod realized_rewards;
+pub mod richest_addresses;
+pub mod rollback_stats;
+pub mod script_execution_units;
+pub mod script_spends;
+pub mod slot_gaps;
+pub mod stake_balance;
+pub mod stake_distribution;
+pub mod state;
+pub mod token_outputs;
 pub mod total_transactions_count;
 pub mod total_transactions_count_by_contract_addresses;
 pub mod transactions_count_by_contract_address;
 pub mod transactions_count_by_contract_address_by_epoch;
 pub mod transactions_count_by_epoch;
+pub mod transfer_volume;
+pub mod tx_io_distribution;
+pub mod tx_throughput;
+pub mod tx_validity_intervals;
+pub mod unique_addresses_by_epoch;
+pub mod utxo_age_histogram;
 pub mod utxo_by_address;
+pub mod utxo_set_size;
+pub mod utxo_value_histogram;
+pub mod watched_utxos;
+pub mod whale_transactions;
+pub mod withdrawals_by_pool;
+pub mod witness_distribution;
 
 pub enum Plugin {
     UtxoByAddress(utxo_by_address::Reducer),
@@ -30,12 +111,59 @@ pub enum Plugin {
     TotalTransactionsCountByContractAddresses(
         total_transactions_count_by_contract_addresses::Reducer,
     ),
+    BlockStats(block_stats::Reducer),
+    RichestAddresses(richest_addresses::Reducer),
+    TxValidityIntervals(tx_validity_intervals::Reducer),
+    PoolMetadataByPool(pool_metadata_by_pool::Reducer),
+    ChainTip(chain_tip::Reducer),
+    InputReuse(input_reuse::Reducer),
+    BalanceHistory(balance_history::Reducer),
+    UniqueAddressesByEpoch(unique_addresses_by_epoch::Reducer),
+    FeesByAddress(fees_by_address::Reducer),
+    TxIoDistribution(tx_io_distribution::Reducer),
+    WitnessDistribution(witness_distribution::Reducer),
+    AssetClassification(asset_classification::Reducer),
+    BlockLinkage(block_linkage::Reducer),
+    StakeDistribution(stake_distribution::Reducer),
+    DelegatorsByPool(delegators_by_pool::Reducer),
+    Deposits(deposits::Reducer),
+    TokenOutputs(token_outputs::Reducer),
+    TxThroughput(tx_throughput::Reducer),
+    AddressClusters(address_clusters::Reducer),
+    RollbackStats(rollback_stats::Reducer),
+    PoolActivity(pool_activity::Reducer),
+    WatchedUtxos(watched_utxos::Reducer),
+    UtxoValueHistogram(utxo_value_histogram::Reducer),
+    TransferVolume(transfer_volume::Reducer),
+    CertificatesByEpoch(certificates_by_epoch::Reducer),
+    PoolRelays(pool_relays::Reducer),
+    ScriptExecutionUnits(script_execution_units::Reducer),
+    AssetsByAddress(assets_by_address::Reducer),
+    OutputStats(output_stats::Reducer),
+    WithdrawalsByPool(withdrawals_by_pool::Reducer),
+    OrphanedBlocks(orphaned_blocks::Reducer),
+    StakeBalance(stake_balance::Reducer),
+    WhaleTransactions(whale_transactions::Reducer),
+    UtxoAgeHistogram(utxo_age_histogram::Reducer),
+    DelegationChurn(delegation_churn::Reducer),
+    SlotGaps(slot_gaps::Reducer),
+    AddressFlows(address_flows::Reducer),
+    MinUtxoViolations(min_utxo_violations::Reducer),
+    CurrentEpoch(current_epoch::Reducer),
+    AssetProvenance(asset_provenance::Reducer),
+    ScriptSpends(script_spends::Reducer),
+    RealizedRewards(realized_rewards::Reducer),
+    UtxoSetSize(utxo_set_size::Reducer),
+    BlockValueByPool(block_value_by_pool::Reducer),
+    RawCbor(raw_cbor::Reducer),
+    MetadataStandards(metadata_standards::Reducer),
 }
 
 impl Plugin {
     pub fn reduce_block(
         &mut self,
         block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
         output: &mut OutputPort,
     ) -> Result<(), gasket::error::Error> {
         match self {
@@ -47,24 +175,399 @@ impl Plugin {
             Plugin::TransactionsCountByContractAddress(x) => x.reduce_block(block, output),
             Plugin::TransactionsCountByContractAddressByEpoch(x) => x.reduce_block(block, output),
             Plugin::TotalTransactionsCountByContractAddresses(x) => x.reduce_block(block, output),
+            Plugin::BlockStats(x) => x.reduce_block(block, output),
+            Plugin::RichestAddresses(x) => x.reduce_block(block, ctx, output),
+            Plugin::TxValidityIntervals(x) => x.reduce_block(block, output),
+            Plugin::PoolMetadataByPool(x) => x.reduce_block(block, output),
+            Plugin::ChainTip(x) => x.reduce_block(block, output),
+            Plugin::InputReuse(x) => x.reduce_block(block, output),
+            Plugin::BalanceHistory(x) => x.reduce_block(block, ctx, output),
+            Plugin::UniqueAddressesByEpoch(x) => x.reduce_block(block, ctx, output),
+            Plugin::FeesByAddress(x) => x.reduce_block(block, ctx, output),
+            Plugin::TxIoDistribution(x) => x.reduce_block(block, output),
+            Plugin::WitnessDistribution(x) => x.reduce_block(block, output),
+            Plugin::AssetClassification(x) => x.reduce_block(block, output),
+            Plugin::BlockLinkage(x) => x.reduce_block(block, output),
+            Plugin::StakeDistribution(x) => x.reduce_block(block, ctx, output),
+            Plugin::DelegatorsByPool(x) => x.reduce_block(block, output),
+            Plugin::Deposits(x) => x.reduce_block(block, output),
+            Plugin::TokenOutputs(x) => x.reduce_block(block, output),
+            Plugin::TxThroughput(x) => x.reduce_block(block, output),
+            Plugin::AddressClusters(x) => x.reduce_block(block, ctx, output),
+            Plugin::RollbackStats(x) => x.reduce_block(block, output),
+            Plugin::PoolActivity(x) => x.reduce_block(block, output),
+            Plugin::WatchedUtxos(x) => x.reduce_block(block, ctx, output),
+            Plugin::UtxoValueHistogram(x) => x.reduce_block(block, ctx, output),
+            Plugin::TransferVolume(x) => x.reduce_block(block, output),
+            Plugin::CertificatesByEpoch(x) => x.reduce_block(block, output),
+            Plugin::PoolRelays(x) => x.reduce_block(block, output),
+            Plugin::ScriptExecutionUnits(x) => x.reduce_block(block, ctx, output),
+            Plugin::AssetsByAddress(x) => x.reduce_block(block, ctx, output),
+            Plugin::OutputStats(x) => x.reduce_block(block, output),
+            Plugin::WithdrawalsByPool(x) => x.reduce_block(block, output),
+            Plugin::OrphanedBlocks(x) => x.reduce_block(block, output),
+            Plugin::StakeBalance(x) => x.reduce_block(block, ctx, output),
+            Plugin::WhaleTransactions(x) => x.reduce_block(block, output),
+            Plugin::UtxoAgeHistogram(x) => x.reduce_block(block, ctx, output),
+            Plugin::DelegationChurn(x) => x.reduce_block(block, output),
+            Plugin::SlotGaps(x) => x.reduce_block(block, output),
+            Plugin::AddressFlows(x) => x.reduce_block(block, ctx, output),
+            Plugin::MinUtxoViolations(x) => x.reduce_block(block, output),
+            Plugin::CurrentEpoch(x) => x.reduce_block(block, output),
+            Plugin::AssetProvenance(x) => x.reduce_block(block, ctx, output),
+            Plugin::ScriptSpends(x) => x.reduce_block(block, ctx, output),
+            Plugin::RealizedRewards(x) => x.reduce_block(block, output),
+            Plugin::UtxoSetSize(x) => x.reduce_block(block, ctx, output),
+            Plugin::BlockValueByPool(x) => x.reduce_block(block, output),
+            Plugin::RawCbor(x) => x.reduce_block(block, ctx, output),
+            Plugin::MetadataStandards(x) => x.reduce_block(block, output),
+        }
+    }
+
+    /// Gives reducers that keep track of the chain position the chance to
+    /// rewind. Most reducers ignore rollbacks.
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        match self {
+            Plugin::ChainTip(x) => x.rollback(point, output),
+            Plugin::InputReuse(x) => x.rollback(point, output),
+            Plugin::BalanceHistory(x) => x.rollback(point, output),
+            Plugin::UniqueAddressesByEpoch(x) => x.rollback(point, output),
+            Plugin::FeesByAddress(x) => x.rollback(point, output),
+            Plugin::TxIoDistribution(x) => x.rollback(point, output),
+            Plugin::WitnessDistribution(x) => x.rollback(point, output),
+            Plugin::AssetClassification(x) => x.rollback(point, output),
+            Plugin::BlockLinkage(x) => x.rollback(point, output),
+            Plugin::StakeDistribution(x) => x.rollback(point, output),
+            Plugin::DelegatorsByPool(x) => x.rollback(point, output),
+            Plugin::Deposits(x) => x.rollback(point, output),
+            Plugin::TokenOutputs(x) => x.rollback(point, output),
+            Plugin::TxThroughput(x) => x.rollback(point, output),
+            Plugin::AddressClusters(x) => x.rollback(point, output),
+            Plugin::RollbackStats(x) => x.rollback(point, output),
+            Plugin::PoolActivity(x) => x.rollback(point, output),
+            Plugin::WatchedUtxos(x) => x.rollback(point, output),
+            Plugin::UtxoValueHistogram(x) => x.rollback(point, output),
+            Plugin::TransferVolume(x) => x.rollback(point, output),
+            Plugin::CertificatesByEpoch(x) => x.rollback(point, output),
+            Plugin::PoolRelays(x) => x.rollback(point, output),
+            Plugin::ScriptExecutionUnits(x) => x.rollback(point, output),
+            Plugin::AssetsByAddress(x) => x.rollback(point, output),
+            Plugin::OutputStats(x) => x.rollback(point, output),
+            Plugin::WithdrawalsByPool(x) => x.rollback(point, output),
+            Plugin::OrphanedBlocks(x) => x.rollback(point, output),
+            Plugin::StakeBalance(x) => x.rollback(point, output),
+            Plugin::WhaleTransactions(x) => x.rollback(point, output),
+            Plugin::UtxoAgeHistogram(x) => x.rollback(point, output),
+            Plugin::DelegationChurn(x) => x.rollback(point, output),
+            Plugin::SlotGaps(x) => x.rollback(point, output),
+            Plugin::AddressFlows(x) => x.rollback(point, output),
+            Plugin::MinUtxoViolations(x) => x.rollback(point, output),
+            Plugin::CurrentEpoch(x) => x.rollback(point, output),
+            Plugin::AssetProvenance(x) => x.rollback(point, output),
+            Plugin::ScriptSpends(x) => x.rollback(point, output),
+            Plugin::RealizedRewards(x) => x.rollback(point, output),
+            Plugin::UtxoSetSize(x) => x.rollback(point, output),
+            Plugin::BlockValueByPool(x) => x.rollback(point, output),
+            Plugin::RawCbor(x) => x.rollback(point, output),
+            Plugin::MetadataStandards(x) => x.rollback(point, output),
+            Plugin::BlockStats(x) => x.rollback(point, output),
+            Plugin::TxValidityIntervals(x) => x.rollback(point, output),
+            Plugin::PoolMetadataByPool(x) => x.rollback(point, output),
+            Plugin::RichestAddresses(x) => x.rollback(point, output),
+            _ => Ok(()),
         }
     }
 }
 
+impl Plugin {
+    /// Allowlists currently applied by the reducer, empty for reducers that
+    /// don't have any
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        match self {
+            Plugin::UtxoByAddress(x) => x.allowlists(),
+            Plugin::AssetsByAddress(x) => x.allowlists(),
+            Plugin::TransferVolume(x) => x.allowlists(),
+            Plugin::ScriptSpends(x) => x.allowlists(),
+            Plugin::RawCbor(x) => x.allowlists(),
+            _ => Default::default(),
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        match self {
+            Plugin::UtxoByAddress(x) => x.set_allowlists(allowlists),
+            Plugin::AssetsByAddress(x) => x.set_allowlists(allowlists),
+            Plugin::TransferVolume(x) => x.set_allowlists(allowlists),
+            Plugin::ScriptSpends(x) => x.set_allowlists(allowlists),
+            Plugin::RawCbor(x) => x.set_allowlists(allowlists),
+            _ => (),
+        }
+    }
+}
+
+/// Number of recent blocks remembered to undo their changes on rollback, the
+/// security parameter (k) of mainnet
+pub const MAX_TRACKED_BLOCKS: usize = 2160;
+
+/// Changes applied by the most recent blocks, to undo them on rollback
+///
+/// The history lives in memory only and starts empty on each run. A rollback
+/// to a point before the start of the run can't be undone through it; the
+/// reducers relying on it only revert the blocks applied by this process.
+pub struct RollbackHistory<T> {
+    recent: VecDeque<(u64, T)>,
+}
+
+impl<T> Default for RollbackHistory<T> {
+    fn default() -> Self {
+        Self {
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> RollbackHistory<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the changes of the block at the slot, forgetting the oldest
+    /// block once more than `MAX_TRACKED_BLOCKS` are tracked
+    pub fn push(&mut self, slot: u64, changes: T) {
+        self.recent.push_back((slot, changes));
+
+        if self.recent.len() > MAX_TRACKED_BLOCKS {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Forgets the blocks after the point and hands back their changes, most
+    /// recent first
+    pub fn rollback(&mut self, point: &Point) -> Vec<(u64, T)> {
+        let slot = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        let mut undone = Vec::new();
+
+        while let Some((applied, _)) = self.recent.back() {
+            if slot.map(|x| *applied <= x).unwrap_or(false) {
+                break;
+            }
+
+            undone.push(self.recent.pop_back().unwrap());
+        }
+
+        undone
+    }
+
+    /// Tracked blocks, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &(u64, T)> {
+        self.recent.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.recent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recent.is_empty()
+    }
+}
+
+/// A reducer of the stage, with the state kept to isolate its failures
+struct Entry {
+    name: &'static str,
+    plugin: Plugin,
+    sampler: Option<crosscut::Sampler>,
+    failures: u32,
+    disabled: bool,
+
+    /// Extra storage port that receives the commands of the reducer, none
+    /// for the main storage
+    route: Option<usize>,
+
+    /// Rewrites the keys of the commands of the reducer, shared with the port
+    /// of each block
+    template: Option<Arc<crosscut::KeyTemplate>>,
+}
+
+impl Entry {
+    /// Runs an operation of the reducer catching its errors and panics, which
+    /// are logged and counted instead of stopping the stage. The reducer is
+    /// disabled once it reaches the max number of failures. Commands sent by
+    /// the reducer before failing are kept as part of the block. Returns the
+    /// reason of the failure, if any.
+    fn isolated<F>(
+        &mut self,
+        errors: &gasket::metrics::Counter,
+        max_failures: Option<u32>,
+        at: &str,
+        op: F,
+    ) -> Result<(), String>
+    where
+        F: FnOnce(&mut Plugin) -> Result<(), gasket::error::Error>,
+    {
+        let plugin = &mut self.plugin;
+
+        let reason = match catch_unwind(AssertUnwindSafe(|| op(plugin))) {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(err)) => format!("{:?}", err),
+            Err(payload) => format!("panic: {}", panic_message(payload.as_ref())),
+        };
+
+        self.failures += 1;
+        errors.inc(1);
+
+        log::error!("reducer {} failed at {}: {}", self.name, at, reason);
+
+        if let Some(max) = max_failures {
+            if self.failures >= max {
+                log::error!("reducer {} disabled after {} failures", self.name, max);
+                self.disabled = true;
+            }
+        }
+
+        Err(reason)
+    }
+}
+
 pub struct Worker {
     input: InputPort,
-    output: OutputPort,
-    reducers: Vec<Plugin>,
+    output: StagePort,
+    routes: Vec<StagePort>,
+    reducers: Vec<Entry>,
+    filters: crosscut::Filters,
+    scope: Option<crosscut::ScopeHandle>,
+    notifier: Option<crosscut::RollbackNotifier>,
+    dead_letter: Option<crosscut::DeadLetterSink>,
+    isolate: bool,
+    max_failures: Option<u32>,
+    recent_slots: RollbackHistory<()>,
     ops_count: gasket::metrics::Counter,
+    ebb_count: gasket::metrics::Counter,
+    reducer_errors: gasket::metrics::Counter,
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
 }
 
 impl Worker {
-    pub fn new(reducers: Vec<Plugin>) -> Self {
+    /// Each reducer comes with its name and an optional sampler that restricts
+    /// the blocks it gets to process
+    pub fn new(
+        reducers: Vec<(&'static str, Plugin, Option<crosscut::Sampler>)>,
+        filters: crosscut::Filters,
+        notifier: Option<crosscut::RollbackNotifier>,
+        policy: &crosscut::RuntimePolicy,
+    ) -> Self {
+        let reducers = reducers
+            .into_iter()
+            .map(|(name, plugin, sampler)| Entry {
+                name,
+                plugin,
+                sampler,
+                failures: 0,
+                disabled: false,
+                route: None,
+                template: None,
+            })
+            .collect();
+
         Worker {
             reducers,
+            filters,
+            scope: None,
+            notifier,
+            dead_letter: None,
+            isolate: policy.isolate_reducers.unwrap_or(false),
+            max_failures: policy.max_reducer_failures,
+            recent_slots: RollbackHistory::new(),
             input: Default::default(),
             output: Default::default(),
+            routes: Vec::new(),
             ops_count: Default::default(),
+            ebb_count: Default::default(),
+            reducer_errors: Default::default(),
+        }
+    }
+
+    /// Lets the filters and allowlists be replaced while the stage runs
+    pub fn with_scope(mut self, scope: crosscut::ScopeHandle) -> Self {
+        self.scope = Some(scope);
+        self
+    }
+
+    /// Keeps the blocks that make an isolated reducer fail
+    pub fn with_dead_letter(mut self, sink: crosscut::DeadLetterSink) -> Self {
+        self.dead_letter = Some(sink);
+        self
+    }
+
+    /// Sends the commands of each reducer to the extra storage port at the
+    /// same position, or to the main one when none is given
+    pub fn with_routes(mut self, routes: Vec<Option<usize>>) -> Self {
+        let count = routes.iter().flatten().map(|x| x + 1).max().unwrap_or(0);
+        self.routes = (0..count).map(|_| Default::default()).collect();
+
+        for (entry, route) in self.reducers.iter_mut().zip(routes) {
+            entry.route = route;
+        }
+
+        self
+    }
+
+    /// Rewrites the keys of each reducer with the template at the same
+    /// position, if any
+    pub fn with_key_templates(mut self, templates: Vec<Option<crosscut::KeyTemplate>>) -> Self {
+        for (entry, template) in self.reducers.iter_mut().zip(templates) {
+            entry.template = template.map(Arc::new);
+        }
+
+        self
+    }
+
+    /// Applies a scope queued since the previous block, if any
+    fn apply_scope(&mut self) {
+        let scope = match self.scope.as_ref().and_then(|x| x.take()) {
+            Some(x) => x,
+            None => return,
+        };
+
+        if scope.allowlists.len() != self.reducers.len() {
+            log::error!("reloaded scope doesn't match the running reducers, ignoring it");
+            return;
+        }
+
+        if scope.filters != self.filters {
+            log::warn!(
+                "filters changed from {:?} to {:?}",
+                self.filters,
+                scope.filters
+            );
+
+            self.filters = scope.filters;
+        }
+
+        for (entry, allowlists) in self.reducers.iter_mut().zip(scope.allowlists) {
+            let changes = allowlists.diff(&entry.plugin.allowlists());
+
+            if !changes.is_empty() {
+                log::warn!("reducer {} {}", entry.name, changes.join(", "));
+                entry.plugin.set_allowlists(allowlists);
+            }
         }
     }
 
@@ -72,27 +575,106 @@ impl Worker {
         &mut self.input
     }
 
-    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+    pub fn borrow_output_port(&mut self) -> &'_ mut StagePort {
         &mut self.output
     }
 
+    /// Port of an extra storage, as set by [Worker::with_routes]
+    pub fn borrow_route_port(&mut self, route: usize) -> &'_ mut StagePort {
+        &mut self.routes[route]
+    }
+
+    /// Sends a command to the main storage and to every extra one, for the
+    /// commands that delimit blocks
+    fn broadcast<F>(&mut self, crdt: F) -> Result<(), gasket::error::Error>
+    where
+        F: Fn() -> CRDTCommand,
+    {
+        for route in self.routes.iter_mut() {
+            route.send(gasket::messaging::Message::from(crdt()))?;
+        }
+
+        self.output.send(gasket::messaging::Message::from(crdt()))?;
+
+        Ok(())
+    }
+
     pub fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
         pipeline.register_stage("reducers", spawn_stage(self, Default::default()));
     }
 
-    fn reduce_block(&mut self, block: &MultiEraBlock) -> Result<(), gasket::error::Error> {
-        self.output.send(gasket::messaging::Message::from(
-            CRDTCommand::block_starting(block),
-        ))?;
+    /// Number of processed blocks undone by a rollback, as far as this process
+    /// has seen
+    fn rollback_depth(&mut self, point: &Point) -> usize {
+        self.recent_slots.rollback(point).len()
+    }
+
+    fn reduce_block(
+        &mut self,
+        block: &MultiEraBlock,
+        ctx: &BlockContext,
+    ) -> Result<(), gasket::error::Error> {
+        self.apply_scope();
+
+        self.broadcast(|| CRDTCommand::block_starting(block))?;
+
+        // EBBs have nothing to reduce, but we still wrap them with the start / finish
+        // commands so that the cursor moves past them
+        if block.is_epoch_boundary() {
+            log::debug!("skipping reducers for epoch boundary block");
+            self.ebb_count.inc(1);
+        } else if let Some(filtered) = self.filters.apply(block).or_work_err()?.block() {
+            let slot = block.slot();
+            let at = format!("slot {}", slot);
+
+            for entry in self.reducers.iter_mut() {
+                if entry.disabled {
+                    continue;
+                }
+
+                let port = match entry.route {
+                    Some(route) => &mut self.routes[route],
+                    None => &mut self.output,
+                };
+
+                if let Some(sampler) = &mut entry.sampler {
+                    if !sampler.accepts(slot) {
+                        continue;
+                    }
+
+                    if let Some(marker) = sampler.marker() {
+                        port.send(gasket::messaging::Message::from(marker))?;
+                    }
+                }
+
+                let template = entry.template.clone();
+                let output = &mut OutputPort::new(port, template.as_deref());
 
-        for reducer in self.reducers.iter_mut() {
-            reducer.reduce_block(block, &mut self.output)?;
-            self.ops_count.inc(1);
+                match self.isolate {
+                    true => {
+                        let reduce = |x: &mut Plugin| x.reduce_block(filtered, ctx, output);
+
+                        let result =
+                            entry.isolated(&self.reducer_errors, self.max_failures, &at, reduce);
+
+                        if let Err(reason) = result {
+                            if let Some(sink) = self.dead_letter.as_mut() {
+                                sink.record(entry.name, block, &reason);
+                            }
+
+                            continue;
+                        }
+                    }
+                    false => entry.plugin.reduce_block(filtered, ctx, output)?,
+                };
+
+                self.ops_count.inc(1);
+            }
         }
 
-        self.output.send(gasket::messaging::Message::from(
-            CRDTCommand::block_finished(block),
-        ))?;
+        self.broadcast(|| CRDTCommand::block_finished(block))?;
+
+        self.recent_slots.push(block.slot(), ());
 
         Ok(())
     }
@@ -100,21 +682,331 @@ impl Worker {
 
 impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new()
+        let mut builder = gasket::metrics::Builder::new()
             .with_counter("ops_count", &self.ops_count)
-            .build()
+            .with_counter("ebb_count", &self.ebb_count)
+            .with_counter("reducer_errors", &self.reducer_errors);
+
+        if let Some(notifier) = &self.notifier {
+            builder = builder.with_counter("rollback_notify_failures", notifier.failed_count());
+        }
+
+        if let Some(sink) = &self.dead_letter {
+            builder = builder.with_counter("dead_letter_dropped", sink.dropped_count());
+        }
+
+        builder.build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
         let msg = self.input.recv()?;
 
         match msg.payload {
-            model::ChainSyncCommandEx::RollForward(block) => self.reduce_block(&block)?,
-            model::ChainSyncCommandEx::RollBack(point) => {
+            model::EnrichedBlockPayload::RollForward(block, ctx) => {
+                self.reduce_block(&block, &ctx)?
+            }
+            model::EnrichedBlockPayload::RollBack(point) => {
                 log::warn!("rollback requested for {:?}", point);
+
+                let at = format!("rollback to {:?}", point);
+
+                for entry in self.reducers.iter_mut().filter(|x| !x.disabled) {
+                    let port = match entry.route {
+                        Some(route) => &mut self.routes[route],
+                        None => &mut self.output,
+                    };
+
+                    let template = entry.template.clone();
+                    let output = &mut OutputPort::new(port, template.as_deref());
+
+                    match self.isolate {
+                        true => {
+                            let rollback = |x: &mut Plugin| x.rollback(&point, output);
+
+                            // failed rollbacks have no block to keep
+                            let _ = entry.isolated(
+                                &self.reducer_errors,
+                                self.max_failures,
+                                &at,
+                                rollback,
+                            );
+                        }
+                        false => entry.plugin.rollback(&point, output)?,
+                    };
+                }
+
+                let depth = self.rollback_depth(&point);
+
+                if let Some(notifier) = &self.notifier {
+                    notifier.notify(&point, depth);
+                }
             }
         }
 
         Ok(WorkOutcome::Partial)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_history_undoes_blocks_after_point() {
+        let mut history = RollbackHistory::new();
+
+        for slot in [10, 20, 30, 40] {
+            history.push(slot, slot * 2);
+        }
+
+        let undone = history.rollback(&Point::Specific(20, vec![]));
+        assert_eq!(undone, vec![(40, 80), (30, 60)]);
+        assert_eq!(history.len(), 2);
+
+        assert!(history.rollback(&Point::Specific(25, vec![])).is_empty());
+
+        let undone = history.rollback(&Point::Origin);
+        assert_eq!(undone, vec![(20, 40), (10, 20)]);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn rollback_history_forgets_oldest_blocks() {
+        let mut history = RollbackHistory::new();
+
+        for slot in 0..(MAX_TRACKED_BLOCKS as u64 + 5) {
+            history.push(slot, ());
+        }
+
+        assert_eq!(history.len(), MAX_TRACKED_BLOCKS);
+        assert_eq!(history.iter().next().map(|(slot, _)| *slot), Some(5));
+    }
+
+    #[test]
+    fn panicking_reducer_is_isolated_from_a_healthy_one() {
+        use crate::testing::{address, block};
+
+        let entry = |plugin| Entry {
+            name: "test",
+            plugin,
+            sampler: None,
+            failures: 0,
+            disabled: false,
+            route: None,
+            template: None,
+        };
+
+        let counter = |prefix: &str| {
+            total_transactions_count::Config {
+                key_prefix: Some(prefix.to_string()),
+            }
+            .plugin()
+        };
+
+        let mut faulty = entry(counter("faulty"));
+        let mut healthy = entry(counter("healthy"));
+
+        let mut port = StagePort::default();
+        let mut received = gasket::messaging::InputPort::<CRDTCommand>::default();
+        gasket::messaging::connect_ports(&mut port, &mut received, 100);
+
+        let errors = gasket::metrics::Counter::default();
+        let built = block().tx(|t| t.output(&address(1), 1_000_000)).build();
+        let ctx = BlockContext::default();
+
+        for _ in 0..3 {
+            if !faulty.disabled {
+                let result = faulty.isolated(&errors, Some(2), "slot 1", |_| {
+                    panic!("deliberate failure");
+                });
+
+                assert_eq!(result, Err("panic: deliberate failure".to_string()));
+            }
+
+            let output = &mut OutputPort::new(&mut port, None);
+            let reduce = |x: &mut Plugin| x.reduce_block(&built, &ctx, output);
+            assert!(healthy.isolated(&errors, Some(2), "slot 1", reduce).is_ok());
+        }
+
+        // disabled after the second failure, the third block never reached it
+        assert!(faulty.disabled);
+        assert_eq!(faulty.failures, 2);
+        assert_eq!(errors.get(), 2);
+
+        assert!(!healthy.disabled);
+
+        for _ in 0..3 {
+            match received.try_recv().unwrap().payload {
+                CRDTCommand::PNCounter(key, value) => {
+                    assert_eq!((key.as_str(), value.as_str()), ("healthy", "1"))
+                }
+                _ => panic!("unexpected command"),
+            }
+        }
+    }
+
+    #[test]
+    fn key_template_applies_to_applies_and_undos() {
+        use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+        let config = richest_addresses::Config {
+            key_prefix: None,
+            size: 1,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let template = crosscut::KeyTemplate::parse("rich:{key}").unwrap();
+
+        let mut harness =
+            Harness::with_key_templates(vec![config.plugin(&chain)], vec![Some(template)]);
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funding, 0).output(&address(2), 4_800_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        let balance = |seed| format!("rich:balance.{}", address(seed));
+
+        {
+            let store = harness.store();
+            assert_eq!(store.counter(&balance(2)), Some(4_800_000));
+            assert!(store.scores("rich:balances").is_some());
+            assert!(store
+                .counter(&format!("richest_addresses.balance.{}", address(2)))
+                .is_none());
+
+            // keys without anything after the prefix are left as they are
+            let top = store.scores("richest_addresses").unwrap();
+            assert_eq!(top.get(&address(2)), Some(&4_800_000));
+        }
+
+        harness.roll_back(first);
+
+        let store = harness.store();
+        assert_eq!(store.counter(&balance(1)), Some(5_000_000));
+        assert_eq!(store.counter(&balance(2)), Some(0));
+
+        let top = store.scores("richest_addresses").unwrap();
+        assert_eq!(top.get(&address(1)), Some(&5_000_000));
+        assert_eq!(top.len(), 1);
+    }
+
+    #[test]
+    fn epoch_boundary_blocks_move_the_cursor_without_reducing() {
+        use crate::testing::{address, block, epoch_boundary, Harness, DEFAULT_SLOT};
+
+        let stats = block_stats::Config { key_prefix: None }.plugin();
+        let mut harness = Harness::new(vec![stats]);
+
+        let ebb = epoch_boundary(1);
+        let point = harness.roll_forward(ebb);
+
+        // the first block of byron epoch 1 is at slot 21600
+        assert!(matches!(point, Point::Specific(21_600, _)));
+        assert_eq!(
+            harness.store().cursor().map(|x| x.to_string()),
+            Some(crosscut::PointArg::from(point).to_string())
+        );
+        assert!(harness.store().scores("block_stats").is_none());
+
+        let next = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        harness.roll_forward(next);
+
+        let scores = harness.store().scores("block_stats").unwrap().clone();
+        assert_eq!(
+            scores.values().copied().collect::<Vec<_>>(),
+            vec![DEFAULT_SLOT as i64]
+        );
+    }
+
+    #[test]
+    fn failed_block_of_an_isolated_reducer_goes_to_the_dead_letter_sink() {
+        use gasket::runtime::Worker as _;
+
+        use crate::testing::{address, block, tx_hash};
+
+        let path =
+            std::env::temp_dir().join(format!("scrolls-dead-letter-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = crosscut::DeadLetterConfig::File {
+            path: path.to_string_lossy().to_string(),
+            max_records: None,
+        }
+        .sink()
+        .unwrap();
+
+        let policy = crosscut::RuntimePolicy {
+            isolate_reducers: Some(true),
+            ..Default::default()
+        };
+
+        // bech32 refuses an empty prefix, the reducer fails on any output
+        let chain = crosscut::ChainWellKnownInfo {
+            address_hrp: String::new(),
+            ..crosscut::ChainWellKnownInfo::mainnet()
+        };
+
+        let reducer = utxo_by_address::Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Bech32),
+            addresses: None,
+        }
+        .plugin(&chain);
+
+        let mut worker = Worker::new(
+            vec![("UtxoByAddress", reducer, None)],
+            crosscut::Filters::default(),
+            None,
+            &policy,
+        )
+        .with_dead_letter(sink);
+
+        let mut source = gasket::messaging::OutputPort::default();
+        let mut storage = gasket::messaging::InputPort::<CRDTCommand>::default();
+        gasket::messaging::connect_ports(&mut source, worker.borrow_input_port(), 10);
+        gasket::messaging::connect_ports(worker.borrow_output_port(), &mut storage, 10);
+
+        let failing = block()
+            .tx(|t| t.output(&address(1), 1_000_000))
+            .tx(|t| t.output(&address(2), 2_000_000))
+            .build();
+
+        let cbor = hex::encode(failing.encode().unwrap());
+        let txs = vec![
+            tx_hash(&failing, 0).to_string(),
+            tx_hash(&failing, 1).to_string(),
+        ];
+
+        source
+            .send(model::EnrichedBlockPayload::roll_forward(
+                Arc::new(failing),
+                BlockContext::default(),
+            ))
+            .unwrap();
+
+        // the failure is isolated, the block still goes through
+        worker.work().unwrap();
+        assert_eq!(worker.reducer_errors.get(), 1);
+
+        let records = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = records
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["reducer"], "UtxoByAddress");
+        assert_eq!(records[0]["point"]["slot"], crate::testing::DEFAULT_SLOT);
+        assert_eq!(records[0]["txs"], serde_json::json!(txs));
+        assert_eq!(records[0]["cbor"], cbor);
+    }
+}
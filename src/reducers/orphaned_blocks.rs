@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gasket::error::AsWorkError;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Keeps a log of the blocks undone by rollbacks, for reorg forensics
+///
+/// Each orphaned block adds a JSON member to the sorted set `{prefix}`,
+/// scored by its slot, with its hash, the rollback point, the depth of the
+/// rollback in blocks and the unix time at which it was observed. Unlike chain
+/// data these entries are never undone, the set is a permanent record of the
+/// abandoned forks seen by this process.
+pub struct Reducer {
+    config: Config,
+    recent: super::RollbackHistory<String>,
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "orphaned_blocks".to_string(),
+        }
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        _output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let hash = match block.point().or_work_err()? {
+            Point::Specific(_, hash) => hex::encode(hash),
+            Point::Origin => return Ok(()),
+        };
+
+        self.recent.push(block.slot(), hash);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let target = match point {
+            Point::Origin => "origin".to_string(),
+            Point::Specific(slot, hash) => format!("{},{}", slot, hex::encode(hash)),
+        };
+
+        let orphans = self.recent.rollback(point);
+
+        let observed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        let depth = orphans.len();
+
+        for (orphan_slot, hash) in orphans {
+            log::warn!("block {} at slot {} orphaned", hash, orphan_slot);
+
+            let entry = json!({
+                "hash": hash,
+                "slot": orphan_slot,
+                "rollback_to": target,
+                "depth": depth,
+                "observed_at": observed_at,
+            });
+
+            let crdt =
+                model::CRDTCommand::LastWriteWins(self.key(), entry.to_string(), orphan_slot);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::OrphanedBlocks(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crosscut;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn rollback_of_two_blocks_records_both() {
+        let mut harness = Harness::new(vec![Config { key_prefix: None }.plugin()]);
+
+        let first = harness.roll_forward(block().build());
+
+        let orphans: Vec<_> = (2..=3)
+            .map(|number| {
+                let block = block()
+                    .slot(DEFAULT_SLOT + number * 20)
+                    .number(number)
+                    .build();
+
+                match harness.roll_forward(block) {
+                    Point::Specific(slot, hash) => (slot, hex::encode(hash)),
+                    Point::Origin => unreachable!("built blocks have a point"),
+                }
+            })
+            .collect();
+
+        assert!(harness.store().scores("orphaned_blocks").is_none());
+
+        let target = crosscut::PointArg::from(first.clone()).to_string();
+        harness.roll_back(first);
+
+        let entries = harness.store().scores("orphaned_blocks").cloned().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        for (slot, hash) in orphans {
+            let entry = entries
+                .iter()
+                .map(|(x, score)| (serde_json::from_str::<serde_json::Value>(x).unwrap(), score))
+                .find(|(x, _)| x["hash"] == hash.as_str())
+                .unwrap();
+
+            assert_eq!(*entry.1, slot as i64);
+            assert_eq!(entry.0["slot"], slot);
+            assert_eq!(entry.0["depth"], 2);
+            assert_eq!(entry.0["rollback_to"], target);
+        }
+    }
+}
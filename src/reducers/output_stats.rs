@@ -0,0 +1,278 @@
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// A data point per block
+    #[default]
+    Block,
+    /// A pair of counters per epoch
+    Epoch,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub granularity: Option<Granularity>,
+}
+
+/// Outputs created by a single block
+#[derive(Serialize)]
+struct BlockOutputs {
+    hash: String,
+    output_count: u64,
+    average_output: u64,
+}
+
+/// Time series of the number of outputs and their average lovelace value
+///
+/// Per block, a JSON data point is added to the sorted set `{prefix}`, scored
+/// by slot. Per epoch, counters are kept under `{prefix}.{epoch}.output_count`
+/// and `{prefix}.{epoch}.output_lovelace`; the average is left to the clients
+/// since the storage can only increment the counters.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<(u64, u128)>,
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "output_stats",
+        }
+    }
+
+    fn send_epoch_counters(
+        &self,
+        slot: u64,
+        count: i128,
+        lovelace: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        for (name, value) in [("output_count", count), ("output_lovelace", lovelace)] {
+            let crdt = model::CRDTCommand::PNCounter(
+                format!("{}.{}.{}", self.prefix(), epoch, name),
+                model::clamp_delta(value).to_string(),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    fn send_stats(
+        &mut self,
+        slot: u64,
+        hash: String,
+        lovelace: impl Iterator<Item = u64>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let (count, total) = lovelace.fold((0u64, 0u128), |(count, total), x| {
+            (count + 1, total + x as u128)
+        });
+
+        if count == 0 {
+            return Ok(());
+        }
+
+        match self.config.granularity.unwrap_or_default() {
+            Granularity::Block => {
+                let stats = BlockOutputs {
+                    hash,
+                    output_count: count,
+                    average_output: (total / count as u128) as u64,
+                };
+
+                let value = serde_json::to_string(&stats).expect("output stats are serializable");
+
+                let crdt =
+                    model::CRDTCommand::LastWriteWins(self.prefix().to_string(), value, slot);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+            Granularity::Epoch => {
+                self.send_epoch_counters(slot, count as i128, total as i128, output)?;
+            }
+        }
+
+        self.recent.push(slot, (count, total));
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let lovelace = x
+                    .body
+                    .tx_payload
+                    .iter()
+                    .flat_map(|tx| tx.transaction.outputs.iter())
+                    .map(|o| o.amount);
+
+                let slot = x.header.consensus_data.0.to_abs_slot();
+                let hash = x.header.to_hash().to_string();
+
+                self.send_stats(slot, hash, lovelace, output)
+            }
+            model::MultiEraBlock::Byron(_) => Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let lovelace =
+                    x.1.transaction_bodies
+                        .iter()
+                        .flat_map(|tx| tx.iter())
+                        .filter_map(|c| match c {
+                            alonzo::TransactionBodyComponent::Outputs(o) => Some(o),
+                            _ => None,
+                        })
+                        .flat_map(|o| o.iter())
+                        .map(model::alonzo_lovelace);
+
+                let slot = x.1.header.header_body.slot;
+                let hash = alonzo::crypto::hash_block_header(&x.1.header).to_string();
+
+                self.send_stats(slot, hash, lovelace, output)
+            }
+        }
+    }
+
+    /// Removes the data points of the blocks after the rollback point, or
+    /// subtracts them from the counters of their epochs
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, (count, total)) in self.recent.rollback(point) {
+            match self.config.granularity.unwrap_or_default() {
+                Granularity::Block => {
+                    let crdt = model::CRDTCommand::SortedSetRemoveRange(
+                        self.prefix().to_string(),
+                        applied,
+                        applied,
+                    );
+
+                    output.send(gasket::messaging::Message::from(crdt))?;
+                }
+                Granularity::Epoch => {
+                    self.send_epoch_counters(applied, -(count as i128), -(total as i128), output)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::OutputStats(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    fn harness(granularity: Granularity) -> Harness {
+        let config = Config {
+            key_prefix: None,
+            granularity: Some(granularity),
+        };
+
+        Harness::new(vec![config.plugin(&crosscut::ChainWellKnownInfo::mainnet())])
+    }
+
+    #[test]
+    fn block_data_point_averages_its_outputs() {
+        let mut harness = harness(Granularity::Block);
+
+        let first = harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.output(&address(1), 1_000_000)
+                        .output(&address(2), 2_000_000)
+                })
+                .tx(|t| t.output(&address(3), 6_000_000))
+                .build(),
+        );
+
+        let points = |harness: &Harness| {
+            harness
+                .store()
+                .scores("output_stats")
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let latest = points(&harness)
+            .into_iter()
+            .find(|(_, slot)| *slot == (DEFAULT_SLOT + 20) as i64)
+            .map(|(x, _)| serde_json::from_str::<serde_json::Value>(&x).unwrap())
+            .unwrap();
+
+        assert_eq!(latest["output_count"], 3);
+        assert_eq!(latest["average_output"], 3_000_000);
+        assert_eq!(points(&harness).len(), 2);
+
+        harness.roll_back(first);
+
+        let remaining: Vec<_> = points(&harness).into_values().collect();
+        assert_eq!(remaining, vec![DEFAULT_SLOT as i64]);
+    }
+
+    #[test]
+    fn epoch_counters_add_up_and_roll_back() {
+        let mut harness = harness(Granularity::Epoch);
+        let epoch = crosscut::ChainWellKnownInfo::mainnet().epoch_for_slot(DEFAULT_SLOT);
+
+        let first = harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.output(&address(1), 2_000_000)
+                        .output(&address(2), 3_000_000)
+                })
+                .build(),
+        );
+
+        let counter = |harness: &Harness, name| {
+            let key = format!("output_stats.{}.{}", epoch, name);
+            harness.store().counter(&key)
+        };
+
+        assert_eq!(counter(&harness, "output_count"), Some(3));
+        assert_eq!(counter(&harness, "output_lovelace"), Some(6_000_000));
+
+        harness.roll_back(first);
+
+        assert_eq!(counter(&harness, "output_count"), Some(1));
+        assert_eq!(counter(&harness, "output_lovelace"), Some(1_000_000));
+    }
+}
@@ -48,8 +48,7 @@ impl Reducer {
                     .tx_payload
                     .iter()
                     .map(|tx| tx.transaction.to_hash())
-                    .map(|tx| self.send_set_add(tx, slot, hash, output))
-                    .collect()
+                    .try_for_each(|tx| self.send_set_add(tx, slot, hash, output))
             }
             model::MultiEraBlock::Byron(_) => Ok(()),
             model::MultiEraBlock::AlonzoCompatible(x) => {
@@ -59,8 +58,7 @@ impl Reducer {
                 x.1.transaction_bodies
                     .iter()
                     .map(|tx| tx.to_hash())
-                    .map(|tx| self.send_set_add(tx, slot, hash, output))
-                    .collect()
+                    .try_for_each(|tx| self.send_set_add(tx, slot, hash, output))
             }
         }
     }
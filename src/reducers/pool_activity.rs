@@ -0,0 +1,192 @@
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hasher;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::model;
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// A block produced by a pool: the pool id and the slot of the previous block
+/// of the pool, if any
+type Produced = (String, Option<u64>);
+
+/// Tracks the slot of the first and of the latest block produced by each pool
+///
+/// Pool ids are members of the sorted sets `{prefix}.first` and
+/// `{prefix}.last`, scored by slot, so dormant pools are the low end of the
+/// latter. The pool id is the hash of the block issuer key. The latest slot of
+/// each pool is kept in the state db to tell its first block apart.
+pub struct Reducer {
+    config: Config,
+    last: state::Map<u64>,
+    recent: state::History<Produced>,
+}
+
+impl Reducer {
+    fn key(&self, name: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, name),
+            None => format!("pool_activity.{}", name),
+        }
+    }
+
+    fn send_slot(
+        &self,
+        name: &str,
+        pool: &str,
+        slot: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = model::CRDTCommand::LastWriteWins(self.key(name), pool.to_string(), slot);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    /// Removes the member scored with the slot, a single block (and pool) can
+    /// hold a given slot
+    fn send_removal(
+        &self,
+        name: &str,
+        slot: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = model::CRDTCommand::SortedSetRemoveRange(self.key(name), slot, slot);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    fn undo(&mut self, produced: Produced) -> Result<(), crate::Error> {
+        let (pool, previous) = produced;
+        self.last.restore(&pool, previous.as_ref())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let header = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => &x.1.header.header_body,
+        };
+
+        // changes left by a run that stopped before the storage committed
+        for (_, produced) in self.recent.rewind(header.slot).or_work_err()? {
+            self.undo(produced).or_work_err()?;
+        }
+
+        let slot = header.slot;
+        let pool = Hasher::<224>::hash(&header.issuer_vkey).to_string();
+
+        let previous = self.last.insert(&pool, &slot).or_work_err()?;
+
+        if previous.is_none() {
+            self.send_slot("first", &pool, slot, output)?;
+        }
+
+        self.send_slot("last", &pool, slot, output)?;
+
+        self.recent.push(slot, &(pool, previous)).or_work_err()?;
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, produced) in self.recent.rollback(point).or_work_err()? {
+            match produced {
+                (ref pool, Some(previous)) => {
+                    self.send_slot("last", pool, previous, output)?;
+                }
+                (_, None) => {
+                    self.send_removal("first", applied, output)?;
+                    self.send_removal("last", applied, output)?;
+                }
+            }
+
+            self.undo(produced).or_work_err()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, state: &state::Db) -> Result<super::Plugin, crate::Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "pool_activity".to_string(),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            last: state.map(&format!("{}.last", name))?,
+            recent: state.history(&name)?,
+        };
+
+        Ok(super::Plugin::PoolActivity(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, state_db, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        Config { key_prefix: None }.plugin(state).unwrap()
+    }
+
+    fn slot(harness: &Harness, name: &str) -> Option<i64> {
+        let pool = Hasher::<224>::hash(&[0; 32]).to_string();
+        let store = harness.store();
+        let key = format!("pool_activity.{}", name);
+        store.scores(&key).and_then(|x| x.get(&pool).copied())
+    }
+
+    #[test]
+    fn rollback_restores_the_latest_block_of_the_pool() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = harness.roll_forward(block().build());
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 20).number(2).build());
+
+        assert_eq!(slot(&harness, "first"), Some(DEFAULT_SLOT as i64));
+        assert_eq!(slot(&harness, "last"), Some(DEFAULT_SLOT as i64 + 20));
+
+        harness.roll_back(first);
+
+        assert_eq!(slot(&harness, "first"), Some(DEFAULT_SLOT as i64));
+        assert_eq!(slot(&harness, "last"), Some(DEFAULT_SLOT as i64));
+    }
+
+    #[test]
+    fn first_block_of_a_previous_run_is_kept() {
+        let state = state_db();
+        let mut harness = Harness::new(vec![reducer(&state)]);
+
+        let first = harness.roll_forward(block().build());
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 20).number(2).build());
+
+        assert_eq!(slot(&harness, "first"), Some(DEFAULT_SLOT as i64));
+        assert_eq!(slot(&harness, "last"), Some(DEFAULT_SLOT as i64 + 20));
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        assert_eq!(slot(&harness, "last"), Some(DEFAULT_SLOT as i64));
+    }
+}
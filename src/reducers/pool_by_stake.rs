@@ -56,8 +56,7 @@ impl Reducer {
                 alonzo::Certificate::StakeDelegation(cred, pool) => Some((cred, pool)),
                 _ => None,
             })
-            .map(|(cred, pool)| self.send_key_write(cred, pool, slot, output))
-            .collect()
+            .try_for_each(|(cred, pool)| self.send_key_write(cred, pool, slot, output))
     }
 
     pub fn reduce_block(
@@ -67,14 +66,11 @@ impl Reducer {
     ) -> Result<(), gasket::error::Error> {
         match block {
             model::MultiEraBlock::Byron(_) => Ok(()),
-            model::MultiEraBlock::AlonzoCompatible(block) => block
-                .1
-                .transaction_bodies
-                .iter()
-                .map(|tx| {
+            model::MultiEraBlock::AlonzoCompatible(block) => {
+                block.1.transaction_bodies.iter().try_for_each(|tx| {
                     self.reduce_alonzo_compatible_tx(block.1.header.header_body.slot, tx, output)
                 })
-                .collect(),
+            }
         }
     }
 }
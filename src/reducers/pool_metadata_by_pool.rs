@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// The off-chain metadata anchor declared by a pool registration
+#[derive(Serialize)]
+struct MetadataAnchor<'a> {
+    url: &'a str,
+    hash: String,
+}
+
+/// Anchor written under a key and the slot of its registration
+type Anchor = (String, u64);
+
+/// Keeps the anchors of each pool in a sorted set scored by slot
+///
+/// A rollback removes the anchors of the reverted blocks and writes back the
+/// anchor that each pool had before them, in case a reverted re-registration
+/// repeated it (which moves the existing member to the newer slot). Only the
+/// anchors seen by this process can be restored that way.
+pub struct Reducer {
+    config: Config,
+    latest: HashMap<String, Anchor>,
+    recent: super::RollbackHistory<Vec<(String, Option<Anchor>)>>,
+}
+
+impl Reducer {
+    fn send_key_write(
+        &mut self,
+        pool: &alonzo::PoolKeyhash,
+        metadata: &alonzo::PoolMetadata,
+        slot: u64,
+        output: &mut super::OutputPort,
+        applied: &mut Vec<(String, Option<Anchor>)>,
+    ) -> Result<(), gasket::error::Error> {
+        let key = match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, pool),
+            None => pool.to_string(),
+        };
+
+        let anchor = MetadataAnchor {
+            url: &metadata.url,
+            hash: metadata.hash.to_string(),
+        };
+
+        let value = serde_json::to_string(&anchor).expect("metadata anchor is serializable");
+
+        // re-registrations are written with a higher slot, the latest anchor is the
+        // one with the highest score
+        let crdt = model::CRDTCommand::LastWriteWins(key.clone(), value.clone(), slot);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        let previous = self.latest.insert(key.clone(), (value, slot));
+        applied.push((key, previous));
+
+        Ok(())
+    }
+
+    fn reduce_alonzo_compatible_tx(
+        &mut self,
+        slot: u64,
+        tx: &alonzo::TransactionBody,
+        output: &mut super::OutputPort,
+        applied: &mut Vec<(String, Option<Anchor>)>,
+    ) -> Result<(), gasket::error::Error> {
+        tx.iter()
+            .filter_map(|b| match b {
+                alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                _ => None,
+            })
+            .flat_map(|c| c.iter())
+            .filter_map(|c| match c {
+                alonzo::Certificate::PoolRegistration {
+                    operator,
+                    pool_metadata: Some(metadata),
+                    ..
+                } => Some((operator, metadata)),
+                _ => None,
+            })
+            .try_for_each(|(pool, metadata)| {
+                self.send_key_write(pool, metadata, slot, output, applied)
+            })
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let block = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(block) => block,
+        };
+
+        let slot = block.1.header.header_body.slot;
+        let mut applied = Vec::new();
+
+        for tx in block.1.transaction_bodies.iter() {
+            self.reduce_alonzo_compatible_tx(slot, tx, output, &mut applied)?;
+        }
+
+        self.recent.push(slot, applied);
+
+        Ok(())
+    }
+
+    /// Removes the anchors of the blocks after the rollback point and
+    /// restores the previous anchor of their pools
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (slot, applied) in self.recent.rollback(point) {
+            for (key, previous) in applied.into_iter().rev() {
+                let crdt = model::CRDTCommand::SortedSetRemoveRange(key.clone(), slot, slot);
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                match previous {
+                    Some((value, previous_slot)) => {
+                        let crdt = model::CRDTCommand::LastWriteWins(
+                            key.clone(),
+                            value.clone(),
+                            previous_slot,
+                        );
+
+                        output.send(gasket::messaging::Message::from(crdt))?;
+                        self.latest.insert(key, (value, previous_slot));
+                    }
+                    None => {
+                        self.latest.remove(&key);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            latest: HashMap::new(),
+            recent: super::RollbackHistory::new(),
+        };
+        super::Plugin::PoolMetadataByPool(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, pool_registration, Harness, DEFAULT_SLOT};
+
+    fn register(slot: u64, number: u64, url: &str) -> model::MultiEraBlock {
+        block()
+            .slot(slot)
+            .number(number)
+            .tx(|t| t.cert(pool_registration(7, Some(url))))
+            .build()
+    }
+
+    fn urls(harness: &Harness) -> Vec<(String, i64)> {
+        let key = hex::encode([7; 28]);
+
+        harness
+            .store()
+            .scores(&key)
+            .unwrap()
+            .iter()
+            .map(|(anchor, slot)| {
+                let anchor: serde_json::Value = serde_json::from_str(anchor).unwrap();
+                (anchor["url"].as_str().unwrap().to_string(), *slot)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rollback_restores_the_prior_anchor() {
+        let mut harness = Harness::new(vec![Config { key_prefix: None }.plugin()]);
+
+        let first = harness.roll_forward(register(DEFAULT_SLOT, 1, "https://a"));
+        harness.roll_forward(register(DEFAULT_SLOT + 20, 2, "https://b"));
+        harness.roll_forward(register(DEFAULT_SLOT + 40, 3, "https://a"));
+
+        assert_eq!(
+            urls(&harness),
+            vec![
+                ("https://a".to_string(), DEFAULT_SLOT as i64 + 40),
+                ("https://b".to_string(), DEFAULT_SLOT as i64 + 20),
+            ]
+        );
+
+        harness.roll_back(first);
+
+        assert_eq!(
+            urls(&harness),
+            vec![("https://a".to_string(), DEFAULT_SLOT as i64)]
+        );
+    }
+}
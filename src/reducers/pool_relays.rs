@@ -0,0 +1,227 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Records the relays declared by each stake pool
+///
+/// Every registration writes the JSON list of relays of the pool into the
+/// sorted set `{prefix}.{pool_id}`, scored by slot, so the current relays are
+/// the member with the highest score and re-registrations take over the
+/// previous ones.
+pub struct Reducer {
+    config: Config,
+    recent: super::RollbackHistory<Vec<String>>,
+}
+
+fn ip_address(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes)
+            .ok()
+            .map(|x| Ipv4Addr::from(x).to_string()),
+        16 => <[u8; 16]>::try_from(bytes)
+            .ok()
+            .map(|x| Ipv6Addr::from(x).to_string()),
+        _ => None,
+    }
+}
+
+fn relay_json(relay: &alonzo::Relay) -> Value {
+    match relay {
+        alonzo::Relay::SingleHostAddr(port, ipv4, ipv6) => json!({
+            "type": "single_host_addr",
+            "port": port,
+            "ipv4": ipv4.as_ref().and_then(|x| ip_address(&x[..])),
+            "ipv6": ipv6.as_ref().and_then(|x| ip_address(&x[..])),
+        }),
+        alonzo::Relay::SingleHostName(port, dns) => json!({
+            "type": "single_host_name",
+            "port": port,
+            "dns": dns,
+        }),
+        alonzo::Relay::MultiHostName(dns) => json!({
+            "type": "multi_host_name",
+            "dns": dns,
+        }),
+    }
+}
+
+impl Reducer {
+    fn key(&self, pool: &alonzo::PoolKeyhash) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, pool),
+            None => format!("pool_relays.{}", pool),
+        }
+    }
+
+    fn reduce_alonzo_compatible_tx(
+        &mut self,
+        slot: u64,
+        tx: &alonzo::TransactionBody,
+        keys: &mut Vec<String>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let registrations = tx
+            .iter()
+            .filter_map(|b| match b {
+                alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                _ => None,
+            })
+            .flat_map(|c| c.iter())
+            .filter_map(|c| match c {
+                alonzo::Certificate::PoolRegistration {
+                    operator, relays, ..
+                } => Some((operator, relays)),
+                _ => None,
+            });
+
+        for (pool, relays) in registrations {
+            let value: Vec<_> = relays.iter().map(relay_json).collect();
+            let key = self.key(pool);
+
+            let crdt = model::CRDTCommand::LastWriteWins(
+                key.clone(),
+                Value::from(value).to_string(),
+                slot,
+            );
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            keys.push(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let block = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = block.1.header.header_body.slot;
+        let mut keys = Vec::new();
+
+        for tx in block.1.transaction_bodies.iter() {
+            self.reduce_alonzo_compatible_tx(slot, tx, &mut keys, output)?;
+        }
+
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        self.recent.push(slot, keys);
+
+        Ok(())
+    }
+
+    /// Removes the registrations of the blocks after the rollback point, which
+    /// brings back the relays declared before them
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, keys) in self.recent.rollback(point) {
+            for key in keys {
+                let crdt = model::CRDTCommand::SortedSetRemoveRange(key, applied, applied);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::PoolRelays(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::codec::utils::MaybeIndefArray;
+
+    use super::*;
+    use crate::testing::{address, block, pool_registration, Harness, DEFAULT_SLOT};
+
+    fn registration(relays: Vec<alonzo::Relay>) -> alonzo::Certificate {
+        let mut cert = pool_registration(7, None);
+
+        if let alonzo::Certificate::PoolRegistration { relays: x, .. } = &mut cert {
+            *x = MaybeIndefArray::Def(relays);
+        }
+
+        cert
+    }
+
+    /// The latest relays of the pool, as the member with the highest score
+    fn current(harness: &Harness) -> Value {
+        let key = format!("pool_relays.{}", "07".repeat(28));
+        let store = harness.store();
+        let scores = store.scores(&key).unwrap();
+        let (latest, _) = scores.iter().max_by_key(|(_, x)| **x).unwrap();
+
+        serde_json::from_str(latest).unwrap()
+    }
+
+    #[test]
+    fn re_registration_replaces_the_relays_until_rolled_back() {
+        let mut harness = Harness::new(vec![Config { key_prefix: None }.plugin()]);
+
+        let relays = vec![
+            alonzo::Relay::SingleHostAddr(Some(3001), Some(vec![10, 0, 0, 1].into()), None),
+            alonzo::Relay::SingleHostName(Some(3002), "relay.pool.io".to_string()),
+        ];
+
+        let first = harness.roll_forward(
+            block()
+                .tx(|t| t.output(&address(1), 1_000_000).cert(registration(relays)))
+                .build(),
+        );
+
+        let declared = json!([
+            { "type": "single_host_addr", "port": 3001, "ipv4": "10.0.0.1", "ipv6": null },
+            { "type": "single_host_name", "port": 3002, "dns": "relay.pool.io" },
+        ]);
+
+        assert_eq!(current(&harness), declared);
+
+        let relays = vec![alonzo::Relay::MultiHostName("pool.io".to_string())];
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| t.output(&address(2), 1_000_000).cert(registration(relays)))
+                .build(),
+        );
+
+        assert_eq!(
+            current(&harness),
+            json!([{ "type": "multi_host_name", "dns": "pool.io" }])
+        );
+
+        harness.roll_back(first);
+
+        assert_eq!(current(&harness), declared);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, byron, Fragment};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+/// What gets stored for the matching txs
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// The body of each matching tx, keyed by tx hash
+    #[default]
+    Tx,
+    /// The whole block holding a matching tx, keyed by point
+    Block,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub granularity: Option<Granularity>,
+
+    /// Only store txs that pay to or spend from these addresses (bech32 or
+    /// hex), every tx when missing
+    pub addresses: Option<Vec<String>>,
+}
+
+/// Stores the raw CBOR of txs, or of blocks, for later re-parsing
+///
+/// An escape hatch for fields that no reducer exposes yet. Values are hex
+/// CBOR under `{prefix}.{tx_hash}` for the body of each tx, or under
+/// `{prefix}.{slot},{block_hash}` for whole blocks. The CBOR is encoded again
+/// from the decoded block, it decodes to the same data but may differ from
+/// the bytes sent by the node. Matching spent inputs requires the enrichment
+/// stage, outputs always match.
+pub struct Reducer {
+    config: Config,
+    allowlist: Option<HashSet<Vec<u8>>>,
+    recent: super::RollbackHistory<Vec<String>>,
+}
+
+/// Raw address of a resolved utxo, as compared against the allowlist
+fn output_address(utxo: &model::MultiEraOutput) -> Result<Vec<u8>, Error> {
+    match utxo {
+        model::MultiEraOutput::Byron(x) => Ok(x.address.encode_fragment()?),
+        model::MultiEraOutput::AlonzoCompatible(x) => Ok(x.address.to_vec()),
+    }
+}
+
+impl Reducer {
+    fn key(&self, id: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, id),
+            None => format!("raw_cbor.{}", id),
+        }
+    }
+
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        crosscut::Allowlists {
+            addresses: self.allowlist.clone(),
+            policies: None,
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        self.allowlist = allowlists.addresses;
+    }
+
+    fn spends_allowed(
+        &self,
+        allowed: &HashSet<Vec<u8>>,
+        utxo: Option<&model::MultiEraOutput>,
+    ) -> Result<bool, Error> {
+        match utxo {
+            Some(utxo) => Ok(allowed.contains(&output_address(utxo)?)),
+            None => Ok(false),
+        }
+    }
+
+    fn byron_tx_matches(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+    ) -> Result<bool, Error> {
+        let allowed = match &self.allowlist {
+            Some(x) => x,
+            None => return Ok(true),
+        };
+
+        for output in tx.transaction.outputs.iter() {
+            if allowed.contains(&output.address.encode_fragment()?) {
+                return Ok(true);
+            }
+        }
+
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+
+                if self.spends_allowed(allowed, ctx.find_utxo(hash, *idx as u64))? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn alonzo_tx_matches(
+        &self,
+        tx: &alonzo::TransactionBody,
+        ctx: &model::BlockContext,
+    ) -> Result<bool, Error> {
+        let allowed = match &self.allowlist {
+            Some(x) => x,
+            None => return Ok(true),
+        };
+
+        for component in tx.iter() {
+            match component {
+                alonzo::TransactionBodyComponent::Outputs(outputs)
+                    if outputs
+                        .iter()
+                        .any(|x| allowed.contains(x.address.as_slice())) =>
+                {
+                    return Ok(true);
+                }
+                alonzo::TransactionBodyComponent::Inputs(inputs) => {
+                    for input in inputs.iter() {
+                        let utxo = ctx.find_utxo(&input.transaction_id, input.index);
+
+                        if self.spends_allowed(allowed, utxo)? {
+                            return Ok(true);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Hash and body CBOR of the txs of the block that match the allowlist
+    fn matching_txs(
+        &self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut found = Vec::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    if self.byron_tx_matches(tx, ctx)? {
+                        let cbor = tx.transaction.encode_fragment()?;
+                        found.push((tx.transaction.to_hash().to_string(), cbor));
+                    }
+                }
+            }
+            model::MultiEraBlock::Byron(_) => (),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                for tx in x.1.transaction_bodies.iter() {
+                    if self.alonzo_tx_matches(tx, ctx)? {
+                        found.push((tx.to_hash().to_string(), tx.encode_fragment()?));
+                    }
+                }
+            }
+        };
+
+        Ok(found)
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let txs = self.matching_txs(block, ctx).or_work_err()?;
+
+        if txs.is_empty() {
+            return Ok(());
+        }
+
+        let entries = match self.config.granularity.unwrap_or_default() {
+            Granularity::Tx => txs
+                .into_iter()
+                .map(|(hash, cbor)| (self.key(&hash), hex::encode(cbor)))
+                .collect(),
+            Granularity::Block => {
+                let point = crosscut::PointArg::from(block.point().or_work_err()?);
+                let cbor = block.encode().or_work_err()?;
+                vec![(self.key(&point.to_string()), hex::encode(cbor))]
+            }
+        };
+
+        let mut keys = Vec::new();
+
+        for (key, value) in entries {
+            let crdt = model::CRDTCommand::AnyWriteWins(key.clone(), value);
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            keys.push(key);
+        }
+
+        self.recent.push(block.slot(), keys);
+
+        Ok(())
+    }
+
+    /// Removes the entries stored for the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, keys) in self.recent.rollback(point) {
+            for key in keys {
+                let crdt = model::CRDTCommand::AnyWriteWinsRemove(key);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Decodes the address allowlist into the raw form compared against txs
+    pub fn allowlist(
+        &self,
+        chain: &crosscut::ChainWellKnownInfo,
+    ) -> Result<Option<HashSet<Vec<u8>>>, Error> {
+        let addresses = match &self.addresses {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let parsed = addresses
+            .iter()
+            .map(|x| crosscut::parse_config_address(x, chain))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(parsed))
+    }
+
+    pub fn validate(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<(), Error> {
+        self.allowlist(chain).map(|_| ())
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let allowlist = self
+            .allowlist(chain)
+            .expect("allowlist is validated when loading the config");
+
+        let reducer = Reducer {
+            config: self,
+            allowlist,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::RawCbor(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn reducer(granularity: Granularity) -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            granularity: Some(granularity),
+            addresses: Some(vec![address(1)]),
+        };
+
+        config.plugin(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    #[test]
+    fn cbor_of_matching_txs_decodes_to_the_same_tx() {
+        let mut harness = Harness::new(vec![reducer(Granularity::Tx)]);
+
+        let genesis = harness.roll_forward(block().build());
+
+        let built = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(1), 5_000_000).fee(170_000))
+            .tx(|t| t.output(&address(2), 5_000_000))
+            .build();
+
+        let (matching, other) = (tx_hash(&built, 0), tx_hash(&built, 1));
+        let expected = match &built {
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                x.1.transaction_bodies[0].encode_fragment().unwrap()
+            }
+            _ => unreachable!("built blocks are alonzo"),
+        };
+
+        harness.roll_forward(built);
+
+        let key = format!("raw_cbor.{}", matching);
+        let stored = harness.store().string(&key).map(String::from).unwrap();
+
+        let decoded =
+            alonzo::TransactionBody::decode_fragment(&hex::decode(stored).unwrap()).unwrap();
+        assert_eq!(decoded.to_hash(), matching);
+        assert_eq!(decoded.encode_fragment().unwrap(), expected);
+
+        assert!(harness
+            .store()
+            .string(&format!("raw_cbor.{}", other))
+            .is_none());
+
+        harness.roll_back(genesis);
+
+        assert!(harness.store().string(&key).is_none());
+    }
+
+    #[test]
+    fn blocks_with_a_matching_tx_are_stored_by_point() {
+        let mut harness = Harness::new(vec![reducer(Granularity::Block)]);
+
+        let built = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+
+        let hash = tx_hash(&built, 0);
+        let point = harness.roll_forward(built);
+
+        let key = format!("raw_cbor.{}", crosscut::PointArg::from(point.clone()));
+        let stored = harness.store().string(&key).map(String::from).unwrap();
+
+        let decoded =
+            crate::sources::utils::parse_block_content(&hex::decode(stored).unwrap()).unwrap();
+
+        assert_eq!(tx_hash(&decoded, 0), hash);
+        assert_eq!(decoded.point().unwrap(), point);
+    }
+}
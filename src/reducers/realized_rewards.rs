@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Sums the rewards realized by each stake address through withdrawals
+///
+/// `{prefix}.{stake_address}.total` is the lovelace withdrawn so far and
+/// `{prefix}.{stake_address}.{epoch}` the amount withdrawn during each
+/// epoch, for account statements. Rewards are accounted when withdrawn, not
+/// when earned, and MIR transfers aren't included.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i128>>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str, suffix: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, address, suffix),
+            None => format!("realized_rewards.{}.{}", address, suffix),
+        }
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &HashMap<String, i128>,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, amount) in deltas.iter() {
+            let delta = model::clamp_delta(sign * amount);
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), delta.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot).to_string();
+        let hrp = self.chain.stake_hrp();
+
+        let mut deltas: HashMap<String, i128> = HashMap::new();
+
+        let withdrawals =
+            x.1.transaction_bodies
+                .iter()
+                .flat_map(|tx| tx.iter())
+                .filter_map(|x| match x {
+                    alonzo::TransactionBodyComponent::Withdrawals(w) => Some(w),
+                    _ => None,
+                })
+                .flat_map(|x| x.iter());
+
+        for (account, amount) in withdrawals {
+            if *amount == 0 {
+                continue;
+            }
+
+            let address = crosscut::encode_stake_address(account.as_slice(), &hrp).or_work_err()?;
+
+            for suffix in [epoch.as_str(), "total"] {
+                *deltas.entry(self.key(&address, suffix)).or_insert(0) += *amount as i128;
+            }
+        }
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(slot, deltas);
+
+        Ok(())
+    }
+
+    /// Subtracts the withdrawals of the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::RealizedRewards(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn withdrawals_are_realized_in_the_epoch_of_their_block() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        let next_epoch_slot = DEFAULT_SLOT + chain.shelley_epoch_length as u64;
+        assert_eq!(chain.epoch_for_slot(next_epoch_slot), epoch + 1);
+
+        let mut account = vec![0xe1];
+        account.extend([9; 28]);
+        let address = crosscut::encode_stake_address(&account, &chain.stake_hrp()).unwrap();
+
+        let key = |suffix: String| format!("realized_rewards.{}.{}", address, suffix);
+        let value = |harness: &Harness, suffix: String| harness.store().counter(&key(suffix));
+
+        let first = block()
+            .tx(|t| t.withdrawal(9, 1_000_000))
+            .tx(|t| t.withdrawal(9, 500_000).withdrawal(4, 200_000))
+            .build();
+
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(next_epoch_slot)
+            .number(2)
+            .tx(|t| t.withdrawal(9, 700_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        assert_eq!(value(&harness, epoch.to_string()), Some(1_500_000));
+        assert_eq!(value(&harness, (epoch + 1).to_string()), Some(700_000));
+        assert_eq!(value(&harness, "total".into()), Some(2_200_000));
+
+        harness.roll_back(first);
+
+        assert_eq!(value(&harness, epoch.to_string()), Some(1_500_000));
+        assert_eq!(value(&harness, (epoch + 1).to_string()).unwrap_or(0), 0);
+        assert_eq!(value(&harness, "total".into()), Some(1_500_000));
+    }
+}
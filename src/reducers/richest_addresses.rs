@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub size: usize,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+}
+
+/// Tracks the top-N addresses by lovelace balance
+///
+/// Balances of every address are kept in a sorted set under
+/// `{prefix}.balances` (addresses are dropped once their balance reaches zero)
+/// and the highest N entries are copied into `{prefix}` after each block.
+/// Requires the enrichment stage to resolve the value of spent inputs.
+///
+/// Redis keeps the scores of sorted sets as doubles, exact up to 2^53 lovelace
+/// (about 9 billion ada), so the exact balance of each address is also kept
+/// in the counter `{prefix}.balance.{address}`. Copying the top entries uses
+/// `ZRANGESTORE`, which needs Redis 6.2 or later.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    recent: super::RollbackHistory<Vec<(String, model::Delta)>>,
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "richest_addresses",
+        }
+    }
+
+    fn key_format(&self) -> crosscut::AddressKeyFormat {
+        self.config.address_key_format.unwrap_or_default()
+    }
+
+    fn add_to_balance(deltas: &mut HashMap<String, i128>, address: Option<String>, amount: i128) {
+        if let Some(address) = address {
+            *deltas.entry(address).or_insert(0) += amount;
+        }
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), gasket::error::Error> {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+
+                match ctx.find_utxo(hash, *idx as u64) {
+                    Some(utxo) => {
+                        let address = self
+                            .key_format()
+                            .output_key_ctx(utxo, &self.address_hrp, ctx)
+                            .or_work_err()?;
+                        Self::add_to_balance(deltas, address, -(utxo.lovelace() as i128));
+                    }
+                    None => log::warn!("missing utxo {}#{} in block context", hash, idx),
+                }
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            let address = self.key_format().byron_output_key(output).or_work_err()?;
+            Self::add_to_balance(deltas, address, output.amount as i128);
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), gasket::error::Error> {
+        for input in model::spent_inputs(tx, failed) {
+            match ctx.find_utxo(&input.transaction_id, input.index) {
+                Some(utxo) => {
+                    let address = self
+                        .key_format()
+                        .output_key_ctx(utxo, &self.address_hrp, ctx)
+                        .or_work_err()?;
+                    Self::add_to_balance(deltas, address, -(utxo.lovelace() as i128));
+                }
+                None => log::warn!(
+                    "missing utxo {}#{} in block context",
+                    input.transaction_id,
+                    input.index
+                ),
+            }
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            let address = self
+                .key_format()
+                .alonzo_output_key_ctx(output, &self.address_hrp, ctx)
+                .or_work_err()?;
+            let amount = model::alonzo_lovelace(output);
+
+            Self::add_to_balance(deltas, address, amount as i128);
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut deltas = HashMap::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut deltas)?;
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    let failed = failed.contains(&idx);
+                    self.collect_alonzo_compatible_tx(tx, failed, ctx, &mut deltas)?;
+                }
+            }
+        };
+
+        let deltas: Vec<_> = deltas
+            .into_iter()
+            .filter(|(_, d)| *d != 0)
+            .map(|(address, delta)| (address, model::clamp_delta(delta)))
+            .collect();
+
+        self.send_deltas(&deltas, 1, output)?;
+        self.recent.push(block.slot(), deltas);
+
+        Ok(())
+    }
+
+    /// Applies the balance deltas, negated with a negative sign, and copies
+    /// the new top entries
+    fn send_deltas(
+        &self,
+        deltas: &[(String, model::Delta)],
+        sign: model::Delta,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let balances = format!("{}.balances", self.prefix());
+
+        for (address, delta) in deltas {
+            let delta = model::signed_delta(*delta, sign);
+
+            let crdt = model::CRDTCommand::PNCounter(
+                format!("{}.balance.{}", self.prefix(), address),
+                delta.to_string(),
+            );
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            let crdt = model::CRDTCommand::SortedSetAdd(balances.clone(), address.clone(), delta);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        let crdt = model::CRDTCommand::SortedSetCopyTop(
+            balances,
+            self.prefix().to_string(),
+            self.config.size,
+        );
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::RichestAddresses(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn rollback_restores_balances_and_top() {
+        let config = Config {
+            key_prefix: None,
+            size: 1,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 5_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .output(&address(3), 4_800_000)
+                    .fee(200_000)
+            })
+            .build();
+
+        harness.roll_forward(second);
+
+        let top = |harness: &Harness| {
+            let store = harness.store();
+            store.scores("richest_addresses").unwrap().clone()
+        };
+
+        assert_eq!(top(&harness).get(&address(3)), Some(&4_800_000));
+        assert_eq!(
+            harness
+                .store()
+                .scores("richest_addresses.balances")
+                .unwrap()
+                .len(),
+            2
+        );
+
+        harness.roll_back(first);
+
+        assert_eq!(top(&harness).get(&address(1)), Some(&5_000_000));
+        assert_eq!(top(&harness).len(), 1);
+
+        let balance = |seed| format!("richest_addresses.balance.{}", address(seed));
+        assert_eq!(harness.store().counter(&balance(1)), Some(5_000_000));
+        assert_eq!(harness.store().counter(&balance(3)), Some(0));
+    }
+
+    fn reducer() -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            size: 3,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+        };
+
+        config.plugin(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    fn balance(harness: &Harness, seed: u8) -> Option<i64> {
+        let key = format!("richest_addresses.balance.{}", address(seed));
+        harness.store().counter(&key)
+    }
+
+    #[test]
+    fn failed_tx_only_spends_its_collateral() {
+        let mut harness = Harness::new(vec![reducer()]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 5_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        harness.roll_forward(first);
+
+        let failed = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .collateral(funding, 1)
+                    .output(&address(3), 4_800_000)
+                    .failed()
+            })
+            .build();
+
+        harness.roll_forward(failed);
+
+        assert_eq!(balance(&harness, 1), Some(5_000_000));
+        assert_eq!(balance(&harness, 2), Some(0));
+        assert_eq!(balance(&harness, 3), None);
+    }
+
+    #[test]
+    fn rollback_of_a_saturated_delta_doesnt_overflow() {
+        let mut harness = Harness::new(vec![reducer()]);
+
+        let first = block().tx(|t| t.output(&address(1), u64::MAX)).build();
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        // the spend of the whole amount saturates at the most negative delta
+        let spend = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funding, 0).output(&address(2), 1_000_000))
+            .build();
+
+        harness.roll_forward(spend);
+        assert_eq!(balance(&harness, 2), Some(1_000_000));
+
+        harness.roll_back(first);
+        assert_eq!(balance(&harness, 2), Some(0));
+    }
+}
@@ -0,0 +1,137 @@
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Counts the rollbacks seen in each epoch and their depth in slots
+///
+/// For the epoch of each rollback point, `{prefix}.{epoch}.count` counts the
+/// rollbacks and `{prefix}.{epoch}.total_depth` adds up their depth, the
+/// average being the ratio of both. The distinct depths go into the sorted set
+/// `{prefix}.{epoch}.depths` scored by depth, so the max is its last member.
+///
+/// The depth is the distance from the last block seen by this reducer to the
+/// rollback point. Rollbacks are observations rather than chain data, so they
+/// aren't undone by later rollbacks. Rollbacks that undo nothing, like the one
+/// to the intersection sent when the pipeline starts, aren't counted.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    tip: Option<u64>,
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64, name: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, epoch, name),
+            None => format!("rollback_stats.{}.{}", epoch, name),
+        }
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        _output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        self.tip = Some(block.slot());
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = match point {
+            Point::Origin => 0,
+            Point::Specific(slot, _) => *slot,
+        };
+
+        let depth = match self.tip {
+            Some(tip) if tip > slot => tip - slot,
+            _ => return Ok(()),
+        };
+
+        self.tip = Some(slot);
+
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let crdt = model::CRDTCommand::PNCounter(self.key(epoch, "count"), "1".to_string());
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        let crdt = model::CRDTCommand::PNCounter(self.key(epoch, "total_depth"), depth.to_string());
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        let crdt =
+            model::CRDTCommand::LastWriteWins(self.key(epoch, "depths"), depth.to_string(), depth);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            tip: None,
+        };
+
+        super::Plugin::RollbackStats(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn rollback_is_counted_once_in_the_epoch_of_its_point() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = harness.roll_forward(block().build());
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 20).number(2).build());
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 40).number(3).build());
+
+        // the rollback to the intersection of a start undoes nothing
+        harness.roll_back(Point::Specific(DEFAULT_SLOT + 40, vec![]));
+        harness.roll_back(first);
+
+        let key = |name| {
+            format!(
+                "rollback_stats.{}.{}",
+                chain.epoch_for_slot(DEFAULT_SLOT),
+                name
+            )
+        };
+
+        let check = |harness: &Harness| {
+            let store = harness.store();
+            assert_eq!(store.counter(&key("count")), Some(1));
+            assert_eq!(store.counter(&key("total_depth")), Some(40));
+
+            let depths = store.scores(&key("depths")).unwrap();
+            assert_eq!(depths.get("40"), Some(&40));
+            assert_eq!(depths.len(), 1);
+        };
+
+        check(&harness);
+
+        // rolling forward again, then back to where the chain already is,
+        // leaves the counters alone
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 30).number(2).build());
+        harness.roll_back(Point::Specific(DEFAULT_SLOT + 30, vec![]));
+
+        check(&harness);
+    }
+}
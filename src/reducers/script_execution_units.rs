@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Scripts whose hash can't be resolved (eg: spent utxo missing from the
+/// block context) are accounted under this name
+const UNKNOWN_SCRIPT: &str = "unknown";
+
+/// Sums the execution units declared by the redeemers of each epoch
+///
+/// Counters are kept under `{prefix}.{epoch}.mem` and `{prefix}.{epoch}.steps`
+/// for the whole epoch, and under `{prefix}.{epoch}.{script_hash}.mem` and
+/// `{prefix}.{epoch}.{script_hash}.steps` for each script. The script of a
+/// spending redeemer is the payment credential of the spent output, so the
+/// enrichment stage is required.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i128>>,
+}
+
+fn script_credential(cred: &alonzo::StakeCredential) -> Option<String> {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(_) => None,
+        alonzo::StakeCredential::Scripthash(x) => Some(x.to_string()),
+    }
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "script_execution_units",
+        }
+    }
+
+    fn spent_script(
+        tx: &alonzo::TransactionBody,
+        index: usize,
+        ctx: &model::BlockContext,
+    ) -> Option<String> {
+        // redeemers point into the inputs sorted by tx hash and index
+        let mut inputs: Vec<_> = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Inputs(inputs) => Some(inputs),
+                _ => None,
+            })
+            .flat_map(|x| x.iter())
+            .collect();
+
+        inputs.sort_by_key(|x| (x.transaction_id.to_string(), x.index));
+
+        let input = inputs.get(index)?;
+
+        // byron outputs can't be locked by scripts
+        let utxo = match ctx.find_utxo(&input.transaction_id, input.index)? {
+            model::MultiEraOutput::Byron(_) => return None,
+            model::MultiEraOutput::AlonzoCompatible(x) => x,
+        };
+
+        match crosscut::parse_output_address(utxo.address.as_slice()).payment() {
+            Some(crosscut::Credential::Script(x)) => Some(hex::encode(x)),
+            _ => None,
+        }
+    }
+
+    fn minted_script(tx: &alonzo::TransactionBody, index: usize) -> Option<String> {
+        let mut policies: Vec<_> = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Mint(mint) => Some(mint),
+                _ => None,
+            })
+            .flat_map(|x| x.iter())
+            .map(|(policy, _)| hex::encode(policy.as_slice()))
+            .collect();
+
+        policies.sort();
+
+        policies.get(index).cloned()
+    }
+
+    fn certificate_script(tx: &alonzo::TransactionBody, index: usize) -> Option<String> {
+        let certificate = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                _ => None,
+            })
+            .flat_map(|x| x.iter())
+            .nth(index)?;
+
+        match certificate {
+            alonzo::Certificate::StakeDeregistration(cred) => script_credential(cred),
+            alonzo::Certificate::StakeDelegation(cred, _) => script_credential(cred),
+            _ => None,
+        }
+    }
+
+    fn withdrawal_script(tx: &alonzo::TransactionBody, index: usize) -> Option<String> {
+        let mut accounts: Vec<_> = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Withdrawals(w) => Some(w),
+                _ => None,
+            })
+            .flat_map(|x| x.iter())
+            .map(|(account, _)| account.to_vec())
+            .collect();
+
+        accounts.sort();
+
+        let account = accounts.get(index)?;
+
+        match crosscut::parse_output_address(account).stake() {
+            Some(crosscut::Credential::Script(x)) => Some(hex::encode(x)),
+            _ => None,
+        }
+    }
+
+    fn collect_tx(
+        &self,
+        epoch: u64,
+        tx: &alonzo::TransactionBody,
+        witnesses: &alonzo::TransactionWitnessSet,
+        ctx: &model::BlockContext,
+        counters: &mut HashMap<String, i128>,
+    ) {
+        let redeemers = match &witnesses.redeemer {
+            Some(x) => x,
+            None => return,
+        };
+
+        for redeemer in redeemers.iter() {
+            let index = redeemer.index as usize;
+
+            let script = match redeemer.tag {
+                alonzo::RedeemerTag::Spend => Self::spent_script(tx, index, ctx),
+                alonzo::RedeemerTag::Mint => Self::minted_script(tx, index),
+                alonzo::RedeemerTag::Cert => Self::certificate_script(tx, index),
+                alonzo::RedeemerTag::Reward => Self::withdrawal_script(tx, index),
+            };
+
+            let script = script.unwrap_or_else(|| {
+                log::warn!("can't resolve the script of a {:?} redeemer", redeemer.tag);
+                UNKNOWN_SCRIPT.to_string()
+            });
+
+            let mem = redeemer.ex_units.mem as i128;
+            let steps = redeemer.ex_units.steps as i128;
+
+            for (key, value) in [
+                (format!("{}.{}.mem", self.prefix(), epoch), mem),
+                (format!("{}.{}.steps", self.prefix(), epoch), steps),
+                (format!("{}.{}.{}.mem", self.prefix(), epoch, script), mem),
+                (
+                    format!("{}.{}.{}.steps", self.prefix(), epoch, script),
+                    steps,
+                ),
+            ] {
+                *counters.entry(key).or_insert(0) += value;
+            }
+        }
+    }
+
+    fn send_counters(
+        &self,
+        counters: &HashMap<String, i128>,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, value) in counters.iter() {
+            let crdt = model::CRDTCommand::PNCounter(
+                key.clone(),
+                model::clamp_delta(sign * value).to_string(),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        // redeemers only exist since Alonzo
+        let block = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = block.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let mut counters = HashMap::new();
+
+        for (tx, witnesses) in block
+            .1
+            .transaction_bodies
+            .iter()
+            .zip(block.1.transaction_witness_sets.iter())
+        {
+            self.collect_tx(epoch, tx, witnesses, ctx, &mut counters);
+        }
+
+        if counters.is_empty() {
+            return Ok(());
+        }
+
+        self.send_counters(&counters, 1, output)?;
+
+        self.recent.push(slot, counters);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counters) in self.recent.rollback(point) {
+            self.send_counters(&counters, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::ScriptExecutionUnits(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    const FIRST: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const SECOND: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    #[test]
+    fn redeemers_add_up_per_script_and_per_epoch() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = harness.roll_forward(block().tx(|t| t.output(&address(1), 1_000_000)).build());
+
+        // the mint redeemers point into the policies sorted by hash
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.mint(SECOND, "B", 1)
+                        .mint(FIRST, "A", 1)
+                        .redeemer(alonzo::RedeemerTag::Mint, 0, 1_000, 200_000)
+                        .redeemer(alonzo::RedeemerTag::Mint, 1, 3_000, 500_000)
+                })
+                .build(),
+        );
+
+        let units = |harness: &Harness, key: &str| {
+            let key = format!("script_execution_units.{}.{}", epoch, key);
+            harness.store().counter(&key)
+        };
+
+        assert_eq!(units(&harness, "mem"), Some(4_000));
+        assert_eq!(units(&harness, "steps"), Some(700_000));
+        assert_eq!(units(&harness, &format!("{}.mem", FIRST)), Some(1_000));
+        assert_eq!(units(&harness, &format!("{}.steps", FIRST)), Some(200_000));
+        assert_eq!(units(&harness, &format!("{}.mem", SECOND)), Some(3_000));
+        assert_eq!(units(&harness, &format!("{}.steps", SECOND)), Some(500_000));
+        assert_eq!(units(&harness, "unknown.mem"), None);
+
+        harness.roll_back(first);
+
+        assert_eq!(units(&harness, "mem"), Some(0));
+        assert_eq!(units(&harness, &format!("{}.steps", SECOND)), Some(0));
+    }
+}
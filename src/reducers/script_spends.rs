@@ -0,0 +1,290 @@
+use std::collections::{HashMap, HashSet};
+
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Only track spends from these script addresses (bech32 or hex)
+    pub addresses: Option<Vec<String>>,
+}
+
+/// Counts the txs that spend utxos locked at script addresses
+///
+/// `{prefix}.{address}.count` is the number of txs that spent from the
+/// address, and the sorted set `{prefix}.{address}.txs` lists their hashes,
+/// scored by slot. A tx spending several utxos of the same address counts
+/// once. Requires the enrichment stage to resolve spent inputs, Byron
+/// addresses can't be locked by scripts.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    allowlist: Option<HashSet<Vec<u8>>>,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, address: &str, suffix: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, address, suffix),
+            None => format!("script_spends.{}.{}", address, suffix),
+        }
+    }
+
+    fn is_allowed(&self, address: &[u8]) -> bool {
+        match &self.allowlist {
+            Some(allowed) => allowed.contains(address),
+            None => true,
+        }
+    }
+
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        crosscut::Allowlists {
+            addresses: self.allowlist.clone(),
+            policies: None,
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        self.allowlist = allowlists.addresses;
+    }
+
+    /// Script addresses of the utxos spent by the tx
+    fn spent_scripts(
+        &self,
+        tx: &alonzo::TransactionBody,
+        ctx: &model::BlockContext,
+    ) -> HashSet<String> {
+        let mut scripts = HashSet::new();
+
+        let inputs = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Inputs(inputs) => Some(inputs),
+                _ => None,
+            })
+            .flat_map(|x| x.iter());
+
+        for input in inputs {
+            let utxo = match ctx.find_utxo(&input.transaction_id, input.index) {
+                Some(model::MultiEraOutput::AlonzoCompatible(x)) => x,
+                Some(model::MultiEraOutput::Byron(_)) => continue,
+                None => {
+                    log::warn!("missing utxo in block context");
+                    continue;
+                }
+            };
+
+            let raw = utxo.address.as_slice();
+
+            if !crosscut::parse_output_address(raw).has_script_payment() || !self.is_allowed(raw) {
+                continue;
+            }
+
+            match utxo.to_bech32_address(&self.address_hrp) {
+                Ok(x) => scripts.insert(x),
+                Err(err) => {
+                    log::warn!("can't encode script address: {}", err);
+                    continue;
+                }
+            };
+        }
+
+        scripts
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let mut counts: HashMap<String, i64> = HashMap::new();
+
+        for tx in x.1.transaction_bodies.iter() {
+            let hash = tx.to_hash().to_string();
+
+            for address in self.spent_scripts(tx, ctx) {
+                let crdt = model::CRDTCommand::LastWriteWins(
+                    self.key(&address, "txs"),
+                    hash.clone(),
+                    slot,
+                );
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                *counts.entry(address).or_insert(0) += 1;
+            }
+        }
+
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        for (address, count) in counts.iter() {
+            let crdt = model::CRDTCommand::PNCounter(self.key(address, "count"), count.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        self.recent.push(slot, counts);
+
+        Ok(())
+    }
+
+    /// Reverts the counts and removes the txs of the blocks after the
+    /// rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, counts) in self.recent.rollback(point) {
+            for (address, count) in counts {
+                let crdt = model::CRDTCommand::PNCounter(
+                    self.key(&address, "count"),
+                    (-count).to_string(),
+                );
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                let crdt = model::CRDTCommand::SortedSetRemoveRange(
+                    self.key(&address, "txs"),
+                    applied,
+                    applied,
+                );
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Decodes the address allowlist into the raw form compared against inputs
+    pub fn allowlist(
+        &self,
+        chain: &crosscut::ChainWellKnownInfo,
+    ) -> Result<Option<HashSet<Vec<u8>>>, Error> {
+        let addresses = match &self.addresses {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let parsed = addresses
+            .iter()
+            .map(|x| crosscut::parse_config_address(x, chain))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(parsed))
+    }
+
+    pub fn validate(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<(), Error> {
+        self.allowlist(chain).map(|_| ())
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let allowlist = self
+            .allowlist(chain)
+            .expect("allowlist is validated when loading the config");
+
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            allowlist,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::ScriptSpends(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bech32::{ToBase32, Variant};
+
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    /// Hex of a mainnet enterprise address locked by the script hash
+    fn script_address(seed: u8) -> String {
+        let mut bytes = vec![0x71];
+        bytes.extend([seed; 28]);
+        hex::encode(bytes)
+    }
+
+    #[test]
+    fn only_spends_from_watched_scripts_are_counted() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+
+        let config = Config {
+            key_prefix: None,
+            addresses: Some(vec![script_address(10)]),
+        };
+
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let funding = block()
+            .tx(|t| {
+                t.output(&script_address(10), 5_000_000)
+                    .output(&script_address(11), 5_000_000)
+                    .output(&address(1), 5_000_000)
+            })
+            .build();
+
+        let funds = tx_hash(&funding, 0);
+        let funding = harness.roll_forward(funding);
+
+        // a spend of the watched script, then of a script and a key address
+        // that aren't watched
+        let spending = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funds, 0).output(&address(2), 4_800_000))
+            .tx(|t| {
+                t.input(funds, 1)
+                    .input(funds, 2)
+                    .output(&address(2), 9_800_000)
+            })
+            .build();
+
+        let spend = tx_hash(&spending, 0).to_string();
+        harness.roll_forward(spending);
+
+        let watched = bech32::encode(
+            "addr",
+            hex::decode(script_address(10)).unwrap().to_base32(),
+            Variant::Bech32,
+        )
+        .unwrap();
+
+        let count = format!("script_spends.{}.count", watched);
+        let txs = format!("script_spends.{}.txs", watched);
+
+        {
+            let store = harness.store();
+            assert_eq!(store.counter(&count), Some(1));
+
+            let listed: Vec<_> = store.scores(&txs).unwrap().keys().cloned().collect();
+            assert_eq!(listed, vec![spend]);
+
+            // nothing else was written
+            assert_eq!(store.keys().count(), 2);
+        }
+
+        harness.roll_back(funding);
+
+        let store = harness.store();
+        assert_eq!(store.counter(&count).unwrap_or(0), 0);
+        assert!(store.scores(&txs).map(|x| x.is_empty()).unwrap_or(true));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Smallest distance in slots between two consecutive blocks counted as
+    /// a gap, defaults to 2 (at least one empty slot)
+    pub min_gap: Option<u64>,
+}
+
+const DEFAULT_MIN_GAP: u64 = 2;
+
+/// What a block changed: the slot of the block before it, and the epoch, size
+/// and previous epoch max of the gap that it closed, if any
+struct Applied {
+    previous_slot: Option<u64>,
+    gap: Option<(u64, u64, Option<u64>)>,
+}
+
+/// Measures the distance in slots between consecutive blocks
+///
+/// Per epoch, `{prefix}.{epoch}.gap_count` counts the gaps of at least
+/// `min_gap` slots, `{prefix}.{epoch}.empty_slots` sums the slots without a
+/// block inside of them and `{prefix}.{epoch}.max_gap` keeps the largest one.
+/// A gap belongs to the epoch of the block that closes it. The slot of the
+/// previous block is kept in memory, so the first block after a restart
+/// doesn't close a gap.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    last_slot: Option<u64>,
+    max_gaps: HashMap<u64, u64>,
+    recent: super::RollbackHistory<Applied>,
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64, stat: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, epoch, stat),
+            None => format!("slot_gaps.{}.{}", epoch, stat),
+        }
+    }
+
+    fn send_counters(
+        &self,
+        epoch: u64,
+        gap: u64,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let count = model::CRDTCommand::PNCounter(self.key(epoch, "gap_count"), sign.to_string());
+        output.send(gasket::messaging::Message::from(count))?;
+
+        let empty = sign * (gap as i64 - 1);
+        let empty =
+            model::CRDTCommand::PNCounter(self.key(epoch, "empty_slots"), empty.to_string());
+        output.send(gasket::messaging::Message::from(empty))?;
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        // EBBs share the slot of the first block of their epoch
+        if block.is_epoch_boundary() {
+            return Ok(());
+        }
+
+        let slot = block.slot();
+        let previous_slot = self.last_slot.replace(slot);
+
+        let gap = match previous_slot {
+            Some(previous) => slot.saturating_sub(previous),
+            None => 0,
+        };
+
+        let mut applied = Applied {
+            previous_slot,
+            gap: None,
+        };
+
+        if gap >= self.config.min_gap.unwrap_or(DEFAULT_MIN_GAP) {
+            let epoch = self.chain.epoch_for_slot(slot);
+
+            self.send_counters(epoch, gap, 1, output)?;
+
+            let previous_max = self.max_gaps.get(&epoch).copied();
+
+            if previous_max.map(|x| gap > x).unwrap_or(true) {
+                self.max_gaps.insert(epoch, gap);
+
+                // the stored max might come from before a restart
+                let crdt = model::CRDTCommand::ConditionalWrite(
+                    self.key(epoch, "max_gap"),
+                    gap.to_string(),
+                    model::WriteCondition::GreaterThan,
+                );
+
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+
+            applied.gap = Some((epoch, gap, previous_max));
+        }
+
+        self.recent.push(slot, applied);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point) {
+            self.last_slot = applied.previous_slot;
+
+            let (epoch, gap, previous_max) = match applied.gap {
+                Some(x) => x,
+                None => continue,
+            };
+
+            self.send_counters(epoch, gap, -1, output)?;
+
+            if self.max_gaps.get(&epoch) == Some(&gap) {
+                let key = self.key(epoch, "max_gap");
+
+                let crdt = match previous_max {
+                    Some(max) => {
+                        self.max_gaps.insert(epoch, max);
+                        model::CRDTCommand::AnyWriteWins(key, max.to_string())
+                    }
+                    None => {
+                        self.max_gaps.remove(&epoch);
+                        model::CRDTCommand::AnyWriteWinsRemove(key)
+                    }
+                };
+
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            last_slot: None,
+            max_gaps: HashMap::new(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::SlotGaps(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn gaps_between_blocks_add_up_per_epoch_and_roll_back() {
+        let config = Config {
+            key_prefix: None,
+            min_gap: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let mut points = Vec::new();
+
+        // consecutive slots, then gaps of 10 and 3 slots
+        for (number, offset) in [0, 1, 11, 14].into_iter().enumerate() {
+            let block = block()
+                .slot(DEFAULT_SLOT + offset)
+                .number(number as u64 + 1)
+                .build();
+
+            points.push(harness.roll_forward(block));
+        }
+
+        let stats = |harness: &Harness| {
+            let store = harness.store();
+            let key = |stat| format!("slot_gaps.{}.{}", epoch, stat);
+
+            (
+                store.counter(&key("gap_count")),
+                store.counter(&key("empty_slots")),
+                store.string(&key("max_gap")).map(String::from),
+            )
+        };
+
+        assert_eq!(stats(&harness), (Some(2), Some(11), Some("10".into())));
+
+        harness.roll_back(points[2].clone());
+        assert_eq!(stats(&harness), (Some(1), Some(9), Some("10".into())));
+
+        harness.roll_back(points[0].clone());
+        assert_eq!(stats(&harness), (Some(0), Some(0), None));
+
+        // the restored last slot measures the next gap
+        harness.roll_forward(block().slot(DEFAULT_SLOT + 5).number(2).build());
+        assert_eq!(stats(&harness), (Some(1), Some(4), Some("5".into())));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Keeps the lovelace controlled by each stake credential
+///
+/// The counter `{prefix}.{stake_cred}` adds up the utxos of every address
+/// that shares the stake credential (hex). Pointer addresses are resolved
+/// through the block context; enterprise, script-only and Byron addresses
+/// have no stake part and aren't accounted. Requires the enrichment stage to
+/// resolve the value of spent inputs.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i128>>,
+}
+
+impl Reducer {
+    fn key(&self, cred: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, cred),
+            None => format!("stake_balance.{}", cred),
+        }
+    }
+
+    fn collect_output(
+        &self,
+        output: &alonzo::TransactionOutput,
+        ctx: &model::BlockContext,
+        sign: i128,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), Error> {
+        let cred = crosscut::AddressKeyFormat::StakeCred.alonzo_output_key_ctx(
+            output,
+            &self.chain.address_hrp,
+            ctx,
+        )?;
+
+        if let Some(cred) = cred {
+            *deltas.entry(cred).or_insert(0) += sign * model::alonzo_lovelace(output) as i128;
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<String, i128>,
+    ) -> Result<(), Error> {
+        for input in model::spent_inputs(tx, failed) {
+            match ctx.find_utxo(&input.transaction_id, input.index) {
+                Some(model::MultiEraOutput::AlonzoCompatible(utxo)) => {
+                    self.collect_output(utxo, ctx, -1, deltas)?
+                }
+                // byron addresses have no stake part
+                Some(model::MultiEraOutput::Byron(_)) => (),
+                None => log::warn!("missing utxo in block context"),
+            }
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            self.collect_output(output, ctx, 1, deltas)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &HashMap<String, i128>,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (cred, delta) in deltas.iter() {
+            let crdt = model::CRDTCommand::PNCounter(
+                self.key(cred),
+                model::clamp_delta(sign * delta).to_string(),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let block = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let mut deltas = HashMap::new();
+
+        let failed = model::failed_txs(&block.1);
+
+        for (idx, tx) in block.1.transaction_bodies.iter().enumerate() {
+            self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut deltas)
+                .or_work_err()?;
+        }
+
+        deltas.retain(|_, x| *x != 0);
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(block.1.header.header_body.slot, deltas);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::StakeBalance(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, base_address, block, tx_hash, Harness, DEFAULT_SLOT};
+    use pallas::crypto::hash::Hash;
+
+    /// Mainnet pointer address with the payment key hash repeating the seed
+    fn pointer_address(payment: u8, slot: u64, tx_idx: u8, cert_idx: u8) -> String {
+        let mut bytes = vec![0x41];
+        bytes.extend([payment; 28]);
+
+        // variable-length natural, 7 bits per byte with the most significant first
+        let mut slot_bytes = vec![(slot & 0x7f) as u8];
+        let mut rest = slot >> 7;
+
+        while rest > 0 {
+            slot_bytes.insert(0, (rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+
+        bytes.extend(slot_bytes);
+        bytes.extend([tx_idx, cert_idx]);
+
+        hex::encode(bytes)
+    }
+
+    #[test]
+    fn pointer_address_counts_for_the_registered_credential() {
+        let config = Config { key_prefix: None };
+        let mut harness =
+            Harness::new(vec![config.plugin(&crosscut::ChainWellKnownInfo::mainnet())]);
+
+        let registration = alonzo::Certificate::StakeRegistration(
+            alonzo::StakeCredential::AddrKeyhash(Hash::from([7; 28])),
+        );
+
+        let first = block()
+            .tx(|t| t.output(&base_address(1, 9), 2_000_000))
+            .tx(|t| t.cert(registration).output(&base_address(2, 7), 3_000_000))
+            .build();
+
+        harness.roll_forward(first);
+
+        // the registration is the first cert of the second tx of the block
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&pointer_address(3, DEFAULT_SLOT, 1, 0), 1_500_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        let key = format!("stake_balance.{}", hex::encode([7; 28]));
+        assert_eq!(harness.store().counter(&key), Some(4_500_000));
+    }
+
+    #[test]
+    fn addresses_sharing_a_stake_key_add_up() {
+        let config = Config { key_prefix: None };
+        let mut harness =
+            Harness::new(vec![config.plugin(&crosscut::ChainWellKnownInfo::mainnet())]);
+
+        // two payment keys delegating the same stake key, and an enterprise address
+        let first = block()
+            .tx(|t| {
+                t.output(&base_address(1, 9), 2_000_000)
+                    .output(&base_address(2, 9), 3_000_000)
+                    .output(&address(3), 4_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let balance = |harness: &Harness| {
+            let key = format!("stake_balance.{}", hex::encode([9; 28]));
+            harness.store().counter(&key)
+        };
+
+        assert_eq!(balance(&harness), Some(5_000_000));
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.input(funding, 1)
+                        .output(&address(4), 2_800_000)
+                        .fee(200_000)
+                })
+                .build(),
+        );
+
+        assert_eq!(balance(&harness), Some(2_000_000));
+
+        harness.roll_back(first);
+
+        assert_eq!(balance(&harness), Some(5_000_000));
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model, Error};
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Target number of pools of the network (the `k` protocol parameter,
+    /// 500 on mainnet). When set, the saturation of each pool is also
+    /// snapshotted.
+    pub saturation_k: Option<u32>,
+}
+
+/// Stake delegated to each pool at the end of an epoch
+type Snapshot = Vec<(String, model::Delta)>;
+
+/// Changes applied by a single block, kept to undo them on rollback
+#[derive(Serialize, Deserialize)]
+struct Applied {
+    /// Epoch of the block before this one, if any
+    previous_epoch: Option<u64>,
+    deltas: HashMap<String, i128>,
+    delegations: Vec<(String, Option<String>)>,
+    snapshot: Option<(u64, Snapshot)>,
+}
+
+/// The epoch of the last block is kept under this key of the `epoch` map
+const EPOCH_KEY: &str = "last";
+
+/// Snapshot of the stake delegated to each pool at every epoch boundary
+///
+/// When a new epoch starts, the total lovelace controlled by the stake
+/// credentials delegated to each pool is added to the sorted set
+/// `{prefix}.{epoch}` of the epoch that just ended (members are pool ids).
+///
+/// With `saturation_k`, the stake of each pool relative to the saturation
+/// point (`total / k`) goes into the sorted set `{prefix}.{epoch}.saturation`,
+/// in parts per million (1000000 is a saturated pool), the pools above it are
+/// members of `{prefix}.{epoch}.oversaturated` and the total delegated stake
+/// is kept under `{prefix}.{epoch}.total`.
+///
+/// Balances and delegations are kept in the state db, since reducers can't
+/// read the data of other collections. This is heavy and requires the
+/// enrichment stage. Only Shelley-era stake is accounted.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    epoch: state::Map<u64>,
+    balances: state::Map<i128>,
+    delegations: state::Map<String>,
+    recent: state::History<Applied>,
+}
+
+fn credential_key(cred: &alonzo::StakeCredential) -> String {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => x.to_string(),
+        alonzo::StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, epoch),
+            None => format!("stake_distribution.{}", epoch),
+        }
+    }
+
+    fn stake_key(
+        &self,
+        output: &model::MultiEraOutput,
+        ctx: &model::BlockContext,
+    ) -> Result<Option<String>, gasket::error::Error> {
+        crosscut::AddressKeyFormat::StakeCred
+            .output_key_ctx(output, &self.chain.address_hrp, ctx)
+            .or_work_err()
+    }
+
+    fn collect_tx(
+        &mut self,
+        tx: &alonzo::TransactionBody,
+        ctx: &model::BlockContext,
+        applied: &mut Applied,
+    ) -> Result<(), gasket::error::Error> {
+        for component in tx.iter() {
+            match component {
+                alonzo::TransactionBodyComponent::Inputs(inputs) => {
+                    for input in inputs.iter() {
+                        if let Some(utxo) = ctx.find_utxo(&input.transaction_id, input.index) {
+                            if let Some(key) = self.stake_key(utxo, ctx)? {
+                                *applied.deltas.entry(key).or_insert(0) -= utxo.lovelace() as i128;
+                            }
+                        }
+                    }
+                }
+                alonzo::TransactionBodyComponent::Outputs(outputs) => {
+                    for output in outputs.iter() {
+                        let key = crosscut::AddressKeyFormat::StakeCred
+                            .alonzo_output_key_ctx(output, &self.chain.address_hrp, ctx)
+                            .or_work_err()?;
+
+                        let lovelace = model::alonzo_lovelace(output);
+
+                        if let Some(key) = key {
+                            *applied.deltas.entry(key).or_insert(0) += lovelace as i128;
+                        }
+                    }
+                }
+                alonzo::TransactionBodyComponent::Certificates(certs) => {
+                    for cert in certs.iter() {
+                        match cert {
+                            alonzo::Certificate::StakeDelegation(cred, pool) => {
+                                let key = credential_key(cred);
+                                let previous = self
+                                    .delegations
+                                    .insert(&key, &pool.to_string())
+                                    .or_work_err()?;
+                                applied.delegations.push((key, previous));
+                            }
+                            alonzo::Certificate::StakeDeregistration(cred) => {
+                                let key = credential_key(cred);
+                                let previous = self.delegations.remove(&key).or_work_err()?;
+                                applied.delegations.push((key, previous));
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_delta(&mut self, cred: &str, delta: i128) -> Result<(), Error> {
+        let balance = self.balances.get(cred)?.unwrap_or(0) + delta;
+        let balance = Some(balance).filter(|x| *x != 0);
+
+        self.balances.restore(cred, balance.as_ref())
+    }
+
+    fn compute_snapshot(&self) -> Result<Snapshot, Error> {
+        let mut totals: HashMap<String, i128> = HashMap::new();
+
+        for (cred, pool) in self.delegations.entries()? {
+            let balance = self.balances.get(&cred)?.unwrap_or(0);
+            *totals.entry(pool).or_insert(0) += balance;
+        }
+
+        let snapshot = totals
+            .into_iter()
+            .filter(|(_, total)| *total > 0)
+            .map(|(pool, total)| (pool, model::clamp_delta(total)))
+            .collect();
+
+        Ok(snapshot)
+    }
+
+    /// Reverts the changes of a block on the state, returns the snapshot that
+    /// it sent, if any
+    fn undo(&mut self, applied: Applied) -> Result<Option<(u64, Snapshot)>, Error> {
+        for (cred, delta) in applied.deltas.iter() {
+            self.apply_delta(cred, -delta)?;
+        }
+
+        for (cred, previous) in applied.delegations.into_iter().rev() {
+            self.delegations.restore(&cred, previous.as_ref())?;
+        }
+
+        self.epoch
+            .restore(EPOCH_KEY, applied.previous_epoch.as_ref())?;
+
+        Ok(applied.snapshot)
+    }
+
+    fn send_snapshot(
+        &self,
+        epoch: u64,
+        snapshot: &[(String, model::Delta)],
+        sign: model::Delta,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (pool, stake) in snapshot {
+            let crdt =
+                model::CRDTCommand::SortedSetAdd(self.key(epoch), pool.clone(), sign * stake);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        if let Some(k) = self.config.saturation_k {
+            self.send_saturation(epoch, snapshot, sign, k, output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Undone by sending it again with a negative sign
+    fn send_saturation(
+        &self,
+        epoch: u64,
+        snapshot: &[(String, model::Delta)],
+        sign: model::Delta,
+        k: u32,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = self.key(epoch);
+        let total: i128 = snapshot.iter().map(|(_, stake)| *stake as i128).sum();
+
+        if total <= 0 {
+            return Ok(());
+        }
+
+        for (pool, stake) in snapshot {
+            let ppm = *stake as i128 * k as i128 * 1_000_000 / total;
+
+            let crdt = model::CRDTCommand::SortedSetAdd(
+                format!("{}.saturation", key),
+                pool.clone(),
+                sign * model::clamp_delta(ppm),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if ppm > 1_000_000 {
+                let set = format!("{}.oversaturated", key);
+
+                let crdt = match sign > 0 {
+                    true => model::CRDTCommand::GrowOnlySetAdd(set, pool.clone()),
+                    false => model::CRDTCommand::SetRemove(set, pool.clone()),
+                };
+
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        let crdt = match sign > 0 {
+            true => model::CRDTCommand::AnyWriteWins(format!("{}.total", key), total.to_string()),
+            false => model::CRDTCommand::AnyWriteWinsRemove(format!("{}.total", key)),
+        };
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        // changes left by a run that stopped before the storage committed
+        for (_, applied) in self.recent.rewind(slot).or_work_err()? {
+            self.undo(applied).or_work_err()?;
+        }
+
+        let previous_epoch = self.epoch.get(EPOCH_KEY).or_work_err()?;
+
+        let mut applied = Applied {
+            previous_epoch,
+            deltas: HashMap::new(),
+            delegations: Vec::new(),
+            snapshot: None,
+        };
+
+        // the snapshot of the previous epoch reflects the state before this block
+        if let Some(previous) = previous_epoch.filter(|x| *x != epoch) {
+            let snapshot = self.compute_snapshot().or_work_err()?;
+            self.send_snapshot(previous, &snapshot, 1, output)?;
+            applied.snapshot = Some((previous, snapshot));
+        }
+
+        self.epoch.insert(EPOCH_KEY, &epoch).or_work_err()?;
+
+        for tx in x.1.transaction_bodies.iter() {
+            self.collect_tx(tx, ctx, &mut applied)?;
+        }
+
+        for (cred, delta) in applied.deltas.iter() {
+            self.apply_delta(cred, *delta).or_work_err()?;
+        }
+
+        self.recent.push(slot, &applied).or_work_err()?;
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point).or_work_err()? {
+            if let Some((epoch, snapshot)) = self.undo(applied).or_work_err()? {
+                self.send_snapshot(epoch, &snapshot, -1, output)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.saturation_k == Some(0) {
+            return Err(Error::config(
+                "StakeDistribution saturation_k should be above zero",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        state: &state::Db,
+    ) -> Result<super::Plugin, Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "stake_distribution".to_string(),
+        };
+
+        let reducer = Reducer {
+            chain: chain.clone(),
+            epoch: state.map(&format!("{}.epoch", name))?,
+            balances: state.map(&format!("{}.balances", name))?,
+            delegations: state.map(&format!("{}.delegations", name))?,
+            recent: state.history(&name)?,
+            config: self,
+        };
+
+        Ok(super::Plugin::StakeDistribution(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{base_address, block, delegation, state_db, Harness, DEFAULT_SLOT};
+
+    /// First slot of the mainnet epoch after the one of [DEFAULT_SLOT]
+    const NEXT_EPOCH_SLOT: u64 = 50_284_800;
+
+    fn reducer(saturation_k: Option<u32>, state: &state::Db) -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            saturation_k,
+        };
+
+        config
+            .plugin(&crosscut::ChainWellKnownInfo::mainnet(), state)
+            .unwrap()
+    }
+
+    fn delegating_block() -> model::MultiEraBlock {
+        block()
+            .tx(|t| {
+                t.output(&base_address(1, 9), 10_000_000)
+                    .cert(delegation(9, 7))
+            })
+            .build()
+    }
+
+    #[test]
+    fn snapshot_at_epoch_boundary_is_rolled_back() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![reducer(Some(2), &state_db())]);
+
+        let first = harness.roll_forward(delegating_block());
+
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        assert_eq!(chain.epoch_for_slot(NEXT_EPOCH_SLOT), epoch + 1);
+
+        let second = block().slot(NEXT_EPOCH_SLOT).number(2).build();
+        harness.roll_forward(second);
+
+        let key = format!("stake_distribution.{}", epoch);
+        let pool = hex::encode([7; 28]);
+
+        {
+            let store = harness.store();
+            assert_eq!(store.scores(&key).unwrap().get(&pool), Some(&10_000_000));
+
+            let saturation = store.scores(&format!("{}.saturation", key)).unwrap();
+            assert_eq!(saturation.get(&pool), Some(&2_000_000));
+
+            let oversaturated = store.members(&format!("{}.oversaturated", key));
+            assert!(oversaturated.unwrap().contains(&pool));
+            assert_eq!(store.string(&format!("{}.total", key)), Some("10000000"));
+        }
+
+        harness.roll_back(first);
+
+        let store = harness.store();
+        assert!(store.scores(&key).is_none());
+        assert!(store.scores(&format!("{}.saturation", key)).is_none());
+        assert!(store.members(&format!("{}.oversaturated", key)).is_none());
+        assert!(store.string(&format!("{}.total", key)).is_none());
+    }
+
+    #[test]
+    fn saturation_of_each_pool_is_relative_to_the_total_stake() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![reducer(Some(2), &state_db())]);
+
+        // (stake credential, pool, lovelace)
+        let distribution = [(9, 7, 6_000_000), (8, 6, 3_000_000), (5, 4, 1_000_000)];
+
+        let mut delegating = block();
+
+        for (stake, pool, lovelace) in distribution {
+            delegating = delegating.tx(|t| {
+                t.output(&base_address(1, stake), lovelace)
+                    .cert(delegation(stake, pool))
+            });
+        }
+
+        harness.roll_forward(delegating.build());
+        harness.roll_forward(block().slot(NEXT_EPOCH_SLOT).number(2).build());
+
+        let key = format!("stake_distribution.{}", chain.epoch_for_slot(DEFAULT_SLOT));
+        let pool = |seed: u8| hex::encode([seed; 28]);
+
+        let store = harness.store();
+        let saturation = store.scores(&format!("{}.saturation", key)).unwrap();
+
+        // 1000000 is a saturated pool, the total is k saturated pools
+        assert_eq!(saturation.get(&pool(7)), Some(&1_200_000));
+        assert_eq!(saturation.get(&pool(6)), Some(&600_000));
+        assert_eq!(saturation.get(&pool(4)), Some(&200_000));
+        assert_eq!(saturation.values().sum::<i64>(), 2_000_000);
+
+        let oversaturated = store.members(&format!("{}.oversaturated", key)).unwrap();
+        assert_eq!(oversaturated.iter().collect::<Vec<_>>(), vec![&pool(7)]);
+
+        assert_eq!(store.string(&format!("{}.total", key)), Some("10000000"));
+    }
+
+    #[test]
+    fn state_of_a_previous_run_is_kept_and_replays_are_not_counted_twice() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let state = state_db();
+
+        let mut harness = Harness::new(vec![reducer(None, &state)]);
+        harness.roll_forward(delegating_block());
+
+        // the storage didn't commit before the stop, so the block is replayed
+        let mut harness = harness.restart(vec![reducer(None, &state)]);
+        let first = harness.roll_forward(delegating_block());
+
+        let second = block().slot(NEXT_EPOCH_SLOT).number(2).build();
+        harness.roll_forward(second);
+
+        let key = format!("stake_distribution.{}", chain.epoch_for_slot(DEFAULT_SLOT));
+        let pool = hex::encode([7; 28]);
+
+        assert_eq!(
+            harness.store().scores(&key).unwrap().get(&pool),
+            Some(&10_000_000)
+        );
+
+        // a rollback after another restart undoes the snapshot of the first run
+        let mut harness = harness.restart(vec![reducer(None, &state)]);
+        harness.roll_back(first);
+
+        assert!(harness.store().scores(&key).is_none());
+    }
+}
@@ -0,0 +1,285 @@
+//! Local state of the reducers that need to read back what they derived from
+//! earlier blocks, eg: the pool that a credential delegates to
+//!
+//! Each reducer keeps its values and the changes of its recent blocks in its
+//! own trees of a sled db, so that the state survives restarts and rollbacks
+//! can undo blocks applied by a previous run.
+
+use std::marker::PhantomData;
+
+use pallas::network::miniprotocols::Point;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::Error;
+
+/// Age in slots after which blocks can't be rolled back anymore (3k/f on
+/// mainnet) and their changes are forgotten
+pub const MAX_HISTORY_SLOTS: u64 = 129_600;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub db_path: String,
+}
+
+impl Config {
+    pub fn open(&self) -> Result<Db, Error> {
+        let db = sled::open(&self.db_path).map_err(Error::storage)?;
+        Ok(Db(db))
+    }
+}
+
+/// Handle of the state db, shared by the reducers of the pipeline
+#[derive(Clone)]
+pub struct Db(sled::Db);
+
+impl Db {
+    /// A db removed once the last handle is dropped, for tests and one-off
+    /// runs
+    pub fn temporary() -> Result<Self, Error> {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .map_err(Error::storage)?;
+
+        Ok(Db(db))
+    }
+
+    pub fn map<V>(&self, name: &str) -> Result<Map<V>, Error> {
+        let tree = self.0.open_tree(name).map_err(Error::storage)?;
+
+        Ok(Map {
+            tree,
+            value: PhantomData,
+        })
+    }
+
+    pub fn history<T>(&self, name: &str) -> Result<History<T>, Error> {
+        let tree = self
+            .0
+            .open_tree(format!("{}.history", name))
+            .map_err(Error::storage)?;
+
+        Ok(History {
+            tree,
+            window: MAX_HISTORY_SLOTS,
+            changes: PhantomData,
+        })
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(value).map_err(Error::storage)
+}
+
+fn decode<T: DeserializeOwned>(raw: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(raw).map_err(Error::storage)
+}
+
+fn decode_slot(raw: &[u8]) -> u64 {
+    let mut slot = [0u8; 8];
+    slot.copy_from_slice(raw);
+    u64::from_be_bytes(slot)
+}
+
+/// Values of a reducer by key, encoded as JSON
+pub struct Map<V> {
+    tree: sled::Tree,
+    value: PhantomData<V>,
+}
+
+impl<V: Serialize + DeserializeOwned> Map<V> {
+    pub fn get(&self, key: &str) -> Result<Option<V>, Error> {
+        match self.tree.get(key).map_err(Error::storage)? {
+            Some(raw) => Ok(Some(decode(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the value of the key, returns the previous one
+    pub fn insert(&self, key: &str, value: &V) -> Result<Option<V>, Error> {
+        match self
+            .tree
+            .insert(key, encode(value)?)
+            .map_err(Error::storage)?
+        {
+            Some(raw) => Ok(Some(decode(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the key, returns its value
+    pub fn remove(&self, key: &str) -> Result<Option<V>, Error> {
+        match self.tree.remove(key).map_err(Error::storage)? {
+            Some(raw) => Ok(Some(decode(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the value of the key or removes it, as undos restore what was
+    /// there before
+    pub fn restore(&self, key: &str, value: Option<&V>) -> Result<(), Error> {
+        match value {
+            Some(x) => self.insert(key, x)?,
+            None => self.remove(key)?,
+        };
+
+        Ok(())
+    }
+
+    /// Every entry, in key order
+    pub fn entries(&self) -> Result<Vec<(String, V)>, Error> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (key, raw) = entry.map_err(Error::storage)?;
+                let key = String::from_utf8_lossy(&key).to_string();
+                Ok((key, decode(&raw)?))
+            })
+            .collect()
+    }
+}
+
+/// Changes applied by the recent blocks of a reducer, to undo them on
+/// rollback
+///
+/// The persisted counterpart of [super::RollbackHistory]. The state of a
+/// reducer is written as its blocks are reduced, ahead of the storage, so a
+/// run that stops before the storage commits leaves changes behind. The
+/// blocks are replayed from the cursor on the next run: [History::rewind]
+/// hands back those changes so that the reducer reverts them, without
+/// undoing anything in the storage, before applying the replayed block.
+pub struct History<T> {
+    tree: sled::Tree,
+    window: u64,
+    changes: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> History<T> {
+    /// Keeps the changes of the blocks within this many slots of the last one,
+    /// instead of [MAX_HISTORY_SLOTS]
+    pub fn with_window(mut self, slots: u64) -> Self {
+        self.window = slots;
+        self
+    }
+
+    /// Remembers the changes of the block at the slot, forgetting the blocks
+    /// that fell out of the window
+    pub fn push(&self, slot: u64, changes: &T) -> Result<(), Error> {
+        self.tree
+            .insert(slot.to_be_bytes(), encode(changes)?)
+            .map_err(Error::storage)?;
+
+        while let Some((key, _)) = self.tree.first().map_err(Error::storage)? {
+            if decode_slot(&key).saturating_add(self.window) >= slot {
+                break;
+            }
+
+            self.tree.remove(key).map_err(Error::storage)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the blocks from the slot onwards and hands back their changes,
+    /// most recent first
+    fn take_from(&self, from: u64) -> Result<Vec<(u64, T)>, Error> {
+        let taken = self
+            .tree
+            .range(from.to_be_bytes()..)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::storage)?;
+
+        let mut undone = Vec::with_capacity(taken.len());
+
+        for (key, raw) in taken.into_iter().rev() {
+            self.tree.remove(&key).map_err(Error::storage)?;
+            undone.push((decode_slot(&key), decode(&raw)?));
+        }
+
+        Ok(undone)
+    }
+
+    /// Forgets the blocks after the point and hands back their changes, most
+    /// recent first
+    pub fn rollback(&self, point: &Point) -> Result<Vec<(u64, T)>, Error> {
+        match point {
+            Point::Origin => self.take_from(0),
+            Point::Specific(slot, _) => self.take_from(slot + 1),
+        }
+    }
+
+    /// Hands back the changes left by a previous run for the blocks at or
+    /// after the slot of a block about to be applied, most recent first
+    pub fn rewind(&self, slot: u64) -> Result<Vec<(u64, T)>, Error> {
+        self.take_from(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_rolls_back_and_rewinds_by_slot() {
+        let db = Db::temporary().unwrap();
+        let history = db.history::<u64>("test").unwrap();
+
+        for slot in [10, 20, 30, 40] {
+            history.push(slot, &(slot * 2)).unwrap();
+        }
+
+        let undone = history.rollback(&Point::Specific(20, vec![])).unwrap();
+        assert_eq!(undone, vec![(40, 80), (30, 60)]);
+
+        let rewound = history.rewind(20).unwrap();
+        assert_eq!(rewound, vec![(20, 40)]);
+
+        let rest = history.rollback(&Point::Origin).unwrap();
+        assert_eq!(rest, vec![(10, 20)]);
+    }
+
+    #[test]
+    fn history_forgets_blocks_out_of_the_window() {
+        let db = Db::temporary().unwrap();
+        let history = db.history::<u64>("test").unwrap().with_window(15);
+
+        for slot in [10, 20, 30] {
+            history.push(slot, &slot).unwrap();
+        }
+
+        let undone = history.rollback(&Point::Origin).unwrap();
+        assert_eq!(undone, vec![(30, 30), (20, 20)]);
+    }
+
+    #[test]
+    fn state_survives_reopening_the_db() {
+        let path = std::env::temp_dir().join(format!("scrolls-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let config = Config {
+            db_path: path.to_string_lossy().to_string(),
+        };
+
+        {
+            let db = config.open().unwrap();
+            let map = db.map::<String>("test").unwrap();
+
+            assert_eq!(map.insert("a", &"x".to_string()).unwrap(), None);
+            assert_eq!(
+                map.insert("a", &"y".to_string()).unwrap(),
+                Some("x".to_string())
+            );
+        }
+
+        let db = config.open().unwrap();
+        let map = db.map::<String>("test").unwrap();
+        assert_eq!(map.get("a").unwrap(), Some("y".to_string()));
+
+        map.restore("a", None).unwrap();
+        assert!(map.entries().unwrap().is_empty());
+
+        drop(map);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// A single pair of counters for the whole chain
+    #[default]
+    Global,
+    /// One pair of counters per epoch
+    Epoch,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub granularity: Option<Granularity>,
+}
+
+/// Counts outputs carrying native tokens vs outputs holding only ADA
+///
+/// Counters are kept under `{prefix}.multiasset` and `{prefix}.ada_only` (or
+/// `{prefix}.{epoch}.multiasset` and `{prefix}.{epoch}.ada_only` per epoch).
+/// The adoption ratio is `multiasset / (multiasset + ada_only)`; it's left to
+/// the clients since the storage can only increment the counters.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+fn carries_tokens(output: &alonzo::TransactionOutput) -> bool {
+    model::alonzo_assets(output)
+        .map(|x| !x.is_empty())
+        .unwrap_or(false)
+}
+
+impl Reducer {
+    fn key(&self, slot: u64, kind: &str) -> String {
+        let prefix = match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "token_outputs".to_string(),
+        };
+
+        match self.config.granularity.unwrap_or_default() {
+            Granularity::Global => format!("{}.{}", prefix, kind),
+            Granularity::Epoch => {
+                format!("{}.{}.{}", prefix, self.chain.epoch_for_slot(slot), kind)
+            }
+        }
+    }
+
+    fn collect(&self, slot: u64, tokens: bool, count: i64, counters: &mut HashMap<String, i64>) {
+        let kind = match tokens {
+            true => "multiasset",
+            false => "ada_only",
+        };
+
+        *counters.entry(self.key(slot, kind)).or_insert(0) += count;
+    }
+
+    fn send_counters(
+        &self,
+        counters: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, value) in counters.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * value).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut counters = HashMap::new();
+
+        let slot = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let slot = x.header.consensus_data.0.to_abs_slot();
+
+                let count: usize = x
+                    .body
+                    .tx_payload
+                    .iter()
+                    .map(|tx| tx.transaction.outputs.len())
+                    .sum();
+
+                // byron outputs can only hold ADA
+                if count > 0 {
+                    self.collect(slot, false, count as i64, &mut counters);
+                }
+
+                slot
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let slot = x.1.header.header_body.slot;
+
+                let outputs =
+                    x.1.transaction_bodies
+                        .iter()
+                        .flat_map(|tx| tx.iter())
+                        .filter_map(|c| match c {
+                            alonzo::TransactionBodyComponent::Outputs(x) => Some(x),
+                            _ => None,
+                        })
+                        .flat_map(|x| x.iter());
+
+                for tx_output in outputs {
+                    self.collect(slot, carries_tokens(tx_output), 1, &mut counters);
+                }
+
+                slot
+            }
+        };
+
+        self.send_counters(&counters, 1, output)?;
+
+        self.recent.push(slot, counters);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counters) in self.recent.rollback(point) {
+            self.send_counters(&counters, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::TokenOutputs(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    const POLICY: &str = "29d222ce763455e3d7a09a665ce554f00ac89d2e99a1a83d267170c6";
+
+    fn tx_with_mixed_outputs() -> model::MultiEraBlock {
+        block()
+            .tx(|t| {
+                t.output_with_assets(&address(1), 2_000_000, &[(POLICY, "MIN", 100_000)])
+                    .output(&address(2), 5_000_000)
+                    .output(&address(3), 3_000_000)
+            })
+            .build()
+    }
+
+    #[test]
+    fn outputs_are_counted_by_kind() {
+        let config = Config {
+            key_prefix: None,
+            granularity: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = harness.roll_forward(tx_with_mixed_outputs());
+
+        assert_eq!(harness.store().counter("token_outputs.multiasset"), Some(1));
+        assert_eq!(harness.store().counter("token_outputs.ada_only"), Some(2));
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(4), 1_000_000))
+            .build();
+
+        harness.roll_forward(second);
+        assert_eq!(harness.store().counter("token_outputs.ada_only"), Some(3));
+
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter("token_outputs.multiasset"), Some(1));
+        assert_eq!(harness.store().counter("token_outputs.ada_only"), Some(2));
+    }
+
+    #[test]
+    fn epoch_granularity_keys_the_counters_by_epoch() {
+        let config = Config {
+            key_prefix: None,
+            granularity: Some(Granularity::Epoch),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        harness.roll_forward(tx_with_mixed_outputs());
+
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+        let key = |kind: &str| format!("token_outputs.{}.{}", epoch, kind);
+
+        assert_eq!(harness.store().counter(&key("multiasset")), Some(1));
+        assert_eq!(harness.store().counter(&key("ada_only")), Some(2));
+        assert!(harness
+            .store()
+            .counter("token_outputs.multiasset")
+            .is_none());
+    }
+}
@@ -39,16 +39,14 @@ impl Reducer {
                 .body
                 .tx_payload
                 .iter()
-                .map(|_tx| self.increment_key(output))
-                .collect(),
+                .try_for_each(|_tx| self.increment_key(output)),
 
             model::MultiEraBlock::Byron(_) => Ok(()),
             model::MultiEraBlock::AlonzoCompatible(block) => block
                 .1
                 .transaction_bodies
                 .iter()
-                .map(|_tx| self.increment_key(output))
-                .collect(),
+                .try_for_each(|_tx| self.increment_key(output)),
         }
     }
 }
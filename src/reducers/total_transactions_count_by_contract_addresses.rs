@@ -1,7 +1,7 @@
 use pallas::ledger::primitives::alonzo;
 use serde::Deserialize;
 
-use crate::model;
+use crate::{crosscut, model};
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -40,29 +40,18 @@ impl Reducer {
                 _ => None,
             })
             .flat_map(|o| o.iter())
-            .enumerate()
-            .any(move |(_tx_idx, output)| {
-                fn get_bit_at(input: u8, n: u8) -> bool {
-                    if n < 32 {
-                        input & (1 << n) != 0
-                    } else {
-                        false
-                    }
-                }
+            .any(move |output| {
+                let is_smart_contract_address =
+                    crosscut::parse_output_address(output.address.as_slice()).has_script_payment();
 
-                // first byte of address is header
-                let first_byte_of_address = output.address.as_slice()[0];
-                // https://github.com/input-output-hk/cardano-ledger/blob/master/eras/alonzo/test-suite/cddl-files/alonzo.cddl#L135
-                let is_smart_contract_address = get_bit_at(first_byte_of_address, 4);
-
-                return is_smart_contract_address;
+                is_smart_contract_address
             });
 
         if is_smart_contract_transaction {
             return self.increment_for_contract_address(output);
         }
 
-        return Ok(());
+        Ok(())
     }
 
     pub fn reduce_block(
@@ -75,8 +64,7 @@ impl Reducer {
             model::MultiEraBlock::AlonzoCompatible(x) => {
                 x.1.transaction_bodies
                     .iter()
-                    .map(|tx| self.reduce_alonzo_compatible_tx(tx, output))
-                    .collect()
+                    .try_for_each(|tx| self.reduce_alonzo_compatible_tx(tx, output))
             }
         }
     }
@@ -8,6 +8,7 @@ use std::collections::HashSet;
 #[derive(Deserialize)]
 pub struct Config {
     pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
 }
 
 pub struct Reducer {
@@ -23,8 +24,8 @@ impl Reducer {
     ) -> Result<(), gasket::error::Error> {
         for contract_address in contract_addresses {
             let key = match &self.config.key_prefix {
-                Some(prefix) => format!("{}.{}", prefix, contract_address.to_string()),
-                None => format!("{}", contract_address.to_string()),
+                Some(prefix) => format!("{}.{}", prefix, contract_address),
+                None => contract_address.to_string(),
             };
 
             let crdt = model::CRDTCommand::PNCounter(key, "1".to_string());
@@ -40,6 +41,7 @@ impl Reducer {
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
         let hrp_addr = &self.address_hrp.clone();
+        let key_format = self.config.address_key_format.unwrap_or_default();
 
         let addresses: Vec<Option<String>> = tx
             .iter()
@@ -48,44 +50,29 @@ impl Reducer {
                 _ => None,
             })
             .flat_map(|o| o.iter())
-            .enumerate()
-            .map(move |(_tx_idx, output)| {
-                let address = output.to_bech32_address(hrp_addr).unwrap();
-
-                fn get_bit_at(input: u8, n: u8) -> bool {
-                    if n < 32 {
-                        input & (1 << n) != 0
-                    } else {
-                        false
-                    }
-                }
+            .map(move |output| {
+                let address = key_format.alonzo_output_key(output, hrp_addr).unwrap();
 
-                // first byte of address is header
-                let first_byte_of_address = output.address.as_slice()[0];
-                // https://github.com/input-output-hk/cardano-ledger/blob/master/eras/alonzo/test-suite/cddl-files/alonzo.cddl#L135
-                let is_smart_contract_address = get_bit_at(first_byte_of_address, 4);
+                let is_smart_contract_address =
+                    crosscut::parse_output_address(output.address.as_slice()).has_script_payment();
 
                 if is_smart_contract_address {
-                    return Some(address);
+                    return address;
                 }
 
-                return None::<String>;
+                None::<String>
             })
             .collect();
 
-        if addresses.len() == 0 {
+        if addresses.is_empty() {
             return Result::Ok(());
         }
 
-        let currated_addresses: Vec<String> = addresses
-            .into_iter()
-            .filter(|x| x.is_some())
-            .map(|x| x.unwrap())
-            .collect();
+        let currated_addresses: Vec<String> = addresses.into_iter().flatten().collect();
 
         let deduped_addresses: HashSet<String> = HashSet::from_iter(currated_addresses);
 
-        return self.increment_for_addresses(&deduped_addresses, output);
+        self.increment_for_addresses(&deduped_addresses, output)
     }
 
     pub fn reduce_block(
@@ -98,8 +85,7 @@ impl Reducer {
             model::MultiEraBlock::AlonzoCompatible(x) => {
                 x.1.transaction_bodies
                     .iter()
-                    .map(|tx| self.reduce_alonzo_compatible_tx(tx, output))
-                    .collect()
+                    .try_for_each(|tx| self.reduce_alonzo_compatible_tx(tx, output))
             }
         }
     }
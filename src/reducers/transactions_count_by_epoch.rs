@@ -31,7 +31,7 @@ impl Reducer {
             slot,
         );
 
-        return self.increment_key(epoch_no, output);
+        self.increment_key(epoch_no, output)
     }
 
     fn reduce_byron_compatible_tx(
@@ -45,7 +45,7 @@ impl Reducer {
             slot,
         );
 
-        return self.increment_key(epoch_no, output);
+        self.increment_key(epoch_no, output)
     }
 
     fn increment_key(
@@ -58,7 +58,7 @@ impl Reducer {
             None => "transactions_by_epoch",
         };
 
-        let key = format!("{}.{}", prefix, epoch_no.to_string());
+        let key = format!("{}.{}", prefix, epoch_no);
 
         let crdt = model::CRDTCommand::PNCounter(key, 1.to_string());
 
@@ -73,22 +73,18 @@ impl Reducer {
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
         match block {
-            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => x
-                .body
-                .tx_payload
-                .iter()
-                .map(|_tx| {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                x.body.tx_payload.iter().try_for_each(|_tx| {
                     self.reduce_byron_compatible_tx(x.header.consensus_data.0.to_abs_slot(), output)
                 })
-                .collect(),
+            }
 
             model::MultiEraBlock::Byron(_) => Ok(()),
-            model::MultiEraBlock::AlonzoCompatible(x) => x
-                .1
-                .transaction_bodies
-                .iter()
-                .map(|_tx| self.reduce_alonzo_compatible_tx(x.1.header.header_body.slot, output))
-                .collect(),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                x.1.transaction_bodies.iter().try_for_each(|_tx| {
+                    self.reduce_alonzo_compatible_tx(x.1.header.header_body.slot, output)
+                })
+            }
         }
     }
 }
@@ -97,10 +93,10 @@ impl Config {
     pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
         let reducer = Reducer {
             config: self,
-            shelley_known_slot: chain.shelley_known_slot.clone() as u64,
-            shelley_epoch_length: chain.shelley_epoch_length.clone() as u64,
-            byron_epoch_length: chain.byron_epoch_length.clone() as u64,
-            byron_slot_length: chain.byron_slot_length.clone() as u64,
+            shelley_known_slot: chain.shelley_known_slot,
+            shelley_epoch_length: chain.shelley_epoch_length as u64,
+            byron_epoch_length: chain.byron_epoch_length as u64,
+            byron_slot_length: chain.byron_slot_length as u64,
         };
 
         super::Plugin::TransactionsCountByEpoch(reducer)
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Only sum the tokens of these policies (hex), ADA is always summed
+    pub policies: Option<Vec<String>>,
+}
+
+/// Sums the value of the outputs created in each epoch
+///
+/// Lovelace goes to `{prefix}.{epoch}.lovelace` and the tokens of each policy,
+/// all of its assets together, to `{prefix}.{epoch}.{policy}`. This is the
+/// gross output volume: change returned to the sender is counted as well since
+/// it can't be told apart from a payment.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    policies: Option<HashSet<String>>,
+    recent: super::RollbackHistory<HashMap<String, i128>>,
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64, asset: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}.{}", prefix, epoch, asset),
+            None => format!("transfer_volume.{}.{}", epoch, asset),
+        }
+    }
+
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        crosscut::Allowlists {
+            addresses: None,
+            policies: self.policies.clone(),
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        self.policies = allowlists.policies;
+    }
+
+    fn is_tracked(&self, policy: &str) -> bool {
+        match &self.policies {
+            Some(x) => x.contains(policy),
+            None => true,
+        }
+    }
+
+    fn collect_alonzo_output(
+        &self,
+        output: &alonzo::TransactionOutput,
+        epoch: u64,
+        volume: &mut HashMap<String, i128>,
+    ) {
+        let lovelace = model::alonzo_lovelace(output);
+        *volume.entry(self.key(epoch, "lovelace")).or_insert(0) += lovelace as i128;
+
+        for (policy, tokens) in model::alonzo_assets(output).iter().flat_map(|x| x.iter()) {
+            let policy = hex::encode(policy.as_slice());
+
+            if !self.is_tracked(&policy) {
+                continue;
+            }
+
+            let amount: i128 = tokens.iter().map(|(_, x)| *x as i128).sum();
+            *volume.entry(self.key(epoch, &policy)).or_insert(0) += amount;
+        }
+    }
+
+    fn send_volume(
+        &self,
+        volume: &HashMap<String, i128>,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, amount) in volume.iter() {
+            let delta = model::clamp_delta(sign * amount);
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), delta.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = block.slot();
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        let mut volume = HashMap::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let lovelace: u64 = x
+                    .body
+                    .tx_payload
+                    .iter()
+                    .flat_map(|tx| tx.transaction.outputs.iter())
+                    .map(|x| x.amount)
+                    .sum();
+
+                volume.insert(self.key(epoch, "lovelace"), lovelace as i128);
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let outputs =
+                    x.1.transaction_bodies
+                        .iter()
+                        .flat_map(|tx| tx.iter())
+                        .filter_map(|c| match c {
+                            alonzo::TransactionBodyComponent::Outputs(x) => Some(x),
+                            _ => None,
+                        })
+                        .flat_map(|x| x.iter());
+
+                for tx_output in outputs {
+                    self.collect_alonzo_output(tx_output, epoch, &mut volume);
+                }
+            }
+        };
+
+        volume.retain(|_, x| *x != 0);
+
+        self.send_volume(&volume, 1, output)?;
+
+        self.recent.push(slot, volume);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, volume) in self.recent.rollback(point) {
+            self.send_volume(&volume, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn policies(&self) -> Result<Option<HashSet<String>>, Error> {
+        let policies = match &self.policies {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        policies
+            .iter()
+            .map(|raw| {
+                let policy = raw.trim().to_lowercase();
+
+                match hex::decode(&policy) {
+                    Ok(x) if x.len() == 28 => Ok(policy),
+                    _ => Err(Error::config(format!("invalid policy id {}", raw))),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.policies().map(|_| ())
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let policies = self
+            .policies()
+            .expect("policies are validated when loading the config");
+
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            policies,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::TransferVolume(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    const TRACKED: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const OTHER: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    #[test]
+    fn outputs_of_a_block_add_up_in_the_epoch_of_its_slot() {
+        let config = Config {
+            key_prefix: None,
+            policies: Some(vec![TRACKED.to_uppercase()]),
+        };
+
+        config.validate().unwrap();
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.epoch_for_slot(DEFAULT_SLOT);
+
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = harness.roll_forward(block().tx(|t| t.output(&address(9), 1_000_000)).build());
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| {
+                    t.output_with_assets(&address(1), 2_000_000, &[(TRACKED, "A", 10)])
+                        .output(&address(2), 3_000_000)
+                })
+                .tx(|t| {
+                    t.output_with_assets(
+                        &address(3),
+                        4_000_000,
+                        &[(TRACKED, "B", 5), (OTHER, "C", 7)],
+                    )
+                })
+                .build(),
+        );
+
+        let volume = |harness: &Harness, asset: &str| {
+            let key = format!("transfer_volume.{}.{}", epoch, asset);
+            harness.store().counter(&key)
+        };
+
+        assert_eq!(volume(&harness, "lovelace"), Some(10_000_000));
+        assert_eq!(volume(&harness, TRACKED), Some(15));
+        assert_eq!(volume(&harness, OTHER), None);
+
+        harness.roll_back(first);
+
+        assert_eq!(volume(&harness, "lovelace"), Some(1_000_000));
+        assert_eq!(volume(&harness, TRACKED), Some(0));
+    }
+
+    #[test]
+    fn invalid_policies_are_refused() {
+        let config = Config {
+            key_prefix: None,
+            policies: Some(vec!["abcd".to_string()]),
+        };
+
+        assert!(config.validate().is_err());
+    }
+}
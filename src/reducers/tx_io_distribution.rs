@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Lower bounds of each bucket, the last one is open-ended
+    pub boundaries: Option<Vec<usize>>,
+
+    /// Aggregate the counters per epoch instead of globally
+    pub per_epoch: Option<bool>,
+}
+
+const DEFAULT_BOUNDARIES: [usize; 5] = [1, 2, 3, 5, 10];
+
+/// Histogram of txs by their number of inputs and outputs
+///
+/// Counters are kept under `{prefix}.inputs.{bucket}` and
+/// `{prefix}.outputs.{bucket}` (or `{prefix}.{epoch}.inputs.{bucket}` when
+/// aggregating per epoch). With the default boundaries, the buckets are `1`,
+/// `2`, `3-4`, `5-9` and `10+`.
+pub struct Reducer {
+    config: Config,
+    boundaries: Vec<usize>,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+impl Reducer {
+    fn bucket(&self, count: usize) -> String {
+        let idx = match self.boundaries.iter().rposition(|b| *b <= count) {
+            Some(x) => x,
+            None => return format!("<{}", self.boundaries[0]),
+        };
+
+        let lower = self.boundaries[idx];
+
+        match self.boundaries.get(idx + 1) {
+            Some(next) if *next == lower + 1 => lower.to_string(),
+            Some(next) => format!("{}-{}", lower, next - 1),
+            None => format!("{}+", lower),
+        }
+    }
+
+    fn key(&self, slot: u64, kind: &str, count: usize) -> String {
+        let prefix = match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "tx_io_distribution".to_string(),
+        };
+
+        match self.config.per_epoch {
+            Some(true) => format!(
+                "{}.{}.{}.{}",
+                prefix,
+                self.chain.epoch_for_slot(slot),
+                kind,
+                self.bucket(count)
+            ),
+            _ => format!("{}.{}.{}", prefix, kind, self.bucket(count)),
+        }
+    }
+
+    fn collect_tx(
+        &self,
+        slot: u64,
+        inputs: usize,
+        outputs: usize,
+        counters: &mut HashMap<String, i64>,
+    ) {
+        *counters
+            .entry(self.key(slot, "inputs", inputs))
+            .or_insert(0) += 1;
+        *counters
+            .entry(self.key(slot, "outputs", outputs))
+            .or_insert(0) += 1;
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        slot: u64,
+        tx: &alonzo::TransactionBody,
+        counters: &mut HashMap<String, i64>,
+    ) {
+        let mut inputs = 0;
+        let mut outputs = 0;
+
+        for component in tx.iter() {
+            match component {
+                alonzo::TransactionBodyComponent::Inputs(x) => inputs = x.len(),
+                alonzo::TransactionBodyComponent::Outputs(x) => outputs = x.len(),
+                _ => (),
+            }
+        }
+
+        self.collect_tx(slot, inputs, outputs, counters);
+    }
+
+    fn send_counters(
+        &self,
+        counters: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, value) in counters.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * value).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut counters = HashMap::new();
+
+        let slot = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let slot = x.header.consensus_data.0.to_abs_slot();
+
+                for tx in x.body.tx_payload.iter() {
+                    let inputs = tx.transaction.inputs.len();
+                    let outputs = tx.transaction.outputs.len();
+                    self.collect_tx(slot, inputs, outputs, &mut counters);
+                }
+
+                slot
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let slot = x.1.header.header_body.slot;
+
+                for tx in x.1.transaction_bodies.iter() {
+                    self.collect_alonzo_compatible_tx(slot, tx, &mut counters);
+                }
+
+                slot
+            }
+        };
+
+        self.send_counters(&counters, 1, output)?;
+
+        self.recent.push(slot, counters);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counters) in self.recent.rollback(point) {
+            self.send_counters(&counters, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let mut boundaries = self
+            .boundaries
+            .clone()
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| DEFAULT_BOUNDARIES.to_vec());
+
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let reducer = Reducer {
+            config: self,
+            boundaries,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::TxIoDistribution(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn tx_lands_in_its_buckets_until_rolled_back() {
+        let config = Config {
+            key_prefix: None,
+            boundaries: None,
+            per_epoch: None,
+        };
+
+        let mut harness =
+            Harness::new(vec![config.plugin(&crosscut::ChainWellKnownInfo::mainnet())]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 1_000_000)
+                    .output(&address(1), 2_000_000)
+                    .output(&address(1), 3_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let spend = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .input(funding, 1)
+                    .input(funding, 2)
+                    .output(&address(2), 4_000_000)
+                    .output(&address(3), 1_800_000)
+            })
+            .build();
+
+        harness.roll_forward(spend);
+
+        let counter = |key: &str| {
+            harness
+                .store()
+                .counter(&format!("tx_io_distribution.{}", key))
+        };
+        assert_eq!(counter("inputs.3-4"), Some(1));
+        assert_eq!(counter("outputs.2"), Some(1));
+        assert_eq!(counter("outputs.3-4"), Some(1));
+
+        harness.roll_back(first);
+
+        let counter = |key: &str| {
+            harness
+                .store()
+                .counter(&format!("tx_io_distribution.{}", key))
+        };
+        assert_eq!(counter("inputs.3-4"), Some(0));
+        assert_eq!(counter("outputs.2"), Some(0));
+        assert_eq!(counter("outputs.3-4"), Some(1));
+    }
+}
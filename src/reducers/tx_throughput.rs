@@ -0,0 +1,264 @@
+use pallas::ledger::primitives::byron;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThroughputResolution {
+    #[default]
+    Slot,
+    Minute,
+    Epoch,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub resolution: Option<ThroughputResolution>,
+}
+
+const SECONDS_PER_MINUTE: u64 = 60;
+
+/// Records a time series of the number of txs per slot
+///
+/// The tx count of each bucket is kept under `{prefix}.count.{bucket}` and
+/// every block appends a `{bucket}:{count}` member to the sorted set
+/// `{prefix}`, scored by the first slot of the bucket. Buckets are single
+/// slots by default; the `minute` and `epoch` resolutions keep a single point
+/// per bucket to bound the size of the series.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<(u64, i64)>,
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix,
+            None => "tx_throughput",
+        }
+    }
+
+    fn resolution(&self) -> ThroughputResolution {
+        self.config.resolution.unwrap_or_default()
+    }
+
+    fn count_key(&self, bucket: u64) -> String {
+        format!("{}.count.{}", self.prefix(), bucket)
+    }
+
+    fn bucket(&self, slot: u64, known: u64, slot_length: u32, epoch_length: u64) -> u64 {
+        let size = match self.resolution() {
+            ThroughputResolution::Slot => return slot,
+            ThroughputResolution::Minute => (SECONDS_PER_MINUTE / slot_length.max(1) as u64).max(1),
+            ThroughputResolution::Epoch => epoch_length,
+        };
+
+        known + ((slot - known) / size) * size
+    }
+
+    fn byron_bucket(&self, slot: u64) -> u64 {
+        let epoch_slots =
+            self.chain.byron_epoch_length as u64 / self.chain.byron_slot_length as u64;
+
+        self.bucket(
+            slot,
+            self.chain.byron_known_slot,
+            self.chain.byron_slot_length,
+            epoch_slots,
+        )
+    }
+
+    fn shelley_bucket(&self, slot: u64) -> u64 {
+        self.bucket(
+            slot,
+            self.chain.shelley_known_slot,
+            self.chain.shelley_slot_length,
+            self.chain.shelley_epoch_length as u64,
+        )
+    }
+
+    fn send_snapshot(
+        &self,
+        bucket: u64,
+        delta: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = model::CRDTCommand::CounterSnapshot(
+            self.count_key(bucket),
+            delta,
+            self.prefix().to_string(),
+            bucket,
+        );
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let (slot, bucket, count) = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let slot = x.header.consensus_data.0.to_abs_slot();
+                (slot, self.byron_bucket(slot), x.body.tx_payload.len())
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let slot = x.1.header.header_body.slot;
+                (
+                    slot,
+                    self.shelley_bucket(slot),
+                    x.1.transaction_bodies.len(),
+                )
+            }
+        };
+
+        self.send_snapshot(bucket, count as i64, output)?;
+
+        self.recent.push(slot, (bucket, count as i64));
+
+        Ok(())
+    }
+
+    /// Removes the tx count of the blocks after the rollback point. With the
+    /// `slot` resolution the points of those blocks are removed, otherwise the
+    /// point of the bucket is rewritten with the reverted count.
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, (bucket, count)) in self.recent.rollback(point) {
+            match self.resolution() {
+                ThroughputResolution::Slot => {
+                    let crdt = model::CRDTCommand::AnyWriteWinsRemove(self.count_key(bucket));
+                    output.send(gasket::messaging::Message::from(crdt))?;
+
+                    let crdt = model::CRDTCommand::SortedSetRemoveRange(
+                        self.prefix().to_string(),
+                        bucket,
+                        bucket,
+                    );
+                    output.send(gasket::messaging::Message::from(crdt))?;
+                }
+                _ => self.send_snapshot(bucket, -count, output)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::TxThroughput(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, Harness, DEFAULT_SLOT};
+
+    const MINUTE: u64 = 49_999_980;
+
+    fn reducer(resolution: ThroughputResolution) -> super::super::Plugin {
+        let config = Config {
+            key_prefix: None,
+            resolution: Some(resolution),
+        };
+
+        config.plugin(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    fn block_with_txs(slot: u64, number: u64, txs: u8) -> model::MultiEraBlock {
+        (0..txs)
+            .fold(block().slot(slot).number(number), |b, seed| {
+                b.tx(|t| t.output(&address(seed), 2_000_000))
+            })
+            .build()
+    }
+
+    #[test]
+    fn tx_counts_land_in_their_minute_and_rollback_removes_the_last() {
+        let mut harness = Harness::new(vec![reducer(ThroughputResolution::Minute)]);
+
+        harness.roll_forward(block_with_txs(DEFAULT_SLOT, 1, 2));
+        let second = harness.roll_forward(block_with_txs(DEFAULT_SLOT + 15, 2, 1));
+        harness.roll_forward(block_with_txs(DEFAULT_SLOT + 45, 3, 3));
+
+        {
+            let store = harness.store();
+            let series = store.scores("tx_throughput").unwrap();
+
+            // the first two blocks share the minute starting 20 slots earlier
+            assert_eq!(
+                store.counter(&format!("tx_throughput.count.{}", MINUTE)),
+                Some(3)
+            );
+            assert_eq!(
+                store.counter(&format!("tx_throughput.count.{}", MINUTE + 60)),
+                Some(3)
+            );
+            assert_eq!(series.len(), 2);
+            assert_eq!(series.get(&format!("{}:3", MINUTE)), Some(&(MINUTE as i64)));
+        }
+
+        harness.roll_back(second);
+
+        let store = harness.store();
+        let series = store.scores("tx_throughput").unwrap();
+
+        assert_eq!(
+            store.counter(&format!("tx_throughput.count.{}", MINUTE)),
+            Some(3)
+        );
+        assert_eq!(
+            store.counter(&format!("tx_throughput.count.{}", MINUTE + 60)),
+            Some(0)
+        );
+        assert_eq!(
+            series.get(&format!("{}:0", MINUTE + 60)),
+            Some(&(MINUTE as i64 + 60))
+        );
+    }
+
+    #[test]
+    fn rollback_removes_the_points_of_the_rolled_back_slots() {
+        let mut harness = Harness::new(vec![reducer(ThroughputResolution::Slot)]);
+
+        harness.roll_forward(block_with_txs(DEFAULT_SLOT, 1, 2));
+        let second = harness.roll_forward(block_with_txs(DEFAULT_SLOT + 1, 2, 1));
+        harness.roll_forward(block_with_txs(DEFAULT_SLOT + 2, 3, 3));
+
+        assert_eq!(harness.store().scores("tx_throughput").unwrap().len(), 3);
+
+        harness.roll_back(second);
+
+        let store = harness.store();
+        let series = store.scores("tx_throughput").unwrap();
+
+        assert_eq!(series.len(), 2);
+        assert_eq!(
+            series.get(&format!("{}:1", DEFAULT_SLOT + 1)),
+            Some(&(DEFAULT_SLOT as i64 + 1))
+        );
+        assert_eq!(
+            store.counter(&format!("tx_throughput.count.{}", DEFAULT_SLOT + 2)),
+            None
+        );
+    }
+}
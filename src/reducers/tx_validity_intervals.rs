@@ -0,0 +1,211 @@
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::model;
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Indexes txs by the slot in which they expire (their TTL)
+///
+/// Members of the sorted set have the format `{tx_hash},{start},{ttl}`, where
+/// `start` is empty if the tx doesn't define a validity start. Txs without a
+/// TTL never expire and are not indexed.
+///
+/// Since members are scored by TTL instead of the slot of their block, the
+/// storage can't drop the members of the reverted blocks by range. The
+/// members of the recent blocks are kept in the state db instead, and a
+/// rollback removes each of them by taking its score down to zero, including
+/// the ones applied by a previous run.
+pub struct Reducer {
+    config: Config,
+    recent: state::History<Vec<(String, u64)>>,
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "tx_validity_intervals".to_string(),
+        }
+    }
+
+    fn reduce_alonzo_compatible_tx(
+        &mut self,
+        tx: &alonzo::TransactionBody,
+        output: &mut super::OutputPort,
+        applied: &mut Vec<(String, u64)>,
+    ) -> Result<(), gasket::error::Error> {
+        let mut ttl = None;
+        let mut start = None;
+
+        for component in tx.iter() {
+            match component {
+                alonzo::TransactionBodyComponent::Ttl(x) => ttl = Some(*x),
+                alonzo::TransactionBodyComponent::ValidityIntervalStart(x) => start = Some(*x),
+                _ => (),
+            }
+        }
+
+        let ttl = match ttl {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let start = start.map(|x| x.to_string()).unwrap_or_default();
+        let member = format!("{},{},{}", tx.to_hash(), start, ttl);
+
+        let crdt = model::CRDTCommand::LastWriteWins(self.key(), member.clone(), ttl);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        applied.push((member, ttl));
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+
+        // members left by a run that stopped before the storage committed
+        self.recent.rewind(slot).or_work_err()?;
+
+        let mut applied = Vec::new();
+
+        for tx in x.1.transaction_bodies.iter() {
+            self.reduce_alonzo_compatible_tx(tx, output, &mut applied)?;
+        }
+
+        self.recent.push(slot, &applied).or_work_err()?;
+
+        Ok(())
+    }
+
+    /// Removes the txs of the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point).or_work_err()? {
+            for (member, ttl) in applied {
+                let delta = -model::clamp_delta(ttl as i128);
+                let crdt = model::CRDTCommand::SortedSetAdd(self.key(), member, delta);
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, state: &state::Db) -> Result<super::Plugin, crate::Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "tx_validity_intervals".to_string(),
+        };
+
+        let reducer = Reducer {
+            recent: state.history(&name)?,
+            config: self,
+        };
+
+        Ok(super::Plugin::TxValidityIntervals(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, state_db, tx_hash, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        Config { key_prefix: None }.plugin(state).unwrap()
+    }
+
+    #[test]
+    fn rollback_removes_txs_of_reverted_blocks() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = block()
+            .tx(|t| t.output(&address(1), 5_000_000).ttl(DEFAULT_SLOT + 100))
+            .build();
+
+        let kept = format!("{},,{}", tx_hash(&first, 0), DEFAULT_SLOT + 100);
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.output(&address(2), 1_000_000)
+                    .validity_start(DEFAULT_SLOT + 10)
+                    .ttl(DEFAULT_SLOT + 100)
+            })
+            .tx(|t| t.output(&address(3), 1_000_000))
+            .build();
+
+        let reverted = format!(
+            "{},{},{}",
+            tx_hash(&second, 0),
+            DEFAULT_SLOT + 10,
+            DEFAULT_SLOT + 100
+        );
+
+        harness.roll_forward(second);
+
+        let scores = harness
+            .store()
+            .scores("tx_validity_intervals")
+            .unwrap()
+            .clone();
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores.get(&reverted), Some(&(DEFAULT_SLOT as i64 + 100)));
+
+        harness.roll_back(first);
+
+        let scores = harness
+            .store()
+            .scores("tx_validity_intervals")
+            .unwrap()
+            .clone();
+        assert_eq!(scores.keys().collect::<Vec<_>>(), vec![&kept]);
+    }
+
+    #[test]
+    fn rollback_removes_txs_applied_by_a_previous_run() {
+        let state = state_db();
+        let mut harness = Harness::new(vec![reducer(&state)]);
+
+        let first = harness.roll_forward(block().build());
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(2), 1_000_000).ttl(DEFAULT_SLOT + 100))
+            .build();
+
+        harness.roll_forward(second);
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        assert!(harness.store().scores("tx_validity_intervals").is_none());
+    }
+}
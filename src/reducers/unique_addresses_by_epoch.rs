@@ -0,0 +1,222 @@
+use std::collections::{HashMap, HashSet};
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+
+    /// Use a HyperLogLog instead of an exact set of addresses
+    pub approximate: Option<bool>,
+}
+
+/// Tracks the distinct addresses active (in inputs or outputs) in each epoch
+///
+/// In exact mode, addresses are members of the set `{prefix}.{epoch}` and the
+/// count is its cardinality (`SCARD`). In approximate mode, addresses are added
+/// to a HyperLogLog under the same key and the count is read with `PFCOUNT`.
+///
+/// In exact mode, rollbacks remove addresses first seen in the current epoch
+/// after the rollback point (as far as this process has seen). A HyperLogLog
+/// can't remove members, so rollbacks in approximate mode are lossy: addresses
+/// from rolled-back blocks remain counted.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    epoch: Option<u64>,
+    first_seen: HashMap<String, u64>,
+}
+
+impl Reducer {
+    fn key(&self, epoch: u64) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, epoch),
+            None => format!("unique_addresses_by_epoch.{}", epoch),
+        }
+    }
+
+    fn key_format(&self) -> crosscut::AddressKeyFormat {
+        self.config.address_key_format.unwrap_or_default()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.config.approximate.unwrap_or(false)
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        addresses: &mut HashSet<String>,
+    ) -> Result<(), gasket::error::Error> {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+
+                if let Some(utxo) = ctx.find_utxo(hash, *idx as u64) {
+                    let address = self
+                        .key_format()
+                        .output_key_ctx(utxo, &self.chain.address_hrp, ctx)
+                        .or_work_err()?;
+
+                    addresses.extend(address);
+                }
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            let address = self.key_format().byron_output_key(output).or_work_err()?;
+            addresses.extend(address);
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        ctx: &model::BlockContext,
+        addresses: &mut HashSet<String>,
+    ) -> Result<(), gasket::error::Error> {
+        for component in tx.iter() {
+            match component {
+                alonzo::TransactionBodyComponent::Inputs(inputs) => {
+                    for input in inputs.iter() {
+                        if let Some(utxo) = ctx.find_utxo(&input.transaction_id, input.index) {
+                            let address = self
+                                .key_format()
+                                .output_key_ctx(utxo, &self.chain.address_hrp, ctx)
+                                .or_work_err()?;
+
+                            addresses.extend(address);
+                        }
+                    }
+                }
+                alonzo::TransactionBodyComponent::Outputs(outputs) => {
+                    for output in outputs.iter() {
+                        let address = self
+                            .key_format()
+                            .alonzo_output_key_ctx(output, &self.chain.address_hrp, ctx)
+                            .or_work_err()?;
+
+                        addresses.extend(address);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut addresses = HashSet::new();
+
+        let slot = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut addresses)?;
+                }
+
+                x.header.consensus_data.0.to_abs_slot()
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                for tx in x.1.transaction_bodies.iter() {
+                    self.collect_alonzo_compatible_tx(tx, ctx, &mut addresses)?;
+                }
+
+                x.1.header.header_body.slot
+            }
+        };
+
+        let epoch = self.chain.epoch_for_slot(slot);
+
+        if self.epoch != Some(epoch) {
+            self.epoch = Some(epoch);
+            self.first_seen.clear();
+        }
+
+        let key = self.key(epoch);
+
+        for address in addresses {
+            if self.is_approximate() {
+                let crdt = model::CRDTCommand::HyperLogLogAdd(key.clone(), address);
+                output.send(gasket::messaging::Message::from(crdt))?;
+                continue;
+            }
+
+            if self.first_seen.contains_key(&address) {
+                continue;
+            }
+
+            self.first_seen.insert(address.clone(), slot);
+
+            let crdt = model::CRDTCommand::GrowOnlySetAdd(key.clone(), address);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if self.is_approximate() {
+            log::warn!("rollback can't be applied to approximate unique address counts");
+            return Ok(());
+        }
+
+        let epoch = match self.epoch {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let slot = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        let undone: Vec<_> = self
+            .first_seen
+            .iter()
+            .filter(|(_, s)| slot.map(|x| **s > x).unwrap_or(true))
+            .map(|(address, _)| address.clone())
+            .collect();
+
+        for address in undone {
+            self.first_seen.remove(&address);
+
+            let crdt = model::CRDTCommand::SetRemove(self.key(epoch), address);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            epoch: None,
+            first_seen: HashMap::new(),
+        };
+
+        super::Plugin::UniqueAddressesByEpoch(reducer)
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::byron;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Lower bounds in epochs of the buckets after the first one, in
+    /// increasing order. Defaults to 1 and 5 epochs.
+    pub boundaries: Option<Vec<u64>>,
+}
+
+const DEFAULT_BOUNDARIES: [u64; 2] = [1, 5];
+
+/// Counts the spent utxos by how long they stayed unspent
+///
+/// The age of a utxo is the slot that spends it minus the slot that produced
+/// it, measured in Shelley epochs. Each bucket is a counter under
+/// `{prefix}.{lower_bound}`, eg: `utxo_age_histogram.0` counts the utxos spent
+/// within their first epoch. Requires the enrichment stage, which records the
+/// creation slot of the outputs it indexes. Outputs indexed before that are
+/// skipped.
+pub struct Reducer {
+    config: Config,
+    boundaries: Vec<u64>,
+    epoch_length: u64,
+    recent: super::RollbackHistory<HashMap<u64, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, bucket: u64) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, bucket),
+            None => format!("utxo_age_histogram.{}", bucket),
+        }
+    }
+
+    /// Lower bound of the bucket that holds the age
+    fn bucket(&self, age_slots: u64) -> u64 {
+        let age = age_slots / self.epoch_length;
+
+        self.boundaries
+            .iter()
+            .rev()
+            .find(|x| **x <= age)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn collect_spent(
+        &self,
+        slot: u64,
+        tx_hash: &Hash<32>,
+        idx: u64,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<u64, i64>,
+    ) {
+        match ctx.find_utxo_slot(tx_hash, idx) {
+            Some(created) => {
+                let bucket = self.bucket(slot.saturating_sub(created));
+                *deltas.entry(bucket).or_insert(0) += 1;
+            }
+            None => log::debug!("missing creation slot of utxo in block context"),
+        }
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &HashMap<u64, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (bucket, delta) in deltas.iter() {
+            let crdt = model::CRDTCommand::PNCounter(self.key(*bucket), (sign * delta).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = block.slot();
+        let mut deltas = HashMap::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    for input in tx.transaction.inputs.iter() {
+                        if let byron::TxIn::Variant0(x) = input {
+                            let (hash, idx) = &x.0;
+                            self.collect_spent(slot, hash, *idx as u64, ctx, &mut deltas);
+                        }
+                    }
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    for input in model::spent_inputs(tx, failed.contains(&idx)) {
+                        self.collect_spent(
+                            slot,
+                            &input.transaction_id,
+                            input.index,
+                            ctx,
+                            &mut deltas,
+                        );
+                    }
+                }
+            }
+        };
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(slot, deltas);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    fn boundaries(&self) -> Vec<u64> {
+        match &self.boundaries {
+            Some(x) => x.clone(),
+            None => DEFAULT_BOUNDARIES.to_vec(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let boundaries = self.boundaries();
+
+        if boundaries.windows(2).any(|x| x[0] >= x[1]) {
+            return Err(Error::config(
+                "boundaries of UtxoAgeHistogram should be in increasing order",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            boundaries: self.boundaries(),
+            epoch_length: (chain.shelley_epoch_length as u64).max(1),
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::UtxoAgeHistogram(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn spent_utxos_land_in_the_bucket_of_their_age() {
+        let config = Config {
+            key_prefix: None,
+            boundaries: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let epoch = chain.shelley_epoch_length as u64;
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 2_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .build();
+
+        let funding = tx_hash(&first, 0);
+        harness.roll_forward(first);
+
+        // spent within its first epoch
+        let second = harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 100)
+                .number(2)
+                .tx(|t| t.input(funding, 0).output(&address(3), 1_800_000))
+                .build(),
+        );
+
+        // spent two epochs and a bit after it was produced
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 2 * epoch + 100)
+                .number(3)
+                .tx(|t| t.input(funding, 1).output(&address(4), 2_800_000))
+                .build(),
+        );
+
+        let buckets = |harness: &Harness| {
+            let store = harness.store();
+
+            [0, 1, 5].map(|x| store.counter(&format!("utxo_age_histogram.{}", x)))
+        };
+
+        assert_eq!(buckets(&harness), [Some(1), Some(1), None]);
+
+        harness.roll_back(second);
+
+        assert_eq!(buckets(&harness), [Some(1), Some(0), None]);
+    }
+}
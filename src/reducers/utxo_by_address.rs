@@ -1,21 +1,46 @@
+use std::collections::HashSet;
+
 use gasket::error::AsWorkError;
 use pallas::crypto::hash::Hash;
-use pallas::ledger::primitives::{alonzo, byron};
+use pallas::ledger::primitives::{alonzo, byron, Fragment};
 use serde::Deserialize;
 
-use crate::{crosscut, model};
+use crate::{crosscut, model, Error};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub key_prefix: Option<String>,
+    pub address_key_format: Option<crosscut::AddressKeyFormat>,
+
+    /// Only track utxos locked at these addresses (bech32 or hex)
+    pub addresses: Option<Vec<String>>,
 }
 
 pub struct Reducer {
     config: Config,
     address_hrp: String,
+    allowlist: Option<HashSet<Vec<u8>>>,
 }
 
 impl Reducer {
+    fn is_allowed(&self, address: &[u8]) -> bool {
+        match &self.allowlist {
+            Some(allowed) => allowed.contains(address),
+            None => true,
+        }
+    }
+
+    pub fn allowlists(&self) -> crosscut::Allowlists {
+        crosscut::Allowlists {
+            addresses: self.allowlist.clone(),
+            policies: None,
+        }
+    }
+
+    pub fn set_allowlists(&mut self, allowlists: crosscut::Allowlists) {
+        self.allowlist = allowlists.addresses;
+    }
+
     fn send_set_add(
         &mut self,
         address: &str,
@@ -47,11 +72,26 @@ impl Reducer {
             .outputs
             .iter()
             .enumerate()
-            .map(move |(tx_idx, tx)| {
-                let address = tx.address.to_addr_string().or_work_err()?;
-                self.send_set_add(&address, tx_hash, tx_idx, output)
+            .try_for_each(move |(tx_idx, tx)| {
+                if self.allowlist.is_some() {
+                    let address = tx
+                        .address
+                        .encode_fragment()
+                        .map_err(Error::from)
+                        .or_work_err()?;
+
+                    if !self.is_allowed(&address) {
+                        return Ok(());
+                    }
+                }
+
+                let key_format = self.config.address_key_format.unwrap_or_default();
+
+                match key_format.byron_output_key(tx).or_work_err()? {
+                    Some(address) => self.send_set_add(&address, tx_hash, tx_idx, output),
+                    None => Ok(()),
+                }
             })
-            .collect()
     }
 
     fn reduce_alonzo_compatible_tx(
@@ -68,13 +108,21 @@ impl Reducer {
             })
             .flat_map(|o| o.iter())
             .enumerate()
-            .map(move |(tx_idx, tx_output)| {
-                let address = tx_output
-                    .to_bech32_address(&self.address_hrp)
-                    .or_work_err()?;
-                self.send_set_add(&address, tx_hash, tx_idx, output)
+            .try_for_each(move |(tx_idx, tx_output)| {
+                if !self.is_allowed(tx_output.address.as_slice()) {
+                    return Ok(());
+                }
+
+                let key_format = self.config.address_key_format.unwrap_or_default();
+
+                match key_format
+                    .alonzo_output_key(tx_output, &self.address_hrp)
+                    .or_work_err()?
+                {
+                    Some(address) => self.send_set_add(&address, tx_hash, tx_idx, output),
+                    None => Ok(()),
+                }
             })
-            .collect()
     }
 
     pub fn reduce_block(
@@ -87,26 +135,178 @@ impl Reducer {
                 .body
                 .tx_payload
                 .iter()
-                .map(|tx| self.reduce_byron_tx(tx, output))
-                .collect(),
+                .try_for_each(|tx| self.reduce_byron_tx(tx, output)),
             model::MultiEraBlock::Byron(_) => Ok(()),
             model::MultiEraBlock::AlonzoCompatible(x) => {
                 x.1.transaction_bodies
                     .iter()
-                    .map(|tx| self.reduce_alonzo_compatible_tx(tx, output))
-                    .collect()
+                    .try_for_each(|tx| self.reduce_alonzo_compatible_tx(tx, output))
             }
         }
     }
 }
 
 impl Config {
+    /// Decodes the address allowlist into the raw form compared against outputs
+    pub fn allowlist(
+        &self,
+        chain: &crosscut::ChainWellKnownInfo,
+    ) -> Result<Option<HashSet<Vec<u8>>>, Error> {
+        let addresses = match &self.addresses {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let parsed = addresses
+            .iter()
+            .map(|x| crosscut::parse_config_address(x, chain))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(parsed))
+    }
+
+    pub fn validate(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<(), Error> {
+        self.allowlist(chain).map(|_| ())
+    }
+
     pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let allowlist = self
+            .allowlist(chain)
+            .expect("allowlist is validated when loading the config");
+
         let reducer = Reducer {
             config: self,
             address_hrp: chain.address_hrp.clone(),
+            allowlist,
         };
 
         super::Plugin::UtxoByAddress(reducer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CIP-19 test vectors of a base address with key credentials
+    const MAINNET: &str = "addr1qx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzer3n0d3vllmyqwsx5wktcd8cc3sq835lu7drv2xwl2wywfgse35a3x";
+    const TESTNET: &str = "addr_test1qz2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzer3n0d3vllmyqwsx5wktcd8cc3sq835lu7drv2xwl2wywfgs68faae";
+    const MAINNET_HEX: &str = "019493315cd92eb5d8c4304e67b7e16ae36d61d34502694657811a2c8e337b62cfff6403a06a3acbc34f8c46003c69fe79a3628cefa9c47251";
+
+    fn config(addresses: &[&str]) -> Config {
+        Config {
+            key_prefix: None,
+            address_key_format: None,
+            addresses: Some(addresses.iter().map(|x| x.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn valid_entries_are_normalized_to_raw_bytes() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let padded_upper = format!("  {} ", MAINNET.to_uppercase());
+
+        let allowlist = config(&[MAINNET, &padded_upper])
+            .allowlist(&chain)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(allowlist.len(), 1);
+        assert!(allowlist.contains(&hex::decode(MAINNET_HEX).unwrap()));
+    }
+
+    #[test]
+    fn entries_of_another_network_are_rejected() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let err = config(&[MAINNET, TESTNET]).validate(&chain).unwrap_err();
+
+        assert!(matches!(err, Error::ConfigError(_)));
+        assert!(err.to_string().contains("expected prefix addr"));
+
+        let testnet = crosscut::ChainWellKnownInfo::testnet();
+        assert!(config(&[TESTNET]).validate(&testnet).is_ok());
+        assert!(config(&[MAINNET_HEX]).validate(&testnet).is_err());
+    }
+
+    #[test]
+    fn hex_entries_are_accepted() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+
+        let allowlist = config(&[&MAINNET_HEX.to_uppercase()])
+            .allowlist(&chain)
+            .unwrap()
+            .unwrap();
+
+        let expected = config(&[MAINNET]).allowlist(&chain).unwrap().unwrap();
+        assert_eq!(allowlist, expected);
+
+        assert!(config(&[]).allowlist(&chain).unwrap().unwrap().is_empty());
+        assert!(Config {
+            addresses: None,
+            ..config(&[])
+        }
+        .allowlist(&chain)
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn reloaded_allowlist_applies_from_the_next_block() {
+        use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+        let reducer = Config {
+            key_prefix: Some("utxos".into()),
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+            ..config(&[&address(1)])
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let scope = crosscut::ScopeHandle::default();
+        let mut harness = Harness::with_scope(vec![reducer.plugin(&chain)], scope.clone());
+
+        let utxos = |harness: &Harness, seed| {
+            let key = format!("utxos.{}", address(seed));
+            harness.store().members(&key).cloned().unwrap_or_default()
+        };
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 2_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .build();
+
+        let first_tx = tx_hash(&first, 0);
+        harness.roll_forward(first);
+
+        assert_eq!(utxos(&harness, 1).len(), 1);
+        assert!(utxos(&harness, 2).is_empty());
+
+        let expanded = config(&[&address(1), &address(2)])
+            .allowlist(&chain)
+            .unwrap();
+
+        scope.replace(crosscut::Scope {
+            filters: Default::default(),
+            allowlists: vec![crosscut::Allowlists {
+                addresses: expanded,
+                policies: None,
+            }],
+        });
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(2), 1_000_000))
+            .build();
+
+        let second_tx = tx_hash(&second, 0);
+        harness.roll_forward(second);
+
+        // the earlier output isn't captured retroactively
+        let captured = utxos(&harness, 2);
+        assert_eq!(captured.len(), 1);
+        assert!(captured.contains(&format!("{}:0", second_tx)));
+        assert!(!captured.contains(&format!("{}:1", first_tx)));
+    }
+}
@@ -0,0 +1,223 @@
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::model;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Net change of the utxo set caused by a block
+#[derive(Default)]
+struct Delta {
+    count: i128,
+    lovelace: i128,
+}
+
+impl Delta {
+    fn produced(&mut self, lovelace: u64) {
+        self.count += 1;
+        self.lovelace += lovelace as i128;
+    }
+
+    /// Unresolved inputs are left out, so that the totals never drop below
+    /// what was produced
+    fn spent(&mut self, utxo: Option<&model::MultiEraOutput>) {
+        match utxo {
+            Some(utxo) => {
+                self.count -= 1;
+                self.lovelace -= utxo.lovelace() as i128;
+            }
+            None => log::warn!("missing utxo in block context"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.lovelace == 0
+    }
+}
+
+/// Keeps the size of the live utxo set
+///
+/// `{prefix}.count` is the number of unspent outputs and `{prefix}.lovelace`
+/// the lovelace that they hold. Outputs increment the counters and spent
+/// inputs decrement them, so they only describe the utxo set when syncing
+/// from origin. Requires the enrichment stage to resolve the value of spent
+/// inputs.
+pub struct Reducer {
+    config: Config,
+    recent: super::RollbackHistory<Delta>,
+}
+
+impl Reducer {
+    fn key(&self, suffix: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, suffix),
+            None => format!("utxo_set_size.{}", suffix),
+        }
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        delta: &mut Delta,
+    ) {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+                delta.spent(ctx.find_utxo(hash, *idx as u64));
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            delta.produced(output.amount);
+        }
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        delta: &mut Delta,
+    ) {
+        for input in model::spent_inputs(tx, failed) {
+            delta.spent(ctx.find_utxo(&input.transaction_id, input.index));
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            let lovelace = model::alonzo_lovelace(output);
+
+            delta.produced(lovelace);
+        }
+    }
+
+    fn send_delta(
+        &self,
+        delta: &Delta,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let values = [("count", delta.count), ("lovelace", delta.lovelace)];
+
+        for (suffix, value) in values {
+            if value == 0 {
+                continue;
+            }
+
+            let value = model::clamp_delta(sign * value);
+            let crdt = model::CRDTCommand::PNCounter(self.key(suffix), value.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut delta = Delta::default();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut delta);
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut delta);
+                }
+            }
+        };
+
+        if delta.is_empty() {
+            return Ok(());
+        }
+
+        self.send_delta(&delta, 1, output)?;
+
+        self.recent.push(block.slot(), delta);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, delta) in self.recent.rollback(point) {
+            self.send_delta(&delta, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::UtxoSetSize(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, hash, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn spent_outputs_leave_the_totals_and_come_back_on_rollback() {
+        let config = Config { key_prefix: None };
+        let mut harness = Harness::new(vec![config.plugin()]);
+
+        let funding = block()
+            .tx(|t| {
+                t.output(&address(1), 3_000_000)
+                    .output(&address(2), 2_000_000)
+            })
+            .build();
+
+        let funds = tx_hash(&funding, 0);
+        let funding = harness.roll_forward(funding);
+
+        let totals = |harness: &Harness| {
+            let store = harness.store();
+            (
+                store.counter("utxo_set_size.count"),
+                store.counter("utxo_set_size.lovelace"),
+            )
+        };
+
+        assert_eq!(totals(&harness), (Some(2), Some(5_000_000)));
+
+        // the input unknown to the enrichment isn't subtracted
+        let spending = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funds, 0).input(hash(99), 0))
+            .build();
+
+        harness.roll_forward(spending);
+
+        assert_eq!(totals(&harness), (Some(1), Some(2_000_000)));
+
+        harness.roll_back(funding);
+
+        assert_eq!(totals(&harness), (Some(2), Some(5_000_000)));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Lower bounds in lovelace of the buckets after the first one, in
+    /// increasing order. Defaults to 1, 10, 100, 1k, 10k and 100k ADA.
+    pub boundaries: Option<Vec<u64>>,
+}
+
+const DEFAULT_BOUNDARIES: [u64; 6] = [
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+    100_000_000_000,
+];
+
+/// Counts the live utxos by the amount of lovelace that they hold
+///
+/// Each bucket is a counter under `{prefix}.{lower_bound}`, eg:
+/// `utxo_value_histogram.0` counts the utxos with less than the first
+/// boundary. Outputs increment their bucket and spent inputs decrement it, so
+/// the counters only describe the utxo set when syncing from origin. Requires
+/// the enrichment stage to resolve the value of spent inputs.
+pub struct Reducer {
+    config: Config,
+    boundaries: Vec<u64>,
+    recent: super::RollbackHistory<HashMap<u64, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, bucket: u64) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, bucket),
+            None => format!("utxo_value_histogram.{}", bucket),
+        }
+    }
+
+    /// Lower bound of the bucket that holds the amount
+    fn bucket(&self, lovelace: u64) -> u64 {
+        self.boundaries
+            .iter()
+            .rev()
+            .find(|x| **x <= lovelace)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn collect(&self, lovelace: u64, count: i64, deltas: &mut HashMap<u64, i64>) {
+        *deltas.entry(self.bucket(lovelace)).or_insert(0) += count;
+    }
+
+    fn collect_spent(&self, utxo: Option<&model::MultiEraOutput>, deltas: &mut HashMap<u64, i64>) {
+        match utxo {
+            Some(utxo) => self.collect(utxo.lovelace(), -1, deltas),
+            None => log::warn!("missing utxo in block context"),
+        }
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<u64, i64>,
+    ) {
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (hash, idx) = &x.0;
+                self.collect_spent(ctx.find_utxo(hash, *idx as u64), deltas);
+            }
+        }
+
+        for output in tx.transaction.outputs.iter() {
+            self.collect(output.amount, 1, deltas);
+        }
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        deltas: &mut HashMap<u64, i64>,
+    ) {
+        for input in model::spent_inputs(tx, failed) {
+            let utxo = ctx.find_utxo(&input.transaction_id, input.index);
+            self.collect_spent(utxo, deltas);
+        }
+
+        for output in model::produced_outputs(tx, failed) {
+            let lovelace = model::alonzo_lovelace(output);
+
+            self.collect(lovelace, 1, deltas);
+        }
+    }
+
+    fn send_deltas(
+        &self,
+        deltas: &HashMap<u64, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (bucket, delta) in deltas.iter() {
+            let crdt = model::CRDTCommand::PNCounter(self.key(*bucket), (sign * delta).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut deltas = HashMap::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut deltas);
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut deltas);
+                }
+            }
+        };
+
+        deltas.retain(|_, x| *x != 0);
+
+        self.send_deltas(&deltas, 1, output)?;
+
+        self.recent.push(block.slot(), deltas);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, deltas) in self.recent.rollback(point) {
+            self.send_deltas(&deltas, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    fn boundaries(&self) -> Vec<u64> {
+        match &self.boundaries {
+            Some(x) => x.clone(),
+            None => DEFAULT_BOUNDARIES.to_vec(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let boundaries = self.boundaries();
+
+        if boundaries.windows(2).any(|x| x[0] >= x[1]) {
+            return Err(Error::config(
+                "boundaries of UtxoValueHistogram should be in increasing order",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(self) -> super::Plugin {
+        let reducer = Reducer {
+            boundaries: self.boundaries(),
+            config: self,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::UtxoValueHistogram(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn spent_utxos_leave_their_bucket_until_rolled_back() {
+        let config = Config {
+            key_prefix: None,
+            boundaries: None,
+        };
+
+        config.validate().unwrap();
+        let mut harness = Harness::new(vec![config.plugin()]);
+
+        let funding = block()
+            .tx(|t| {
+                t.output(&address(1), 500_000)
+                    .output(&address(2), 5_000_000)
+            })
+            .build();
+
+        let funded = tx_hash(&funding, 0);
+        let funding = harness.roll_forward(funding);
+
+        let count = |harness: &Harness, bucket: u64| {
+            let store = harness.store();
+            store.counter(&format!("utxo_value_histogram.{}", bucket))
+        };
+
+        assert_eq!(count(&harness, 0), Some(1));
+        assert_eq!(count(&harness, 1_000_000), Some(1));
+
+        let spending = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funded, 1).output(&address(3), 800_000))
+            .build();
+
+        harness.roll_forward(spending);
+
+        assert_eq!(count(&harness, 0), Some(2));
+        assert_eq!(count(&harness, 1_000_000), Some(0));
+
+        harness.roll_back(funding);
+
+        assert_eq!(count(&harness, 0), Some(1));
+        assert_eq!(count(&harness, 1_000_000), Some(1));
+    }
+
+    #[test]
+    fn boundaries_should_increase() {
+        let config = Config {
+            key_prefix: None,
+            boundaries: Some(vec![10, 10, 20]),
+        };
+
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::{alonzo, byron, Fragment};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Utxos reported when spent, as `{tx_hash}#{index}`
+    pub utxos: Option<Vec<String>>,
+
+    /// Addresses (bech32 or hex) reported when they spend or receive funds
+    pub addresses: Option<Vec<String>>,
+
+    /// Also deliver each event to a webhook and / or a pub/sub channel, same
+    /// settings as `rollback_notify`
+    pub notify: Option<crosscut::RollbackNotifyConfig>,
+}
+
+type Event = serde_json::Value;
+
+/// Reports the movements of a fixed set of utxos and addresses
+///
+/// Each event is a JSON member of the sorted set `{prefix}`, scored by the
+/// slot of the block, eg: `{"event":"spent","utxo":"...#0","tx":"...",
+/// "slot":...}`. Events of rolled-back blocks are removed and, when
+/// notifications are enabled, a `reverted` event carrying the original one is
+/// delivered. Spends from watched addresses need the enrichment stage.
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    utxos: HashSet<model::OutputRef>,
+    addresses: HashSet<Vec<u8>>,
+    notifier: Option<crosscut::RollbackNotifier>,
+    recent: super::RollbackHistory<Vec<Event>>,
+}
+
+fn output_address_bytes(output: &model::MultiEraOutput) -> Result<Vec<u8>, Error> {
+    match output {
+        model::MultiEraOutput::Byron(x) => Ok(x.address.encode_fragment()?),
+        model::MultiEraOutput::AlonzoCompatible(x) => Ok(x.address.to_vec()),
+    }
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "watched_utxos".to_string(),
+        }
+    }
+
+    fn collect_spent(
+        &self,
+        tx: &Hash<32>,
+        input_hash: &Hash<32>,
+        input_idx: u64,
+        ctx: &model::BlockContext,
+        events: &mut Vec<Event>,
+    ) -> Result<(), Error> {
+        let utxo = model::output_ref(input_hash, input_idx);
+
+        if self.utxos.contains(&utxo) {
+            events.push(json!({ "event": "spent", "utxo": utxo, "tx": tx.to_string() }));
+            return Ok(());
+        }
+
+        if self.addresses.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(output) = ctx.find_utxo(input_hash, input_idx) {
+            if self.addresses.contains(&output_address_bytes(output)?) {
+                events.push(json!({
+                    "event": "spent",
+                    "utxo": utxo,
+                    "tx": tx.to_string(),
+                    "address": output.address(&self.address_hrp)?,
+                    "lovelace": output.lovelace(),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn received_event(utxo: model::OutputRef, address: String, lovelace: u64) -> Event {
+        json!({
+            "event": "received",
+            "utxo": utxo,
+            "address": address,
+            "lovelace": lovelace,
+        })
+    }
+
+    fn collect_byron_tx(
+        &self,
+        tx: &byron::TxPayload,
+        ctx: &model::BlockContext,
+        events: &mut Vec<Event>,
+    ) -> Result<(), Error> {
+        let hash = tx.transaction.to_hash();
+
+        for input in tx.transaction.inputs.iter() {
+            if let byron::TxIn::Variant0(x) = input {
+                let (input_hash, input_idx) = &x.0;
+                self.collect_spent(&hash, input_hash, *input_idx as u64, ctx, events)?;
+            }
+        }
+
+        for (idx, output) in tx.transaction.outputs.iter().enumerate() {
+            if self.addresses.contains(&output.address.encode_fragment()?) {
+                let address = output.address.to_addr_string().map_err(Error::ledger)?;
+                let utxo = model::output_ref(&hash, idx as u64);
+                events.push(Self::received_event(utxo, address, output.amount));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        tx: &alonzo::TransactionBody,
+        failed: bool,
+        ctx: &model::BlockContext,
+        events: &mut Vec<Event>,
+    ) -> Result<(), Error> {
+        let hash = tx.to_hash();
+
+        for input in model::spent_inputs(tx, failed) {
+            self.collect_spent(&hash, &input.transaction_id, input.index, ctx, events)?;
+        }
+
+        for (idx, output) in model::produced_outputs(tx, failed).into_iter().enumerate() {
+            if !self.addresses.contains(output.address.as_slice()) {
+                continue;
+            }
+
+            let address = output
+                .to_bech32_address(&self.address_hrp)
+                .map_err(Error::ledger)?;
+
+            let lovelace = model::alonzo_lovelace(output);
+
+            let utxo = model::output_ref(&hash, idx as u64);
+            events.push(Self::received_event(utxo, address, lovelace));
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut events = Vec::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_byron_tx(tx, ctx, &mut events).or_work_err()?;
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let failed = model::failed_txs(&x.1);
+
+                for (idx, tx) in x.1.transaction_bodies.iter().enumerate() {
+                    self.collect_alonzo_compatible_tx(tx, failed.contains(&idx), ctx, &mut events)
+                        .or_work_err()?;
+                }
+            }
+        };
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let slot = block.slot();
+
+        for event in events.iter_mut() {
+            event["slot"] = json!(slot);
+
+            log::info!("watched funds moved: {}", event);
+
+            let crdt = model::CRDTCommand::LastWriteWins(self.key(), event.to_string(), slot);
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if let Some(notifier) = &self.notifier {
+                notifier.send(event.clone());
+            }
+        }
+
+        self.recent.push(slot, events);
+
+        Ok(())
+    }
+
+    /// Removes the events of the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, events) in self.recent.rollback(point) {
+            let crdt = model::CRDTCommand::SortedSetRemoveRange(self.key(), applied, applied);
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if let Some(notifier) = &self.notifier {
+                for event in events {
+                    notifier.send(json!({ "event": "reverted", "original": event }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a `{tx_hash}#{index}` utxo written in a config file into the format
+/// of `model::output_ref`
+fn parse_config_utxo(raw: &str) -> Result<model::OutputRef, Error> {
+    let invalid = || Error::config(format!("invalid utxo {}, expected tx_hash#index", raw));
+
+    let (hash, idx) = raw.trim().split_once('#').ok_or_else(invalid)?;
+
+    let hash = hash.to_lowercase();
+
+    match hex::decode(&hash) {
+        Ok(bytes) if bytes.len() == 32 => (),
+        _ => return Err(invalid()),
+    };
+
+    let idx: u64 = idx.parse().map_err(|_| invalid())?;
+
+    Ok(format!("{}#{}", hash, idx))
+}
+
+impl Config {
+    fn watched_utxos(&self) -> Result<HashSet<model::OutputRef>, Error> {
+        self.utxos
+            .iter()
+            .flatten()
+            .map(|x| parse_config_utxo(x))
+            .collect()
+    }
+
+    fn watched_addresses(
+        &self,
+        chain: &crosscut::ChainWellKnownInfo,
+    ) -> Result<HashSet<Vec<u8>>, Error> {
+        self.addresses
+            .iter()
+            .flatten()
+            .map(|x| crosscut::parse_config_address(x, chain))
+            .collect()
+    }
+
+    pub fn validate(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<(), Error> {
+        let utxos = self.watched_utxos()?;
+        let addresses = self.watched_addresses(chain)?;
+
+        if utxos.is_empty() && addresses.is_empty() {
+            return Err(Error::config(
+                "WatchedUtxos reducer needs at least one utxo or address to watch",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(mut self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let utxos = self
+            .watched_utxos()
+            .expect("utxos are validated when loading the config");
+
+        let addresses = self
+            .watched_addresses(chain)
+            .expect("addresses are validated when loading the config");
+
+        let notifier = self.notify.take().and_then(|x| x.notifier());
+
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            utxos,
+            addresses,
+            notifier,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::WatchedUtxos(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn spend_of_a_watched_utxo_is_reported_until_rolled_back() {
+        let funding = block()
+            .tx(|t| {
+                t.output(&address(1), 5_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .build();
+
+        let funded = tx_hash(&funding, 0);
+
+        let config = Config {
+            key_prefix: None,
+            utxos: Some(vec![format!("{}#0", funded)]),
+            addresses: None,
+            notify: None,
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        config.validate(&chain).unwrap();
+
+        let mut harness = Harness::new(vec![config.plugin(&chain)]);
+        let funding = harness.roll_forward(funding);
+
+        // the other output isn't watched
+        let spending = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.input(funded, 1).output(&address(3), 2_800_000))
+            .tx(|t| t.input(funded, 0).output(&address(4), 4_800_000))
+            .build();
+
+        let spend = tx_hash(&spending, 1);
+        harness.roll_forward(spending);
+
+        let events = harness.store().scores("watched_utxos").unwrap().clone();
+        assert_eq!(events.len(), 1);
+
+        let (event, slot) = events.into_iter().next().unwrap();
+        let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+
+        assert_eq!(slot, DEFAULT_SLOT as i64 + 20);
+        assert_eq!(event["event"], "spent");
+        assert_eq!(event["utxo"], format!("{}#0", funded));
+        assert_eq!(event["tx"], spend.to_string());
+
+        harness.roll_back(funding);
+
+        assert!(harness.store().scores("watched_utxos").is_none());
+    }
+}
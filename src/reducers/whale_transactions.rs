@@ -0,0 +1,214 @@
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{crosscut, model, Error};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+
+    /// Minimum total output value, in lovelace, of a flagged transaction
+    pub threshold: u64,
+
+    /// Also deliver each flagged transaction to a webhook and / or a pub/sub
+    /// channel, same settings as `rollback_notify`
+    pub notify: Option<crosscut::RollbackNotifyConfig>,
+}
+
+type Entry = serde_json::Value;
+
+/// Flags the transactions that move at least `threshold` lovelace
+///
+/// Each flagged transaction is a JSON member of the sorted set `{prefix}`,
+/// scored by the slot of its block, eg: `{"tx":"...","lovelace":...,
+/// "slot":...}`. The entries of rolled-back blocks are removed and, when
+/// notifications are enabled, a `reverted` event carrying the original one is
+/// delivered.
+pub struct Reducer {
+    config: Config,
+    notifier: Option<crosscut::RollbackNotifier>,
+    recent: super::RollbackHistory<Vec<Entry>>,
+}
+
+fn alonzo_output_value(tx: &alonzo::TransactionBody) -> u64 {
+    tx.iter()
+        .filter_map(|x| match x {
+            alonzo::TransactionBodyComponent::Outputs(outputs) => Some(outputs),
+            _ => None,
+        })
+        .flat_map(|x| x.iter())
+        .map(model::alonzo_lovelace)
+        .sum()
+}
+
+impl Reducer {
+    fn key(&self) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "whale_transactions".to_string(),
+        }
+    }
+
+    fn flag(&self, tx: String, lovelace: u64, entries: &mut Vec<Entry>) {
+        if lovelace >= self.config.threshold {
+            entries.push(json!({ "tx": tx, "lovelace": lovelace }));
+        }
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut entries = Vec::new();
+
+        match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                for tx in x.body.tx_payload.iter() {
+                    let lovelace = tx.transaction.outputs.iter().map(|x| x.amount).sum();
+                    self.flag(tx.transaction.to_hash().to_string(), lovelace, &mut entries);
+                }
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                for tx in x.1.transaction_bodies.iter() {
+                    self.flag(
+                        tx.to_hash().to_string(),
+                        alonzo_output_value(tx),
+                        &mut entries,
+                    );
+                }
+            }
+        };
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let slot = block.slot();
+
+        for entry in entries.iter_mut() {
+            entry["slot"] = json!(slot);
+
+            log::info!("whale transaction: {}", entry);
+
+            let crdt = model::CRDTCommand::LastWriteWins(self.key(), entry.to_string(), slot);
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if let Some(notifier) = &self.notifier {
+                notifier.send(json!({ "event": "whale_transaction", "tx": entry.clone() }));
+            }
+        }
+
+        self.recent.push(slot, entries);
+
+        Ok(())
+    }
+
+    /// Removes the entries of the blocks after the rollback point
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (applied, entries) in self.recent.rollback(point) {
+            let crdt = model::CRDTCommand::SortedSetRemoveRange(self.key(), applied, applied);
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if let Some(notifier) = &self.notifier {
+                for entry in entries {
+                    notifier.send(json!({ "event": "reverted", "original": entry }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.threshold == 0 {
+            return Err(Error::config(
+                "WhaleTransactions reducer needs a threshold above zero",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn plugin(mut self) -> super::Plugin {
+        let notifier = self.notify.take().and_then(|x| x.notifier());
+
+        let reducer = Reducer {
+            config: self,
+            notifier,
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::WhaleTransactions(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::testing::{address, block, tx_hash, Harness, DEFAULT_SLOT};
+
+    #[test]
+    fn only_transactions_over_the_threshold_are_flagged() {
+        let config = Config {
+            key_prefix: None,
+            threshold: 5_000_000,
+            notify: None,
+        };
+
+        let mut harness = Harness::new(vec![config.plugin()]);
+
+        let first = block()
+            .tx(|t| {
+                t.output(&address(1), 3_000_000)
+                    .output(&address(2), 3_000_000)
+            })
+            .tx(|t| t.output(&address(3), 4_000_000))
+            .build();
+
+        let whale = tx_hash(&first, 0).to_string();
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.output(&address(4), 8_000_000))
+            .build();
+
+        let later_whale = tx_hash(&second, 0).to_string();
+        harness.roll_forward(second);
+
+        let flagged = |harness: &Harness| {
+            let scores = harness.store().scores("whale_transactions").cloned();
+
+            scores
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(entry, slot)| {
+                    let entry: serde_json::Value = serde_json::from_str(&entry).unwrap();
+                    assert_eq!(entry["slot"], json!(slot));
+                    let tx = entry["tx"].as_str().unwrap().to_string();
+                    (tx, entry["lovelace"].as_u64().unwrap())
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        let expected = BTreeMap::from([(whale.clone(), 6_000_000), (later_whale, 8_000_000)]);
+        assert_eq!(flagged(&harness), expected);
+
+        harness.roll_back(first);
+
+        assert_eq!(flagged(&harness), BTreeMap::from([(whale, 6_000_000)]));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo;
+use pallas::network::miniprotocols::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model, Error};
+
+use super::state;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+/// Withdrawals of accounts without a known delegation are summed under this
+/// pool name
+const UNKNOWN_POOL: &str = "unknown";
+
+/// A change of delegation: the credential, its previous pool and its new pool
+type Change = (String, Option<String>, Option<String>);
+
+#[derive(Serialize, Deserialize)]
+struct AppliedBlock {
+    changes: Vec<Change>,
+    withdrawn: HashMap<String, i128>,
+}
+
+/// Sums the rewards withdrawn by the delegators of each pool
+///
+/// Counters are kept under `{prefix}.{pool}`, in lovelace. A withdrawal is
+/// attributed to the pool that the account delegates to at the slot of the
+/// withdrawal, which approximates the rewards realized per pool; the rewards
+/// may have been earned while delegating to another pool. The delegation of
+/// each credential is kept in the state db. Withdrawals of accounts that never
+/// delegated go to `{prefix}.unknown`.
+pub struct Reducer {
+    config: Config,
+    current: state::Map<String>,
+    recent: state::History<AppliedBlock>,
+}
+
+fn credential_key(cred: &alonzo::StakeCredential) -> String {
+    match cred {
+        alonzo::StakeCredential::AddrKeyhash(x) => x.to_string(),
+        alonzo::StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+impl Reducer {
+    fn key(&self, pool: &str) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, pool),
+            None => format!("withdrawals_by_pool.{}", pool),
+        }
+    }
+
+    fn apply_delegation(
+        &mut self,
+        cred: String,
+        pool: Option<String>,
+        changes: &mut Vec<Change>,
+    ) -> Result<(), Error> {
+        let previous = match &pool {
+            Some(pool) => self.current.insert(&cred, pool)?,
+            None => self.current.remove(&cred)?,
+        };
+
+        changes.push((cred, previous, pool));
+
+        Ok(())
+    }
+
+    fn collect_tx(
+        &mut self,
+        tx: &alonzo::TransactionBody,
+        changes: &mut Vec<Change>,
+        withdrawn: &mut HashMap<String, i128>,
+    ) -> Result<(), Error> {
+        // the ledger processes the withdrawals of a tx before its certificates
+        let withdrawals = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Withdrawals(w) => Some(w),
+                _ => None,
+            })
+            .flat_map(|x| x.iter());
+
+        for (account, amount) in withdrawals {
+            let cred = crosscut::parse_output_address(account.as_slice())
+                .stake()
+                .map(|x| hex::encode(x.hash()));
+
+            let pool = match cred {
+                Some(x) => self.current.get(&x)?,
+                None => None,
+            };
+
+            let pool = pool.unwrap_or_else(|| UNKNOWN_POOL.to_string());
+
+            *withdrawn.entry(pool).or_insert(0) += *amount as i128;
+        }
+
+        let certs = tx
+            .iter()
+            .filter_map(|x| match x {
+                alonzo::TransactionBodyComponent::Certificates(c) => Some(c),
+                _ => None,
+            })
+            .flat_map(|x| x.iter());
+
+        for cert in certs {
+            match cert {
+                alonzo::Certificate::StakeDelegation(cred, pool) => {
+                    self.apply_delegation(credential_key(cred), Some(pool.to_string()), changes)?;
+                }
+                alonzo::Certificate::StakeDeregistration(cred) => {
+                    self.apply_delegation(credential_key(cred), None, changes)?;
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_withdrawn(
+        &self,
+        withdrawn: &HashMap<String, i128>,
+        sign: i128,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (pool, amount) in withdrawn.iter() {
+            let crdt = model::CRDTCommand::PNCounter(
+                self.key(pool),
+                model::clamp_delta(sign * amount).to_string(),
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let x = match block {
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => x,
+        };
+
+        let slot = x.1.header.header_body.slot;
+
+        // changes left by a run that stopped before the storage committed
+        for (_, applied) in self.recent.rewind(slot).or_work_err()? {
+            self.undo(&applied.changes).or_work_err()?;
+        }
+
+        let mut changes = Vec::new();
+        let mut withdrawn = HashMap::new();
+
+        for tx in x.1.transaction_bodies.iter() {
+            self.collect_tx(tx, &mut changes, &mut withdrawn)
+                .or_work_err()?;
+        }
+
+        withdrawn.retain(|_, x| *x != 0);
+
+        if changes.is_empty() && withdrawn.is_empty() {
+            return Ok(());
+        }
+
+        self.send_withdrawn(&withdrawn, 1, output)?;
+
+        self.recent
+            .push(slot, &AppliedBlock { changes, withdrawn })
+            .or_work_err()?;
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, applied) in self.recent.rollback(point).or_work_err()? {
+            self.send_withdrawn(&applied.withdrawn, -1, output)?;
+            self.undo(&applied.changes).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the pools that the credentials had before the changes
+    fn undo(&mut self, changes: &[Change]) -> Result<(), Error> {
+        for (cred, previous, _) in changes.iter().rev() {
+            self.current.restore(cred, previous.as_ref())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, state: &state::Db) -> Result<super::Plugin, Error> {
+        let name = match &self.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "withdrawals_by_pool".to_string(),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            current: state.map(&format!("{}.current", name))?,
+            recent: state.history(&name)?,
+        };
+
+        Ok(super::Plugin::WithdrawalsByPool(reducer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block, delegation, state_db, Harness, DEFAULT_SLOT};
+
+    fn reducer(state: &state::Db) -> super::super::Plugin {
+        Config { key_prefix: None }.plugin(state).unwrap()
+    }
+
+    #[test]
+    fn withdrawals_go_to_the_current_pool_until_rolled_back() {
+        let mut harness = Harness::new(vec![reducer(&state_db())]);
+
+        let first = block().tx(|t| t.cert(delegation(9, 7))).build();
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| t.withdrawal(9, 1_000_000).withdrawal(4, 500_000))
+            .build();
+
+        harness.roll_forward(second);
+
+        let pool = format!("withdrawals_by_pool.{}", hex::encode([7; 28]));
+        assert_eq!(harness.store().counter(&pool), Some(1_000_000));
+        assert_eq!(
+            harness.store().counter("withdrawals_by_pool.unknown"),
+            Some(500_000)
+        );
+
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter(&pool), Some(0));
+        assert_eq!(
+            harness.store().counter("withdrawals_by_pool.unknown"),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn delegations_survive_a_restart() {
+        let state = state_db();
+
+        let mut harness = Harness::new(vec![reducer(&state)]);
+        let first = harness.roll_forward(block().tx(|t| t.cert(delegation(9, 7))).build());
+
+        // the new run only knows the pool of the account through the state
+        let mut harness = harness.restart(vec![reducer(&state)]);
+
+        harness.roll_forward(
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| t.withdrawal(9, 1_000_000))
+                .build(),
+        );
+
+        let pool = format!("withdrawals_by_pool.{}", hex::encode([7; 28]));
+        assert_eq!(harness.store().counter(&pool), Some(1_000_000));
+        assert_eq!(harness.store().counter("withdrawals_by_pool.unknown"), None);
+
+        let mut harness = harness.restart(vec![reducer(&state)]);
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter(&pool), Some(0));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use pallas::ledger::primitives::{alonzo, byron};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// A single histogram for the whole chain
+    #[default]
+    Global,
+    /// One histogram per epoch
+    Epoch,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub granularity: Option<Granularity>,
+}
+
+/// Counts above this value share a single open-ended bucket
+const MAX_EXACT_COUNT: usize = 10;
+
+/// Histogram of txs by their number of witnesses of each kind
+///
+/// Counters are kept under `{prefix}.{kind}.{count}` (or
+/// `{prefix}.{epoch}.{kind}.{count}` per epoch), where kind is `vkey`,
+/// `native_script` or `plutus_script`. Bootstrap witnesses count as vkey
+/// witnesses. Counts above 10 go into the `10+` bucket.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    recent: super::RollbackHistory<HashMap<String, i64>>,
+}
+
+impl Reducer {
+    fn key(&self, slot: u64, kind: &str, count: usize) -> String {
+        let prefix = match &self.config.key_prefix {
+            Some(prefix) => prefix.to_string(),
+            None => "witness_distribution".to_string(),
+        };
+
+        let bucket = match count {
+            x if x >= MAX_EXACT_COUNT => format!("{}+", MAX_EXACT_COUNT),
+            x => x.to_string(),
+        };
+
+        match self.config.granularity.unwrap_or_default() {
+            Granularity::Global => format!("{}.{}.{}", prefix, kind, bucket),
+            Granularity::Epoch => format!(
+                "{}.{}.{}.{}",
+                prefix,
+                self.chain.epoch_for_slot(slot),
+                kind,
+                bucket
+            ),
+        }
+    }
+
+    fn collect_tx(
+        &self,
+        slot: u64,
+        vkey: usize,
+        native: usize,
+        plutus: usize,
+        counters: &mut HashMap<String, i64>,
+    ) {
+        for (kind, count) in [
+            ("vkey", vkey),
+            ("native_script", native),
+            ("plutus_script", plutus),
+        ] {
+            *counters.entry(self.key(slot, kind, count)).or_insert(0) += 1;
+        }
+    }
+
+    fn collect_alonzo_compatible_tx(
+        &self,
+        slot: u64,
+        witnesses: &alonzo::TransactionWitnessSet,
+        counters: &mut HashMap<String, i64>,
+    ) {
+        let vkey = witnesses.vkeywitness.as_ref().map(|x| x.len()).unwrap_or(0)
+            + witnesses
+                .bootstrap_witness
+                .as_ref()
+                .map(|x| x.len())
+                .unwrap_or(0);
+
+        let native = witnesses
+            .native_script
+            .as_ref()
+            .map(|x| x.len())
+            .unwrap_or(0);
+
+        let plutus = witnesses
+            .plutus_script
+            .as_ref()
+            .map(|x| x.len())
+            .unwrap_or(0);
+
+        self.collect_tx(slot, vkey, native, plutus, counters);
+    }
+
+    fn send_counters(
+        &self,
+        counters: &HashMap<String, i64>,
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (key, value) in counters.iter() {
+            let crdt = model::CRDTCommand::PNCounter(key.clone(), (sign * value).to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block(
+        &mut self,
+        block: &model::MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let mut counters = HashMap::new();
+
+        let slot = match block {
+            model::MultiEraBlock::Byron(byron::Block::MainBlock(x)) => {
+                let slot = x.header.consensus_data.0.to_abs_slot();
+
+                for tx in x.body.tx_payload.iter() {
+                    self.collect_tx(slot, tx.witness.len(), 0, 0, &mut counters);
+                }
+
+                slot
+            }
+            model::MultiEraBlock::Byron(_) => return Ok(()),
+            model::MultiEraBlock::AlonzoCompatible(x) => {
+                let slot = x.1.header.header_body.slot;
+
+                for witnesses in x.1.transaction_witness_sets.iter() {
+                    self.collect_alonzo_compatible_tx(slot, witnesses, &mut counters);
+                }
+
+                slot
+            }
+        };
+
+        self.send_counters(&counters, 1, output)?;
+
+        self.recent.push(slot, counters);
+
+        Ok(())
+    }
+
+    pub fn rollback(
+        &mut self,
+        point: &Point,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (_, counters) in self.recent.rollback(point) {
+            self.send_counters(&counters, -1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Plugin {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            recent: super::RollbackHistory::new(),
+        };
+
+        super::Plugin::WitnessDistribution(reducer)
+    }
+}
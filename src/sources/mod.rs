@@ -6,6 +6,7 @@ use crate::{bootstrap, model};
 pub mod n2c;
 
 pub mod n2n;
+pub mod oura;
 pub mod utils;
 
 pub trait Pluggable {
@@ -16,6 +17,7 @@ pub trait Pluggable {
 pub enum Plugin {
     N2N(n2n::Plugin),
     N2C(n2c::Plugin),
+    Oura(oura::Plugin),
 }
 
 impl Plugin {
@@ -23,6 +25,7 @@ impl Plugin {
         match self {
             Plugin::N2N(p) => p.borrow_output_port(),
             Plugin::N2C(p) => p.borrow_output_port(),
+            Plugin::Oura(p) => p.borrow_output_port(),
         }
     }
 
@@ -30,6 +33,7 @@ impl Plugin {
         match self {
             Plugin::N2N(p) => p.spawn(pipeline),
             Plugin::N2C(p) => p.spawn(pipeline),
+            Plugin::Oura(p) => p.spawn(pipeline),
         }
     }
 }
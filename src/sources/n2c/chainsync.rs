@@ -11,32 +11,42 @@ use gasket::{
 };
 
 use crate::{
+    crosscut,
     model::{ChainSyncCommandEx, MultiEraBlock},
     sources::utils,
 };
 
 struct ChainObserver {
     min_depth: usize,
+    policy: crosscut::RuntimePolicy,
     output: gasket::messaging::FanoutPort<ChainSyncCommandEx>,
     chain_buffer: chainsync::RollbackBuffer,
     blocks: HashMap<Point, MultiEraBlock>,
     block_count: gasket::metrics::Counter,
+    skipped_count: gasket::metrics::Counter,
     chain_tip: Gauge,
+    pending_intersection: Option<Vec<Point>>,
 }
 
 impl ChainObserver {
     fn new(
         min_depth: usize,
+        policy: crosscut::RuntimePolicy,
         block_count: Counter,
+        skipped_count: Counter,
         chain_tip: Gauge,
+        known_points: Option<Vec<Point>>,
         output: gasket::messaging::FanoutPort<ChainSyncCommandEx>,
     ) -> Self {
         Self {
             min_depth,
+            policy,
             block_count,
+            skipped_count,
             chain_tip,
             output,
             chain_buffer: Default::default(),
+            pending_intersection: known_points,
             blocks: Default::default(),
         }
     }
@@ -48,9 +58,25 @@ impl chainsync::Observer<chainsync::BlockContent> for ChainObserver {
         content: chainsync::BlockContent,
         tip: &chainsync::Tip,
     ) -> Result<chainsync::Continuation, Error> {
+        self.policy.pause.wait();
+
         // parse the block and extract the point of the chain
         let cbor = Vec::from(content.deref());
-        let block = utils::parse_block_content(&cbor)?;
+
+        let block = match utils::parse_block_content(&cbor) {
+            Ok(x) => x,
+            Err(err) => match self.policy.on_decode_error.unwrap_or_default() {
+                crosscut::ErrorAction::Fail => return Err(err.into()),
+                crosscut::ErrorAction::Skip => {
+                    // we can't know the point of a block we can't decode, the tip is the
+                    // best reference we have for the logs
+                    log::error!("skipping undecodable block, tip at {:?}: {}", tip.0, err);
+                    self.skipped_count.inc(1);
+                    return Ok(chainsync::Continuation::Proceed);
+                }
+            },
+        };
+
         let point = block.point()?;
 
         // store the block for later retrieval
@@ -82,6 +108,13 @@ impl chainsync::Observer<chainsync::BlockContent> for ChainObserver {
     }
 
     fn on_rollback(&mut self, point: &Point) -> Result<chainsync::Continuation, Error> {
+        if let Some(known_points) = self.pending_intersection.take() {
+            utils::log_intersection(&known_points, point);
+        }
+
+        // the intersection is found, hold the first rollback until resumed
+        self.policy.pause.wait();
+
         log::info!("rolling block to point {:?}", point);
 
         match self.chain_buffer.roll_back(point) {
@@ -109,6 +142,7 @@ pub struct Worker {
     //finalize_config: Option<FinalizeConfig>,
     runner: Runner,
     block_count: gasket::metrics::Counter,
+    skipped_count: gasket::metrics::Counter,
     chain_tip: Gauge,
 }
 
@@ -117,17 +151,22 @@ impl Worker {
         channel: Channel,
         min_depth: usize,
         known_points: Option<Vec<Point>>,
+        policy: crosscut::RuntimePolicy,
         output: OutputPort,
     ) -> Self {
         let block_count = Counter::default();
+        let skipped_count = Counter::default();
         let chain_tip = Gauge::default();
 
         let runner = Runner::new(chainsync::Consumer::initial(
             known_points.clone(),
             ChainObserver::new(
-                min_depth as usize,
+                min_depth,
+                policy,
                 block_count.clone(),
+                skipped_count.clone(),
                 chain_tip.clone(),
+                known_points.clone(),
                 output,
             ),
         ));
@@ -138,6 +177,7 @@ impl Worker {
             known_points,
             runner,
             block_count,
+            skipped_count,
             chain_tip,
         }
     }
@@ -147,6 +187,7 @@ impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
         gasket::metrics::Builder::new()
             .with_counter("block_count", &self.block_count)
+            .with_counter("skipped_blocks", &self.skipped_count)
             .with_gauge("chain_tip", &self.chain_tip)
             .build()
     }
@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+use gasket::{
+    error::AsWorkError,
+    messaging::{Message, OutputPort},
+    metrics::{Counter, Gauge},
+};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{bootstrap::Pipeline, crosscut, model, reducers::mempool};
+
+use super::{
+    transport::Transport,
+    txmonitor::{self, Client},
+};
+use crate::sources::utils;
+
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 5;
+
+/// Byron txs don't have the layout of the later eras
+const BYRON_ERA: u16 = 0;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub path: String,
+
+    #[serde(flatten)]
+    pub versions: utils::VersionRange,
+
+    /// Seconds between two snapshots of the mempool
+    pub poll_interval_secs: Option<u64>,
+
+    #[serde(flatten)]
+    pub reducer: mempool::Config,
+}
+
+/// Polls the mempool of the node through LocalTxMonitor and writes what the
+/// mempool reducer derives from each snapshot
+///
+/// The stage runs next to the chain-sync pipeline and has its own storage.
+/// Each snapshot is written as a block of its own, at the slot where the
+/// snapshot was acquired.
+pub struct Worker {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    client: Option<Client>,
+    reducer: mempool::Reducer,
+    output: OutputPort<model::CRDTCommand>,
+    snapshot_count: Counter,
+    skipped_count: Counter,
+    pending_txs: Gauge,
+}
+
+impl Worker {
+    fn interval(&self) -> Duration {
+        let secs = self
+            .config
+            .poll_interval_secs
+            .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+
+        Duration::from_secs(secs)
+    }
+
+    /// The pending txs of the snapshot, leaving out the ones that can't be
+    /// read
+    fn pending_txs(&self, snapshot: &txmonitor::Snapshot) -> Vec<mempool::PendingTx> {
+        let mut pending = Vec::with_capacity(snapshot.txs.len());
+
+        for txmonitor::EraTx(era, cbor) in snapshot.txs.iter() {
+            let decoded = match *era {
+                BYRON_ERA => Err(crate::Error::message("byron tx in the mempool")),
+                _ => mempool::pending_tx(cbor),
+            };
+
+            match decoded {
+                Ok(x) => pending.push(x),
+                Err(err) => {
+                    log::warn!("skipping mempool tx of era {}: {}", era, err);
+                    self.skipped_count.inc(1);
+                }
+            }
+        }
+
+        pending
+    }
+
+    fn write_snapshot(
+        &mut self,
+        snapshot: txmonitor::Snapshot,
+    ) -> Result<(), gasket::error::Error> {
+        let pending = self.pending_txs(&snapshot);
+        self.pending_txs.set(pending.len() as i64);
+
+        let point = Point::Specific(snapshot.slot, vec![]);
+
+        self.output
+            .send(Message::from(model::CRDTCommand::BlockStarting(
+                point.clone(),
+            )))?;
+
+        for command in self.reducer.reduce_snapshot(pending) {
+            self.output.send(Message::from(command))?;
+        }
+
+        self.output
+            .send(Message::from(model::CRDTCommand::BlockFinished(point)))?;
+
+        self.snapshot_count.inc(1);
+
+        Ok(())
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("snapshot_count", &self.snapshot_count)
+            .with_counter("skipped_txs", &self.skipped_count)
+            .with_gauge("pending_txs", &self.pending_txs)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let mut transport =
+            Transport::setup_tx_monitor(&self.config.path, self.chain.magic, &self.config.versions)
+                .or_work_err()?;
+
+        let channel = transport.muxer.use_channel(txmonitor::PROTOCOL_ID);
+        self.client = Some(Client::new(channel));
+
+        Ok(())
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let client = self
+            .client
+            .as_mut()
+            .expect("client is connected on bootstrap");
+        let snapshot = client.snapshot().or_work_err()?;
+
+        self.write_snapshot(snapshot)?;
+
+        std::thread::sleep(self.interval());
+
+        Ok(gasket::runtime::WorkOutcome::Partial)
+    }
+}
+
+impl Worker {
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort<model::CRDTCommand> {
+        &mut self.output
+    }
+
+    pub fn spawn(self, pipeline: &mut Pipeline) {
+        pipeline.register_stage(
+            "mempool",
+            gasket::runtime::spawn_stage(self, gasket::runtime::Policy::default()),
+        );
+    }
+}
+
+impl Config {
+    pub fn stage(self, chain: &crosscut::ChainWellKnownInfo) -> Worker {
+        let reducer = mempool::Reducer::new(self.reducer.clone());
+
+        Worker {
+            config: self,
+            chain: chain.clone(),
+            client: None,
+            reducer,
+            output: Default::default(),
+            snapshot_count: Default::default(),
+            skipped_count: Default::default(),
+            pending_txs: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gasket::{messaging::connect_ports, runtime::Worker as _};
+
+    use super::*;
+    use crate::{cursor, storage};
+
+    // [{0: [], 1: [], 2: fee}, {}, true, null]
+    fn tx(fee: u8) -> txmonitor::EraTx {
+        let mut cbor = hex::decode("84a30080018002").unwrap();
+        cbor.extend([0x18, fee]);
+        cbor.extend(hex::decode("a0f5f6").unwrap());
+
+        txmonitor::EraTx(5, cbor)
+    }
+
+    #[test]
+    fn each_snapshot_is_written_as_a_block() {
+        let config = Config {
+            path: "node.socket".to_string(),
+            versions: Default::default(),
+            poll_interval_secs: None,
+            reducer: mempool::Config { key_prefix: None },
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut stage = config.stage(&chain);
+
+        let mut storage = match storage::memory::Config::default().plugin(
+            &chain,
+            &crosscut::IntersectConfig::Origin,
+            cursor::Store::Skip,
+        ) {
+            storage::Plugin::Memory(x) => x,
+            _ => unreachable!("memory config builds a memory plugin"),
+        };
+
+        connect_ports(
+            stage.borrow_output_port(),
+            storage::Pluggable::borrow_input_port(&mut storage),
+            100,
+        );
+
+        let store = storage.store();
+
+        let mut write = |slot, txs| {
+            stage
+                .write_snapshot(txmonitor::Snapshot { slot, txs })
+                .unwrap();
+
+            let expected = crosscut::PointArg::Specific(slot, String::new()).to_string();
+
+            while store.lock().unwrap().cursor().map(|x| x.to_string()) != Some(expected.clone()) {
+                storage.work().unwrap();
+            }
+        };
+
+        // the byron tx can't be read, the others are pending
+        write(
+            10,
+            vec![tx(100), tx(200), txmonitor::EraTx(BYRON_ERA, vec![0x80])],
+        );
+
+        {
+            let store = store.lock().unwrap();
+            assert_eq!(store.members("mempool.txs").map(|x| x.len()), Some(2));
+            assert_eq!(store.string("mempool.fees"), Some("300"));
+        }
+
+        write(20, vec![tx(200)]);
+
+        let store = store.lock().unwrap();
+        assert_eq!(store.members("mempool.txs").map(|x| x.len()), Some(1));
+        assert_eq!(store.string("mempool.tx_count"), Some("1"));
+        assert_eq!(store.string("mempool.fees"), Some("200"));
+        drop(store);
+
+        assert_eq!(stage.snapshot_count.get(), 2);
+        assert_eq!(stage.skipped_count.get(), 1);
+    }
+}
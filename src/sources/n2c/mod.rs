@@ -1,10 +1,13 @@
 pub mod chainsync;
+pub mod mempool;
 mod transport;
+pub mod txmonitor;
 
 use std::time::Duration;
 
 use gasket::{error::AsWorkError, messaging::FanoutPort, retries};
 
+use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
 
 use crate::{bootstrap::Pipeline, crosscut, model::ChainSyncCommandEx};
@@ -16,6 +19,9 @@ use super::utils;
 #[derive(Deserialize)]
 pub struct Config {
     pub path: String,
+
+    #[serde(flatten)]
+    pub versions: utils::VersionRange,
 }
 
 pub struct Plugin {
@@ -23,13 +29,17 @@ pub struct Plugin {
     intersect: crosscut::IntersectConfig,
     chain: crosscut::ChainWellKnownInfo,
     cursor: crosscut::Cursor,
+    policy: crosscut::RuntimePolicy,
     output: FanoutPort<ChainSyncCommandEx>,
 }
 
 impl Plugin {
     fn bootstrap_transport(&self) -> Result<Transport, gasket::error::Error> {
         gasket::retries::retry_operation(
-            || Transport::setup(&self.config.path, self.chain.magic).or_work_err(),
+            || {
+                Transport::setup(&self.config.path, self.chain.magic, &self.config.versions)
+                    .or_work_err()
+            },
             &retries::Policy {
                 max_retries: 5,
                 backoff_factor: 2,
@@ -60,7 +70,7 @@ impl super::Pluggable for Plugin {
         pipeline.register_stage(
             "n2c",
             gasket::runtime::spawn_stage(
-                self::chainsync::Worker::new(cs_channel, 0, known_points, self.output),
+                self::chainsync::Worker::new(cs_channel, 0, known_points, self.policy, self.output),
                 gasket::runtime::Policy::default(),
             ),
         );
@@ -68,17 +78,29 @@ impl super::Pluggable for Plugin {
 }
 
 impl Config {
+    /// Asks the node for the current tip of its chain
+    pub fn find_tip(&self, chain: &crosscut::ChainWellKnownInfo) -> Result<Point, crate::Error> {
+        let mut transport = Transport::setup(&self.path, chain.magic, &self.versions)
+            .map_err(crate::Error::ouroboros)?;
+
+        let mut channel = transport.muxer.use_channel(5);
+
+        utils::find_end_of_chain(chain, &mut channel)
+    }
+
     pub fn plugin(
         self,
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
         cursor: &crosscut::Cursor,
+        policy: &crosscut::RuntimePolicy,
     ) -> super::Plugin {
         let plugin = Plugin {
             config: self,
             intersect: intersect.clone(),
             chain: chain.clone(),
             cursor: cursor.clone(),
+            policy: policy.clone(),
             output: Default::default(),
         };
 
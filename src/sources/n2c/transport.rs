@@ -5,41 +5,69 @@ use pallas::network::{
     multiplexer::Multiplexer,
 };
 
+use crate::sources::utils::{negotiate_version, VersionRange};
+
+use super::txmonitor;
+
 pub struct Transport {
     pub muxer: Multiplexer,
+    #[allow(dead_code)]
     pub version: handshake::VersionNumber,
 }
 
 impl Transport {
-    fn connect_muxer(address: &str) -> Result<Multiplexer, miniprotocols::Error> {
+    fn connect_muxer(
+        address: &str,
+        protocols: &[u16],
+    ) -> Result<Multiplexer, miniprotocols::Error> {
         log::debug!("connecting muxer");
         let unix = UnixStream::connect(address)?;
-        let muxer = Multiplexer::setup(unix, &[0, 5])?;
+        let muxer = Multiplexer::setup(unix, protocols)?;
 
         Ok(muxer)
     }
 
     fn do_handshake(
         muxer: &mut Multiplexer,
-        magic: u64,
+        versions: handshake::n2c::VersionTable,
+        range: &VersionRange,
     ) -> Result<handshake::VersionNumber, miniprotocols::Error> {
-        log::debug!("doing handshake");
-
         let mut channel = muxer.use_channel(0);
+
+        negotiate_version(&mut channel, versions, range)
+    }
+
+    pub fn setup(
+        address: &str,
+        magic: u64,
+        range: &VersionRange,
+    ) -> Result<Self, miniprotocols::Error> {
+        let mut muxer = Self::connect_muxer(address, &[0, 5])?;
         let versions = handshake::n2c::VersionTable::v1_and_above(magic);
-        let agent =
-            miniprotocols::run_agent(handshake::Initiator::initial(versions), &mut channel)?;
-        log::info!("handshake output: {:?}", agent.output);
-
-        match agent.output {
-            handshake::Output::Accepted(version, _) => Ok(version),
-            _ => Err("couldn't agree on handshake version".into()),
-        }
+        let version = Self::do_handshake(&mut muxer, versions, range)?;
+
+        Ok(Self { muxer, version })
     }
 
-    pub fn setup(address: &str, magic: u64) -> Result<Self, miniprotocols::Error> {
-        let mut muxer = Self::connect_muxer(address)?;
-        let version = Self::do_handshake(&mut muxer, magic)?;
+    /// Connects for the LocalTxMonitor mini-protocol only, which needs a
+    /// version newer than the ones that pallas knows of
+    pub fn setup_tx_monitor(
+        address: &str,
+        magic: u64,
+        range: &VersionRange,
+    ) -> Result<Self, miniprotocols::Error> {
+        let mut muxer = Self::connect_muxer(address, &[0, txmonitor::PROTOCOL_ID])?;
+
+        // newer versions carry the same data, the magic of the network
+        let data = handshake::n2c::VersionTable::only_v10(magic).values[&32778].clone();
+
+        let values = txmonitor::VERSIONS
+            .iter()
+            .map(|x| (*x, data.clone()))
+            .collect();
+
+        let versions = handshake::n2c::VersionTable { values };
+        let version = Self::do_handshake(&mut muxer, versions, range)?;
 
         Ok(Self { muxer, version })
     }
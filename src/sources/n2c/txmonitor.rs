@@ -0,0 +1,261 @@
+//! Client side of the LocalTxMonitor mini-protocol, which pallas doesn't
+//! implement yet
+//!
+//! The client acquires a snapshot of the mempool of the node, walks its txs
+//! one by one and releases it. Only the messages needed for that walk are
+//! supported.
+
+use pallas::codec::minicbor::{self, data::Tag, decode, encode, Decode, Encode, Encoder};
+use pallas::network::{miniprotocols, multiplexer::Channel};
+
+/// Id of the mini-protocol in the node-to-client mux
+pub const PROTOCOL_ID: u16 = 9;
+
+/// Node-to-client versions that run the mini-protocol (V12 and V13), pallas
+/// only knows up to V10
+pub const VERSIONS: [u64; 2] = [32780, 32781];
+
+/// A tx of the mempool, as sent by the node: its era and its CBOR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EraTx(pub u16, pub Vec<u8>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Done,
+    Acquire,
+    Acquired(u64),
+    Release,
+    NextTx,
+    ReplyNextTx(Option<EraTx>),
+}
+
+impl Encode for EraTx {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(2)?.u16(self.0)?;
+        e.tag(Tag::Cbor)?.bytes(&self.1)?;
+
+        Ok(())
+    }
+}
+
+impl<'b> Decode<'b> for EraTx {
+    fn decode(d: &mut minicbor::Decoder<'b>) -> Result<Self, decode::Error> {
+        d.array()?;
+        let era = d.u16()?;
+
+        match d.tag()? {
+            Tag::Cbor => Ok(EraTx(era, d.bytes()?.to_vec())),
+            _ => Err(decode::Error::message(
+                "expected tx wrapped as embedded cbor",
+            )),
+        }
+    }
+}
+
+impl Encode for Message {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        match self {
+            Message::Done => {
+                e.array(1)?.u16(0)?;
+            }
+            Message::Acquire => {
+                e.array(1)?.u16(1)?;
+            }
+            Message::Acquired(slot) => {
+                e.array(2)?.u16(2)?.u64(*slot)?;
+            }
+            Message::Release => {
+                e.array(1)?.u16(3)?;
+            }
+            Message::NextTx => {
+                e.array(1)?.u16(5)?;
+            }
+            Message::ReplyNextTx(None) => {
+                e.array(1)?.u16(6)?;
+            }
+            Message::ReplyNextTx(Some(tx)) => {
+                e.array(2)?.u16(6)?;
+                e.encode(tx)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'b> Decode<'b> for Message {
+    fn decode(d: &mut minicbor::Decoder<'b>) -> Result<Self, decode::Error> {
+        let size = d.array()?;
+        let label = d.u16()?;
+
+        match (label, size) {
+            (0, _) => Ok(Message::Done),
+            (1, _) => Ok(Message::Acquire),
+            (2, _) => Ok(Message::Acquired(d.u64()?)),
+            (3, _) => Ok(Message::Release),
+            (5, _) => Ok(Message::NextTx),
+            (6, Some(1)) => Ok(Message::ReplyNextTx(None)),
+            (6, _) => Ok(Message::ReplyNextTx(Some(d.decode()?))),
+            _ => Err(decode::Error::message(
+                "unknown variant of tx monitor message",
+            )),
+        }
+    }
+}
+
+/// The txs of the mempool at the slot where the snapshot was acquired
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub slot: u64,
+    pub txs: Vec<EraTx>,
+}
+
+pub struct Client {
+    channel: Channel,
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, msg: Message) -> Result<(), miniprotocols::Error> {
+        let mut payload = Vec::new();
+        minicbor::encode(&msg, &mut payload)?;
+        self.channel.0.send(payload)?;
+
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Message, miniprotocols::Error> {
+        miniprotocols::read_until_full_msg(&mut self.buffer, &mut self.channel.1)
+    }
+
+    /// Acquires a snapshot of the current mempool, reads all of its txs and
+    /// releases it
+    pub fn snapshot(&mut self) -> Result<Snapshot, miniprotocols::Error> {
+        self.send(Message::Acquire)?;
+
+        let slot = match self.recv()? {
+            Message::Acquired(slot) => slot,
+            other => return Err(format!("expected acquired, got {:?}", other).into()),
+        };
+
+        let mut txs = Vec::new();
+
+        loop {
+            self.send(Message::NextTx)?;
+
+            match self.recv()? {
+                Message::ReplyNextTx(Some(tx)) => txs.push(tx),
+                Message::ReplyNextTx(None) => break,
+                other => return Err(format!("expected next tx reply, got {:?}", other).into()),
+            }
+        }
+
+        self.send(Message::Release)?;
+
+        Ok(Snapshot { slot, txs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn roundtrip(msg: Message) -> Message {
+        let mut payload = Vec::new();
+        minicbor::encode(&msg, &mut payload).unwrap();
+        minicbor::decode(&payload).unwrap()
+    }
+
+    #[test]
+    fn messages_roundtrip() {
+        let messages = [
+            Message::Done,
+            Message::Acquire,
+            Message::Acquired(42),
+            Message::Release,
+            Message::NextTx,
+            Message::ReplyNextTx(None),
+            Message::ReplyNextTx(Some(EraTx(5, vec![0x84, 0xa0]))),
+        ];
+
+        for msg in messages {
+            assert_eq!(roundtrip(msg.clone()), msg);
+        }
+
+        // [6, [5, 24(h'84a0')]]
+        let mut payload = Vec::new();
+        minicbor::encode(
+            Message::ReplyNextTx(Some(EraTx(5, vec![0x84, 0xa0]))),
+            &mut payload,
+        )
+        .unwrap();
+        assert_eq!(hex::encode(payload), "82068205d8184284a0");
+    }
+
+    #[test]
+    fn snapshot_walks_the_mempool_and_releases_it() {
+        let (to_node, from_client) = mpsc::channel();
+        let (to_client, from_node) = mpsc::channel();
+
+        let mut client = Client::new(Channel(to_node, from_node));
+
+        let node = std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let mut rx = from_client;
+            let mut received = Vec::new();
+
+            let reply = |msg: Message| {
+                let mut payload = Vec::new();
+                minicbor::encode(&msg, &mut payload).unwrap();
+                to_client.send(payload).unwrap();
+            };
+
+            let mut replies = vec![
+                Message::Acquired(100),
+                Message::ReplyNextTx(Some(EraTx(4, vec![1]))),
+                Message::ReplyNextTx(Some(EraTx(5, vec![2]))),
+                Message::ReplyNextTx(None),
+            ]
+            .into_iter();
+
+            loop {
+                let msg: Message =
+                    miniprotocols::read_until_full_msg(&mut buffer, &mut rx).unwrap();
+                let release = msg == Message::Release;
+                received.push(msg);
+
+                if release {
+                    return received;
+                }
+
+                reply(replies.next().unwrap());
+            }
+        });
+
+        let snapshot = client.snapshot().unwrap();
+
+        assert_eq!(snapshot.slot, 100);
+        assert_eq!(snapshot.txs, vec![EraTx(4, vec![1]), EraTx(5, vec![2])]);
+
+        let received = node.join().unwrap();
+        assert_eq!(
+            received,
+            vec![
+                Message::Acquire,
+                Message::NextTx,
+                Message::NextTx,
+                Message::NextTx,
+                Message::Release
+            ]
+        );
+    }
+}
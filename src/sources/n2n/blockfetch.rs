@@ -5,18 +5,32 @@ use pallas::network::{
 
 use gasket::{error::*, runtime::WorkOutcome};
 
+use crate::crosscut;
 use crate::model::{ChainSyncCommand, ChainSyncCommandEx};
 
 use crate::sources::utils;
 
 struct Observer<'a> {
+    point: Point,
+    policy: &'a crosscut::RuntimePolicy,
+    skipped_count: &'a gasket::metrics::Counter,
     output: &'a mut FanoutPort,
 }
 
 impl<'a> blockfetch::Observer for Observer<'a> {
     fn on_block_received(&mut self, body: Vec<u8>) -> Result<(), Error> {
-        let block = utils::parse_block_content(&body)?;
-        self.output.send(ChainSyncCommandEx::roll_forward(block))?;
+        match utils::parse_block_content(&body) {
+            Ok(block) => {
+                self.output.send(ChainSyncCommandEx::roll_forward(block))?;
+            }
+            Err(err) => match self.policy.on_decode_error.unwrap_or_default() {
+                crosscut::ErrorAction::Fail => return Err(err.into()),
+                crosscut::ErrorAction::Skip => {
+                    log::error!("skipping undecodable block at {:?}: {}", self.point, err);
+                    self.skipped_count.inc(1);
+                }
+            },
+        };
 
         Ok(())
     }
@@ -27,18 +41,27 @@ pub type FanoutPort = gasket::messaging::FanoutPort<ChainSyncCommandEx>;
 
 pub struct Worker {
     channel: Channel,
+    policy: crosscut::RuntimePolicy,
     block_count: gasket::metrics::Counter,
+    skipped_count: gasket::metrics::Counter,
     input: InputPort,
     output: FanoutPort,
 }
 
 impl Worker {
-    pub fn new(channel: Channel, input: InputPort, output: FanoutPort) -> Self {
+    pub fn new(
+        channel: Channel,
+        policy: crosscut::RuntimePolicy,
+        input: InputPort,
+        output: FanoutPort,
+    ) -> Self {
         Self {
             channel,
+            policy,
             input,
             output,
             block_count: Default::default(),
+            skipped_count: Default::default(),
         }
     }
 
@@ -46,6 +69,9 @@ impl Worker {
         log::debug!("initiating chainsync");
 
         let observer = Observer {
+            point: point.clone(),
+            policy: &self.policy,
+            skipped_count: &self.skipped_count,
             output: &mut self.output,
         };
 
@@ -62,10 +88,17 @@ impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
         gasket::metrics::Builder::new()
             .with_counter("block_count", &self.block_count)
+            .with_counter("skipped_blocks", &self.skipped_count)
             .build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
+        // the headers stage keeps the intersection, blocks wait in the channel
+        if self.policy.pause.is_paused() {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            return Ok(WorkOutcome::Idle);
+        }
+
         let input = self.input.recv()?;
 
         match input.payload {
@@ -80,3 +113,74 @@ impl gasket::runtime::Worker for Worker {
         Ok(WorkOutcome::Partial)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{address, block};
+
+    fn observe(on_decode_error: crosscut::ErrorAction, body: Vec<u8>) -> (bool, usize) {
+        let policy = crosscut::RuntimePolicy {
+            on_decode_error: Some(on_decode_error),
+            ..Default::default()
+        };
+
+        let skipped_count = gasket::metrics::Counter::default();
+
+        let mut output = FanoutPort::default();
+        let mut input = gasket::messaging::InputPort::default();
+        gasket::messaging::connect_ports(&mut output, &mut input, 10);
+
+        let mut observer = Observer {
+            point: Point::Specific(50_000_000, vec![0; 32]),
+            policy: &policy,
+            skipped_count: &skipped_count,
+            output: &mut output,
+        };
+
+        let result = blockfetch::Observer::on_block_received(&mut observer, body);
+
+        let mut received = 0;
+        while input.try_recv().is_ok() {
+            received += 1;
+        }
+
+        (result.is_ok(), received)
+    }
+
+    fn corrupt_block() -> Vec<u8> {
+        let valid = block()
+            .tx(|t| t.output(&address(1), 5_000_000))
+            .build()
+            .encode()
+            .unwrap();
+
+        // still tagged as an alonzo block, but cut in the middle of the body
+        valid[..valid.len() / 2].to_vec()
+    }
+
+    #[test]
+    fn corrupt_block_is_skipped_under_skip() {
+        let (ok, received) = observe(crosscut::ErrorAction::Skip, corrupt_block());
+
+        assert!(ok);
+        assert_eq!(received, 0);
+    }
+
+    #[test]
+    fn corrupt_block_fails_under_fail() {
+        let (ok, received) = observe(crosscut::ErrorAction::Fail, corrupt_block());
+
+        assert!(!ok);
+        assert_eq!(received, 0);
+    }
+
+    #[test]
+    fn valid_block_goes_through_under_skip() {
+        let valid = block().build().encode().unwrap();
+        let (ok, received) = observe(crosscut::ErrorAction::Skip, valid);
+
+        assert!(ok);
+        assert_eq!(received, 1);
+    }
+}
@@ -10,7 +10,7 @@ use gasket::{
     metrics::{Counter, Gauge},
 };
 
-use crate::model::ChainSyncCommand;
+use crate::{model::ChainSyncCommand, sources::utils};
 
 use super::messages;
 
@@ -20,6 +20,7 @@ struct ChainObserver {
     chain_buffer: chainsync::RollbackBuffer,
     block_count: gasket::metrics::Counter,
     chain_tip: gasket::metrics::Gauge,
+    pending_intersection: Option<Vec<Point>>,
 }
 
 impl ChainObserver {
@@ -27,6 +28,7 @@ impl ChainObserver {
         min_depth: usize,
         block_count: Counter,
         chain_tip: Gauge,
+        known_points: Option<Vec<Point>>,
         output: gasket::messaging::OutputPort<ChainSyncCommand>,
     ) -> Self {
         Self {
@@ -35,6 +37,7 @@ impl ChainObserver {
             chain_tip,
             output,
             chain_buffer: Default::default(),
+            pending_intersection: known_points,
         }
     }
 }
@@ -78,6 +81,10 @@ impl chainsync::Observer<chainsync::HeaderContent> for ChainObserver {
     }
 
     fn on_rollback(&mut self, point: &Point) -> Result<chainsync::Continuation, Error> {
+        if let Some(known_points) = self.pending_intersection.take() {
+            utils::log_intersection(&known_points, point);
+        }
+
         log::info!("rolling block to point {:?}", point);
 
         match self.chain_buffer.roll_back(point) {
@@ -140,9 +147,10 @@ impl gasket::runtime::Worker for Worker {
         let mut runner = Runner::new(chainsync::Consumer::initial(
             self.known_points.clone(),
             ChainObserver::new(
-                self.min_depth as usize,
+                self.min_depth,
                 self.block_count.clone(),
                 self.chain_tip.clone(),
+                self.known_points.clone(),
                 self.output.clone(),
             ),
         ));
@@ -171,3 +179,105 @@ impl gasket::runtime::Worker for Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use gasket::runtime::Worker as _;
+    use pallas::codec::minicbor;
+
+    use super::*;
+    use crate::crosscut;
+
+    type Msg = chainsync::Message<chainsync::HeaderContent>;
+
+    fn point(slot: u64) -> Point {
+        Point::Specific(slot, vec![slot as u8; 32])
+    }
+
+    /// A peer whose chain has the given points, it intersects at the highest
+    /// of the requested ones and rolls the client back to it
+    fn mock_peer(chain: Vec<Point>) -> (Channel, std::thread::JoinHandle<Vec<Point>>) {
+        let (to_peer, from_client) = mpsc::channel();
+        let (to_client, from_peer) = mpsc::channel();
+
+        let peer = std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let mut rx = from_client;
+
+            let reply = |msg: Msg| {
+                let mut payload = Vec::new();
+                minicbor::encode(&msg, &mut payload).unwrap();
+                to_client.send(payload).unwrap();
+            };
+
+            let tip = chainsync::Tip(chain.last().unwrap().clone(), chain.len() as u64);
+
+            let requested = match miniprotocols::read_until_full_msg(&mut buffer, &mut rx).unwrap()
+            {
+                Msg::FindIntersect(points) => points,
+                other => panic!("expected find intersect, got {:?}", other),
+            };
+
+            let found = requested
+                .iter()
+                .filter(|x| chain.contains(x))
+                .max_by_key(|x| x.slot_or_default())
+                .unwrap()
+                .clone();
+
+            reply(Msg::IntersectFound(found.clone(), tip.clone()));
+
+            match miniprotocols::read_until_full_msg(&mut buffer, &mut rx).unwrap() {
+                Msg::RequestNext => reply(Msg::RollBackward(found, tip)),
+                other => panic!("expected request next, got {:?}", other),
+            }
+
+            requested
+        });
+
+        (Channel(to_peer, from_peer), peer)
+    }
+
+    #[test]
+    fn sync_starts_from_the_highest_common_fallback() {
+        let (mut channel, peer) = mock_peer(vec![point(10), point(20), point(30)]);
+
+        let intersect = crosscut::IntersectConfig::Fallbacks(vec![
+            point(10).into(),
+            point(99).into(),
+            point(20).into(),
+            point(10).into(),
+        ]);
+
+        let known_points = utils::define_known_points(
+            &crosscut::ChainWellKnownInfo::mainnet(),
+            &intersect,
+            &None,
+            &mut channel,
+        )
+        .unwrap();
+
+        let mut output = OutputPort::default();
+        let mut input = gasket::messaging::InputPort::default();
+        gasket::messaging::connect_ports(&mut output, &mut input, 10);
+
+        let mut worker = Worker::new(channel, 0, known_points, output);
+        worker.bootstrap().unwrap();
+
+        // each step sends or receives a single message: the intersection,
+        // then the rollback to it
+        for _ in 0..4 {
+            worker.work().unwrap();
+        }
+
+        let requested = peer.join().unwrap();
+        assert_eq!(requested, vec![point(99), point(20), point(10)]);
+
+        match input.recv().unwrap().payload {
+            ChainSyncCommand::RollBack(x) => assert_eq!(x, point(20)),
+            other => panic!("expected a rollback, got {:?}", other),
+        }
+    }
+}
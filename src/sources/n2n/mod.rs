@@ -27,6 +27,9 @@ use super::utils;
 #[derive(Deserialize)]
 pub struct Config {
     pub address: String,
+
+    #[serde(flatten)]
+    pub versions: utils::VersionRange,
 }
 
 pub struct Plugin {
@@ -34,13 +37,21 @@ pub struct Plugin {
     intersect: crosscut::IntersectConfig,
     chain: crosscut::ChainWellKnownInfo,
     cursor: crosscut::Cursor,
+    policy: crosscut::RuntimePolicy,
     output: FanoutPort<ChainSyncCommandEx>,
 }
 
 impl Plugin {
     fn bootstrap_transport(&self) -> Result<Transport, gasket::error::Error> {
         gasket::retries::retry_operation(
-            || Transport::setup(&self.config.address, self.chain.magic).or_work_err(),
+            || {
+                Transport::setup(
+                    &self.config.address,
+                    self.chain.magic,
+                    &self.config.versions,
+                )
+                .or_work_err()
+            },
             &retries::Policy {
                 max_retries: 5,
                 backoff_factor: 2,
@@ -84,7 +95,7 @@ impl super::Pluggable for Plugin {
         pipeline.register_stage(
             "n2n-blocks",
             gasket::runtime::spawn_stage(
-                self::blockfetch::Worker::new(bf_channel, headers_in, self.output),
+                self::blockfetch::Worker::new(bf_channel, self.policy, headers_in, self.output),
                 gasket::runtime::Policy::default(),
             ),
         );
@@ -97,12 +108,14 @@ impl Config {
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
         cursor: &crosscut::Cursor,
+        policy: &crosscut::RuntimePolicy,
     ) -> super::Plugin {
         let plugin = Plugin {
             config: self,
             intersect: intersect.clone(),
             chain: chain.clone(),
             cursor: cursor.clone(),
+            policy: policy.clone(),
             output: Default::default(),
         };
 
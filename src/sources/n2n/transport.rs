@@ -5,8 +5,11 @@ use pallas::network::{
     multiplexer::Multiplexer,
 };
 
+use crate::sources::utils::{negotiate_version, VersionRange};
+
 pub struct Transport {
     pub muxer: Multiplexer,
+    #[allow(dead_code)]
     pub version: handshake::VersionNumber,
 }
 
@@ -26,24 +29,21 @@ impl Transport {
     fn do_handshake(
         muxer: &mut Multiplexer,
         magic: u64,
+        range: &VersionRange,
     ) -> Result<handshake::VersionNumber, miniprotocols::Error> {
-        log::debug!("doing handshake");
-
         let mut channel = muxer.use_channel(0);
         let versions = handshake::n2n::VersionTable::v6_and_above(magic);
-        let agent =
-            miniprotocols::run_agent(handshake::Initiator::initial(versions), &mut channel)?;
-        log::info!("handshake output: {:?}", agent.output);
-
-        match agent.output {
-            handshake::Output::Accepted(version, _) => Ok(version),
-            _ => Err("couldn't agree on handshake version".into()),
-        }
+
+        negotiate_version(&mut channel, versions, range)
     }
 
-    pub fn setup(address: &str, magic: u64) -> Result<Self, miniprotocols::Error> {
+    pub fn setup(
+        address: &str,
+        magic: u64,
+        range: &VersionRange,
+    ) -> Result<Self, miniprotocols::Error> {
         let mut muxer = Self::connect_muxer(address)?;
-        let version = Self::do_handshake(&mut muxer, magic)?;
+        let version = Self::do_handshake(&mut muxer, magic, range)?;
 
         Ok(Self { muxer, version })
     }
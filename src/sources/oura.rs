@@ -0,0 +1,389 @@
+//! Source that replays the NDJSON output of an Oura file sink
+//!
+//! Blocks are rebuilt from the raw CBOR attached to Oura `block` events, so
+//! Oura has to run with `include_block_cbor = true`. With that, every reducer
+//! gets the same data as with a direct node connection. Other event types are
+//! ignored since their content is already part of the block CBOR. Rollbacks are
+//! taken from `roll_back` events.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    time::Duration,
+};
+
+use gasket::{
+    error::AsWorkError,
+    messaging::FanoutPort,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{bootstrap::Pipeline, crosscut, model::ChainSyncCommandEx, Error};
+
+use super::utils;
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Path of the NDJSON file written by Oura
+    pub path: String,
+}
+
+pub struct Plugin {
+    config: Config,
+    intersect: crosscut::IntersectConfig,
+    policy: crosscut::RuntimePolicy,
+    output: FanoutPort<ChainSyncCommandEx>,
+}
+
+impl super::Pluggable for Plugin {
+    fn borrow_output_port(&mut self) -> &'_ mut FanoutPort<ChainSyncCommandEx> {
+        &mut self.output
+    }
+
+    fn spawn(self, pipeline: &mut Pipeline) {
+        let start = match &self.intersect {
+            crosscut::IntersectConfig::Point(x) => Some(x.clone()),
+            crosscut::IntersectConfig::Range(x, _) => Some(x.clone()),
+            crosscut::IntersectConfig::Fallbacks(x) => x.first().cloned(),
+            crosscut::IntersectConfig::Origin => None,
+            crosscut::IntersectConfig::Tip | crosscut::IntersectConfig::Checkpoint => {
+                log::warn!("oura source can't start from tip, replaying the whole file");
+                None
+            }
+        };
+
+        let start_slot = match start {
+            Some(crosscut::PointArg::Specific(slot, _)) => Some(slot),
+            _ => None,
+        };
+
+        let worker = Worker {
+            path: self.config.path,
+            start_slot,
+            policy: self.policy,
+            reader: None,
+            pending: String::new(),
+            output: self.output,
+            block_count: Default::default(),
+            skipped_count: Default::default(),
+        };
+
+        pipeline.register_stage("oura", spawn_stage(worker, Default::default()));
+    }
+}
+
+pub struct Worker {
+    path: String,
+    start_slot: Option<u64>,
+    policy: crosscut::RuntimePolicy,
+    reader: Option<BufReader<File>>,
+    pending: String,
+    output: FanoutPort<ChainSyncCommandEx>,
+    block_count: gasket::metrics::Counter,
+    skipped_count: gasket::metrics::Counter,
+}
+
+fn parse_roll_back(data: &serde_json::Value) -> Result<Point, Error> {
+    let slot = data["block_slot"]
+        .as_u64()
+        .ok_or_else(|| Error::message("oura rollback event without slot"))?;
+
+    let hash = data["block_hash"]
+        .as_str()
+        .ok_or_else(|| Error::message("oura rollback event without hash"))?;
+
+    let hash = hex::decode(hash).map_err(|_| Error::message("invalid oura rollback hash"))?;
+
+    Ok(Point::Specific(slot, hash))
+}
+
+impl Worker {
+    fn process_block(&mut self, data: &serde_json::Value) -> Result<(), gasket::error::Error> {
+        if let (Some(start), Some(slot)) = (self.start_slot, data["slot"].as_u64()) {
+            if slot <= start {
+                return Ok(());
+            }
+        }
+
+        let cbor = match data["cbor_hex"].as_str() {
+            Some(x) => x,
+            None => {
+                return Err(Error::config(
+                    "oura block events need the cbor, enable `include_block_cbor` in oura",
+                ))
+                .or_work_err()
+            }
+        };
+
+        let block = hex::decode(cbor)
+            .map_err(|_| Error::message("invalid oura block cbor hex"))
+            .and_then(|x| utils::parse_block_content(&x));
+
+        match block {
+            Ok(block) => {
+                self.output.send(ChainSyncCommandEx::roll_forward(block))?;
+                self.block_count.inc(1);
+            }
+            Err(err) => match self.policy.on_decode_error.unwrap_or_default() {
+                crosscut::ErrorAction::Fail => return Err(err).or_work_err(),
+                crosscut::ErrorAction::Skip => {
+                    log::error!("skipping undecodable oura block: {}", err);
+                    self.skipped_count.inc(1);
+                }
+            },
+        };
+
+        Ok(())
+    }
+
+    fn process_line(&mut self, line: &str) -> Result<(), gasket::error::Error> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        let event: serde_json::Value = serde_json::from_str(line)
+            .map_err(|err| Error::message(format!("invalid oura event: {}", err)))
+            .or_work_err()?;
+
+        if let Some(data) = event.get("block") {
+            self.process_block(data)?;
+        } else if let Some(data) = event.get("roll_back") {
+            let point = parse_roll_back(data).or_work_err()?;
+            self.output.send(ChainSyncCommandEx::roll_back(point))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_counter("skipped_blocks", &self.skipped_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let file = File::open(&self.path).or_work_err()?;
+        self.reader = Some(BufReader::new(file));
+
+        Ok(())
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        if self.policy.pause.is_paused() {
+            std::thread::sleep(Duration::from_millis(500));
+            return Ok(WorkOutcome::Idle);
+        }
+
+        let read = self
+            .reader
+            .as_mut()
+            .unwrap()
+            .read_line(&mut self.pending)
+            .or_work_err()?;
+
+        // oura might still be writing the file, wait for complete lines
+        if read == 0 || !self.pending.ends_with('\n') {
+            std::thread::sleep(Duration::from_millis(500));
+            return Ok(WorkOutcome::Idle);
+        }
+
+        let line = std::mem::take(&mut self.pending);
+        self.process_line(&line)?;
+
+        Ok(WorkOutcome::Partial)
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        _chain: &crosscut::ChainWellKnownInfo,
+        intersect: &crosscut::IntersectConfig,
+        _cursor: &crosscut::Cursor,
+        policy: &crosscut::RuntimePolicy,
+    ) -> super::Plugin {
+        let plugin = Plugin {
+            config: self,
+            intersect: intersect.clone(),
+            policy: policy.clone(),
+            output: Default::default(),
+        };
+
+        super::Plugin::Oura(plugin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model, testing};
+
+    fn block_event(block: &model::MultiEraBlock) -> serde_json::Value {
+        serde_json::json!({
+            "context": { "slot": block.slot() },
+            "block": {
+                "slot": block.slot(),
+                "cbor_hex": hex::encode(block.encode().unwrap()),
+            }
+        })
+    }
+
+    fn worker(
+        path: &std::path::Path,
+        start_slot: Option<u64>,
+        policy: crosscut::RuntimePolicy,
+    ) -> Worker {
+        Worker {
+            path: path.to_string_lossy().to_string(),
+            start_slot,
+            policy,
+            reader: None,
+            pending: String::new(),
+            output: Default::default(),
+            block_count: Default::default(),
+            skipped_count: Default::default(),
+        }
+    }
+
+    #[test]
+    fn fixture_with_a_rollback_replays_in_order() {
+        let blocks: Vec<_> = (0..3)
+            .map(|i| {
+                testing::block()
+                    .slot(testing::DEFAULT_SLOT + i * 20)
+                    .number(i + 1)
+                    .tx(|t| t.output(&testing::address(i as u8), 1_000_000))
+                    .build()
+            })
+            .collect();
+
+        let rollback_to = blocks[0].point().unwrap();
+        let hash = match &rollback_to {
+            Point::Specific(_, x) => hex::encode(x),
+            Point::Origin => unreachable!(),
+        };
+
+        let events = [
+            block_event(&blocks[0]),
+            serde_json::json!({ "transaction": { "hash": "ab" } }),
+            block_event(&blocks[1]),
+            serde_json::json!({
+                "roll_back": { "block_slot": testing::DEFAULT_SLOT, "block_hash": hash }
+            }),
+            block_event(&blocks[2]),
+        ];
+
+        let path = std::env::temp_dir().join(format!("scrolls-oura-{}.ndjson", std::process::id()));
+        let content: String = events.iter().map(|x| format!("{}\n", x)).collect();
+        std::fs::write(&path, content).unwrap();
+
+        let mut worker = worker(&path, None, Default::default());
+
+        let mut input = gasket::messaging::InputPort::default();
+        gasket::messaging::connect_ports(&mut worker.output, &mut input, 10);
+
+        gasket::runtime::Worker::bootstrap(&mut worker).unwrap();
+
+        for _ in events.iter() {
+            gasket::runtime::Worker::work(&mut worker).unwrap();
+        }
+
+        let mut received = Vec::new();
+
+        while let Ok(msg) = input.try_recv() {
+            let step = match msg.payload {
+                ChainSyncCommandEx::RollForward(x) => format!("forward {}", x.slot()),
+                ChainSyncCommandEx::RollBack(x) => format!("back {}", x.slot_or_default()),
+            };
+
+            received.push(step);
+        }
+
+        let slot = |i: u64| testing::DEFAULT_SLOT + i * 20;
+
+        assert_eq!(
+            received,
+            vec![
+                format!("forward {}", slot(0)),
+                format!("forward {}", slot(1)),
+                format!("back {}", slot(0)),
+                format!("forward {}", slot(2)),
+            ]
+        );
+        assert_eq!(worker.block_count.get(), 3);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn paused_source_holds_the_blocks_until_resumed() {
+        let blocks: Vec<_> = (0..3)
+            .map(|i| {
+                testing::block()
+                    .slot(testing::DEFAULT_SLOT + i * 20)
+                    .number(i + 1)
+                    .build()
+            })
+            .collect();
+
+        let path =
+            std::env::temp_dir().join(format!("scrolls-oura-paused-{}.ndjson", std::process::id()));
+
+        let content: String = blocks
+            .iter()
+            .map(|x| format!("{}\n", block_event(x)))
+            .collect();
+
+        std::fs::write(&path, content).unwrap();
+
+        let pause = crosscut::PauseGate::paused();
+
+        let policy = crosscut::RuntimePolicy {
+            pause: pause.clone(),
+            ..Default::default()
+        };
+
+        // resumes from the cursor, at the first block
+        let mut worker = worker(&path, Some(testing::DEFAULT_SLOT), policy);
+
+        let mut input = gasket::messaging::InputPort::default();
+        gasket::messaging::connect_ports(&mut worker.output, &mut input, 10);
+
+        gasket::runtime::Worker::bootstrap(&mut worker).unwrap();
+
+        for _ in 0..2 {
+            let outcome = gasket::runtime::Worker::work(&mut worker).unwrap();
+            assert!(matches!(outcome, WorkOutcome::Idle));
+        }
+
+        assert!(input.try_recv().is_err());
+        assert_eq!(worker.block_count.get(), 0);
+
+        pause.resume();
+
+        for _ in blocks.iter() {
+            gasket::runtime::Worker::work(&mut worker).unwrap();
+        }
+
+        let mut received = Vec::new();
+
+        while let Ok(msg) = input.try_recv() {
+            match msg.payload {
+                ChainSyncCommandEx::RollForward(x) => received.push(x.slot()),
+                ChainSyncCommandEx::RollBack(_) => panic!("no rollback in the file"),
+            }
+        }
+
+        assert_eq!(
+            received,
+            vec![testing::DEFAULT_SLOT + 20, testing::DEFAULT_SLOT + 40]
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,23 +1,109 @@
+use std::{collections::HashMap, fmt::Debug};
+
 use pallas::{
     ledger::primitives::{alonzo, byron, probing, Era, Fragment},
     network::{
-        miniprotocols::{chainsync::TipFinder, run_agent, Point},
+        miniprotocols::{self, chainsync::TipFinder, handshake, run_agent, Point},
         multiplexer::Channel,
     },
 };
 
+use serde::Deserialize;
+
 use crate::{crosscut, model, Error};
 
+/// Bounds of the protocol versions advertised in the handshake with the node
+///
+/// The node accepts the highest version that both sides support, narrowing
+/// the range pins the connection to versions known to behave as expected.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct VersionRange {
+    pub min_protocol_version: Option<u64>,
+    pub max_protocol_version: Option<u64>,
+}
+
+impl VersionRange {
+    fn contains(&self, version: u64) -> bool {
+        self.min_protocol_version
+            .map(|x| version >= x)
+            .unwrap_or(true)
+            && self
+                .max_protocol_version
+                .map(|x| version <= x)
+                .unwrap_or(true)
+    }
+
+    /// Removes the versions out of the range from a version table, returns
+    /// the remaining ones in ascending order
+    pub fn restrict<T>(&self, values: &mut HashMap<u64, T>) -> Result<Vec<u64>, Error> {
+        let known: Vec<_> = values.keys().copied().collect();
+
+        values.retain(|version, _| self.contains(*version));
+
+        let mut supported: Vec<_> = values.keys().copied().collect();
+        supported.sort_unstable();
+
+        if supported.is_empty() {
+            return Err(Error::config(format!(
+                "protocol version range {:?}..{:?} excludes every known version {:?}",
+                self.min_protocol_version, self.max_protocol_version, known
+            )));
+        }
+
+        Ok(supported)
+    }
+}
+
+/// Describes a handshake refused because the node offered none of the
+/// versions that we support
+pub fn version_mismatch(supported: &[u64], offered: &[u64]) -> String {
+    format!(
+        "no protocol version in common with the node, supported: {:?}, offered by the node: {:?}",
+        supported, offered
+    )
+}
+
+/// Runs the handshake over the channel, proposing the versions of the table
+/// within the range, and returns the one accepted by the node
+pub fn negotiate_version<D>(
+    channel: &mut Channel,
+    mut versions: handshake::VersionTable<D>,
+    range: &VersionRange,
+) -> Result<handshake::VersionNumber, miniprotocols::Error>
+where
+    D: Debug + Clone,
+    handshake::Message<D>: pallas::codec::Fragment,
+{
+    log::debug!("doing handshake");
+
+    let supported = range.restrict(&mut versions.values)?;
+    log::debug!("advertising protocol versions {:?}", supported);
+
+    let agent = run_agent(handshake::Initiator::initial(versions), channel)?;
+    log::debug!("handshake output: {:?}", agent.output);
+
+    match agent.output {
+        handshake::Output::Accepted(version, _) => {
+            log::info!("negotiated protocol version {}", version);
+            Ok(version)
+        }
+        handshake::Output::Refused(handshake::RefuseReason::VersionMismatch(offered)) => {
+            Err(version_mismatch(&supported, &offered).into())
+        }
+        other => Err(format!("couldn't agree on handshake version: {:?}", other).into()),
+    }
+}
+
 pub fn parse_block_content(body: &[u8]) -> Result<model::MultiEraBlock, Error> {
-    match probing::probe_block_cbor_era(&body) {
+    match probing::probe_block_cbor_era(body) {
         probing::Outcome::Matched(era) => match era {
             Era::Byron => {
-                let primitive = byron::Block::decode_fragment(&body)?;
+                let primitive = byron::Block::decode_fragment(body)?;
                 let block = model::MultiEraBlock::Byron(primitive);
                 Ok(block)
             }
             _ => {
-                let primitive = alonzo::BlockWrapper::decode_fragment(&body)?;
+                let primitive = alonzo::BlockWrapper::decode_fragment(body)?;
                 let block = model::MultiEraBlock::AlonzoCompatible(primitive);
                 Ok(block)
             }
@@ -25,13 +111,13 @@ pub fn parse_block_content(body: &[u8]) -> Result<model::MultiEraBlock, Error> {
         // TODO: we're assuming that the genesis block is Byron-compatible. Is this a safe
         // assumption?
         probing::Outcome::GenesisBlock => {
-            let primitive = byron::Block::decode_fragment(&body)?;
+            let primitive = byron::Block::decode_fragment(body)?;
             let block = model::MultiEraBlock::Byron(primitive);
             Ok(block)
         }
         probing::Outcome::Inconclusive => {
             let msg = format!("can't infer primitive block from cbor, inconclusive probing. CBOR hex for debugging: {}", hex::encode(body));
-            return Err(Error::Message(msg));
+            Err(Error::Message(msg))
         }
     }
 }
@@ -55,6 +141,37 @@ pub fn find_end_of_chain(
     }
 }
 
+fn point_slot(point: &Point) -> u64 {
+    match point {
+        Point::Origin => 0,
+        Point::Specific(slot, _) => *slot,
+    }
+}
+
+/// Sorts the fallback points from the most recent one and removes duplicates.
+/// The node intersects at the first point of the list that is on its chain,
+/// so this order makes it pick the highest common point.
+fn sort_fallbacks(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by_key(|x| std::cmp::Reverse(point_slot(x)));
+    points.dedup();
+    points
+}
+
+/// Logs which of the known points the node picked as the intersection, the
+/// first rollback of the chain-sync protocol goes to it
+pub fn log_intersection(known_points: &[Point], point: &Point) {
+    match known_points.iter().position(|x| x == point) {
+        Some(idx) if known_points.len() > 1 => log::info!(
+            "chain intersected at {:?}, fallback {} of {}",
+            point,
+            idx + 1,
+            known_points.len()
+        ),
+        Some(_) => log::info!("chain intersected at {:?}", point),
+        None => log::warn!("chain intersected at unknown point {:?}", point),
+    }
+}
+
 pub fn define_known_points(
     chain: &crosscut::ChainWellKnownInfo,
     intersect: &crosscut::IntersectConfig,
@@ -64,7 +181,7 @@ pub fn define_known_points(
     // if we have a cursor available, it should override any other configuration
     // opiton
     if let Some(point) = cursor {
-        return Ok(Some(vec![point.clone().try_into()?]));
+        return Ok(Some(vec![point.clone().normalize(chain).try_into()?]));
     }
 
     match &intersect {
@@ -79,7 +196,105 @@ pub fn define_known_points(
         }
         crosscut::IntersectConfig::Fallbacks(x) => {
             let points: Result<Vec<_>, _> = x.iter().cloned().map(|x| x.try_into()).collect();
-            Ok(Some(points?))
+            Ok(Some(sort_fallbacks(points?)))
+        }
+        crosscut::IntersectConfig::Range(from, _) => {
+            let point = from.clone().try_into()?;
+            Ok(Some(vec![point]))
         }
+        crosscut::IntersectConfig::Checkpoint => {
+            let resolved = intersect.clone().resolve(chain)?;
+            define_known_points(chain, &resolved, cursor, channel)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use pallas::codec::minicbor;
+
+    use super::*;
+
+    type N2nMessage = handshake::Message<handshake::n2n::VersionData>;
+
+    /// Runs the handshake against a peer that answers the proposal with the
+    /// given message, returns the outcome and the versions that were proposed
+    fn negotiate_with(
+        range: VersionRange,
+        reply: N2nMessage,
+    ) -> (Result<u64, miniprotocols::Error>, Vec<u64>) {
+        let (to_node, from_client) = mpsc::channel::<Vec<u8>>();
+        let (to_client, from_node) = mpsc::channel();
+
+        let node = std::thread::spawn(move || {
+            // [0, {version: data, ...}], pallas doesn't decode proposals
+            let payload = from_client.recv().unwrap();
+            let mut d = minicbor::Decoder::new(&payload);
+            d.array().unwrap();
+            assert_eq!(d.u16().unwrap(), 0);
+
+            let mut proposed = Vec::new();
+
+            for _ in 0..d.map().unwrap().unwrap() {
+                proposed.push(d.u64().unwrap());
+                d.skip().unwrap();
+            }
+
+            let mut payload = Vec::new();
+            minicbor::encode(&reply, &mut payload).unwrap();
+            to_client.send(payload).unwrap();
+
+            proposed
+        });
+
+        let mut channel = Channel(to_node, from_node);
+        let versions = handshake::n2n::VersionTable::v6_and_above(764824073);
+        let result = negotiate_version(&mut channel, versions, &range);
+
+        (result, node.join().unwrap())
+    }
+
+    #[test]
+    fn only_versions_within_the_range_are_proposed() {
+        let range = VersionRange {
+            min_protocol_version: Some(7),
+            max_protocol_version: None,
+        };
+
+        let accept = handshake::Message::Accept(7, handshake::n2n::VersionData::new(1, false));
+        let (result, proposed) = negotiate_with(range, accept);
+
+        assert_eq!(result.unwrap(), 7);
+        assert_eq!(proposed, vec![7]);
+    }
+
+    #[test]
+    fn peer_outside_of_the_range_is_refused_with_both_version_lists() {
+        let range = VersionRange {
+            min_protocol_version: None,
+            max_protocol_version: Some(6),
+        };
+
+        let refuse = handshake::Message::Refuse(handshake::RefuseReason::VersionMismatch(vec![9]));
+        let (result, proposed) = negotiate_with(range, refuse);
+
+        assert_eq!(proposed, vec![6]);
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("supported: [6]"), "{}", err);
+        assert!(err.contains("offered by the node: [9]"), "{}", err);
+    }
+
+    #[test]
+    fn range_without_known_versions_fails_before_connecting() {
+        let range = VersionRange {
+            min_protocol_version: Some(8),
+            max_protocol_version: None,
+        };
+
+        let mut versions = handshake::n2n::VersionTable::v6_and_above(1).values;
+        assert!(range.restrict(&mut versions).is_err());
     }
 }
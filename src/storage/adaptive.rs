@@ -0,0 +1,197 @@
+//! Commit batch sizing driven by the memory used by the process
+//!
+//! The commands of a batch are held in memory until its commit, large batches
+//! speed up the initial sync but can exhaust the memory of small hosts. The
+//! controller halves the batch when the resident memory of the process goes
+//! over the limit and grows it back one block at a time once usage is
+//! comfortably below it.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Memory usage is sampled at most this often
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Smallest number of blocks per commit, defaults to 1
+    pub min_blocks: Option<u64>,
+
+    /// Largest number of blocks per commit
+    pub max_blocks: u64,
+
+    /// Resident memory of the process, in MiB, above which batches shrink
+    pub max_rss_mb: u64,
+}
+
+pub struct Controller {
+    config: Config,
+    current: u64,
+    sampled_at: Option<Instant>,
+    size_gauge: gasket::metrics::Gauge,
+}
+
+/// Resident memory of the process in bytes, none where it can't be read
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    let line = status.lines().find(|x| x.starts_with("VmRSS:"))?;
+
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+impl Controller {
+    pub fn new(config: Config) -> Self {
+        let current = config.max_blocks.max(1);
+
+        Controller {
+            config,
+            current,
+            sampled_at: None,
+            size_gauge: Default::default(),
+        }
+    }
+
+    fn min_blocks(&self) -> u64 {
+        self.config.min_blocks.unwrap_or(1).max(1)
+    }
+
+    /// Adjusts the batch size to a memory usage reading
+    pub fn observe(&mut self, rss: u64) {
+        let limit = self.config.max_rss_mb * 1024 * 1024;
+
+        if rss >= limit {
+            let shrunk = (self.current / 2).max(self.min_blocks());
+
+            if shrunk < self.current {
+                log::warn!(
+                    "memory usage of {} MiB over the limit, commit batches shrink to {} blocks",
+                    rss / 1024 / 1024,
+                    shrunk
+                );
+            }
+
+            self.current = shrunk;
+        } else if rss < limit / 4 * 3 {
+            self.current = (self.current + 1).min(self.config.max_blocks.max(1));
+        }
+
+        self.size_gauge.set(self.current as i64);
+    }
+
+    /// Number of blocks that the current batch should hold
+    pub fn batch_size(&mut self) -> u64 {
+        let due = self
+            .sampled_at
+            .map(|x| x.elapsed() >= SAMPLE_INTERVAL)
+            .unwrap_or(true);
+
+        if due {
+            self.sampled_at = Some(Instant::now());
+
+            match resident_memory() {
+                Some(rss) => self.observe(rss),
+                None => log::debug!("can't read the memory usage of the process"),
+            }
+        }
+
+        self.current
+    }
+
+    pub fn size_gauge(&self) -> &gasket::metrics::Gauge {
+        &self.size_gauge
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.max_blocks == 0 || self.max_rss_mb == 0 {
+            return Err(crate::Error::config(
+                "adaptive batching needs max_blocks and max_rss_mb above zero",
+            ));
+        }
+
+        if self.min_blocks.unwrap_or(1) > self.max_blocks {
+            return Err(crate::Error::config(
+                "adaptive batching min_blocks can't be above max_blocks",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn controller() -> Controller {
+        Controller::new(Config {
+            min_blocks: Some(4),
+            max_blocks: 100,
+            max_rss_mb: 512,
+        })
+    }
+
+    #[test]
+    fn pressure_shrinks_the_batches_down_to_the_minimum() {
+        let mut controller = controller();
+        assert_eq!(controller.current, 100);
+
+        let sizes: Vec<_> = (0..6)
+            .map(|_| {
+                controller.observe(600 * MIB);
+                controller.current
+            })
+            .collect();
+
+        assert_eq!(sizes, vec![50, 25, 12, 6, 4, 4]);
+        assert_eq!(controller.size_gauge().get(), 4);
+    }
+
+    #[test]
+    fn comfortable_usage_grows_the_batches_back_to_the_maximum() {
+        let mut controller = controller();
+        controller.observe(600 * MIB);
+        assert_eq!(controller.current, 50);
+
+        // between the growth threshold and the limit, the size holds
+        controller.observe(400 * MIB);
+        assert_eq!(controller.current, 50);
+
+        controller.observe(100 * MIB);
+        controller.observe(100 * MIB);
+        assert_eq!(controller.current, 52);
+
+        for _ in 0..100 {
+            controller.observe(100 * MIB);
+        }
+
+        assert_eq!(controller.current, 100);
+    }
+
+    #[test]
+    fn bounds_are_validated() {
+        let config = |min_blocks, max_blocks, max_rss_mb| Config {
+            min_blocks,
+            max_blocks,
+            max_rss_mb,
+        };
+
+        assert!(config(None, 10, 512).validate().is_ok());
+        assert!(config(Some(20), 10, 512).validate().is_err());
+        assert!(config(None, 0, 512).validate().is_err());
+        assert!(config(None, 10, 0).validate().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Merges the commands of a batch that target the same key, so that the
+//! backend receives fewer ops for keys updated several times (eg: global
+//! counters)
+//!
+//! Only keys that are exclusively touched by commutative counter increments,
+//! or exclusively by plain writes and removals, are merged. Any other command
+//! on a key keeps all of the commands of that key as they are, so the final
+//! state of the batch is the same as applying every command in order.
+
+use std::{collections::HashMap, hash::Hash};
+
+use crate::model::{self, CRDTCommand};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Register,
+    Other,
+}
+
+fn touched_keys(command: &CRDTCommand) -> Vec<(String, Kind)> {
+    use CRDTCommand::*;
+
+    match command {
+        BlockStarting(_) | BlockFinished(_) => vec![],
+        PNCounter(key, value) => match value.parse::<i64>() {
+            Ok(_) => vec![(key.clone(), Kind::Counter)],
+            Err(_) => vec![(key.clone(), Kind::Other)],
+        },
+        AnyWriteWins(key, _) | AnyWriteWinsRemove(key) => vec![(key.clone(), Kind::Register)],
+        TwoPhaseSetRemove(key, _) => vec![
+            (key.clone(), Kind::Other),
+            (format!("{}.ts", key), Kind::Other),
+        ],
+        SortedSetCopyTop(from, into, _) => {
+            vec![(from.clone(), Kind::Other), (into.clone(), Kind::Other)]
+        }
+        CounterSnapshot(key, _, set, _) => {
+            vec![(key.clone(), Kind::Other), (set.clone(), Kind::Other)]
+        }
+        TwoPhaseSetAdd(key, _)
+        | GrowOnlySetAdd(key, _)
+        | SetRemove(key, _)
+        | HyperLogLogAdd(key, _)
+        | LastWriteWins(key, _, _)
+        | ConditionalWrite(key, _, _)
+        | SortedSetAdd(key, _, _)
+        | SortedSetRemoveRange(key, _, _) => vec![(key.clone(), Kind::Other)],
+    }
+}
+
+/// Merges the commands of the batch, each one tagged with the partition of
+/// the backend that it goes to (eg: a logical db) and the slot of its block.
+/// Returns the remaining commands, in order, and how many were merged away.
+pub fn coalesce<T: Eq + Hash + Clone>(
+    batch: Vec<(T, CRDTCommand, u64)>,
+) -> (Vec<(T, CRDTCommand, u64)>, usize) {
+    let mut kinds: HashMap<(T, String), Kind> = HashMap::new();
+
+    for (tag, command, _) in batch.iter() {
+        for (key, kind) in touched_keys(command) {
+            kinds
+                .entry((tag.clone(), key))
+                .and_modify(|x| {
+                    if *x != kind {
+                        *x = Kind::Other
+                    }
+                })
+                .or_insert(kind);
+        }
+    }
+
+    let kind_of = |tag: &T, key: &str| {
+        kinds
+            .get(&(tag.clone(), key.to_string()))
+            .copied()
+            .unwrap_or(Kind::Other)
+    };
+
+    // totals of the counters and position of the last write of the registers
+    let mut totals: HashMap<(T, String), i128> = HashMap::new();
+    let mut last_writes: HashMap<(T, String), usize> = HashMap::new();
+
+    for (idx, (tag, command, _)) in batch.iter().enumerate() {
+        match command {
+            CRDTCommand::PNCounter(key, value) if kind_of(tag, key) == Kind::Counter => {
+                let delta = value.parse::<i64>().unwrap_or_default() as i128;
+                *totals.entry((tag.clone(), key.clone())).or_insert(0) += delta;
+            }
+            CRDTCommand::AnyWriteWins(key, _) | CRDTCommand::AnyWriteWinsRemove(key)
+                if kind_of(tag, key) == Kind::Register =>
+            {
+                last_writes.insert((tag.clone(), key.clone()), idx);
+            }
+            _ => (),
+        }
+    }
+
+    let original = batch.len();
+    let mut out = Vec::with_capacity(original);
+
+    for (idx, (tag, command, slot)) in batch.into_iter().enumerate() {
+        match command {
+            CRDTCommand::PNCounter(key, _value) if kind_of(&tag, &key) == Kind::Counter => {
+                // the whole increment goes with the first command of the key
+                if let Some(total) = totals.remove(&(tag.clone(), key.clone())) {
+                    let value = model::clamp_delta(total).to_string();
+                    out.push((tag, CRDTCommand::PNCounter(key, value), slot));
+                }
+            }
+            CRDTCommand::AnyWriteWins(ref key, _) | CRDTCommand::AnyWriteWinsRemove(ref key)
+                if kind_of(&tag, key) == Kind::Register =>
+            {
+                if last_writes.get(&(tag.clone(), key.clone())) == Some(&idx) {
+                    out.push((tag, command, slot));
+                }
+            }
+            command => out.push((tag, command, slot)),
+        }
+    }
+
+    let merged = original - out.len();
+
+    (out, merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Store;
+    use CRDTCommand::*;
+
+    fn apply(batch: &[(u8, CRDTCommand, u64)]) -> Store {
+        let mut store = Store::default();
+
+        for (_, command, _) in batch {
+            store.apply(copy(command)).unwrap();
+        }
+
+        store
+    }
+
+    // commands aren't Clone, only the variants used below are needed
+    fn copy(command: &CRDTCommand) -> CRDTCommand {
+        match command {
+            PNCounter(k, v) => PNCounter(k.clone(), v.clone()),
+            AnyWriteWins(k, v) => AnyWriteWins(k.clone(), v.clone()),
+            AnyWriteWinsRemove(k) => AnyWriteWinsRemove(k.clone()),
+            GrowOnlySetAdd(k, m) => GrowOnlySetAdd(k.clone(), m.clone()),
+            SortedSetAdd(k, m, d) => SortedSetAdd(k.clone(), m.clone(), *d),
+            CounterSnapshot(k, d, s, t) => CounterSnapshot(k.clone(), *d, s.clone(), *t),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn batch() -> Vec<(u8, CRDTCommand, u64)> {
+        vec![
+            (0, PNCounter("count".into(), "2".into()), 1),
+            (0, AnyWriteWins("tip".into(), "a".into()), 1),
+            (0, PNCounter("count".into(), "3".into()), 2),
+            (1, PNCounter("count".into(), "7".into()), 2),
+            (0, AnyWriteWinsRemove("tip".into()), 2),
+            (0, AnyWriteWins("tip".into(), "b".into()), 3),
+            (0, PNCounter("count".into(), "-1".into()), 3),
+            (0, PNCounter("mixed".into(), "1".into()), 3),
+            (
+                0,
+                CounterSnapshot("mixed".into(), 1, "mixed.h".into(), 3),
+                3,
+            ),
+            (0, PNCounter("mixed".into(), "1".into()), 3),
+        ]
+    }
+
+    #[test]
+    fn merges_counters_and_registers_per_tag() {
+        let (out, merged) = coalesce(batch());
+
+        assert_eq!(merged, 4);
+        assert_eq!(out.len(), 6);
+
+        let counts: Vec<_> = out
+            .iter()
+            .filter_map(|(tag, command, _)| match command {
+                PNCounter(key, value) if key == "count" => Some((*tag, value.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(counts, vec![(0, "4"), (1, "7")]);
+    }
+
+    #[test]
+    fn keeps_the_final_state() {
+        let original = batch();
+        let expected = apply(&original);
+
+        let (out, _) = coalesce(original);
+        let actual = apply(&out);
+
+        for key in ["count", "tip", "mixed", "mixed.h"] {
+            assert_eq!(actual.get(key), expected.get(key), "key {}", key);
+        }
+    }
+}
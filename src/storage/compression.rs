@@ -0,0 +1,144 @@
+use std::io::{Read, Write};
+
+use serde::Deserialize;
+
+use crate::Error;
+
+/// Leading bytes of the gzip and zstd frames, neither can start a valid UTF-8
+/// string so compressed values can't be mistaken for plain ones
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Values shorter than this are kept plain unless configured otherwise, the
+/// frame header would eat most of what compression saves on them
+pub const DEFAULT_MIN_BYTES: usize = 256;
+
+/// Compression applied to values before they reach the storage backend
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).map_err(Error::storage)?;
+                encoder.finish().map_err(Error::storage)
+            }
+            Compression::Zstd => zstd::encode_all(data, 0).map_err(Error::storage),
+        }
+    }
+
+    /// Restores a value stored with any of the codecs, told apart by their
+    /// frame header
+    pub fn decode(data: &[u8]) -> Result<Vec<u8>, Error> {
+        let codec = if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        };
+
+        codec.decompress(data)
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut decoder = flate2::read::GzDecoder::new(data);
+                let mut out = Vec::new();
+                decoder.read_to_end(&mut out).map_err(Error::storage)?;
+                Ok(out)
+            }
+            Compression::Zstd => zstd::decode_all(data).map_err(Error::storage),
+        }
+    }
+}
+
+/// A compression along with the size from which values get it
+///
+/// Numbers are never compressed, the backend has to read them for counters
+/// and compare-and-set writes. Neither are members of sets and sorted sets,
+/// which the backend matches byte for byte on removal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Codec {
+    pub compression: Compression,
+    pub min_bytes: usize,
+}
+
+impl Codec {
+    pub fn new(compression: Compression, min_bytes: Option<usize>) -> Self {
+        Codec {
+            compression,
+            min_bytes: min_bytes.unwrap_or(DEFAULT_MIN_BYTES),
+        }
+    }
+
+    /// Compresses a value unless it's a number or shorter than the threshold
+    pub fn encode(&self, value: &[u8]) -> Result<Vec<u8>, Error> {
+        if value.len() < self.min_bytes {
+            return Ok(value.to_vec());
+        }
+
+        let numeric = std::str::from_utf8(value)
+            .map(|x| x.parse::<f64>().is_ok())
+            .unwrap_or(false);
+
+        match numeric {
+            true => Ok(value.to_vec()),
+            false => self.compression.compress(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_value_round_trips_through_zstd() {
+        let value = "a1b2c3d4".repeat(4096);
+
+        let stored = Codec::new(Compression::Zstd, None)
+            .encode(value.as_bytes())
+            .unwrap();
+        assert!(stored.len() < value.len());
+
+        assert_eq!(Compression::decode(&stored).unwrap(), value.as_bytes());
+    }
+
+    #[test]
+    fn codec_is_told_apart_by_the_stored_bytes() {
+        let value = br#"{"url":"https://example.com"}"#;
+
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let stored = Codec::new(compression, Some(0)).encode(value).unwrap();
+            assert_eq!(Compression::decode(&stored).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn small_values_and_numbers_stay_plain() {
+        let codec = Codec::new(Compression::Gzip, None);
+        let small = br#"{"url":"https://example.com"}"#;
+        assert_eq!(codec.encode(small).unwrap(), small);
+
+        let codec = Codec::new(Compression::Zstd, Some(0));
+        assert_eq!(codec.encode(b"-42").unwrap(), b"-42");
+        assert_eq!(codec.encode(b"1.5").unwrap(), b"1.5");
+
+        let large = "7".repeat(DEFAULT_MIN_BYTES * 2);
+        let codec = Codec::new(Compression::Zstd, None);
+        assert_eq!(codec.encode(large.as_bytes()).unwrap(), large.as_bytes());
+    }
+}
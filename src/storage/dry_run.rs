@@ -0,0 +1,351 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use gasket::runtime::{spawn_stage, WorkOutcome};
+use serde::Deserialize;
+
+use crate::{bootstrap, crosscut, cursor, model};
+
+type FunnelPort = gasket::messaging::FunnelPort<model::CRDTCommand>;
+
+const DEFAULT_REPORT_EVERY_BLOCKS: u64 = 1_000;
+
+/// Approximate size of a counter or score value
+const NUMBER_BYTES: usize = 8;
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Key prefixes to group the figures by, keys that don't match any of
+    /// them are grouped by the segment before their first dot
+    pub groups: Option<Vec<String>>,
+
+    pub report_every_blocks: Option<u64>,
+}
+
+#[derive(Default)]
+struct Usage {
+    keys: HashSet<u64>,
+    ops: u64,
+    bytes: usize,
+}
+
+/// Discards the commands, only measuring the storage that they would use
+///
+/// Keeps, per group of keys, the number of distinct keys written, the number
+/// of ops and an estimate of the bytes written (keys, members and values),
+/// which helps sizing a backend before syncing into it. A report is logged
+/// periodically and when the end of the range is reached. The cursor is never
+/// saved.
+pub struct Worker {
+    config: Config,
+    until: Option<u64>,
+    usage: BTreeMap<String, Usage>,
+    blocks: u64,
+    input: FunnelPort,
+    block_count: gasket::metrics::Counter,
+    last_slot: gasket::metrics::Gauge,
+}
+
+fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Worker {
+    fn group(&self, key: &str) -> String {
+        let configured = self
+            .config
+            .groups
+            .iter()
+            .flatten()
+            .filter(|x| key.starts_with(x.as_str()))
+            .max_by_key(|x| x.len());
+
+        match configured {
+            Some(group) => group.clone(),
+            None => match key.split_once('.') {
+                Some((group, _)) => group.to_string(),
+                None => "(no prefix)".to_string(),
+            },
+        }
+    }
+
+    fn record(&mut self, key: &str, bytes: Option<usize>) {
+        let group = self.group(key);
+        let usage = self.usage.entry(group).or_default();
+
+        usage.ops += 1;
+
+        // removals don't create keys
+        if let Some(bytes) = bytes {
+            usage.keys.insert(key_hash(key));
+            usage.bytes += key.len() + bytes;
+        }
+    }
+
+    fn report(&self) {
+        for (group, usage) in self.usage.iter() {
+            log::info!(
+                "dry run usage, group={} keys={} ops={} bytes={}",
+                group,
+                usage.keys.len(),
+                usage.ops,
+                usage.bytes
+            );
+        }
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_gauge("last_slot", &self.last_slot)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        use model::CRDTCommand::*;
+
+        match msg.payload {
+            BlockStarting(_) => (),
+            TwoPhaseSetAdd(key, member) | GrowOnlySetAdd(key, member) => {
+                self.record(&key, Some(member.len()))
+            }
+            TwoPhaseSetRemove(key, member) => {
+                self.record(&format!("{}.ts", key), Some(member.len()))
+            }
+            HyperLogLogAdd(key, member) => self.record(&key, Some(member.len())),
+            LastWriteWins(key, value, _) => self.record(&key, Some(value.len() + NUMBER_BYTES)),
+            AnyWriteWins(key, value) | ConditionalWrite(key, value, _) => {
+                self.record(&key, Some(value.len()))
+            }
+            PNCounter(key, _) => self.record(&key, Some(NUMBER_BYTES)),
+            SortedSetAdd(key, member, _) => self.record(&key, Some(member.len() + NUMBER_BYTES)),
+            SortedSetCopyTop(_, into, _) => self.record(&into, Some(0)),
+            CounterSnapshot(key, _, set, timestamp) => {
+                self.record(&key, Some(NUMBER_BYTES));
+
+                let member = format!("{}:", timestamp);
+                self.record(&set, Some(member.len() + 2 * NUMBER_BYTES));
+            }
+            SetRemove(key, _) | AnyWriteWinsRemove(key) | SortedSetRemoveRange(key, _, _) => {
+                self.record(&key, None)
+            }
+            BlockFinished(point) => {
+                let cursor = crosscut::PointArg::from(point);
+
+                self.block_count.inc(1);
+                self.blocks += 1;
+
+                let every = self
+                    .config
+                    .report_every_blocks
+                    .unwrap_or(DEFAULT_REPORT_EVERY_BLOCKS);
+
+                if self.blocks.is_multiple_of(every.max(1)) {
+                    self.report();
+                }
+
+                if let crosscut::PointArg::Specific(slot, _) = &cursor {
+                    self.last_slot.set(*slot as i64);
+
+                    if self.until.map(|x| *slot >= x).unwrap_or(false) {
+                        log::info!("reached end of requested range at {}", cursor.to_string());
+                        self.report();
+                        return Ok(WorkOutcome::Done);
+                    }
+                }
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+}
+
+impl super::Pluggable for Worker {
+    fn borrow_input_port(&mut self) -> &'_ mut FunnelPort {
+        &mut self.input
+    }
+
+    fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("dry-run", spawn_stage(self, Default::default()));
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        _chain: &crosscut::ChainWellKnownInfo,
+        intersect: &crosscut::IntersectConfig,
+        _cursor: cursor::Store,
+    ) -> super::Plugin {
+        let until = match intersect {
+            crosscut::IntersectConfig::Range(_, crosscut::PointArg::Specific(slot, _)) => {
+                Some(*slot)
+            }
+            _ => None,
+        };
+
+        let worker = Worker {
+            config: self,
+            until,
+            usage: BTreeMap::new(),
+            blocks: 0,
+            input: Default::default(),
+            block_count: Default::default(),
+            last_slot: Default::default(),
+        };
+
+        super::Plugin::DryRun(worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use gasket::{
+        messaging::{connect_ports, InputPort, OutputPort},
+        runtime::Worker as _,
+    };
+
+    use super::*;
+    use crate::reducers::{self, point_by_tx, total_transactions_count};
+    use crate::storage::memory;
+    use crate::testing::{address, block, DEFAULT_SLOT};
+
+    /// The commands that the reducers emit for a couple of blocks
+    fn emitted_commands() -> Vec<model::CRDTCommand> {
+        let plugins = vec![
+            (
+                "count",
+                total_transactions_count::Config { key_prefix: None }.plugin(),
+                None,
+            ),
+            (
+                "points",
+                point_by_tx::Config {
+                    key_prefix: Some("point_by_tx".into()),
+                }
+                .plugin(),
+                None,
+            ),
+        ];
+
+        let mut reducers = reducers::Worker::new(
+            plugins,
+            crosscut::Filters::default(),
+            None,
+            &crosscut::RuntimePolicy::default(),
+        );
+
+        let mut source = OutputPort::default();
+        let mut emitted = InputPort::default();
+        connect_ports(&mut source, reducers.borrow_input_port(), 100);
+        connect_ports(reducers.borrow_output_port(), &mut emitted, 100);
+
+        let blocks = [
+            block()
+                .tx(|t| t.output(&address(1), 5_000_000))
+                .tx(|t| t.output(&address(2), 1_000_000))
+                .build(),
+            block()
+                .slot(DEFAULT_SLOT + 20)
+                .number(2)
+                .tx(|t| t.output(&address(3), 2_000_000))
+                .build(),
+        ];
+
+        for block in blocks {
+            let ctx = model::BlockContext::default();
+            source
+                .send(model::EnrichedBlockPayload::roll_forward(
+                    Arc::new(block),
+                    ctx,
+                ))
+                .unwrap();
+
+            reducers.work().unwrap();
+        }
+
+        let mut commands = Vec::new();
+
+        while let Ok(msg) = emitted.try_recv() {
+            commands.push(msg.payload);
+        }
+
+        commands
+    }
+
+    #[test]
+    fn usage_matches_the_commands_of_the_reducers() {
+        // the counter key has no dot to group it by
+        let config = Config {
+            groups: Some(vec!["total_transactions_count".into()]),
+            report_every_blocks: None,
+        };
+
+        let mut worker = match config.plugin(
+            &crosscut::ChainWellKnownInfo::mainnet(),
+            &crosscut::IntersectConfig::Tip,
+            cursor::Store::Skip,
+        ) {
+            super::super::Plugin::DryRun(x) => x,
+            _ => unreachable!("dry run config builds a dry run plugin"),
+        };
+
+        let mut port = OutputPort::default();
+        connect_ports(
+            &mut port,
+            super::super::Pluggable::borrow_input_port(&mut worker),
+            100,
+        );
+
+        // the reducers emit the same commands every time, the storage gets a
+        // copy to tell the keys that it ends up with
+        let mut store = memory::Store::default();
+
+        for command in emitted_commands() {
+            store.apply(command).unwrap();
+        }
+
+        let commands = emitted_commands();
+
+        let bytes: usize = commands
+            .iter()
+            .map(|x| match x {
+                model::CRDTCommand::GrowOnlySetAdd(key, member) => key.len() + member.len(),
+                _ => 0,
+            })
+            .sum();
+
+        for command in commands {
+            port.send(gasket::messaging::Message::from(command))
+                .unwrap();
+            worker.work().unwrap();
+        }
+
+        assert_eq!(worker.blocks, 2);
+
+        let counter = &worker.usage["total_transactions_count"];
+        assert_eq!(counter.keys.len(), 1);
+        assert_eq!(counter.ops, 3);
+
+        let points = &worker.usage["point_by_tx"];
+        let written: Vec<_> = store
+            .keys()
+            .filter(|x| x.starts_with("point_by_tx."))
+            .collect();
+
+        assert_eq!(points.keys.len(), written.len());
+        assert_eq!(points.ops, 3);
+        assert_eq!(points.bytes, bytes);
+        assert_eq!(worker.usage.len(), 2);
+    }
+}
@@ -0,0 +1,868 @@
+//! Keeps the collections in a DynamoDB table
+//!
+//! Each key is an item of the table: the segment before its first dot is the
+//! partition key (`pk`) and the rest is the sort key (`sk`), so the keys of a
+//! collection can be queried as a single partition. Keys without a rest use
+//! `.` as sort key. The value lives in the `value` attribute: a string, a
+//! number for counters, a string set for sets and a map of member to score for
+//! sorted sets.
+//!
+//! The commands of a block are committed with `TransactWriteItems`, which
+//! takes at most 100 items and touches each item only once, so a block is
+//! split into several transactions when needed. Conditional writes and the
+//! commands of sorted sets need the current value of their item, they're
+//! applied on their own between those transactions. The cursor only moves
+//! once every write of the block went through.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use aws_sdk_dynamodb::{
+    error::DisplayErrorContext,
+    types::{AttributeValue, Delete, ReturnValue, TransactWriteItem, Update},
+    Client,
+};
+use gasket::{
+    error::AsWorkError,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use crate::{
+    bootstrap, crosscut,
+    cursor::{self, CursorStore},
+    model::{self, CRDTCommand},
+    Error,
+};
+
+type FunnelPort = gasket::messaging::FunnelPort<model::CRDTCommand>;
+
+/// Limit of `TransactWriteItems`
+const MAX_TRANSACTION_ITEMS: usize = 100;
+
+/// Sort key of the keys that have nothing after their prefix
+const NO_SORT_KEY: &str = ".";
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Table with a string partition key `pk` and a string sort key `sk`
+    pub table: String,
+
+    /// Defaults to the region of the AWS environment
+    pub region: Option<String>,
+
+    /// Endpoint to use instead of the AWS one, eg: DynamoDB Local at
+    /// `http://localhost:8000`
+    pub endpoint_url: Option<String>,
+
+    /// Persist the cursor at these checkpoints only. Defaults to every block.
+    pub cursor_checkpoint: Option<cursor::Checkpoint>,
+
+    /// The operation timeout bounds each request, retries included
+    #[serde(flatten)]
+    pub timeouts: super::timeouts::Timeouts,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Item {
+    pk: String,
+    sk: String,
+}
+
+impl From<&str> for Item {
+    fn from(key: &str) -> Self {
+        let (pk, sk) = match key.split_once('.') {
+            Some((prefix, rest)) if !rest.is_empty() => (prefix, rest),
+            Some((prefix, _)) => (prefix, NO_SORT_KEY),
+            None => (key, NO_SORT_KEY),
+        };
+
+        Item {
+            pk: pk.to_string(),
+            sk: sk.to_string(),
+        }
+    }
+}
+
+impl Item {
+    fn key(&self) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(self.pk.clone())),
+            ("sk".to_string(), AttributeValue::S(self.sk.clone())),
+        ])
+    }
+}
+
+/// A write that doesn't depend on the current value of its item
+#[derive(Debug, PartialEq)]
+enum Op {
+    Increment(Item, i64),
+    Write(Item, String),
+    AddMember(Item, String),
+    RemoveMember(Item, String),
+    Remove(Item),
+}
+
+impl Op {
+    fn item(&self) -> &Item {
+        match self {
+            Op::Increment(x, _)
+            | Op::Write(x, _)
+            | Op::AddMember(x, _)
+            | Op::RemoveMember(x, _)
+            | Op::Remove(x) => x,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Step {
+    /// Committed together, each item at most once
+    Transaction(Vec<Op>),
+
+    /// Reads the current value of its items, applied on its own
+    Single(CRDTCommand),
+}
+
+fn parse_number(key: &str, value: &str) -> Result<i64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::storage(format!("value of {} is not an integer: {}", key, value)))
+}
+
+/// The op of the commands that can go into a transaction, none for the others
+fn to_op(command: &CRDTCommand) -> Result<Option<Op>, Error> {
+    use CRDTCommand::*;
+
+    let op = match command {
+        GrowOnlySetAdd(key, member) | TwoPhaseSetAdd(key, member) | HyperLogLogAdd(key, member) => {
+            Op::AddMember(key.as_str().into(), member.clone())
+        }
+        TwoPhaseSetRemove(key, member) => {
+            Op::AddMember(format!("{}.ts", key).as_str().into(), member.clone())
+        }
+        SetRemove(key, member) => Op::RemoveMember(key.as_str().into(), member.clone()),
+        AnyWriteWins(key, value) => Op::Write(key.as_str().into(), value.clone()),
+        AnyWriteWinsRemove(key) => Op::Remove(key.as_str().into()),
+        PNCounter(key, value) => Op::Increment(key.as_str().into(), parse_number(key, value)?),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(op))
+}
+
+/// Splits the commands of a block into the writes sent to the table, in order
+fn plan(commands: Vec<CRDTCommand>) -> Result<Vec<Step>, Error> {
+    let mut steps = Vec::new();
+    let mut current = Vec::new();
+    let mut items = HashSet::new();
+
+    fn flush(current: &mut Vec<Op>, items: &mut HashSet<Item>, steps: &mut Vec<Step>) {
+        if !current.is_empty() {
+            steps.push(Step::Transaction(std::mem::take(current)));
+            items.clear();
+        }
+    }
+
+    for command in commands {
+        if let CRDTCommand::BlockStarting(_) | CRDTCommand::BlockFinished(_) = command {
+            continue;
+        }
+
+        match to_op(&command)? {
+            Some(op) => {
+                if current.len() == MAX_TRANSACTION_ITEMS || items.contains(op.item()) {
+                    flush(&mut current, &mut items, &mut steps);
+                }
+
+                items.insert(op.item().clone());
+                current.push(op);
+            }
+            None => {
+                flush(&mut current, &mut items, &mut steps);
+                steps.push(Step::Single(command));
+            }
+        }
+    }
+
+    flush(&mut current, &mut items, &mut steps);
+
+    Ok(steps)
+}
+
+fn update(table: &str, item: &Item, expression: &str, value: AttributeValue) -> Update {
+    Update::builder()
+        .table_name(table)
+        .set_key(Some(item.key()))
+        .update_expression(expression)
+        .expression_attribute_names("#v", "value")
+        .expression_attribute_values(":x", value)
+        .build()
+        .expect("update has every required field")
+}
+
+fn to_transact_item(table: &str, op: Op) -> TransactWriteItem {
+    let builder = TransactWriteItem::builder();
+
+    let builder = match op {
+        Op::Increment(item, delta) => builder.update(update(
+            table,
+            &item,
+            "ADD #v :x",
+            AttributeValue::N(delta.to_string()),
+        )),
+        Op::Write(item, value) => builder.update(update(
+            table,
+            &item,
+            "SET #v = :x",
+            AttributeValue::S(value),
+        )),
+        Op::AddMember(item, member) => builder.update(update(
+            table,
+            &item,
+            "ADD #v :x",
+            AttributeValue::Ss(vec![member]),
+        )),
+        Op::RemoveMember(item, member) => builder.update(update(
+            table,
+            &item,
+            "DELETE #v :x",
+            AttributeValue::Ss(vec![member]),
+        )),
+        Op::Remove(item) => builder.delete(
+            Delete::builder()
+                .table_name(table)
+                .set_key(Some(item.key()))
+                .build()
+                .expect("delete has every required field"),
+        ),
+    };
+
+    builder.build()
+}
+
+fn to_scores(key: &str, value: Option<&AttributeValue>) -> Result<BTreeMap<String, i64>, Error> {
+    let members = match value {
+        Some(AttributeValue::M(x)) => x,
+        None => return Ok(BTreeMap::new()),
+        Some(_) => {
+            return Err(Error::storage(format!(
+                "key {} holds a value of another type",
+                key
+            )))
+        }
+    };
+
+    members
+        .iter()
+        .map(|(member, score)| match score {
+            AttributeValue::N(x) => Ok((member.clone(), parse_number(key, x)?)),
+            _ => Err(Error::storage(format!("score of {} is not a number", key))),
+        })
+        .collect()
+}
+
+/// Applies the steps of a block to a table
+pub struct Writer {
+    client: Client,
+    table: String,
+}
+
+impl Writer {
+    pub fn new(client: Client, table: String) -> Self {
+        Writer { client, table }
+    }
+
+    async fn transact(&self, ops: Vec<Op>) -> Result<(), Error> {
+        let items = ops
+            .into_iter()
+            .map(|x| to_transact_item(&self.table, x))
+            .collect();
+
+        self.client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await
+            .map_err(|err| Error::storage(DisplayErrorContext(err)))?;
+
+        Ok(())
+    }
+
+    /// Skips the write when its condition doesn't hold
+    async fn write_if(
+        &self,
+        key: &str,
+        value: String,
+        condition: model::WriteCondition,
+    ) -> Result<(), Error> {
+        let (condition, value) = match condition {
+            model::WriteCondition::NotExists => {
+                ("attribute_not_exists(#v)", AttributeValue::S(value))
+            }
+            model::WriteCondition::GreaterThan => (
+                "attribute_not_exists(#v) OR #v < :x",
+                AttributeValue::N(parse_number(key, &value)?.to_string()),
+            ),
+            model::WriteCondition::LessThan => (
+                "attribute_not_exists(#v) OR #v > :x",
+                AttributeValue::N(parse_number(key, &value)?.to_string()),
+            ),
+        };
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .set_key(Some(Item::from(key).key()))
+            .update_expression("SET #v = :x")
+            .condition_expression(condition)
+            .expression_attribute_names("#v", "value")
+            .expression_attribute_values(":x", value)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => match err.into_service_error() {
+                x if x.is_conditional_check_failed_exception() => Ok(()),
+                x => Err(Error::storage(DisplayErrorContext(x))),
+            },
+        }
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64, Error> {
+        let output = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .set_key(Some(Item::from(key).key()))
+            .update_expression("ADD #v :x")
+            .expression_attribute_names("#v", "value")
+            .expression_attribute_values(":x", AttributeValue::N(delta.to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await
+            .map_err(|err| Error::storage(DisplayErrorContext(err)))?;
+
+        match output.attributes().and_then(|x| x.get("value")) {
+            Some(AttributeValue::N(x)) => parse_number(key, x),
+            _ => Err(Error::storage(format!("counter {} has no value", key))),
+        }
+    }
+
+    async fn read_scores(&self, key: &str) -> Result<BTreeMap<String, i64>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .set_key(Some(Item::from(key).key()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|err| Error::storage(DisplayErrorContext(err)))?;
+
+        to_scores(key, output.item().and_then(|x| x.get("value")))
+    }
+
+    /// Empty sorted sets are removed
+    async fn write_scores(&self, key: &str, scores: BTreeMap<String, i64>) -> Result<(), Error> {
+        let item = Item::from(key);
+
+        match scores.is_empty() {
+            true => self
+                .client
+                .delete_item()
+                .table_name(&self.table)
+                .set_key(Some(item.key()))
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|err| Error::storage(DisplayErrorContext(err))),
+            false => {
+                let members = scores
+                    .into_iter()
+                    .map(|(member, score)| (member, AttributeValue::N(score.to_string())))
+                    .collect();
+
+                let mut values = item.key();
+                values.insert("value".to_string(), AttributeValue::M(members));
+
+                self.client
+                    .put_item()
+                    .table_name(&self.table)
+                    .set_item(Some(values))
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| Error::storage(DisplayErrorContext(err)))
+            }
+        }
+    }
+
+    async fn apply_single(&self, command: CRDTCommand) -> Result<(), Error> {
+        use CRDTCommand::*;
+
+        match command {
+            ConditionalWrite(key, value, condition) => self.write_if(&key, value, condition).await,
+            LastWriteWins(key, value, timestamp) => {
+                let mut scores = self.read_scores(&key).await?;
+                scores.insert(value, timestamp as i64);
+                self.write_scores(&key, scores).await
+            }
+            SortedSetAdd(key, member, delta) => {
+                let mut scores = self.read_scores(&key).await?;
+                let score = scores.entry(member.clone()).or_insert(0);
+                *score += delta;
+
+                if *score <= 0 {
+                    scores.remove(&member);
+                }
+
+                self.write_scores(&key, scores).await
+            }
+            SortedSetCopyTop(from, into, size) => {
+                let mut top: Vec<_> = self.read_scores(&from).await?.into_iter().collect();
+
+                // highest scores first, ties in reverse member order like ZRANGE REV
+                top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+                top.truncate(size);
+
+                self.write_scores(&into, top.into_iter().collect()).await
+            }
+            SortedSetRemoveRange(key, min, max) => {
+                let range = model::timestamp_score(min)..=model::timestamp_score(max);
+
+                let mut scores = self.read_scores(&key).await?;
+                scores.retain(|_, score| !range.contains(score));
+                self.write_scores(&key, scores).await
+            }
+            CounterSnapshot(key, delta, set, timestamp) => {
+                let value = self.increment(&key, delta).await?;
+
+                let mut snapshots = self.read_scores(&set).await?;
+                snapshots.retain(|_, score| *score != timestamp as i64);
+                snapshots.insert(format!("{}:{}", timestamp, value), timestamp as i64);
+                self.write_scores(&set, snapshots).await
+            }
+            x => Err(Error::storage(format!("unexpected command {:?}", x))),
+        }
+    }
+
+    /// Returns the number of transactions that it took
+    pub async fn apply(&self, commands: Vec<CRDTCommand>) -> Result<u64, Error> {
+        let mut transactions = 0;
+
+        for step in plan(commands)? {
+            match step {
+                Step::Transaction(ops) => {
+                    self.transact(ops).await?;
+                    transactions += 1;
+                }
+                Step::Single(command) => self.apply_single(command).await?,
+            }
+        }
+
+        Ok(transactions)
+    }
+}
+
+pub struct Worker {
+    config: Config,
+    runtime: Option<tokio::runtime::Runtime>,
+    writer: Option<Writer>,
+    cursor: cursor::Store,
+    checkpointer: Option<cursor::Checkpointer>,
+    until: Option<u64>,
+    pending: Vec<CRDTCommand>,
+    input: FunnelPort,
+    block_count: gasket::metrics::Counter,
+    transaction_count: gasket::metrics::Counter,
+    last_slot: gasket::metrics::Gauge,
+}
+
+impl Worker {
+    fn save_cursor(&mut self, point: crosscut::PointArg) -> Result<(), gasket::error::Error> {
+        if let Some(checkpointer) = &mut self.checkpointer {
+            if !checkpointer.is_due(&point) {
+                return Ok(());
+            }
+        }
+
+        self.cursor.save(&point).or_work_err()?;
+        log::info!("new cursor saved {}", point.to_string());
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), gasket::error::Error> {
+        // merged increments and writes are fewer items written twice, which
+        // would need another transaction
+        let commands = std::mem::take(&mut self.pending);
+        let (commands, _) =
+            super::coalesce::coalesce(commands.into_iter().map(|x| ((), x, 0)).collect());
+
+        let commands = commands.into_iter().map(|(_, x, _)| x).collect();
+
+        let runtime = self.runtime.as_ref().unwrap();
+        let writer = self.writer.as_ref().unwrap();
+
+        let transactions = runtime.block_on(writer.apply(commands)).or_work_err()?;
+        self.transaction_count.inc(transactions);
+
+        Ok(())
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_counter("transaction_count", &self.transaction_count)
+            .with_gauge("last_slot", &self.last_slot)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        match msg.payload {
+            model::CRDTCommand::BlockStarting(_) => self.pending.clear(),
+            model::CRDTCommand::BlockFinished(point) => {
+                self.commit()?;
+                self.save_cursor(crosscut::PointArg::from(point.clone()))?;
+
+                self.block_count.inc(1);
+
+                if let Point::Specific(slot, _) = point {
+                    self.last_slot.set(slot as i64);
+
+                    if self.until.map(|x| slot >= x).unwrap_or(false) {
+                        log::info!("reached end of requested range at slot {}", slot);
+                        return Ok(WorkOutcome::Done);
+                    }
+                }
+            }
+            command => self.pending.push(command),
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .or_work_err()?;
+
+        let client = runtime.block_on(self.config.client());
+
+        self.writer = Some(Writer::new(client, self.config.table.clone()));
+        self.runtime = Some(runtime);
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        Ok(())
+    }
+}
+
+impl super::Pluggable for Worker {
+    fn borrow_input_port(&mut self) -> &'_ mut FunnelPort {
+        &mut self.input
+    }
+
+    fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("dynamodb", spawn_stage(self, Default::default()));
+    }
+}
+
+impl Config {
+    fn timeout_config(&self) -> aws_config::timeout::TimeoutConfig {
+        aws_config::timeout::TimeoutConfig::builder()
+            .connect_timeout(self.timeouts.connect())
+            .operation_timeout(self.timeouts.operation())
+            .build()
+    }
+
+    /// Takes the credentials from the AWS environment
+    pub async fn client(&self) -> Client {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .timeout_config(self.timeout_config());
+
+        if let Some(region) = &self.region {
+            loader = loader.region(aws_config::Region::new(region.clone()));
+        }
+
+        if let Some(url) = &self.endpoint_url {
+            loader = loader.endpoint_url(url);
+        }
+
+        Client::new(&loader.load().await)
+    }
+
+    pub fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        intersect: &crosscut::IntersectConfig,
+        cursor: cursor::Store,
+    ) -> super::Plugin {
+        let until = match intersect {
+            crosscut::IntersectConfig::Range(_, crosscut::PointArg::Specific(slot, _)) => {
+                Some(*slot)
+            }
+            _ => None,
+        };
+
+        let checkpointer = self
+            .cursor_checkpoint
+            .map(|x| cursor::Checkpointer::new(x, chain));
+
+        let worker = Worker {
+            config: self,
+            runtime: None,
+            writer: None,
+            cursor,
+            checkpointer,
+            until,
+            pending: Vec::new(),
+            input: Default::default(),
+            block_count: Default::default(),
+            transaction_count: Default::default(),
+            last_slot: Default::default(),
+        };
+
+        super::Plugin::DynamoDB(worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CRDTCommand::*;
+
+    fn item(pk: &str, sk: &str) -> Item {
+        Item {
+            pk: pk.into(),
+            sk: sk.into(),
+        }
+    }
+
+    #[test]
+    fn keys_split_into_prefix_and_rest() {
+        assert_eq!(Item::from("balance.addr1.x"), item("balance", "addr1.x"));
+        assert_eq!(
+            Item::from("total_transactions_count"),
+            item("total_transactions_count", ".")
+        );
+        assert_eq!(Item::from("balance."), item("balance", "."));
+    }
+
+    /// Ops of each transaction, none for the commands applied on their own
+    fn transactions(steps: Vec<Step>) -> Vec<Option<Vec<Op>>> {
+        steps
+            .into_iter()
+            .map(|x| match x {
+                Step::Transaction(ops) => Some(ops),
+                Step::Single(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_split_into_transactions() {
+        let steps = plan(vec![
+            PNCounter("count".into(), "1".into()),
+            GrowOnlySetAdd("set.a".into(), "x".into()),
+            GrowOnlySetAdd("set.a".into(), "y".into()),
+            ConditionalWrite("max".into(), "5".into(), model::WriteCondition::GreaterThan),
+            AnyWriteWins("value.b".into(), "z".into()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            transactions(steps),
+            vec![
+                Some(vec![
+                    Op::Increment(item("count", "."), 1),
+                    Op::AddMember(item("set", "a"), "x".into()),
+                ]),
+                // the same item can't be written twice in a transaction
+                Some(vec![Op::AddMember(item("set", "a"), "y".into())]),
+                None,
+                Some(vec![Op::Write(item("value", "b"), "z".into())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn transactions_take_at_most_100_items() {
+        let commands = (0..250)
+            .map(|x| PNCounter(format!("count.{}", x), "1".into()))
+            .collect();
+
+        let sizes: Vec<_> = transactions(plan(commands).unwrap())
+            .into_iter()
+            .map(|x| x.map(|ops| ops.len()))
+            .collect();
+
+        assert_eq!(sizes, vec![Some(100), Some(100), Some(50)]);
+    }
+
+    /// Runs against DynamoDB Local, eg: `docker run -p 8000:8000
+    /// amazon/dynamodb-local`, at `DYNAMODB_ENDPOINT` (defaults to
+    /// `http://localhost:8000`)
+    #[cfg(feature = "dynamodb-local")]
+    #[test]
+    fn commands_reach_dynamodb_local() {
+        use aws_sdk_dynamodb::types::{
+            AttributeDefinition, BillingMode, KeySchemaElement, KeyType, ScalarAttributeType,
+        };
+
+        let endpoint = std::env::var("DYNAMODB_ENDPOINT")
+            .unwrap_or_else(|_| "http://localhost:8000".to_string());
+
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .endpoint_url(endpoint)
+            .credentials_provider(aws_sdk_dynamodb::config::Credentials::new(
+                "local", "local", None, None, "test",
+            ))
+            .build();
+
+        let client = Client::from_conf(config);
+        let table = format!("scrolls_test_{}", std::process::id());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            let attribute = |name: &str| {
+                AttributeDefinition::builder()
+                    .attribute_name(name)
+                    .attribute_type(ScalarAttributeType::S)
+                    .build()
+                    .unwrap()
+            };
+
+            let key = |name: &str, kind| {
+                KeySchemaElement::builder()
+                    .attribute_name(name)
+                    .key_type(kind)
+                    .build()
+                    .unwrap()
+            };
+
+            client
+                .create_table()
+                .table_name(&table)
+                .attribute_definitions(attribute("pk"))
+                .attribute_definitions(attribute("sk"))
+                .key_schema(key("pk", KeyType::Hash))
+                .key_schema(key("sk", KeyType::Range))
+                .billing_mode(BillingMode::PayPerRequest)
+                .send()
+                .await
+                .unwrap();
+
+            let writer = Writer::new(client.clone(), table.clone());
+
+            let mut commands: Vec<_> = (0..150)
+                .map(|x| GrowOnlySetAdd(format!("set.{}", x), "a".into()))
+                .collect();
+
+            commands.extend([
+                PNCounter("count".into(), "2".into()),
+                PNCounter("count".into(), "3".into()),
+                ConditionalWrite("max".into(), "5".into(), model::WriteCondition::GreaterThan),
+                ConditionalWrite("max".into(), "3".into(), model::WriteCondition::GreaterThan),
+                SortedSetAdd("rank".into(), "a".into(), 5),
+                SortedSetAdd("rank".into(), "b".into(), 2),
+                SortedSetAdd("rank".into(), "b".into(), -2),
+            ]);
+
+            writer.apply(commands).await.unwrap();
+
+            let value = |key: &'static str| {
+                let client = client.clone();
+                let table = table.clone();
+
+                async move {
+                    client
+                        .get_item()
+                        .table_name(table)
+                        .set_key(Some(Item::from(key).key()))
+                        .send()
+                        .await
+                        .unwrap()
+                        .item()
+                        .and_then(|x| x.get("value"))
+                        .cloned()
+                }
+            };
+
+            assert_eq!(value("count").await, Some(AttributeValue::N("5".into())));
+            assert_eq!(value("max").await, Some(AttributeValue::N("5".into())));
+            assert_eq!(
+                value("set.149").await,
+                Some(AttributeValue::Ss(vec!["a".into()]))
+            );
+
+            let rank = value("rank").await;
+            assert_eq!(
+                to_scores("rank", rank.as_ref()).unwrap(),
+                BTreeMap::from([("a".into(), 5)])
+            );
+
+            client
+                .delete_table()
+                .table_name(&table)
+                .send()
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn hung_endpoint_fails_with_a_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        // takes the connections and never answers
+        std::thread::spawn(move || {
+            let mut streams = Vec::new();
+
+            for stream in listener.incoming().flatten() {
+                streams.push(stream);
+            }
+        });
+
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "table": "scrolls",
+            "endpoint_url": endpoint,
+            "operation_timeout_ms": 200,
+        }))
+        .unwrap();
+
+        let sdk = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .endpoint_url(config.endpoint_url.clone().unwrap())
+            .credentials_provider(aws_sdk_dynamodb::config::Credentials::new(
+                "local", "local", None, None, "test",
+            ))
+            .timeout_config(config.timeout_config())
+            .build();
+
+        let writer = Writer::new(Client::from_conf(sdk), config.table.clone());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let started = std::time::Instant::now();
+        let result = runtime.block_on(writer.apply(vec![PNCounter("a.b".into(), "1".into())]));
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,84 @@
+//! Waiting out a storage that refuses writes, eg: Redis out of memory with a
+//! `noeviction` policy, or a read-only replica after a failover
+//!
+//! The commit is retried until the storage takes it. Meanwhile the storage
+//! stage doesn't read new commands, so the pipeline stalls behind it and the
+//! cursor stays at the last committed block.
+
+use std::{fmt::Display, time::Duration};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Time between two attempts of a refused commit, defaults to 5 seconds
+    pub retry_interval_ms: Option<u64>,
+}
+
+/// Retries the operations refused by a full storage, reporting the wait in
+/// its metrics
+#[derive(Clone)]
+pub struct Retry {
+    interval: Duration,
+    waiting: gasket::metrics::Gauge,
+    retries: gasket::metrics::Counter,
+}
+
+impl Retry {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            interval: Duration::from_millis(config.retry_interval_ms.unwrap_or(5_000)),
+            waiting: Default::default(),
+            retries: Default::default(),
+        }
+    }
+
+    /// 1 while a commit waits for the storage to take writes again
+    pub fn waiting_gauge(&self) -> &gasket::metrics::Gauge {
+        &self.waiting
+    }
+
+    pub fn retries_counter(&self) -> &gasket::metrics::Counter {
+        &self.retries
+    }
+
+    /// Runs the operation until it succeeds or fails with an error other than
+    /// the ones of a full storage. The operation must have no effect when
+    /// refused, so that it's applied once.
+    pub fn run<T, E, F, P>(&self, is_full: P, mut op: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Result<T, E>,
+        P: Fn(&E) -> bool,
+    {
+        let mut refused = false;
+
+        loop {
+            match op() {
+                Err(err) if is_full(&err) => {
+                    if !refused {
+                        log::error!("storage refuses writes, pausing commits: {}", err);
+                        refused = true;
+                    }
+
+                    self.waiting.set(1);
+                    self.retries.inc(1);
+
+                    log::warn!("storage still full, retrying commit in {:?}", self.interval);
+                    std::thread::sleep(self.interval);
+                }
+                result => {
+                    if refused {
+                        self.waiting.set(0);
+
+                        if result.is_ok() {
+                            log::warn!("storage takes writes again, resuming commits");
+                        }
+                    }
+
+                    return result;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use ::kafka::consumer::{Consumer, FetchOffset};
+use ::kafka::producer::{Producer, Record, RequiredAcks};
+use gasket::{
+    error::AsWorkError,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    bootstrap, crosscut,
+    cursor::{self, CursorStore},
+    model, Error,
+};
+
+type FunnelPort = gasket::messaging::FunnelPort<model::CRDTCommand>;
+
+/// Delivery guarantees of the messages sent to the broker
+///
+/// With `at_least_once`, the cursor only moves after the broker acknowledged
+/// every message of the block; a failure stops the pipeline and the block is
+/// sent again after a restart, so consumers may see duplicates. With
+/// `at_most_once`, the cursor moves before the messages of the block are sent
+/// and delivery errors are only logged; after a failure or a restart, the
+/// messages of that block may be lost but are never replayed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    #[default]
+    AtLeastOnce,
+    AtMostOnce,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub delivery: Option<Delivery>,
+    pub ack_timeout_ms: Option<u64>,
+
+    /// Persist the cursor at these checkpoints only. Defaults to every block.
+    pub cursor_checkpoint: Option<cursor::Checkpoint>,
+
+    /// The kafka client doesn't take a connect timeout, each broker is probed
+    /// with it instead before connecting. The operation timeout becomes the
+    /// idle timeout of the connections, reads and writes on them are bounded
+    /// by the client at 2 minutes.
+    #[serde(flatten)]
+    pub timeouts: super::timeouts::Timeouts,
+}
+
+/// Fails unless every broker takes a connection within the timeout
+fn probe_brokers(brokers: &[String], timeout: Duration) -> Result<(), Error> {
+    for broker in brokers {
+        let addresses = broker
+            .to_socket_addrs()
+            .map_err(|x| Error::storage(format!("can't resolve broker {}: {}", broker, x)))?;
+
+        let reachable = addresses
+            .into_iter()
+            .any(|x| TcpStream::connect_timeout(&x, timeout).is_ok());
+
+        if !reachable {
+            return Err(Error::storage(format!(
+                "broker {} unreachable within {:?}",
+                broker, timeout
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Publishes each CRDT command as a JSON message keyed by the affected key, in
+/// the format of `storage::ops`
+pub struct Worker {
+    config: Config,
+    producer: Option<Producer>,
+    cursor: cursor::Store,
+    checkpointer: Option<cursor::Checkpointer>,
+    current: Option<crosscut::PointArg>,
+
+    /// Block with a message that the broker didn't acknowledge, the cursor
+    /// can't move past it anymore under at-least-once
+    undelivered: Option<crosscut::PointArg>,
+
+    input: FunnelPort,
+    sent_count: gasket::metrics::Counter,
+    failed_count: gasket::metrics::Counter,
+    block_count: gasket::metrics::Counter,
+    last_slot: gasket::metrics::Gauge,
+}
+
+impl Worker {
+    fn delivery(&self) -> Delivery {
+        self.config.delivery.unwrap_or_default()
+    }
+
+    fn save_cursor(&mut self, point: crosscut::PointArg) -> Result<(), gasket::error::Error> {
+        if let Some(checkpointer) = &mut self.checkpointer {
+            if !checkpointer.is_due(&point) {
+                return Ok(());
+            }
+        }
+
+        self.cursor.save(&point).or_work_err()?;
+        log::info!("new cursor saved {}", point.to_string());
+
+        Ok(())
+    }
+
+    fn publish(
+        &mut self,
+        key: &str,
+        mut payload: serde_json::Value,
+    ) -> Result<(), gasket::error::Error> {
+        // the key is repeated in the payload so that a dump of the topic can be
+        // replayed on its own
+        payload["key"] = json!(key);
+
+        if let Some(point) = &self.current {
+            payload["point"] = json!(point.to_string());
+        }
+
+        let value = payload.to_string();
+        let record = Record::from_key_value(&self.config.topic, key, value.as_bytes());
+
+        let result = self.producer.as_mut().unwrap().send(&record);
+
+        match (result, self.delivery()) {
+            (Ok(_), _) => {
+                self.sent_count.inc(1);
+                Ok(())
+            }
+            (Err(err), Delivery::AtMostOnce) => {
+                log::error!("message to kafka dropped: {}", err);
+                self.failed_count.inc(1);
+                Ok(())
+            }
+            (Err(err), Delivery::AtLeastOnce) => {
+                self.failed_count.inc(1);
+                self.undelivered = self.undelivered.take().or_else(|| self.current.clone());
+                Err(err).or_work_err()
+            }
+        }
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("sent_count", &self.sent_count)
+            .with_counter("failed_count", &self.failed_count)
+            .with_counter("block_count", &self.block_count)
+            .with_gauge("last_slot", &self.last_slot)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        match msg.payload {
+            model::CRDTCommand::BlockStarting(point) => {
+                let point = crosscut::PointArg::from(point);
+
+                if self.delivery() == Delivery::AtMostOnce {
+                    self.save_cursor(point.clone())?;
+                }
+
+                self.current = Some(point);
+            }
+            model::CRDTCommand::BlockFinished(point) => {
+                // messages are sent synchronously, reaching this point means that the broker
+                // acknowledged all of the messages of the block
+                if self.delivery() == Delivery::AtLeastOnce {
+                    if let Some(undelivered) = &self.undelivered {
+                        return Err(Error::storage(format!(
+                            "messages of block {} weren't delivered, cursor kept before it",
+                            undelivered
+                        )))
+                        .or_work_err();
+                    }
+
+                    self.save_cursor(crosscut::PointArg::from(point.clone()))?;
+                }
+
+                self.block_count.inc(1);
+
+                if let Point::Specific(slot, _) = point {
+                    self.last_slot.set(slot as i64);
+                }
+
+                self.current = None;
+            }
+            command => {
+                if let Some((key, payload)) = super::ops::to_json(&command) {
+                    let key = key.to_string();
+                    self.publish(&key, payload)?;
+                }
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let acks = match self.delivery() {
+            Delivery::AtLeastOnce => RequiredAcks::All,
+            Delivery::AtMostOnce => RequiredAcks::One,
+        };
+
+        let timeout = Duration::from_millis(self.config.ack_timeout_ms.unwrap_or(1000));
+
+        probe_brokers(&self.config.brokers, self.config.timeouts.connect()).or_work_err()?;
+
+        let producer = Producer::from_hosts(self.config.brokers.clone())
+            .with_connection_idle_timeout(self.config.timeouts.operation())
+            .with_ack_timeout(timeout)
+            .with_required_acks(acks)
+            .create()
+            .or_work_err()?;
+
+        self.producer = Some(producer);
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        Ok(())
+    }
+}
+
+impl super::Pluggable for Worker {
+    fn borrow_input_port(&mut self) -> &'_ mut FunnelPort {
+        &mut self.input
+    }
+
+    fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("kafka", spawn_stage(self, Default::default()));
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        chain: &crosscut::ChainWellKnownInfo,
+        _intersect: &crosscut::IntersectConfig,
+        cursor: cursor::Store,
+    ) -> super::Plugin {
+        let checkpointer = self
+            .cursor_checkpoint
+            .map(|x| cursor::Checkpointer::new(x, chain));
+
+        let worker = Worker {
+            config: self,
+            producer: None,
+            cursor,
+            checkpointer,
+            current: None,
+            undelivered: None,
+            input: Default::default(),
+            sent_count: Default::default(),
+            failed_count: Default::default(),
+            block_count: Default::default(),
+            last_slot: Default::default(),
+        };
+
+        super::Plugin::Kafka(worker)
+    }
+}
+
+impl Config {
+    pub fn watcher(&self) -> Result<Watcher, Error> {
+        probe_brokers(&self.brokers, self.timeouts.connect())?;
+
+        let consumer = Consumer::from_hosts(self.brokers.clone())
+            .with_connection_idle_timeout(self.timeouts.operation())
+            .with_topic(self.topic.clone())
+            .with_fallback_offset(FetchOffset::Latest)
+            .create()
+            .map_err(Error::storage)?;
+
+        Ok(Watcher { consumer })
+    }
+}
+
+/// Follows the ops published to the topic, starting from the latest ones
+pub struct Watcher {
+    consumer: Consumer,
+}
+
+impl super::watch::Watchable for Watcher {
+    fn watch(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(super::watch::Change) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        loop {
+            let sets = self.consumer.poll().map_err(Error::storage)?;
+
+            for set in sets.iter() {
+                for message in set.messages() {
+                    let key = String::from_utf8_lossy(message.key);
+
+                    if !key.starts_with(prefix) {
+                        continue;
+                    }
+
+                    visit(super::watch::Change {
+                        key: key.to_string(),
+                        event: String::from_utf8_lossy(message.value).to_string(),
+                    })?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::kafka::client::KafkaClient;
+
+    fn worker(delivery: Delivery, cursor_path: &std::path::Path) -> Worker {
+        let config = Config {
+            brokers: vec!["127.0.0.1:9092".to_string()],
+            topic: "scrolls".to_string(),
+            delivery: Some(delivery),
+            ack_timeout_ms: None,
+            cursor_checkpoint: None,
+            timeouts: Default::default(),
+        };
+
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+
+        let cursor = cursor::file::Config {
+            path: Some(cursor_path.to_string_lossy().to_string()),
+            integrity: None,
+        }
+        .store(&chain);
+
+        let mut worker = match config.plugin(&chain, &crosscut::IntersectConfig::Origin, cursor) {
+            super::super::Plugin::Kafka(x) => x,
+            _ => unreachable!("kafka config builds a kafka plugin"),
+        };
+
+        // a client without metadata doesn't know any partition of the topic, so
+        // the broker never acknowledges a message
+        let client = KafkaClient::new(worker.config.brokers.clone());
+        worker.producer = Some(Producer::from_client(client).create().unwrap());
+
+        worker
+    }
+
+    /// Feeds a block with a single command, returns the result of each step
+    fn feed(worker: &mut Worker) -> Vec<bool> {
+        let mut output = gasket::messaging::OutputPort::default();
+        gasket::messaging::connect_ports(&mut output, &mut worker.input, 10);
+
+        let point = Point::Specific(10, vec![0xab; 32]);
+
+        let commands = vec![
+            model::CRDTCommand::BlockStarting(point.clone()),
+            model::CRDTCommand::PNCounter("a".to_string(), "1".to_string()),
+            model::CRDTCommand::BlockFinished(point),
+        ];
+
+        commands
+            .into_iter()
+            .map(|x| {
+                output.send(x.into()).unwrap();
+                gasket::runtime::Worker::work(worker).is_ok()
+            })
+            .collect()
+    }
+
+    fn cursor_path(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("scrolls-kafka-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn failed_delivery_holds_the_cursor_under_at_least_once() {
+        let path = cursor_path("at-least-once");
+        let mut worker = worker(Delivery::AtLeastOnce, &path);
+
+        assert_eq!(feed(&mut worker), [true, false, false]);
+        assert_eq!(worker.failed_count.get(), 1);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn failed_delivery_is_dropped_under_at_most_once() {
+        let path = cursor_path("at-most-once");
+        let mut worker = worker(Delivery::AtMostOnce, &path);
+
+        assert_eq!(feed(&mut worker), [true, true, true]);
+        assert_eq!(worker.failed_count.get(), 1);
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("10,"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn brokers_are_probed_before_connecting() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let live = listener.local_addr().unwrap().to_string();
+
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let timeout = Duration::from_millis(200);
+
+        assert!(probe_brokers(std::slice::from_ref(&live), timeout).is_ok());
+        assert!(probe_brokers(&[live, closed], timeout).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Guard against values too large for the storage (eg: a huge datum or
+//! metadata blob), applied before the commands reach the backend
+
+use serde::Deserialize;
+
+use crate::{model::CRDTCommand, Error};
+
+/// What to do with a command whose value exceeds the configured limit
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedAction {
+    /// Cut the value down to the limit
+    Truncate,
+    /// Drop the command
+    #[default]
+    Skip,
+    /// Stop the pipeline
+    Fail,
+}
+
+/// The key and the free-form value (or member) of the command, if any
+fn key_and_value(command: &mut CRDTCommand) -> Option<(&str, &mut String)> {
+    use CRDTCommand::*;
+
+    match command {
+        TwoPhaseSetAdd(key, value)
+        | TwoPhaseSetRemove(key, value)
+        | GrowOnlySetAdd(key, value)
+        | SetRemove(key, value)
+        | HyperLogLogAdd(key, value)
+        | LastWriteWins(key, value, _)
+        | AnyWriteWins(key, value)
+        | ConditionalWrite(key, value, _)
+        | SortedSetAdd(key, value, _) => Some((key.as_str(), value)),
+        _ => None,
+    }
+}
+
+/// Applies the limit to the value of the command. Returns `None` if the
+/// command has to be skipped, and whether the value was oversized.
+pub fn guard(
+    mut command: CRDTCommand,
+    max_bytes: usize,
+    action: OversizedAction,
+) -> Result<(Option<CRDTCommand>, bool), Error> {
+    let oversized = key_and_value(&mut command)
+        .map(|(_, value)| value.len() > max_bytes)
+        .unwrap_or(false);
+
+    if !oversized {
+        return Ok((Some(command), false));
+    }
+
+    let (key, value) = key_and_value(&mut command).expect("oversized commands have a value");
+
+    match action {
+        OversizedAction::Truncate => {
+            log::warn!(
+                "truncating value of {} from {} to {} bytes",
+                key,
+                value.len(),
+                max_bytes
+            );
+
+            let mut end = max_bytes;
+
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            value.truncate(end);
+
+            Ok((Some(command), true))
+        }
+        OversizedAction::Skip => {
+            log::warn!("skipping value of {} with {} bytes", key, value.len());
+            Ok((None, true))
+        }
+        OversizedAction::Fail => Err(Error::message(format!(
+            "value of {} has {} bytes, over the limit of {}",
+            key,
+            value.len(),
+            max_bytes
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large() -> CRDTCommand {
+        CRDTCommand::AnyWriteWins("key".into(), "é".repeat(6))
+    }
+
+    #[test]
+    fn values_within_the_limit_go_through() {
+        let command = CRDTCommand::AnyWriteWins("key".into(), "abc".into());
+
+        let (guarded, oversized) = guard(command, 3, OversizedAction::Fail).unwrap();
+
+        assert!(!oversized);
+        assert!(matches!(guarded, Some(CRDTCommand::AnyWriteWins(_, x)) if x == "abc"));
+    }
+
+    #[test]
+    fn oversized_values_follow_the_action() {
+        let (guarded, oversized) = guard(large(), 5, OversizedAction::Skip).unwrap();
+        assert!(oversized);
+        assert!(guarded.is_none());
+
+        // the cut can't split a char
+        let (guarded, oversized) = guard(large(), 5, OversizedAction::Truncate).unwrap();
+        assert!(oversized);
+        assert!(matches!(guarded, Some(CRDTCommand::AnyWriteWins(_, x)) if x == "éé"));
+
+        assert!(guard(large(), 5, OversizedAction::Fail).is_err());
+    }
+
+    #[test]
+    fn commands_without_a_value_are_left_alone() {
+        let command = CRDTCommand::PNCounter("key".into(), "123456".into());
+
+        let (guarded, oversized) = guard(command, 1, OversizedAction::Fail).unwrap();
+
+        assert!(!oversized);
+        assert!(guarded.is_some());
+    }
+}
@@ -0,0 +1,685 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{mpsc, Arc, Mutex},
+};
+
+use gasket::{
+    error::AsWorkError,
+    runtime::{spawn_stage, WorkOutcome},
+};
+use serde::Deserialize;
+
+use crate::{
+    bootstrap, crosscut,
+    cursor::{self, CursorStore},
+    model, Error,
+};
+
+use super::{snapshot, watch};
+
+type FunnelPort = gasket::messaging::FunnelPort<model::CRDTCommand>;
+
+#[derive(Deserialize, Default)]
+pub struct Config {}
+
+/// A value of the in-memory storage, shaped as the Redis type that holds it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Set(BTreeSet<String>),
+    SortedSet(BTreeMap<String, i64>),
+}
+
+/// Keys and values written by the commands, applied as the Redis storage
+/// does without tombstones
+///
+/// HyperLogLogs are kept as plain sets, so their counts are exact.
+#[derive(Debug, Default)]
+pub struct Store {
+    values: BTreeMap<String, Value>,
+    cursor: Option<crosscut::PointArg>,
+
+    /// Receive every applied command, dropped once their [Watcher] is gone
+    watchers: Vec<mpsc::Sender<watch::Change>>,
+}
+
+fn parse_number(key: &str, value: &str) -> Result<i64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::storage(format!("value of {} is not an integer: {}", key, value)))
+}
+
+fn wrong_type(key: &str) -> Error {
+    Error::storage(format!("key {} holds a value of another type", key))
+}
+
+impl Store {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(|x| x.as_str())
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        match self.values.get(key) {
+            Some(Value::String(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Value of a counter, none if the key is missing or isn't a number
+    pub fn counter(&self, key: &str) -> Option<i64> {
+        self.string(key).and_then(|x| x.parse().ok())
+    }
+
+    pub fn members(&self, key: &str) -> Option<&BTreeSet<String>> {
+        match self.values.get(key) {
+            Some(Value::Set(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn scores(&self, key: &str) -> Option<&BTreeMap<String, i64>> {
+        match self.values.get(key) {
+            Some(Value::SortedSet(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Point of the last finished block
+    pub fn cursor(&self) -> Option<&crosscut::PointArg> {
+        self.cursor.as_ref()
+    }
+
+    /// Follows the commands applied from now on
+    pub fn watcher(&mut self) -> Watcher {
+        let (sender, changes) = mpsc::channel();
+        self.watchers.push(sender);
+
+        Watcher { changes }
+    }
+
+    /// Value of the key in the shape of a snapshot entry
+    fn entry(&self, key: &str) -> Option<snapshot::Entry> {
+        let entry = match self.values.get(key)? {
+            Value::String(x) => snapshot::Entry::String(key.to_string(), x.as_bytes().to_vec()),
+            Value::Set(x) => snapshot::Entry::Set(
+                key.to_string(),
+                x.iter().map(|m| m.as_bytes().to_vec()).collect(),
+            ),
+            Value::SortedSet(x) => snapshot::Entry::SortedSet(
+                key.to_string(),
+                x.iter()
+                    .map(|(m, s)| (m.as_bytes().to_vec(), *s as f64))
+                    .collect(),
+            ),
+        };
+
+        Some(entry)
+    }
+
+    fn set_mut(&mut self, key: &str) -> Result<&mut BTreeSet<String>, Error> {
+        let value = self
+            .values
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Set(BTreeSet::new()));
+
+        match value {
+            Value::Set(x) => Ok(x),
+            _ => Err(wrong_type(key)),
+        }
+    }
+
+    fn sorted_set_mut(&mut self, key: &str) -> Result<&mut BTreeMap<String, i64>, Error> {
+        let value = self
+            .values
+            .entry(key.to_string())
+            .or_insert_with(|| Value::SortedSet(BTreeMap::new()));
+
+        match value {
+            Value::SortedSet(x) => Ok(x),
+            _ => Err(wrong_type(key)),
+        }
+    }
+
+    /// Removes the key once its set or sorted set is empty, as Redis does
+    fn drop_if_empty(&mut self, key: &str) {
+        let empty = match self.values.get(key) {
+            Some(Value::Set(x)) => x.is_empty(),
+            Some(Value::SortedSet(x)) => x.is_empty(),
+            _ => false,
+        };
+
+        if empty {
+            self.values.remove(key);
+        }
+    }
+
+    fn increment(&mut self, key: &str, delta: i64) -> Result<i64, Error> {
+        let current = match self.values.get(key) {
+            Some(Value::String(x)) => parse_number(key, x)?,
+            Some(_) => return Err(wrong_type(key)),
+            None => 0,
+        };
+
+        let value = current + delta;
+        self.values
+            .insert(key.to_string(), Value::String(value.to_string()));
+
+        Ok(value)
+    }
+
+    /// Change reported to the watchers for a command, as the JSON op
+    /// published by the Kafka storage
+    fn change(&self, command: &model::CRDTCommand) -> Option<watch::Change> {
+        if self.watchers.is_empty() {
+            return None;
+        }
+
+        let (key, op) = super::ops::to_json(command)?;
+
+        Some(watch::Change {
+            key: key.to_string(),
+            event: op.to_string(),
+        })
+    }
+
+    pub fn apply(&mut self, command: model::CRDTCommand) -> Result<(), Error> {
+        let change = self.change(&command);
+
+        self.write(command)?;
+
+        if let Some(change) = change {
+            self.watchers.retain(|x| x.send(change.clone()).is_ok());
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, command: model::CRDTCommand) -> Result<(), Error> {
+        use model::CRDTCommand::*;
+
+        match command {
+            BlockStarting(_) => (),
+            BlockFinished(point) => self.cursor = Some(point.into()),
+            GrowOnlySetAdd(key, member)
+            | TwoPhaseSetAdd(key, member)
+            | HyperLogLogAdd(key, member) => {
+                self.set_mut(&key)?.insert(member);
+            }
+            TwoPhaseSetRemove(key, member) => {
+                self.set_mut(&format!("{}.ts", key))?.insert(member);
+            }
+            SetRemove(key, member) => {
+                self.set_mut(&key)?.remove(&member);
+                self.drop_if_empty(&key);
+            }
+            LastWriteWins(key, value, timestamp) => {
+                self.sorted_set_mut(&key)?.insert(value, timestamp as i64);
+            }
+            AnyWriteWins(key, value) => {
+                self.values.insert(key, Value::String(value));
+            }
+            AnyWriteWinsRemove(key) => {
+                self.values.remove(&key);
+            }
+            ConditionalWrite(key, value, condition) => {
+                let write = match (self.values.get(&key), condition) {
+                    (None, _) => true,
+                    (Some(_), model::WriteCondition::NotExists) => false,
+                    (Some(Value::String(current)), condition) => {
+                        let current = parse_number(&key, current)?;
+                        let value = parse_number(&key, &value)?;
+
+                        match condition {
+                            model::WriteCondition::LessThan => value < current,
+                            _ => value > current,
+                        }
+                    }
+                    (Some(_), _) => return Err(wrong_type(&key)),
+                };
+
+                if write {
+                    self.values.insert(key, Value::String(value));
+                }
+            }
+            PNCounter(key, value) => {
+                let delta = parse_number(&key, &value)?;
+                self.increment(&key, delta)?;
+            }
+            SortedSetAdd(key, member, delta) => {
+                let set = self.sorted_set_mut(&key)?;
+                let score = set.entry(member.clone()).or_insert(0);
+                *score += delta;
+
+                if *score <= 0 {
+                    set.remove(&member);
+                }
+
+                self.drop_if_empty(&key);
+            }
+            SortedSetCopyTop(from, into, size) => {
+                let mut top: Vec<_> = self
+                    .scores(&from)
+                    .map(|x| x.iter().map(|(m, s)| (m.clone(), *s)).collect())
+                    .unwrap_or_default();
+
+                // highest scores first, ties in reverse member order like ZRANGE REV
+                top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+                top.truncate(size);
+
+                match top.is_empty() {
+                    true => self.values.remove(&into),
+                    false => self
+                        .values
+                        .insert(into, Value::SortedSet(top.into_iter().collect())),
+                };
+            }
+            SortedSetRemoveRange(key, min, max) => {
+                let range = model::timestamp_score(min)..=model::timestamp_score(max);
+                self.sorted_set_mut(&key)?
+                    .retain(|_, score| !range.contains(score));
+                self.drop_if_empty(&key);
+            }
+            CounterSnapshot(key, delta, set, timestamp) => {
+                let value = self.increment(&key, delta)?;
+
+                let snapshots = self.sorted_set_mut(&set)?;
+                snapshots.retain(|_, score| *score != timestamp as i64);
+                snapshots.insert(format!("{}:{}", timestamp, value), timestamp as i64);
+            }
+        };
+
+        Ok(())
+    }
+}
+
+/// Follows the commands applied to a [Store] since it was created, until the
+/// store is dropped
+pub struct Watcher {
+    changes: mpsc::Receiver<watch::Change>,
+}
+
+impl watch::Watchable for Watcher {
+    fn watch(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(watch::Change) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for change in self.changes.iter() {
+            if change.key.starts_with(prefix) {
+                visit(change)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads and restores the keys of a shared [Store]
+pub struct Snapshotter {
+    store: Arc<Mutex<Store>>,
+}
+
+impl Snapshotter {
+    pub fn new(store: Arc<Mutex<Store>>) -> Self {
+        Snapshotter { store }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().expect("memory store lock poisoned")
+    }
+}
+
+impl snapshot::Snapshottable for Snapshotter {
+    fn scan_prefix(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(snapshot::Entry) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        // the entries are collected first, so that the store isn't locked
+        // while they are visited
+        let entries: Vec<_> = {
+            let store = self.lock();
+
+            store
+                .values
+                .range(prefix.to_string()..)
+                .map(|(key, _)| key)
+                .take_while(|key| key.starts_with(prefix))
+                .filter_map(|key| store.entry(key))
+                .collect()
+        };
+
+        for entry in entries {
+            visit(entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<snapshot::Entry>, Error> {
+        Ok(self.lock().entry(key))
+    }
+
+    fn import(&mut self, entry: snapshot::Entry) -> Result<(), Error> {
+        let text = |x: Vec<u8>| {
+            String::from_utf8(x).map_err(|_| Error::storage("memory values are utf-8 strings"))
+        };
+
+        let (key, value) = match entry {
+            snapshot::Entry::String(key, value) => (key, Value::String(text(value)?)),
+            snapshot::Entry::Set(key, members) => {
+                let members = members.into_iter().map(text).collect::<Result<_, _>>()?;
+                (key, Value::Set(members))
+            }
+            snapshot::Entry::SortedSet(key, members) => {
+                let members = members
+                    .into_iter()
+                    .map(|(m, s)| Ok((text(m)?, s as i64)))
+                    .collect::<Result<_, Error>>()?;
+
+                (key, Value::SortedSet(members))
+            }
+        };
+
+        self.lock().values.insert(key, value);
+
+        Ok(())
+    }
+}
+
+/// Keeps the data in memory, shared with whoever holds [Worker::store]
+///
+/// Meant for tests and for trying out a pipeline, nothing outlives the
+/// process and the cursor is never saved. Next to other storages, it still
+/// reports its blocks to their [cursor::gate::Gate].
+pub struct Worker {
+    store: Arc<Mutex<Store>>,
+    until: Option<u64>,
+    gate: Option<cursor::Store>,
+    input: FunnelPort,
+    ops_count: gasket::metrics::Counter,
+    block_count: gasket::metrics::Counter,
+    last_slot: gasket::metrics::Gauge,
+}
+
+impl Worker {
+    pub fn store(&self) -> Arc<Mutex<Store>> {
+        self.store.clone()
+    }
+
+    /// Writes into an existing store, eg: the one of a previous run
+    pub fn with_store(mut self, store: Arc<Mutex<Store>>) -> Self {
+        self.store = store;
+        self
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("storage_ops", &self.ops_count)
+            .with_counter("block_count", &self.block_count)
+            .with_gauge("last_slot", &self.last_slot)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = self.input.recv()?;
+
+        let finished = match &msg.payload {
+            model::CRDTCommand::BlockFinished(point) => {
+                Some(crosscut::PointArg::from(point.clone()))
+            }
+            _ => None,
+        };
+
+        self.store
+            .lock()
+            .expect("memory store lock poisoned")
+            .apply(msg.payload)
+            .or_work_err()?;
+
+        self.ops_count.inc(1);
+
+        if let (Some(gate), Some(point)) = (&mut self.gate, &finished) {
+            gate.save(point).or_work_err()?;
+        }
+
+        if let Some(crosscut::PointArg::Specific(slot, _)) = &finished {
+            self.block_count.inc(1);
+            self.last_slot.set(*slot as i64);
+
+            if self.until.map(|x| *slot >= x).unwrap_or(false) {
+                log::info!("reached end of requested range at slot {}", slot);
+                return Ok(WorkOutcome::Done);
+            }
+        }
+
+        Ok(WorkOutcome::Partial)
+    }
+}
+
+impl super::Pluggable for Worker {
+    fn borrow_input_port(&mut self) -> &'_ mut FunnelPort {
+        &mut self.input
+    }
+
+    fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
+        pipeline.register_stage("memory", spawn_stage(self, Default::default()));
+    }
+}
+
+impl Config {
+    pub fn plugin(
+        self,
+        _chain: &crosscut::ChainWellKnownInfo,
+        intersect: &crosscut::IntersectConfig,
+        cursor: cursor::Store,
+    ) -> super::Plugin {
+        let gate = match cursor {
+            cursor::Store::Gated(_) => Some(cursor),
+            _ => None,
+        };
+
+        let until = match intersect {
+            crosscut::IntersectConfig::Range(_, crosscut::PointArg::Specific(slot, _)) => {
+                Some(*slot)
+            }
+            _ => None,
+        };
+
+        let worker = Worker {
+            store: Default::default(),
+            until,
+            gate,
+            input: Default::default(),
+            ops_count: Default::default(),
+            block_count: Default::default(),
+            last_slot: Default::default(),
+        };
+
+        super::Plugin::Memory(worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::CRDTCommand::*;
+
+    fn store_with(commands: Vec<model::CRDTCommand>) -> Store {
+        let mut store = Store::default();
+
+        for command in commands {
+            store.apply(command).unwrap();
+        }
+
+        store
+    }
+
+    #[test]
+    fn counters_add_up_and_reject_other_types() {
+        let mut store = store_with(vec![
+            PNCounter("a".into(), "5".into()),
+            PNCounter("a".into(), "-2".into()),
+            GrowOnlySetAdd("s".into(), "x".into()),
+        ]);
+
+        assert_eq!(store.counter("a"), Some(3));
+        assert!(store.apply(PNCounter("s".into(), "1".into())).is_err());
+    }
+
+    #[test]
+    fn sorted_set_members_drop_at_zero() {
+        let store = store_with(vec![
+            SortedSetAdd("z".into(), "a".into(), 5),
+            SortedSetAdd("z".into(), "b".into(), 3),
+            SortedSetAdd("z".into(), "a".into(), -5),
+        ]);
+
+        assert_eq!(store.scores("z").unwrap().get("a"), None);
+        assert_eq!(store.scores("z").unwrap().get("b"), Some(&3));
+
+        let store = store_with(vec![
+            SortedSetAdd("z".into(), "a".into(), 1),
+            SortedSetAdd("z".into(), "a".into(), -1),
+        ]);
+
+        assert_eq!(store.get("z"), None);
+    }
+
+    #[test]
+    fn copy_top_keeps_highest_scores() {
+        let store = store_with(vec![
+            SortedSetAdd("z".into(), "a".into(), 1),
+            SortedSetAdd("z".into(), "b".into(), 3),
+            SortedSetAdd("z".into(), "c".into(), 2),
+            SortedSetCopyTop("z".into(), "top".into(), 2),
+        ]);
+
+        let top = store.scores("top").unwrap();
+        assert_eq!(top.len(), 2);
+        assert!(top.contains_key("b") && top.contains_key("c"));
+    }
+
+    #[test]
+    fn conditional_writes_compare_numbers() {
+        let store = store_with(vec![
+            ConditionalWrite("max".into(), "5".into(), model::WriteCondition::GreaterThan),
+            ConditionalWrite(
+                "max".into(),
+                "12".into(),
+                model::WriteCondition::GreaterThan,
+            ),
+            ConditionalWrite("max".into(), "7".into(), model::WriteCondition::GreaterThan),
+            ConditionalWrite("first".into(), "a".into(), model::WriteCondition::NotExists),
+            ConditionalWrite("first".into(), "b".into(), model::WriteCondition::NotExists),
+        ]);
+
+        assert_eq!(store.string("max"), Some("12"));
+        assert_eq!(store.string("first"), Some("a"));
+    }
+
+    #[test]
+    fn snapshots_and_watchers_see_the_applied_commands() {
+        use snapshot::Snapshottable;
+        use watch::Watchable;
+
+        let shared = Arc::new(Mutex::new(Store::default()));
+        let mut watcher = shared.lock().unwrap().watcher();
+
+        {
+            let mut store = shared.lock().unwrap();
+            store.apply(PNCounter("a.1".into(), "5".into())).unwrap();
+            store
+                .apply(GrowOnlySetAdd("a.2".into(), "x".into()))
+                .unwrap();
+            store.apply(PNCounter("b".into(), "1".into())).unwrap();
+        }
+
+        let mut snapshotter = Snapshotter::new(shared.clone());
+
+        let mut keys = Vec::new();
+        snapshotter
+            .scan_prefix("a.", &mut |entry| {
+                keys.push(entry.key().to_string());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(keys, vec!["a.1", "a.2"]);
+        assert_eq!(
+            snapshotter.get("a.1").unwrap(),
+            Some(snapshot::Entry::String("a.1".into(), b"5".to_vec()))
+        );
+        assert_eq!(snapshotter.get("c").unwrap(), None);
+
+        // the store goes away once the only other handle is dropped
+        drop(snapshotter);
+        drop(shared);
+
+        let mut changes = Vec::new();
+        watcher
+            .watch("a.", &mut |change| {
+                changes.push(change.key);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(changes, vec!["a.1", "a.2"]);
+    }
+
+    #[test]
+    fn remove_range_and_snapshots() {
+        let store = store_with(vec![
+            LastWriteWins("l".into(), "a".into(), 10),
+            LastWriteWins("l".into(), "b".into(), 20),
+            SortedSetRemoveRange("l".into(), 15, 25),
+            CounterSnapshot("c".into(), 4, "h".into(), 100),
+            CounterSnapshot("c".into(), 1, "h".into(), 100),
+        ]);
+
+        assert_eq!(store.scores("l").unwrap().len(), 1);
+        assert_eq!(store.counter("c"), Some(5));
+
+        let history = store.scores("h").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.get("100:5"), Some(&100));
+    }
+
+    #[test]
+    fn scanning_a_prefix_returns_exactly_its_keys() {
+        use snapshot::Snapshottable;
+
+        let store = store_with(
+            ["a", "a.", "a.1", "a.1.x", "a_2", "ab", "b.1"]
+                .iter()
+                .map(|x| AnyWriteWins(x.to_string(), "1".into()))
+                .collect(),
+        );
+
+        let mut snapshotter = Snapshotter::new(Arc::new(Mutex::new(store)));
+
+        let mut scan = |prefix: &str| {
+            let mut keys = Vec::new();
+
+            snapshotter
+                .scan_prefix(prefix, &mut |entry| {
+                    keys.push(entry.key().to_string());
+                    Ok(())
+                })
+                .unwrap();
+
+            keys
+        };
+
+        assert_eq!(scan("a."), vec!["a.", "a.1", "a.1.x"]);
+        assert_eq!(scan("a.1"), vec!["a.1", "a.1.x"]);
+        assert_eq!(scan("c"), Vec::<String>::new());
+        assert_eq!(scan("").len(), 7);
+    }
+}
@@ -1,35 +1,71 @@
+pub mod adaptive;
+pub mod coalesce;
+pub mod compression;
+pub mod dry_run;
+pub mod full;
+pub mod limits;
+pub mod memory;
+pub mod ops;
 pub mod redis;
+pub mod shard;
+pub mod snapshot;
+pub mod timeouts;
+pub mod watch;
+
+#[cfg(feature = "kafkasink")]
+pub mod kafka;
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
 
 use gasket::messaging::FunnelPort;
 
-use crate::{bootstrap, crosscut, model, Error};
+use crate::{bootstrap, model};
 
 pub trait Pluggable {
     fn borrow_input_port(&mut self) -> &'_ mut FunnelPort<model::CRDTCommand>;
     fn spawn(self, pipeline: &mut bootstrap::Pipeline);
-    fn read_cursor(&self) -> Result<crosscut::Cursor, Error>;
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum Plugin {
     Redis(redis::Worker),
+    DryRun(dry_run::Worker),
+    Memory(memory::Worker),
+
+    #[cfg(feature = "kafkasink")]
+    Kafka(kafka::Worker),
+
+    #[cfg(feature = "dynamodb")]
+    DynamoDB(dynamodb::Worker),
 }
 
 impl Plugin {
     pub fn borrow_input_port(&mut self) -> &'_ mut FunnelPort<model::CRDTCommand> {
         match self {
             Plugin::Redis(x) => x.borrow_input_port(),
-        }
-    }
+            Plugin::DryRun(x) => x.borrow_input_port(),
+            Plugin::Memory(x) => x.borrow_input_port(),
 
-    pub fn read_cursor(&self) -> Result<crosscut::Cursor, Error> {
-        match self {
-            Plugin::Redis(x) => x.read_cursor(),
+            #[cfg(feature = "kafkasink")]
+            Plugin::Kafka(x) => x.borrow_input_port(),
+
+            #[cfg(feature = "dynamodb")]
+            Plugin::DynamoDB(x) => x.borrow_input_port(),
         }
     }
 
     pub fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
         match self {
             Plugin::Redis(x) => x.spawn(pipeline),
+            Plugin::DryRun(x) => x.spawn(pipeline),
+            Plugin::Memory(x) => x.spawn(pipeline),
+
+            #[cfg(feature = "kafkasink")]
+            Plugin::Kafka(x) => x.spawn(pipeline),
+
+            #[cfg(feature = "dynamodb")]
+            Plugin::DynamoDB(x) => x.spawn(pipeline),
         }
     }
 }
@@ -0,0 +1,246 @@
+//! JSON representation of the CRDT commands, shared by the sinks that publish
+//! the ops and the tools that replay them
+
+use serde_json::json;
+
+use crate::{model, Error};
+
+fn condition_name(condition: model::WriteCondition) -> &'static str {
+    match condition {
+        model::WriteCondition::NotExists => "not_exists",
+        model::WriteCondition::GreaterThan => "greater_than",
+        model::WriteCondition::LessThan => "less_than",
+    }
+}
+
+fn parse_condition(name: &str) -> Result<model::WriteCondition, Error> {
+    match name {
+        "not_exists" => Ok(model::WriteCondition::NotExists),
+        "greater_than" => Ok(model::WriteCondition::GreaterThan),
+        "less_than" => Ok(model::WriteCondition::LessThan),
+        x => Err(Error::message(format!("unknown write condition {}", x))),
+    }
+}
+
+/// Encodes a command as the affected key and a JSON payload, block boundaries
+/// don't have a representation
+pub fn to_json(command: &model::CRDTCommand) -> Option<(&str, serde_json::Value)> {
+    let out = match command {
+        model::CRDTCommand::BlockStarting(_) | model::CRDTCommand::BlockFinished(_) => return None,
+        model::CRDTCommand::TwoPhaseSetAdd(set, member) => {
+            (set, json!({ "op": "two_phase_set_add", "member": member }))
+        }
+        model::CRDTCommand::TwoPhaseSetRemove(set, member) => (
+            set,
+            json!({ "op": "two_phase_set_remove", "member": member }),
+        ),
+        model::CRDTCommand::GrowOnlySetAdd(set, member) => {
+            (set, json!({ "op": "grow_only_set_add", "member": member }))
+        }
+        model::CRDTCommand::SetRemove(set, member) => {
+            (set, json!({ "op": "set_remove", "member": member }))
+        }
+        model::CRDTCommand::HyperLogLogAdd(key, member) => {
+            (key, json!({ "op": "hyper_log_log_add", "member": member }))
+        }
+        model::CRDTCommand::LastWriteWins(key, value, timestamp) => (
+            key,
+            json!({ "op": "last_write_wins", "value": value, "timestamp": timestamp }),
+        ),
+        model::CRDTCommand::AnyWriteWins(key, value) => {
+            (key, json!({ "op": "any_write_wins", "value": value }))
+        }
+        model::CRDTCommand::AnyWriteWinsRemove(key) => {
+            (key, json!({ "op": "any_write_wins_remove" }))
+        }
+        model::CRDTCommand::ConditionalWrite(key, value, condition) => (
+            key,
+            json!({
+                "op": "conditional_write",
+                "value": value,
+                "condition": condition_name(*condition),
+            }),
+        ),
+        model::CRDTCommand::PNCounter(key, value) => {
+            (key, json!({ "op": "pn_counter", "delta": value }))
+        }
+        model::CRDTCommand::SortedSetAdd(set, member, delta) => (
+            set,
+            json!({ "op": "sorted_set_add", "member": member, "delta": delta }),
+        ),
+        model::CRDTCommand::SortedSetCopyTop(from, into, size) => (
+            into,
+            json!({ "op": "sorted_set_copy_top", "from": from, "size": size }),
+        ),
+        model::CRDTCommand::SortedSetRemoveRange(set, min, max) => (
+            set,
+            json!({ "op": "sorted_set_remove_range", "min": min, "max": max }),
+        ),
+        model::CRDTCommand::CounterSnapshot(key, delta, set, timestamp) => (
+            key,
+            json!({
+                "op": "counter_snapshot",
+                "delta": delta,
+                "set": set,
+                "timestamp": timestamp,
+            }),
+        ),
+    };
+
+    Some((out.0.as_str(), out.1))
+}
+
+fn field<'a>(payload: &'a serde_json::Value, name: &str) -> Result<&'a serde_json::Value, Error> {
+    payload
+        .get(name)
+        .ok_or_else(|| Error::message(format!("op is missing the `{}` field", name)))
+}
+
+fn str_field(payload: &serde_json::Value, name: &str) -> Result<String, Error> {
+    field(payload, name)?
+        .as_str()
+        .map(|x| x.to_string())
+        .ok_or_else(|| Error::message(format!("`{}` field of op should be a string", name)))
+}
+
+fn u64_field(payload: &serde_json::Value, name: &str) -> Result<u64, Error> {
+    field(payload, name)?
+        .as_u64()
+        .ok_or_else(|| Error::message(format!("`{}` field of op should be a number", name)))
+}
+
+fn i64_field(payload: &serde_json::Value, name: &str) -> Result<i64, Error> {
+    field(payload, name)?
+        .as_i64()
+        .ok_or_else(|| Error::message(format!("`{}` field of op should be a number", name)))
+}
+
+/// Decodes a command from the affected key and the payload built by `to_json`
+pub fn from_json(key: String, payload: &serde_json::Value) -> Result<model::CRDTCommand, Error> {
+    use model::CRDTCommand::*;
+
+    let op = str_field(payload, "op")?;
+
+    let command = match op.as_str() {
+        "two_phase_set_add" => TwoPhaseSetAdd(key, str_field(payload, "member")?),
+        "two_phase_set_remove" => TwoPhaseSetRemove(key, str_field(payload, "member")?),
+        "grow_only_set_add" => GrowOnlySetAdd(key, str_field(payload, "member")?),
+        "set_remove" => SetRemove(key, str_field(payload, "member")?),
+        "hyper_log_log_add" => HyperLogLogAdd(key, str_field(payload, "member")?),
+        "last_write_wins" => LastWriteWins(
+            key,
+            str_field(payload, "value")?,
+            u64_field(payload, "timestamp")?,
+        ),
+        "any_write_wins" => AnyWriteWins(key, str_field(payload, "value")?),
+        "any_write_wins_remove" => AnyWriteWinsRemove(key),
+        "conditional_write" => ConditionalWrite(
+            key,
+            str_field(payload, "value")?,
+            parse_condition(&str_field(payload, "condition")?)?,
+        ),
+        "pn_counter" => PNCounter(key, str_field(payload, "delta")?),
+        "sorted_set_add" => SortedSetAdd(
+            key,
+            str_field(payload, "member")?,
+            i64_field(payload, "delta")?,
+        ),
+        "sorted_set_copy_top" => SortedSetCopyTop(
+            str_field(payload, "from")?,
+            key,
+            u64_field(payload, "size")? as usize,
+        ),
+        "sorted_set_remove_range" => {
+            SortedSetRemoveRange(key, u64_field(payload, "min")?, u64_field(payload, "max")?)
+        }
+        "counter_snapshot" => CounterSnapshot(
+            key,
+            i64_field(payload, "delta")?,
+            str_field(payload, "set")?,
+            u64_field(payload, "timestamp")?,
+        ),
+        x => return Err(Error::message(format!("unknown op {}", x))),
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::CRDTCommand::*;
+
+    #[test]
+    fn every_op_round_trips() {
+        let commands = vec![
+            TwoPhaseSetAdd("k".into(), "m".into()),
+            TwoPhaseSetRemove("k".into(), "m".into()),
+            GrowOnlySetAdd("k".into(), "m".into()),
+            SetRemove("k".into(), "m".into()),
+            HyperLogLogAdd("k".into(), "m".into()),
+            LastWriteWins("k".into(), "v".into(), 42),
+            AnyWriteWins("k".into(), "v".into()),
+            AnyWriteWinsRemove("k".into()),
+            ConditionalWrite("k".into(), "7".into(), model::WriteCondition::LessThan),
+            PNCounter("k".into(), "-3".into()),
+            SortedSetAdd("k".into(), "m".into(), -5),
+            SortedSetCopyTop("from".into(), "k".into(), 10),
+            SortedSetRemoveRange("k".into(), 1, 2),
+            CounterSnapshot("k".into(), 4, "set".into(), 9),
+        ];
+
+        for command in commands {
+            let (key, payload) = to_json(&command).unwrap();
+            let decoded = from_json(key.to_string(), &payload).unwrap();
+            let (decoded_key, decoded_payload) = to_json(&decoded).unwrap();
+
+            assert_eq!(decoded_key, key);
+            assert_eq!(decoded_payload, payload);
+        }
+    }
+
+    #[test]
+    fn each_write_condition_keeps_its_name() {
+        let conditions = [
+            (model::WriteCondition::NotExists, "not_exists"),
+            (model::WriteCondition::GreaterThan, "greater_than"),
+            (model::WriteCondition::LessThan, "less_than"),
+        ];
+
+        for (condition, name) in conditions {
+            let command = ConditionalWrite("k".into(), "7".into(), condition);
+            let (key, payload) = to_json(&command).unwrap();
+
+            assert_eq!(
+                payload,
+                json!({ "op": "conditional_write", "value": "7", "condition": name })
+            );
+
+            match from_json(key.to_string(), &payload).unwrap() {
+                ConditionalWrite(key, value, decoded) => {
+                    assert_eq!((key.as_str(), value.as_str()), ("k", "7"));
+                    assert_eq!(decoded, condition);
+                }
+                other => panic!("decoded as {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn block_boundaries_have_no_op() {
+        let point = pallas::network::miniprotocols::Point::Origin;
+        assert!(to_json(&BlockFinished(point)).is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_and_incomplete_ops() {
+        let unknown = json!({ "op": "teleport" });
+        assert!(from_json("k".into(), &unknown).is_err());
+
+        let incomplete = json!({ "op": "last_write_wins", "value": "v" });
+        assert!(from_json("k".into(), &incomplete).is_err());
+
+        let condition = json!({ "op": "conditional_write", "value": "1", "condition": "equal" });
+        assert!(from_json("k".into(), &condition).is_err());
+    }
+}
@@ -1,92 +1,886 @@
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc,
+};
 
 use gasket::{
     error::AsWorkError,
     runtime::{spawn_stage, WorkOutcome},
 };
 
-use redis::Commands;
+use pallas::network::miniprotocols::Point;
+use redis::{Commands, IntoConnectionInfo};
 use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    bootstrap, crosscut,
+    cursor::{self, CursorStore},
+    model, Error,
+};
 
-use crate::{bootstrap, crosscut, model};
+use super::compression::{Codec, Compression};
+use super::limits::OversizedAction;
+use super::timeouts::Timeouts;
 
 type FunnelPort = gasket::messaging::FunnelPort<model::CRDTCommand>;
 
-#[derive(Deserialize)]
+/// Opens a connection bounded by the timeouts: the connect one, and the
+/// operation one for each read and write on it
+pub fn connect(
+    timeouts: &Timeouts,
+    info: impl IntoConnectionInfo,
+) -> Result<redis::Connection, redis::RedisError> {
+    let connection = redis::Client::open(info)?.get_connection_with_timeout(timeouts.connect())?;
+
+    connection.set_read_timeout(Some(timeouts.operation()))?;
+    connection.set_write_timeout(Some(timeouts.operation()))?;
+
+    Ok(connection)
+}
+
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub connection_params: String,
+
+    #[serde(flatten)]
+    pub timeouts: Timeouts,
+
+    /// Compression of the string values, the keyspaces can override it.
+    /// Numbers and the members of sets and sorted sets are kept plain.
+    /// Readers restore values with [Compression::decode], which also accepts
+    /// plain ones.
+    pub compress_values: Option<Compression>,
+
+    /// Values shorter than this are kept plain, defaults to
+    /// [super::compression::DEFAULT_MIN_BYTES]
+    pub compress_min_bytes: Option<usize>,
+
+    /// Rules applied to the keys of the collections, matched by key prefix
+    pub keyspaces: Option<Vec<KeyspaceConfig>>,
+
+    pub deletion_mode: Option<DeletionMode>,
+
+    /// Number of blocks whose commands are committed together, along with the
+    /// cursor. Defaults to committing every block.
+    pub commit_every_n_blocks: Option<u64>,
+
+    /// Size the commit batches by the memory used by the process instead,
+    /// overrides `commit_every_n_blocks`
+    pub adaptive_batching: Option<super::adaptive::Config>,
+
+    /// Merge the counter increments and the writes of a key within a commit
+    /// into a single op, see `storage::coalesce`
+    pub coalesce_writes: Option<bool>,
+
+    /// Persist the cursor at these checkpoints only, forcing a commit on each
+    /// of them. Defaults to persisting the cursor on every commit.
+    pub cursor_checkpoint: Option<cursor::Checkpoint>,
+
+    /// Record the last committed block in each db, along with its commands,
+    /// and skip the commands of blocks up to it when they're replayed after a
+    /// crash (eg: the cursor wasn't saved, or lags behind with sparse
+    /// checkpoints)
+    pub idempotent_replay: Option<bool>,
+
+    /// Limit on the size of values and members, before compression
+    pub max_value_bytes: Option<usize>,
+    pub on_oversized_value: Option<OversizedAction>,
+
+    /// Number of writers executing each commit in parallel, on their own
+    /// connections. Commands are split by key, see `storage::shard`.
+    /// Defaults to a single writer.
+    pub write_shards: Option<usize>,
+
+    /// Wait and retry the commits refused because the server is out of
+    /// memory or read-only, instead of failing
+    pub on_full: Option<super::full::Config>,
+}
+
+/// How removals requested by the reducers reach the db
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletionMode {
+    /// Keys and members are removed
+    #[default]
+    Hard,
+
+    /// Removed keys are overwritten with a marker holding the slot of the
+    /// removal, and removed members of sets are added to the sorted set
+    /// `{key}.tombstones`, scored by that slot. Lets change-data-capture
+    /// consumers of the db observe deletions.
+    Tombstone,
+}
+
+/// Placement of the keys that start with a given prefix
+///
+/// A hash tag makes all of the matching keys share a Redis Cluster hash slot,
+/// eg: keys starting with `richest_addresses` become
+/// `{richest}richest_addresses...` with `hash_tag = "richest"`. The db index
+/// selects a logical db of a standalone Redis for the matching keys. The
+/// compression replaces the global one for the matching keys, so the key
+/// prefix of a reducer picks the compression of its values.
+#[derive(Deserialize, Clone)]
+pub struct KeyspaceConfig {
+    pub prefix: String,
+    pub hash_tag: Option<String>,
+    pub db: Option<i64>,
+    pub compress_values: Option<Compression>,
+}
+
+fn connect_db(
+    connection_params: &str,
+    timeouts: &Timeouts,
+    db: Option<i64>,
+) -> Result<redis::Connection, redis::RedisError> {
+    let mut info = connection_params.into_connection_info()?;
+
+    if let Some(db) = db {
+        info.redis.db = db;
+    }
+
+    connect(timeouts, info)
+}
+
+/// True for the errors of a server that refuses writes until it gets more
+/// memory or turns writable again
+fn is_full(err: &redis::RedisError) -> bool {
+    matches!(err.code(), Some("OOM") | Some("READONLY"))
+}
+
+/// Executes an atomic pipeline, retrying it while the server is full when
+/// configured. Redis checks these conditions as commands are queued and
+/// discards the whole transaction, so a retried pipeline applies once.
+fn execute_pipe(
+    pipe: &redis::Pipeline,
+    connection: &mut redis::Connection,
+    retry: Option<&super::full::Retry>,
+) -> Result<(), redis::RedisError> {
+    match retry {
+        Some(retry) => retry.run(is_full, || pipe.query::<()>(connection)),
+        None => pipe.query(connection),
+    }
+}
+
+/// The pipelines of one shard for a commit, by db
+type ShardBatch = Vec<(Option<i64>, redis::Pipeline)>;
+
+/// The dbs of a batch whose pipelines went through, and the error that
+/// stopped the others
+type ShardResult = (Vec<Option<i64>>, Option<String>);
+
+/// A thread executing the pipelines of one shard on its own connections
+struct ShardWriter {
+    batches: mpsc::Sender<ShardBatch>,
+    results: mpsc::Receiver<ShardResult>,
+}
+
+impl ShardWriter {
+    fn start(
+        index: usize,
+        connection_params: String,
+        timeouts: Timeouts,
+        retry: Option<super::full::Retry>,
+    ) -> Self {
+        let (batches, pending) = mpsc::channel::<ShardBatch>();
+        let (done, results) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut connections: HashMap<Option<i64>, redis::Connection> = HashMap::new();
+
+            for batch in pending {
+                let mut written = Vec::new();
+
+                let result = batch.into_iter().try_for_each(|(db, pipe)| {
+                    if let std::collections::hash_map::Entry::Vacant(e) = connections.entry(db) {
+                        let connection = connect_db(&connection_params, &timeouts, db)?;
+                        e.insert(connection);
+                    }
+
+                    let connection = connections.get_mut(&db).unwrap();
+                    let result = execute_pipe(&pipe, connection, retry.as_ref());
+
+                    // a connection that timed out may be out of sync with the server
+                    if result.is_err() {
+                        connections.remove(&db);
+                    }
+
+                    result?;
+                    written.push(db);
+
+                    Ok::<_, redis::RedisError>(())
+                });
+
+                if done
+                    .send((written, result.err().map(|x| x.to_string())))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+
+            log::debug!("redis writer {} stopped", index);
+        });
+
+        ShardWriter { batches, results }
+    }
 }
 
 pub struct Worker {
     config: Config,
-    connection: Option<redis::Connection>,
+    connections: HashMap<Option<i64>, redis::Connection>,
+    writers: Vec<ShardWriter>,
+    adaptive: Option<super::adaptive::Controller>,
+    full_retry: Option<super::full::Retry>,
+    cursor: cursor::Store,
+
+    /// Key of the cursor when it lives on the same server, written in the
+    /// same transaction as the data of the main db
+    cursor_key: Option<String>,
+
+    checkpointer: Option<cursor::Checkpointer>,
+    until: Option<u64>,
     input: FunnelPort,
+    current_slot: u64,
+    in_block: bool,
+    committed: HashMap<Option<i64>, crosscut::PointArg>,
+    pending: Vec<(Option<i64>, model::CRDTCommand, u64)>,
+    pending_blocks: u64,
+    block_count: gasket::metrics::Counter,
+    commit_count: gasket::metrics::Counter,
+    coalesced_count: gasket::metrics::Counter,
+    skipped_count: gasket::metrics::Counter,
+    oversized_count: gasket::metrics::Counter,
+    last_slot: gasket::metrics::Gauge,
+}
+
+/// Why the replayed block can't be on the chain of the committed block: it
+/// sits at the committed slot with another hash, or past it without the
+/// committed block ever being replayed
+fn replay_divergence(committed: &crosscut::PointArg, point: &Point) -> Option<&'static str> {
+    let (committed_slot, committed_hash) = match committed {
+        crosscut::PointArg::Specific(slot, hash) => (*slot, hash),
+        crosscut::PointArg::Origin => return None,
+    };
+
+    match point {
+        Point::Specific(slot, hash) if *slot == committed_slot => {
+            match hex::encode(hash).eq_ignore_ascii_case(committed_hash) {
+                true => None,
+                false => Some("another block at the same slot"),
+            }
+        }
+        Point::Specific(slot, _) if *slot > committed_slot => {
+            Some("the committed block was never replayed")
+        }
+        _ => None,
+    }
+}
+
+fn tombstone_marker(slot: u64) -> String {
+    json!({ "tombstone": true, "slot": slot }).to_string()
+}
+
+/// Key holding the point of the last block committed into a db, see
+/// [Config::idempotent_replay]
+const COMMITTED_BLOCK_KEY: &str = "_committed_block";
+
+fn tombstones_key(key: &str) -> String {
+    format!("{}.tombstones", key)
 }
 
 impl Worker {
-    fn redis_connect(&self) -> Result<redis::Connection, redis::RedisError> {
-        let client = redis::Client::open(self.config.connection_params.clone())?;
-        client.get_connection()
+    fn redis_connect(&self, db: Option<i64>) -> Result<redis::Connection, redis::RedisError> {
+        connect_db(&self.config.connection_params, &self.config.timeouts, db)
+    }
+
+    /// Finds the keyspace with the longest prefix matching the key
+    fn keyspace(&self, key: &str) -> Option<&KeyspaceConfig> {
+        self.config
+            .keyspaces
+            .iter()
+            .flatten()
+            .filter(|x| key.starts_with(&x.prefix))
+            .max_by_key(|x| x.prefix.len())
+    }
+
+    fn tag_key(&self, key: String) -> String {
+        let keyspace = match self.keyspace(&key) {
+            Some(x) => x,
+            None => return key,
+        };
+
+        match &keyspace.hash_tag {
+            Some(tag) => format!("{{{}}}{}", tag, key),
+            None => key,
+        }
+    }
+
+    /// Returns the db where the command should be executed, as set by the
+    /// keyspace of its key
+    fn route(&self, command: model::CRDTCommand) -> (Option<i64>, model::CRDTCommand) {
+        let db = super::shard::shard_key(&command)
+            .and_then(|key| self.keyspace(key))
+            .and_then(|x| x.db);
+
+        (db, command)
+    }
+
+    /// Codec of the values of the command, as set by the keyspace of its key
+    /// or the global config
+    /// The writer of the command, picked from its key once tagged
+    fn shard_of(&self, command: &model::CRDTCommand) -> usize {
+        let command = command.clone().map_keys(|x| self.tag_key(x));
+        super::shard::shard_of(&command, self.write_shards())
+    }
+
+    fn codec(&self, command: &model::CRDTCommand) -> Codec {
+        let compression = super::shard::shard_key(command)
+            .and_then(|key| self.keyspace(key))
+            .and_then(|x| x.compress_values)
+            .or(self.config.compress_values)
+            .unwrap_or_default();
+
+        Codec::new(compression, self.config.compress_min_bytes)
+    }
+}
+
+/// Increments the score of a member, removing it (and recording its
+/// tombstone if `ARGV[3]` is set) when nothing is left to rank
+const SORTED_SET_ADD_SCRIPT: &str = r#"
+local score = tonumber(redis.call('ZINCRBY', KEYS[1], ARGV[2], ARGV[1]))
+if score <= 0 then
+    redis.call('ZREM', KEYS[1], ARGV[1])
+    if ARGV[3] == '1' then
+        redis.call('ZADD', KEYS[2], ARGV[4], ARGV[1])
+    end
+end
+"#;
+
+/// Increments the counter and keeps a single `{ts}:{value}` snapshot per
+/// timestamp, the latest value replaces previous ones
+const COUNTER_SNAPSHOT_SCRIPT: &str = r#"
+local value = redis.call('INCRBY', KEYS[1], ARGV[1])
+redis.call('ZREMRANGEBYSCORE', KEYS[2], ARGV[2], ARGV[2])
+redis.call('ZADD', KEYS[2], ARGV[2], ARGV[2] .. ':' .. value)
+"#;
+
+/// Sets the value if the key is missing, or if the value compares as `ARGV[2]`
+/// (`gt` or `lt`) against the current one
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+local value = tonumber(ARGV[1])
+if not current
+    or (ARGV[2] == 'gt' and value > tonumber(current))
+    or (ARGV[2] == 'lt' and value < tonumber(current)) then
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+"#;
+
+/// Records the tombstones of the members in a score range before removing them
+const REMOVE_RANGE_TOMBSTONES_SCRIPT: &str = r#"
+local removed = redis.call('ZRANGEBYSCORE', KEYS[1], ARGV[1], ARGV[2])
+for _, member in ipairs(removed) do
+    redis.call('ZADD', KEYS[2], ARGV[3], member)
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], ARGV[1], ARGV[2])
+"#;
+
+fn atomic_pipe() -> redis::Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    pipe
+}
+
+/// Sets the cursor in the transaction of the main db, so that it only moves
+/// along with the data of its block
+fn queue_cursor(
+    pipes: &mut HashMap<(Option<i64>, usize), redis::Pipeline>,
+    key: &str,
+    point: &crosscut::PointArg,
+) {
+    let pipe = pipes.entry((None, 0)).or_insert_with(atomic_pipe);
+    pipe.set(key, point.to_string()).ignore();
+}
+
+/// Adds the Redis commands that implement a CRDT command to the pipeline
+///
+/// Commands that depend on the result of a previous one run as Lua scripts so
+/// that they can be part of an atomic pipeline.
+fn queue_command(
+    pipe: &mut redis::Pipeline,
+    command: model::CRDTCommand,
+    slot: u64,
+    tombstones: bool,
+    codec: Codec,
+) -> Result<(), gasket::error::Error> {
+    use model::CRDTCommand::*;
+
+    match command {
+        BlockStarting(_) | BlockFinished(_) => (),
+        GrowOnlySetAdd(key, value) | TwoPhaseSetAdd(key, value) => {
+            pipe.sadd(key, value).ignore();
+        }
+        TwoPhaseSetRemove(key, value) => {
+            pipe.sadd(format!("{}.ts", key), value).ignore();
+        }
+        SetRemove(key, member) => {
+            pipe.srem(&key, &member).ignore();
+
+            if tombstones {
+                pipe.zadd(tombstones_key(&key), member, slot).ignore();
+            }
+        }
+        HyperLogLogAdd(key, member) => {
+            pipe.pfadd(key, member).ignore();
+        }
+        LastWriteWins(key, value, timestamp) => {
+            pipe.zadd(key, value, timestamp).ignore();
+        }
+        AnyWriteWins(key, value) => {
+            let value = codec.encode(value.as_bytes()).or_work_err()?;
+            pipe.set(key, value).ignore();
+        }
+        AnyWriteWinsRemove(key) => {
+            match tombstones {
+                true => pipe.set(key, tombstone_marker(slot)).ignore(),
+                false => pipe.del(key).ignore(),
+            };
+        }
+        ConditionalWrite(key, value, model::WriteCondition::NotExists) => {
+            let value = codec.encode(value.as_bytes()).or_work_err()?;
+            pipe.set_nx(key, value).ignore();
+        }
+        // compared as numbers by the script, which are never compressed
+        ConditionalWrite(key, value, condition) => {
+            let op = match condition {
+                model::WriteCondition::LessThan => "lt",
+                _ => "gt",
+            };
+
+            pipe.cmd("EVAL")
+                .arg(COMPARE_AND_SET_SCRIPT)
+                .arg(1)
+                .arg(key)
+                .arg(value)
+                .arg(op)
+                .ignore();
+        }
+        PNCounter(key, value) => {
+            pipe.incr(key, value).ignore();
+        }
+        SortedSetAdd(key, member, delta) => {
+            pipe.cmd("EVAL")
+                .arg(SORTED_SET_ADD_SCRIPT)
+                .arg(2)
+                .arg(&key)
+                .arg(tombstones_key(&key))
+                .arg(member)
+                .arg(delta)
+                .arg(if tombstones { "1" } else { "0" })
+                .arg(slot)
+                .ignore();
+        }
+        SortedSetCopyTop(from, into, size) => {
+            pipe.cmd("ZRANGESTORE")
+                .arg(into)
+                .arg(from)
+                .arg(0)
+                .arg(size as isize - 1)
+                .arg("REV")
+                .ignore();
+        }
+        SortedSetRemoveRange(key, min, max) => match tombstones {
+            true => {
+                pipe.cmd("EVAL")
+                    .arg(REMOVE_RANGE_TOMBSTONES_SCRIPT)
+                    .arg(2)
+                    .arg(&key)
+                    .arg(tombstones_key(&key))
+                    .arg(min)
+                    .arg(max)
+                    .arg(slot)
+                    .ignore();
+            }
+            false => {
+                pipe.zrembyscore(key, min, max).ignore();
+            }
+        },
+        CounterSnapshot(key, delta, set, timestamp) => {
+            pipe.cmd("EVAL")
+                .arg(COUNTER_SNAPSHOT_SCRIPT)
+                .arg(2)
+                .arg(key)
+                .arg(set)
+                .arg(delta)
+                .arg(timestamp)
+                .ignore();
+        }
+    };
+
+    Ok(())
+}
+
+impl Worker {
+    fn commit_interval(&mut self) -> u64 {
+        match &mut self.adaptive {
+            Some(x) => x.batch_size(),
+            None => self.config.commit_every_n_blocks.unwrap_or(1).max(1),
+        }
+    }
+
+    fn write_shards(&self) -> usize {
+        self.config.write_shards.unwrap_or(1).max(1)
+    }
+
+    fn idempotent_replay(&self) -> bool {
+        self.config.idempotent_replay.unwrap_or(false)
+    }
+
+    /// True if the commands of the current block were already committed into
+    /// the db before a restart, [Worker::check_replay] made sure that the
+    /// replay is on the chain of the committed block
+    fn is_committed(&self, db: Option<i64>) -> bool {
+        match self.committed.get(&db) {
+            Some(crosscut::PointArg::Specific(slot, _)) => self.current_slot <= *slot,
+            _ => false,
+        }
+    }
+
+    /// Fails if the replayed block shows that the chain no longer goes through
+    /// the committed block of a db, so that its commands aren't skipped
+    fn check_replay(&self, point: &Point) -> Result<(), Error> {
+        for (db, committed) in self.committed.iter() {
+            if let Some(reason) = replay_divergence(committed, point) {
+                return Err(Error::storage(format!(
+                    "replay of db {:?} diverged from the committed block {}: {}",
+                    db, committed, reason
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rollback undos revert blocks that may be committed, the replay can't
+    /// tell which ones, so it stops while any db still waits for its block
+    fn check_undo(&self, db: Option<i64>) -> Result<(), Error> {
+        match self.committed.get(&db) {
+            Some(committed) => Err(Error::storage(format!(
+                "rollback during the replay of db {:?}, before its committed block {}",
+                db, committed
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops skipping the commands of the dbs whose last committed block was
+    /// reached by the replay
+    fn forget_committed(&mut self, point: &crosscut::PointArg) {
+        let slot = match point {
+            crosscut::PointArg::Specific(slot, _) => *slot,
+            crosscut::PointArg::Origin => return,
+        };
+
+        self.committed.retain(|_, committed| match committed {
+            crosscut::PointArg::Specific(x, _) => *x > slot,
+            crosscut::PointArg::Origin => false,
+        });
+    }
+
+    /// Loads the last committed block of each db that the keyspaces route to
+    fn load_committed(&mut self) -> Result<(), gasket::error::Error> {
+        let mut dbs = vec![None];
+
+        dbs.extend(
+            self.config
+                .keyspaces
+                .iter()
+                .flatten()
+                .filter_map(|x| x.db)
+                .map(Some),
+        );
+
+        for db in dbs {
+            if !self.connections.contains_key(&db) {
+                let connection = self.redis_connect(db).or_work_err()?;
+                self.connections.insert(db, connection);
+            }
+
+            let connection = self.connections.get_mut(&db).unwrap();
+            let raw: Option<String> = connection.get(COMMITTED_BLOCK_KEY).or_work_err()?;
+
+            if let Some(raw) = raw {
+                let point = raw.parse::<crosscut::PointArg>().or_work_err()?;
+                log::info!("db {:?} has commands up to block {}", db, raw);
+                self.committed.insert(db, point);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the pending commands, atomically within each db, returns true if
+    /// the cursor was written along with them
+    ///
+    /// The commands of a db stay pending until its transaction went through,
+    /// so that a failed commit is retried along with the next one. With
+    /// several writers, only the commands of the writers that failed stay
+    /// pending, the parts already written aren't applied twice.
+    fn commit(
+        &mut self,
+        point: &crosscut::PointArg,
+        persist_cursor: bool,
+    ) -> Result<bool, gasket::error::Error> {
+        let mut pending = self.pending.clone();
+
+        if self.config.coalesce_writes.unwrap_or(false) {
+            let (coalesced, merged) = super::coalesce::coalesce(pending);
+            self.coalesced_count.inc(merged as u64);
+            pending = coalesced;
+        }
+
+        let tombstones = self.config.deletion_mode.unwrap_or_default() == DeletionMode::Tombstone;
+
+        let shards = self.write_shards();
+        let mut pipes: HashMap<(Option<i64>, usize), redis::Pipeline> = HashMap::new();
+
+        for (db, command, slot) in pending {
+            let codec = self.codec(&command);
+            let command = command.map_keys(|x| self.tag_key(x));
+            let shard = super::shard::shard_of(&command, shards);
+
+            let pipe = pipes.entry((db, shard)).or_insert_with(atomic_pipe);
+
+            queue_command(pipe, command, slot, tombstones, codec)?;
+        }
+
+        if self.idempotent_replay() {
+            for pipe in pipes.values_mut() {
+                pipe.set(COMMITTED_BLOCK_KEY, point.to_string()).ignore();
+            }
+        }
+
+        if shards > 1 {
+            let (written, failure) = self.execute_sharded(pipes);
+
+            let pending = std::mem::take(&mut self.pending);
+            self.pending = pending
+                .into_iter()
+                .filter(|(db, command, _)| !written.contains(&(*db, self.shard_of(command))))
+                .collect();
+
+            if let Some(err) = failure {
+                return Err(err).or_work_err();
+            }
+
+            self.pending_blocks = 0;
+            self.commit_count.inc(1);
+
+            return Ok(false);
+        }
+
+        let cursor_key = self.cursor_key.as_ref().filter(|_| persist_cursor);
+
+        if let Some(key) = cursor_key {
+            queue_cursor(&mut pipes, key, point);
+        }
+
+        // the main db goes last, so that the cursor only moves once the other
+        // dbs have their part of the batch
+        let mut pipes: Vec<_> = pipes.into_iter().collect();
+        pipes.sort_by_key(|((db, _), _)| db.is_none());
+
+        let written = cursor_key.is_some();
+
+        for ((db, _), pipe) in pipes {
+            if !self.connections.contains_key(&db) {
+                let connection = self.redis_connect(db).or_work_err()?;
+                self.connections.insert(db, connection);
+            }
+
+            let connection = self.connections.get_mut(&db).unwrap();
+            let result = execute_pipe(&pipe, connection, self.full_retry.as_ref());
+
+            // a connection that timed out may be out of sync with the server,
+            // the next commit opens it again
+            if result.is_err() {
+                self.connections.remove(&db);
+            }
+
+            result.or_work_err()?;
+            self.pending.retain(|(x, _, _)| *x != db);
+        }
+
+        self.pending_blocks = 0;
+        self.commit_count.inc(1);
+
+        Ok(written)
+    }
+}
+
+impl Worker {
+    /// Hands the pipelines of each shard to its writer and waits for all of
+    /// them, a commit is only done once every shard executed its part
+    ///
+    /// Each pipeline is atomic on its own, so a failed writer leaves the others
+    /// applied. Returns the pipelines that went through, by db and shard,
+    /// along with the first failure.
+    fn execute_sharded(
+        &mut self,
+        pipes: HashMap<(Option<i64>, usize), redis::Pipeline>,
+    ) -> (HashSet<(Option<i64>, usize)>, Option<Error>) {
+        let mut batches: Vec<ShardBatch> = self.writers.iter().map(|_| Vec::new()).collect();
+
+        for ((db, shard), pipe) in pipes {
+            batches[shard].push((db, pipe));
+        }
+
+        let mut waiting = Vec::new();
+        let mut failure = None;
+
+        for (shard, (writer, batch)) in self.writers.iter().zip(batches).enumerate() {
+            if batch.is_empty() {
+                continue;
+            }
+
+            match writer.batches.send(batch) {
+                Ok(()) => waiting.push((shard, writer)),
+                Err(_) => failure = failure.or(Some("redis writer stopped".to_string())),
+            }
+        }
+
+        // every result is collected before failing, so that none is left
+        // behind for the next commit
+        let mut written = HashSet::new();
+
+        for (shard, writer) in waiting {
+            let (dbs, err) = match writer.results.recv() {
+                Ok(x) => x,
+                Err(_) => (vec![], Some("redis writer stopped".to_string())),
+            };
+
+            written.extend(dbs.into_iter().map(|db| (db, shard)));
+
+            if let Some(err) = err {
+                failure = failure.or(Some(err));
+            }
+        }
+
+        (written, failure.map(Error::storage))
     }
 }
 
 impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new().build()
+        let mut builder = gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_counter("commit_count", &self.commit_count)
+            .with_counter("coalesced_count", &self.coalesced_count)
+            .with_counter("skipped_count", &self.skipped_count)
+            .with_counter("oversized_count", &self.oversized_count)
+            .with_gauge("last_slot", &self.last_slot);
+
+        if let Some(adaptive) = &self.adaptive {
+            builder = builder.with_gauge("commit_batch_size", adaptive.size_gauge());
+        }
+
+        if let Some(retry) = &self.full_retry {
+            builder = builder
+                .with_gauge("storage_full", retry.waiting_gauge())
+                .with_counter("full_retries", retry.retries_counter());
+        }
+
+        builder.build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
         let msg = self.input.recv()?;
 
-        match msg.payload {
-            model::CRDTCommand::BlockStarting(_) => {
-                // TODO: start transaction
-            }
-            model::CRDTCommand::GrowOnlySetAdd(key, value) => {
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .sadd(key, value)
-                    .or_work_err()?;
-            }
-            model::CRDTCommand::TwoPhaseSetAdd(key, value) => {
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .sadd(key, value)
-                    .or_work_err()?;
-            }
-            model::CRDTCommand::TwoPhaseSetRemove(key, value) => {
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .sadd(format!("{}.ts", key), value)
-                    .or_work_err()?;
-            }
-            model::CRDTCommand::LastWriteWins(key, value, timestamp) => {
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .zadd(key, value, timestamp)
-                    .or_work_err()?;
-            }
-            model::CRDTCommand::PNCounter(key, value) => {
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .incr(key, value)
-                    .or_work_err()?;
+        let (db, command) = self.route(msg.payload);
+
+        match command {
+            model::CRDTCommand::BlockStarting(point) => {
+                self.check_replay(&point).or_work_err()?;
+
+                if let Point::Specific(slot, _) = point {
+                    self.current_slot = slot;
+                }
+
+                self.in_block = true;
             }
             model::CRDTCommand::BlockFinished(point) => {
-                let cursor_str = crosscut::PointArg::from(point).to_string();
+                let cursor = crosscut::PointArg::from(point);
+
+                self.in_block = false;
+                self.forget_committed(&cursor);
+
+                self.block_count.inc(1);
+                self.pending_blocks += 1;
+
+                if let crosscut::PointArg::Specific(slot, _) = &cursor {
+                    self.last_slot.set(*slot as i64);
+                }
+
+                let done = match (self.until, &cursor) {
+                    (Some(until), crosscut::PointArg::Specific(slot, _)) => *slot >= until,
+                    _ => false,
+                };
+
+                let interval = self.commit_interval();
+
+                let checkpoint = match &mut self.checkpointer {
+                    Some(x) => x.is_due(&cursor),
+                    None => self.pending_blocks >= interval,
+                };
+
+                let persist = done || checkpoint;
+
+                let written = match persist || self.pending_blocks >= interval {
+                    true => self.commit(&cursor, persist)?,
+                    false => false,
+                };
+
+                // the cursor only moves once the batch is committed, a crash
+                // before that replays the blocks since the last checkpoint
+                if persist {
+                    if !written {
+                        self.cursor.save(&cursor).or_work_err()?;
+                    }
+
+                    log::info!("new cursor saved {}", cursor.to_string());
+                }
+
+                if done {
+                    log::info!("reached end of requested range at {}", cursor.to_string());
+                    return Ok(WorkOutcome::Done);
+                }
+            }
+            // commands outside of a block are undos of a rollback, the blocks
+            // that they revert were applied either way
+            _ if self.in_block && self.is_committed(db) => self.skipped_count.inc(1),
+            _ if !self.in_block && self.committed.contains_key(&db) => {
+                self.check_undo(db).or_work_err()?;
+            }
+            command => {
+                let command = match self.config.max_value_bytes {
+                    Some(max) => {
+                        let action = self.config.on_oversized_value.unwrap_or_default();
+                        let (command, oversized) =
+                            super::limits::guard(command, max, action).or_work_err()?;
 
-                self.connection
-                    .as_mut()
-                    .unwrap()
-                    .set("_cursor", &cursor_str)
-                    .or_work_err()?;
+                        if oversized {
+                            self.oversized_count.inc(1);
+                        }
 
-                log::info!("new cursor saved to redis {}", &cursor_str)
+                        command
+                    }
+                    None => Some(command),
+                };
+
+                if let Some(command) = command {
+                    self.pending.push((db, command, self.current_slot));
+                }
             }
         };
 
@@ -94,9 +888,26 @@ impl gasket::runtime::Worker for Worker {
     }
 
     fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
-        let connection = self.redis_connect().or_work_err()?;
+        let connection = self.redis_connect(None).or_work_err()?;
+
+        self.connections.insert(None, connection);
 
-        self.connection = Some(connection);
+        if self.write_shards() > 1 {
+            self.writers = (0..self.write_shards())
+                .map(|i| {
+                    ShardWriter::start(
+                        i,
+                        self.config.connection_params.clone(),
+                        self.config.timeouts,
+                        self.full_retry.clone(),
+                    )
+                })
+                .collect();
+        }
+
+        if self.idempotent_replay() {
+            self.load_committed()?;
+        }
 
         Ok(())
     }
@@ -111,36 +922,929 @@ impl super::Pluggable for Worker {
         &mut self.input
     }
 
-    fn read_cursor(&self) -> Result<crosscut::Cursor, crate::Error> {
-        let mut connection = self.redis_connect().map_err(crate::Error::storage)?;
-
-        let raw: Option<String> = connection.get("_cursor").map_err(crate::Error::storage)?;
-
-        let point = match raw {
-            Some(x) => Some(crosscut::PointArg::from_str(&x)?),
-            None => None,
-        };
-
-        Ok(point)
-    }
-
     fn spawn(self, pipeline: &mut bootstrap::Pipeline) {
         pipeline.register_stage("redis", spawn_stage(self, Default::default()));
     }
 }
 
 impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(adaptive) = &self.adaptive_batching {
+            adaptive.validate()?;
+        }
+
+        if self.write_shards == Some(0) {
+            return Err(Error::config("redis write_shards should be at least 1"));
+        }
+
+        // the committed block marker relies on each commit being atomic
+        if self.write_shards.unwrap_or(1) > 1 && self.idempotent_replay.unwrap_or(false) {
+            return Err(Error::config(
+                "redis idempotent_replay can't be used with more than one write shard",
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn plugin(
         self,
-        _chain: &crosscut::ChainWellKnownInfo,
-        _intersect: &crosscut::IntersectConfig,
+        chain: &crosscut::ChainWellKnownInfo,
+        intersect: &crosscut::IntersectConfig,
+        cursor: cursor::Store,
     ) -> super::Plugin {
+        let checkpointer = self
+            .cursor_checkpoint
+            .map(|x| cursor::Checkpointer::new(x, chain));
+
+        let until = match intersect {
+            crosscut::IntersectConfig::Range(_, crosscut::PointArg::Specific(slot, _)) => {
+                Some(*slot)
+            }
+            _ => None,
+        };
+
+        let adaptive = self
+            .adaptive_batching
+            .clone()
+            .map(super::adaptive::Controller::new);
+
+        let full_retry = self.on_full.as_ref().map(super::full::Retry::new);
+
+        let cursor_key = match &cursor {
+            cursor::Store::Redis(x) if x.shares_connection(&self.connection_params) => {
+                Some(x.key().to_string())
+            }
+            _ => None,
+        };
+
         let worker = Worker {
             config: self,
-            connection: None,
+            connections: HashMap::new(),
+            writers: Vec::new(),
+            adaptive,
+            full_retry,
+            cursor,
+            cursor_key,
+            checkpointer,
+            until,
             input: Default::default(),
+            current_slot: 0,
+            in_block: false,
+            committed: HashMap::new(),
+            pending: Vec::new(),
+            pending_blocks: 0,
+            block_count: Default::default(),
+            commit_count: Default::default(),
+            coalesced_count: Default::default(),
+            skipped_count: Default::default(),
+            oversized_count: Default::default(),
+            last_slot: Default::default(),
         };
 
         super::Plugin::Redis(worker)
     }
 }
+
+/// Keys of the advisory lock expire by themselves and never go into snapshots,
+/// neither does the [COMMITTED_BLOCK_KEY] of the running daemon
+const LOCK_KEY_PREFIX: &str = "_lock.";
+
+/// Exports and imports the keyspace of a Redis db
+///
+/// Values are exported decompressed, so that snapshots don't depend on the
+/// codec of the db, and compressed again on import with the global codec of
+/// the target db.
+pub struct Snapshotter {
+    connection: redis::Connection,
+    codec: Codec,
+}
+
+/// Builds a SCAN pattern matching the keys that start with the prefix
+fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+
+        pattern.push(c);
+    }
+
+    pattern.push('*');
+    pattern
+}
+
+impl Snapshotter {
+    /// Value of the key with its members decompressed, none if the key is
+    /// missing or holds a type that snapshots don't carry
+    fn read(&mut self, key: String) -> Result<Option<super::snapshot::Entry>, Error> {
+        let kind: String = redis::cmd("TYPE")
+            .arg(&key)
+            .query(&mut self.connection)
+            .map_err(Error::storage)?;
+
+        let entry = match kind.as_str() {
+            "none" => return Ok(None),
+            "string" => {
+                let value: Vec<u8> = self.connection.get(&key).map_err(Error::storage)?;
+                super::snapshot::Entry::String(key, Compression::decode(&value)?)
+            }
+            "set" => {
+                let members: Vec<Vec<u8>> =
+                    self.connection.smembers(&key).map_err(Error::storage)?;
+                super::snapshot::Entry::Set(key, members)
+            }
+            "zset" => {
+                let members: Vec<(Vec<u8>, f64)> = self
+                    .connection
+                    .zrange_withscores(&key, 0, -1)
+                    .map_err(Error::storage)?;
+
+                let members = members
+                    .into_iter()
+                    .map(|(m, s)| Ok((Compression::decode(&m)?, s)))
+                    .collect::<Result<_, Error>>()?;
+
+                super::snapshot::Entry::SortedSet(key, members)
+            }
+            other => {
+                log::warn!("skipping key {} of unsupported type {}", key, other);
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(entry))
+    }
+}
+
+impl super::snapshot::Snapshottable for Snapshotter {
+    fn scan_prefix(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(super::snapshot::Entry) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let keys: Vec<String> = self
+            .connection
+            .scan_match(prefix_pattern(prefix))
+            .map_err(Error::storage)?
+            .filter(|k: &String| !k.starts_with(LOCK_KEY_PREFIX) && k != COMMITTED_BLOCK_KEY)
+            .collect();
+
+        for key in keys {
+            if let Some(entry) = self.read(key)? {
+                visit(entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<super::snapshot::Entry>, Error> {
+        if key.starts_with(LOCK_KEY_PREFIX) || key == COMMITTED_BLOCK_KEY {
+            return Ok(None);
+        }
+
+        self.read(key.to_string())
+    }
+
+    fn import(&mut self, entry: super::snapshot::Entry) -> Result<(), Error> {
+        let mut pipe = atomic_pipe();
+
+        match entry {
+            super::snapshot::Entry::String(key, value) => {
+                let value = self.codec.encode(&value)?;
+                pipe.del(&key).ignore().set(&key, value).ignore();
+            }
+            super::snapshot::Entry::Set(key, members) => {
+                pipe.del(&key).ignore();
+
+                if !members.is_empty() {
+                    pipe.sadd(&key, members).ignore();
+                }
+            }
+            super::snapshot::Entry::SortedSet(key, members) => {
+                pipe.del(&key).ignore();
+
+                let items: Vec<_> = members.into_iter().map(|(m, s)| (s, m)).collect();
+
+                if !items.is_empty() {
+                    pipe.zadd_multiple(&key, &items).ignore();
+                }
+            }
+        };
+
+        let _: () = pipe.query(&mut self.connection).map_err(Error::storage)?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn snapshotter(&self) -> Result<Snapshotter, Error> {
+        let connection =
+            connect(&self.timeouts, self.connection_params.as_str()).map_err(Error::storage)?;
+
+        Ok(Snapshotter {
+            connection,
+            codec: Codec::new(
+                self.compress_values.unwrap_or_default(),
+                self.compress_min_bytes,
+            ),
+        })
+    }
+
+    pub fn watcher(&self) -> Result<Watcher, Error> {
+        let connection =
+            connect(&self.timeouts, self.connection_params.as_str()).map_err(Error::storage)?;
+
+        // notifications can take any time to come
+        connection.set_read_timeout(None).map_err(Error::storage)?;
+
+        Ok(Watcher { connection })
+    }
+}
+
+/// Follows the changes of a Redis server through its keyspace notifications
+///
+/// The notifications have to be enabled on the server, eg: `CONFIG SET
+/// notify-keyspace-events KA`. They report the name of the command applied to
+/// the key (eg: `incrby`, `zadd`, `del`), not its arguments, so compressed
+/// values never show up in the changes.
+pub struct Watcher {
+    connection: redis::Connection,
+}
+
+impl super::watch::Watchable for Watcher {
+    fn watch(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(super::watch::Change) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let config: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("notify-keyspace-events")
+            .query(&mut self.connection)
+            .map_err(Error::storage)?;
+
+        if !config.get(1).map(|x| x.contains('K')).unwrap_or(false) {
+            log::warn!("keyspace notifications seem disabled, no change will be reported");
+        }
+
+        let mut pubsub = self.connection.as_pubsub();
+
+        pubsub
+            .psubscribe(format!("__keyspace@*__:{}", prefix_pattern(prefix)))
+            .map_err(Error::storage)?;
+
+        loop {
+            let msg = pubsub.get_message().map_err(Error::storage)?;
+
+            // channels look like `__keyspace@0__:{key}`
+            let key = match msg.get_channel_name().split_once("__:") {
+                Some((_, key)) => key.to_string(),
+                None => continue,
+            };
+
+            let event: String = msg.get_payload().map_err(Error::storage)?;
+
+            visit(super::watch::Change { key, event })?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::redis::{bulk, error, fake_redis_by_connection, int, ok};
+
+    #[test]
+    fn cursor_goes_in_the_transaction_of_the_main_db() {
+        let mut pipes = HashMap::new();
+        pipes.insert((Some(1), 0), atomic_pipe());
+
+        let point = crosscut::PointArg::Specific(100, hex::encode([1; 32]));
+        queue_cursor(&mut pipes, "_cursor", &point);
+
+        assert!(pipes[&(Some(1), 0)].cmd_iter().next().is_none());
+
+        let packed = pipes[&(None, 0)].get_packed_pipeline();
+        let packed = String::from_utf8_lossy(&packed);
+
+        let multi = packed.find("MULTI").unwrap();
+        let cursor = packed.find("_cursor").unwrap();
+        assert!(multi < cursor && cursor < packed.find("EXEC").unwrap());
+        assert!(packed.contains(&point.to_string()));
+    }
+
+    #[test]
+    fn prefix_patterns_match_the_prefix_literally() {
+        assert_eq!(prefix_pattern("balance."), "balance.*");
+        assert_eq!(prefix_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\*");
+        assert_eq!(prefix_pattern(""), "*");
+    }
+
+    #[test]
+    fn replay_stops_when_it_leaves_the_committed_chain() {
+        let committed = crosscut::PointArg::Specific(100, hex::encode([1; 32]));
+
+        assert_eq!(replay_divergence(&committed, &Point::Origin), None);
+        assert_eq!(
+            replay_divergence(&committed, &Point::Specific(90, vec![2; 32])),
+            None
+        );
+        assert_eq!(
+            replay_divergence(&committed, &Point::Specific(100, vec![1; 32])),
+            None
+        );
+
+        assert!(replay_divergence(&committed, &Point::Specific(100, vec![2; 32])).is_some());
+        assert!(replay_divergence(&committed, &Point::Specific(110, vec![1; 32])).is_some());
+    }
+
+    fn worker(config: serde_json::Value) -> Worker {
+        let config: Config = serde_json::from_value(config).unwrap();
+
+        let plugin = config.plugin(
+            &crosscut::ChainWellKnownInfo::mainnet(),
+            &crosscut::IntersectConfig::Tip,
+            cursor::Store::Skip,
+        );
+
+        match plugin {
+            super::super::Plugin::Redis(x) => x,
+            _ => unreachable!("redis config builds a redis plugin"),
+        }
+    }
+
+    #[test]
+    fn keyspaces_override_the_compression() {
+        let worker = worker(json!({
+            "connection_params": "redis://127.0.0.1:6379",
+            "compress_values": "gzip",
+            "compress_min_bytes": 16,
+            "keyspaces": [
+                { "prefix": "raw_cbor", "compress_values": "zstd" },
+                { "prefix": "point_by_tx", "compress_values": "none" },
+            ],
+        }));
+
+        let write = |key: &str| model::CRDTCommand::AnyWriteWins(key.into(), "x".repeat(64));
+
+        let codec = worker.codec(&write("raw_cbor.tx"));
+        assert_eq!(codec.compression, Compression::Zstd);
+        assert_eq!(codec.min_bytes, 16);
+
+        let codec = worker.codec(&write("point_by_tx.abc"));
+        assert_eq!(codec.compression, Compression::None);
+
+        let codec = worker.codec(&write("block_stats"));
+        assert_eq!(codec.compression, Compression::Gzip);
+    }
+
+    #[test]
+    fn only_large_string_values_are_compressed() {
+        let codec = Codec::new(Compression::Zstd, Some(16));
+        let value = "x".repeat(64);
+
+        let packed = |command| {
+            let mut pipe = atomic_pipe();
+            queue_command(&mut pipe, command, 10, false, codec).unwrap();
+            String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string()
+        };
+
+        let written = packed(model::CRDTCommand::AnyWriteWins("k".into(), value.clone()));
+        assert!(!written.contains(&value));
+
+        // members are matched byte for byte when removed, they stay plain
+        let members = [
+            model::CRDTCommand::LastWriteWins("k".into(), value.clone(), 10),
+            model::CRDTCommand::SortedSetAdd("k".into(), value.clone(), 1),
+            model::CRDTCommand::GrowOnlySetAdd("k".into(), value.clone()),
+        ];
+
+        for command in members {
+            assert!(packed(command).contains(&value));
+        }
+
+        let small = packed(model::CRDTCommand::AnyWriteWins("k".into(), "short".into()));
+        assert!(small.contains("short"));
+    }
+
+    #[test]
+    fn keyspaces_tag_the_keys_and_pick_the_db() {
+        let worker = worker(json!({
+            "connection_params": "redis://127.0.0.1:6379",
+            "keyspaces": [
+                { "prefix": "richest_addresses", "hash_tag": "richest" },
+                { "prefix": "richest_addresses.by_epoch", "hash_tag": "epochs", "db": 3 },
+                { "prefix": "point_by_tx", "db": 2 },
+            ],
+        }));
+
+        let produced = |command: model::CRDTCommand| {
+            let (db, command) = worker.route(command);
+            let command = command.map_keys(|x| worker.tag_key(x));
+
+            let mut pipe = atomic_pipe();
+            queue_command(
+                &mut pipe,
+                command,
+                10,
+                false,
+                Codec::new(Compression::None, None),
+            )
+            .unwrap();
+            let packed = String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string();
+
+            (db, packed)
+        };
+
+        let (db, packed) = produced(model::CRDTCommand::SortedSetAdd(
+            "richest_addresses".into(),
+            "addr1".into(),
+            5,
+        ));
+        assert_eq!(db, None);
+        assert!(packed.contains("{richest}richest_addresses"));
+
+        // the longest prefix wins
+        let (db, packed) = produced(model::CRDTCommand::AnyWriteWins(
+            "richest_addresses.by_epoch.300".into(),
+            "x".into(),
+        ));
+        assert_eq!(db, Some(3));
+        assert!(packed.contains("{epochs}richest_addresses.by_epoch.300"));
+
+        let (db, packed) = produced(model::CRDTCommand::GrowOnlySetAdd(
+            "point_by_tx.abc".into(),
+            "x".into(),
+        ));
+        assert_eq!(db, Some(2));
+        assert!(packed.contains("point_by_tx.abc") && !packed.contains('{'));
+
+        let (db, packed) = produced(model::CRDTCommand::PNCounter(
+            "block_stats".into(),
+            "1".into(),
+        ));
+        assert_eq!(db, None);
+        assert!(packed.contains("block_stats") && !packed.contains('{'));
+    }
+
+    #[test]
+    fn tombstone_mode_marks_removals_instead_of_deleting() {
+        let packed = |command, tombstones| {
+            let mut pipe = atomic_pipe();
+            let codec = Codec::new(Compression::None, None);
+            queue_command(&mut pipe, command, 120, tombstones, codec).unwrap();
+            String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string()
+        };
+
+        // what a spend removes: the output from the set of its address and
+        // the value kept for the output itself
+        let spent_member = || model::CRDTCommand::SetRemove("addr1".into(), "abc#0".into());
+        let spent_value = || model::CRDTCommand::AnyWriteWinsRemove("utxo.abc#0".into());
+
+        let member = packed(spent_member(), true);
+        assert!(member.contains("SREM"));
+        assert!(member.contains("ZADD") && member.contains("addr1.tombstones"));
+        assert!(member.contains("$3\r\n120\r\n"));
+
+        let value = packed(spent_value(), true);
+        assert!(!value.contains("DEL"));
+        assert!(value.contains(&tombstone_marker(120)));
+
+        let member = packed(spent_member(), false);
+        assert!(!member.contains("tombstones"));
+
+        let value = packed(spent_value(), false);
+        assert!(value.contains("DEL") && !value.contains("tombstone"));
+    }
+
+    /// Values and transactions of a [fake_server]
+    #[derive(Default)]
+    struct FakeDb {
+        values: HashMap<String, String>,
+        /// Commands of the open MULTI block of each connection
+        queued: HashMap<usize, Vec<Vec<String>>>,
+        execs: usize,
+
+        /// Hangs on EXEC for this long, then drops the transaction
+        hang: Option<std::time::Duration>,
+
+        /// Drops the transactions touching this key
+        refused: Option<String>,
+
+        /// Refuses every transaction as out of memory while set
+        full: bool,
+    }
+
+    type SharedDb = std::sync::Arc<std::sync::Mutex<FakeDb>>;
+
+    fn fake_apply(values: &mut HashMap<String, String>, args: &[String]) -> String {
+        match args[0].to_uppercase().as_str() {
+            "GET" => bulk(values.get(&args[1]).map(String::as_str)),
+            "SET" => {
+                values.insert(args[1].clone(), args[2].clone());
+                ok()
+            }
+            "INCRBY" => {
+                let value = values.entry(args[1].clone()).or_insert_with(|| "0".into());
+                let next = value.parse::<i64>().unwrap() + args[2].parse::<i64>().unwrap();
+                *value = next.to_string();
+                int(next)
+            }
+            _ => error("ERR unknown command"),
+        }
+    }
+
+    /// A server holding strings and counters that applies MULTI blocks on EXEC
+    fn fake_server() -> (String, SharedDb) {
+        let db = SharedDb::default();
+        let shared = db.clone();
+
+        let address = fake_redis_by_connection(move |connection, args| {
+            let mut db = shared.lock().unwrap();
+            let db = &mut *db;
+
+            match (
+                args[0].to_uppercase().as_str(),
+                db.queued.get_mut(&connection),
+            ) {
+                ("MULTI", _) => {
+                    db.queued.insert(connection, Vec::new());
+                    ok()
+                }
+                ("EXEC", Some(_)) if db.hang.is_some() => {
+                    db.queued.remove(&connection);
+                    std::thread::sleep(db.hang.unwrap());
+                    error("ERR hung")
+                }
+                ("EXEC", Some(_)) if db.full => {
+                    db.queued.remove(&connection);
+                    error("OOM command not allowed when used memory > 'maxmemory'.")
+                }
+                ("EXEC", Some(queued))
+                    if queued
+                        .iter()
+                        .any(|x| x.get(1).is_some() && x.get(1) == db.refused.as_ref()) =>
+                {
+                    db.queued.remove(&connection);
+                    error("ERR refused")
+                }
+                ("EXEC", Some(_)) => {
+                    let queued = db.queued.remove(&connection).unwrap();
+                    db.execs += 1;
+
+                    let replies: String = queued
+                        .iter()
+                        .map(|x| fake_apply(&mut db.values, x))
+                        .collect();
+
+                    format!("*{}\r\n{}", queued.len(), replies)
+                }
+                (_, Some(queued)) => {
+                    queued.push(args.to_vec());
+                    "+QUEUED\r\n".to_string()
+                }
+                (_, None) => fake_apply(&mut db.values, args),
+            }
+        });
+
+        (address, db)
+    }
+
+    fn block_point(number: u64) -> Point {
+        Point::Specific(number * 20, vec![number as u8; 32])
+    }
+
+    /// Feeds the worker a block that increments a counter
+    fn counted_block(
+        worker: &mut Worker,
+        port: &mut gasket::messaging::OutputPort<model::CRDTCommand>,
+        number: u64,
+    ) {
+        use gasket::runtime::Worker as _;
+
+        let commands = [
+            model::CRDTCommand::BlockStarting(block_point(number)),
+            model::CRDTCommand::PNCounter("blocks".into(), "1".into()),
+            model::CRDTCommand::BlockFinished(block_point(number)),
+        ];
+
+        for command in commands {
+            port.send(gasket::messaging::Message::from(command))
+                .unwrap();
+            worker.work().unwrap();
+        }
+    }
+
+    fn batching_worker(
+        address: &str,
+        commit_every_n_blocks: u64,
+    ) -> (Worker, gasket::messaging::OutputPort<model::CRDTCommand>) {
+        let config = json!({
+            "connection_params": address,
+            "commit_every_n_blocks": commit_every_n_blocks,
+        });
+
+        let cursor = cursor::redis::Config {
+            connection_params: address.to_string(),
+            key: None,
+            timeouts: Default::default(),
+        }
+        .store();
+
+        bootstrapped_worker(config, cursor)
+    }
+
+    fn bootstrapped_worker(
+        config: serde_json::Value,
+        cursor: cursor::Store,
+    ) -> (Worker, gasket::messaging::OutputPort<model::CRDTCommand>) {
+        use gasket::runtime::Worker as _;
+
+        let config: Config = serde_json::from_value(config).unwrap();
+
+        let mut worker = match config.plugin(
+            &crosscut::ChainWellKnownInfo::mainnet(),
+            &crosscut::IntersectConfig::Tip,
+            cursor,
+        ) {
+            super::super::Plugin::Redis(x) => x,
+            _ => unreachable!("redis config builds a redis plugin"),
+        };
+
+        worker.bootstrap().unwrap();
+
+        let mut port = gasket::messaging::OutputPort::default();
+        gasket::messaging::connect_ports(
+            &mut port,
+            super::super::Pluggable::borrow_input_port(&mut worker),
+            100,
+        );
+
+        (worker, port)
+    }
+
+    #[test]
+    fn cursor_moves_with_each_batch_and_a_crash_replays_the_open_one() {
+        let (address, db) = fake_server();
+        let cursor = |db: &SharedDb| db.lock().unwrap().values.get("_cursor").cloned();
+        let point = |number| Some(crosscut::PointArg::from(block_point(number)).to_string());
+
+        let (mut worker, mut port) = batching_worker(&address, 5);
+
+        for number in 1..=7 {
+            counted_block(&mut worker, &mut port, number);
+
+            match number {
+                1..=4 => assert_eq!(cursor(&db), None),
+                _ => assert_eq!(cursor(&db), point(5)),
+            }
+        }
+
+        // a crash loses blocks 6 and 7, the cursor and their data stay at 5
+        drop(worker);
+        assert_eq!(db.lock().unwrap().values.get("blocks"), Some(&"5".into()));
+
+        let (mut worker, mut port) = batching_worker(&address, 5);
+
+        for number in 6..=10 {
+            counted_block(&mut worker, &mut port, number);
+        }
+
+        let db = db.lock().unwrap();
+        assert_eq!(db.values.get("blocks"), Some(&"10".into()));
+        assert_eq!(db.values.get("_cursor").cloned(), point(10));
+        assert_eq!(db.execs, 2);
+    }
+
+    #[test]
+    fn replay_after_a_crash_skips_the_committed_blocks() {
+        let (address, db) = fake_server();
+
+        // the cursor lives elsewhere and didn't move before the crash
+        let replay_worker = || {
+            let config = json!({
+                "connection_params": address,
+                "idempotent_replay": true,
+            });
+
+            bootstrapped_worker(config, cursor::Store::Skip)
+        };
+
+        let (mut worker, mut port) = replay_worker();
+
+        for number in 1..=3 {
+            counted_block(&mut worker, &mut port, number);
+        }
+
+        drop(worker);
+
+        let committed = crosscut::PointArg::from(block_point(3)).to_string();
+
+        {
+            let db = db.lock().unwrap();
+            assert_eq!(db.values.get("blocks"), Some(&"3".into()));
+            assert_eq!(db.values.get(COMMITTED_BLOCK_KEY), Some(&committed));
+        }
+
+        // the restart replays from block 2, only block 4 increments again
+        let (mut worker, mut port) = replay_worker();
+
+        for number in 2..=4 {
+            counted_block(&mut worker, &mut port, number);
+        }
+
+        drop(worker);
+
+        let db = db.lock().unwrap();
+        assert_eq!(db.values.get("blocks"), Some(&"4".into()));
+        assert_eq!(
+            db.values.get(COMMITTED_BLOCK_KEY).cloned(),
+            Some(crosscut::PointArg::from(block_point(4)).to_string())
+        );
+    }
+
+    #[test]
+    fn oversized_values_are_skipped_before_the_commit() {
+        let (address, db) = fake_server();
+
+        let config = json!({
+            "connection_params": address,
+            "max_value_bytes": 4,
+            "on_oversized_value": "skip",
+        });
+
+        let (mut worker, mut port) = bootstrapped_worker(config, cursor::Store::Skip);
+
+        let commands = [
+            model::CRDTCommand::BlockStarting(block_point(1)),
+            model::CRDTCommand::AnyWriteWins("small".into(), "abcd".into()),
+            model::CRDTCommand::AnyWriteWins("large".into(), "abcde".into()),
+            model::CRDTCommand::BlockFinished(block_point(1)),
+        ];
+
+        for command in commands {
+            use gasket::runtime::Worker as _;
+
+            port.send(gasket::messaging::Message::from(command))
+                .unwrap();
+            worker.work().unwrap();
+        }
+
+        let db = db.lock().unwrap();
+        assert_eq!(db.values.get("small"), Some(&"abcd".into()));
+        assert_eq!(db.values.get("large"), None);
+    }
+
+    #[test]
+    fn timed_out_commit_keeps_its_commands_for_the_next_one() {
+        use gasket::runtime::Worker as _;
+
+        let (address, db) = fake_server();
+        db.lock().unwrap().hang = Some(std::time::Duration::from_millis(500));
+
+        let config = json!({
+            "connection_params": address,
+            "operation_timeout_ms": 100,
+        });
+
+        let (mut worker, mut port) = bootstrapped_worker(config, cursor::Store::Skip);
+
+        let commands = [
+            model::CRDTCommand::BlockStarting(block_point(1)),
+            model::CRDTCommand::PNCounter("blocks".into(), "1".into()),
+            model::CRDTCommand::BlockFinished(block_point(1)),
+        ];
+
+        let results: Vec<_> = commands
+            .into_iter()
+            .map(|command| {
+                port.send(gasket::messaging::Message::from(command))
+                    .unwrap();
+                worker.work().is_ok()
+            })
+            .collect();
+
+        assert_eq!(results, vec![true, true, false]);
+        assert_eq!(worker.pending.len(), 1);
+
+        // the server recovers, the next commit takes both blocks
+        db.lock().unwrap().hang = None;
+        counted_block(&mut worker, &mut port, 2);
+
+        assert!(worker.pending.is_empty());
+
+        let db = db.lock().unwrap();
+        assert_eq!(db.values.get("blocks"), Some(&"2".into()));
+        assert_eq!(db.execs, 1);
+    }
+
+    #[test]
+    fn sharded_commit_waits_for_every_writer_and_retries_the_failed_ones() {
+        use gasket::runtime::Worker as _;
+
+        let (address, db) = fake_server();
+
+        let config = json!({
+            "connection_params": address,
+            "write_shards": 2,
+        });
+
+        let (mut worker, mut port) = bootstrapped_worker(config, cursor::Store::Skip);
+
+        // two keys of different writers
+        let counter = |key: &str| model::CRDTCommand::PNCounter(key.into(), "1".into());
+        let first = "counter.0".to_string();
+        let second = (1..)
+            .map(|x| format!("counter.{}", x))
+            .find(|x| worker.shard_of(&counter(x)) != worker.shard_of(&counter(&first)))
+            .unwrap();
+
+        let mut commit = |number| {
+            let commands = [
+                model::CRDTCommand::BlockStarting(block_point(number)),
+                counter(&first),
+                counter(&second),
+                model::CRDTCommand::BlockFinished(block_point(number)),
+            ];
+
+            commands
+                .into_iter()
+                .map(|command| {
+                    port.send(gasket::messaging::Message::from(command))
+                        .unwrap();
+                    worker.work().is_ok()
+                })
+                .last()
+                .unwrap()
+        };
+
+        let value = |key: &str| db.lock().unwrap().values.get(key).cloned();
+
+        // the commit returns once both writers are done
+        assert!(commit(1));
+        assert_eq!(value(&first), Some("1".into()));
+        assert_eq!(value(&second), Some("1".into()));
+
+        // one writer fails, its part stays pending while the other is applied
+        db.lock().unwrap().refused = Some(second.clone());
+        assert!(!commit(2));
+        assert_eq!(value(&first), Some("2".into()));
+        assert_eq!(value(&second), Some("1".into()));
+
+        db.lock().unwrap().refused = None;
+        assert!(commit(3));
+        assert_eq!(value(&first), Some("3".into()));
+        assert_eq!(value(&second), Some("3".into()));
+    }
+
+    #[test]
+    fn commits_pause_while_the_server_is_full_and_resume_after() {
+        let (address, db) = fake_server();
+
+        let config = json!({
+            "connection_params": address,
+            "on_full": { "retry_interval_ms": 20 },
+        });
+
+        let (mut worker, mut port) = bootstrapped_worker(config, cursor::Store::Skip);
+        let retry = worker.full_retry.clone().unwrap();
+
+        counted_block(&mut worker, &mut port, 1);
+
+        db.lock().unwrap().full = true;
+
+        let feeding = std::thread::spawn(move || {
+            counted_block(&mut worker, &mut port, 2);
+            counted_block(&mut worker, &mut port, 3);
+        });
+
+        let started = std::time::Instant::now();
+
+        while retry.retries_counter().get() < 3 {
+            assert!(started.elapsed().as_secs() < 10, "commit was never retried");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // the block waits, nothing after it is applied
+        assert_eq!(retry.waiting_gauge().get(), 1);
+        assert!(!feeding.is_finished());
+        assert_eq!(
+            db.lock().unwrap().values.get("blocks").cloned(),
+            Some("1".into())
+        );
+
+        db.lock().unwrap().full = false;
+        feeding.join().unwrap();
+
+        assert_eq!(retry.waiting_gauge().get(), 0);
+
+        let db = db.lock().unwrap();
+        assert_eq!(db.values.get("blocks").cloned(), Some("3".into()));
+        assert_eq!(db.execs, 3);
+    }
+}
@@ -0,0 +1,92 @@
+//! Routing of storage ops to parallel writers
+//!
+//! Each op goes to the writer picked by hashing its main key, so that all of
+//! the ops on a key are executed by the same writer, in order. Ops touching
+//! several keys (eg: copying the top of a sorted set) follow the key they
+//! read from, which is where the ops they depend on are written.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use crate::model::CRDTCommand;
+
+/// The key that decides the writer of the command, none for block markers
+pub fn shard_key(command: &CRDTCommand) -> Option<&str> {
+    use CRDTCommand::*;
+
+    match command {
+        BlockStarting(_) | BlockFinished(_) => None,
+        TwoPhaseSetAdd(key, _)
+        | TwoPhaseSetRemove(key, _)
+        | GrowOnlySetAdd(key, _)
+        | SetRemove(key, _)
+        | HyperLogLogAdd(key, _)
+        | LastWriteWins(key, _, _)
+        | AnyWriteWins(key, _)
+        | AnyWriteWinsRemove(key)
+        | ConditionalWrite(key, _, _)
+        | PNCounter(key, _)
+        | SortedSetAdd(key, _, _)
+        | SortedSetCopyTop(key, _, _)
+        | SortedSetRemoveRange(key, _, _)
+        | CounterSnapshot(key, _, _, _) => Some(key),
+    }
+}
+
+/// Index of the writer, out of `shards`, that executes the command
+pub fn shard_of(command: &CRDTCommand, shards: usize) -> usize {
+    if shards <= 1 {
+        return 0;
+    }
+
+    let key = match shard_key(command) {
+        Some(x) => x,
+        None => return 0,
+    };
+
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+
+    #[test]
+    fn ops_of_a_key_always_go_to_the_same_writer() {
+        let ops = |key: &str| {
+            vec![
+                CRDTCommand::PNCounter(key.into(), "1".into()),
+                CRDTCommand::AnyWriteWins(key.into(), "value".into()),
+                CRDTCommand::SortedSetAdd(key.into(), "member".into(), 1),
+                CRDTCommand::SortedSetCopyTop(key.into(), "other".into(), 10),
+                CRDTCommand::AnyWriteWinsRemove(key.into()),
+            ]
+        };
+
+        let mut used = std::collections::HashSet::new();
+
+        for index in 0..32 {
+            let key = format!("key.{}", index);
+            let shards: Vec<_> = ops(&key).iter().map(|x| shard_of(x, 4)).collect();
+
+            assert!(shards.iter().all(|x| *x == shards[0] && *x < 4));
+            used.insert(shards[0]);
+
+            // a single writer takes everything
+            assert!(ops(&key).iter().all(|x| shard_of(x, 1) == 0));
+        }
+
+        assert!(used.len() > 1);
+
+        let marker = CRDTCommand::BlockFinished(Point::Origin);
+        assert_eq!(shard_key(&marker), None);
+        assert_eq!(shard_of(&marker, 4), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! Portable dump of the whole keyspace of a storage backend
+//!
+//! A snapshot is a sequence of records, each one a CBOR document prefixed by
+//! its length as a big-endian u32. The first record is a header carrying the
+//! format version and the cursor at the time of the export, every other record
+//! is a single key with its full value.
+
+use std::io::{Read, Write};
+
+use pallas::codec::minicbor::{data::Type, Decoder, Encoder};
+
+use crate::{crosscut, Error};
+
+const MAGIC: &str = "scrolls-snapshot";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    String(String, Vec<u8>),
+    Set(String, Vec<Vec<u8>>),
+    SortedSet(String, Vec<(Vec<u8>, f64)>),
+}
+
+impl Entry {
+    pub fn key(&self) -> &str {
+        match self {
+            Entry::String(key, _) | Entry::Set(key, _) | Entry::SortedSet(key, _) => key,
+        }
+    }
+}
+
+/// Backends able to dump and restore their keyspace
+pub trait Snapshottable {
+    /// Visits every key that starts with the prefix, along with its value
+    fn scan_prefix(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(Entry) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+
+    /// Value of a single key, none if it's missing. Goes through the keys that
+    /// start with it unless the backend can look it up directly.
+    fn get(&mut self, key: &str) -> Result<Option<Entry>, Error> {
+        let mut found = None;
+
+        self.scan_prefix(key, &mut |entry| {
+            if entry.key() == key {
+                found = Some(entry);
+            }
+
+            Ok(())
+        })?;
+
+        Ok(found)
+    }
+
+    fn import(&mut self, entry: Entry) -> Result<(), Error>;
+
+    fn export(&mut self, visit: &mut dyn FnMut(Entry) -> Result<(), Error>) -> Result<(), Error> {
+        self.scan_prefix("", visit)
+    }
+}
+
+fn cbor_error(err: impl std::fmt::Display) -> Error {
+    Error::message(format!("invalid snapshot record: {}", err))
+}
+
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, cursor: &crosscut::Cursor) -> Result<Self, Error> {
+        let mut writer = Writer { inner };
+
+        let mut buf = Vec::new();
+        let mut e = Encoder::new(&mut buf);
+        e.array(3).map_err(cbor_error)?;
+        e.str(MAGIC).map_err(cbor_error)?;
+        e.u8(VERSION).map_err(cbor_error)?;
+
+        match cursor {
+            Some(x) => e.str(&x.to_string()).map_err(cbor_error)?,
+            None => e.null().map_err(cbor_error)?,
+        };
+
+        writer.write_record(&buf)?;
+
+        Ok(writer)
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> Result<(), Error> {
+        let len = u32::try_from(record.len())
+            .map_err(|_| Error::message("snapshot record is too large"))?;
+
+        self.inner
+            .write_all(&len.to_be_bytes())
+            .and_then(|_| self.inner.write_all(record))
+            .map_err(Error::storage)
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let mut e = Encoder::new(&mut buf);
+        e.array(3).map_err(cbor_error)?;
+
+        match entry {
+            Entry::String(key, value) => {
+                e.u8(0).map_err(cbor_error)?;
+                e.str(key).map_err(cbor_error)?;
+                e.bytes(value).map_err(cbor_error)?;
+            }
+            Entry::Set(key, members) => {
+                e.u8(1).map_err(cbor_error)?;
+                e.str(key).map_err(cbor_error)?;
+                e.array(members.len() as u64).map_err(cbor_error)?;
+
+                for member in members {
+                    e.bytes(member).map_err(cbor_error)?;
+                }
+            }
+            Entry::SortedSet(key, members) => {
+                e.u8(2).map_err(cbor_error)?;
+                e.str(key).map_err(cbor_error)?;
+                e.array(members.len() as u64).map_err(cbor_error)?;
+
+                for (member, score) in members {
+                    e.array(2).map_err(cbor_error)?;
+                    e.bytes(member).map_err(cbor_error)?;
+                    e.f64(*score).map_err(cbor_error)?;
+                }
+            }
+        };
+
+        self.write_record(&buf)
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.flush().map_err(Error::storage)?;
+        Ok(self.inner)
+    }
+}
+
+pub struct Reader<R: Read> {
+    inner: R,
+    pub cursor: crosscut::Cursor,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Result<Self, Error> {
+        let mut reader = Reader {
+            inner,
+            cursor: None,
+        };
+
+        let header = reader
+            .read_record()?
+            .ok_or_else(|| Error::message("snapshot is empty"))?;
+
+        let mut d = Decoder::new(&header);
+        d.array().map_err(cbor_error)?;
+
+        if d.str().map_err(cbor_error)? != MAGIC {
+            return Err(Error::message("file is not a scrolls snapshot"));
+        }
+
+        let version = d.u8().map_err(cbor_error)?;
+
+        if version != VERSION {
+            return Err(Error::message(format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+
+        reader.cursor = match d.datatype().map_err(cbor_error)? {
+            Type::Null => None,
+            _ => {
+                let raw = d.str().map_err(cbor_error)?;
+                Some(raw.parse()?)
+            }
+        };
+
+        Ok(reader)
+    }
+
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut len = [0u8; 4];
+
+        match self.inner.read_exact(&mut len) {
+            Ok(_) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(Error::storage(err)),
+        };
+
+        let mut record = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.inner.read_exact(&mut record).map_err(Error::storage)?;
+
+        Ok(Some(record))
+    }
+
+    pub fn read(&mut self) -> Result<Option<Entry>, Error> {
+        let record = match self.read_record()? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let mut d = Decoder::new(&record);
+        d.array().map_err(cbor_error)?;
+
+        let kind = d.u8().map_err(cbor_error)?;
+        let key = d.str().map_err(cbor_error)?.to_string();
+
+        let entry = match kind {
+            0 => Entry::String(key, d.bytes().map_err(cbor_error)?.to_vec()),
+            1 => {
+                let len = d.array().map_err(cbor_error)?.unwrap_or_default();
+                let mut members = Vec::new();
+
+                for _ in 0..len {
+                    members.push(d.bytes().map_err(cbor_error)?.to_vec());
+                }
+
+                Entry::Set(key, members)
+            }
+            2 => {
+                let len = d.array().map_err(cbor_error)?.unwrap_or_default();
+                let mut members = Vec::new();
+
+                for _ in 0..len {
+                    d.array().map_err(cbor_error)?;
+                    let member = d.bytes().map_err(cbor_error)?.to_vec();
+                    let score = d.f64().map_err(cbor_error)?;
+                    members.push((member, score));
+                }
+
+                Entry::SortedSet(key, members)
+            }
+            x => return Err(Error::message(format!("unknown snapshot entry type {}", x))),
+        };
+
+        Ok(Some(entry))
+    }
+}
@@ -0,0 +1,36 @@
+//! Timeouts of the connections to the backends, so that a hung server
+//! surfaces as an error, which the retry policy of the stage then handles,
+//! instead of stalling the pipeline
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5_000;
+
+const DEFAULT_OPERATION_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// Defaults to 5 seconds
+    pub connect_timeout_ms: Option<u64>,
+
+    /// Applies to each request to the backend, defaults to 30 seconds
+    pub operation_timeout_ms: Option<u64>,
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(
+            self.connect_timeout_ms
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS),
+        )
+    }
+
+    pub fn operation(&self) -> Duration {
+        Duration::from_millis(
+            self.operation_timeout_ms
+                .unwrap_or(DEFAULT_OPERATION_TIMEOUT_MS),
+        )
+    }
+}
@@ -0,0 +1,25 @@
+//! Live feed of the changes applied to a storage, used to follow the output of
+//! the reducers while the daemon is running
+
+use crate::Error;
+
+/// A change observed on a key of the storage
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub key: String,
+
+    /// What happened to the key, as reported by the backend (eg: a Redis
+    /// command name or the JSON op published to Kafka)
+    pub event: String,
+}
+
+/// Backends able to report the changes made to their keys by another process
+pub trait Watchable {
+    /// Visits the changes of the keys that start with the prefix as they
+    /// happen, until the visitor or the backend fails
+    fn watch(
+        &mut self,
+        prefix: &str,
+        visit: &mut dyn FnMut(Change) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+}
@@ -0,0 +1,559 @@
+use bech32::FromBase32;
+use pallas::{
+    codec::{
+        minicbor::{self, bytes::ByteVec},
+        utils::{CborWrap, EmptyMap, KeyValuePairs, MaybeIndefArray},
+    },
+    crypto::hash::Hash,
+    ledger::primitives::{alonzo, byron, Fragment},
+};
+
+use crate::model;
+
+/// Slot of the built blocks unless set, well into the Shelley eras of mainnet
+pub const DEFAULT_SLOT: u64 = 50_000_000;
+
+/// Era tag of the built blocks, Alonzo
+const ALONZO_ERA: u16 = 5;
+
+/// Raw bytes of an address given as bech32 or hex, without any network check
+pub fn address_bytes(raw: &str) -> Vec<u8> {
+    match raw.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => hex::decode(raw).expect("valid hex address"),
+        false => {
+            let (_, data, _) = bech32::decode(raw).expect("valid bech32 address");
+            Vec::<u8>::from_base32(&data).expect("valid bech32 data")
+        }
+    }
+}
+
+/// Hex of a mainnet enterprise address whose key hash repeats the seed
+pub fn address(seed: u8) -> String {
+    let mut bytes = vec![0x61];
+    bytes.extend([seed; 28]);
+    hex::encode(bytes)
+}
+
+/// Hex of a mainnet base address with the payment and stake key hashes
+/// repeating their seeds
+pub fn base_address(payment: u8, stake: u8) -> String {
+    let mut bytes = vec![0x01];
+    bytes.extend([payment; 28]);
+    bytes.extend([stake; 28]);
+    hex::encode(bytes)
+}
+
+/// A 32-byte hash repeating the seed
+pub fn hash(seed: u8) -> Hash<32> {
+    Hash::from([seed; 32])
+}
+
+/// Delegation of the stake key hash repeating the seed to the pool whose id
+/// repeats its seed
+pub fn delegation(stake: u8, pool: u8) -> alonzo::Certificate {
+    alonzo::Certificate::StakeDelegation(
+        alonzo::StakeCredential::AddrKeyhash(Hash::from([stake; 28])),
+        Hash::from([pool; 28]),
+    )
+}
+
+/// Registration of the pool whose id repeats the seed, with its metadata
+/// anchored at the url
+pub fn pool_registration(pool: u8, metadata_url: Option<&str>) -> alonzo::Certificate {
+    alonzo::Certificate::PoolRegistration {
+        operator: Hash::from([pool; 28]),
+        vrf_keyhash: hash(pool),
+        pledge: 0,
+        cost: 340_000_000,
+        margin: alonzo::RationalNumber {
+            numerator: 0,
+            denominator: 1,
+        },
+        reward_account: vec![0xe1; 29].into(),
+        pool_owners: MaybeIndefArray::Def(vec![]),
+        relays: MaybeIndefArray::Def(vec![]),
+        pool_metadata: metadata_url.map(|url| alonzo::PoolMetadata {
+            url: url.to_string(),
+            hash: hash(pool),
+        }),
+    }
+}
+
+/// Hash of the tx at the index of a built block
+pub fn tx_hash(block: &model::MultiEraBlock, index: usize) -> Hash<32> {
+    match block {
+        model::MultiEraBlock::AlonzoCompatible(x) => x.1.transaction_bodies[index].to_hash(),
+        model::MultiEraBlock::Byron(_) => panic!("built blocks are alonzo-compatible"),
+    }
+}
+
+/// A byron epoch boundary block opening the epoch, it has no txs and the
+/// slot of the first block of the epoch
+pub fn epoch_boundary(epoch: u64) -> model::MultiEraBlock {
+    let header = byron::EbbHead {
+        protocol_magic: 764824073,
+        prev_block: hash(0),
+        body_proof: hash(0),
+        consensus_data: byron::EbbCons {
+            epoch_id: epoch,
+            difficulty: MaybeIndefArray::Def(vec![epoch * 21_600]),
+        },
+        extra_data: (EmptyMap,),
+    };
+
+    model::MultiEraBlock::Byron(byron::Block::EbBlock(byron::EbBlock {
+        header,
+        body: MaybeIndefArray::Def(vec![]),
+        extra: MaybeIndefArray::Def(vec![]),
+    }))
+}
+
+/// Slots per epoch of the byron era on mainnet
+const BYRON_EPOCH_SLOTS: u64 = 21_600;
+
+/// Decodes the parts of byron blocks that can't be built, their fields are
+/// private
+fn decode_part<T: for<'b> minicbor::Decode<'b>>(cbor: &[u8]) -> T {
+    minicbor::decode(cbor).expect("valid cbor of a byron block part")
+}
+
+/// A byron main block at the absolute slot with a tx per amount, each one
+/// spending an input of its own and locking the amount at a bootstrap address
+pub fn byron_block(slot: u64, amounts: &[u64]) -> model::MultiEraBlock {
+    let address = || byron::Address {
+        payload: CborWrap(byron::AddressPayload {
+            root: Hash::from([0xb7; 28]),
+            // empty map
+            attributes: decode_part(&[0xa0]),
+            addrtype: byron::AddrType::PubKey,
+        }),
+        crc: 0,
+    };
+
+    let txs = amounts
+        .iter()
+        .enumerate()
+        .map(|(idx, amount)| byron::TxPayload {
+            transaction: byron::Tx {
+                inputs: MaybeIndefArray::Def(vec![byron::TxIn::Variant0(CborWrap((
+                    hash(idx as u8 + 1),
+                    0,
+                )))]),
+                outputs: MaybeIndefArray::Def(vec![byron::TxOut {
+                    address: address(),
+                    amount: *amount,
+                }]),
+                attributes: EmptyMap,
+            },
+            witness: MaybeIndefArray::Def(vec![]),
+        })
+        .collect();
+
+    let header = byron::BlockHead {
+        protocol_magic: 764824073,
+        prev_block: hash(0),
+        body_proof: byron::BlockProof {
+            tx_proof: (amounts.len() as u32, hash(0), hash(0)),
+            ssc_proof: byron::SscProof::Variant3(hash(0)),
+            dlg_proof: hash(0),
+            upd_proof: hash(0),
+        },
+        consensus_data: byron::BlockCons(
+            byron::SlotId {
+                epoch: slot / BYRON_EPOCH_SLOTS,
+                slot: slot % BYRON_EPOCH_SLOTS,
+            },
+            vec![0; 64].into(),
+            MaybeIndefArray::Def(vec![slot]),
+            byron::BlockSig::Signature(vec![0; 64].into()),
+        ),
+        extra_data: byron::BlockHeadEx {
+            block_version: (0, 2, 0),
+            software_version: ("cardano-sl".to_string(), 1),
+            attributes: Some(EmptyMap),
+            extra_proof: hash(0),
+        },
+    };
+
+    let body = byron::BlockBody {
+        tx_payload: MaybeIndefArray::Def(txs),
+        // no certificates, as a tag 258 set
+        ssc_payload: byron::Ssc::Variant3(decode_part(&[0xd9, 0x01, 0x02, 0x80])),
+        dlg_payload: MaybeIndefArray::Def(vec![]),
+        upd_payload: byron::Up {
+            // empty array
+            proposal: decode_part(&[0x80]),
+            votes: MaybeIndefArray::Def(vec![]),
+        },
+    };
+
+    model::MultiEraBlock::Byron(byron::Block::MainBlock(byron::MainBlock {
+        header,
+        body,
+        extra: MaybeIndefArray::Def(vec![]),
+    }))
+}
+
+/// Builds the body of a synthetic tx
+#[derive(Default)]
+pub struct TxBuilder {
+    inputs: Vec<alonzo::TransactionInput>,
+    collateral: Vec<alonzo::TransactionInput>,
+    failed: bool,
+    outputs: Vec<alonzo::TransactionOutput>,
+    fee: u64,
+    ttl: Option<u64>,
+    validity_start: Option<u64>,
+    certs: Vec<alonzo::Certificate>,
+    withdrawals: Vec<(ByteVec, u64)>,
+    mint: Vec<(Vec<u8>, Vec<u8>, i64)>,
+    metadata: Vec<(u64, String)>,
+    redeemers: Vec<alonzo::Redeemer>,
+}
+
+impl TxBuilder {
+    /// Spends the output at the index of a tx
+    pub fn input(mut self, tx: Hash<32>, index: u64) -> Self {
+        self.inputs.push(alonzo::TransactionInput {
+            transaction_id: tx,
+            index,
+        });
+
+        self
+    }
+
+    /// Puts up the output at the index of a tx as collateral
+    pub fn collateral(mut self, tx: Hash<32>, index: u64) -> Self {
+        self.collateral.push(alonzo::TransactionInput {
+            transaction_id: tx,
+            index,
+        });
+
+        self
+    }
+
+    /// Marks the tx as failing phase-2 validation, listing it among the
+    /// invalid txs of the block
+    pub fn failed(mut self) -> Self {
+        self.failed = true;
+        self
+    }
+
+    /// Pays lovelace to an address, given as bech32 or hex
+    pub fn output(self, address: &str, lovelace: u64) -> Self {
+        self.output_with_assets(address, lovelace, &[])
+    }
+
+    /// Pays lovelace and native assets to an address, the assets are given as
+    /// the hex policy id, the asset name and the amount
+    pub fn output_with_assets(
+        mut self,
+        address: &str,
+        lovelace: u64,
+        assets: &[(&str, &str, u64)],
+    ) -> Self {
+        let amount = match assets.is_empty() {
+            true => alonzo::Value::Coin(lovelace),
+            false => alonzo::Value::Multiasset(lovelace, multiasset(assets)),
+        };
+
+        self.outputs.push(alonzo::TransactionOutput {
+            address: address_bytes(address).into(),
+            amount,
+            datum_hash: None,
+        });
+
+        self
+    }
+
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn ttl(mut self, slot: u64) -> Self {
+        self.ttl = Some(slot);
+        self
+    }
+
+    pub fn validity_start(mut self, slot: u64) -> Self {
+        self.validity_start = Some(slot);
+        self
+    }
+
+    pub fn cert(mut self, cert: alonzo::Certificate) -> Self {
+        self.certs.push(cert);
+        self
+    }
+
+    /// Withdraws the rewards of the mainnet stake key hash repeating the seed
+    pub fn withdrawal(mut self, stake: u8, amount: u64) -> Self {
+        let mut account = vec![0xe1];
+        account.extend([stake; 28]);
+        self.withdrawals.push((account.into(), amount));
+        self
+    }
+
+    /// Mints (or burns, with a negative amount) an asset of the hex policy id
+    pub fn mint(mut self, policy: &str, name: &str, amount: i64) -> Self {
+        let policy = hex::decode(policy).expect("valid hex policy id");
+        self.mint.push((policy, name.as_bytes().to_vec(), amount));
+        self
+    }
+
+    /// Attaches a text metadatum under the label
+    pub fn metadata(mut self, label: u64, text: &str) -> Self {
+        self.metadata.push((label, text.to_string()));
+        self
+    }
+
+    /// Attaches a redeemer with a unit datum, declaring the execution units
+    pub fn redeemer(mut self, tag: alonzo::RedeemerTag, index: u32, mem: u32, steps: u64) -> Self {
+        self.redeemers.push(alonzo::Redeemer {
+            tag,
+            index,
+            data: alonzo::PlutusData::Array(MaybeIndefArray::Def(vec![])),
+            ex_units: alonzo::ExUnits { mem, steps },
+        });
+
+        self
+    }
+
+    /// Witnesses of the tx, only its redeemers are set
+    fn witness_set(&mut self) -> alonzo::TransactionWitnessSet {
+        let redeemer = match self.redeemers.is_empty() {
+            true => None,
+            false => Some(MaybeIndefArray::Def(std::mem::take(&mut self.redeemers))),
+        };
+
+        alonzo::TransactionWitnessSet {
+            vkeywitness: None,
+            native_script: None,
+            bootstrap_witness: None,
+            plutus_script: None,
+            plutus_data: None,
+            redeemer,
+        }
+    }
+
+    /// Auxiliary data carrying the metadata of the tx, if any
+    fn auxiliary_data(&self) -> Option<alonzo::AuxiliaryData> {
+        if self.metadata.is_empty() {
+            return None;
+        }
+
+        let metadata = self
+            .metadata
+            .iter()
+            .map(|(label, text)| {
+                (
+                    alonzo::Metadatum::Int(minicbor::data::Int::from(*label)),
+                    alonzo::Metadatum::Text(text.clone()),
+                )
+            })
+            .collect();
+
+        Some(alonzo::AuxiliaryData::Shelley(KeyValuePairs::Def(metadata)))
+    }
+
+    /// pallas keeps the components of a tx body private, so the body goes
+    /// through its CBOR form
+    fn build(self) -> alonzo::TransactionBody {
+        let mut components = vec![
+            alonzo::TransactionBodyComponent::Inputs(MaybeIndefArray::Def(self.inputs)),
+            alonzo::TransactionBodyComponent::Outputs(MaybeIndefArray::Def(self.outputs)),
+            alonzo::TransactionBodyComponent::Fee(self.fee),
+        ];
+
+        if let Some(ttl) = self.ttl {
+            components.push(alonzo::TransactionBodyComponent::Ttl(ttl));
+        }
+
+        if let Some(start) = self.validity_start {
+            components.push(alonzo::TransactionBodyComponent::ValidityIntervalStart(
+                start,
+            ));
+        }
+
+        if !self.certs.is_empty() {
+            components.push(alonzo::TransactionBodyComponent::Certificates(
+                MaybeIndefArray::Def(self.certs),
+            ));
+        }
+
+        if !self.withdrawals.is_empty() {
+            components.push(alonzo::TransactionBodyComponent::Withdrawals(
+                KeyValuePairs::Def(self.withdrawals),
+            ));
+        }
+
+        if !self.mint.is_empty() {
+            components.push(alonzo::TransactionBodyComponent::Mint(group_by_policy(
+                self.mint,
+            )));
+        }
+
+        if !self.collateral.is_empty() {
+            components.push(alonzo::TransactionBodyComponent::Collateral(
+                MaybeIndefArray::Def(self.collateral),
+            ));
+        }
+
+        let mut cbor = Vec::new();
+        let mut encoder = minicbor::Encoder::new(&mut cbor);
+
+        encoder
+            .map(components.len() as u64)
+            .expect("encoding into a vec can't fail");
+
+        for component in components.iter() {
+            encoder
+                .encode(component)
+                .expect("encoding into a vec can't fail");
+        }
+
+        alonzo::TransactionBody::decode_fragment(&cbor).expect("synthetic tx body decodes")
+    }
+}
+
+fn group_by_policy<A>(assets: Vec<(Vec<u8>, Vec<u8>, A)>) -> alonzo::Multiasset<A> {
+    let mut policies: Vec<(ByteVec, Vec<(ByteVec, A)>)> = Vec::new();
+
+    for (policy, name, amount) in assets {
+        let policy = ByteVec::from(policy);
+
+        match policies.iter_mut().find(|(x, _)| *x == policy) {
+            Some((_, names)) => names.push((name.into(), amount)),
+            None => policies.push((policy, vec![(name.into(), amount)])),
+        }
+    }
+
+    KeyValuePairs::Def(
+        policies
+            .into_iter()
+            .map(|(policy, names)| (policy, KeyValuePairs::Def(names)))
+            .collect(),
+    )
+}
+
+fn multiasset(assets: &[(&str, &str, u64)]) -> alonzo::Multiasset<u64> {
+    let assets = assets
+        .iter()
+        .map(|(policy, name, amount)| {
+            let policy = hex::decode(policy).expect("valid hex policy id");
+            (policy, name.as_bytes().to_vec(), *amount)
+        })
+        .collect();
+
+    group_by_policy(assets)
+}
+
+/// Builds a synthetic alonzo block
+pub struct BlockBuilder {
+    slot: u64,
+    number: u64,
+    body_size: u64,
+    prev_hash: Hash<32>,
+    issuer: u8,
+    txs: Vec<TxBuilder>,
+}
+
+/// Starts a block at [DEFAULT_SLOT] without any tx
+///
+/// eg: `block().slot(10).tx(|t| t.output(&address(1), 5_000_000)).build()`
+pub fn block() -> BlockBuilder {
+    BlockBuilder {
+        slot: DEFAULT_SLOT,
+        number: 1,
+        body_size: 0,
+        prev_hash: hash(0),
+        issuer: 0,
+        txs: Vec::new(),
+    }
+}
+
+impl BlockBuilder {
+    pub fn slot(mut self, slot: u64) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    pub fn number(mut self, number: u64) -> Self {
+        self.number = number;
+        self
+    }
+
+    /// Size of the body declared by the header, it isn't checked
+    pub fn body_size(mut self, size: u64) -> Self {
+        self.body_size = size;
+        self
+    }
+
+    pub fn prev_hash(mut self, hash: Hash<32>) -> Self {
+        self.prev_hash = hash;
+        self
+    }
+
+    /// Issuer key of the header, repeating the seed
+    pub fn issuer(mut self, seed: u8) -> Self {
+        self.issuer = seed;
+        self
+    }
+
+    pub fn tx(mut self, build: impl FnOnce(TxBuilder) -> TxBuilder) -> Self {
+        self.txs.push(build(TxBuilder::default()));
+        self
+    }
+
+    pub fn build(mut self) -> model::MultiEraBlock {
+        let witnesses = self.txs.iter_mut().map(TxBuilder::witness_set).collect();
+
+        let failed: Vec<_> = self
+            .txs
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.failed)
+            .map(|(idx, _)| idx as u32)
+            .collect();
+
+        let aux = self
+            .txs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, tx)| Some((idx as u32, tx.auxiliary_data()?)))
+            .collect();
+
+        let bodies = self.txs.into_iter().map(TxBuilder::build).collect();
+
+        let header_body = alonzo::HeaderBody {
+            block_number: self.number,
+            slot: self.slot,
+            prev_hash: self.prev_hash,
+            issuer_vkey: vec![self.issuer; 32].into(),
+            vrf_vkey: vec![0; 32].into(),
+            nonce_vrf: alonzo::VrfCert(vec![].into(), vec![].into()),
+            leader_vrf: alonzo::VrfCert(vec![].into(), vec![].into()),
+            block_body_size: self.body_size,
+            block_body_hash: hash(0),
+            operational_cert: vec![].into(),
+            unknown_0: 0,
+            unknown_1: 0,
+            unknown_2: vec![].into(),
+            protocol_version_major: 6,
+            protocol_version_minor: 0,
+        };
+
+        let block = alonzo::Block {
+            header: alonzo::Header {
+                header_body,
+                body_signature: vec![].into(),
+            },
+            transaction_bodies: MaybeIndefArray::Def(bodies),
+            transaction_witness_sets: MaybeIndefArray::Def(witnesses),
+            auxiliary_data_set: KeyValuePairs::Def(aux),
+            invalid_transactions: Some(MaybeIndefArray::Def(failed)),
+        };
+
+        model::MultiEraBlock::AlonzoCompatible(alonzo::BlockWrapper(ALONZO_ERA, block))
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use gasket::{
+    messaging::{connect_ports, OutputPort},
+    runtime::Worker,
+};
+use pallas::network::miniprotocols::Point;
+
+use crate::{crosscut, enrich, model, reducers, storage};
+
+/// Capacity of the ports between the stages, the harness runs them one after
+/// the other so a full port would block it
+const PORT_CAP: usize = 100_000;
+
+static NEXT_DB: AtomicUsize = AtomicUsize::new(0);
+
+/// Drives blocks through the sled enrichment, the reducers and the memory
+/// storage, one stage after the other on the calling thread
+///
+/// The enrichment db lives in a temporary directory removed on drop.
+pub struct Harness {
+    source: OutputPort<model::ChainSyncCommandEx>,
+    enrich: enrich::sled::Worker,
+    reducers: reducers::Worker,
+    storage: storage::memory::Worker,
+    store: Arc<Mutex<storage::memory::Store>>,
+    db_path: PathBuf,
+}
+
+fn unwrap_work(result: gasket::runtime::WorkResult, stage: &str) {
+    if let Err(err) = result {
+        panic!("{} stage failed: {:?}", stage, err);
+    }
+}
+
+fn unsampled(
+    reducers: Vec<reducers::Plugin>,
+) -> Vec<(reducers::Plugin, Option<crosscut::Sampler>)> {
+    reducers.into_iter().map(|x| (x, None)).collect()
+}
+
+/// A state db for the reducers that keep one, removed once its last handle
+/// is dropped. Share it between harnesses to simulate a restart.
+pub fn state_db() -> reducers::state::Db {
+    reducers::state::Db::temporary().expect("temporary state db opens")
+}
+
+impl Harness {
+    pub fn new(reducers: Vec<reducers::Plugin>) -> Self {
+        Self::with_filters(reducers, crosscut::Filters::default())
+    }
+
+    pub fn with_filters(reducers: Vec<reducers::Plugin>, filters: crosscut::Filters) -> Self {
+        Self::build(unsampled(reducers), filters, Vec::new(), None)
+    }
+
+    /// Lets a scope replace the filters and allowlists before the next block,
+    /// as a reload of the config does
+    pub fn with_scope(reducers: Vec<reducers::Plugin>, scope: crosscut::ScopeHandle) -> Self {
+        Self::build(
+            unsampled(reducers),
+            crosscut::Filters::default(),
+            Vec::new(),
+            Some(scope),
+        )
+    }
+
+    /// Restricts each reducer to the blocks accepted by its sampler, if any
+    pub fn with_samplers(reducers: Vec<(reducers::Plugin, Option<crosscut::Sampler>)>) -> Self {
+        Self::build(reducers, crosscut::Filters::default(), Vec::new(), None)
+    }
+
+    /// Rewrites the keys of each reducer with the template at the same position
+    pub fn with_key_templates(
+        reducers: Vec<reducers::Plugin>,
+        templates: Vec<Option<crosscut::KeyTemplate>>,
+    ) -> Self {
+        Self::build(
+            unsampled(reducers),
+            crosscut::Filters::default(),
+            templates,
+            None,
+        )
+    }
+
+    /// Simulates a restart of the pipeline: the enrichment db and the data
+    /// written so far are kept, while the reducers start over
+    pub fn restart(mut self, reducers: Vec<reducers::Plugin>) -> Self {
+        let db_path = std::mem::take(&mut self.db_path);
+        let store = self.store.clone();
+
+        drop(self);
+
+        Self::build_at(
+            db_path,
+            Some(store),
+            unsampled(reducers),
+            crosscut::Filters::default(),
+            Vec::new(),
+            None,
+        )
+    }
+
+    fn build(
+        reducers: Vec<(reducers::Plugin, Option<crosscut::Sampler>)>,
+        filters: crosscut::Filters,
+        templates: Vec<Option<crosscut::KeyTemplate>>,
+        scope: Option<crosscut::ScopeHandle>,
+    ) -> Self {
+        let db_path = std::env::temp_dir().join(format!(
+            "scrolls-harness-{}-{}",
+            std::process::id(),
+            NEXT_DB.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        Self::build_at(db_path, None, reducers, filters, templates, scope)
+    }
+
+    fn build_at(
+        db_path: PathBuf,
+        store: Option<Arc<Mutex<storage::memory::Store>>>,
+        reducers: Vec<(reducers::Plugin, Option<crosscut::Sampler>)>,
+        filters: crosscut::Filters,
+        templates: Vec<Option<crosscut::KeyTemplate>>,
+        scope: Option<crosscut::ScopeHandle>,
+    ) -> Self {
+        let config = enrich::sled::Config {
+            db_path: db_path.to_string_lossy().to_string(),
+        };
+
+        let mut enrich = match config.plugin() {
+            enrich::Plugin::Sled(x) => x,
+            _ => unreachable!("sled config builds a sled plugin"),
+        };
+
+        // sled releases the lock of a restarted db from a background thread,
+        // shortly after the previous worker is dropped
+        let mut attempts = 0;
+
+        while let Err(err) = enrich.bootstrap() {
+            attempts += 1;
+
+            if attempts == 50 {
+                panic!("temporary enrichment db doesn't open: {:?}", err);
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let reducers = reducers
+            .into_iter()
+            .map(|(x, sampler)| ("harness", x, sampler))
+            .collect();
+
+        let mut reducers =
+            reducers::Worker::new(reducers, filters, None, &crosscut::RuntimePolicy::default())
+                .with_key_templates(templates);
+
+        if let Some(scope) = scope {
+            reducers = reducers.with_scope(scope);
+        }
+
+        let mut storage = match storage::memory::Config::default().plugin(
+            &crosscut::ChainWellKnownInfo::mainnet(),
+            &crosscut::IntersectConfig::Origin,
+            crate::cursor::Store::Skip,
+        ) {
+            storage::Plugin::Memory(x) => x,
+            _ => unreachable!("memory config builds a memory plugin"),
+        };
+
+        if let Some(store) = store {
+            storage = storage.with_store(store);
+        }
+
+        let mut source = OutputPort::default();
+
+        connect_ports(&mut source, enrich.borrow_input_port(), PORT_CAP);
+        connect_ports(
+            enrich.borrow_output_port(),
+            reducers.borrow_input_port(),
+            PORT_CAP,
+        );
+        connect_ports(
+            reducers.borrow_output_port(),
+            storage::Pluggable::borrow_input_port(&mut storage),
+            PORT_CAP,
+        );
+
+        let store = storage.store();
+
+        Harness {
+            source,
+            enrich,
+            reducers,
+            storage,
+            store,
+            db_path,
+        }
+    }
+
+    fn run_upstream(&mut self, command: gasket::messaging::Message<model::ChainSyncCommandEx>) {
+        self.source.send(command).expect("enrich port is connected");
+
+        unwrap_work(self.enrich.work(), "enrich");
+        unwrap_work(self.reducers.work(), "reducers");
+    }
+
+    /// Applies the block and returns its point once the storage has finished it
+    pub fn roll_forward(&mut self, block: model::MultiEraBlock) -> Point {
+        let point = block.point().expect("built blocks have a point");
+        let expected = crosscut::PointArg::from(point.clone()).to_string();
+
+        self.run_upstream(model::ChainSyncCommandEx::roll_forward(block));
+
+        loop {
+            unwrap_work(self.storage.work(), "storage");
+
+            let store = self.store();
+            let finished = store.cursor().map(|x| x.to_string());
+
+            if finished.as_deref() == Some(expected.as_str()) {
+                break;
+            }
+        }
+
+        point
+    }
+
+    /// Rolls back to the point, returning once every undo reached the storage
+    ///
+    /// Undos aren't delimited like blocks, so the storage is drained until it
+    /// goes idle, which takes the receive timeout of the port.
+    pub fn roll_back(&mut self, point: Point) {
+        self.run_upstream(model::ChainSyncCommandEx::roll_back(point));
+
+        loop {
+            match self.storage.work() {
+                Ok(_) => (),
+                Err(gasket::error::Error::RecvIdle) => break,
+                Err(err) => panic!("storage stage failed: {:?}", err),
+            }
+        }
+    }
+
+    /// The data written so far
+    pub fn store(&self) -> MutexGuard<'_, storage::memory::Store> {
+        self.store.lock().expect("memory store lock poisoned")
+    }
+
+    /// The store itself, to read it from other threads
+    pub fn store_handle(&self) -> Arc<Mutex<storage::memory::Store>> {
+        self.store.clone()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = self.enrich.teardown();
+
+        // a restarted harness takes over the db
+        if !self.db_path.as_os_str().is_empty() {
+            let _ = std::fs::remove_dir_all(&self.db_path);
+        }
+    }
+}
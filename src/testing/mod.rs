@@ -0,0 +1,67 @@
+//! Synthetic blocks and a synchronous pipeline to test reducers end to end
+//!
+//! Built with the `testing` feature, or within the tests of the crate.
+
+mod blocks;
+mod harness;
+pub mod redis;
+
+pub use blocks::*;
+pub use harness::{state_db, Harness};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crosscut, reducers};
+
+    fn balance_history() -> reducers::Plugin {
+        reducers::balance_history::Config {
+            key_prefix: None,
+            address_key_format: Some(crosscut::AddressKeyFormat::Hex),
+            history_resolution: None,
+            max_history_slots: None,
+        }
+        .plugin(&crosscut::ChainWellKnownInfo::mainnet())
+    }
+
+    #[test]
+    fn output_shows_in_balance() {
+        let mut harness = Harness::new(vec![balance_history()]);
+
+        let block = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        harness.roll_forward(block);
+
+        let key = format!("balance_history.balance.{}", address(1));
+        assert_eq!(harness.store().counter(&key), Some(5_000_000));
+    }
+
+    #[test]
+    fn spent_output_is_resolved_and_rolled_back() {
+        let mut harness = Harness::new(vec![balance_history()]);
+
+        let first = block().tx(|t| t.output(&address(1), 5_000_000)).build();
+        let funding = tx_hash(&first, 0);
+        let first = harness.roll_forward(first);
+
+        let second = block()
+            .slot(DEFAULT_SLOT + 20)
+            .number(2)
+            .tx(|t| {
+                t.input(funding, 0)
+                    .output(&address(2), 4_800_000)
+                    .fee(200_000)
+            })
+            .build();
+
+        harness.roll_forward(second);
+
+        let key = |seed| format!("balance_history.balance.{}", address(seed));
+        assert_eq!(harness.store().counter(&key(1)), Some(0));
+        assert_eq!(harness.store().counter(&key(2)), Some(4_800_000));
+
+        harness.roll_back(first);
+
+        assert_eq!(harness.store().counter(&key(1)), Some(5_000_000));
+        assert_eq!(harness.store().counter(&key(2)), Some(0));
+    }
+}
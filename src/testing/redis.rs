@@ -0,0 +1,88 @@
+//! A stand-in for a Redis server that speaks just enough of the protocol for
+//! the commands of a test, each one answered by a handler
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+type Handler = Arc<Mutex<dyn FnMut(usize, &[String]) -> String + Send>>;
+
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+
+    let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        args.push(line.trim_end_matches("\r\n").to_string());
+    }
+
+    Some(args)
+}
+
+fn serve(connection: usize, stream: TcpStream, handler: Handler) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    while let Some(args) = read_command(&mut reader) {
+        let reply = (handler.lock().unwrap())(connection, &args);
+
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Serves the connections on a local port, returns the url to connect to
+///
+/// The handler gets the arguments of each command, starting with its name,
+/// and returns the raw reply, eg: built with [ok] or [bulk].
+pub fn fake_redis(mut handler: impl FnMut(&[String]) -> String + Send + 'static) -> String {
+    fake_redis_by_connection(move |_, args| handler(args))
+}
+
+/// Like [fake_redis], with the handler also getting the index of the
+/// connection, for state that Redis keeps per connection (eg: MULTI blocks)
+pub fn fake_redis_by_connection(
+    handler: impl FnMut(usize, &[String]) -> String + Send + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("redis://{}", listener.local_addr().unwrap());
+    let handler: Handler = Arc::new(Mutex::new(handler));
+
+    std::thread::spawn(move || {
+        for (connection, stream) in listener.incoming().flatten().enumerate() {
+            let handler = handler.clone();
+            std::thread::spawn(move || serve(connection, stream, handler));
+        }
+    });
+
+    address
+}
+
+pub fn ok() -> String {
+    "+OK\r\n".to_string()
+}
+
+pub fn bulk(value: Option<&str>) -> String {
+    match value {
+        Some(x) => format!("${}\r\n{}\r\n", x.len(), x),
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+pub fn int(value: i64) -> String {
+    format!(":{}\r\n", value)
+}
+
+pub fn error(message: &str) -> String {
+    format!("-{}\r\n", message)
+}